@@ -0,0 +1,7 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate foxbox_taxonomy;
+
+fuzz_target!(|data: &[u8]| {
+    foxbox_taxonomy::fuzz::fuzz_decompress(data);
+});
@@ -0,0 +1,519 @@
+//!
+//! The contract between the FoxBox and its adapters.
+//!
+//! An adapter bridges the FoxBox with a family of devices, e.g. Z-Wave
+//! devices or a cloud service. The FoxBox talks to adapters exclusively
+//! through trait `Adapter`, while adapters talk to the FoxBox through
+//! trait `AdapterManagerHandle`, which they use to register and remove
+//! the nodes and channels they manage as devices are discovered or
+//! disconnected.
+//!
+//! Adapters only deal with individual channels: evaluating selectors,
+//! managing tags, arbitrating between writes and dispatching events to
+//! the watches of applications is the responsibility of the manager.
+//! See `manager::AdapterManager`.
+//!
+//! # Namespaces
+//!
+//! The ids of the nodes and channels registered by an adapter must be
+//! prefixed with the id of the adapter, followed by `/`, e.g.
+//! `zwave@foxlink.mozilla.com/node-3`, so that two adapters never
+//! collide and that every id remains traceable to its adapter. Use
+//! `namespaced` to build such ids. The manager rejects registrations
+//! that violate this rule with `RegistrationError::InvalidId`.
+//!
+//! Users tag and label channels by id, so a device should receive the
+//! same ids each time it is registered, e.g. after a restart. Adapters
+//! that have no natural local ids may derive them from the hardware
+//! identifier of the device with `stable_id`.
+//!
+//! # Configuration
+//!
+//! Adapters declare the options they accept, e.g. the path of the
+//! serial port of a Z-Wave stick, with `Adapter::config_schema`. Users
+//! change options through `API::put_adapter_config`, which the manager
+//! checks against the schema before passing them to
+//! `Adapter::config_changed`. Adapters read the current options with
+//! `AdapterManagerHandle::get_config`, e.g. as they start.
+//!
+//! # Lifecycle
+//!
+//! Adapters report when they start, stop or fail through
+//! `AdapterManagerHandle`. The manager reports these changes as
+//! `AdapterEvent`s, both to instances of `AdapterListener` and to the
+//! watches of the topology. While an adapter is stopped or failed, its
+//! nodes remain in the topology but are reported as unavailable, so
+//! that frontends can tell users that e.g. the Z-Wave adapter has
+//! crashed rather than let devices vanish silently.
+//!
+//! # Extension kinds
+//!
+//! Adapters describe the extension kinds of their channels, i.e.
+//! `ChannelKind::Extension`, with
+//! `AdapterManagerHandle::add_extension_kind`, e.g. as they discover a
+//! device offering a novel service. The manager lists them in
+//! `API::get_supported_kinds`, and reports their registration and
+//! removal to the watches of the topology, so that clients may refresh
+//! their catalog of kinds without reloading.
+//!
+
+use devices::{ AdapterId, Channel, ChannelKind, Getter, KindDescription, Node, NodeId, Setter, StateId };
+use api::Acknowledgement;
+use discovery::CandidateId;
+use history::Sample;
+use values::{ TimeStamp, Type, Value };
+use util::{ Id, ID_SEPARATOR };
+
+use serde_json;
+
+use std::sync::{ Arc, Mutex };
+
+/// An adapter, as seen by the FoxBox.
+///
+/// Methods may be called from any thread, and should not block for
+/// longer than it takes to communicate with the devices.
+pub trait Adapter: Send + Sync {
+    /// An id unique to this adapter, e.g. "zwave@foxlink.mozilla.com".
+    /// The id may not contain `/`.
+    fn id(&self) -> Id<AdapterId>;
+
+    /// A human-readable name for the adapter, e.g. "Z-Wave".
+    fn name(&self) -> &str;
+
+    /// The vendor of the adapter, e.g. "foxlink@mozilla.com".
+    fn vendor(&self) -> &str;
+
+    /// The version of the adapter, e.g. `[1, 2, 0, 0]`.
+    fn version(&self) -> &[u32; 4];
+
+    /// Request the latest value of a set of getters, all of which have
+    /// been registered by this adapter.
+    ///
+    /// Returns one result per getter. A result of `Ok(None)` indicates
+    /// that no value is available yet, e.g. for a sensor that only
+    /// sends data when it sees fit.
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)>;
+
+    /// Request all the values produced since `since` by a set of
+    /// getters, all of which have been registered by this adapter,
+    /// oldest first, each with the date at which it was produced.
+    ///
+    /// Adapters for devices that buffer their readings, e.g. pollution
+    /// sensors delivering a batch every few hours, should override this
+    /// to hand over the whole batch. By default, only the latest value
+    /// is reported, as obtained with `fetch_values`, dated from now.
+    fn fetch_values_since(&self, getters: Vec<Id<Getter>>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, AdapterError>)> {
+        let _ = since;
+        self.fetch_values(getters).into_iter()
+            .map(|(id, result)| (id, result.map(|value| value.into_iter()
+                .map(|value| Sample {
+                    timestamp: TimeStamp::now(),
+                    value: value,
+                })
+                .collect())))
+            .collect()
+    }
+
+    /// Send values to a set of setters, all of which have been
+    /// registered by this adapter. The manager has already checked
+    /// that each value has the type expected by its setter.
+    ///
+    /// Returns one result per setter.
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)>;
+
+    /// Send values to a set of setters, as `send_values`, reporting for
+    /// each setter the state of the device once it has applied the
+    /// value.
+    ///
+    /// Adapters for devices that confirm actuation, e.g. a lock
+    /// reporting that it has actually locked, should override this. By
+    /// default, values are sent with `send_values`, and the resulting
+    /// state is not reported.
+    fn send_values_acknowledged(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<Acknowledgement, AdapterError>)> {
+        self.send_values(values).into_iter()
+            .map(|(id, result)| (id, result.map(|()| Acknowledgement::sent())))
+            .collect()
+    }
+
+    /// Start watching a getter registered by this adapter, which
+    /// supports watching. `cb` must be called whenever a new value is
+    /// available, or whenever an error occurs while producing values.
+    ///
+    /// Watching stops once the guard is dropped.
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError>;
+
+    /// Stop the adapter, e.g. because the FoxBox is shutting down or
+    /// the adapter is being removed. By default, do nothing.
+    fn stop(&self) {
+    }
+
+    /// Check that the adapter is able to do its job, e.g. that the
+    /// Z-Wave stick still responds or that the cloud service is
+    /// reachable. Called on demand by `API::get_adapter_health`. By
+    /// default, succeed.
+    fn health_check(&self) -> Result<(), AdapterError> {
+        Ok(())
+    }
+
+    /// The options accepted by the configuration of the adapter. By
+    /// default, none.
+    fn config_schema(&self) -> Vec<ConfigOption> {
+        vec![]
+    }
+
+    /// Apply a change to the configuration of the adapter. The value
+    /// has already been checked against the schema. If this returns an
+    /// error, e.g. because the serial port does not exist, the change is
+    /// rejected. By default, accept all changes.
+    fn config_changed(&self, key: &str, value: &Value) -> Result<(), AdapterError> {
+        let _ = (key, value);
+        Ok(())
+    }
+
+    /// Start managing a device previously announced through
+    /// `discovery::Discovery`, typically by registering its nodes and
+    /// channels. By default, adapters do not announce devices, so this
+    /// reports `AdapterError::UnsupportedOperation`.
+    fn adopt(&self, candidate: &Id<CandidateId>) -> Result<(), AdapterError> {
+        Err(AdapterError::UnsupportedOperation(format!("Cannot adopt {}", candidate.as_string())))
+    }
+
+    /// Unpair or otherwise remove a device that a user has asked to
+    /// forget with `API::forget_node`. This is called before the node
+    /// is removed from the topology, so the adapter does not need to
+    /// remove it. If this returns an error, e.g. because the device
+    /// cannot be reached, the node is kept. By default, there is
+    /// nothing to unpair, so accept.
+    fn forget(&self, node: &Id<NodeId>) -> Result<(), AdapterError> {
+        let _ = node;
+        Ok(())
+    }
+}
+
+/// An option of the configuration of an adapter. See
+/// `Adapter::config_schema`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigOption {
+    /// A key unique to the adapter, e.g. "serial-port".
+    pub key: String,
+
+    /// The type of the values accepted.
+    pub typ: Type,
+
+    /// A human-readable description, e.g. "The path of the serial port
+    /// of the Z-Wave stick".
+    pub description: String,
+
+    /// The value used by the adapter while the option is not set.
+    #[serde(default)]
+    pub default: Option<Value>,
+}
+
+/// A watch started by an adapter. Dropping the guard stops watching.
+pub trait AdapterWatchGuard: Send {
+}
+
+type Callback = Arc<Mutex<Box<Fn(Result<Value, AdapterError>) + Send + 'static>>>;
+
+struct WatcherSet {
+    counter: usize,
+    watchers: Vec<(usize, Id<Getter>, Callback)>,
+}
+
+/// The watches registered on the getters of an adapter, for the
+/// benefit of adapters that do not need anything more elaborate.
+#[derive(Clone)]
+pub struct Watchers(Arc<Mutex<WatcherSet>>);
+
+impl Watchers {
+    pub fn new() -> Self {
+        Watchers(Arc::new(Mutex::new(WatcherSet {
+            counter: 0,
+            watchers: vec![],
+        })))
+    }
+
+    /// Register a watch on `getter`, for use in `Adapter::register_watch`.
+    /// The watch is unregistered once the guard is dropped.
+    pub fn add(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>) -> Box<AdapterWatchGuard> {
+        let mut set = self.0.lock().unwrap();
+        set.counter += 1;
+        let key = set.counter;
+        set.watchers.push((key, getter.clone(), Arc::new(Mutex::new(cb))));
+        Box::new(WatcherGuard {
+            key: key,
+            watchers: self.clone(),
+        })
+    }
+
+    /// Report a new value or an error to the watches of `getter`.
+    /// Callbacks are called without holding the lock, as they may drop
+    /// their guard.
+    pub fn notify(&self, getter: &Id<Getter>, result: Result<Value, AdapterError>) {
+        let callbacks: Vec<_> = self.0.lock().unwrap().watchers.iter()
+            .filter(|&&(_, ref id, _)| id == getter)
+            .map(|&(_, _, ref cb)| cb.clone())
+            .collect();
+        for cb in callbacks {
+            let cb = cb.lock().unwrap();
+            (**cb)(result.clone());
+        }
+    }
+}
+
+impl Default for Watchers {
+    fn default() -> Self {
+        Watchers::new()
+    }
+}
+
+struct WatcherGuard {
+    key: usize,
+    watchers: Watchers,
+}
+
+impl AdapterWatchGuard for WatcherGuard {
+}
+
+impl Drop for WatcherGuard {
+    fn drop(&mut self) {
+        let key = self.key;
+        self.watchers.0.lock().unwrap().watchers.retain(|&(other, _, _)| other != key);
+    }
+}
+
+/// An error reported by an adapter while communicating with a device.
+///
+/// The manager reports these errors to applications as
+/// `api::Error::Adapter`, so that they can distinguish errors worth
+/// retrying from errors that will happen again. See `is_transient`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AdapterError {
+    /// The device cannot be reached at the moment, e.g. because it is
+    /// out of range or its battery is empty. Transient.
+    DeviceUnreachable,
+
+    /// The device or service did not follow the protocol, e.g. it sent
+    /// a frame that could not be decoded. Transient, as this is
+    /// typically caused by interference or by a device being reset.
+    Protocol(String),
+
+    /// The device does not support this operation, e.g. a setter that
+    /// only accepts some of the values of its kind. Permanent.
+    UnsupportedOperation(String),
+
+    /// Another error that may not happen again, e.g. a timeout or a
+    /// busy device.
+    Transient(String),
+
+    /// Another error that will happen again, e.g. a device that has
+    /// been excluded from the network without the adapter noticing.
+    Permanent(String),
+}
+
+impl AdapterError {
+    /// Determine whether the operation may succeed if it is attempted
+    /// again later.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            AdapterError::DeviceUnreachable | AdapterError::Protocol(_) | AdapterError::Transient(_) => true,
+            AdapterError::UnsupportedOperation(_) | AdapterError::Permanent(_) => false,
+        }
+    }
+}
+
+/// A change in the lifecycle of an adapter.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AdapterEvent {
+    /// The adapter has been registered. Its nodes are available.
+    Registered(Id<AdapterId>),
+
+    /// The adapter has started again after having stopped or failed.
+    /// Its nodes are available again.
+    Started(Id<AdapterId>),
+
+    /// The adapter has stopped. Its nodes are unavailable.
+    Stopped(Id<AdapterId>),
+
+    /// The adapter has failed, e.g. because it has lost its connection
+    /// to the Z-Wave controller. Its nodes are unavailable until it
+    /// starts again.
+    Failed {
+        adapter: Id<AdapterId>,
+
+        /// A human-readable description of the failure.
+        error: String
+    },
+
+    /// The adapter has been removed, along with its nodes.
+    Removed(Id<AdapterId>),
+}
+
+/// An observer of the lifecycle of adapters.
+///
+/// Listeners are called without any lock held, and may call the API.
+pub trait AdapterListener: Send + Sync {
+    fn on_adapter_event(&self, event: &AdapterEvent);
+}
+
+/// An error while registering or removing an adapter, node or channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistrationError {
+    /// An adapter with this id is already registered.
+    DuplicateAdapter(Id<AdapterId>),
+
+    /// A node with this id is already registered.
+    DuplicateNode(Id<NodeId>),
+
+    /// A getter channel with this id is already registered.
+    DuplicateGetter(Id<Getter>),
+
+    /// A setter channel with this id is already registered.
+    DuplicateSetter(Id<Setter>),
+
+    /// A state with this id is already registered.
+    DuplicateState(Id<StateId>),
+
+    /// A state pairs channels that do not belong to its node, or whose
+    /// kind differs from the kind of the state, or a getter that emits
+    /// events. See `devices::StateChannel`.
+    InvalidState(Id<StateId>),
+
+    /// The id of an adapter contains `/`, or the id of a node or
+    /// channel is not prefixed with the id of its adapter. See
+    /// `namespaced`.
+    InvalidId(String),
+
+    /// A kind described by an adapter is not `ChannelKind::Extension`,
+    /// or its description does not match its type.
+    InvalidKind(ChannelKind),
+
+    /// An extension kind has already been described by another adapter.
+    DuplicateKind(ChannelKind),
+
+    /// The adapter has not described this extension kind.
+    NoSuchKind(ChannelKind),
+
+    NoSuchAdapter(Id<AdapterId>),
+    NoSuchNode(Id<NodeId>),
+    NoSuchGetter(Id<Getter>),
+    NoSuchSetter(Id<Setter>),
+}
+
+/// The id of a node or channel registered by `adapter`, e.g.
+/// `zwave@foxlink.mozilla.com/node-3` for local id `node-3`. See
+/// `util::Id` for the structure of composite ids.
+pub fn namespaced<T>(adapter: &Id<AdapterId>, local: &str) -> Id<T> {
+    Id::new(format!("{}{}{}", adapter.as_string(), ID_SEPARATOR, local))
+}
+
+/// The id of the channel of kind `kind` offered by the device with
+/// hardware identifier `hardware`, e.g. a MAC address or a Z-Wave home
+/// id and node id, as registered by `adapter`. The id only depends on
+/// its arguments, so it remains the same across restarts.
+///
+/// The id is `namespaced(adapter, hash)`, where `hash` is the 64-bit
+/// FNV-1a hash, as 16 lowercase hexadecimal digits, of the bytes of the
+/// id of the adapter, a zero byte, the bytes of `hardware`, a zero
+/// byte and the JSON serialization of `kind`. This scheme must not
+/// change, as ids are persisted by users.
+///
+/// ```ignore
+/// let id: Id<Getter> = stable_id(&adapter, "zwave:0xc0ffee:5", &ChannelKind::OpenClosed);
+/// ```
+pub fn stable_id<T>(adapter: &Id<AdapterId>, hardware: &str, kind: &ChannelKind) -> Id<T> {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    // Serializing a `ChannelKind` cannot fail.
+    let kind = serde_json::to_string(kind).unwrap();
+    let mut hash = OFFSET_BASIS;
+    for part in &[adapter.as_string().as_bytes(), &[0], hardware.as_bytes(), &[0], kind.as_bytes()] {
+        for byte in part.iter() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+    namespaced(adapter, &format!("{:016x}", hash))
+}
+
+/// Determine whether `id` is the id of a node or channel registered by
+/// `adapter`, i.e. whether it is prefixed with the id of the adapter.
+pub fn is_namespaced<T>(adapter: &Id<AdapterId>, id: &Id<T>) -> bool {
+    id.adapter_part() == Some(adapter.as_string())
+}
+
+/// The FoxBox, as seen by adapters.
+///
+/// Changes to the topology are visible to applications as soon as the
+/// method returns, and are reported to the watches of the topology.
+pub trait AdapterManagerHandle: Send + Sync {
+    /// Register an adapter. The adapter may then register nodes.
+    ///
+    /// Adapters may be registered at any time, e.g. as they are loaded
+    /// dynamically.
+    fn add_adapter(&self, adapter: Arc<Adapter>) -> Result<(), RegistrationError>;
+
+    /// Register an adapter along with the nodes it already knows, e.g.
+    /// devices paired during a previous run, as a single change of the
+    /// topology. If any of the nodes cannot be registered, neither the
+    /// adapter nor any of its nodes are.
+    fn add_adapter_with_nodes(&self, adapter: Arc<Adapter>, nodes: Vec<Node>) -> Result<(), RegistrationError>;
+
+    /// Remove an adapter, along with all its nodes and channels, as a
+    /// single change of the topology, then stop it. Watches of
+    /// applications remain registered, and will watch the channels of
+    /// the adapter again if it is registered again.
+    fn remove_adapter(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError>;
+
+    /// Register a node managed by `adapter`, along with the channels it
+    /// already contains.
+    fn add_node(&self, adapter: &Id<AdapterId>, node: Node) -> Result<(), RegistrationError>;
+
+    /// Remove a node, along with all its channels.
+    fn remove_node(&self, id: &Id<NodeId>) -> Result<(), RegistrationError>;
+
+    /// Register a getter channel on a node registered previously. The
+    /// channel is managed by the adapter managing the node.
+    fn add_getter(&self, channel: Channel<Getter>) -> Result<(), RegistrationError>;
+
+    fn remove_getter(&self, id: &Id<Getter>) -> Result<(), RegistrationError>;
+
+    /// Register a setter channel on a node registered previously. The
+    /// channel is managed by the adapter managing the node.
+    fn add_setter(&self, channel: Channel<Setter>) -> Result<(), RegistrationError>;
+
+    fn remove_setter(&self, id: &Id<Setter>) -> Result<(), RegistrationError>;
+
+    /// Report that an adapter has started again after having stopped or
+    /// failed, making its nodes available. Does nothing if the adapter
+    /// is already running.
+    fn adapter_started(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError>;
+
+    /// Report that an adapter has registered all the nodes it currently
+    /// knows, e.g. once it has scanned the network after starting. The
+    /// nodes of the adapter restored from a snapshot that it has not
+    /// registered again are removed, see `manager::AdapterManager::restore`.
+    fn adapter_scanned(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError>;
+
+    /// Report that an adapter has stopped, making its nodes unavailable.
+    fn adapter_stopped(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError>;
+
+    /// Report that an adapter has failed, making its nodes unavailable
+    /// until it reports that it has started again.
+    fn adapter_failed(&self, id: &Id<AdapterId>, error: String) -> Result<(), RegistrationError>;
+
+    /// The value of an option of the configuration of an adapter, or
+    /// `None` if the option has not been set, in which case the adapter
+    /// should use its default. This may be called before the adapter is
+    /// registered.
+    fn get_config(&self, adapter: &Id<AdapterId>, key: &str) -> Option<Value>;
+
+    /// Describe an extension kind introduced by `adapter`, or update
+    /// its description. The kind is removed along with the adapter.
+    fn add_extension_kind(&self, adapter: &Id<AdapterId>, description: KindDescription) -> Result<(), RegistrationError>;
+
+    /// Remove an extension kind described by `adapter`.
+    fn remove_extension_kind(&self, adapter: &Id<AdapterId>, kind: &ChannelKind) -> Result<(), RegistrationError>;
+}
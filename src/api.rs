@@ -3,7 +3,8 @@
 //!
 //! This API is provided as Traits to be implemented:
 //!
-//! - by the low-level layers of the FoxBox, including the adapters;
+//! - by the low-level layers of the FoxBox, which in turn talk to the
+//!   adapters through module `adapter`;
 //! - by test suites and tools that need to simulate connected devices.
 //!
 //! In turn, this API is used to implement:
@@ -13,10 +14,22 @@
 //!
 //!
 
+use adapter::{AdapterError, AdapterEvent, ConfigOption};
+use compression::CompressionError;
+use discovery::{Candidate, CandidateId};
+use document::DocumentError;
+use history::{ Bucket, Sample };
+use metrics::Metrics;
+use tagstore::StoreError;
 use devices::*;
 use selector::*;
-use values::Value;
-use util::Id;
+use values::{Value, TimeStamp, ValDuration};
+use util::{ Id, TagId };
+use auth::{Caller, CallerId};
+use arbitration::{Priority, WriteRecord};
+
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver};
 
 /// An error produced by one of the APIs in this module.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -30,8 +43,148 @@ pub enum Error {
     /// There is no such setter channel connected to the Foxbox, even indirectly.
     NoSuchSetter(Id<Setter>),
 
+    /// There is no such state connected to the Foxbox. See
+    /// `devices::StateChannel`.
+    NoSuchState(Id<StateId>),
+
+    /// There is no such watch registered, or it has already been
+    /// unregistered.
+    NoSuchWatch(Id<WatchId>),
+
+    /// There is no such tagging rule registered, or it has already
+    /// been unregistered.
+    NoSuchTaggingRule(Id<TaggingRuleId>),
+
+    /// This getter channel cannot be polled, i.e. it only sends
+    /// data to the FoxBox when it sees fit.
+    NotPollable(Id<Getter>),
+
+    /// Attempting to fetch the value of a getter that emits events,
+    /// which has no current value. See `devices::Semantics`.
+    EventChannel(Id<Getter>),
+
     /// Attempting to set a value with the wrong type
     TypeError,
+
+    /// There is no such adapter registered.
+    NoSuchAdapter(Id<AdapterId>),
+
+    /// No adapter currently announces this device, or the adapter
+    /// requested does not announce it.
+    NoSuchCandidate(Id<CandidateId>),
+
+    /// No durable watch has been registered for this owner, or it has
+    /// been unregistered.
+    NoSuchSubscription(String),
+
+    /// The adapter does not declare this option of configuration. See
+    /// `adapter::Adapter::config_schema`.
+    NoSuchConfigOption {
+        adapter: Id<AdapterId>,
+        key: String,
+    },
+
+    /// There is no such scheduled write, or it has already been
+    /// executed or cancelled.
+    NoSuchSchedule(Id<ScheduleId>),
+
+    /// The setter channel is reserved by another caller. See
+    /// `API::reserve_setters`.
+    Reserved {
+        /// The setter channel.
+        setter: Id<Setter>,
+
+        /// The caller holding the reservation.
+        holder: Id<CallerId>,
+
+        /// The date at which the reservation expires.
+        expires: TimeStamp,
+    },
+
+    /// There is no such reservation, or it has already expired or
+    /// been released.
+    NoSuchReservation(Id<ReservationId>),
+
+    /// There is no such polling hint, or it has already expired or
+    /// been withdrawn.
+    NoSuchPollingHint(Id<PollingHintId>),
+
+    /// The write has been suppressed in favor of a write with a
+    /// higher priority. Payload is the winning write. See
+    /// `arbitration::ArbitrationPolicy`.
+    Suppressed(WriteRecord),
+
+    /// The caller is not authorized to perform this operation.
+    /// See `auth::PermissionPolicy`.
+    PermissionDenied(Id<CallerId>),
+
+    /// The caller has exceeded its quota for this operation. Payload
+    /// is the duration after which the operation may be attempted
+    /// again. See `ratelimit::RateLimited`.
+    RateLimited(ValDuration),
+
+    /// This optional feature is not supported by the implementation
+    /// of the API. See `API::get_api_capabilities`.
+    Unsupported(Capability),
+
+    /// The adapter managing the channel has too many requests waiting,
+    /// typically because it is slow to respond. The operation may be
+    /// attempted again later. See `manager::DispatchPolicy`.
+    Overloaded(Id<AdapterId>),
+
+    /// The adapter managing the channel has failed to communicate with
+    /// the device. See `adapter::AdapterError::is_transient` to decide
+    /// whether the operation is worth retrying.
+    Adapter {
+        /// The adapter that has reported the error.
+        adapter: Id<AdapterId>,
+
+        /// The error.
+        error: AdapterError,
+    },
+
+    /// The tags have been changed, but could not be persisted, so the
+    /// change will not survive a restart. See `tagstore::TagStore`.
+    TagStore(StoreError),
+
+    /// The history could not be read. See `history::HistoryStore`.
+    HistoryStore(StoreError),
+
+    /// A durable watch could not be saved, read or forgotten. See
+    /// `watchstore::WatchStore`.
+    WatchStore(StoreError),
+
+    /// A document cannot be imported. See module `document`.
+    Document(DocumentError),
+
+    /// A `Value::Compressed` cannot be decompressed. See module
+    /// `compression`.
+    Compression(CompressionError),
+
+    /// The adapter has not completed the operation in time. The
+    /// adapter may still complete it later. See
+    /// `manager::RetryPolicy`.
+    TimedOut {
+        /// The adapter managing the channel.
+        adapter: Id<AdapterId>,
+
+        /// The time after which the operation was abandoned.
+        timeout: ValDuration,
+    },
+
+    /// The adapter has failed to communicate with the device, even
+    /// though the operation has been attempted several times. See
+    /// `manager::RetryPolicy`.
+    RetriesExhausted {
+        /// The adapter that has reported the error.
+        adapter: Id<AdapterId>,
+
+        /// The number of times the operation has been attempted.
+        attempts: u32,
+
+        /// The error reported by the last attempt.
+        error: AdapterError,
+    },
 }
 
 /// An event during watching.
@@ -46,6 +199,17 @@ pub enum WatchEvent {
         value: Value
     },
 
+    /// An error occurred while producing values for a watched
+    /// channel, e.g. the device is unreachable or the adapter could
+    /// not decode the data it received. The watch remains active.
+    Error {
+        /// The channel that failed.
+        from: Id<Getter>,
+
+        /// The error.
+        error: Error
+    },
+
     /// The set of devices being watched has changed, typically either
     /// because a tag was edited or because a device was
     /// removed. Payload is the id of the device that was removed.
@@ -55,16 +219,553 @@ pub enum WatchEvent {
     /// because a tag was edited or because a device was
     /// added. Payload is the id of the device that was added.
     GetterAdded(Id<Getter>),
+
+    /// The label of a node or channel has been set or cleared.
+    /// Delivered to watches that watch the topology.
+    LabelChanged {
+        /// The node or channel whose label has changed.
+        target: Labelled,
+
+        /// The new label, or `None` if the label has been cleared.
+        label: Option<String>
+    },
+
+    /// Several events, in the order in which they happened. Only
+    /// delivered to watches registered with `WatchOptions::with_batching`.
+    Batch(Vec<WatchEvent>),
+
+    /// An adapter has been registered, started, stopped, failed or
+    /// removed. Delivered to watches that watch the topology.
+    Adapter(AdapterEvent),
+
+    /// A node has become available or unavailable, typically because
+    /// its adapter has stopped, failed or started again. Delivered to
+    /// watches that watch the topology.
+    NodeAvailability {
+        node: Id<NodeId>,
+        available: bool
+    },
+
+    /// Some events have been dropped at this point, because the
+    /// callback did not consume them fast enough. Payload is the
+    /// number of events dropped. Only delivered to watches registered
+    /// with `WatchOptions::with_backpressure`, which may need to fetch
+    /// the values of the channels they watch to resynchronize.
+    Missed(u64),
+
+    /// The watch has expired and has been unregistered. This is the
+    /// last event delivered to the watch. Only delivered to watches
+    /// registered with `WatchOptions::with_expires_after` or
+    /// `WatchOptions::with_max_events`.
+    Expired,
+
+    /// An adapter has described an extension kind, or updated its
+    /// description. Delivered to watches that watch the topology, so
+    /// that clients may refresh the kinds returned by
+    /// `API::get_supported_kinds`.
+    KindRegistered {
+        adapter: Id<AdapterId>,
+        description: KindDescription
+    },
+
+    /// An extension kind has been removed, either by its adapter or
+    /// along with its adapter. Delivered to watches that watch the
+    /// topology.
+    KindRemoved {
+        adapter: Id<AdapterId>,
+        kind: ChannelKind
+    },
+}
+
+/// A node or channel, identified by its id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Labelled {
+    Node(Id<NodeId>),
+    Getter(Id<Getter>),
+    Setter(Id<Setter>),
+}
+
+/// A revision of the topology.
+///
+/// Revisions increase monotonically: any change to the nodes, the
+/// channels, their tags or their labels produces a new revision.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Revision(pub u64);
+
+/// The result of a read conditioned on a revision.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Conditional<T> {
+    /// Nothing has changed since the revision provided by the client.
+    Unchanged(Revision),
+
+    /// Something has changed.
+    Changed {
+        /// The revision from which `data` was read.
+        revision: Revision,
+
+        /// The result of the read.
+        data: T,
+    },
+}
+
+/// A marker for Id.
+/// Only useful for writing `Id<ScheduleId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct ScheduleId;
+
+/// A write scheduled with `API::put_channel_value_at`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScheduledWrite {
+    /// The id of the scheduled write.
+    pub id: Id<ScheduleId>,
+
+    /// The setters to which the value will be sent.
+    pub set: Vec<SetterSelector>,
+
+    /// The value to send.
+    pub value: Value,
+
+    /// The date at which the value will be sent.
+    pub at: TimeStamp,
+
+    /// The caller who scheduled the write, and on behalf of which it
+    /// will be executed.
+    pub owner: Id<CallerId>,
+}
+
+/// A marker for Id.
+/// Only useful for writing `Id<ReservationId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct ReservationId;
+
+/// An exclusive reservation of setters, obtained with `API::reserve_setters`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reservation {
+    /// The id of the reservation.
+    pub id: Id<ReservationId>,
+
+    /// The caller holding the reservation.
+    pub holder: Id<CallerId>,
+
+    /// The setters reserved.
+    pub setters: Vec<Id<Setter>>,
+
+    /// The date at which the reservation expires.
+    pub expires: TimeStamp,
+}
+
+/// A marker for Id.
+/// Only useful for writing `Id<PollingHintId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct PollingHintId;
+
+/// A request for fresher values of some getters, obtained with
+/// `API::put_polling_hint`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollingHint {
+    /// The id of the hint.
+    pub id: Id<PollingHintId>,
+
+    /// The caller who requested the hint.
+    pub owner: Id<CallerId>,
+
+    /// The getters that should be polled more often.
+    pub source: Vec<GetterSelector>,
+
+    /// The interval between two polls requested by the caller.
+    pub interval: ValDuration,
+
+    /// The date at which the hint expires.
+    pub expires: TimeStamp,
+}
+
+/// A value read from a getter channel, along with its provenance.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchedValue {
+    /// The actual value.
+    pub value: Value,
+
+    /// Date at which the value was produced, or, if the device does
+    /// not provide this information, at which it was received by the
+    /// FoxBox.
+    pub timestamp: TimeStamp,
+
+    /// Whether the value was obtained from the device for this
+    /// request or served from a cache.
+    pub source: ValueSource,
+
+    /// The adapter that supplied the value.
+    pub adapter: Id<AdapterId>,
+
+    /// If the value was served from a cache, how long ago it was
+    /// received, if known.
+    #[serde(default)]
+    pub age: Option<ValDuration>,
+}
+
+/// The outcome of a value sent to a setter channel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Acknowledgement {
+    /// The state of the device once it has applied the value, if the
+    /// adapter confirms actuation, e.g. `Locked` once a lock has
+    /// actually locked, which may differ from the value sent. `None`
+    /// if the adapter only reports that the value has been sent.
+    #[serde(default)]
+    pub value: Option<Value>,
+
+    /// Date at which the device has completed the operation, or, if
+    /// the device does not provide this information, at which the
+    /// adapter has reported it.
+    pub timestamp: TimeStamp,
+}
+
+impl Acknowledgement {
+    /// An acknowledgement without confirmation of the resulting state,
+    /// dated from now.
+    pub fn sent() -> Self {
+        Acknowledgement {
+            value: None,
+            timestamp: TimeStamp::now(),
+        }
+    }
+}
+
+/// The provenance of a value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ValueSource {
+    /// The value was obtained from the device while handling the request.
+    Live,
+
+    /// The value was served from a cache, e.g. because the device only
+    /// sends data when it sees fit or has been polled recently.
+    Cache,
+}
+
+/// The result of adding tags to or removing tags from a set of
+/// nodes or channels.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagResult<T> {
+    /// The entities whose tags have changed.
+    pub changed: Vec<Id<T>>,
+
+    /// The entities matched by the selectors, but whose tags were
+    /// already in the requested state.
+    pub unchanged: Vec<Id<T>>,
+}
+
+impl<T> TagResult<T> {
+    pub fn new() -> Self {
+        TagResult {
+            changed: vec![],
+            unchanged: vec![],
+        }
+    }
+
+    /// The number of entities matched by the selectors, whether their
+    /// tags have changed or not.
+    pub fn count(&self) -> usize {
+        self.changed.len() + self.unchanged.len()
+    }
+}
+
+/// A marker for Id.
+/// Only useful for writing `Id<TaggingRuleId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct TaggingRuleId;
+
+/// A live tagging rule: any node matching _either_ of the selectors of
+/// `set` is labelled with all the tags of `tags`, including nodes
+/// added after the rule was registered.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaggingRule {
+    /// The nodes to label.
+    pub set: Vec<NodeSelector>,
+
+    /// The tags to apply to these nodes.
+    pub tags: Vec<TagId>,
+}
+
+/// The function used to compute the value of an aggregate getter, or
+/// to combine the samples of a bucket of history, see
+/// `history::aggregate`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    /// The arithmetic mean of the latest values of the source getters.
+    Mean,
+
+    /// The smallest of the latest values of the source getters.
+    Min,
+
+    /// The largest of the latest values of the source getters.
+    Max,
+
+    /// The number of source getters that have a value.
+    Count,
+
+    /// The sum of the latest values of the source getters.
+    Sum,
+}
+
+/// A virtual getter, whose value is computed from the latest values of
+/// all the getters matching _either_ of the selectors of `source`,
+/// including getters added after the aggregate was registered.
+///
+/// For instance, an aggregate with `Aggregation::Mean` over all the
+/// getters of kind `ActualTemperature` tagged "house" is a channel
+/// representing the average temperature of the house.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateGetter {
+    /// The getters to aggregate.
+    pub source: Vec<GetterSelector>,
+
+    /// The kind of getters to aggregate. Getters matching `source`
+    /// with a different kind are ignored.
+    ///
+    /// With `Aggregation::Mean`, `Aggregation::Min`,
+    /// `Aggregation::Max` and `Aggregation::Sum`, this is also the kind
+    /// of the aggregate getter. With `Aggregation::Count`, the aggregate getter has kind
+    /// `ChannelKind::Extension { vendor: "foxlink@mozilla.com", adapter:
+    /// "aggregate", kind: "count", typ: Type::ExtNumeric }`.
+    pub kind: ChannelKind,
+
+    /// The function used to compute the value.
+    pub aggregation: Aggregation,
+
+    /// The tags of the aggregate getter.
+    #[serde(default)]
+    pub tags: Vec<TagId>,
+
+    /// The label of the aggregate getter.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// An option of the configuration of an adapter, along with its
+/// current value.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConfigEntry {
+    pub option: ConfigOption,
+
+    /// The value of the option, or `None` if it has not been set, in
+    /// which case the adapter uses `option.default`.
+    #[serde(default)]
+    pub value: Option<Value>,
+}
+
+/// The lifecycle status of an adapter. See `adapter::AdapterEvent`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum AdapterStatus {
+    Running,
+    Stopped,
+
+    /// The adapter has failed. Payload is the error it reported.
+    Failed(String),
+}
+
+/// Statistics on the requests of a given type handled by an adapter.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RequestStats {
+    /// The number of channels fetched from or sent to.
+    pub requests: u64,
+
+    /// The number of channels for which the request has failed,
+    /// including requests rejected with `Error::Overloaded`.
+    pub failures: u64,
+
+    /// The mean and largest time taken by the adapter to handle a
+    /// batch of requests, or `None` if it has not handled any yet.
+    #[serde(default)]
+    pub mean_latency: Option<ValDuration>,
+    #[serde(default)]
+    pub max_latency: Option<ValDuration>,
+}
+
+impl RequestStats {
+    /// The fraction of requests that have succeeded, between 0 and 1,
+    /// or `None` if there has been no request.
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.requests == 0 {
+            None
+        } else {
+            Some((self.requests - self.failures) as f64 / self.requests as f64)
+        }
+    }
+}
+
+/// An error, along with the date at which it occurred.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimedError {
+    pub timestamp: TimeStamp,
+    pub error: Error,
+}
+
+/// The health of an adapter, as reported by `API::get_adapter_health`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdapterHealth {
+    pub adapter: Id<AdapterId>,
+
+    /// The human-readable name of the adapter.
+    pub name: String,
+
+    pub status: AdapterStatus,
+
+    /// The result of `adapter::Adapter::health_check`, along with the
+    /// time it took on success.
+    pub check: Result<ValDuration, Error>,
+
+    /// The latest error reported by the adapter while fetching or
+    /// sending, if any.
+    #[serde(default)]
+    pub last_error: Option<TimedError>,
+
+    pub fetches: RequestStats,
+    pub sends: RequestStats,
+}
+
+/// An optional feature of the API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Capability {
+    /// Forcing polls with `API::poll_now`.
+    PollNow,
+
+    /// Fetching all the values buffered by devices with
+    /// `API::get_channel_values_since`.
+    MultiSampleFetch,
+
+    /// Reading and writing states through a single id, with
+    /// `API::get_state_value` and `API::put_state_value`.
+    StateChannels,
+
+    /// Live tagging rules, with `API::put_tagging_rule` and friends.
+    TaggingRules,
+
+    /// Watches identified by tokens, with
+    /// `API::register_channel_watch_with_token` and `API::unregister_watch`.
+    WatchTokens,
+
+    /// Listing active watches with `API::get_watches`.
+    WatchIntrospection,
+
+    /// Delivering watch events in batches, as requested with
+    /// `WatchOptions::with_batching`.
+    WatchBatching,
+
+    /// Bounding the number of watch events waiting for delivery, as
+    /// requested with `WatchOptions::with_backpressure`.
+    WatchBackpressure,
+
+    /// Watches unregistering themselves after a deadline or a number
+    /// of events, as requested with `WatchOptions::with_expires_after`
+    /// and `WatchOptions::with_max_events`.
+    WatchExpiration,
+
+    /// Filtering out small changes of numeric values, as requested with
+    /// `WatchOptions::with_min_delta`.
+    WatchMinDelta,
+
+    /// Scheduling writes with `API::put_channel_value_at` and friends.
+    ScheduledWrites,
+
+    /// Reserving setters with `API::reserve_setters` and friends.
+    Reservations,
+
+    /// Requesting fresher values with `API::put_polling_hint` and
+    /// friends.
+    PollingHints,
+
+    /// Aggregate getters, with `API::put_aggregate_getter` and friends.
+    AggregateGetters,
+
+    /// Reporting the health of adapters with `API::get_adapter_health`.
+    AdapterHealth,
+
+    /// Reporting metrics on adapters and channels with `API::get_metrics`.
+    Metrics,
+
+    /// Configuring adapters with `API::put_adapter_config` and friends.
+    AdapterConfig,
+
+    /// Reading the values recorded for getters with
+    /// `API::get_channel_history`.
+    History,
+
+    /// Adopting devices found on the network with
+    /// `API::adopt_candidate` and friends.
+    Discovery,
+
+    /// Watches surviving a restart, with `API::register_durable_watch`
+    /// and `API::resume_watch`.
+    DurableWatches,
+
+    /// A feature that has not been standardized yet.
+    Extension {
+        /// The vendor. Used for namespacing purposes, to avoid
+        /// confusing two incompatible extensions with similar
+        /// names. For instance, "foxlink@mozilla.com".
+        vendor: String,
+
+        /// A string describing the feature.
+        name: String,
+    },
 }
 
 /// A handle to the public API.
+///
+/// Every method is called on behalf of a `Caller`. Implementations
+/// are expected to consult an `auth::PermissionPolicy` before mutating
+/// tags, sending values to setters and registering watches. Wrapping
+/// an implementation in `audit::Audited` reports these operations to
+/// an `audit::AuditSink`.
+///
+/// The REST endpoints documented below are also listed in
+/// `rest::ROUTES`, in a machine-readable form.
 pub trait API: Send {
+    /// Get the list of optional features supported by this
+    /// implementation of the API.
+    ///
+    /// Clients are expected to check the capabilities they need before
+    /// using optional features, so that they can degrade gracefully
+    /// across versions of the FoxBox. Optional features that are not
+    /// supported fail with `Error::Unsupported`.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/capabilities`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `Capability`.
+    fn get_api_capabilities(&self, caller: &Caller) -> Vec<Capability>;
+
+    /// Get the list of kinds of channels supported by this
+    /// implementation of the API, i.e. all the standardized kinds
+    /// (see `ChannelKind::standard_kinds`) and the extension kinds
+    /// registered by adapters.
+    ///
+    /// Designed to let client SDK generators and UI pickers stay in
+    /// sync with the FoxBox. As adapters register and remove extension
+    /// kinds, watches of the topology receive `WatchEvent::KindRegistered`
+    /// and `WatchEvent::KindRemoved`.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/kinds`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `KindDescription`.
+    fn get_supported_kinds(&self, caller: &Caller) -> Vec<KindDescription>;
+
     /// Get the metadata on nodes matching some conditions.
     ///
     /// A call to `API::get_nodes(vec![req1, req2, ...])` will return
     /// the metadata on all nodes matching _either_ `req1` or `req2`
     /// or ...
     ///
+    /// The nodes, and their channels, may be shared with the
+    /// implementation, so that large topologies are not copied for
+    /// each request.
+    ///
     /// # REST API
     ///
     /// `GET /api/v1/nodes`
@@ -129,34 +830,119 @@ pub trait API: Send {
     ///   ]
     /// }]
     /// ```
-    fn get_nodes(&self, &Vec<NodeSelector>) -> Vec<Node>;
+    fn get_nodes(&self, caller: &Caller, &Vec<NodeSelector>) -> Vec<Arc<Node>>;
+
+    /// Get the current revision of the topology.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/nodes/revision`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing a number.
+    fn get_topology_revision(&self, caller: &Caller) -> Revision;
+
+    /// Get the metadata on nodes matching some conditions, unless the
+    /// topology has not changed since a given revision.
+    ///
+    /// This is a variant of `API::get_nodes` designed to let clients
+    /// implement cheap conditional reads. If `since` is `Some(rev)`
+    /// and the topology is still at revision `rev`, the result is
+    /// `Conditional::Unchanged`. Otherwise, the result contains the
+    /// nodes along with the revision of the topology from which they
+    /// were read, to be passed as `since` to the next call.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/nodes?if_changed_since=<revision>`
+    ///
+    /// ## Requests
+    ///
+    /// As `API::get_nodes`.
+    ///
+    /// ## Success
+    ///
+    /// If the topology has not changed, Status 304. Otherwise, a JSON
+    /// representing an object `{"revision": number, "data": [Node]}`.
+    fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>>;
+
+    /// Visit the metadata on nodes matching some conditions, one node
+    /// at a time.
+    ///
+    /// This is a variant of `API::get_nodes` designed for installations
+    /// with many nodes and channels: nodes are passed to `cb` as they
+    /// are found, rather than cloned and collected in a `Vec`. The
+    /// default implementation falls back to `API::get_nodes`.
+    ///
+    /// Note that `cb` is called while the implementation may hold
+    /// locks, so it should return quickly and should not call the API.
+    fn visit_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>, cb: &mut FnMut(&Node)) {
+        for node in self.get_nodes(caller, selectors) {
+            cb(&node)
+        }
+    }
+
+    /// Forget a node.
+    ///
+    /// The adapter owning the node is requested to unpair or otherwise
+    /// remove the device, see `Adapter::forget`. The node, its channels,
+    /// their tags and their values are then purged, and watchers are informed that the
+    /// channels have been removed. Watches themselves remain active,
+    /// as they may match channels added later.
+    ///
+    /// This is the sanctioned way to remove stale devices, e.g. a
+    /// light that has been thrown away.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/nodes`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the node.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchNode` if there is no such node.
+    ///
+    /// `Adapter` if the adapter fails to forget the device, in which
+    /// case the node is kept.
+    ///
+    /// If the caller is not authorized to manage nodes, `PermissionDenied`.
+    fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error>;
 
     /// Label a set of nodes with a set of tags.
     ///
     /// A call to `API::put_node_tag(vec![req1, req2, ...], vec![tag1,
     /// ...])` will label all the nodes matching _either_ `req1` or
-    /// `req2` or ... with `tag1`, ... and return the number of nodes
+    /// `req2` or ... with `tag1`, ... and return the ids of the nodes
     /// matching any of the selectors.
     ///
-    /// Some of the nodes may already be labelled with `tag1`, or
-    /// `tag2`, ... They will not change state. They are counted in
-    /// the resulting `usize` nevertheless.
+    /// Some of the nodes may already be labelled with `tag1`, and
+    /// `tag2`, ... They will not change state. They are reported in
+    /// `TagResult::unchanged`.
     ///
     /// Note that this call is _not live_. In other words, if nodes
-    /// are added after the call, they will not be affected.
+    /// are added after the call, they will not be affected. See
+    /// `API::put_tagging_rule` for a live variant.
+    ///
+    /// Tags are normalized, see `util::TagId`, so tags "Kitchen" and
+    /// "kitchen " are the same tag. In the REST API, a tag that cannot
+    /// be normalized is a syntax error.
     ///
     /// # REST API
     ///
     /// `POST /api/v1/nodes/tag`
     ///
-    /// ## Getters
+    /// ## Requests
     ///
     /// Any JSON that can be deserialized to
     ///
     /// ```ignore
     /// {
     ///   set: Vec<NodeSelector>,
-    ///   tags: Vec<String>,
+    ///   tags: Vec<TagId>,
     /// }
     /// ```
     ///
@@ -165,21 +951,23 @@ pub trait API: Send {
     /// In case of syntax error, Error 400, accompanied with a
     /// somewhat human-readable JSON string detailing the error.
     ///
+    /// If the caller is not authorized to edit tags, `PermissionDenied`.
+    ///
     /// ## Success
     ///
-    /// A JSON string representing a number.
-    fn put_node_tag(&self, set: &Vec<NodeSelector>, tags: &Vec<String>) -> usize;
+    /// A JSON representing a `TagResult`.
+    fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error>;
 
     /// Remove a set of tags from a set of nodes.
     ///
     /// A call to `API::delete_node_tag(vec![req1, req2, ...], vec![tag1,
     /// ...])` will remove from all the nodes matching _either_ `req1` or
-    /// `req2` or ... all of the tags `tag1`, ... and return the number of nodes
+    /// `req2` or ... all of the tags `tag1`, ... and return the ids of the nodes
     /// matching any of the selectors.
     ///
-    /// Some of the nodes may not be labelled with `tag1`, or `tag2`,
-    /// ... They will not change state. They are counted in the
-    /// resulting `usize` nevertheless.
+    /// Some of the nodes may not be labelled with any of `tag1`, `tag2`,
+    /// ... They will not change state. They are reported in
+    /// `TagResult::unchanged`.
     ///
     /// Note that this call is _not live_. In other words, if nodes
     /// are added after the call, they will not be affected.
@@ -188,14 +976,14 @@ pub trait API: Send {
     ///
     /// `DELETE /api/v1/nodes/tag`
     ///
-    /// ## Getters
+    /// ## Requests
     ///
     /// Any JSON that can be deserialized to
     ///
     /// ```ignore
     /// {
     ///   set: Vec<NodeSelector>,
-    ///   tags: Vec<String>,
+    ///   tags: TagId,
     /// }
     /// ```
     ///
@@ -204,136 +992,1194 @@ pub trait API: Send {
     /// In case of syntax error, Error 400, accompanied with a
     /// somewhat human-readable JSON string detailing the error.
     ///
+    /// If the caller is not authorized to edit tags, `PermissionDenied`.
+    ///
     /// ## Success
     ///
-    /// A JSON representing a number.
-    fn delete_node_tag(&self, set: &Vec<NodeSelector>, tags: String) -> usize;
+    /// A JSON representing a `TagResult`.
+    fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error>;
     
-    /// Get a list of getters matching some conditions
+    /// Register a live tagging rule.
+    ///
+    /// A call to `API::put_tagging_rule(rule)` will label all the nodes
+    /// matching _either_ `rule.set[0]` or `rule.set[1]` or ... with all
+    /// the tags of `rule.tags`, just as `API::put_node_tag`. In
+    /// addition, as long as the rule is registered, nodes added later
+    /// are labelled as well if they match any of the selectors. This
+    /// lets installers ensure that, for instance, all new devices
+    /// discovered by some vendor adapter inherit a room tag.
     ///
     /// # REST API
     ///
-    /// `GET /api/v1/channels`
-    fn get_getter_channels(&self, &Vec<GetterSelector>) -> Vec<Channel<Getter>>;
-    fn get_setter_channels(&self, &Vec<SetterSelector>) -> Vec<Channel<Setter>>;
-
-    /// Label a set of channels with a set of tags.
+    /// `POST /api/v1/nodes/tag/rules`
     ///
-    /// A call to `API::put_{getter, setter}_tag(vec![req1, req2, ...], vec![tag1,
-    /// ...])` will label all the channels matching _either_ `req1` or
-    /// `req2` or ... with `tag1`, ... and return the number of channels
-    /// matching any of the selectors.
+    /// ## Requests
     ///
-    /// Some of the channels may already be labelled with `tag1`, or
-    /// `tag2`, ... They will not change state. They are counted in
-    /// the resulting `usize` nevertheless.
+    /// Any JSON that can be deserialized to a `TaggingRule`.
     ///
-    /// Note that this call is _not live_. In other words, if channels
-    /// are added after the call, they will not be affected.
+    /// ## Errors
     ///
-    /// # REST API
+    /// `Unsupported(Capability::TaggingRules)` if the implementation
+    /// does not support live tagging rules.
+    ///
+    /// If the caller is not authorized to edit tags, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the id of the rule.
+    fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error>;
+
+    /// Unregister a live tagging rule.
+    ///
+    /// Nodes that have been labelled by the rule keep their tags.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/nodes/tag/rules`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the rule.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchTaggingRule` if the rule has never been registered or has
+    /// already been unregistered.
+    fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error>;
+
+    /// Get the list of live tagging rules currently registered.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/nodes/tag/rules`
+    fn get_tagging_rules(&self, caller: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)>;
+
+    /// Set the label of a set of nodes.
+    ///
+    /// A call to `API::set_node_label(vec![req1, req2, ...], label)`
+    /// will set the label of all the nodes matching _either_ `req1` or
+    /// `req2` or ... to `label`, and return the ids of these nodes.
+    /// Watchers are informed with `WatchEvent::LabelChanged`, so that
+    /// renaming a device in one client is reflected in all clients.
+    ///
+    /// # REST API
+    ///
+    /// `PUT /api/v1/nodes/label`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<NodeSelector>,
+    ///   label: String,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// If the caller is not authorized to edit labels, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of node ids.
+    fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error>;
+
+    /// Clear the label of a set of nodes.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/nodes/label`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to a `Vec<NodeSelector>`.
+    ///
+    /// ## Errors
+    ///
+    /// If the caller is not authorized to edit labels, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of node ids.
+    fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error>;
+
+    /// Get a list of getters matching some conditions
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels`
+    fn get_getter_channels(&self, caller: &Caller, &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>>;
+    fn get_setter_channels(&self, caller: &Caller, &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>>;
+
+    /// Visit the channels matching some conditions, one channel at a
+    /// time.
+    ///
+    /// These are variants of `API::get_{getter, setter}_channels` that
+    /// do not collect channels in a `Vec`. See `API::visit_nodes`.
+    fn visit_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>, cb: &mut FnMut(&Channel<Getter>)) {
+        for channel in self.get_getter_channels(caller, selectors) {
+            cb(&channel)
+        }
+    }
+    fn visit_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>, cb: &mut FnMut(&Channel<Setter>)) {
+        for channel in self.get_setter_channels(caller, selectors) {
+            cb(&channel)
+        }
+    }
+
+    /// Register an aggregate getter.
+    ///
+    /// The aggregate getter is a channel like any other: it appears in
+    /// the results of `API::get_getter_channels`, attached to a virtual
+    /// node of the FoxBox, and can be fetched and watched. Its value is
+    /// updated whenever one of the source getters produces a new value,
+    /// and whenever getters matching the selectors are added or removed.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/aggregates`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to an `AggregateGetter`.
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::AggregateGetters)` if the implementation
+    /// does not support aggregate getters.
+    ///
+    /// `TypeError` if values of `kind` do not support the aggregation,
+    /// e.g. the mean of colors.
+    ///
+    /// If the caller is not authorized to manage nodes, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the id of the aggregate getter.
+    fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error>;
+
+    /// Unregister an aggregate getter.
+    ///
+    /// Watches on the aggregate getter receive `WatchEvent::GetterRemoved`.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/aggregates`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the aggregate getter.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchGetter` if there is no such aggregate getter, including
+    /// if `id` is the id of a getter that is not an aggregate.
+    ///
+    /// If the caller is not authorized to manage nodes, `PermissionDenied`.
+    fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error>;
+
+    /// Get the list of aggregate getters currently registered.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/aggregates`
+    fn get_aggregate_getters(&self, caller: &Caller) -> Vec<(Id<Getter>, AggregateGetter)>;
+
+    /// Label a set of channels with a set of tags.
+    ///
+    /// A call to `API::put_{getter, setter}_tag(vec![req1, req2, ...], vec![tag1,
+    /// ...])` will label all the channels matching _either_ `req1` or
+    /// `req2` or ... with `tag1`, ... and return the ids of the channels
+    /// matching any of the selectors.
+    ///
+    /// Some of the channels may already be labelled with `tag1`, and
+    /// `tag2`, ... They will not change state. They are reported in
+    /// `TagResult::unchanged`.
+    ///
+    /// Note that this call is _not live_. In other words, if channels
+    /// are added after the call, they will not be affected.
+    ///
+    /// # REST API
     ///
     /// `POST /api/v1/channels/tag`
     ///
     /// ## Requests
     ///
-    /// Any JSON that can be deserialized to
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<GetterSelector>,
+    ///   tags: Vec<TagId>,
+    /// }
+    /// ```
+    /// or
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   tags: Vec<TagId>,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the caller is not authorized to edit tags, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing a `TagResult`.
+    fn put_getter_tag(&self, caller: &Caller, &Vec<GetterSelector>, &Vec<TagId>) -> Result<TagResult<Getter>, Error>;
+    fn put_setter_tag(&self, caller: &Caller, &Vec<SetterSelector>, &Vec<TagId>) -> Result<TagResult<Setter>, Error>;
+
+    /// Remove a set of tags from a set of channels.
+    ///
+    /// A call to `API::delete_{getter, setter}_tag(vec![req1, req2, ...], vec![tag1,
+    /// ...])` will remove from all the channels matching _either_ `req1` or
+    /// `req2` or ... all of the tags `tag1`, ... and return the ids of the channels
+    /// matching any of the selectors.
+    ///
+    /// Some of the channels may not be labelled with any of `tag1`, `tag2`,
+    /// ... They will not change state. They are reported in
+    /// `TagResult::unchanged`.
+    ///
+    /// Note that this call is _not live_. In other words, if channels
+    /// are added after the call, they will not be affected.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/tag`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<GetterSelector>,
+    ///   tags: Vec<TagId>,
+    /// }
+    /// ```
+    /// or
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   tags: Vec<TagId>,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// In case of syntax error, Error 400, accompanied with a
+    /// somewhat human-readable JSON string detailing the error.
+    ///
+    /// If the caller is not authorized to edit tags, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing a `TagResult`.
+    fn delete_getter_tag(&self, caller: &Caller, &Vec<GetterSelector>, &Vec<TagId>) -> Result<TagResult<Getter>, Error>;
+    fn delete_setter_tag(&self, caller: &Caller, &Vec<SetterSelector>, &Vec<TagId>) -> Result<TagResult<Setter>, Error>;
+
+    /// Set the label of a set of channels.
+    ///
+    /// A call to `API::set_{getter, setter}_label(vec![req1, req2, ...], label)`
+    /// will set the label of all the channels matching _either_ `req1` or
+    /// `req2` or ... to `label`, and return the ids of these channels.
+    /// Watchers are informed with `WatchEvent::LabelChanged`.
+    ///
+    /// # REST API
+    ///
+    /// `PUT /api/v1/channels/label`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<GetterSelector>,
+    ///   label: String,
+    /// }
+    /// ```
+    /// or
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   label: String,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// If the caller is not authorized to edit labels, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of channel ids.
+    fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error>;
+    fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error>;
+
+    /// Clear the label of a set of channels.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/label`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to a `Vec<GetterSelector>`
+    /// or a `Vec<SetterSelector>`.
+    ///
+    /// ## Errors
+    ///
+    /// If the caller is not authorized to edit labels, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of channel ids.
+    fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error>;
+    fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error>;
+
+    /// Read the latest value from a set of channels
+    ///
+    /// Each value is accompanied with the date at which it was
+    /// produced, whether it was served from a cache or obtained from
+    /// the device, and the adapter that supplied it, so that clients
+    /// can display the freshness of the data.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/value`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of pairs `[id, result]`, where
+    /// `result` is either `{"Ok": FetchedValue}` or `{"Err": Error}`.
+    ///
+    /// ## Errors
+    ///
+    /// For each channel that emits events, and thus has no current
+    /// value, `EventChannel`. Watch the channel, or read its history,
+    /// instead.
+    fn get_channel_value(&self, caller: &Caller, &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)>;
+
+    /// Poll a set of channels immediately, rather than waiting for
+    /// the next scheduled poll, and return the fresh values.
+    ///
+    /// The minimal interval between two polls of a channel (see
+    /// `Getter::poll`) is respected: if a channel has been polled too
+    /// recently, the value obtained by the latest poll is returned.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/poll`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to a `Vec<GetterSelector>`.
+    ///
+    /// ## Errors
+    ///
+    /// For each channel that only pushes data to the FoxBox and cannot
+    /// be polled, `NotPollable`.
+    ///
+    /// For each channel that emits events, `EventChannel`.
+    ///
+    /// For each channel, `Unsupported(Capability::PollNow)` if the
+    /// implementation does not support forcing polls.
+    fn poll_now(&self, caller: &Caller, &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)>;
+
+    /// Read all the values produced by a set of channels since a date,
+    /// oldest first, each with the date at which it was produced.
+    ///
+    /// Some devices, e.g. pollution sensors, buffer their readings and
+    /// deliver them in batches covering several hours. Where
+    /// `API::get_channel_value` only returns the latest reading, this
+    /// returns every reading that the adapter still holds. Adapters
+    /// that do not buffer readings report their latest value only.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/values/since`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON object `{ selectors: Vec<GetterSelector>, since: TimeStamp }`.
+    ///
+    /// ## Errors
+    ///
+    /// For each channel, `Unsupported(Capability::MultiSampleFetch)` if
+    /// the implementation does not support fetching several values.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of pairs `[id, result]`, where
+    /// `result` is either `{"Ok": Vec<Sample>}` or `{"Err": Error}`.
+    fn get_channel_values_since(&self, caller: &Caller, &Vec<GetterSelector>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)>;
+
+    /// Send one value to a set of channels
+    ///
+    /// The write carries a priority. If a write with a higher priority
+    /// has been sent recently to some of the channels, e.g. a user
+    /// explicitly turned on a light that a rule attempts to turn off,
+    /// the implementation may suppress the write for these channels.
+    /// See `arbitration::ArbitrationPolicy`.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/value`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   value: Value,
+    ///   priority: Priority, // Optional, defaults to `Automation`.
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// For each channel to which the caller is not authorized to send
+    /// the value, `PermissionDenied`.
+    ///
+    /// For each channel reserved by another caller, `Reserved`.
+    ///
+    /// For each channel for which the write has lost arbitration,
+    /// `Suppressed`, along with the winning write.
+    ///
+    /// ## Success
+    ///
+    /// For each channel, an `Acknowledgement`, which carries the
+    /// resulting state of the device if the adapter confirms actuation,
+    /// so that clients may display the actual state of the device
+    /// rather than assume that the value has been applied.
+    fn put_channel_value(&self, caller: &Caller, &Vec<SetterSelector>, Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)>;
+
+    /// Read the latest value of a state, i.e. of the getter paired with
+    /// its setter. See `devices::StateChannel`.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/states/value`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the state.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchState` if there is no such state.
+    ///
+    /// `Unsupported(Capability::StateChannels)` if the implementation
+    /// does not support states.
+    ///
+    /// Otherwise, the errors of `API::get_channel_value`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing a `FetchedValue`, or `null` if the getter has
+    /// no value yet.
+    fn get_state_value(&self, caller: &Caller, id: &Id<StateId>) -> Result<Option<FetchedValue>, Error>;
+
+    /// Change a state, i.e. send a value to the setter paired with its
+    /// getter. Together with `API::get_state_value`, this lets clients
+    /// e.g. toggle a lamp through a single id. See
+    /// `devices::StateChannel`.
+    ///
+    /// # REST API
+    ///
+    /// `PUT /api/v1/states/value`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   state: Id<StateId>,
+    ///   value: Value,
+    ///   priority: Priority, // Optional, defaults to `Automation`.
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchState` if there is no such state.
+    ///
+    /// `Unsupported(Capability::StateChannels)` if the implementation
+    /// does not support states.
+    ///
+    /// Otherwise, the errors of `API::put_channel_value`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an `Acknowledgement`.
+    fn put_state_value(&self, caller: &Caller, id: &Id<StateId>, value: Value, priority: Priority) -> Result<Acknowledgement, Error>;
+
+    /// Schedule sending one value to a set of channels at a given date.
+    ///
+    /// Once the date is reached, the value is sent as if `API::put_channel_value`
+    /// had been called by the same caller with the same selectors and
+    /// priority `Priority::Automation`. Note
+    /// that the selectors are evaluated at that date, not at the time of
+    /// scheduling. This lets applications implement e.g. "turn off the
+    /// heater in two hours" without running their own timers.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/value/scheduled`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   value: Value,
+    ///   at: TimeStamp,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::ScheduledWrites)` if the implementation
+    /// does not support scheduling writes.
+    ///
+    /// If the caller is not authorized to send values, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the id of the scheduled write, which
+    /// may be used to cancel it.
+    fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error>;
+
+    /// Schedule sending one value to a set of channels after a delay.
+    ///
+    /// See `API::put_channel_value_at`.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/value/scheduled`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   value: Value,
+    ///   delay: ValDuration,
+    /// }
+    /// ```
+    fn put_channel_value_after(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, delay: ValDuration) -> Result<Id<ScheduleId>, Error> {
+        self.put_channel_value_at(caller, set, value, TimeStamp::now().plus(&delay))
+    }
+
+    /// Get the list of writes scheduled and not executed yet.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/value/scheduled`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `ScheduledWrite`.
+    fn get_scheduled_writes(&self, caller: &Caller) -> Vec<ScheduledWrite>;
+
+    /// Cancel a scheduled write that has not been executed yet.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/value/scheduled`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the scheduled write.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchSchedule` if there is no such scheduled write, e.g.
+    /// because it has already been executed or cancelled.
+    ///
+    /// If the caller did not schedule the write, `PermissionDenied`.
+    fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error>;
+
+    /// Reserve a set of setters for exclusive use by the caller.
+    ///
+    /// While the reservation is active, any value sent to one of the
+    /// setters by another caller is rejected with `Error::Reserved`.
+    /// This is designed for operations that must not be disturbed, e.g.
+    /// a firmware update or a calibration session. The reservation
+    /// expires automatically after `duration`, so that a crashed
+    /// application cannot lock devices forever.
+    ///
+    /// Reservations are all-or-nothing: if any of the setters matching
+    /// the selectors is already reserved by another caller, nothing is
+    /// reserved.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/reservations`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   set: Vec<SetterSelector>,
+    ///   duration: ValDuration,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// `Reserved` if one of the setters is already reserved by another
+    /// caller.
+    ///
+    /// `Unsupported(Capability::Reservations)` if the implementation
+    /// does not support reservations.
+    ///
+    /// If the caller is not authorized to send values, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing a `Reservation`.
+    fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error>;
+
+    /// Release a reservation before it expires.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/reservations`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the reservation.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchReservation` if there is no such reservation, e.g. because
+    /// it has already expired or been released.
+    ///
+    /// If the caller is not the holder of the reservation, `PermissionDenied`.
+    fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error>;
+
+    /// Get the list of active reservations.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/reservations`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `Reservation`.
+    fn get_reservations(&self, caller: &Caller) -> Vec<Reservation>;
+
+    /// Request that a set of getters be polled at least every
+    /// `interval`, e.g. while a dashboard displaying them is open.
+    ///
+    /// The implementation reconciles the hints of all callers with the
+    /// interval declared by each getter (see `Getter::poll`), which is
+    /// the shortest interval at which the getter may be polled: a
+    /// getter is polled at the shortest interval requested, but never
+    /// more often than it declares. Getters that push their values or
+    /// cannot be polled are not affected. The hint expires
+    /// automatically after `duration`, so that a closed dashboard does
+    /// not keep devices busy; callers renew it with a new hint while
+    /// they need fresh values.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/polling`
+    ///
+    /// ## Requests
+    ///
+    /// Any JSON that can be deserialized to
+    ///
+    /// ```ignore
+    /// {
+    ///   source: Vec<GetterSelector>,
+    ///   interval: ValDuration,
+    ///   duration: ValDuration,
+    /// }
+    /// ```
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::PollingHints)` if the implementation
+    /// does not support polling hints.
+    ///
+    /// If the caller is not authorized to watch, `PermissionDenied`.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing a `PollingHint`.
+    fn put_polling_hint(&self, caller: &Caller, source: &Vec<GetterSelector>, interval: ValDuration, duration: ValDuration) -> Result<PollingHint, Error>;
+
+    /// Withdraw a polling hint before it expires.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/polling`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the hint.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchPollingHint` if there is no such hint, e.g. because it
+    /// has already expired or been withdrawn.
+    ///
+    /// If the caller is not the owner of the hint, `PermissionDenied`.
+    fn delete_polling_hint(&self, caller: &Caller, id: &Id<PollingHintId>) -> Result<(), Error>;
+
+    /// Get the list of active polling hints.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/polling`
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `PollingHint`.
+    fn get_polling_hints(&self, caller: &Caller) -> Vec<PollingHint>;
+
+    /// Watch for any change
+    ///
+    /// # WebSocket API
+    ///
+    /// `/api/v1/channels/watch`
+    ///
+    /// The messages exchanged over the WebSocket are described in
+    /// module `websocket`.
+    ///
+    /// ## Errors
+    ///
+    /// If the caller is not authorized to watch, `PermissionDenied`.
+    fn register_channel_watch(&self, caller: &Caller, Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error>;
+
+    /// Watch for any change, receiving events through a channel
+    /// rather than a callback.
+    ///
+    /// This lets clients integrate watches into their own event loop,
+    /// or select across several watches. Events are delivered as long
+    /// as the guard is active. Once it is unregistered or dropped, the
+    /// channel is closed.
+    ///
+    /// Note that events are forwarded to the channel as soon as they
+    /// are delivered, so the channel itself is unbounded, regardless
+    /// of `WatchOptions::with_backpressure`.
+    fn register_channel_watch_channel(&self, caller: &Caller, options: Vec<WatchOptions>) -> Result<(Receiver<WatchEvent>, Self::WatchGuard), Error> {
+        let (tx, rx) = channel();
+        let guard = try!(self.register_channel_watch(caller, options, Box::new(move |event| {
+            // If the receiver has been dropped, there is nobody left
+            // to notify.
+            let _ = tx.send(event);
+        })));
+        Ok((rx, guard))
+    }
+
+    /// Watch for any change, identifying the watch by a token rather
+    /// than by a guard.
+    ///
+    /// This variant is designed for clients that cannot hold a
+    /// `WatchGuard` across requests, e.g. REST handlers. The watch
+    /// remains active until `unregister_watch` is called with the
+    /// token returned by this call.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/watch`
+    ///
+    /// The events may then be received as Server-Sent Events. See
+    /// module `sse`.
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::WatchTokens)` if the implementation
+    /// does not support this variant.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the token.
+    fn register_channel_watch_with_token(&self, caller: &Caller, Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error>;
+
+    /// Unregister a watch previously registered with
+    /// `register_channel_watch_with_token`.
+    ///
+    /// # REST API
+    ///
+    /// `DELETE /api/v1/channels/watch`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the token.
     ///
-    /// ```ignore
-    /// {
-    ///   set: Vec<GetterSelector>,
-    ///   tags: Vec<String>,
-    /// }
-    /// ```
-    /// or
-    /// ```ignore
-    /// {
-    ///   set: Vec<SetterSelector>,
-    ///   tags: Vec<String>,
-    /// }
-    /// ```
+    /// ## Errors
+    ///
+    /// `NoSuchWatch` if the watch has never been registered or has
+    /// already been unregistered.
+    ///
+    /// If the caller is not authorized to watch, or is not the caller
+    /// that registered the watch, `PermissionDenied`.
+    ///
+    /// `WatchStore` if the watch was durable and could not be removed
+    /// from storage. The watch is unregistered nonetheless, but may be
+    /// resumed after a restart.
+    fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error>;
+
+    /// Get the list of watches currently active, whether they have
+    /// been registered with a guard or with a token.
+    ///
+    /// Designed to help diagnose runaway subscriptions and to let
+    /// clients reconcile their state after a reconnection.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/watch`
     ///
     /// ## Errors
     ///
-    /// In case of syntax error, Error 400, accompanied with a
-    /// somewhat human-readable JSON string detailing the error.
+    /// `Unsupported(Capability::WatchIntrospection)` if the implementation
+    /// does not keep track of this information.
     ///
     /// ## Success
     ///
-    /// A JSON representing a number.
-    fn put_getter_tag(&self, &Vec<GetterSelector>, &Vec<String>) -> usize;
-    fn put_setter_tag(&self, &Vec<SetterSelector>, &Vec<String>) -> usize;
+    /// A JSON representing an array of `WatchInfo`.
+    fn get_watches(&self, caller: &Caller) -> Result<Vec<WatchInfo>, Error>;
 
-    /// Remove a set of tags from a set of channels.
+    /// Get the health of each adapter, including the result of a
+    /// health check performed for this request, along with statistics
+    /// on the fetches and sends handled by the adapter since it was
+    /// registered.
     ///
-    /// A call to `API::delete_{getter, setter}_tag(vec![req1, req2, ...], vec![tag1,
-    /// ...])` will remove from all the channels matching _either_ `req1` or
-    /// `req2` or ... all of the tags `tag1`, ... and return the number of channels
-    /// matching any of the selectors.
+    /// Designed to let frontends display the status of adapters and
+    /// alert users when an adapter degrades.
     ///
-    /// Some of the channels may not be labelled with `tag1`, or `tag2`,
-    /// ... They will not change state. They are counted in the
-    /// resulting `usize` nevertheless.
+    /// # REST API
     ///
-    /// Note that this call is _not live_. In other words, if channels
-    /// are added after the call, they will not be affected.
+    /// `GET /api/v1/adapters/health`
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::AdapterHealth)` if the implementation
+    /// does not keep track of this information.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `AdapterHealth`.
+    fn get_adapter_health(&self, caller: &Caller) -> Result<Vec<AdapterHealth>, Error>;
+
+    /// Get the counters and latency histograms of adapters and
+    /// channels, e.g. to find out which devices are noisy or failing.
+    /// See module `metrics`.
     ///
     /// # REST API
     ///
-    /// `DELETE /api/v1/channels/tag`
+    /// `GET /api/v1/metrics`
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::Metrics)` if the implementation does not
+    /// keep track of metrics.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `metrics::Metrics`, or, if the
+    /// request has header `Accept: text/plain`, the same metrics in the
+    /// Prometheus text exposition format. See `metrics::to_prometheus`.
+    fn get_metrics(&self, caller: &Caller) -> Result<Vec<Metrics>, Error>;
+
+    /// Get the options of the configuration of an adapter, as declared
+    /// by the adapter, along with their current value.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/adapters/config`
     ///
     /// ## Requests
     ///
-    /// Any JSON that can be deserialized to
+    /// A JSON string representing the id of the adapter.
     ///
-    /// ```ignore
-    /// {
-    ///   set: Vec<GetterSelector>,
-    ///   tags: Vec<String>,
-    /// }
-    /// ```
-    /// or
-    /// ```ignore
-    /// {
-    ///   set: Vec<SetterSelector>,
-    ///   tags: Vec<String>,
-    /// }
-    /// ```
+    /// ## Errors
+    ///
+    /// `NoSuchAdapter` if the adapter is not registered.
+    ///
+    /// `Unsupported(Capability::AdapterConfig)` if the implementation
+    /// does not support configuring adapters.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `ConfigEntry`.
+    fn get_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error>;
+
+    /// Change an option of the configuration of an adapter. The
+    /// adapter is informed of the change, and may reject it.
+    ///
+    /// # REST API
+    ///
+    /// `PUT /api/v1/adapters/config`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON object `{ adapter: Id<AdapterId>, key: String, value:
+    /// Value }`.
     ///
     /// ## Errors
     ///
-    /// In case of syntax error, Error 400, accompanied with a
-    /// somewhat human-readable JSON string detailing the error.
+    /// `NoSuchAdapter` if the adapter is not registered.
+    ///
+    /// `NoSuchConfigOption` if the adapter does not declare this option.
+    ///
+    /// `TypeError` if the value does not have the type declared by the
+    /// adapter.
+    ///
+    /// `Adapter` if the adapter has rejected the change.
+    ///
+    /// `Unsupported(Capability::AdapterConfig)` if the implementation
+    /// does not support configuring adapters.
+    fn put_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error>;
+
+    /// Get the values recorded for a set of getters, oldest first,
+    /// between `since` and `until`, inclusive. Only the getters whose
+    /// values are recorded are reported.
+    ///
+    /// # REST API
+    ///
+    /// `GET /api/v1/channels/history`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON object `{ selectors: Vec<GetterSelector>, since:
+    /// Option<TimeStamp>, until: Option<TimeStamp> }`.
+    ///
+    /// ## Errors
+    ///
+    /// `HistoryStore` if the history could not be read.
+    ///
+    /// `Unsupported(Capability::History)` if the implementation does
+    /// not record values.
     ///
     /// ## Success
     ///
-    /// A JSON representing a number.
-    fn delete_getter_tag(&self, &Vec<GetterSelector>, &Vec<String>) -> usize;
-    fn delete_setter_tag(&self, &Vec<SetterSelector>, &Vec<String>) -> usize;
+    /// A JSON representing an array of pairs of getter ids and arrays
+    /// of `Sample`.
+    fn get_channel_history(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error>;
 
-    /// Read the latest value from a set of channels
+    /// Get the values recorded for a set of getters between `since` and
+    /// `until`, inclusive, combined per bucket of duration `bucket`,
+    /// oldest first, e.g. the mean temperature of each hour of the last
+    /// week. Buckets without samples are omitted. See
+    /// `history::aggregate`.
     ///
     /// # REST API
     ///
-    /// `GET /api/v1/channels/value`
-    fn get_channel_value(&self, &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<Value, Error>)>;
+    /// `GET /api/v1/channels/history/aggregate`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON object `{ selectors: Vec<GetterSelector>, since:
+    /// Option<TimeStamp>, until: Option<TimeStamp>, bucket: ValDuration,
+    /// aggregation: Aggregation }`.
+    ///
+    /// ## Errors
+    ///
+    /// `HistoryStore` if the history could not be read.
+    ///
+    /// `Unsupported(Capability::History)` if the implementation does
+    /// not record values.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of pairs of getter ids and arrays
+    /// of `Bucket`.
+    fn get_channel_history_aggregate(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>,
+                                     bucket: ValDuration, aggregation: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error>;
 
-    /// Send one value to a set of channels
+    /// Get the devices found on the network by adapters, whether they
+    /// have been adopted, rejected or are waiting for a decision.
     ///
     /// # REST API
     ///
-    /// `POST /api/v1/channels/value`
-    fn put_channel_value(&self, &Vec<SetterSelector>, Value) -> Vec<(Id<Setter>, Result<(), Error>)>;
+    /// `GET /api/v1/candidates`
+    ///
+    /// ## Errors
+    ///
+    /// `Unsupported(Capability::Discovery)` if the implementation does
+    /// not support discovery.
+    ///
+    /// ## Success
+    ///
+    /// A JSON representing an array of `Candidate`.
+    fn get_candidates(&self, caller: &Caller) -> Result<Vec<Candidate>, Error>;
 
-    /// Watch for any change
+    /// Adopt a device found on the network. The device is adopted by
+    /// `adapter`, or by the first adapter that has announced it if
+    /// `None`, which then registers the corresponding nodes.
     ///
-    /// # WebSocket API
+    /// # REST API
     ///
-    /// `/api/v1/channels/watch`
-    fn register_channel_watch(&self, Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Self::WatchGuard;
+    /// `PUT /api/v1/candidates/adopt`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON object `{ candidate: Id<CandidateId>, adapter:
+    /// Option<Id<AdapterId>> }`.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchCandidate` if no adapter announces this device, or if
+    /// `adapter` does not announce it.
+    ///
+    /// `Adapter` if the adapter has failed to adopt the device.
+    ///
+    /// `Unsupported(Capability::Discovery)` if the implementation does
+    /// not support discovery.
+    fn adopt_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>, adapter: Option<Id<AdapterId>>) -> Result<(), Error>;
+
+    /// Reject a device found on the network. The device will not be
+    /// offered again, even if it is announced again, but may still be
+    /// adopted with `adopt_candidate`.
+    ///
+    /// # REST API
+    ///
+    /// `PUT /api/v1/candidates/reject`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the id of the candidate.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchCandidate` if the device has never been announced.
+    ///
+    /// `Unsupported(Capability::Discovery)` if the implementation does
+    /// not support discovery.
+    fn reject_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>) -> Result<(), Error>;
+
+    /// Register a watch that survives a restart of the FoxBox, under
+    /// an id chosen by its owner, e.g. "rules/turn-on-the-light". Any
+    /// durable watch previously registered under the same owner is
+    /// replaced. The watch is identified by a token, as with
+    /// `register_channel_watch_with_token`, and remains durable until
+    /// it is unregistered with `unregister_watch`.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/watch/durable`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON object `{ owner: String, options: Vec<WatchOptions> }`.
+    ///
+    /// ## Errors
+    ///
+    /// `WatchStore` if the watch could not be saved. The watch is not
+    /// registered.
+    ///
+    /// `Unsupported(Capability::DurableWatches)` if the implementation
+    /// cannot persist watches.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the token.
+    fn register_durable_watch(&self, caller: &Caller, owner: String, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error>;
+
+    /// Re-establish a watch registered with `register_durable_watch`,
+    /// typically after a restart, with the same options. Before this
+    /// method returns, `cb` receives `GetterAdded`/`GetterRemoved` for
+    /// the changes of topology since the watch was last active.
+    ///
+    /// # REST API
+    ///
+    /// `POST /api/v1/channels/watch/resume`
+    ///
+    /// ## Requests
+    ///
+    /// A JSON string representing the owner.
+    ///
+    /// ## Errors
+    ///
+    /// `NoSuchSubscription` if no durable watch has been registered
+    /// under this owner.
+    ///
+    /// `WatchStore` if the watch could not be read.
+    ///
+    /// `Unsupported(Capability::DurableWatches)` if the implementation
+    /// cannot persist watches.
+    ///
+    /// ## Success
+    ///
+    /// A JSON string representing the token.
+    fn resume_watch(&self, caller: &Caller, owner: &str, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error>;
 
     /// A value that causes a disconnection once it is dropped.
-    type WatchGuard;
+    type WatchGuard: WatchGuard;
+}
+
+/// A marker for Id.
+/// Only useful for writing `Id<WatchId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct WatchId;
+
+/// A handle on a watch subscription.
+///
+/// Dropping the guard unregisters the watch. The watch may also be
+/// unregistered explicitly, e.g. to make sure that no further events
+/// are delivered from a given point.
+pub trait WatchGuard: Send {
+    /// An id for this subscription, stable for its whole lifetime.
+    /// Designed to aid with tracing and debugging.
+    fn id(&self) -> &Id<WatchId>;
+
+    /// Unregister the watch. Once this method has returned, the
+    /// callback will not be called anymore.
+    ///
+    /// Calling this method on a watch that is not active anymore
+    /// has no effect.
+    fn unregister(&mut self);
+
+    /// Determine whether the watch is still active, i.e. whether
+    /// events may still be delivered to the callback.
+    fn is_active(&self) -> bool;
+}
+
+/// Metadata on an active watch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchInfo {
+    /// The id of the watch, i.e. the id of its guard or its token.
+    pub id: Id<WatchId>,
+
+    /// The options with which the watch was registered.
+    pub options: Vec<WatchOptions>,
+
+    /// The owner of the watch, as specified with
+    /// `WatchOptions::with_owner`. If several options specify an
+    /// owner, this is the first one.
+    pub owner: Option<String>,
+
+    /// The number of events delivered so far.
+    pub events: u64,
+
+    /// The number of events dropped so far because the callback did
+    /// not consume them fast enough. See `WatchOptions::with_backpressure`.
+    pub dropped: u64,
+
+    /// Date at which the watch was registered.
+    pub created: TimeStamp,
 }
 
 /// Options for watching changes in one or more channels.
@@ -349,6 +2195,43 @@ pub struct WatchOptions {
     /// If `true`, watch as nodes are connected/disconnected.
     pub should_watch_topology: bool,
 
+    /// If `Some(owner)`, a human-readable description of whoever
+    /// registered the watch, e.g. an application name. Designed to
+    /// aid with tracing and debugging.
+    #[serde(default)]
+    pub owner: Option<String>,
+
+    /// If `Some(batching)`, events are delivered in batches rather
+    /// than one at a time. See `WatchOptions::with_batching`.
+    #[serde(default)]
+    pub batching: Option<Batching>,
+
+    /// If `Some(backpressure)`, the number of events waiting for
+    /// delivery is bounded. See `WatchOptions::with_backpressure`.
+    #[serde(default)]
+    pub backpressure: Option<Backpressure>,
+
+    /// If `Some(emulation)`, how to emulate watching getters that do
+    /// not support watching. See `WatchOptions::with_emulation`.
+    #[serde(default)]
+    pub emulation: Option<Emulation>,
+
+    /// If `Some(duration)`, the watch expires `duration` after its
+    /// registration. See `WatchOptions::with_expires_after`.
+    #[serde(default)]
+    pub expires_after: Option<ValDuration>,
+
+    /// If `Some(n)`, the watch expires once it has delivered `n`
+    /// events. See `WatchOptions::with_max_events`.
+    #[serde(default)]
+    pub max_events: Option<u64>,
+
+    /// If `Some(delta)`, values are only delivered once they differ
+    /// from the latest value delivered by at least `delta`. See
+    /// `WatchOptions::with_min_delta`.
+    #[serde(default)]
+    pub min_delta: Option<Value>,
+
     /// Make sure that we can't instantiate from another crate.
     #[serde(default, skip_serializing)]
     private: (),
@@ -360,6 +2243,13 @@ impl WatchOptions {
             source: GetterSelector::new(),
             should_watch_values: false,
             should_watch_topology: false,
+            owner: None,
+            batching: None,
+            backpressure: None,
+            emulation: None,
+            expires_after: None,
+            max_events: None,
+            min_delta: None,
             private: (),
         }
     }
@@ -390,4 +2280,192 @@ impl WatchOptions {
             ..self
         }
     }
+
+    /// Record the owner of the watch, as reported by `API::get_watches`.
+    pub fn with_owner(self, owner: String) -> Self {
+        WatchOptions {
+            owner: Some(owner),
+            ..self
+        }
+    }
+
+    /// Deliver events in batches, as instances of `WatchEvent::Batch`.
+    ///
+    /// A batch is delivered as soon as it holds `max_events` events,
+    /// or `max_delay` after its first event has been received,
+    /// whichever comes first. This considerably reduces the overhead
+    /// of watching high-frequency sensors.
+    ///
+    /// If several of the options passed to `API::register_channel_watch`
+    /// request batching, the smallest `max_delay` and the smallest
+    /// `max_events` prevail.
+    pub fn with_batching(self, max_delay: ValDuration, max_events: usize) -> Self {
+        WatchOptions {
+            batching: Some(Batching {
+                max_delay: max_delay,
+                max_events: max_events,
+            }),
+            ..self
+        }
+    }
+
+    /// Bound the number of events waiting for delivery to the callback.
+    ///
+    /// By default, events that have not been delivered yet are held
+    /// in an unbounded buffer, so a slow callback (e.g. one writing
+    /// to a slow WebSocket client) may cause unbounded memory growth.
+    /// With this option, at most `capacity` events are held, and
+    /// `policy` determines what happens once the buffer is full.
+    /// Dropped events are counted in `WatchInfo::dropped`, and each run
+    /// of dropped events is reported to the callback, where the events
+    /// would have been delivered, with `WatchEvent::Missed`.
+    ///
+    /// If several of the options passed to `API::register_channel_watch`
+    /// request backpressure, the smallest `capacity` prevails, along
+    /// with the policy of the first option.
+    pub fn with_backpressure(self, capacity: usize, policy: OverflowPolicy) -> Self {
+        WatchOptions {
+            backpressure: Some(Backpressure {
+                capacity: capacity,
+                policy: policy,
+            }),
+            ..self
+        }
+    }
+
+    /// Configure how watching is emulated for getters that do not
+    /// support watching.
+    ///
+    /// Implementations may emulate watching such getters by polling
+    /// them. By default, getters are polled at the interval declared
+    /// by `Getter::poll`, and a value is delivered only if it differs
+    /// from the previous value. With this option, getters are polled
+    /// every `interval`, if specified, including getters that do not
+    /// declare a polling interval, and `change` determines which
+    /// values are delivered.
+    ///
+    /// If several of the options passed to `API::register_channel_watch`
+    /// match a getter, the smallest `interval` prevails, and values are
+    /// delivered if any option requests `ChangeDetection::Always`.
+    /// Implementations that do not emulate watching ignore this option.
+    pub fn with_emulation(self, interval: Option<ValDuration>, change: ChangeDetection) -> Self {
+        WatchOptions {
+            emulation: Some(Emulation {
+                interval: interval,
+                change: change,
+            }),
+            ..self
+        }
+    }
+
+    /// Unregister the watch `duration` after its registration.
+    ///
+    /// Once the watch has expired, the callback receives
+    /// `WatchEvent::Expired`, then no further event, as if the guard
+    /// had been dropped. This lets clients wait for a limited time
+    /// without keeping track of the guard.
+    ///
+    /// If several of the options passed to `API::register_channel_watch`
+    /// specify a duration, the shortest prevails.
+    pub fn with_expires_after(self, duration: ValDuration) -> Self {
+        WatchOptions {
+            expires_after: Some(duration),
+            ..self
+        }
+    }
+
+    /// Unregister the watch once it has delivered `max_events` events,
+    /// e.g. 1 to be notified the next time a door opens.
+    ///
+    /// Once the watch has expired, the callback receives
+    /// `WatchEvent::Expired`, then no further event. Markers
+    /// `WatchEvent::Missed` do not count as events.
+    ///
+    /// If several of the options passed to `API::register_channel_watch`
+    /// specify a number of events, the smallest prevails. This option
+    /// may be combined with `with_expires_after`, in which case the
+    /// watch expires as soon as either limit is reached.
+    pub fn with_max_events(self, max_events: u64) -> Self {
+        WatchOptions {
+            max_events: Some(max_events),
+            ..self
+        }
+    }
+
+    /// Only deliver a value if it differs from the latest value
+    /// delivered for the same getter by at least `delta`, e.g.
+    /// `Value::Temperature(Temperature::C(0.5))` to ignore a sensor
+    /// that jitters by hundredths of a degree. See `Value::differs_by`
+    /// for the meaning of `delta` for each type of value.
+    ///
+    /// The first value of each getter is always delivered, as are
+    /// errors, and values for which `delta` is meaningless, e.g.
+    /// strings.
+    ///
+    /// If several of the options passed to `API::register_channel_watch`
+    /// match a getter, a value is delivered if any of them accepts it.
+    pub fn with_min_delta(self, delta: Value) -> Self {
+        WatchOptions {
+            min_delta: Some(delta),
+            ..self
+        }
+    }
+}
+
+/// Configuration for emulating watches by polling.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Emulation {
+    /// The interval between two polls, or `None` to use the interval
+    /// declared by `Getter::poll`.
+    #[serde(default)]
+    pub interval: Option<ValDuration>,
+
+    /// Which of the values obtained by polling are delivered.
+    pub change: ChangeDetection,
+}
+
+/// Which of the values obtained by polling a getter are delivered.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ChangeDetection {
+    /// Deliver every value, even if it is equal to the previous one.
+    Always,
+
+    /// Deliver a value only if it differs from the previous one.
+    OnChange,
+}
+
+/// Configuration for bounding the number of events waiting for delivery.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Backpressure {
+    /// The largest number of events waiting for delivery.
+    pub capacity: usize,
+
+    /// What to do once `capacity` events are waiting for delivery.
+    pub policy: OverflowPolicy,
+}
+
+/// What to do with a new event once the buffer of a watch is full.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Drop the oldest event in the buffer to make room for the new one.
+    DropOldest,
+
+    /// Drop the new event.
+    DropNewest,
+
+    /// Block the producer of the event until there is room in the
+    /// buffer. Note that this may slow down delivery to other
+    /// watches, or even adapters.
+    BlockProducer,
+}
+
+/// Configuration for delivering watch events in batches.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Batching {
+    /// The longest duration during which an event may be held before
+    /// being delivered.
+    pub max_delay: ValDuration,
+
+    /// The largest number of events in a single batch.
+    pub max_events: usize,
 }
@@ -0,0 +1,428 @@
+//!
+//! Generators of the core types, for property-based testing.
+//!
+//! This module implements `quickcheck::Arbitrary` for types, values,
+//! ranges, channel kinds, tags, selectors and topologies, so that
+//! properties of the taxonomy, e.g. that `Range::contains` agrees with
+//! the ordering of values, or that values survive a round trip through
+//! serde, may be checked against many random inputs, and failing inputs
+//! shrunk to a minimal counterexample. Selectors cannot be built field
+//! by field outside of this crate, hence the generators live here.
+//!
+//! Ids and tags are drawn from small pools, so that generated selectors
+//! have a fair chance of matching the nodes and channels of generated
+//! topologies. Values are shrunk without changing their type, so that
+//! the bounds of a shrunk range remain comparable.
+//!
+//! # Example
+//!
+//! ```ignore
+//! extern crate quickcheck;
+//! use foxbox_taxonomy::arbitrary::Topology;
+//! use foxbox_taxonomy::selector::NodeSelector;
+//!
+//! fn matches_all(topology: Topology) -> bool {
+//!     topology.nodes.iter().all(|node| NodeSelector::new().matches(node))
+//! }
+//! quickcheck::quickcheck(matches_all as fn(Topology) -> bool);
+//! ```
+//!
+
+use devices::{ Channel, ChannelKind, Getter, Node, NodeId, Setter };
+use selector::{ GetterSelector, NodeSelector, SetterSelector };
+use util::{ Exactly, Id, TagId };
+use values::{ Bytes, Color, ExtNumeric, Json, Range, Temperature, TimeStamp, Type, ValDuration, Value };
+
+use quickcheck::{ empty_shrinker, Arbitrary, Gen };
+use serde_json::value::Value as JSON;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The adapter managing the nodes and channels of generated topologies,
+/// and introducing generated extension kinds.
+pub const ADAPTER: &'static str = "arbitrary@foxlink.mozilla.com";
+
+/// The vendor of generated extension kinds and values.
+pub const VENDOR: &'static str = "foxlink@mozilla.com";
+
+/// The number of distinct node ids, and the largest number of nodes in
+/// a topology.
+const NODES: usize = 8;
+
+/// The largest number of getters, and of setters, of a node.
+const CHANNELS: usize = 4;
+
+const TAGS: &'static [&'static str] = &["kitchen", "entrance", "bedroom", "battery", "plugged"];
+
+const EXTENSIONS: &'static [&'static str] = &["GroundHumidity", "Luminance"];
+
+const MIMETYPES: &'static [&'static str] = &["application/octet-stream", "image/png", "text/plain"];
+
+/// An integer in `0..len`, for `len > 0`.
+fn pick<G: Gen>(g: &mut G, len: usize) -> usize {
+    usize::arbitrary(g) % len
+}
+
+fn node_id_at(index: usize) -> Id<NodeId> {
+    Id::new(format!("{}/node-{}", ADAPTER, index))
+}
+
+fn getter_id_at(node: &Id<NodeId>, index: usize) -> Id<Getter> {
+    Id::new(format!("{}/getter-{}", node.as_string(), index))
+}
+
+fn setter_id_at(node: &Id<NodeId>, index: usize) -> Id<Setter> {
+    Id::new(format!("{}/setter-{}", node.as_string(), index))
+}
+
+fn node_id<G: Gen>(g: &mut G) -> Id<NodeId> {
+    node_id_at(pick(g, NODES))
+}
+
+fn getter_id<G: Gen>(g: &mut G) -> Id<Getter> {
+    let node = node_id(g);
+    getter_id_at(&node, pick(g, CHANNELS))
+}
+
+fn setter_id<G: Gen>(g: &mut G) -> Id<Setter> {
+    let node = node_id(g);
+    setter_id_at(&node, pick(g, CHANNELS))
+}
+
+/// Each tag of the pool, with probability `1 / one_in`.
+fn tags<G: Gen>(g: &mut G, one_in: usize) -> Vec<TagId> {
+    TAGS.iter()
+        .filter(|_| pick(g, one_in) == 0)
+        .map(|tag| TagId::new(tag).unwrap())
+        .collect()
+}
+
+/// A number in `[0, 1]`.
+fn unit<G: Gen>(g: &mut G) -> f64 {
+    u8::arbitrary(g) as f64 / 255.
+}
+
+fn json<G: Gen>(g: &mut G, depth: u32) -> JSON {
+    match pick(g, if depth == 0 { 5 } else { 7 }) {
+        0 => JSON::Null,
+        1 => JSON::Bool(bool::arbitrary(g)),
+        2 => JSON::I64(i64::arbitrary(g)),
+        3 => JSON::F64(f64::arbitrary(g)),
+        4 => JSON::String(String::arbitrary(g)),
+        5 => {
+            let len = pick(g, 4);
+            JSON::Array((0..len).map(|_| json(g, depth - 1)).collect())
+        }
+        _ => {
+            let len = pick(g, 4);
+            JSON::Object((0..len).map(|_| (String::arbitrary(g), json(g, depth - 1))).collect())
+        }
+    }
+}
+
+impl Arbitrary for Type {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        use values::Type::*;
+        let types = [Unit, Bool, Duration, TimeStamp, Temperature, String, Count, Color, Json, Binary, ExtNumeric];
+        types[pick(g, types.len())].clone()
+    }
+}
+
+/// A value of type `typ`.
+pub fn value_of_type<G: Gen>(g: &mut G, typ: &Type) -> Value {
+    match *typ {
+        Type::Unit => Value::Unit,
+        Type::Bool => Value::Bool(bool::arbitrary(g)),
+        Type::Duration => Value::Duration(ValDuration::new(Duration::from_millis(u32::arbitrary(g) as u64))),
+        Type::TimeStamp => Value::TimeStamp(TimeStamp::from_s(u32::arbitrary(g) as i64)),
+        Type::Temperature => Value::Temperature(if bool::arbitrary(g) {
+            Temperature::C(f64::arbitrary(g))
+        } else {
+            Temperature::F(f64::arbitrary(g))
+        }),
+        Type::String => Value::String(Arc::new(String::arbitrary(g))),
+        Type::Count => Value::Count(u32::arbitrary(g)),
+        Type::Color => Value::Color(Color::RGBA(unit(g), unit(g), unit(g), unit(g), unit(g))),
+        Type::Json => Value::Json(Arc::new(Json(json(g, 2)))),
+        Type::Binary => Value::Binary {
+            data: Bytes(Arc::new(Vec::arbitrary(g))),
+            mimetype: MIMETYPES[pick(g, MIMETYPES.len())].to_owned()
+        },
+        Type::ExtNumeric => Value::ExtNumeric(ExtNumeric {
+            value: f64::arbitrary(g),
+            vendor: VENDOR.to_owned(),
+            adapter: ADAPTER.to_owned(),
+            kind: EXTENSIONS[pick(g, EXTENSIONS.len())].to_owned(),
+        }),
+    }
+}
+
+impl Arbitrary for Value {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let typ = Type::arbitrary(g);
+        value_of_type(g, &typ)
+    }
+
+    /// Shrink the payload of the value, keeping its type.
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        match *self {
+            Value::Bool(value) => Box::new(value.shrink().map(Value::Bool)),
+            Value::Duration(ref duration) => Box::new(duration.as_ms().shrink()
+                .map(|ms| Value::Duration(ValDuration::new(Duration::from_millis(ms))))),
+            Value::TimeStamp(ref timestamp) => Box::new((timestamp.as_ms() / 1000).shrink()
+                .map(|s| Value::TimeStamp(TimeStamp::from_s(s)))),
+            Value::Temperature(Temperature::C(value)) => Box::new(value.shrink()
+                .map(|value| Value::Temperature(Temperature::C(value)))),
+            Value::Temperature(Temperature::F(value)) => Box::new(value.shrink()
+                .map(|value| Value::Temperature(Temperature::F(value)))),
+            Value::String(ref value) => Box::new(value.shrink().map(|value| Value::String(Arc::new(value)))),
+            Value::Count(count) => Box::new(count.shrink().map(Value::Count)),
+            Value::ExtNumeric(ref ext) => {
+                let ext = ext.clone();
+                Box::new(ext.value.shrink().map(move |value| Value::ExtNumeric(ExtNumeric {
+                    value: value,
+                    ..ext.clone()
+                })))
+            }
+            Value::Binary { ref data, ref mimetype } => {
+                let mimetype = mimetype.clone();
+                Box::new(data.shrink().map(move |data| Value::Binary {
+                    data: Bytes(Arc::new(data)),
+                    mimetype: mimetype.clone()
+                }))
+            }
+            Value::Json(ref json) => match json.0 {
+                JSON::Null => empty_shrinker(),
+                _ => Box::new(Some(Value::Json(Arc::new(Json(JSON::Null)))).into_iter())
+            },
+            Value::Unit | Value::Color(_) | Value::JsonPatch(_) | Value::Compressed(_) => empty_shrinker(),
+        }
+    }
+}
+
+/// Shrink both bounds of a range, one at a time.
+fn shrink_bounds<F>(min: &Value, max: &Value, range: F) -> Box<Iterator<Item = Range>>
+    where F: Fn(Value, Value) -> Range + Clone + 'static {
+    let (min, max) = (min.clone(), max.clone());
+    let shrunk_min = {
+        let (max, range) = (max.clone(), range.clone());
+        min.shrink().map(move |min| range(min, max.clone()))
+    };
+    let shrunk_max = max.shrink().map(move |max| range(min.clone(), max));
+    Box::new(shrunk_min.chain(shrunk_max))
+}
+
+impl Arbitrary for Range {
+    /// A range whose bounds have the same type.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let typ = Type::arbitrary(g);
+        match pick(g, 5) {
+            0 => Range::Leq(value_of_type(g, &typ)),
+            1 => Range::Geq(value_of_type(g, &typ)),
+            2 => Range::Eq(value_of_type(g, &typ)),
+            3 => Range::BetweenEq {
+                min: value_of_type(g, &typ),
+                max: value_of_type(g, &typ),
+            },
+            _ => Range::OutOfStrict {
+                min: value_of_type(g, &typ),
+                max: value_of_type(g, &typ),
+            },
+        }
+    }
+
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        match *self {
+            Range::Leq(ref value) => Box::new(value.shrink().map(Range::Leq)),
+            Range::Geq(ref value) => Box::new(value.shrink().map(Range::Geq)),
+            Range::Eq(ref value) => Box::new(value.shrink().map(Range::Eq)),
+            Range::BetweenEq { ref min, ref max } =>
+                shrink_bounds(min, max, |min, max| Range::BetweenEq { min: min, max: max }),
+            Range::OutOfStrict { ref min, ref max } =>
+                shrink_bounds(min, max, |min, max| Range::OutOfStrict { min: min, max: max }),
+        }
+    }
+}
+
+impl Arbitrary for TagId {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        TagId::new(TAGS[pick(g, TAGS.len())]).unwrap()
+    }
+}
+
+impl Arbitrary for ChannelKind {
+    /// A standard kind or, once in a while, an extension kind.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        use devices::ChannelKind::*;
+        let kinds = [Ready, OnOff, OpenClosed, CurrentTime, CurrentTimeOfDay,
+                     RemainingTime, Thermostat, ActualTemperature, ButtonPressed,
+                     DoorbellRang];
+        match pick(g, kinds.len() + 1) {
+            index if index < kinds.len() => kinds[index].clone(),
+            _ => Extension {
+                vendor: VENDOR.to_owned(),
+                adapter: ADAPTER.to_owned(),
+                kind: EXTENSIONS[pick(g, EXTENSIONS.len())].to_owned(),
+                typ: Type::arbitrary(g),
+            }
+        }
+    }
+}
+
+/// A copy of `value`, changed by `change`.
+fn changed<T, F>(value: &T, change: F) -> T where T: Clone, F: FnOnce(&mut T) {
+    let mut value = value.clone();
+    change(&mut value);
+    value
+}
+
+impl Arbitrary for GetterSelector {
+    /// A selector with a few constraints, possibly conflicting.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut selector = GetterSelector::new();
+        for _ in 0..pick(g, 4) {
+            selector = match pick(g, 4) {
+                0 => selector.with_id(getter_id(g)),
+                1 => selector.with_parent(node_id(g)),
+                2 => selector.with_tags(vec![TagId::arbitrary(g)]),
+                _ => selector.with_kind(ChannelKind::arbitrary(g)),
+            };
+        }
+        selector
+    }
+
+    /// Remove one constraint.
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        let mut shrunk = vec![];
+        if !self.id.is_empty() {
+            shrunk.push(changed(self, |selector| selector.id = Exactly::Empty));
+        }
+        if !self.parent.is_empty() {
+            shrunk.push(changed(self, |selector| selector.parent = Exactly::Empty));
+        }
+        if !self.kind.is_empty() {
+            shrunk.push(changed(self, |selector| selector.kind = Exactly::Empty));
+        }
+        for index in 0..self.tags.len() {
+            shrunk.push(changed(self, |selector| { selector.tags.remove(index); }));
+        }
+        Box::new(shrunk.into_iter())
+    }
+}
+
+impl Arbitrary for SetterSelector {
+    /// A selector with a few constraints, possibly conflicting.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut selector = SetterSelector::new();
+        for _ in 0..pick(g, 4) {
+            selector = match pick(g, 4) {
+                0 => selector.with_id(setter_id(g)),
+                1 => selector.with_parent(node_id(g)),
+                2 => selector.with_tags(vec![TagId::arbitrary(g)]),
+                _ => selector.with_kind(ChannelKind::arbitrary(g)),
+            };
+        }
+        selector
+    }
+
+    /// Remove one constraint.
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        let mut shrunk = vec![];
+        if !self.id.is_empty() {
+            shrunk.push(changed(self, |selector| selector.id = Exactly::Empty));
+        }
+        if !self.parent.is_empty() {
+            shrunk.push(changed(self, |selector| selector.parent = Exactly::Empty));
+        }
+        if !self.kind.is_empty() {
+            shrunk.push(changed(self, |selector| selector.kind = Exactly::Empty));
+        }
+        for index in 0..self.tags.len() {
+            shrunk.push(changed(self, |selector| { selector.tags.remove(index); }));
+        }
+        Box::new(shrunk.into_iter())
+    }
+}
+
+impl Arbitrary for NodeSelector {
+    /// A selector with a few constraints, possibly conflicting.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut selector = NodeSelector::new();
+        for _ in 0..pick(g, 4) {
+            selector = match pick(g, 4) {
+                0 => selector.with_id(node_id(g)),
+                1 => selector.with_tags(vec![TagId::arbitrary(g)]),
+                2 => selector.with_getters(vec![GetterSelector::arbitrary(g)]),
+                _ => selector.with_setters(vec![SetterSelector::arbitrary(g)]),
+            };
+        }
+        selector
+    }
+
+    /// Remove one constraint.
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        let mut shrunk = vec![];
+        if !self.id.is_empty() {
+            shrunk.push(changed(self, |selector| selector.id = Exactly::Empty));
+        }
+        for index in 0..self.tags.len() {
+            shrunk.push(changed(self, |selector| { selector.tags.remove(index); }));
+        }
+        for index in 0..self.getters.len() {
+            shrunk.push(changed(self, |selector| { selector.getters.remove(index); }));
+        }
+        for index in 0..self.setters.len() {
+            shrunk.push(changed(self, |selector| { selector.setters.remove(index); }));
+        }
+        Box::new(shrunk.into_iter())
+    }
+}
+
+/// A set of nodes, along with their channels, all of them managed by
+/// adapter `ADAPTER`. Ids are distinct, and the id of each channel
+/// starts with the id of its node, as expected by
+/// `adapter::AdapterManagerHandle`.
+#[derive(Clone, Debug)]
+pub struct Topology {
+    pub nodes: Vec<Node>,
+}
+
+impl Arbitrary for Topology {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut nodes = vec![];
+        for index in 0..pick(g, NODES + 1) {
+            let id = node_id_at(index);
+            let mut node = Node::new(id.clone()).with_tags(tags(g, 3));
+            for index in 0..pick(g, CHANNELS + 1) {
+                let kind = ChannelKind::arbitrary(g);
+                node = node.with_getter(Channel::new(getter_id_at(&id, index), id.clone(), Getter::new(kind))
+                    .with_tags(tags(g, 3)));
+            }
+            for index in 0..pick(g, CHANNELS + 1) {
+                let kind = ChannelKind::arbitrary(g);
+                node = node.with_setter(Channel::new(setter_id_at(&id, index), id.clone(), Setter::new(kind))
+                    .with_tags(tags(g, 3)));
+            }
+            nodes.push(node);
+        }
+        Topology {
+            nodes: nodes
+        }
+    }
+
+    /// Remove one node or one channel.
+    fn shrink(&self) -> Box<Iterator<Item = Self>> {
+        let mut shrunk = vec![];
+        for (position, node) in self.nodes.iter().enumerate() {
+            shrunk.push(changed(self, |topology| { topology.nodes.remove(position); }));
+            for index in 0..node.getters.len() {
+                shrunk.push(changed(self, |topology| { topology.nodes[position].getters.remove(index); }));
+            }
+            for index in 0..node.setters.len() {
+                shrunk.push(changed(self, |topology| { topology.nodes[position].setters.remove(index); }));
+            }
+        }
+        Box::new(shrunk.into_iter())
+    }
+}
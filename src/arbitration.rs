@@ -0,0 +1,96 @@
+//!
+//! Arbitration between conflicting writes.
+//!
+//! Several callers may send values to the same setter channel within a
+//! short period, e.g. a user turning on a light while a rule of the
+//! rules engine turns it off. Each write carries a `Priority`, and the
+//! implementation of the API consults an `ArbitrationPolicy` to
+//! determine whether a write may override the latest write to the same
+//! channel. Suppressed writes fail with `Error::Suppressed`, which
+//! reports the winning write.
+//!
+
+use api::Error;
+use auth::CallerId;
+use devices::Setter;
+use values::{TimeStamp, Value, ValDuration};
+use util::Id;
+
+/// The priority of a write.
+///
+/// Priorities are ordered: `Background < Automation < Manual`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// A write performed in the background, e.g. by a scheduler or to
+    /// restore a default state.
+    Background,
+
+    /// A write performed by an automation, e.g. a rule of the rules
+    /// engine.
+    Automation,
+
+    /// A write requested explicitly by a human user.
+    Manual,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Automation
+    }
+}
+
+/// A write to a setter channel, as seen by the arbitration policy.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WriteRecord {
+    /// The caller who requested the write.
+    pub caller: Id<CallerId>,
+
+    /// The priority of the write.
+    pub priority: Priority,
+
+    /// The value written.
+    pub value: Value,
+
+    /// Date at which the write was requested.
+    pub timestamp: TimeStamp,
+}
+
+/// A policy determining whether a write may override the latest write
+/// to the same setter channel.
+pub trait ArbitrationPolicy: Send + Sync {
+    /// Determine whether `incoming` may be sent to `setter`, given that
+    /// the latest write to `setter` was `current`.
+    ///
+    /// Produce `Error::Suppressed` if `incoming` should be rejected.
+    fn arbitrate(&self, setter: &Id<Setter>, current: &WriteRecord, incoming: &WriteRecord) -> Result<(), Error>;
+}
+
+/// A policy letting each write hold its setter channel for a given
+/// duration. During that duration, writes with a lower priority are
+/// suppressed. Writes with the same or a higher priority are always
+/// accepted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HoldPolicy {
+    /// How long a write holds its setter channel.
+    pub hold: ValDuration,
+}
+
+impl HoldPolicy {
+    pub fn new(hold: ValDuration) -> Self {
+        HoldPolicy {
+            hold: hold
+        }
+    }
+}
+
+impl ArbitrationPolicy for HoldPolicy {
+    fn arbitrate(&self, _: &Id<Setter>, current: &WriteRecord, incoming: &WriteRecord) -> Result<(), Error> {
+        if incoming.priority >= current.priority {
+            return Ok(());
+        }
+        if incoming.timestamp >= current.timestamp.plus(&self.hold) {
+            return Ok(());
+        }
+        Err(Error::Suppressed(current.clone()))
+    }
+}
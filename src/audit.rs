@@ -0,0 +1,801 @@
+//!
+//! Auditing of the operations performed through the API.
+//!
+//! `Audited` wraps any implementation of the API and invokes an
+//! `AuditSink` with an `AuditRecord` for every operation that mutates
+//! the state of the FoxBox or of the devices, i.e. every `put_*`, every
+//! `delete_*`, every change of label, every scheduling, every
+//! reservation, every watch registration and every removal of a node,
+//! whether the operation succeeded or not. This lets deployments answer
+//! questions such as "who unlocked the door at 3am?".
+//!
+//! # Example
+//!
+//! ```ignore
+//! use foxbox_taxonomy::audit::*;
+//!
+//! let api = Audited::new(manager, Box::new(|record: AuditRecord| {
+//!     println!("{:?}", record);
+//! }));
+//! ```
+//!
+
+use api::*;
+use auth::Caller;
+use arbitration::Priority;
+use devices::*;
+use discovery::{Candidate, CandidateId};
+use history::{ Bucket, Sample };
+use metrics::Metrics;
+use selector::*;
+use values::{TimeStamp, Value, ValDuration};
+use util::{ Id, TagId };
+
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+
+/// A mutating operation, as recorded in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditOperation {
+    /// `API::put_node_tag`.
+    PutNodeTag {
+        set: Vec<NodeSelector>,
+        tags: Vec<TagId>,
+    },
+
+    /// `API::delete_node_tag`.
+    DeleteNodeTag {
+        set: Vec<NodeSelector>,
+        tags: Vec<TagId>,
+    },
+
+    /// `API::put_getter_tag`.
+    PutGetterTag {
+        set: Vec<GetterSelector>,
+        tags: Vec<TagId>,
+    },
+
+    /// `API::put_setter_tag`.
+    PutSetterTag {
+        set: Vec<SetterSelector>,
+        tags: Vec<TagId>,
+    },
+
+    /// `API::delete_getter_tag`.
+    DeleteGetterTag {
+        set: Vec<GetterSelector>,
+        tags: Vec<TagId>,
+    },
+
+    /// `API::delete_setter_tag`.
+    DeleteSetterTag {
+        set: Vec<SetterSelector>,
+        tags: Vec<TagId>,
+    },
+
+    /// `API::set_node_label` and `API::clear_node_label`.
+    /// A label of `None` means that labels have been cleared.
+    SetNodeLabel {
+        set: Vec<NodeSelector>,
+        label: Option<String>,
+    },
+
+    /// `API::set_getter_label` and `API::clear_getter_label`.
+    SetGetterLabel {
+        set: Vec<GetterSelector>,
+        label: Option<String>,
+    },
+
+    /// `API::set_setter_label` and `API::clear_setter_label`.
+    SetSetterLabel {
+        set: Vec<SetterSelector>,
+        label: Option<String>,
+    },
+
+    /// `API::put_tagging_rule`.
+    PutTaggingRule {
+        rule: TaggingRule,
+    },
+
+    /// `API::delete_tagging_rule`.
+    DeleteTaggingRule {
+        id: Id<TaggingRuleId>,
+    },
+
+    /// `API::put_aggregate_getter`.
+    PutAggregateGetter {
+        aggregate: AggregateGetter,
+    },
+
+    /// `API::delete_aggregate_getter`.
+    DeleteAggregateGetter {
+        id: Id<Getter>,
+    },
+
+    /// `API::put_channel_value`.
+    PutChannelValue {
+        set: Vec<SetterSelector>,
+        value: Value,
+        priority: Priority,
+    },
+
+    /// `API::put_channel_value_at` and its variants.
+    PutChannelValueAt {
+        set: Vec<SetterSelector>,
+        value: Value,
+        at: TimeStamp,
+    },
+
+    /// `API::put_state_value`.
+    PutStateValue {
+        id: Id<StateId>,
+        value: Value,
+        priority: Priority,
+    },
+
+    /// `API::cancel_scheduled_write`.
+    CancelScheduledWrite {
+        id: Id<ScheduleId>,
+    },
+
+    /// `API::reserve_setters`.
+    ReserveSetters {
+        set: Vec<SetterSelector>,
+        duration: ValDuration,
+    },
+
+    /// `API::release_reservation`.
+    ReleaseReservation {
+        id: Id<ReservationId>,
+    },
+
+    /// `API::put_polling_hint`.
+    PutPollingHint {
+        source: Vec<GetterSelector>,
+        interval: ValDuration,
+        duration: ValDuration,
+    },
+
+    /// `API::delete_polling_hint`.
+    DeletePollingHint {
+        id: Id<PollingHintId>,
+    },
+
+    /// `API::register_channel_watch` and its variants.
+    RegisterWatch {
+        options: Vec<WatchOptions>,
+    },
+
+    /// `API::register_durable_watch`.
+    RegisterDurableWatch {
+        owner: String,
+        options: Vec<WatchOptions>,
+    },
+
+    /// `API::resume_watch`.
+    ResumeWatch {
+        owner: String,
+    },
+
+    /// `API::unregister_watch`.
+    UnregisterWatch {
+        id: Id<WatchId>,
+    },
+
+    /// `API::forget_node`.
+    ForgetNode {
+        id: Id<NodeId>,
+    },
+
+    /// `API::adopt_candidate`.
+    AdoptCandidate {
+        candidate: Id<CandidateId>,
+        adapter: Option<Id<AdapterId>>,
+    },
+
+    /// `API::reject_candidate`.
+    RejectCandidate {
+        candidate: Id<CandidateId>,
+    },
+
+    /// `API::put_adapter_config`.
+    PutAdapterConfig {
+        adapter: Id<AdapterId>,
+        key: String,
+        value: Value,
+    },
+}
+
+/// The outcome of an operation, as recorded in the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AuditOutcome {
+    /// The operation succeeded.
+    Success,
+
+    /// The operation was rejected or failed altogether.
+    Failure(Error),
+
+    /// The operation was dispatched to several setter channels, with
+    /// one result per channel.
+    PerSetter(Vec<(Id<Setter>, Result<(), Error>)>),
+}
+
+/// An entry of the audit log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The caller on behalf of which the operation was performed.
+    pub caller: Caller,
+
+    /// Date at which the operation was performed.
+    pub timestamp: TimeStamp,
+
+    /// The operation.
+    pub operation: AuditOperation,
+
+    /// The outcome of the operation.
+    pub outcome: AuditOutcome,
+}
+
+impl AuditRecord {
+    /// Create a record for an operation performed now.
+    pub fn new(caller: &Caller, operation: AuditOperation, outcome: AuditOutcome) -> Self {
+        AuditRecord {
+            caller: caller.clone(),
+            timestamp: TimeStamp::now(),
+            operation: operation,
+            outcome: outcome,
+        }
+    }
+}
+
+/// A destination for audit records, e.g. a log file or a database.
+///
+/// Records are delivered synchronously, from the thread that performed
+/// the operation, so implementations should return quickly.
+pub trait AuditSink: Send + Sync {
+    /// Record one operation.
+    fn record(&self, record: AuditRecord);
+}
+
+impl<F> AuditSink for F where F: Fn(AuditRecord) + Send + Sync {
+    fn record(&self, record: AuditRecord) {
+        self(record)
+    }
+}
+
+impl AuditOutcome {
+    /// The outcome of an operation that returned `result`.
+    fn of<T>(result: &Result<T, Error>) -> Self {
+        match *result {
+            Ok(_) => AuditOutcome::Success,
+            Err(ref err) => AuditOutcome::Failure(err.clone())
+        }
+    }
+}
+
+/// An implementation of the API reporting the mutating operations
+/// performed through another implementation to an `AuditSink`.
+///
+/// Operations are recorded once they have been performed, including
+/// when they are rejected by the underlying implementation, e.g. for
+/// lack of permissions.
+pub struct Audited<A> where A: API {
+    inner: A,
+    sink: Box<AuditSink>,
+}
+
+impl<A> Audited<A> where A: API {
+    pub fn new(inner: A, sink: Box<AuditSink>) -> Self {
+        Audited {
+            inner: inner,
+            sink: sink,
+        }
+    }
+
+    /// Access the underlying implementation, bypassing the audit log.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Record `operation`, performed on behalf of `caller`, and pass
+    /// `result` through.
+    fn record<T>(&self, caller: &Caller, operation: AuditOperation, result: Result<T, Error>) -> Result<T, Error> {
+        self.sink.record(AuditRecord::new(caller, operation, AuditOutcome::of(&result)));
+        result
+    }
+}
+
+impl<A> API for Audited<A> where A: API {
+    type WatchGuard = A::WatchGuard;
+
+    fn get_api_capabilities(&self, caller: &Caller) -> Vec<Capability> {
+        self.inner.get_api_capabilities(caller)
+    }
+
+    fn get_supported_kinds(&self, caller: &Caller) -> Vec<KindDescription> {
+        self.inner.get_supported_kinds(caller)
+    }
+
+    fn get_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>) -> Vec<Arc<Node>> {
+        self.inner.get_nodes(caller, selectors)
+    }
+
+    fn get_topology_revision(&self, caller: &Caller) -> Revision {
+        self.inner.get_topology_revision(caller)
+    }
+
+    fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>> {
+        self.inner.get_nodes_if_changed(caller, selectors, since)
+    }
+
+    fn visit_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>, cb: &mut FnMut(&Node)) {
+        self.inner.visit_nodes(caller, selectors, cb)
+    }
+
+    fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error> {
+        let result = self.inner.forget_node(caller, id);
+        self.record(caller, AuditOperation::ForgetNode {
+            id: id.clone()
+        }, result)
+    }
+
+    fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error> {
+        let result = self.inner.put_node_tag(caller, set, tags);
+        self.record(caller, AuditOperation::PutNodeTag {
+            set: set.clone(),
+            tags: tags.clone()
+        }, result)
+    }
+
+    fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error> {
+        let result = self.inner.delete_node_tag(caller, set, tags.clone());
+        self.record(caller, AuditOperation::DeleteNodeTag {
+            set: set.clone(),
+            tags: vec![tags]
+        }, result)
+    }
+
+    fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error> {
+        let result = self.inner.put_tagging_rule(caller, rule.clone());
+        self.record(caller, AuditOperation::PutTaggingRule {
+            rule: rule
+        }, result)
+    }
+
+    fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error> {
+        let result = self.inner.delete_tagging_rule(caller, id);
+        self.record(caller, AuditOperation::DeleteTaggingRule {
+            id: id.clone()
+        }, result)
+    }
+
+    fn get_tagging_rules(&self, caller: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)> {
+        self.inner.get_tagging_rules(caller)
+    }
+
+    fn get_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>> {
+        self.inner.get_getter_channels(caller, selectors)
+    }
+
+    fn get_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>> {
+        self.inner.get_setter_channels(caller, selectors)
+    }
+
+    fn visit_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>, cb: &mut FnMut(&Channel<Getter>)) {
+        self.inner.visit_getter_channels(caller, selectors, cb)
+    }
+
+    fn visit_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>, cb: &mut FnMut(&Channel<Setter>)) {
+        self.inner.visit_setter_channels(caller, selectors, cb)
+    }
+
+    fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error> {
+        let result = self.inner.put_aggregate_getter(caller, aggregate.clone());
+        self.record(caller, AuditOperation::PutAggregateGetter {
+            aggregate: aggregate
+        }, result)
+    }
+
+    fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error> {
+        let result = self.inner.delete_aggregate_getter(caller, id);
+        self.record(caller, AuditOperation::DeleteAggregateGetter {
+            id: id.clone()
+        }, result)
+    }
+
+    fn get_aggregate_getters(&self, caller: &Caller) -> Vec<(Id<Getter>, AggregateGetter)> {
+        self.inner.get_aggregate_getters(caller)
+    }
+
+    fn put_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        let result = self.inner.put_getter_tag(caller, set, tags);
+        self.record(caller, AuditOperation::PutGetterTag {
+            set: set.clone(),
+            tags: tags.clone()
+        }, result)
+    }
+
+    fn put_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        let result = self.inner.put_setter_tag(caller, set, tags);
+        self.record(caller, AuditOperation::PutSetterTag {
+            set: set.clone(),
+            tags: tags.clone()
+        }, result)
+    }
+
+    fn delete_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        let result = self.inner.delete_getter_tag(caller, set, tags);
+        self.record(caller, AuditOperation::DeleteGetterTag {
+            set: set.clone(),
+            tags: tags.clone()
+        }, result)
+    }
+
+    fn delete_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        let result = self.inner.delete_setter_tag(caller, set, tags);
+        self.record(caller, AuditOperation::DeleteSetterTag {
+            set: set.clone(),
+            tags: tags.clone()
+        }, result)
+    }
+
+    fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error> {
+        let result = self.inner.set_node_label(caller, set, label.clone());
+        self.record(caller, AuditOperation::SetNodeLabel {
+            set: set.clone(),
+            label: Some(label)
+        }, result)
+    }
+
+    fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error> {
+        let result = self.inner.clear_node_label(caller, set);
+        self.record(caller, AuditOperation::SetNodeLabel {
+            set: set.clone(),
+            label: None
+        }, result)
+    }
+
+    fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error> {
+        let result = self.inner.set_getter_label(caller, set, label.clone());
+        self.record(caller, AuditOperation::SetGetterLabel {
+            set: set.clone(),
+            label: Some(label)
+        }, result)
+    }
+
+    fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error> {
+        let result = self.inner.set_setter_label(caller, set, label.clone());
+        self.record(caller, AuditOperation::SetSetterLabel {
+            set: set.clone(),
+            label: Some(label)
+        }, result)
+    }
+
+    fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error> {
+        let result = self.inner.clear_getter_label(caller, set);
+        self.record(caller, AuditOperation::SetGetterLabel {
+            set: set.clone(),
+            label: None
+        }, result)
+    }
+
+    fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error> {
+        let result = self.inner.clear_setter_label(caller, set);
+        self.record(caller, AuditOperation::SetSetterLabel {
+            set: set.clone(),
+            label: None
+        }, result)
+    }
+
+    fn get_channel_value(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        self.inner.get_channel_value(caller, selectors)
+    }
+
+    fn poll_now(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        self.inner.poll_now(caller, selectors)
+    }
+
+    fn get_channel_values_since(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)> {
+        self.inner.get_channel_values_since(caller, selectors, since)
+    }
+
+    fn put_channel_value(&self, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+        let results = self.inner.put_channel_value(caller, selectors, value.clone(), priority);
+        let outcome = AuditOutcome::PerSetter(results.iter()
+            .map(|&(ref id, ref result)| (id.clone(), result.as_ref().map(|_| ()).map_err(|err| err.clone())))
+            .collect());
+        self.sink.record(AuditRecord::new(caller, AuditOperation::PutChannelValue {
+            set: selectors.clone(),
+            value: value,
+            priority: priority
+        }, outcome));
+        results
+    }
+
+    fn get_state_value(&self, caller: &Caller, id: &Id<StateId>) -> Result<Option<FetchedValue>, Error> {
+        self.inner.get_state_value(caller, id)
+    }
+
+    fn put_state_value(&self, caller: &Caller, id: &Id<StateId>, value: Value, priority: Priority) -> Result<Acknowledgement, Error> {
+        let result = self.inner.put_state_value(caller, id, value.clone(), priority);
+        self.record(caller, AuditOperation::PutStateValue {
+            id: id.clone(),
+            value: value,
+            priority: priority
+        }, result)
+    }
+
+    fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error> {
+        let result = self.inner.put_channel_value_at(caller, set, value.clone(), at.clone());
+        self.record(caller, AuditOperation::PutChannelValueAt {
+            set: set.clone(),
+            value: value,
+            at: at
+        }, result)
+    }
+
+    fn put_channel_value_after(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, delay: ValDuration) -> Result<Id<ScheduleId>, Error> {
+        let at = TimeStamp::now().plus(&delay);
+        let result = self.inner.put_channel_value_after(caller, set, value.clone(), delay);
+        self.record(caller, AuditOperation::PutChannelValueAt {
+            set: set.clone(),
+            value: value,
+            at: at
+        }, result)
+    }
+
+    fn get_scheduled_writes(&self, caller: &Caller) -> Vec<ScheduledWrite> {
+        self.inner.get_scheduled_writes(caller)
+    }
+
+    fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error> {
+        let result = self.inner.cancel_scheduled_write(caller, id);
+        self.record(caller, AuditOperation::CancelScheduledWrite {
+            id: id.clone()
+        }, result)
+    }
+
+    fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error> {
+        let result = self.inner.reserve_setters(caller, set, duration.clone());
+        self.record(caller, AuditOperation::ReserveSetters {
+            set: set.clone(),
+            duration: duration
+        }, result)
+    }
+
+    fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error> {
+        let result = self.inner.release_reservation(caller, id);
+        self.record(caller, AuditOperation::ReleaseReservation {
+            id: id.clone()
+        }, result)
+    }
+
+    fn get_reservations(&self, caller: &Caller) -> Vec<Reservation> {
+        self.inner.get_reservations(caller)
+    }
+
+    fn put_polling_hint(&self, caller: &Caller, source: &Vec<GetterSelector>, interval: ValDuration, duration: ValDuration) -> Result<PollingHint, Error> {
+        let result = self.inner.put_polling_hint(caller, source, interval.clone(), duration.clone());
+        self.record(caller, AuditOperation::PutPollingHint {
+            source: source.clone(),
+            interval: interval,
+            duration: duration
+        }, result)
+    }
+
+    fn delete_polling_hint(&self, caller: &Caller, id: &Id<PollingHintId>) -> Result<(), Error> {
+        let result = self.inner.delete_polling_hint(caller, id);
+        self.record(caller, AuditOperation::DeletePollingHint {
+            id: id.clone()
+        }, result)
+    }
+
+    fn get_polling_hints(&self, caller: &Caller) -> Vec<PollingHint> {
+        self.inner.get_polling_hints(caller)
+    }
+
+    fn register_channel_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error> {
+        let result = self.inner.register_channel_watch(caller, options.clone(), cb);
+        self.record(caller, AuditOperation::RegisterWatch {
+            options: options
+        }, result)
+    }
+
+    fn register_channel_watch_channel(&self, caller: &Caller, options: Vec<WatchOptions>) -> Result<(Receiver<WatchEvent>, Self::WatchGuard), Error> {
+        let result = self.inner.register_channel_watch_channel(caller, options.clone());
+        self.record(caller, AuditOperation::RegisterWatch {
+            options: options
+        }, result)
+    }
+
+    fn register_channel_watch_with_token(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        let result = self.inner.register_channel_watch_with_token(caller, options.clone(), cb);
+        self.record(caller, AuditOperation::RegisterWatch {
+            options: options
+        }, result)
+    }
+
+    fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error> {
+        let result = self.inner.unregister_watch(caller, token);
+        self.record(caller, AuditOperation::UnregisterWatch {
+            id: token.clone()
+        }, result)
+    }
+
+    fn get_watches(&self, caller: &Caller) -> Result<Vec<WatchInfo>, Error> {
+        self.inner.get_watches(caller)
+    }
+
+    fn get_adapter_health(&self, caller: &Caller) -> Result<Vec<AdapterHealth>, Error> {
+        self.inner.get_adapter_health(caller)
+    }
+
+    fn get_metrics(&self, caller: &Caller) -> Result<Vec<Metrics>, Error> {
+        self.inner.get_metrics(caller)
+    }
+
+    fn get_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error> {
+        self.inner.get_adapter_config(caller, adapter)
+    }
+
+    fn put_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error> {
+        let result = self.inner.put_adapter_config(caller, adapter, key.clone(), value.clone());
+        self.record(caller, AuditOperation::PutAdapterConfig {
+            adapter: adapter.clone(),
+            key: key,
+            value: value
+        }, result)
+    }
+
+    fn get_channel_history(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error> {
+        self.inner.get_channel_history(caller, selectors, since, until)
+    }
+
+    fn get_channel_history_aggregate(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>,
+                                     bucket: ValDuration, aggregation: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error> {
+        self.inner.get_channel_history_aggregate(caller, selectors, since, until, bucket, aggregation)
+    }
+
+    fn get_candidates(&self, caller: &Caller) -> Result<Vec<Candidate>, Error> {
+        self.inner.get_candidates(caller)
+    }
+
+    fn adopt_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>, adapter: Option<Id<AdapterId>>) -> Result<(), Error> {
+        let result = self.inner.adopt_candidate(caller, candidate, adapter.clone());
+        self.record(caller, AuditOperation::AdoptCandidate {
+            candidate: candidate.clone(),
+            adapter: adapter
+        }, result)
+    }
+
+    fn reject_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>) -> Result<(), Error> {
+        let result = self.inner.reject_candidate(caller, candidate);
+        self.record(caller, AuditOperation::RejectCandidate {
+            candidate: candidate.clone()
+        }, result)
+    }
+
+    fn register_durable_watch(&self, caller: &Caller, owner: String, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        let result = self.inner.register_durable_watch(caller, owner.clone(), options.clone(), cb);
+        self.record(caller, AuditOperation::RegisterDurableWatch {
+            owner: owner,
+            options: options
+        }, result)
+    }
+
+    fn resume_watch(&self, caller: &Caller, owner: &str, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        let result = self.inner.resume_watch(caller, owner, cb);
+        self.record(caller, AuditOperation::ResumeWatch {
+            owner: owner.to_owned()
+        }, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Audited, AuditOperation, AuditOutcome, AuditRecord };
+    use api::*;
+    use arbitration::Priority;
+    use auth::{ Caller, CallerKind };
+    use devices::*;
+    use fake::FakeAPI;
+    use selector::*;
+    use values::Value;
+    use util::{ Id, TagId };
+
+    use std::sync::{ Arc, Mutex };
+
+    /// A fake with a node holding a switch, wrapped so that records are
+    /// collected in the returned vector.
+    fn setup() -> (Audited<FakeAPI>, Id<NodeId>, Id<Setter>, Arc<Mutex<Vec<AuditRecord>>>) {
+        let fake = FakeAPI::new();
+        let node: Id<NodeId> = Id::new("audit-node".to_owned());
+        let switch: Id<Setter> = Id::new("audit-node/switch".to_owned());
+        fake.add_node(Node::new(node.clone())
+            .with_setter(Channel::new(switch.clone(), node.clone(), Setter::new(ChannelKind::OnOff))));
+        let records = Arc::new(Mutex::new(vec![]));
+        let sink = {
+            let records = records.clone();
+            Box::new(move |record| records.lock().unwrap().push(record))
+        };
+        (Audited::new(fake, sink), node, switch, records)
+    }
+
+    #[test]
+    fn test_records_callers_and_outcomes() {
+        let (api, _, _, records) = setup();
+        let system = Caller::system();
+        let guest = Caller::new(Id::new("guest".to_owned()), CallerKind::User);
+        let tags = vec![TagId::new("kitchen").unwrap()];
+
+        api.put_node_tag(&system, &vec![NodeSelector::new()], &tags).unwrap();
+        assert!(api.put_node_tag(&guest, &vec![NodeSelector::new()], &tags).is_err());
+
+        // Reads are not recorded.
+        assert_eq!(api.get_nodes(&system, &vec![NodeSelector::new()]).len(), 1);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].caller.id, system.id);
+        match (&records[0].operation, &records[0].outcome) {
+            (&AuditOperation::PutNodeTag { ref tags, .. }, &AuditOutcome::Success) =>
+                assert_eq!(tags, &vec![TagId::new("kitchen").unwrap()]),
+            other => panic!("Unexpected record {:?}", other)
+        }
+        assert_eq!(records[1].caller.id, guest.id);
+        match (&records[1].operation, &records[1].outcome) {
+            (&AuditOperation::PutNodeTag { .. }, &AuditOutcome::Failure(Error::PermissionDenied(ref id))) =>
+                assert_eq!(*id, guest.id),
+            other => panic!("Unexpected record {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_records_sends_per_setter() {
+        let (api, _, switch, records) = setup();
+        let system = Caller::system();
+        api.put_channel_value(&system, &vec![SetterSelector::new()], Value::Bool(true), Priority::Manual);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        match (&records[0].operation, &records[0].outcome) {
+            (&AuditOperation::PutChannelValue { value: Value::Bool(true), priority: Priority::Manual, .. },
+             &AuditOutcome::PerSetter(ref results)) => {
+                assert_eq!(results.len(), 1);
+                assert_eq!(results[0].0, switch);
+                assert!(results[0].1.is_ok());
+            }
+            other => panic!("Unexpected record {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_records_watches_and_forget_node() {
+        let (api, node, _, records) = setup();
+        let system = Caller::system();
+        let token = api.register_channel_watch_with_token(&system, vec![WatchOptions::new().with_watch_values(true)], Box::new(|_| {})).unwrap();
+        api.unregister_watch(&system, &token).unwrap();
+        let missing: Id<NodeId> = Id::new("audit-missing".to_owned());
+        assert!(api.forget_node(&system, &missing).is_err());
+        api.forget_node(&system, &node).unwrap();
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 4);
+        match (&records[0].operation, &records[0].outcome) {
+            (&AuditOperation::RegisterWatch { ref options }, &AuditOutcome::Success) => assert_eq!(options.len(), 1),
+            other => panic!("Unexpected record {:?}", other)
+        }
+        match (&records[1].operation, &records[1].outcome) {
+            (&AuditOperation::UnregisterWatch { ref id }, &AuditOutcome::Success) => assert_eq!(*id, token),
+            other => panic!("Unexpected record {:?}", other)
+        }
+        match (&records[2].operation, &records[2].outcome) {
+            (&AuditOperation::ForgetNode { ref id }, &AuditOutcome::Failure(Error::NoSuchNode(_))) => assert_eq!(*id, missing),
+            other => panic!("Unexpected record {:?}", other)
+        }
+        match (&records[3].operation, &records[3].outcome) {
+            (&AuditOperation::ForgetNode { ref id }, &AuditOutcome::Success) => assert_eq!(*id, node),
+            other => panic!("Unexpected record {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//!
+//! Identification and authorization of the callers of the API.
+//!
+//! Every call to the API is performed on behalf of a `Caller`, which
+//! carries the identity of a user or application along with the scopes
+//! that have been granted to it. Implementations of the API consult a
+//! `PermissionPolicy` before mutating tags or labels, sending values
+//! to setters, registering watches or managing nodes.
+//!
+
+use api::{Error, WatchOptions};
+use devices::{AdapterId, NodeId, Setter};
+use discovery::CandidateId;
+use selector::GetterSelector;
+use values::{TimeStamp, Value, ValDuration};
+use util::{Id, TagId};
+
+/// A marker for Id.
+/// Only useful for writing `Id<CallerId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct CallerId;
+
+/// The nature of a caller.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CallerKind {
+    /// A human user, e.g. through the web frontend.
+    User,
+
+    /// An application, e.g. a rule of the rules engine.
+    Application,
+
+    /// The FoxBox itself, e.g. a cache or a scheduler acting on behalf
+    /// of another caller.
+    System,
+}
+
+/// A permission that may be granted to a caller.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Scope {
+    /// Adding or removing tags on nodes and channels, including
+    /// through live tagging rules.
+    WriteTags,
+
+    /// Sending values to setter channels.
+    WriteValues,
+
+    /// Watching getter channels, including requesting that they be
+    /// polled more often.
+    Watch,
+
+    /// Setting or clearing the labels of nodes and channels.
+    WriteLabels,
+
+    /// Removing nodes.
+    ManageNodes,
+
+    /// Changing the configuration of adapters.
+    ManageAdapters,
+
+    /// All of the above, and any scope introduced in the future.
+    All,
+}
+
+/// The identity of a caller of the API, along with the scopes granted
+/// to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Caller {
+    /// An id unique to this caller, e.g. a user name or an
+    /// application id.
+    pub id: Id<CallerId>,
+
+    /// The nature of the caller.
+    pub kind: CallerKind,
+
+    /// The scopes granted to this caller.
+    #[serde(default)]
+    pub scopes: Vec<Scope>,
+}
+
+impl Caller {
+    /// Create a new caller, without any scope.
+    pub fn new(id: Id<CallerId>, kind: CallerKind) -> Self {
+        Caller {
+            id: id,
+            kind: kind,
+            scopes: vec![],
+        }
+    }
+
+    /// The FoxBox itself, with all scopes.
+    pub fn system() -> Self {
+        Caller {
+            id: Id::new("foxbox".to_owned()),
+            kind: CallerKind::System,
+            scopes: vec![Scope::All],
+        }
+    }
+
+    /// Grant additional scopes to this caller.
+    pub fn with_scopes(mut self, mut scopes: Vec<Scope>) -> Self {
+        self.scopes.append(&mut scopes);
+        self
+    }
+
+    /// Determine whether a scope has been granted to this caller.
+    pub fn has_scope(&self, scope: &Scope) -> bool {
+        self.scopes.iter().any(|granted| granted == scope || *granted == Scope::All)
+    }
+}
+
+/// An operation submitted to a `PermissionPolicy`.
+#[derive(Debug)]
+pub enum Operation<'a> {
+    /// Adding or removing tags.
+    Tag {
+        /// The tags being added or removed.
+        tags: &'a [TagId]
+    },
+
+    /// Sending a value to a setter channel.
+    Send {
+        /// The channel to which the value is sent.
+        setter: &'a Id<Setter>,
+
+        /// The value being sent.
+        value: &'a Value
+    },
+
+    /// Scheduling a value to be sent later. Once the value is
+    /// actually sent, `Send` is checked as well, for each setter.
+    Schedule {
+        /// The value being scheduled.
+        value: &'a Value,
+
+        /// The date at which the value will be sent.
+        at: &'a TimeStamp
+    },
+
+    /// Reserving setter channels for exclusive use.
+    Reserve {
+        /// The channels being reserved.
+        setters: &'a [Id<Setter>]
+    },
+
+    /// Registering a watch.
+    Watch {
+        /// The options of the watch.
+        options: &'a [WatchOptions]
+    },
+
+    /// Requesting that getter channels be polled more often.
+    PollingHint {
+        /// The getters to poll.
+        source: &'a [GetterSelector],
+
+        /// The interval at which they should be polled.
+        interval: &'a ValDuration
+    },
+
+    /// Setting or clearing labels.
+    Label {
+        /// The new label, or `None` if labels are cleared.
+        label: Option<&'a str>
+    },
+
+    /// Forgetting a node.
+    ForgetNode {
+        /// The node to forget.
+        node: &'a Id<NodeId>
+    },
+
+    /// Registering or unregistering an aggregate getter.
+    Aggregate {
+        /// The getters aggregated.
+        source: &'a [GetterSelector]
+    },
+
+    /// Adopting or rejecting a device found on the network.
+    Adopt {
+        /// The candidate being adopted or rejected.
+        candidate: &'a Id<CandidateId>
+    },
+
+    /// Changing an option of the configuration of an adapter.
+    Configure {
+        /// The adapter being configured.
+        adapter: &'a Id<AdapterId>,
+
+        /// The option being changed.
+        key: &'a str
+    },
+}
+
+impl<'a> Operation<'a> {
+    /// The scope required to perform this operation.
+    pub fn scope(&self) -> Scope {
+        match *self {
+            Operation::Tag { .. } => Scope::WriteTags,
+            Operation::Send { .. } | Operation::Schedule { .. } |
+            Operation::Reserve { .. } => Scope::WriteValues,
+            Operation::Watch { .. } | Operation::PollingHint { .. } => Scope::Watch,
+            Operation::Label { .. } => Scope::WriteLabels,
+            Operation::ForgetNode { .. } | Operation::Aggregate { .. } |
+            Operation::Adopt { .. } => Scope::ManageNodes,
+            Operation::Configure { .. } => Scope::ManageAdapters,
+        }
+    }
+}
+
+/// A policy determining whether a caller may perform an operation.
+pub trait PermissionPolicy: Send + Sync {
+    /// Determine whether `caller` may perform `operation`.
+    ///
+    /// Produce `Error::PermissionDenied` if the operation should be
+    /// rejected.
+    fn check(&self, caller: &Caller, operation: &Operation) -> Result<(), Error>;
+}
+
+/// A policy accepting an operation if and only if the caller has been
+/// granted the corresponding scope.
+#[derive(Debug, Clone, Default)]
+pub struct ScopePolicy;
+
+impl PermissionPolicy for ScopePolicy {
+    fn check(&self, caller: &Caller, operation: &Operation) -> Result<(), Error> {
+        if caller.has_scope(&operation.scope()) {
+            Ok(())
+        } else {
+            Err(Error::PermissionDenied(caller.id.clone()))
+        }
+    }
+}
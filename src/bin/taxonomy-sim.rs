@@ -0,0 +1,311 @@
+//!
+//! A fake smart home, for developing frontends and rules without
+//! hardware.
+//!
+//! `taxonomy-sim` plays a scenario through an `AdapterManager`, then
+//! serves the read and write operations of the REST API, as listed in
+//! `rest::ROUTES`, over plain HTTP. Other routes are answered with
+//! Error 501. See `simulator::Scenario` for the format of scenarios.
+//!
+//! Binary values, e.g. the snapshots of a simulated camera, are served
+//! as raw or multipart bodies to clients requesting them through their
+//! `Accept` header. See module `body`.
+//!
+//! Only available with feature `simulator`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! cargo run --features simulator --bin taxonomy-sim -- home.json 127.0.0.1:3000 10
+//! curl http://127.0.0.1:3000/api/v1/nodes
+//! curl -H "Accept: multipart/form-data" http://127.0.0.1:3000/api/v1/channels/value
+//! ```
+//!
+
+extern crate foxbox_taxonomy;
+extern crate serde;
+extern crate serde_json;
+
+use foxbox_taxonomy::api::{ Error, FetchedValue, API };
+use foxbox_taxonomy::auth::Caller;
+use foxbox_taxonomy::body::{ self, Part, Representation };
+use foxbox_taxonomy::devices::Getter;
+use foxbox_taxonomy::manager::{ AdapterManager, PollingPolicy };
+use foxbox_taxonomy::rest::{ self, Method, Route };
+use foxbox_taxonomy::selector::{ GetterSelector, NodeSelector, SetterSelector };
+use foxbox_taxonomy::simulator::Scenario;
+use foxbox_taxonomy::util::Id;
+
+use serde::de::Deserialize;
+use serde::ser::Serialize;
+use serde_json::value::Value as JSON;
+
+use std::env;
+use std::fs::File;
+use std::io::{ BufRead, BufReader, Read, Write };
+use std::net::{ TcpListener, TcpStream };
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+const USAGE: &'static str = "Usage: taxonomy-sim SCENARIO [ADDRESS] [SPEED]";
+
+/// Why a route could not serve a request.
+enum Failure {
+    /// The body of the request does not have the type expected by the
+    /// route.
+    Syntax(String),
+
+    /// The route is not served by the simulator.
+    NotImplemented,
+
+    /// None of the representations accepted by the client is
+    /// available.
+    NotAcceptable,
+}
+
+/// The body of a response.
+struct Response {
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// Parse the body of a request. An empty body stands for `default`,
+/// e.g. a selector matching everything.
+fn parse<T>(body: &str, default: T) -> Result<T, Failure> where T: Deserialize {
+    if body.trim().is_empty() {
+        return Ok(default);
+    }
+    serde_json::from_str(body).map_err(|err| Failure::Syntax(format!("{:?}", err)))
+}
+
+/// Extract field `key` of a request such as `{ set: ..., value: ... }`.
+fn field<T>(json: &JSON, key: &str) -> Result<T, Failure> where T: Deserialize {
+    match json.find(key) {
+        Some(value) => serde_json::from_value(value.clone()).map_err(|err| Failure::Syntax(format!("{}: {:?}", key, err))),
+        None => Err(Failure::Syntax(format!("Missing field {}", key)))
+    }
+}
+
+fn json<T>(result: &T) -> Response where T: Serialize {
+    Response {
+        content_type: "application/json".to_owned(),
+        body: serde_json::to_vec(result).unwrap(),
+    }
+}
+
+fn respond<T>(result: &T) -> Result<Response, Failure> where T: Serialize {
+    Ok(json(result))
+}
+
+/// Respond with the values of getters, in the representation requested
+/// by `accept`. Values are only served raw or multipart if all of them
+/// have been fetched successfully.
+fn respond_values(accept: &str, values: Vec<(Id<Getter>, Result<FetchedValue, Error>)>) -> Result<Response, Failure> {
+    let mimetypes: Vec<_> = values.iter()
+        .map(|&(_, ref result)| result.as_ref().ok().and_then(|fetched| body::mimetype(&fetched.value)))
+        .collect();
+    match body::negotiate(accept, &mimetypes) {
+        None => Err(Failure::NotAcceptable),
+        Some(Representation::Json) => respond(&values),
+        Some(Representation::Raw) => match values.into_iter().next() {
+            Some((_, Ok(fetched))) => match body::to_raw(&fetched.value) {
+                Ok(raw) => Ok(Response {
+                    content_type: raw.content_type,
+                    body: (*raw.data).clone(),
+                }),
+                Err(_) => Err(Failure::NotAcceptable)
+            },
+            _ => Err(Failure::NotAcceptable)
+        },
+        Some(Representation::Multipart) => {
+            let parts: Vec<_> = values.into_iter()
+                .filter_map(|(id, result)| result.ok().map(|fetched| Part {
+                    name: id.to_string(),
+                    value: fetched.value,
+                }))
+                .collect();
+            match body::to_multipart(&parts) {
+                Ok((content_type, body)) => Ok(Response {
+                    content_type: content_type,
+                    body: body,
+                }),
+                Err(_) => Err(Failure::NotAcceptable)
+            }
+        }
+    }
+}
+
+/// Serve a request through `route`.
+fn call(api: &AdapterManager, caller: &Caller, route: &Route, accept: &str, body: &str) -> Result<Response, Failure> {
+    match route.operation {
+        "get_api_capabilities" => respond(&api.get_api_capabilities(caller)),
+        "get_supported_kinds" => respond(&api.get_supported_kinds(caller)),
+        "get_topology_revision" => respond(&api.get_topology_revision(caller)),
+        "get_nodes" => {
+            let selectors = try!(parse(body, vec![NodeSelector::new()]));
+            respond(&api.get_nodes(caller, &selectors))
+        }
+        "get_getter_channels" => {
+            let selectors = try!(parse(body, vec![GetterSelector::new()]));
+            respond(&api.get_getter_channels(caller, &selectors))
+        }
+        "get_setter_channels" => {
+            let selectors = try!(parse(body, vec![SetterSelector::new()]));
+            respond(&api.get_setter_channels(caller, &selectors))
+        }
+        "get_channel_value" => {
+            let selectors = try!(parse(body, vec![GetterSelector::new()]));
+            respond_values(accept, api.get_channel_value(caller, &selectors))
+        }
+        "put_channel_value" => {
+            let json: JSON = try!(parse(body, JSON::Null));
+            let set: Vec<SetterSelector> = try!(field(&json, "set"));
+            let value = try!(field(&json, "value"));
+            let priority = try!(field(&json, "priority"));
+            respond(&api.put_channel_value(caller, &set, value, priority))
+        }
+        _ => Err(Failure::NotImplemented)
+    }
+}
+
+/// Serve a request, trying each matching route in turn. Returns the
+/// status and the body of the response.
+fn dispatch(api: &AdapterManager, method: Method, target: &str, accept: &str, body: &str) -> (&'static str, Response) {
+    let (path, query) = match target.find('?') {
+        Some(index) => (&target[..index], &target[index + 1..]),
+        None => (target, "")
+    };
+    let routes: Vec<_> = rest::find_routes(method, path).into_iter()
+        .filter(|route| match route.query {
+            Some(param) => query.split('&').any(|pair| pair.split('=').next() == Some(param)),
+            None => true
+        })
+        .collect();
+    if routes.is_empty() {
+        return ("404 Not Found", empty());
+    }
+    let caller = Caller::system();
+    let mut syntax = None;
+    for route in routes {
+        match call(api, &caller, route, accept, body) {
+            Ok(response) => return ("200 OK", response),
+            Err(Failure::Syntax(err)) => syntax = Some(err),
+            Err(Failure::NotAcceptable) => return ("406 Not Acceptable", empty()),
+            Err(Failure::NotImplemented) => {}
+        }
+    }
+    match syntax {
+        Some(err) => ("400 Bad Request", json(&err)),
+        None => ("501 Not Implemented", empty())
+    }
+}
+
+fn empty() -> Response {
+    Response {
+        content_type: "application/json".to_owned(),
+        body: vec![],
+    }
+}
+
+/// Read a single request from `stream` and answer it, then close the
+/// connection.
+fn serve(api: &AdapterManager, stream: TcpStream) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(stream) => stream,
+        Err(_) => return
+    });
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+    let mut words = line.split_whitespace();
+    let method = match words.next() {
+        Some("GET") => Some(Method::Get),
+        Some("POST") => Some(Method::Post),
+        Some("PUT") => Some(Method::Put),
+        Some("DELETE") => Some(Method::Delete),
+        _ => None
+    };
+    let target = words.next().unwrap_or("/").to_owned();
+
+    let mut length = 0;
+    let mut accept = String::new();
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        let mut parts = header.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        match &*name.trim().to_lowercase() {
+            "content-length" => length = parts.next().and_then(|value| value.trim().parse().ok()).unwrap_or(0),
+            "accept" => accept = parts.next().unwrap_or("").trim().to_owned(),
+            _ => {}
+        }
+    }
+    let mut body = vec![0; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let (status, response) = match (method, String::from_utf8(body)) {
+        (Some(method), Ok(body)) => dispatch(api, method, &target, &accept, &body),
+        (None, _) => ("405 Method Not Allowed", empty()),
+        (_, Err(_)) => ("400 Bad Request", empty()),
+    };
+    let mut stream = stream;
+    let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                   status, response.content_type, response.body.len());
+    let _ = stream.write_all(&response.body);
+}
+
+fn fail(message: String) -> ! {
+    let _ = writeln!(std::io::stderr(), "{}\n{}", message, USAGE);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = match args.get(0) {
+        Some(path) => path,
+        None => fail("Missing scenario".to_owned())
+    };
+    let address = args.get(1).map(|address| &**address).unwrap_or("127.0.0.1:3000");
+    let speed = match args.get(2).map(|speed| speed.parse::<f64>()) {
+        None => 1.,
+        Some(Ok(speed)) if speed > 0. => speed,
+        Some(_) => fail("The speed must be a positive number".to_owned())
+    };
+
+    let mut text = String::new();
+    if let Err(err) = File::open(path).and_then(|mut file| file.read_to_string(&mut text)) {
+        fail(format!("Cannot read {}: {}", path, err));
+    }
+    let scenario = match Scenario::parse(&text) {
+        Ok(scenario) => scenario,
+        Err(err) => fail(format!("Invalid scenario {}: {:?}", path, err))
+    };
+
+    let manager = Arc::new(AdapterManager::new().with_polling(PollingPolicy::default()));
+    if let Err(err) = scenario.play(manager.clone(), speed) {
+        fail(format!("Cannot play scenario {}: {:?}", path, err));
+    }
+
+    let listener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(err) => fail(format!("Cannot listen on {}: {}", address, err))
+    };
+    println!("Serving {} on http://{}/api/v1", path, address);
+    for stream in listener.incoming() {
+        if let Ok(stream) = stream {
+            let manager = manager.clone();
+            thread::spawn(move || serve(&manager, stream));
+        }
+    }
+}
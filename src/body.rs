@@ -0,0 +1,396 @@
+//!
+//! Binary values as raw and multipart HTTP bodies.
+//!
+//! In JSON, a `Value::Binary` is represented as an array of numbers,
+//! which takes up to four times the size of the data and forces clients
+//! to decode it before, e.g., displaying the snapshot of a camera. HTTP
+//! frontends may instead exchange binary values as:
+//!
+//! - a raw body, whose `Content-Type` is the mimetype of the value,
+//!   when a single value is transferred;
+//! - a `multipart/form-data` body, with one part per value, each part
+//!   named after the id of its channel and carrying the mimetype of
+//!   its value as `Content-Type`.
+//!
+//! Which representation is used for a response is determined by the
+//! `Accept` header of the request, with `negotiate`. Representations
+//! other than JSON are only available if all the values of the
+//! response are binary.
+//!
+//! ```ignore
+//! let mimetypes: Vec<_> = values.iter().map(body::mimetype).collect();
+//! match body::negotiate(accept, &mimetypes) {
+//!     Some(Representation::Raw) => { /* body::to_raw(&values[0]) */ }
+//!     Some(Representation::Multipart) => { /* body::to_multipart(&parts) */ }
+//!     Some(Representation::Json) => { /* serde_json::to_string(&values) */ }
+//!     None => { /* Error 406 */ }
+//! }
+//! ```
+//!
+
+use values::{ Bytes, Type, Value };
+
+use std::sync::Arc;
+
+/// A representation of values in the body of an HTTP message.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Representation {
+    /// Values serialized as JSON, as documented in `API`.
+    Json,
+
+    /// The data of a single binary value.
+    Raw,
+
+    /// A `multipart/form-data` body, with one part per binary value.
+    Multipart,
+}
+
+/// A media type offered to clients, along with its representation.
+#[derive(Serialize, Debug, Clone)]
+pub struct Offer {
+    pub media_type: &'static str,
+    pub representation: Representation,
+}
+
+/// The media types offered to clients, in order of preference, in
+/// addition to the mimetype of a single binary value, which is always
+/// preferred and is served as `Representation::Raw`.
+pub static OFFERS: &'static [Offer] = &[
+    Offer {
+        media_type: "application/json",
+        representation: Representation::Json,
+    },
+    Offer {
+        media_type: "multipart/form-data",
+        representation: Representation::Multipart,
+    },
+];
+
+/// An error while decoding a body.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BodyError {
+    /// The `Content-Type` of a multipart body does not specify a
+    /// boundary.
+    MissingBoundary,
+
+    /// The body is not a valid multipart body. Payload is a description
+    /// of the problem.
+    Malformed(String),
+
+    /// A value cannot be represented as a raw body or a part, as it is
+    /// not binary. Payload is the name of the part, if any.
+    NotBinary(String),
+}
+
+/// A binary value, as the raw body of an HTTP message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawBody {
+    /// The `Content-Type` of the body.
+    pub content_type: String,
+
+    pub data: Bytes,
+}
+
+/// A part of a multipart body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// The name of the part, typically the id of a channel.
+    pub name: String,
+
+    pub value: Value,
+}
+
+/// The media type of a `Content-Type` header, lowercased and without
+/// its parameters, e.g. "image/png" for "image/PNG; q=0.5".
+pub fn media_type(header: &str) -> String {
+    header.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+/// Parameter `name` of a header such as `Content-Type` or
+/// `Content-Disposition`, unquoted.
+pub fn parameter(header: &str, name: &str) -> Option<String> {
+    let mut rest = match header.find(';') {
+        None => return None,
+        Some(index) => &header[index + 1..]
+    };
+    loop {
+        rest = rest.trim_left();
+        let equal = match rest.find('=') {
+            None => return None,
+            Some(index) => index
+        };
+        let key = rest[..equal].trim().to_lowercase();
+        rest = rest[equal + 1..].trim_left();
+        let (value, next) = if rest.starts_with('"') {
+            // A quoted string, in which `\` escapes the next character.
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let mut end = None;
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = Some(index + 2);
+                        break;
+                    }
+                    '\\' => if let Some((_, c)) = chars.next() {
+                        value.push(c)
+                    },
+                    c => value.push(c)
+                }
+            }
+            match end {
+                None => return None,
+                Some(end) => (value, &rest[end..])
+            }
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            (rest[..end].trim().to_owned(), &rest[end..])
+        };
+        if key == name {
+            return Some(value);
+        }
+        rest = match next.find(';') {
+            None => return None,
+            Some(index) => &next[index + 1..]
+        };
+    }
+}
+
+/// Quote a parameter of a header.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+/// The mimetype of `value`, if it is binary, possibly compressed.
+pub fn mimetype(value: &Value) -> Option<&str> {
+    match *value {
+        Value::Binary { ref mimetype, .. } => Some(mimetype),
+        Value::Compressed(ref compressed) if compressed.get_type() == Type::Binary =>
+            compressed.mimetype.as_ref().map(|mimetype| &**mimetype),
+        _ => None
+    }
+}
+
+/// Whether the media range of an `Accept` header, e.g. "image/*",
+/// matches `media_type`.
+fn matches(range: &str, media_type: &str) -> bool {
+    if range == "*/*" || range == media_type {
+        return true;
+    }
+    if range.ends_with("/*") {
+        let prefix = &range[..range.len() - 1];
+        return media_type.starts_with(prefix);
+    }
+    false
+}
+
+/// The representation of a response, as requested by the `Accept`
+/// header of the request, or `None` if none of the representations
+/// acceptable to the client is available, in which case the response
+/// should be Error 406.
+///
+/// `mimetypes` are the mimetypes of the values of the response, as
+/// per `mimetype`. An empty `Accept` header, as sent by clients that do
+/// not negotiate, selects `Representation::Json`.
+///
+/// The media ranges of the header are considered by decreasing quality,
+/// then in the order in which they appear. For each range, the mimetype
+/// of a single binary value is preferred, then `OFFERS`, in order.
+/// Range `*/*` does not match the mimetype of a value, so that clients
+/// accepting anything keep receiving JSON.
+pub fn negotiate(accept: &str, mimetypes: &[Option<&str>]) -> Option<Representation> {
+    if accept.trim().is_empty() {
+        return Some(Representation::Json);
+    }
+    let binary = mimetypes.iter().all(Option::is_some);
+    let single = match (mimetypes.len(), mimetypes.first()) {
+        (1, Some(&Some(mimetype))) => Some(mimetype.to_lowercase()),
+        _ => None
+    };
+
+    let mut best: Option<(f64, Representation)> = None;
+    for range in accept.split(',') {
+        let quality = parameter(range, "q").and_then(|q| q.parse::<f64>().ok()).unwrap_or(1.);
+        if quality <= 0. || best.map_or(false, |(best, _)| best >= quality) {
+            continue;
+        }
+        let range = media_type(range);
+        let raw = range != "*/*" && single.as_ref().map_or(false, |mimetype| matches(&range, mimetype));
+        let representation = if raw {
+            Some(Representation::Raw)
+        } else {
+            OFFERS.iter()
+                .filter(|offer| offer.representation == Representation::Json || binary)
+                .find(|offer| matches(&range, offer.media_type))
+                .map(|offer| offer.representation)
+        };
+        if let Some(representation) = representation {
+            best = Some((quality, representation));
+        }
+    }
+    best.map(|(_, representation)| representation)
+}
+
+/// The data of a binary value. Compressed values are decompressed.
+fn binary_data(value: &Value) -> Option<(String, Bytes)> {
+    match *value {
+        Value::Binary { ref data, ref mimetype } => Some((mimetype.clone(), data.clone())),
+        Value::Compressed(ref compressed) if compressed.get_type() == Type::Binary => {
+            match compressed.decompress() {
+                Ok(Value::Binary { data, mimetype }) => Some((mimetype, data)),
+                _ => None
+            }
+        }
+        _ => None
+    }
+}
+
+/// Represent a binary value as a raw body.
+pub fn to_raw(value: &Value) -> Result<RawBody, BodyError> {
+    match binary_data(value) {
+        Some((mimetype, data)) => Ok(RawBody {
+            content_type: mimetype,
+            data: data,
+        }),
+        None => Err(BodyError::NotBinary(String::new()))
+    }
+}
+
+/// The binary value held by a raw body. The mimetype of the value is
+/// the media type of `content_type`, or `application/octet-stream` if
+/// the body has no `Content-Type`.
+pub fn from_raw(content_type: Option<&str>, data: Vec<u8>) -> Value {
+    let mimetype = match content_type.map(media_type) {
+        Some(ref mimetype) if !mimetype.is_empty() => mimetype.clone(),
+        _ => "application/octet-stream".to_owned()
+    };
+    Value::Binary {
+        data: Bytes(Arc::new(data)),
+        mimetype: mimetype
+    }
+}
+
+/// The position of `needle` in `haystack`, starting from `from`.
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() || from > haystack.len() {
+        return None;
+    }
+    haystack[from..].windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| from + position)
+}
+
+/// Represent binary values as a multipart body. Returns the
+/// `Content-Type` of the body, which holds the boundary, and the body.
+pub fn to_multipart(parts: &[Part]) -> Result<(String, Vec<u8>), BodyError> {
+    let mut binaries = Vec::with_capacity(parts.len());
+    for part in parts {
+        match binary_data(&part.value) {
+            Some(binary) => binaries.push(binary),
+            None => return Err(BodyError::NotBinary(part.name.clone()))
+        }
+    }
+
+    // Pick a boundary that does not appear in the data.
+    let mut counter = 0;
+    let mut boundary = format!("foxbox-taxonomy-{}", counter);
+    while binaries.iter().any(|&(_, ref data)| find(data, boundary.as_bytes(), 0).is_some()) {
+        counter += 1;
+        boundary = format!("foxbox-taxonomy-{}", counter);
+    }
+
+    let mut body = vec![];
+    for (part, (mimetype, data)) in parts.iter().zip(binaries) {
+        body.extend_from_slice(format!("--{}\r\nContent-Disposition: form-data; name={}\r\nContent-Type: {}\r\n\r\n",
+                                       boundary, quote(&part.name), mimetype).as_bytes());
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok((format!("multipart/form-data; boundary={}", quote(&boundary)), body))
+}
+
+/// Decode a part, without its delimiter.
+fn parse_part(part: &[u8]) -> Result<Part, BodyError> {
+    let (headers, data) = if part.starts_with(b"\r\n") {
+        (&part[..0], &part[2..])
+    } else {
+        match find(part, b"\r\n\r\n", 0) {
+            None => return Err(BodyError::Malformed("Missing end of headers".to_owned())),
+            Some(end) => (&part[..end], &part[end + 4..])
+        }
+    };
+    let headers = match ::std::str::from_utf8(headers) {
+        Ok(headers) => headers,
+        Err(_) => return Err(BodyError::Malformed("Headers are not UTF-8".to_owned()))
+    };
+
+    let mut name = None;
+    let mut content_type = None;
+    for header in headers.split("\r\n") {
+        let mut fields = header.splitn(2, ':');
+        let key = fields.next().unwrap_or("").trim().to_lowercase();
+        let value = fields.next().unwrap_or("");
+        match &*key {
+            "content-disposition" => name = parameter(value, "name"),
+            "content-type" => content_type = Some(value),
+            _ => {}
+        }
+    }
+    let name = match name {
+        None => return Err(BodyError::Malformed("Missing name of part".to_owned())),
+        Some(name) => name
+    };
+    // As per RFC 7578, parts are `text/plain` by default.
+    let value = from_raw(Some(content_type.unwrap_or("text/plain")), data.to_vec());
+    Ok(Part {
+        name: name,
+        value: value
+    })
+}
+
+/// The binary values held by a multipart body, whose `Content-Type` is
+/// `content_type`. Each part becomes a `Value::Binary`, whose mimetype
+/// is the `Content-Type` of the part.
+pub fn from_multipart(content_type: &str, body: &[u8]) -> Result<Vec<Part>, BodyError> {
+    let boundary = match parameter(content_type, "boundary") {
+        Some(ref boundary) if !boundary.is_empty() => boundary.clone(),
+        _ => return Err(BodyError::MissingBoundary)
+    };
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut separator = b"\r\n".to_vec();
+    separator.extend_from_slice(&delimiter);
+
+    // Skip the preamble, if any.
+    let mut position = if body.starts_with(&delimiter) {
+        delimiter.len()
+    } else {
+        match find(body, &separator, 0) {
+            None => return Err(BodyError::Malformed("Missing delimiter".to_owned())),
+            Some(index) => index + separator.len()
+        }
+    };
+
+    let mut parts = vec![];
+    loop {
+        if body[position..].starts_with(b"--") {
+            // Closing delimiter. Ignore the epilogue.
+            return Ok(parts);
+        }
+        // Skip the transport padding and the line break after the
+        // delimiter.
+        while body[position..].starts_with(b" ") || body[position..].starts_with(b"\t") {
+            position += 1;
+        }
+        if !body[position..].starts_with(b"\r\n") {
+            return Err(BodyError::Malformed("Invalid delimiter".to_owned()));
+        }
+        position += 2;
+        let end = match find(body, &separator, position) {
+            None => return Err(BodyError::Malformed("Missing closing delimiter".to_owned())),
+            Some(end) => end
+        };
+        parts.push(try!(parse_part(&body[position..end])));
+        position = end + separator.len();
+    }
+}
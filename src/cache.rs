@@ -0,0 +1,484 @@
+//!
+//! Caching of the API.
+//!
+//! `Cached` wraps any implementation of the API and caches the results
+//! of `get_nodes` and `get_channel_value`, so that clients polling the
+//! API frequently (e.g. dashboards) do not hammer battery-powered
+//! devices. Values are cached for a duration that depends on their
+//! kind, while the topology is cached until it changes.
+//!
+//! Results of `get_nodes` are cached per caller, as the scopes of the
+//! caller may affect them. Any call that may change the topology, or a
+//! value, through `Cached` invalidates what it may change, even if it
+//! fails, as it may have partially succeeded.
+//!
+
+use api::*;
+use arbitration::Priority;
+use auth::Caller;
+use devices::*;
+use discovery::{Candidate, CandidateId};
+use history::{ Bucket, Sample };
+use metrics::Metrics;
+use selector::*;
+use values::{TimeStamp, Value, ValDuration};
+use util::{ Id, TagId };
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// The durations during which `Cached` keeps results.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachePolicy {
+    /// How long the result of `get_nodes` remains valid, unless the
+    /// topology changes in the meantime.
+    pub nodes_ttl: ValDuration,
+
+    /// How long a value remains valid, for specific kinds of channels.
+    #[serde(default)]
+    pub value_ttls: Vec<(ChannelKind, ValDuration)>,
+
+    /// How long a value remains valid, for kinds of channels that do
+    /// not appear in `value_ttls`.
+    pub default_value_ttl: ValDuration,
+}
+
+impl CachePolicy {
+    /// Create a policy keeping the topology for `nodes_ttl` and any
+    /// value for `default_value_ttl`.
+    pub fn new(nodes_ttl: ValDuration, default_value_ttl: ValDuration) -> Self {
+        CachePolicy {
+            nodes_ttl: nodes_ttl,
+            value_ttls: vec![],
+            default_value_ttl: default_value_ttl,
+        }
+    }
+
+    /// Keep values of a given kind for a specific duration.
+    pub fn with_value_ttl(mut self, kind: ChannelKind, ttl: ValDuration) -> Self {
+        self.value_ttls.retain(|&(ref k, _)| *k != kind);
+        self.value_ttls.push((kind, ttl));
+        self
+    }
+
+    fn value_ttl(&self, kind: &ChannelKind) -> Duration {
+        for &(ref k, ref ttl) in &self.value_ttls {
+            if k == kind {
+                return ttl.as_duration();
+            }
+        }
+        self.default_value_ttl.as_duration()
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    /// The results of `get_nodes`, indexed by the JSON representation
+    /// of the caller and the selectors.
+    nodes: HashMap<String, (Instant, Vec<Arc<Node>>)>,
+
+    /// The latest value fetched from each channel.
+    values: HashMap<Id<Getter>, (Instant, FetchedValue)>,
+}
+
+impl CacheState {
+    fn invalidate_topology(&mut self) {
+        self.nodes.clear();
+    }
+
+    fn invalidate_values(&mut self) {
+        self.values.clear();
+    }
+}
+
+/// An implementation of the API caching the results of another
+/// implementation.
+pub struct Cached<A> where A: API {
+    inner: A,
+    policy: CachePolicy,
+    state: Arc<Mutex<CacheState>>,
+
+    /// Watching the topology, to invalidate the cache whenever it changes.
+    _topology_guard: A::WatchGuard,
+}
+
+impl<A> Cached<A> where A: API {
+    /// Wrap `inner`.
+    ///
+    /// This registers a watch on `inner` to be informed of changes in
+    /// the topology, so this fails if the watch cannot be registered.
+    pub fn new(inner: A, policy: CachePolicy) -> Result<Self, Error> {
+        let state = Arc::new(Mutex::new(CacheState::default()));
+        let guard = {
+            let state = state.clone();
+            let options = vec![WatchOptions::new()
+                .with_watch_topology(true)
+                .with_owner("cache".to_owned())];
+            try!(inner.register_channel_watch(&Caller::system(), options, Box::new(move |event| {
+                let mut state = state.lock().unwrap();
+                match event {
+                    WatchEvent::GetterRemoved(id) => {
+                        state.values.remove(&id);
+                        state.invalidate_topology();
+                    }
+                    // Values are cached for a duration of their own.
+                    WatchEvent::Value { .. } |
+                    WatchEvent::Error { .. } => {}
+                    // Any other event, e.g. a node becoming unavailable,
+                    // may affect the results of `get_nodes`.
+                    _ => state.invalidate_topology()
+                }
+            })))
+        };
+        Ok(Cached {
+            inner: inner,
+            policy: policy,
+            state: state,
+            _topology_guard: guard,
+        })
+    }
+
+    /// Access the underlying implementation, bypassing the cache.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Forget all cached results.
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.nodes.clear();
+        state.values.clear();
+    }
+
+    fn invalidate_topology(&self) {
+        self.state.lock().unwrap().invalidate_topology();
+    }
+
+    /// Invalidate the topology once a tag, label or topology operation
+    /// has been performed, as it may affect the results of `get_nodes`.
+    fn after_edit<T>(&self, result: Result<T, Error>) -> Result<T, Error> {
+        self.invalidate_topology();
+        result
+    }
+
+    /// Invalidate the values once a value has been sent, as sending a
+    /// value to a setter may change the value of any getter, e.g. the
+    /// temperature measured by a thermostat.
+    fn after_send<T>(&self, result: T) -> T {
+        self.state.lock().unwrap().invalidate_values();
+        result
+    }
+}
+
+impl<A> API for Cached<A> where A: API {
+    type WatchGuard = A::WatchGuard;
+
+    fn get_api_capabilities(&self, caller: &Caller) -> Vec<Capability> {
+        self.inner.get_api_capabilities(caller)
+    }
+
+    fn get_supported_kinds(&self, caller: &Caller) -> Vec<KindDescription> {
+        self.inner.get_supported_kinds(caller)
+    }
+
+    fn get_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>) -> Vec<Arc<Node>> {
+        let key = match serde_json::to_string(&(caller, selectors)) {
+            Ok(key) => key,
+            Err(_) => return self.inner.get_nodes(caller, selectors)
+        };
+        let ttl = self.policy.nodes_ttl.as_duration();
+        if let Some(&(ref date, ref nodes)) = self.state.lock().unwrap().nodes.get(&key) {
+            if date.elapsed() < ttl {
+                return nodes.clone();
+            }
+        }
+        let nodes = self.inner.get_nodes(caller, selectors);
+        self.state.lock().unwrap().nodes.insert(key, (Instant::now(), nodes.clone()));
+        nodes
+    }
+
+    fn get_topology_revision(&self, caller: &Caller) -> Revision {
+        self.inner.get_topology_revision(caller)
+    }
+
+    fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>> {
+        self.inner.get_nodes_if_changed(caller, selectors, since)
+    }
+
+    fn visit_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>, cb: &mut FnMut(&Node)) {
+        self.inner.visit_nodes(caller, selectors, cb)
+    }
+
+    fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error> {
+        let getters = self.inner.get_getter_channels(caller, &vec![GetterSelector::new().with_parent(id.clone())]);
+        let result = self.inner.forget_node(caller, id);
+        let mut state = self.state.lock().unwrap();
+        for getter in getters {
+            state.values.remove(&getter.id);
+        }
+        state.invalidate_topology();
+        result
+    }
+
+    fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error> {
+        self.after_edit(self.inner.put_node_tag(caller, set, tags))
+    }
+
+    fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error> {
+        self.after_edit(self.inner.delete_node_tag(caller, set, tags))
+    }
+
+    fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error> {
+        self.after_edit(self.inner.put_tagging_rule(caller, rule))
+    }
+
+    fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error> {
+        self.after_edit(self.inner.delete_tagging_rule(caller, id))
+    }
+
+    fn get_tagging_rules(&self, caller: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)> {
+        self.inner.get_tagging_rules(caller)
+    }
+
+    fn get_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>> {
+        self.inner.get_getter_channels(caller, selectors)
+    }
+
+    fn get_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>> {
+        self.inner.get_setter_channels(caller, selectors)
+    }
+
+    fn visit_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>, cb: &mut FnMut(&Channel<Getter>)) {
+        self.inner.visit_getter_channels(caller, selectors, cb)
+    }
+
+    fn visit_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>, cb: &mut FnMut(&Channel<Setter>)) {
+        self.inner.visit_setter_channels(caller, selectors, cb)
+    }
+
+    fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error> {
+        self.after_edit(self.inner.put_aggregate_getter(caller, aggregate))
+    }
+
+    fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error> {
+        let result = self.inner.delete_aggregate_getter(caller, id);
+        let mut state = self.state.lock().unwrap();
+        state.values.remove(id);
+        state.invalidate_topology();
+        result
+    }
+
+    fn get_aggregate_getters(&self, caller: &Caller) -> Vec<(Id<Getter>, AggregateGetter)> {
+        self.inner.get_aggregate_getters(caller)
+    }
+
+    fn put_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.after_edit(self.inner.put_getter_tag(caller, set, tags))
+    }
+
+    fn put_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.after_edit(self.inner.put_setter_tag(caller, set, tags))
+    }
+
+    fn delete_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.after_edit(self.inner.delete_getter_tag(caller, set, tags))
+    }
+
+    fn delete_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.after_edit(self.inner.delete_setter_tag(caller, set, tags))
+    }
+
+    fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error> {
+        self.after_edit(self.inner.set_node_label(caller, set, label))
+    }
+
+    fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error> {
+        self.after_edit(self.inner.clear_node_label(caller, set))
+    }
+
+    fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error> {
+        self.after_edit(self.inner.set_getter_label(caller, set, label))
+    }
+
+    fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error> {
+        self.after_edit(self.inner.set_setter_label(caller, set, label))
+    }
+
+    fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error> {
+        self.after_edit(self.inner.clear_getter_label(caller, set))
+    }
+
+    fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error> {
+        self.after_edit(self.inner.clear_setter_label(caller, set))
+    }
+
+    fn get_channel_value(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let channels = self.inner.get_getter_channels(caller, selectors);
+        let mut results = vec![];
+        let mut missing = vec![];
+        {
+            let state = self.state.lock().unwrap();
+            for channel in channels {
+                let ttl = self.policy.value_ttl(&channel.mechanism.kind);
+                match state.values.get(&channel.id) {
+                    Some(&(ref date, ref value)) if date.elapsed() < ttl => {
+                        let mut value = value.clone();
+                        value.source = ValueSource::Cache;
+                        value.age = Some(ValDuration::new(date.elapsed()));
+                        results.push((channel.id.clone(), Ok(value)));
+                    }
+                    _ => missing.push(GetterSelector::new().with_id(channel.id.clone()))
+                }
+            }
+        }
+        if !missing.is_empty() {
+            let fetched = self.inner.get_channel_value(caller, &missing);
+            let mut state = self.state.lock().unwrap();
+            for (id, result) in fetched {
+                if let Ok(ref value) = result {
+                    state.values.insert(id.clone(), (Instant::now(), value.clone()));
+                }
+                results.push((id, result));
+            }
+        }
+        results
+    }
+
+    fn poll_now(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let fetched = self.inner.poll_now(caller, selectors);
+        let mut state = self.state.lock().unwrap();
+        for &(ref id, ref result) in &fetched {
+            if let Ok(ref value) = *result {
+                state.values.insert(id.clone(), (Instant::now(), value.clone()));
+            }
+        }
+        fetched
+    }
+
+    fn get_channel_values_since(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)> {
+        self.inner.get_channel_values_since(caller, selectors, since)
+    }
+
+    fn put_channel_value(&self, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+        self.after_send(self.inner.put_channel_value(caller, selectors, value, priority))
+    }
+
+    fn get_state_value(&self, caller: &Caller, id: &Id<StateId>) -> Result<Option<FetchedValue>, Error> {
+        self.inner.get_state_value(caller, id)
+    }
+
+    fn put_state_value(&self, caller: &Caller, id: &Id<StateId>, value: Value, priority: Priority) -> Result<Acknowledgement, Error> {
+        self.after_send(self.inner.put_state_value(caller, id, value, priority))
+    }
+
+    fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error> {
+        self.inner.put_channel_value_at(caller, set, value, at)
+    }
+
+    fn put_channel_value_after(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, delay: ValDuration) -> Result<Id<ScheduleId>, Error> {
+        self.inner.put_channel_value_after(caller, set, value, delay)
+    }
+
+    fn get_scheduled_writes(&self, caller: &Caller) -> Vec<ScheduledWrite> {
+        self.inner.get_scheduled_writes(caller)
+    }
+
+    fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error> {
+        self.inner.cancel_scheduled_write(caller, id)
+    }
+
+    fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error> {
+        self.inner.reserve_setters(caller, set, duration)
+    }
+
+    fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error> {
+        self.inner.release_reservation(caller, id)
+    }
+
+    fn get_reservations(&self, caller: &Caller) -> Vec<Reservation> {
+        self.inner.get_reservations(caller)
+    }
+
+    fn put_polling_hint(&self, caller: &Caller, source: &Vec<GetterSelector>, interval: ValDuration, duration: ValDuration) -> Result<PollingHint, Error> {
+        self.inner.put_polling_hint(caller, source, interval, duration)
+    }
+
+    fn delete_polling_hint(&self, caller: &Caller, id: &Id<PollingHintId>) -> Result<(), Error> {
+        self.inner.delete_polling_hint(caller, id)
+    }
+
+    fn get_polling_hints(&self, caller: &Caller) -> Vec<PollingHint> {
+        self.inner.get_polling_hints(caller)
+    }
+
+    fn register_channel_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error> {
+        self.inner.register_channel_watch(caller, options, cb)
+    }
+
+    fn register_channel_watch_channel(&self, caller: &Caller, options: Vec<WatchOptions>) -> Result<(Receiver<WatchEvent>, Self::WatchGuard), Error> {
+        self.inner.register_channel_watch_channel(caller, options)
+    }
+
+    fn register_channel_watch_with_token(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        self.inner.register_channel_watch_with_token(caller, options, cb)
+    }
+
+    fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error> {
+        self.inner.unregister_watch(caller, token)
+    }
+
+    fn get_watches(&self, caller: &Caller) -> Result<Vec<WatchInfo>, Error> {
+        self.inner.get_watches(caller)
+    }
+
+    fn get_adapter_health(&self, caller: &Caller) -> Result<Vec<AdapterHealth>, Error> {
+        self.inner.get_adapter_health(caller)
+    }
+
+    fn get_metrics(&self, caller: &Caller) -> Result<Vec<Metrics>, Error> {
+        self.inner.get_metrics(caller)
+    }
+
+    fn get_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error> {
+        self.inner.get_adapter_config(caller, adapter)
+    }
+
+    fn put_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error> {
+        // The configuration of an adapter may affect its nodes and their values.
+        let result = self.after_edit(self.inner.put_adapter_config(caller, adapter, key, value));
+        self.after_send(result)
+    }
+
+    fn get_channel_history(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error> {
+        self.inner.get_channel_history(caller, selectors, since, until)
+    }
+
+    fn get_channel_history_aggregate(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>,
+                                     bucket: ValDuration, aggregation: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error> {
+        self.inner.get_channel_history_aggregate(caller, selectors, since, until, bucket, aggregation)
+    }
+
+    fn get_candidates(&self, caller: &Caller) -> Result<Vec<Candidate>, Error> {
+        self.inner.get_candidates(caller)
+    }
+
+    fn adopt_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>, adapter: Option<Id<AdapterId>>) -> Result<(), Error> {
+        self.after_edit(self.inner.adopt_candidate(caller, candidate, adapter))
+    }
+
+    fn reject_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>) -> Result<(), Error> {
+        self.after_edit(self.inner.reject_candidate(caller, candidate))
+    }
+
+    fn register_durable_watch(&self, caller: &Caller, owner: String, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        self.inner.register_durable_watch(caller, owner, options, cb)
+    }
+
+    fn resume_watch(&self, caller: &Caller, owner: &str, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        self.inner.resume_watch(caller, owner, cb)
+    }
+}
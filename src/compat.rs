@@ -0,0 +1,295 @@
+//!
+//! Golden examples of the wire format.
+//!
+//! FoxBoxes are updated independently from their clients, e.g. web
+//! applications, mobile clients or the rules stored by rules engines,
+//! which keep sending and parsing what they were written against. This
+//! module embeds canonical serialized examples of the public types, as
+//! written by each version of the wire format, and `check_compat`
+//! verifies that the current code still parses all of them, so that a
+//! change breaking deployed clients fails the tests of the change
+//! rather than surprising users.
+//!
+//! Fixtures are never changed nor removed once their version has
+//! shipped. A change that cannot parse a fixture any longer must either
+//! be reverted or come with a migration, see module `migration`. New
+//! fixtures are added with the current `VERSION` whenever a type or a
+//! variant is added to the wire format.
+//!
+//! JSON fixtures are always checked. Protocol Buffers fixtures are
+//! checked with feature `protobuf`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use foxbox_taxonomy::compat::check_compat;
+//!
+//! if let Err(errors) = check_compat() {
+//!     panic!("The wire format has changed: {:?}", errors);
+//! }
+//! ```
+//!
+
+use api::{ WatchEvent, WatchOptions };
+use devices::{ Channel, ChannelKind, Getter, Node, Setter };
+use document::Document;
+use selector::{ GetterSelector, NodeSelector, SetterSelector };
+use values::{ Range, Type, Value };
+use util::TagId;
+use websocket::{ ClientMessage, ServerMessage };
+
+use serde::de::Deserialize;
+use serde_json;
+
+use std::str;
+
+/// The version of the wire format written by this implementation.
+pub const VERSION: u32 = 1;
+
+/// The encoding of a fixture.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Protobuf,
+}
+
+/// A canonical serialized example of a public type.
+pub struct Fixture {
+    /// The name of the type, e.g. "Value".
+    pub typ: &'static str,
+
+    /// Distinguishes the fixtures of the same type, e.g. "Temperature".
+    pub name: &'static str,
+
+    /// The version of the wire format with which the fixture was written.
+    pub version: u32,
+
+    pub format: Format,
+
+    pub data: &'static [u8],
+
+    parse: fn(&[u8]) -> Result<(), String>,
+}
+
+impl Fixture {
+    /// Parse the fixture with the current code.
+    pub fn check(&self) -> Result<(), CompatError> {
+        (self.parse)(self.data).map_err(|reason| CompatError {
+            typ: self.typ.to_owned(),
+            name: self.name.to_owned(),
+            version: self.version,
+            format: self.format,
+            reason: reason,
+        })
+    }
+}
+
+/// A fixture that the current code cannot parse.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CompatError {
+    pub typ: String,
+    pub name: String,
+    pub version: u32,
+    pub format: Format,
+
+    /// A human-readable description of the error.
+    pub reason: String,
+}
+
+fn parse_json<T>(data: &[u8]) -> Result<(), String> where T: Deserialize {
+    let source = try!(str::from_utf8(data).map_err(|err| format!("{}", err)));
+    serde_json::from_str::<T>(source)
+        .map(|_| ())
+        .map_err(|err| format!("{:?}", err))
+}
+
+fn parse_document(data: &[u8]) -> Result<(), String> {
+    let source = try!(str::from_utf8(data).map_err(|err| format!("{}", err)));
+    Document::from_json(source)
+        .map(|_| ())
+        .map_err(|err| format!("{:?}", err))
+}
+
+fn json(typ: &'static str, name: &'static str, version: u32, data: &'static [u8], parse: fn(&[u8]) -> Result<(), String>) -> Fixture {
+    Fixture {
+        typ: typ,
+        name: name,
+        version: version,
+        format: Format::Json,
+        data: data,
+        parse: parse,
+    }
+}
+
+fn json_fixtures() -> Vec<Fixture> {
+    vec![
+        json("Value", "Unit", 1, br#"{"Unit":[]}"#, parse_json::<Value>),
+        json("Value", "Bool", 1, br#"{"Bool":true}"#, parse_json::<Value>),
+        json("Value", "Duration", 1, br#"{"Duration":1500}"#, parse_json::<Value>),
+        json("Value", "TimeStamp", 1, br#"{"TimeStamp":"2016-04-12T10:30:00+00:00"}"#, parse_json::<Value>),
+        json("Value", "Temperature", 1, br#"{"Temperature":{"C":21.5}}"#, parse_json::<Value>),
+        json("Value", "Color", 1, br#"{"Color":{"RGBA":[1.0,0.5,0.0,1.0,0.0]}}"#, parse_json::<Value>),
+        json("Value", "String", 1, br#"{"String":"Hello"}"#, parse_json::<Value>),
+        json("Value", "Count", 1, br#"{"Count":2}"#, parse_json::<Value>),
+        json("Value", "ExtNumeric", 1,
+             br#"{"ExtNumeric":{"value":42.0,"vendor":"foxlink@mozilla.com","adapter":"zwave","kind":"GroundHumidity"}}"#,
+             parse_json::<Value>),
+        json("Value", "Json", 1, br#"{"Json":{"temperature":21,"unit":"C"}}"#, parse_json::<Value>),
+        json("Value", "JsonPatch", 1, br#"{"JsonPatch":[{"op":"replace","path":"/volume","value":5}]}"#,
+             parse_json::<Value>),
+        json("Value", "Binary", 1, br#"{"Binary":{"data":[0,1,2,255],"mimetype":"application/octet-stream"}}"#,
+             parse_json::<Value>),
+        json("Value", "Compressed", 1,
+             br#"{"Compressed":{"algorithm":{"Deflate":[]},"original_size":4,"mimetype":"application/octet-stream","data":[99,96,100,250,15,0]}}"#,
+             parse_json::<Value>),
+
+        json("Type", "Temperature", 1, br#"{"Temperature":[]}"#, parse_json::<Type>),
+
+        json("Range", "Leq", 1, br#"{"Leq":{"Temperature":{"C":18.0}}}"#, parse_json::<Range>),
+        json("Range", "BetweenEq", 1,
+             br#"{"BetweenEq":{"min":{"Temperature":{"C":18.0}},"max":{"Temperature":{"C":22.0}}}}"#,
+             parse_json::<Range>),
+        json("Range", "Eq", 1, br#"{"Eq":{"Bool":true}}"#, parse_json::<Range>),
+
+        json("TagId", "Normalized", 1, br#""kitchen""#, parse_json::<TagId>),
+
+        json("ChannelKind", "Standard", 1, br#"{"OnOff":[]}"#, parse_json::<ChannelKind>),
+        json("ChannelKind", "Event", 1, br#"{"ButtonPressed":[]}"#, parse_json::<ChannelKind>),
+        json("ChannelKind", "Extension", 1,
+             br#"{"Extension":{"vendor":"foxlink@mozilla.com","adapter":"zwave","kind":"GroundHumidity","typ":{"ExtNumeric":[]}}}"#,
+             parse_json::<ChannelKind>),
+
+        json("Channel<Getter>", "Full", 1,
+             br#"{"tags":["kitchen"],"id":"zwave@foxlink.mozilla.com/node-3/switch","label":"Lamp","node":"zwave@foxlink.mozilla.com/node-3","mechanism":{"kind":{"OnOff":[]},"poll":null,"trigger":null,"watch":true,"updated":null},"last_seen":"2016-04-12T10:30:00+00:00"}"#,
+             parse_json::<Channel<Getter>>),
+        json("Channel<Getter>", "Event", 1,
+             br#"{"tags":[],"id":"zwave@foxlink.mozilla.com/node-4/button","label":null,"node":"zwave@foxlink.mozilla.com/node-4","mechanism":{"kind":{"OnOff":[]},"semantics":{"Event":[]},"poll":null,"trigger":null,"watch":true,"updated":null},"last_seen":null}"#,
+             parse_json::<Channel<Getter>>),
+        json("Channel<Setter>", "Minimal", 1,
+             br#"{"id":"zwave@foxlink.mozilla.com/node-3/set-switch","node":"zwave@foxlink.mozilla.com/node-3","mechanism":{"kind":{"OnOff":[]}}}"#,
+             parse_json::<Channel<Setter>>),
+        json("Node", "Full", 1,
+             br#"{"tags":["kitchen"],"id":"zwave@foxlink.mozilla.com/node-3","label":"Fridge","getters":[{"tags":[],"id":"zwave@foxlink.mozilla.com/node-3/switch","label":null,"node":"zwave@foxlink.mozilla.com/node-3","mechanism":{"kind":{"OnOff":[]},"poll":null,"trigger":null,"watch":false,"updated":null},"last_seen":null}],"setters":[]}"#,
+             parse_json::<Node>),
+        json("Node", "State", 1,
+             br#"{"tags":[],"id":"zwave@foxlink.mozilla.com/node-3","label":null,"getters":[{"tags":[],"id":"zwave@foxlink.mozilla.com/node-3/switch","label":null,"node":"zwave@foxlink.mozilla.com/node-3","mechanism":{"kind":{"OnOff":[]},"poll":null,"trigger":null,"watch":true,"updated":null},"last_seen":null}],"setters":[{"tags":[],"id":"zwave@foxlink.mozilla.com/node-3/set-switch","label":null,"node":"zwave@foxlink.mozilla.com/node-3","mechanism":{"kind":{"OnOff":[]},"push":null,"updated":null},"last_seen":null}],"states":[{"id":"zwave@foxlink.mozilla.com/node-3/power","getter":"zwave@foxlink.mozilla.com/node-3/switch","setter":"zwave@foxlink.mozilla.com/node-3/set-switch","kind":{"OnOff":[]}}]}"#,
+             parse_json::<Node>),
+
+        json("NodeSelector", "Empty", 1, br#"{}"#, parse_json::<NodeSelector>),
+        json("NodeSelector", "Full", 1,
+             br#"{"id":{"Exactly":"zwave@foxlink.mozilla.com/node-3"},"tags":["kitchen"],"getters":[{"kind":{"Exactly":{"OnOff":[]}}}],"setters":[]}"#,
+             parse_json::<NodeSelector>),
+        json("GetterSelector", "Full", 1,
+             br#"{"id":{"Empty":[]},"parent":{"Exactly":"zwave@foxlink.mozilla.com/node-3"},"tags":["kitchen"],"kind":{"Exactly":{"ActualTemperature":[]}},"poll":{"min":1000,"max":null},"trigger":null}"#,
+             parse_json::<GetterSelector>),
+        json("SetterSelector", "Tags", 1, br#"{"tags":["kitchen","lamp"]}"#, parse_json::<SetterSelector>),
+
+        json("WatchOptions", "Values", 1,
+             br#"{"source":{"tags":["kitchen"]},"should_watch_values":true,"should_watch_topology":false}"#,
+             parse_json::<WatchOptions>),
+        json("WatchOptions", "Expiring", 1,
+             br#"{"source":{},"should_watch_values":true,"should_watch_topology":false,"expires_after":60,"max_events":1}"#,
+             parse_json::<WatchOptions>),
+        json("WatchOptions", "MinDelta", 1,
+             br#"{"source":{},"should_watch_values":true,"should_watch_topology":false,"min_delta":{"Temperature":{"C":0.5}}}"#,
+             parse_json::<WatchOptions>),
+        json("WatchEvent", "Value", 1,
+             br#"{"Value":{"from":"thermometer/temperature","value":{"Temperature":{"C":21.5}}}}"#,
+             parse_json::<WatchEvent>),
+        json("WatchEvent", "GetterAdded", 1, br#"{"GetterAdded":"thermometer/temperature"}"#, parse_json::<WatchEvent>),
+        json("WatchEvent", "Missed", 1, br#"{"Missed":3}"#, parse_json::<WatchEvent>),
+        json("WatchEvent", "Expired", 1, br#"{"Expired":[]}"#, parse_json::<WatchEvent>),
+        json("WatchEvent", "KindRegistered", 1,
+             br#"{"KindRegistered":{"adapter":"zwave@foxlink.mozilla.com","description":{"kind":{"Extension":{"vendor":"foxlink@mozilla.com","adapter":"zwave","kind":"GroundHumidity","typ":{"ExtNumeric":[]}}},"typ":{"ExtNumeric":[]},"semantics":{"State":[]},"description":"The humidity of the ground.","getter":true,"setter":false}}}"#,
+             parse_json::<WatchEvent>),
+
+        json("ClientMessage", "Subscribe", 1,
+             br#"{"Subscribe":{"request":"1","options":[{"source":{},"should_watch_values":true,"should_watch_topology":false}]}}"#,
+             parse_json::<ClientMessage>),
+        json("ClientMessage", "Resume", 1,
+             br#"{"Resume":{"request":"7","subscription":"watch-4","sequence":12}}"#,
+             parse_json::<ClientMessage>),
+        json("ServerMessage", "Event", 1,
+             br#"{"Event":{"subscription":"watch-4","sequence":0,"event":{"GetterRemoved":"thermometer/temperature"}}}"#,
+             parse_json::<ServerMessage>),
+        json("ServerMessage", "Heartbeat", 1, br#"{"Heartbeat":[]}"#, parse_json::<ServerMessage>),
+        json("ServerMessage", "Resync", 1,
+             br#"{"Resync":{"request":"7","subscription":"watch-4","sequence":40}}"#,
+             parse_json::<ServerMessage>),
+
+        json("Document", "Annotations", 1,
+             br#"{"format":"foxbox-taxonomy/state","version":1,"exported":"2016-04-12T10:30:00+00:00","annotations":[{"target":{"Node":"zwave@foxlink.mozilla.com/node-3"},"tags":["kitchen"],"label":"Fridge"}],"tagging_rules":[],"config":{}}"#,
+             parse_document),
+    ]
+}
+
+#[cfg(feature = "protobuf")]
+fn protobuf_fixtures() -> Vec<Fixture> {
+    use protobuf::Message;
+
+    fn parse<T>(data: &[u8]) -> Result<(), String> where T: Message {
+        T::from_bytes(data)
+            .map(|_| ())
+            .map_err(|err| format!("{:?}", err))
+    }
+
+    fn protobuf(typ: &'static str, name: &'static str, version: u32, data: &'static [u8], parse: fn(&[u8]) -> Result<(), String>) -> Fixture {
+        Fixture {
+            typ: typ,
+            name: name,
+            version: version,
+            format: Format::Protobuf,
+            data: data,
+            parse: parse,
+        }
+    }
+
+    vec![
+        protobuf("Value", "Bool", 1, b"\x10\x01", parse::<Value>),
+        protobuf("Value", "Temperature", 1, b"\x2a\x09\x11\x00\x00\x00\x00\x00\x80\x35\x40", parse::<Value>),
+        protobuf("Value", "String", 1, b"\x3a\x05Hello", parse::<Value>),
+        protobuf("Value", "Count", 1, b"\x60\x02", parse::<Value>),
+        protobuf("GetterSelector", "Kind and tags", 1, b"\x1a\x07kitchen\x22\x09\x0a\x07\x0a\x05OnOff",
+                 parse::<GetterSelector>),
+        protobuf("Node", "Full", 1,
+                 b"\x0a\x20zwave@foxlink.mozilla.com/node-3\x12\x07kitchen\x1a\x06Fridge\
+                   \x22\x56\x0a\x27zwave@foxlink.mozilla.com/node-3/switch\
+                   \x12\x20zwave@foxlink.mozilla.com/node-3\x32\x07\x0a\x05OnOff\x48\x01",
+                 parse::<Node>),
+        protobuf("WatchEvent", "Value", 1,
+                 b"\x0a\x26\x0a\x17thermometer/temperature\x12\x0b\x2a\x09\x11\x00\x00\x00\x00\x00\x80\x35\x40",
+                 parse::<WatchEvent>),
+        protobuf("WatchEvent", "Missed", 1, b"\x48\x03", parse::<WatchEvent>),
+        protobuf("WatchEvent", "Expired", 1, b"\x50\x01", parse::<WatchEvent>),
+        protobuf("WatchEvent", "KindRemoved", 1,
+                 b"\x62\x57\x0a\x19zwave@foxlink.mozilla.com\x12\x3a\x12\x38\x0a\x13foxlink@mozilla.com\
+                   \x12\x05zwave\x1a\x0eGroundHumidity\x22\x0aExtNumeric",
+                 parse::<WatchEvent>),
+    ]
+}
+
+#[cfg(not(feature = "protobuf"))]
+fn protobuf_fixtures() -> Vec<Fixture> {
+    vec![]
+}
+
+/// All the fixtures, for all versions of the wire format up to
+/// `VERSION`.
+pub fn fixtures() -> Vec<Fixture> {
+    let mut fixtures = json_fixtures();
+    fixtures.extend(protobuf_fixtures());
+    fixtures
+}
+
+/// Check that the current code parses all the fixtures, returning the
+/// fixtures that it cannot parse, if any.
+pub fn check_compat() -> Result<(), Vec<CompatError>> {
+    let errors: Vec<_> = fixtures().iter()
+        .filter_map(|fixture| fixture.check().err())
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,823 @@
+//!
+//! Compression of large values on the wire.
+//!
+//! A `Value::Binary`, e.g. a frame of a camera, or a large `Value::Json`
+//! may be sent to clients as a `Value::Compressed`, which wraps the
+//! data compressed with DEFLATE (RFC 1951, without zlib header) or gzip
+//! (RFC 1952), along with the size of the original data.
+//! `CompressionPolicy::apply` compresses the values whose data exceeds
+//! a threshold, as done by the WebSocket sessions created with
+//! `websocket::Session::with_compression`. Clients decompress the data
+//! with any standard library, e.g. `DecompressionStream` in browsers,
+//! then interpret it as the original value: the data of a `Binary`
+//! with the given mimetype, or the JSON text of a `Json`.
+//!
+//! The compressor favours speed over ratio: it searches matches along
+//! hash chains of limited length and uses the fixed Huffman codes of
+//! DEFLATE. The decompressor accepts any valid stream, and rejects
+//! streams that expand beyond the size announced by the envelope.
+//! Clients choose that size, so envelopes announcing more than
+//! `MAX_DECOMPRESSED_SIZE` are rejected before decompressing anything.
+//!
+
+use values::{ Bytes, Json, Type, Value };
+
+use serde_json;
+
+use std::str;
+use std::sync::Arc;
+
+/// The largest size of data, in bytes, to which a `Compressed` may
+/// decompress.
+pub const MAX_DECOMPRESSED_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The compression algorithm of a `Compressed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    /// A raw DEFLATE stream, as per RFC 1951.
+    Deflate,
+
+    /// A gzip member, as per RFC 1952, i.e. a DEFLATE stream with a
+    /// header and a checksum.
+    Gzip,
+}
+
+/// An error while decompressing a value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CompressionError {
+    /// The data is not a valid stream for the algorithm. Payload is a
+    /// description of the problem.
+    Corrupt(String),
+
+    /// The data does not decompress to the size announced by the
+    /// envelope.
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+
+    /// The envelope announces a size larger than
+    /// `MAX_DECOMPRESSED_SIZE`. Payload is the announced size.
+    TooLarge(u64),
+
+    /// The checksum of a gzip member does not match its data.
+    ChecksumMismatch,
+
+    /// The decompressed data is not a valid JSON text.
+    InvalidJson,
+}
+
+/// The data of a `Value::Binary` or `Value::Json`, compressed. See the
+/// module documentation.
+///
+/// Compressed values are a representation on the wire: adapters never
+/// receive them, as the manager decompresses the values sent to
+/// setters, and should not produce them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Compressed {
+    pub algorithm: Algorithm,
+
+    /// The size of the data once decompressed, in bytes.
+    pub original_size: u64,
+
+    /// If `Some(mimetype)`, the original value is a `Value::Binary`
+    /// with this mimetype. Otherwise, it is a `Value::Json`, and the
+    /// data is its JSON text.
+    #[serde(default)]
+    pub mimetype: Option<String>,
+
+    /// The compressed data.
+    pub data: Bytes,
+}
+
+impl Compressed {
+    /// Compress a `Value::Binary` or a `Value::Json`, or return `None`
+    /// for other values.
+    pub fn compress(value: &Value, algorithm: Algorithm) -> Option<Self> {
+        let (original, mimetype) = match *value {
+            Value::Binary { ref data, ref mimetype } => ((**data).clone(), Some(mimetype.clone())),
+            // Serializing a JSON value cannot fail.
+            Value::Json(ref json) => (serde_json::to_string(&json.0).unwrap().into_bytes(), None),
+            _ => return None
+        };
+        let data = match algorithm {
+            Algorithm::Deflate => deflate(&original),
+            Algorithm::Gzip => gzip(&original),
+        };
+        Some(Compressed {
+            algorithm: algorithm,
+            original_size: original.len() as u64,
+            mimetype: mimetype,
+            data: Bytes(Arc::new(data)),
+        })
+    }
+
+    /// The type of the original value.
+    pub fn get_type(&self) -> Type {
+        match self.mimetype {
+            Some(_) => Type::Binary,
+            None => Type::Json,
+        }
+    }
+
+    /// Decompress the original value.
+    ///
+    /// # Errors
+    ///
+    /// `TooLarge` if the original value is announced to be larger than
+    /// `MAX_DECOMPRESSED_SIZE`, without decompressing anything.
+    pub fn decompress(&self) -> Result<Value, CompressionError> {
+        if self.original_size > MAX_DECOMPRESSED_SIZE {
+            return Err(CompressionError::TooLarge(self.original_size));
+        }
+        let data = try!(match self.algorithm {
+            Algorithm::Deflate => inflate(&self.data, self.original_size).map(|(data, _)| data),
+            Algorithm::Gzip => gunzip(&self.data, self.original_size),
+        });
+        if data.len() as u64 != self.original_size {
+            return Err(CompressionError::SizeMismatch {
+                expected: self.original_size,
+                actual: data.len() as u64,
+            });
+        }
+        match self.mimetype {
+            Some(ref mimetype) => Ok(Value::Binary {
+                data: Bytes(Arc::new(data)),
+                mimetype: mimetype.clone()
+            }),
+            None => {
+                let json = try!(str::from_utf8(&data).ok()
+                    .and_then(|text| serde_json::from_str(text).ok())
+                    .ok_or(CompressionError::InvalidJson));
+                Ok(Value::Json(Arc::new(Json(json))))
+            }
+        }
+    }
+}
+
+/// Decompress `value` if it is a `Value::Compressed`, or return it
+/// unchanged otherwise.
+pub fn decompressed(value: Value) -> Result<Value, CompressionError> {
+    match value {
+        Value::Compressed(ref compressed) => compressed.decompress(),
+        value => Ok(value)
+    }
+}
+
+/// When to compress values.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompressionPolicy {
+    pub algorithm: Algorithm,
+
+    /// The size of data, in bytes, from which values are compressed.
+    pub threshold: u64,
+}
+
+impl Default for CompressionPolicy {
+    /// Gzip, for values of 4kb or more.
+    fn default() -> Self {
+        CompressionPolicy {
+            algorithm: Algorithm::Gzip,
+            threshold: 4096,
+        }
+    }
+}
+
+impl CompressionPolicy {
+    /// Compress `value` if it is a `Value::Binary` or a `Value::Json`
+    /// whose data reaches the threshold. Values that compression does
+    /// not make smaller, e.g. images that are already compressed, are
+    /// returned unchanged.
+    pub fn apply(&self, value: Value) -> Value {
+        let size = match value {
+            Value::Binary { ref data, .. } => data.len() as u64,
+            // Serializing a JSON value cannot fail.
+            Value::Json(ref json) => serde_json::to_string(&json.0).unwrap().len() as u64,
+            _ => return value
+        };
+        if size < self.threshold {
+            return value;
+        }
+        match Compressed::compress(&value, self.algorithm) {
+            Some(ref compressed) if (compressed.data.len() as u64) < size => Value::Compressed(Arc::new(compressed.clone())),
+            _ => value
+        }
+    }
+}
+
+///
+/// # DEFLATE
+///
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+/// The number of candidates examined when searching for a match.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: usize = 15;
+const NONE: usize = ::std::usize::MAX;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+                                35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+                                3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+                                  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+                                  8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+                                  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// The order in which the lengths of the code length alphabet are
+/// stored in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Writing bit fields, least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: vec![],
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, len: u32) {
+        self.bits |= value << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is stored most significant bit
+    /// first.
+    fn write_code(&mut self, code: u32, len: u32) {
+        let mut reversed = 0;
+        for i in 0..len {
+            reversed |= ((code >> i) & 1) << (len - 1 - i);
+        }
+        self.write(reversed, len);
+    }
+
+    /// Write a symbol of the fixed literal/length alphabet.
+    fn write_symbol(&mut self, symbol: u32) {
+        match symbol {
+            0...143 => self.write_code(0x30 + symbol, 8),
+            144...255 => self.write_code(0x190 + symbol - 144, 9),
+            256...279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+/// The longest match of the data at `pos` with the data preceding it,
+/// as `(length, distance)`.
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max = ::std::cmp::min(MAX_MATCH, data.len() - pos);
+    let (mut best, mut distance) = (0, 0);
+    let mut candidate = head[hash(data, pos)];
+    let mut chain = 0;
+    while candidate != NONE && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+        let mut length = 0;
+        while length < max && data[candidate + length] == data[pos + length] {
+            length += 1;
+        }
+        if length > best {
+            best = length;
+            distance = pos - candidate;
+            if best == max {
+                break;
+            }
+        }
+        candidate = prev[candidate];
+        chain += 1;
+    }
+    (best, distance)
+}
+
+/// Record position `pos` in the hash chains.
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let hash = hash(data, pos);
+        prev[pos] = head[hash];
+        head[hash] = pos;
+    }
+}
+
+/// Compress `data` as a single DEFLATE block with fixed Huffman codes.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // BFINAL, then BTYPE 01, i.e. fixed Huffman codes.
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut head = vec![NONE; 1 << HASH_BITS];
+    let mut prev = vec![NONE; data.len()];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+        if length < MIN_MATCH {
+            writer.write_symbol(data[pos] as u32);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+            continue;
+        }
+        let code = (0..LENGTH_BASE.len()).rev()
+            .find(|&code| LENGTH_BASE[code] as usize <= length)
+            .unwrap();
+        writer.write_symbol(257 + code as u32);
+        writer.write((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+        let code = (0..DISTANCE_BASE.len()).rev()
+            .find(|&code| DISTANCE_BASE[code] as usize <= distance)
+            .unwrap();
+        writer.write_code(code as u32, 5);
+        writer.write((distance - DISTANCE_BASE[code] as usize) as u32, DISTANCE_EXTRA[code] as u32);
+        for pos in pos..pos + length {
+            insert(data, pos, &mut head, &mut prev);
+        }
+        pos += length;
+    }
+    writer.write_symbol(256);
+    writer.finish()
+}
+
+fn corrupt(description: &str) -> CompressionError {
+    CompressionError::Corrupt(description.to_owned())
+}
+
+/// Reading bit fields, least significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, len: u32) -> Result<u32, CompressionError> {
+        let mut value = 0;
+        for i in 0..len {
+            if self.pos >= self.data.len() {
+                return Err(corrupt("Unexpected end of data"));
+            }
+            value |= ((self.data[self.pos] >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary.
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and
+/// the symbols ordered by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// The code in which symbol `i` has length `lengths[i]`, 0 for
+    /// symbols that do not appear.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[15] as usize + counts[15] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman {
+            counts: counts,
+            symbols: symbols,
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, CompressionError> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= try!(reader.bits(1)) as usize;
+            let count = self.counts[length] as usize;
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("Invalid Huffman code"))
+    }
+}
+
+/// The fixed codes of DEFLATE, for literals/lengths and distances.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// The codes of a block with dynamic Huffman codes, as stored at the
+/// start of the block.
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), CompressionError> {
+    let literals = try!(reader.bits(5)) as usize + 257;
+    let distances = try!(reader.bits(5)) as usize + 1;
+    let code_lengths = try!(reader.bits(4)) as usize + 4;
+    if literals > 286 || distances > 30 {
+        return Err(corrupt("Too many codes"));
+    }
+    let mut lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[symbol] = try!(reader.bits(3)) as u8;
+    }
+    let code_length_code = Huffman::new(&lengths);
+
+    let mut lengths = vec![];
+    while lengths.len() < literals + distances {
+        let (length, repeat) = match try!(code_length_code.decode(reader)) {
+            symbol @ 0...15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                None => return Err(corrupt("Repeating a missing length")),
+                Some(&previous) => (previous, 3 + try!(reader.bits(2)) as usize)
+            },
+            17 => (0, 3 + try!(reader.bits(3)) as usize),
+            _ => (0, 11 + try!(reader.bits(7)) as usize),
+        };
+        if lengths.len() + repeat > literals + distances {
+            return Err(corrupt("Too many lengths"));
+        }
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    if lengths[256] == 0 {
+        return Err(corrupt("Missing end of block"));
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+/// Decode the data of a block compressed with `literals` and
+/// `distances`, appending it to `output`.
+fn inflate_block(reader: &mut BitReader, literals: &Huffman, distances: &Huffman, output: &mut Vec<u8>, limit: u64)
+    -> Result<(), CompressionError> {
+    loop {
+        let symbol = try!(literals.decode(reader)) as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let code = symbol - 257;
+            if code >= LENGTH_BASE.len() {
+                return Err(corrupt("Invalid length code"));
+            }
+            let length = LENGTH_BASE[code] as usize + try!(reader.bits(LENGTH_EXTRA[code] as u32)) as usize;
+            let code = try!(distances.decode(reader)) as usize;
+            if code >= DISTANCE_BASE.len() {
+                return Err(corrupt("Invalid distance code"));
+            }
+            let distance = DISTANCE_BASE[code] as usize + try!(reader.bits(DISTANCE_EXTRA[code] as u32)) as usize;
+            if distance > output.len() {
+                return Err(corrupt("Distance beyond the start of the data"));
+            }
+            for _ in 0..length {
+                let byte = output[output.len() - distance];
+                output.push(byte);
+            }
+        }
+        if output.len() as u64 > limit {
+            return Err(CompressionError::SizeMismatch {
+                expected: limit,
+                actual: output.len() as u64,
+            });
+        }
+    }
+}
+
+/// Decompress a DEFLATE stream, which may not expand to more than
+/// `limit` bytes. Returns the data, along with the number of bytes of
+/// the stream.
+pub fn inflate(data: &[u8], limit: u64) -> Result<(Vec<u8>, usize), CompressionError> {
+    let mut reader = BitReader {
+        data: data,
+        pos: 0,
+        bit: 0,
+    };
+    let mut output = vec![];
+    loop {
+        let last = try!(reader.bits(1)) == 1;
+        match try!(reader.bits(2)) {
+            0 => {
+                reader.align();
+                let header = try!(reader.bits(32));
+                let (len, nlen) = (header & 0xFFFF, header >> 16);
+                if len != !nlen & 0xFFFF {
+                    return Err(corrupt("Invalid length of stored block"));
+                }
+                let end = reader.pos + len as usize;
+                if end > data.len() {
+                    return Err(corrupt("Unexpected end of data"));
+                }
+                if (output.len() + len as usize) as u64 > limit {
+                    return Err(CompressionError::SizeMismatch {
+                        expected: limit,
+                        actual: (output.len() + len as usize) as u64,
+                    });
+                }
+                output.extend_from_slice(&data[reader.pos..end]);
+                reader.pos = end;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                try!(inflate_block(&mut reader, &literals, &distances, &mut output, limit));
+            }
+            2 => {
+                let (literals, distances) = try!(dynamic_codes(&mut reader));
+                try!(inflate_block(&mut reader, &literals, &distances, &mut output, limit));
+            }
+            _ => return Err(corrupt("Invalid block type"))
+        }
+        if last {
+            reader.align();
+            return Ok((output, reader.pos));
+        }
+    }
+}
+
+///
+/// # gzip
+///
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const GZIP_DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 2;
+const FLAG_EXTRA: u8 = 4;
+const FLAG_NAME: u8 = 8;
+const FLAG_COMMENT: u8 = 16;
+
+/// The CRC-32 of `data`, as used by gzip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn write_u32(output: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        output.push((value >> (8 * i)) as u8);
+    }
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (0..4).fold(0, |value, i| value | (data[i] as u32) << (8 * i))
+}
+
+/// Compress `data` as a single gzip member, without name or date.
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    // Magic, method, flags, date, extra flags, then OS "unknown".
+    let mut output = vec![GZIP_MAGIC[0], GZIP_MAGIC[1], GZIP_DEFLATE, 0, 0, 0, 0, 0, 0, 255];
+    output.extend_from_slice(&deflate(data));
+    write_u32(&mut output, crc32(data));
+    write_u32(&mut output, data.len() as u32);
+    output
+}
+
+/// Decompress a gzip member, which may not expand to more than `limit`
+/// bytes.
+pub fn gunzip(data: &[u8], limit: u64) -> Result<Vec<u8>, CompressionError> {
+    if data.len() < 10 || data[0..2] != GZIP_MAGIC || data[2] != GZIP_DEFLATE {
+        return Err(corrupt("Invalid gzip header"));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FLAG_EXTRA != 0 {
+        if data.len() < pos + 2 {
+            return Err(corrupt("Invalid gzip header"));
+        }
+        pos += 2 + (data[pos] as usize | (data[pos + 1] as usize) << 8);
+    }
+    for &flag in &[FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            match data.get(pos..).and_then(|rest| rest.iter().position(|&byte| byte == 0)) {
+                None => return Err(corrupt("Invalid gzip header")),
+                Some(end) => pos += end + 1
+            }
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err(corrupt("Invalid gzip header"));
+    }
+    let (output, len) = try!(inflate(&data[pos..], limit));
+    let trailer = &data[pos + len..];
+    if trailer.len() < 8 {
+        return Err(corrupt("Missing gzip trailer"));
+    }
+    if read_u32(trailer) != crc32(&output) || read_u32(&trailer[4..]) != output.len() as u32 {
+        return Err(CompressionError::ChecksumMismatch);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ crc32, deflate, gunzip, gzip, inflate, Algorithm, Compressed, CompressionError, MAX_DECOMPRESSED_SIZE };
+    use values::{ Bytes, Value };
+
+    use std::sync::Arc;
+    use std::u64;
+
+    const TEXT: &'static [u8] = b"A temperature. Internal representation may be either Fahrenheit or Celcius. \
+The FoxBox adapters are expected to perform conversions to the format requested by their devices.";
+
+    fn hex(source: &str) -> Vec<u8> {
+        (0..source.len() / 2).map(|i| u8::from_str_radix(&source[2 * i..2 * i + 2], 16).unwrap()).collect()
+    }
+
+    /// Data that does not compress well, longer than the window.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u32 = 42;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }).collect()
+    }
+
+    fn binary(data: Vec<u8>) -> Value {
+        Value::Binary {
+            data: Bytes(Arc::new(data)),
+            mimetype: "application/octet-stream".to_owned()
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn test_known_streams() {
+        // Produced by zlib: a gzip member, a stored block, then a block
+        // with dynamic Huffman codes.
+        let gzipped = hex("1f8b0800000000000203cb48cdc9c95728cf2fca49e102002d3b08af0c000000");
+        assert_eq!(gunzip(&gzipped, 100).unwrap(), b"hello world\n".to_vec());
+
+        let stored = hex("010c00f3ff68656c6c6f20776f726c640a");
+        assert_eq!(inflate(&stored, 100).unwrap(), (b"hello world\n".to_vec(), stored.len()));
+
+        let dynamic = hex("158dd10d83300c4457b909d8a1ad84d4ff2e60c255890471ea1804dbd7f9bc77f6bb079c7ba3891fc\
+                           609efeab42a1b8ccdd8595dbc68c52e371682c5330db36463cd91a08617b7548e3ee1938959afa75e905\
+                           55a983ac4e2eb6a4cce15ae88a9afda8ea4f58c3edc7de0d06270f158fe1dece37cb9072f86956749ecd31f");
+        assert_eq!(inflate(&dynamic, 1000).unwrap(), (TEXT.to_vec(), dynamic.len()));
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let mut repeated = vec![];
+        for _ in 0..100 {
+            repeated.extend_from_slice(TEXT);
+        }
+        let samples = vec![vec![], b"a".to_vec(), TEXT.to_vec(), repeated, vec![0; 100_000], noise(70_000)];
+        for data in samples {
+            let len = data.len() as u64;
+            assert_eq!(inflate(&deflate(&data), len).unwrap().0, data);
+            assert_eq!(gunzip(&gzip(&data), len).unwrap(), data);
+            for &algorithm in &[Algorithm::Deflate, Algorithm::Gzip] {
+                let value = binary(data.clone());
+                let compressed = Compressed::compress(&value, algorithm).unwrap();
+                assert_eq!(compressed.original_size, len);
+                assert_eq!(compressed.decompress().unwrap(), value);
+            }
+        }
+        assert!(deflate(&vec![0; 100_000]).len() < 1000);
+    }
+
+    #[test]
+    fn test_truncated_and_corrupt_streams() {
+        let gzipped = gzip(TEXT);
+        for len in 0..gzipped.len() {
+            assert!(gunzip(&gzipped[..len], 1000).is_err());
+        }
+
+        let mut corrupt = gzipped.clone();
+        let len = corrupt.len();
+        corrupt[len - 8] ^= 1;
+        assert_eq!(gunzip(&corrupt, 1000), Err(CompressionError::ChecksumMismatch));
+
+        let mut corrupt = gzipped.clone();
+        corrupt[0] = 0;
+        match gunzip(&corrupt, 1000) {
+            Err(CompressionError::Corrupt(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        // A final block of type 3, which does not exist.
+        match inflate(&[0x07], 1000) {
+            Err(CompressionError::Corrupt(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        // A stored block whose length does not match its complement.
+        match inflate(&hex("010c00f3fe68656c6c6f20776f726c640a"), 1000) {
+            Err(CompressionError::Corrupt(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let compressed = Compressed::compress(&binary(TEXT.to_vec()), Algorithm::Gzip).unwrap();
+        let len = TEXT.len() as u64;
+
+        let larger = Compressed { original_size: len + 1, ..compressed.clone() };
+        assert_eq!(larger.decompress(), Err(CompressionError::SizeMismatch {
+            expected: len + 1,
+            actual: len
+        }));
+
+        let smaller = Compressed { original_size: len - 1, ..compressed.clone() };
+        match smaller.decompress() {
+            Err(CompressionError::SizeMismatch { expected, .. }) => assert_eq!(expected, len - 1),
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        // A stream expanding far beyond its limit is interrupted.
+        let bomb = deflate(&vec![0; 1 << 20]);
+        match inflate(&bomb, 1000) {
+            Err(CompressionError::SizeMismatch { expected: 1000, actual }) => assert!(actual < 2000),
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        // Sizes announced by clients are capped.
+        for &size in &[MAX_DECOMPRESSED_SIZE + 1, u64::MAX] {
+            let compressed = Compressed {
+                algorithm: Algorithm::Deflate,
+                original_size: size,
+                mimetype: Some("application/octet-stream".to_owned()),
+                data: Bytes(Arc::new(bomb.clone())),
+            };
+            assert_eq!(compressed.decompress(), Err(CompressionError::TooLarge(size)));
+        }
+    }
+}
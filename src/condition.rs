@@ -0,0 +1,254 @@
+//!
+//! Watching getters enter and leave ranges of values.
+//!
+//! Rules engines such as ThinkerBell trigger actions when a value
+//! enters a range, leaves it, or has remained within it for some time,
+//! e.g. "the door has been open for 5 minutes". A `ConditionWatcher`
+//! implements this state machine on top of `API::register_channel_watch`,
+//! so that rules engines do not need to rebuild it from raw
+//! `WatchEvent::Value` events.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let condition = Condition {
+//!     source: GetterSelector::new().with_kind(ChannelKind::OpenClosed),
+//!     range: Range::Eq(Value::Bool(true)),
+//!     duration: ValDuration::new(Duration::from_secs(300)),
+//! };
+//! let watcher = ConditionWatcher::register(&api, &caller, condition, Box::new(|event| {
+//!     if let ConditionEvent::Sustained { getter, .. } = event {
+//!         println!("{:?} has been open for 5 minutes", getter);
+//!     }
+//! })).unwrap();
+//! ```
+//!
+
+use api::{ Error, WatchEvent, WatchGuard, WatchOptions, API };
+use auth::Caller;
+use devices::Getter;
+use selector::GetterSelector;
+use values::{ Range, ValDuration, Value };
+use util::Id;
+
+use std::collections::HashMap;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread;
+use std::time::Instant;
+
+/// A condition on the values of a set of getters.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Condition {
+    /// The getters to watch. Each getter is considered independently.
+    pub source: GetterSelector,
+
+    /// The range in which values satisfy the condition.
+    pub range: Range,
+
+    /// How long a getter must remain within `range` before
+    /// `ConditionEvent::Sustained` is delivered.
+    pub duration: ValDuration,
+}
+
+/// A change in the state of a condition, for a single getter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ConditionEvent {
+    /// The getter has produced a value within the range, while its
+    /// previous value, if any, was out of the range.
+    Enter {
+        getter: Id<Getter>,
+        value: Value,
+    },
+
+    /// The getter has produced a value out of the range, while its
+    /// previous value was within the range.
+    Exit {
+        getter: Id<Getter>,
+        value: Value,
+    },
+
+    /// The getter has remained within the range for
+    /// `Condition::duration` since it entered. This is delivered at
+    /// most once per `Enter`. Payload `value` is the latest value of
+    /// the getter.
+    Sustained {
+        getter: Id<Getter>,
+        value: Value,
+    },
+}
+
+/// A getter whose latest value is within the range.
+struct Inside {
+    since: Instant,
+    latest: Value,
+    sustained: bool,
+}
+
+struct Machine {
+    range: Range,
+    inside: HashMap<Id<Getter>, Inside>,
+    stopping: bool,
+}
+
+type Callback = Box<Fn(ConditionEvent) + Send + 'static>;
+
+/// The state shared by the watch and the thread delivering
+/// `ConditionEvent::Sustained`.
+///
+/// Events are produced while holding the lock on the machine, then
+/// delivered while holding the lock on the callback only, so that they
+/// are delivered in order and that the callback may drop the
+/// `ConditionWatcher`.
+struct Shared {
+    machine: Mutex<Machine>,
+    cvar: Condvar,
+    cb: Mutex<Callback>,
+}
+
+impl Shared {
+    fn on_event(&self, event: WatchEvent) {
+        match event {
+            WatchEvent::Value { from, value } => self.on_value(from, value),
+            WatchEvent::GetterRemoved(id) => {
+                self.machine.lock().unwrap().inside.remove(&id);
+            }
+            WatchEvent::Batch(events) => {
+                for event in events {
+                    self.on_event(event)
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn on_value(&self, getter: Id<Getter>, value: Value) {
+        let mut machine = self.machine.lock().unwrap();
+        let within = machine.range.contains(&value);
+        let event = match (machine.inside.contains_key(&getter), within) {
+            (false, true) => {
+                machine.inside.insert(getter.clone(), Inside {
+                    since: Instant::now(),
+                    latest: value.clone(),
+                    sustained: false,
+                });
+                self.cvar.notify_all();
+                ConditionEvent::Enter {
+                    getter: getter,
+                    value: value,
+                }
+            }
+            (true, true) => {
+                if let Some(inside) = machine.inside.get_mut(&getter) {
+                    inside.latest = value;
+                }
+                return;
+            }
+            (true, false) => {
+                machine.inside.remove(&getter);
+                ConditionEvent::Exit {
+                    getter: getter,
+                    value: value,
+                }
+            }
+            (false, false) => return
+        };
+        let cb = self.cb.lock().unwrap();
+        drop(machine);
+        cb(event);
+    }
+
+    /// Deliver `ConditionEvent::Sustained` as getters reach
+    /// `duration`, until the watcher is dropped.
+    fn run(&self, duration: ValDuration) {
+        let duration = duration.as_duration();
+        let mut machine = self.machine.lock().unwrap();
+        loop {
+            if machine.stopping {
+                return;
+            }
+            let now = Instant::now();
+            let mut events = vec![];
+            let mut next = None;
+            for (getter, inside) in machine.inside.iter_mut().filter(|&(_, ref inside)| !inside.sustained) {
+                let due = inside.since + duration;
+                if due <= now {
+                    inside.sustained = true;
+                    events.push(ConditionEvent::Sustained {
+                        getter: getter.clone(),
+                        value: inside.latest.clone(),
+                    });
+                } else if next.map_or(true, |next| due < next) {
+                    next = Some(due);
+                }
+            }
+            if !events.is_empty() {
+                {
+                    let cb = self.cb.lock().unwrap();
+                    drop(machine);
+                    for event in events {
+                        cb(event);
+                    }
+                }
+                machine = self.machine.lock().unwrap();
+                continue;
+            }
+            machine = match next {
+                Some(due) => self.cvar.wait_timeout(machine, due - now).unwrap().0,
+                None => self.cvar.wait(machine).unwrap()
+            };
+        }
+    }
+}
+
+/// A condition being watched. Dropping the watcher stops watching.
+pub struct ConditionWatcher {
+    shared: Arc<Shared>,
+    guard: Box<WatchGuard>,
+}
+
+impl ConditionWatcher {
+    /// Start watching `condition` through `api`, on behalf of `caller`.
+    pub fn register<A>(api: &A, caller: &Caller, condition: Condition, cb: Box<Fn(ConditionEvent) + Send + 'static>) -> Result<Self, Error>
+        where A: API, A::WatchGuard: 'static
+    {
+        let shared = Arc::new(Shared {
+            machine: Mutex::new(Machine {
+                range: condition.range,
+                inside: HashMap::new(),
+                stopping: false,
+            }),
+            cvar: Condvar::new(),
+            cb: Mutex::new(cb),
+        });
+        let options = vec![WatchOptions::new()
+            .with_getters(condition.source)
+            .with_watch_values(true)];
+        let guard = {
+            let shared = shared.clone();
+            try!(api.register_channel_watch(caller, options, Box::new(move |event| shared.on_event(event))))
+        };
+        {
+            let shared = shared.clone();
+            let duration = condition.duration;
+            thread::spawn(move || shared.run(duration));
+        }
+        Ok(ConditionWatcher {
+            shared: shared,
+            guard: Box::new(guard),
+        })
+    }
+
+    /// Stop watching. Once this method has returned, the callback will
+    /// not be called anymore, except if it is currently running.
+    pub fn unregister(&mut self) {
+        self.guard.unregister();
+        self.shared.machine.lock().unwrap().stopping = true;
+        self.shared.cvar.notify_all();
+    }
+}
+
+impl Drop for ConditionWatcher {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
@@ -0,0 +1,197 @@
+//!
+//! Delivering the events of a watch in batches.
+//!
+//! A watch registered with `WatchOptions::with_batching` delivers its
+//! events through a `Batch`, which holds them until it has accumulated
+//! `max_events` events, or until `max_delay` has passed since the first
+//! of them, as measured by a `Timer`. The events are
+//! then delivered at once, as a single `WatchEvent::Batch`.
+//!
+//! `WatchEvent::Missed` markers are batched along with the events they
+//! stand between. `WatchEvent::Expired` flushes the batch in progress,
+//! then is delivered on its own, so that it remains the last event
+//! delivered to the watch.
+//!
+
+use super::timer::{ TaskId, TimerHandle };
+use api::{ Batching, WatchEvent };
+
+use std::cmp;
+use std::mem;
+use std::sync::{ Arc, Mutex, Weak };
+use std::time::{ Duration, Instant };
+
+struct Pending {
+    /// The events of the batch in progress.
+    events: Vec<WatchEvent>,
+
+    /// The number of batches delivered so far, so that the task
+    /// flushing a batch never flushes a later one.
+    delivered: u64,
+
+    /// The task flushing the batch in progress once its first event
+    /// has waited for `max_delay`.
+    task: Option<TaskId>,
+
+    cb: Box<Fn(WatchEvent) + Send + 'static>,
+}
+
+/// The events waiting to be delivered to a watch as a batch.
+pub struct Batch {
+    /// Held while delivering, so that batches are delivered in order.
+    pending: Mutex<Pending>,
+    max_delay: Duration,
+    max_events: usize,
+    timer: TimerHandle,
+}
+
+impl Batch {
+    /// Wrap `cb` to receive events in batches. Returns the callback
+    /// accumulating events.
+    pub fn start(batching: &Batching, timer: TimerHandle, cb: Box<Fn(WatchEvent) + Send + 'static>)
+        -> Box<Fn(WatchEvent) + Send + 'static> {
+        let batch = Arc::new(Batch {
+            pending: Mutex::new(Pending {
+                events: vec![],
+                delivered: 0,
+                task: None,
+                cb: cb,
+            }),
+            max_delay: batching.max_delay.as_duration(),
+            // A batch that cannot hold any event would never be delivered.
+            max_events: cmp::max(batching.max_events, 1),
+            timer: timer,
+        });
+        Box::new(move |event| Batch::push(&batch, event))
+    }
+
+    fn push(batch: &Arc<Batch>, event: WatchEvent) {
+        let mut pending = batch.pending.lock().unwrap();
+        if let WatchEvent::Expired = event {
+            batch.flush(&mut pending);
+            (pending.cb)(event);
+            return;
+        }
+        pending.events.push(event);
+        if pending.events.len() >= batch.max_events {
+            batch.flush(&mut pending);
+        } else if pending.task.is_none() {
+            let weak: Weak<Batch> = Arc::downgrade(batch);
+            let current = pending.delivered;
+            pending.task = Some(batch.timer.schedule(Instant::now() + batch.max_delay, Box::new(move || {
+                if let Some(batch) = weak.upgrade() {
+                    let mut pending = batch.pending.lock().unwrap();
+                    if pending.delivered == current {
+                        pending.task = None;
+                        batch.flush(&mut pending);
+                    }
+                }
+            })));
+        }
+    }
+
+    /// Deliver the batch in progress, if it holds any event.
+    fn flush(&self, pending: &mut Pending) {
+        if let Some(task) = pending.task.take() {
+            self.timer.cancel(task);
+        }
+        if pending.events.is_empty() {
+            return;
+        }
+        pending.delivered += 1;
+        let events = mem::replace(&mut pending.events, vec![]);
+        (pending.cb)(WatchEvent::Batch(events));
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if let Some(task) = self.pending.lock().unwrap().task.take() {
+            self.timer.cancel(task);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batch;
+    use super::super::timer::Timer;
+    use api::{ Batching, WatchEvent };
+    use util::Id;
+    use values::ValDuration;
+
+    use std::sync::mpsc::{ channel, Receiver };
+    use std::time::{ Duration, Instant };
+
+    fn event(i: u32) -> WatchEvent {
+        WatchEvent::GetterAdded(Id::new(format!("getter {}", i)))
+    }
+
+    fn describe(event: &WatchEvent) -> String {
+        match *event {
+            WatchEvent::GetterAdded(ref id) => id.to_string(),
+            WatchEvent::Missed(missed) => format!("missed {}", missed),
+            WatchEvent::Expired => "expired".to_owned(),
+            WatchEvent::Batch(ref events) => {
+                let events: Vec<_> = events.iter().map(describe).collect();
+                format!("[{}]", events.join(", "))
+            }
+            ref other => panic!("Unexpected event {:?}", other)
+        }
+    }
+
+    /// Start a batch reporting the events it delivers.
+    fn start(timer: &Timer, max_delay: Duration, max_events: usize)
+        -> (Box<Fn(WatchEvent) + Send + 'static>, Receiver<String>) {
+        let (tx, rx) = channel();
+        let batching = Batching {
+            max_delay: ValDuration::new(max_delay),
+            max_events: max_events,
+        };
+        let batch = Batch::start(&batching, timer.handle(), Box::new(move |event| tx.send(describe(&event)).unwrap()));
+        (batch, rx)
+    }
+
+    #[test]
+    fn test_max_events() {
+        let timer = Timer::start();
+        let (batch, rx) = start(&timer, Duration::from_secs(3600), 3);
+        batch(event(1));
+        batch(WatchEvent::Missed(2));
+        assert!(rx.try_recv().is_err());
+        batch(event(4));
+        assert_eq!(rx.try_recv().unwrap(), "[getter 1, missed 2, getter 4]");
+        batch(event(5));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_max_delay() {
+        let timer = Timer::start();
+        let start_time = Instant::now();
+        let (batch, rx) = start(&timer, Duration::from_millis(20), 10);
+        batch(event(1));
+        batch(event(2));
+        assert_eq!(rx.recv().unwrap(), "[getter 1, getter 2]");
+        assert!(Instant::now() >= start_time + Duration::from_millis(20));
+
+        // The delay starts again with the next batch.
+        batch(event(3));
+        assert_eq!(rx.recv().unwrap(), "[getter 3]");
+    }
+
+    #[test]
+    fn test_expired() {
+        let timer = Timer::start();
+        let (batch, rx) = start(&timer, Duration::from_secs(3600), 10);
+        batch(event(1));
+        batch(event(2));
+
+        // The batch in progress is flushed, then `Expired` is delivered
+        // on its own.
+        batch(WatchEvent::Expired);
+        assert_eq!(rx.try_recv().unwrap(), "[getter 1, getter 2]");
+        assert_eq!(rx.try_recv().unwrap(), "expired");
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,386 @@
+//!
+//! Bounding the events waiting for delivery to a watch.
+//!
+//! Events are normally delivered to the callback of a watch by the
+//! thread that has produced them, once the state of the implementation
+//! of the API has been unlocked. A watch registered with `WatchOptions::with_backpressure`
+//! instead receives its events from a thread of its own, through a
+//! `Buffer` holding at most `capacity` events, so that a slow callback
+//! neither delays the producers nor causes unbounded memory growth.
+//!
+//! Once the buffer is full, events are dropped or producers are blocked,
+//! as specified by the `OverflowPolicy`. Each run of dropped events is
+//! replaced in the buffer by a single `WatchEvent::Missed` marker,
+//! delivered where the events would have been, so that the callback
+//! knows how many events it has missed and may resynchronize, e.g. by
+//! fetching the values of the channels it watches. Markers do not count
+//! towards the capacity.
+//!
+//! `WatchEvent::Expired` is never dropped, as it must remain the last
+//! event delivered to the watch: it does not count towards the capacity
+//! either, never blocks its producer, and is delivered even if the
+//! buffer is closed before its turn has come. Events pushed after it are
+//! ignored.
+//!
+//! With `OverflowPolicy::BlockProducer`, a callback that causes events
+//! for its own watch, e.g. by sending a value to a channel that it
+//! watches, may block forever once the buffer is full.
+//!
+
+use api::{ Backpressure, OverflowPolicy, WatchEvent };
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread;
+
+struct Queue {
+    /// The events waiting for delivery, interleaved with markers.
+    events: VecDeque<WatchEvent>,
+
+    /// The number of events in `events`, markers excluded.
+    len: usize,
+
+    /// The number of events dropped so far.
+    dropped: u64,
+
+    /// `true` once the watch has been unregistered.
+    closed: bool,
+
+    /// `true` once `WatchEvent::Expired` has been pushed.
+    expired: bool,
+}
+
+impl Queue {
+    /// Drop the oldest event, which is preceded by a marker, if any.
+    fn drop_oldest(&mut self) {
+        let missed = match self.events.front() {
+            Some(&WatchEvent::Missed(missed)) => {
+                self.events.pop_front();
+                missed
+            }
+            _ => 0
+        };
+        self.events.pop_front();
+        self.events.push_front(WatchEvent::Missed(missed + 1));
+        self.len -= 1;
+    }
+
+    /// Record that a new event has been dropped.
+    fn drop_newest(&mut self) {
+        if let Some(&mut WatchEvent::Missed(ref mut missed)) = self.events.back_mut() {
+            *missed += 1;
+            return;
+        }
+        self.events.push_back(WatchEvent::Missed(1));
+    }
+}
+
+/// The events waiting for delivery to a watch.
+pub struct Buffer {
+    queue: Mutex<Queue>,
+    capacity: usize,
+    policy: OverflowPolicy,
+
+    /// Notified whenever an event is pushed or the buffer is closed.
+    pushed: Condvar,
+
+    /// Notified whenever an event is delivered or the buffer is closed.
+    popped: Condvar,
+}
+
+impl Buffer {
+    /// Start a thread delivering events to `cb`. Returns the buffer,
+    /// along with the callback pushing events to the buffer, which
+    /// closes the buffer once it is dropped.
+    pub fn start(backpressure: &Backpressure, cb: Box<Fn(WatchEvent) + Send + 'static>)
+        -> (Arc<Buffer>, Box<Fn(WatchEvent) + Send + 'static>) {
+        let buffer = Arc::new(Buffer {
+            queue: Mutex::new(Queue {
+                events: VecDeque::new(),
+                len: 0,
+                dropped: 0,
+                closed: false,
+                expired: false,
+            }),
+            // A buffer that cannot hold any event would drop everything.
+            capacity: cmp::max(backpressure.capacity, 1),
+            policy: backpressure.policy.clone(),
+            pushed: Condvar::new(),
+            popped: Condvar::new(),
+        });
+        {
+            let buffer = buffer.clone();
+            thread::spawn(move || buffer.run(cb));
+        }
+        let producer = Producer(buffer.clone());
+        (buffer, Box::new(move |event| producer.0.push(event)))
+    }
+
+    /// The number of events dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().unwrap().dropped
+    }
+
+    fn push(&self, event: WatchEvent) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.expired {
+            return;
+        }
+        if let WatchEvent::Expired = event {
+            // Takes a slot of its own, beyond the capacity.
+            queue.expired = true;
+            queue.events.push_back(event);
+            self.pushed.notify_one();
+            // Producers waiting for room would only push events to ignore.
+            self.popped.notify_all();
+            return;
+        }
+        if self.policy == OverflowPolicy::BlockProducer {
+            while queue.len >= self.capacity && !queue.closed && !queue.expired {
+                queue = self.popped.wait(queue).unwrap();
+            }
+        }
+        if queue.closed || queue.expired {
+            return;
+        }
+        if queue.len >= self.capacity {
+            queue.dropped += 1;
+            if self.policy == OverflowPolicy::DropNewest {
+                queue.drop_newest();
+                return;
+            }
+            queue.drop_oldest();
+        }
+        queue.events.push_back(event);
+        queue.len += 1;
+        self.pushed.notify_one();
+    }
+
+    fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.pushed.notify_all();
+        self.popped.notify_all();
+    }
+
+    /// Deliver events until the buffer is closed or `WatchEvent::Expired`
+    /// has been delivered. Events still waiting once the buffer is closed
+    /// are discarded, as the watch has been unregistered, except for
+    /// `WatchEvent::Expired`.
+    fn run(&self, cb: Box<Fn(WatchEvent) + Send + 'static>) {
+        loop {
+            let event = {
+                let mut queue = self.queue.lock().unwrap();
+                while queue.events.is_empty() && !queue.closed {
+                    queue = self.pushed.wait(queue).unwrap();
+                }
+                if queue.closed {
+                    // `WatchEvent::Expired` has not been delivered yet,
+                    // as this thread stops once it has.
+                    if !queue.expired {
+                        return;
+                    }
+                    queue.events.clear();
+                    WatchEvent::Expired
+                } else {
+                    let event = queue.events.pop_front().unwrap();
+                    match event {
+                        WatchEvent::Missed(_) | WatchEvent::Expired => {}
+                        _ => {
+                            queue.len -= 1;
+                            self.popped.notify_all();
+                        }
+                    }
+                    event
+                }
+            };
+            let last = match event {
+                WatchEvent::Expired => true,
+                _ => false
+            };
+            cb(event);
+            if last {
+                return;
+            }
+        }
+    }
+}
+
+/// The producing end of a buffer.
+struct Producer(Arc<Buffer>);
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Buffer;
+    use api::{ Backpressure, OverflowPolicy, WatchEvent };
+    use util::Id;
+
+    use std::sync::{ Arc, Mutex };
+    use std::sync::mpsc::{ channel, Receiver };
+    use std::thread;
+    use std::time::Duration;
+
+    fn event(i: u32) -> WatchEvent {
+        WatchEvent::GetterAdded(Id::new(format!("getter {}", i)))
+    }
+
+    fn describe(event: &WatchEvent) -> String {
+        match *event {
+            WatchEvent::GetterAdded(ref id) => id.to_string(),
+            WatchEvent::Missed(missed) => format!("missed {}", missed),
+            WatchEvent::Expired => "expired".to_owned(),
+            ref other => panic!("Unexpected event {:?}", other)
+        }
+    }
+
+    /// Start a buffer whose callback reports the events it receives,
+    /// then waits until `gate` is unlocked. The buffer is closed once
+    /// the returned callback is dropped.
+    fn start(capacity: usize, policy: OverflowPolicy, gate: &Arc<Mutex<()>>)
+        -> (Arc<Buffer>, Box<Fn(WatchEvent) + Send + 'static>, Receiver<String>) {
+        let (tx, rx) = channel();
+        let gate = gate.clone();
+        let backpressure = Backpressure {
+            capacity: capacity,
+            policy: policy,
+        };
+        let (buffer, producer) = Buffer::start(&backpressure, Box::new(move |event| {
+            tx.send(describe(&event)).unwrap();
+            let _gate = gate.lock().unwrap();
+        }));
+        (buffer, producer, rx)
+    }
+
+    /// Receive `count` events.
+    fn received(rx: &Receiver<String>, count: usize) -> Vec<String> {
+        (0..count).map(|_| rx.recv().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let (buffer, producer, rx) = start(2, OverflowPolicy::DropNewest, &gate);
+        producer(event(0));
+        // The callback is now busy with the first event.
+        assert_eq!(rx.recv().unwrap(), "getter 0");
+        for i in 1..5 {
+            producer(event(i));
+        }
+        assert_eq!(buffer.dropped(), 2);
+        drop(blocked);
+        assert_eq!(received(&rx, 3), vec!["getter 1", "getter 2", "missed 2"]);
+
+        // There is room again.
+        producer(event(5));
+        assert_eq!(rx.recv().unwrap(), "getter 5");
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let (buffer, producer, rx) = start(2, OverflowPolicy::DropOldest, &gate);
+        producer(event(0));
+        assert_eq!(rx.recv().unwrap(), "getter 0");
+        for i in 1..5 {
+            producer(event(i));
+        }
+        assert_eq!(buffer.dropped(), 2);
+        drop(blocked);
+        assert_eq!(received(&rx, 3), vec!["missed 2", "getter 3", "getter 4"]);
+    }
+
+    #[test]
+    fn test_block_producer() {
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let (buffer, producer, rx) = start(2, OverflowPolicy::BlockProducer, &gate);
+        producer(event(0));
+        assert_eq!(rx.recv().unwrap(), "getter 0");
+        producer(event(1));
+        producer(event(2));
+
+        // The buffer is full, so the next producer waits for room.
+        let (tx_done, rx_done) = channel();
+        {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                buffer.push(event(3));
+                tx_done.send(()).unwrap();
+            });
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert!(rx_done.try_recv().is_err());
+
+        drop(blocked);
+        assert_eq!(received(&rx, 3), vec!["getter 1", "getter 2", "getter 3"]);
+        rx_done.recv().unwrap();
+        assert_eq!(buffer.dropped(), 0);
+    }
+
+    #[test]
+    fn test_close() {
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let (_, producer, rx) = start(2, OverflowPolicy::DropNewest, &gate);
+        producer(event(0));
+        assert_eq!(rx.recv().unwrap(), "getter 0");
+        producer(event(1));
+        producer(event(2));
+
+        // Events waiting once the buffer is closed are discarded, and
+        // the callback is dropped.
+        drop(producer);
+        drop(blocked);
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_expired_is_delivered() {
+        for policy in vec![OverflowPolicy::DropNewest, OverflowPolicy::DropOldest, OverflowPolicy::BlockProducer] {
+            let gate = Arc::new(Mutex::new(()));
+            let blocked = gate.lock().unwrap();
+            let (buffer, producer, rx) = start(2, policy.clone(), &gate);
+            producer(event(0));
+            assert_eq!(rx.recv().unwrap(), "getter 0");
+            producer(event(1));
+            producer(event(2));
+
+            // The buffer is full, yet `Expired` neither is dropped nor
+            // drops an event, nor blocks. Events that follow it are
+            // ignored.
+            producer(WatchEvent::Expired);
+            producer(event(3));
+            assert_eq!(buffer.dropped(), 0, "{:?}", policy);
+            drop(blocked);
+            assert_eq!(received(&rx, 3), vec!["getter 1", "getter 2", "expired"], "{:?}", policy);
+
+            // `Expired` is the last event delivered.
+            assert!(rx.recv().is_err(), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_expired_survives_close() {
+        let gate = Arc::new(Mutex::new(()));
+        let blocked = gate.lock().unwrap();
+        let (_, producer, rx) = start(2, OverflowPolicy::DropOldest, &gate);
+        producer(event(0));
+        assert_eq!(rx.recv().unwrap(), "getter 0");
+        producer(event(1));
+        producer(WatchEvent::Expired);
+
+        // The watch is unregistered before `Expired` has been delivered.
+        drop(producer);
+        drop(blocked);
+        assert_eq!(rx.recv().unwrap(), "expired");
+        assert!(rx.recv().is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//!
+//! Unregistering watches after a deadline or a number of events.
+//!
+//! A watch registered with `WatchOptions::with_expires_after` or
+//! `WatchOptions::with_max_events` delivers its events through an
+//! `Expiry`, which counts them and arms a deadline on a `Timer`. Once
+//! the deadline has passed or the last event of the budget has been
+//! delivered, the callback receives `WatchEvent::Expired`, after which
+//! it receives no further event, and the watch is unregistered, as if
+//! its guard had been dropped.
+//!
+//! `WatchEvent::Missed` markers do not count towards the budget.
+//!
+
+use super::timer::{ TaskId, TimerHandle };
+use api::WatchEvent;
+
+use std::sync::{ Arc, Mutex, Weak };
+use std::time::{ Duration, Instant };
+
+struct Budget {
+    /// The number of events that may still be delivered, if limited.
+    remaining: Option<u64>,
+
+    /// `true` once `WatchEvent::Expired` has been delivered.
+    expired: bool,
+
+    cb: Box<Fn(WatchEvent) + Send + 'static>,
+}
+
+/// The delivery of events to a watch that expires.
+pub struct Expiry {
+    /// Held while delivering, so that `WatchEvent::Expired` is the
+    /// last event delivered.
+    budget: Mutex<Budget>,
+
+    /// Unregister the watch.
+    unregister: Mutex<Box<Fn() + Send + 'static>>,
+
+    /// The task expiring the watch, cancelled once the watch has been
+    /// unregistered.
+    deadline: Mutex<Option<(TimerHandle, TaskId)>>,
+}
+
+impl Expiry {
+    /// Wrap `cb` to deliver at most `max_events` events, if specified.
+    /// Returns the expiry, along with the callback counting events.
+    /// `unregister` is called once the watch has expired.
+    pub fn start(max_events: Option<u64>, cb: Box<Fn(WatchEvent) + Send + 'static>, unregister: Box<Fn() + Send + 'static>)
+        -> (Arc<Expiry>, Box<Fn(WatchEvent) + Send + 'static>) {
+        let expiry = Arc::new(Expiry {
+            budget: Mutex::new(Budget {
+                remaining: max_events,
+                expired: false,
+                cb: cb,
+            }),
+            unregister: Mutex::new(unregister),
+            deadline: Mutex::new(None),
+        });
+        let counting = expiry.clone();
+        (expiry, Box::new(move |event| counting.deliver(event)))
+    }
+
+    /// Expire after `delay`, unless the watch has expired or has been
+    /// unregistered in the meantime.
+    pub fn expire_after(expiry: &Arc<Expiry>, timer: &TimerHandle, delay: Duration) {
+        let weak: Weak<Expiry> = Arc::downgrade(expiry);
+        let task = timer.schedule(Instant::now() + delay, Box::new(move || {
+            if let Some(expiry) = weak.upgrade() {
+                expiry.expire();
+            }
+        }));
+        *expiry.deadline.lock().unwrap() = Some((timer.clone(), task));
+    }
+
+    fn unregister(&self) {
+        let unregister = self.unregister.lock().unwrap();
+        (*unregister)();
+    }
+
+    fn deliver(&self, event: WatchEvent) {
+        let exhausted = {
+            let mut budget = self.budget.lock().unwrap();
+            if budget.expired || budget.remaining == Some(0) {
+                return;
+            }
+            let exhausted = match (&event, budget.remaining.as_mut()) {
+                (&WatchEvent::Missed(_), _) | (_, None) => false,
+                (_, Some(remaining)) => {
+                    *remaining -= 1;
+                    *remaining == 0
+                }
+            };
+            (budget.cb)(event);
+            if exhausted {
+                budget.expired = true;
+                (budget.cb)(WatchEvent::Expired);
+            }
+            exhausted
+        };
+        if exhausted {
+            self.unregister();
+        }
+    }
+
+    /// Deliver `WatchEvent::Expired` and unregister the watch, unless
+    /// this has already been done.
+    pub fn expire(&self) {
+        {
+            let mut budget = self.budget.lock().unwrap();
+            if budget.expired {
+                return;
+            }
+            budget.expired = true;
+            (budget.cb)(WatchEvent::Expired);
+        }
+        self.unregister();
+    }
+}
+
+impl Drop for Expiry {
+    fn drop(&mut self) {
+        if let Some((ref timer, task)) = *self.deadline.lock().unwrap() {
+            timer.cancel(task);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Expiry;
+    use super::super::timer::Timer;
+    use api::WatchEvent;
+    use util::Id;
+
+    use std::sync::Arc;
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+    use std::sync::mpsc::{ channel, Receiver };
+    use std::time::Duration;
+
+    fn event(i: u32) -> WatchEvent {
+        WatchEvent::GetterAdded(Id::new(format!("getter {}", i)))
+    }
+
+    fn describe(event: &WatchEvent) -> String {
+        match *event {
+            WatchEvent::GetterAdded(ref id) => id.to_string(),
+            WatchEvent::Missed(missed) => format!("missed {}", missed),
+            WatchEvent::Expired => "expired".to_owned(),
+            ref other => panic!("Unexpected event {:?}", other)
+        }
+    }
+
+    /// Start an expiry reporting the events it delivers, along with the
+    /// number of times the watch has been unregistered.
+    fn start(max_events: Option<u64>)
+        -> (Arc<Expiry>, Box<Fn(WatchEvent) + Send + 'static>, Receiver<String>, Arc<AtomicUsize>) {
+        let (tx, rx) = channel();
+        let unregistered = Arc::new(AtomicUsize::new(0));
+        let (expiry, counting) = {
+            let unregistered = unregistered.clone();
+            Expiry::start(max_events, Box::new(move |event| tx.send(describe(&event)).unwrap()), Box::new(move || {
+                unregistered.fetch_add(1, Ordering::SeqCst);
+            }))
+        };
+        (expiry, counting, rx, unregistered)
+    }
+
+    /// Receive `count` events.
+    fn received(rx: &Receiver<String>, count: usize) -> Vec<String> {
+        (0..count).map(|_| rx.recv().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_max_events() {
+        let (_, counting, rx, unregistered) = start(Some(2));
+        counting(WatchEvent::Missed(1));
+        counting(event(1));
+        assert_eq!(unregistered.load(Ordering::SeqCst), 0);
+        counting(event(2));
+        counting(event(3));
+
+        // Markers do not count towards the budget, and nothing is
+        // delivered once the watch has expired.
+        assert_eq!(received(&rx, 4), vec!["missed 1", "getter 1", "getter 2", "expired"]);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(unregistered.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_expire() {
+        let (expiry, counting, rx, unregistered) = start(None);
+        counting(event(1));
+        expiry.expire();
+        expiry.expire();
+        counting(event(2));
+        assert_eq!(received(&rx, 2), vec!["getter 1", "expired"]);
+        assert!(rx.try_recv().is_err());
+        assert_eq!(unregistered.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_expire_after() {
+        let timer = Timer::start();
+        let (expiry, counting, rx, unregistered) = start(Some(10));
+        Expiry::expire_after(&expiry, &timer.handle(), Duration::from_millis(20));
+        counting(event(1));
+        assert_eq!(rx.recv().unwrap(), "getter 1");
+        assert_eq!(rx.recv().unwrap(), "expired");
+        assert_eq!(unregistered.load(Ordering::SeqCst), 1);
+
+        // Nothing is delivered once the watch has expired, even within
+        // the budget.
+        for i in 2..20 {
+            counting(event(i));
+        }
+        assert!(rx.try_recv().is_err());
+        assert_eq!(unregistered.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,88 @@
+//!
+//! Building blocks shared by the implementations of the API:
+//! delivering watch events, along with the options of watches, and
+//! updating the tags of nodes and channels.
+//!
+
+use api::{WatchEvent, WatchOptions};
+use devices::{Channel, Getter};
+use values::Value;
+use util::{Id, TagId};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Bounding the events waiting for delivery to a watch.
+pub mod buffer;
+
+/// Delivering the events of a watch in batches.
+pub mod batch;
+
+/// Unregistering watches after a deadline or a number of events.
+pub mod expiry;
+
+/// Running tasks once their deadline has passed.
+pub mod timer;
+
+/// A callback registered with `API::register_channel_watch`.
+pub type Callback = Arc<Mutex<Box<Fn(WatchEvent) + Send + 'static>>>;
+
+/// Events waiting for delivery. Events are only delivered once the
+/// state has been unlocked, so that callbacks may call the API.
+pub type Deliveries = Vec<(Callback, WatchEvent)>;
+
+pub fn deliver(deliveries: Deliveries) {
+    for (cb, event) in deliveries {
+        let cb = cb.lock().unwrap();
+        (**cb)(event);
+    }
+}
+
+/// Determine whether a value produced by `channel` should be delivered
+/// to a watch registered with `options`, as far as
+/// `WatchOptions::with_min_delta` is concerned, given the latest value
+/// `delivered` for each getter, and record it as delivered if so.
+pub fn accepts(options: &[WatchOptions], delivered: &mut HashMap<Id<Getter>, Value>, channel: &Channel<Getter>, value: &Value) -> bool {
+    // Each event is an occurrence of its own, however close to the
+    // previous one.
+    if channel.mechanism.is_event() {
+        return true;
+    }
+    if options.iter().all(|options| options.min_delta.is_none()) {
+        return true;
+    }
+    let accepted = match delivered.get(&channel.id) {
+        None => true,
+        Some(previous) => options.iter()
+            .filter(|options| options.should_watch_values && options.source.matches(channel))
+            .any(|options| match options.min_delta {
+                None => true,
+                Some(ref delta) => value.differs_by(previous, delta).unwrap_or(true)
+            })
+    };
+    if accepted {
+        delivered.insert(channel.id.clone(), value.clone());
+    }
+    accepted
+}
+
+/// Add the tags that are not present yet. Return `true` if any tag
+/// was added.
+pub fn add_tags(tags: &mut Vec<TagId>, added: &[TagId]) -> bool {
+    let mut changed = false;
+    for tag in added {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Remove the tags that are present. Return `true` if any tag was
+/// removed.
+pub fn remove_tags(tags: &mut Vec<TagId>, removed: &[TagId]) -> bool {
+    let len = tags.len();
+    tags.retain(|tag| !removed.contains(tag));
+    tags.len() != len
+}
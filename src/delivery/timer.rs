@@ -0,0 +1,272 @@
+//!
+//! Running tasks once their deadline has passed.
+//!
+//! The timer runs on a thread of its own, which sleeps until the
+//! earliest deadline, or until a task is scheduled or cancelled, so
+//! that implementations of the API may arm any number of deadlines,
+//! e.g. for the expiry of watches, without dedicating a thread to each of them.
+//!
+//! Tasks run on the thread of the timer, one at a time, so they should
+//! return quickly, lest they delay the tasks that follow.
+//!
+
+use std::cmp::Ordering;
+use std::collections::{ BinaryHeap, HashMap };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread::{ self, JoinHandle };
+use std::time::Instant;
+
+type Task = Box<Fn() + Send + 'static>;
+
+/// The identifier of a task scheduled with `TimerHandle::schedule`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+/// An entry of the heap of deadlines, ordered so that the earliest
+/// deadline is at the top of the heap.
+struct Deadline {
+    when: Instant,
+    task: TaskId,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.when == other.when && self.task == other.task
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, as `BinaryHeap` is a max-heap. Tasks sharing a
+        // deadline run in the order in which they have been scheduled.
+        match other.when.cmp(&self.when) {
+            Ordering::Equal => (other.task.0).cmp(&self.task.0),
+            ordering => ordering
+        }
+    }
+}
+
+struct Tasks {
+    /// The deadlines of the tasks, including the deadlines of tasks
+    /// that have been cancelled, which are skipped once they reach the
+    /// top of the heap.
+    deadlines: BinaryHeap<Deadline>,
+
+    /// The tasks that have neither run nor been cancelled.
+    pending: HashMap<TaskId, Task>,
+
+    /// A counter used to generate task ids.
+    counter: u64,
+
+    stopping: bool,
+}
+
+struct Shared {
+    tasks: Mutex<Tasks>,
+
+    /// Notified whenever a task is scheduled or cancelled, or the timer
+    /// is stopped.
+    changed: Condvar,
+}
+
+/// A handle on the timer, used to schedule and cancel tasks. Tasks
+/// scheduled once the timer has been stopped never run.
+#[derive(Clone)]
+pub struct TimerHandle(Arc<Shared>);
+
+impl TimerHandle {
+    /// Run `task` once `when` has passed.
+    pub fn schedule(&self, when: Instant, task: Task) -> TaskId {
+        let mut tasks = self.0.tasks.lock().unwrap();
+        tasks.counter += 1;
+        let id = TaskId(tasks.counter);
+        tasks.deadlines.push(Deadline {
+            when: when,
+            task: id,
+        });
+        tasks.pending.insert(id, task);
+        self.0.changed.notify_all();
+        id
+    }
+
+    /// Cancel a task, unless it has already run.
+    pub fn cancel(&self, id: TaskId) {
+        let mut tasks = self.0.tasks.lock().unwrap();
+        let tasks = &mut *tasks;
+        if tasks.pending.remove(&id).is_none() {
+            return;
+        }
+        // Do not let the deadlines of cancelled tasks accumulate, e.g.
+        // if watches with a distant deadline are registered and
+        // unregistered repeatedly.
+        if tasks.deadlines.len() > 2 * tasks.pending.len() + 16 {
+            let pending = &tasks.pending;
+            tasks.deadlines = tasks.deadlines.drain()
+                .filter(|deadline| pending.contains_key(&deadline.task))
+                .collect();
+        }
+        self.0.changed.notify_all();
+    }
+}
+
+/// The thread running tasks. Dropping the timer stops it, discarding
+/// the tasks that have not run yet.
+pub struct Timer {
+    handle: TimerHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        let handle = TimerHandle(Arc::new(Shared {
+            tasks: Mutex::new(Tasks {
+                deadlines: BinaryHeap::new(),
+                pending: HashMap::new(),
+                counter: 0,
+                stopping: false,
+            }),
+            changed: Condvar::new(),
+        }));
+        let thread = {
+            let shared = handle.0.clone();
+            thread::spawn(move || run(&shared))
+        };
+        Timer {
+            handle: handle,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn handle(&self) -> TimerHandle {
+        self.handle.clone()
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            {
+                let mut tasks = self.handle.0.tasks.lock().unwrap();
+                tasks.stopping = true;
+                tasks.pending.clear();
+                self.handle.0.changed.notify_all();
+            }
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(shared: &Shared) {
+    // Tasks may schedule or cancel tasks, so they run without holding
+    // the lock.
+    while let Some(task) = next(shared) {
+        task();
+    }
+}
+
+/// Wait for the next task that is due. Returns `None` once the timer
+/// is stopped.
+fn next(shared: &Shared) -> Option<Task> {
+    let mut tasks = shared.tasks.lock().unwrap();
+    loop {
+        if tasks.stopping {
+            return None;
+        }
+        let earliest = tasks.deadlines.peek().map(|deadline| (deadline.when, deadline.task));
+        let (when, id) = match earliest {
+            None => {
+                tasks = shared.changed.wait(tasks).unwrap();
+                continue;
+            }
+            Some(earliest) => earliest
+        };
+        if !tasks.pending.contains_key(&id) {
+            // Cancelled.
+            tasks.deadlines.pop();
+            continue;
+        }
+        let now = Instant::now();
+        if when > now {
+            tasks = shared.changed.wait_timeout(tasks, when - now).unwrap().0;
+            continue;
+        }
+        tasks.deadlines.pop();
+        return tasks.pending.remove(&id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timer;
+
+    use std::sync::mpsc::channel;
+    use std::time::{ Duration, Instant };
+
+    #[test]
+    fn test_deadline_order() {
+        let timer = Timer::start();
+        let handle = timer.handle();
+        let (tx, rx) = channel();
+        let now = Instant::now();
+        for &(name, delay) in &[("late", 40), ("first", 20), ("second", 20), ("early", 10)] {
+            let tx = tx.clone();
+            handle.schedule(now + Duration::from_millis(delay), Box::new(move || tx.send(name).unwrap()));
+        }
+        let order: Vec<_> = (0..4).map(|_| rx.recv().unwrap()).collect();
+        assert_eq!(order, vec!["early", "first", "second", "late"]);
+        assert!(Instant::now() >= now + Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let timer = Timer::start();
+        let handle = timer.handle();
+        let (tx, rx) = channel();
+        let now = Instant::now();
+        let cancelled = {
+            let tx = tx.clone();
+            handle.schedule(now + Duration::from_millis(10), Box::new(move || tx.send("cancelled").unwrap()))
+        };
+        let ran = {
+            let tx = tx.clone();
+            handle.schedule(now + Duration::from_millis(20), Box::new(move || tx.send("ran").unwrap()))
+        };
+        handle.cancel(cancelled);
+        assert_eq!(rx.recv().unwrap(), "ran");
+
+        // Cancelling a task that has run has no effect.
+        handle.cancel(ran);
+
+        // Cancelled tasks do not accumulate.
+        let distant = now + Duration::from_secs(3600);
+        for _ in 0..100 {
+            let task = handle.schedule(distant, Box::new(|| panic!("Cancelled task ran")));
+            handle.cancel(task);
+        }
+        assert!(handle.0.tasks.lock().unwrap().deadlines.len() <= 16);
+
+        // Only the sender of the test remains.
+        drop(tx);
+        drop(timer);
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn test_stop() {
+        let timer = Timer::start();
+        let (tx, rx) = channel();
+        timer.handle().schedule(Instant::now() + Duration::from_secs(3600), Box::new(move || tx.send(()).unwrap()));
+
+        // Dropping the timer discards the task, along with its sender.
+        drop(timer);
+        assert!(rx.recv().is_err());
+    }
+}
@@ -0,0 +1,453 @@
+//!
+//! Virtual getters, whose values are derived from other getters.
+//!
+//! A `DerivedAdapter` is an adapter whose getters do not talk to any
+//! device. Each of them watches a set of source getters through the
+//! API and computes its value from their latest values with a
+//! `Transform`, e.g. to convert units, to invert a boolean reported
+//! upside down by a vendor or to turn a temperature into a boolean with
+//! some hysteresis. Derived getters are registered like any other
+//! channel, so applications can fetch, watch and tag them without
+//! knowing that they are virtual, and quirky vendor data can be
+//! normalized without touching the adapter.
+//!
+//! The sources of a derived getter are either a fixed list of getters,
+//! or all the getters matching some selectors, including getters added
+//! later. The latter is how `AdapterManager` implements the aggregate
+//! getters of `API::put_aggregate_getter`, see `DerivedGetter::aggregate`.
+//! As such a getter may match its own selectors, its sources are
+//! watched with `WatchOptions::with_backpressure`: it then receives
+//! events from a thread of its own, so that the values it produces may
+//! be delivered to its own watch without waiting for the callback in
+//! progress. The getters of the adapter itself are never sources of
+//! such a getter, lest derived getters feed each other forever.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let derived = Arc::new(DerivedAdapter::new());
+//! manager.add_adapter_with_nodes(derived.clone(), vec![derived.node()]).unwrap();
+//! derived.add_getter(&*manager, &*manager, DerivedGetter::new("frost",
+//!     ChannelKind::Extension { ... },
+//!     vec![thermometer],
+//!     derived::hysteresis(Value::Temperature(Temperature::C(0.)),
+//!                         Value::Temperature(Temperature::C(2.))))).unwrap();
+//! ```
+//!
+
+use adapter::{ is_namespaced, namespaced, Adapter, AdapterError, AdapterManagerHandle, AdapterWatchGuard, RegistrationError, Watchers };
+use api::{ AggregateGetter, Aggregation, Error, OverflowPolicy, WatchEvent, WatchGuard, WatchOptions, API };
+use auth::Caller;
+use devices::{ AdapterId, Channel, ChannelKind, Getter, Node, NodeId, Setter };
+use history;
+use selector::GetterSelector;
+use values::Value;
+use util::{ Id, TagId };
+
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+
+const VERSION: [u32; 4] = [0, 1, 0, 0];
+
+/// The largest number of events waiting for a derived getter whose
+/// sources are matched by selectors. The oldest events are dropped
+/// beyond that, in which case the getter misses some values.
+const MATCHING_CAPACITY: usize = 1024;
+
+/// A function computing the value of a derived getter from the latest
+/// values of its sources, in the order of `DerivedGetter::sources`, or
+/// `None` if the sources have not produced a value yet. Returning
+/// `None` leaves the value of the derived getter unchanged.
+pub type Transform = Box<FnMut(&[Option<Value>]) -> Option<Value> + Send>;
+
+/// A transform applying `f` to the value of the first source, e.g. to
+/// convert units.
+pub fn map<F>(f: F) -> Transform where F: Fn(&Value) -> Option<Value> + Send + 'static {
+    Box::new(move |sources: &[Option<Value>]| match sources.first() {
+        Some(&Some(ref value)) => f(value),
+        _ => None
+    })
+}
+
+/// A transform inverting the `Value::Bool` of the first source.
+pub fn invert() -> Transform {
+    map(|value| match *value {
+        Value::Bool(b) => Some(Value::Bool(!b)),
+        _ => None
+    })
+}
+
+/// A transform turning the value of the first source into a
+/// `Value::Bool`, which becomes `true` once the source reaches `high`
+/// and only becomes `false` again once the source falls to `low`, so
+/// that a value oscillating around a single threshold does not cause a
+/// flurry of changes. Values that cannot be compared with the
+/// thresholds are ignored.
+pub fn hysteresis(low: Value, high: Value) -> Transform {
+    let mut state = None;
+    Box::new(move |sources: &[Option<Value>]| {
+        let value = match sources.first() {
+            Some(&Some(ref value)) => value,
+            _ => return None
+        };
+        if *value >= high {
+            state = Some(true);
+        } else if *value <= low {
+            state = Some(false);
+        }
+        state.map(Value::Bool)
+    })
+}
+
+/// A transform combining the values of the sources with `aggregation`,
+/// as specified by `AggregateGetter`, see `history::combine_latest`.
+/// Sources that have not produced a value yet are ignored.
+pub fn aggregate(aggregation: Aggregation) -> Transform {
+    Box::new(move |sources: &[Option<Value>]| {
+        let values: Vec<_> = sources.iter().filter_map(|value| value.as_ref()).collect();
+        history::combine_latest(&values, &aggregation)
+    })
+}
+
+/// The getters from which a derived getter is computed.
+enum Sources {
+    /// A fixed list of getters, whose values are passed to the
+    /// transform in this order.
+    Ids(Vec<Id<Getter>>),
+
+    /// All the getters matching _either_ of the selectors, including
+    /// getters added later. Their values are passed to the transform
+    /// in no particular order.
+    Matching(Vec<GetterSelector>),
+}
+
+/// The description of a derived getter.
+pub struct DerivedGetter {
+    name: String,
+    kind: ChannelKind,
+    sources: Sources,
+    transform: Transform,
+    tags: Vec<TagId>,
+    label: Option<String>,
+}
+
+impl DerivedGetter {
+    /// A getter of kind `kind`, computed with `transform` from
+    /// `sources`. Its id is `name`, namespaced by the id of the
+    /// `DerivedAdapter`.
+    pub fn new(name: &str, kind: ChannelKind, sources: Vec<Id<Getter>>, transform: Transform) -> Self {
+        DerivedGetter {
+            name: name.to_owned(),
+            kind: kind,
+            sources: Sources::Ids(sources),
+            transform: transform,
+            tags: vec![],
+            label: None,
+        }
+    }
+
+    /// A getter of kind `kind`, computed with `transform` from all the
+    /// getters matching _either_ of the selectors of `source`,
+    /// including getters added later, except the getters of the
+    /// `DerivedAdapter` itself.
+    pub fn matching(name: &str, kind: ChannelKind, source: Vec<GetterSelector>, transform: Transform) -> Self {
+        DerivedGetter {
+            sources: Sources::Matching(source),
+            .. DerivedGetter::new(name, kind, vec![], transform)
+        }
+    }
+
+    /// The getter computing `aggregate`. Getters matching the selectors
+    /// of the aggregate with a distinct kind are ignored.
+    pub fn aggregate(name: &str, aggregate: &AggregateGetter) -> Self {
+        let kind = history::aggregate_kind(&aggregate.kind, &aggregate.aggregation);
+        let source = aggregate.source.iter()
+            .map(|selector| selector.clone().with_kind(aggregate.kind.clone()))
+            .collect();
+        let getter = DerivedGetter::matching(name, kind, source, self::aggregate(aggregate.aggregation.clone()))
+            .with_tags(aggregate.tags.clone());
+        match aggregate.label {
+            None => getter,
+            Some(ref label) => getter.with_label(label.clone())
+        }
+    }
+
+    pub fn with_tags(mut self, mut tags: Vec<TagId>) -> Self {
+        self.tags.append(&mut tags);
+        self
+    }
+
+    pub fn with_label(self, label: String) -> Self {
+        DerivedGetter {
+            label: Some(label),
+            .. self
+        }
+    }
+}
+
+/// An error while adding a derived getter.
+#[derive(Debug, Clone)]
+pub enum DerivedError {
+    /// The getter could not be registered, e.g. its name is already
+    /// used by another derived getter.
+    Registration(RegistrationError),
+
+    /// The sources could not be watched.
+    Watch(Error),
+}
+
+/// The state shared between the adapter and the watches of the sources.
+#[derive(Default)]
+struct Shared {
+    values: Mutex<HashMap<Id<Getter>, Value>>,
+    watchers: Watchers,
+}
+
+impl Shared {
+    fn update(&self, getter: &Id<Getter>, value: Value) {
+        self.values.lock().unwrap().insert(getter.clone(), value.clone());
+        self.watchers.notify(getter, Ok(value));
+    }
+}
+
+/// The inputs of a derived getter.
+struct Inputs {
+    /// The adapter of the derived getter.
+    adapter: Id<AdapterId>,
+
+    /// `true` if sources are added as they produce values and removed
+    /// as they disappear, see `Sources::Matching`.
+    matching: bool,
+
+    sources: Vec<Id<Getter>>,
+    latest: Vec<Option<Value>>,
+    transform: Transform,
+}
+
+impl Inputs {
+    /// Record the value of a source, and return the new value of the
+    /// derived getter, if any.
+    fn set(&mut self, source: &Id<Getter>, value: Value) -> Option<Value> {
+        if self.matching && !is_namespaced(&self.adapter, source) && !self.sources.contains(source) {
+            self.sources.push(source.clone());
+            self.latest.push(None);
+        }
+        let mut changed = false;
+        for (id, latest) in self.sources.iter().zip(self.latest.iter_mut()) {
+            if id == source {
+                *latest = Some(value.clone());
+                changed = true;
+            }
+        }
+        if changed {
+            (self.transform)(&self.latest)
+        } else {
+            None
+        }
+    }
+
+    /// Forget a source that no longer matches, and return the new value
+    /// of the derived getter, if any.
+    fn remove(&mut self, source: &Id<Getter>) -> Option<Value> {
+        if !self.matching {
+            return None;
+        }
+        match self.sources.iter().position(|id| id == source) {
+            None => None,
+            Some(index) => {
+                self.sources.remove(index);
+                self.latest.remove(index);
+                (self.transform)(&self.latest)
+            }
+        }
+    }
+}
+
+/// An adapter exposing derived getters, all of which belong to a single
+/// node. See the module documentation.
+///
+/// Stopping the adapter unregisters the watches on the sources.
+pub struct DerivedAdapter {
+    id: Id<AdapterId>,
+    node: Id<NodeId>,
+    shared: Arc<Shared>,
+    guards: Mutex<HashMap<Id<Getter>, Box<WatchGuard>>>,
+}
+
+impl DerivedAdapter {
+    pub fn new() -> Self {
+        DerivedAdapter::with_id(Id::new("derived@foxlink.mozilla.com".to_owned()))
+    }
+
+    /// An adapter with id `id`, so that several instances may be
+    /// registered with the same manager.
+    pub fn with_id(id: Id<AdapterId>) -> Self {
+        DerivedAdapter {
+            node: namespaced(&id, "derived"),
+            id: id,
+            shared: Arc::new(Shared::default()),
+            guards: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The node to which all derived getters belong, which must be
+    /// registered along with the adapter.
+    pub fn node(&self) -> Node {
+        Node::new(self.node.clone())
+            .with_label("Derived channels".to_owned())
+    }
+
+    /// Register a derived getter with `handle`, then start watching its
+    /// sources through `api`, on behalf of `Caller::system()`.
+    ///
+    /// The sources need not exist yet: the derived getter starts
+    /// producing values once they do.
+    pub fn add_getter<A>(&self, api: &A, handle: &AdapterManagerHandle, getter: DerivedGetter) -> Result<Id<Getter>, DerivedError>
+        where A: API, A::WatchGuard: 'static
+    {
+        let id = namespaced(&self.id, &getter.name);
+        let mut channel = Channel::new(id.clone(), self.node.clone(), Getter::new(getter.kind).with_watch(true))
+            .with_tags(getter.tags);
+        channel.label = getter.label;
+        try!(handle.add_getter(channel).map_err(DerivedError::Registration));
+
+        let (selectors, sources, matching): (Vec<_>, _, _) = match getter.sources {
+            Sources::Ids(sources) => (sources.iter()
+                .map(|source| GetterSelector::new().with_id(source.clone()))
+                .collect(), sources, false),
+            Sources::Matching(selectors) => (selectors, vec![], true)
+        };
+        // Watching the topology reports the sources that no longer
+        // match. See the module documentation for the backpressure.
+        let options = selectors.iter()
+            .map(|selector| {
+                let options = WatchOptions::new().with_getters(selector.clone())
+                    .with_watch_values(true)
+                    .with_watch_topology(matching);
+                if matching {
+                    options.with_backpressure(MATCHING_CAPACITY, OverflowPolicy::DropOldest)
+                } else {
+                    options
+                }
+            })
+            .collect();
+        let inputs = Arc::new(Mutex::new(Inputs {
+            adapter: self.id.clone(),
+            matching: matching,
+            latest: vec![None; sources.len()],
+            sources: sources,
+            transform: getter.transform,
+        }));
+        let caller = Caller::system();
+        let guard = {
+            let shared = self.shared.clone();
+            let inputs = inputs.clone();
+            let id = id.clone();
+            api.register_channel_watch(&caller, options, Box::new(move |event| {
+                on_event(&shared, &inputs, &id, event)
+            }))
+        };
+        let guard = match guard {
+            Ok(guard) => guard,
+            Err(err) => {
+                let _ = handle.remove_getter(&id);
+                return Err(DerivedError::Watch(err));
+            }
+        };
+        self.guards.lock().unwrap().insert(id.clone(), Box::new(guard));
+
+        // Start from the current values of the sources, rather than wait
+        // until they change.
+        for (source, result) in api.get_channel_value(&caller, &selectors) {
+            if let Ok(fetched) = result {
+                let value = inputs.lock().unwrap().set(&source, fetched.value);
+                if let Some(value) = value {
+                    self.shared.update(&id, value);
+                }
+            }
+        }
+        Ok(id)
+    }
+
+    /// Stop watching the sources of a derived getter, then remove it
+    /// from `handle`.
+    pub fn remove_getter(&self, handle: &AdapterManagerHandle, id: &Id<Getter>) -> Result<(), RegistrationError> {
+        if self.guards.lock().unwrap().remove(id).is_none() {
+            return Err(RegistrationError::NoSuchGetter(id.clone()));
+        }
+        self.shared.values.lock().unwrap().remove(id);
+        handle.remove_getter(id)
+    }
+}
+
+impl Default for DerivedAdapter {
+    fn default() -> Self {
+        DerivedAdapter::new()
+    }
+}
+
+fn on_event(shared: &Shared, inputs: &Mutex<Inputs>, id: &Id<Getter>, event: WatchEvent) {
+    match event {
+        WatchEvent::Value { from, value } => {
+            let value = inputs.lock().unwrap().set(&from, value);
+            if let Some(value) = value {
+                shared.update(id, value);
+            }
+        }
+        WatchEvent::GetterRemoved(source) => {
+            let value = inputs.lock().unwrap().remove(&source);
+            if let Some(value) = value {
+                shared.update(id, value);
+            }
+        }
+        WatchEvent::Batch(events) => {
+            for event in events {
+                on_event(shared, inputs, id, event)
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Adapter for DerivedAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "Derived channels"
+    }
+
+    fn vendor(&self) -> &str {
+        "foxlink@mozilla.com"
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        &VERSION
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        let values = self.shared.values.lock().unwrap();
+        getters.into_iter()
+            .map(|id| {
+                let value = values.get(&id).cloned();
+                (id, Ok(value))
+            })
+            .collect()
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        values.into_iter()
+            .map(|(id, _)| (id, Err(AdapterError::UnsupportedOperation("Derived channels have no setters".to_owned()))))
+            .collect()
+    }
+
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        Ok(self.shared.watchers.add(getter, cb))
+    }
+
+    fn stop(&self) {
+        self.guards.lock().unwrap().clear();
+    }
+}
@@ -7,22 +7,67 @@
 //! these data structures are read.
 
 use values::*;
-use util::Id;
+use util::{ Id, TagId };
 
+#[cfg(feature = "serde")]
 use serde::ser::{Serialize, Serializer};
+#[cfg(feature = "serde")]
 use serde::de::{Deserialize, Deserializer, Error};
 
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use std::sync::Arc;
+
 
 /// A marker for Id.
 /// Only useful for writing `Id<NodeId>`.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeId;
 
+/// A marker for Id.
+/// Only useful for writing `Id<AdapterId>`.
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AdapterId;
+
+/// A marker for Id.
+/// Only useful for writing `Id<StateId>`.
+#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StateId;
+
+/// A logical state of a node, exposed both as a getter reporting the
+/// state and a setter changing it, e.g. whether a lamp is on. Clients
+/// read and write the state through its id, with `API::get_state_value`
+/// and `API::put_state_value`, rather than pairing channels themselves.
+///
+/// Both channels belong to the node and have the kind of the state, and
+/// the getter reports a state rather than events, which the manager
+/// checks when the node is registered.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StateChannel {
+    /// An id unique to this state.
+    pub id: Id<StateId>,
+
+    /// The getter reporting the state.
+    pub getter: Id<Getter>,
+
+    /// The setter changing the state.
+    pub setter: Id<Setter>,
+
+    /// The kind of both channels.
+    pub kind: ChannelKind,
+}
+
 /// Metadata on a node. A node is a device or collection of devices
 /// that may offer services. The FoxBox itself a node offering
 /// services such as a clock, communication with the user through her
 /// smart devices, etc.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Node {
     /// Tags describing the node.
     ///
@@ -35,20 +80,99 @@ pub struct Node {
     /// controlling blue lights. An adapter may set tags "plugged" or
     /// "battery" to devices that respectively depend on a plugged
     /// power source or on a battery.
-    pub tags: Vec<String>,
+    pub tags: Vec<TagId>,
 
     /// An id unique to this node.
     pub id: Id<NodeId>,
 
+    /// A human-readable name for the node, e.g. "Kitchen lamp".
+    ///
+    /// Unlike tags, labels are designed for display purposes, not to
+    /// find nodes.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<String>,
+
     /// Channels connected directly to this node.
-    pub getters: Vec<Channel<Getter>>,
-    pub setters: Vec<Channel<Setter>>,
+    ///
+    /// Channels are shared between the copies of a node, so cloning a
+    /// node does not clone its channels. Use `Arc::make_mut` to change
+    /// a channel, which copies it only if it is shared.
+    pub getters: Vec<Arc<Channel<Getter>>>,
+    pub setters: Vec<Arc<Channel<Setter>>>,
+
+    /// The states of the node, each pairing a getter and a setter of
+    /// `getters` and `setters`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub states: Vec<StateChannel>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 
+impl Node {
+    /// Create a new node, without tags, label or channels.
+    pub fn new(id: Id<NodeId>) -> Self {
+        Node {
+            tags: vec![],
+            id: id,
+            label: None,
+            getters: vec![],
+            setters: vec![],
+            states: vec![],
+            private: (),
+        }
+    }
+
+    /// Add tags to the node.
+    pub fn with_tags(mut self, mut tags: Vec<TagId>) -> Self {
+        self.tags.append(&mut tags);
+        self
+    }
+
+    /// Set the label of the node.
+    pub fn with_label(self, label: String) -> Self {
+        Node {
+            label: Some(label),
+            ..self
+        }
+    }
+
+    /// Attach a getter channel to the node. The parent of the channel
+    /// is set to this node.
+    pub fn with_getter(mut self, channel: Channel<Getter>) -> Self {
+        self.getters.push(Arc::new(Channel {
+            node: self.id.clone(),
+            ..channel
+        }));
+        self
+    }
+
+    /// Attach a setter channel to the node. The parent of the channel
+    /// is set to this node.
+    pub fn with_setter(mut self, channel: Channel<Setter>) -> Self {
+        self.setters.push(Arc::new(Channel {
+            node: self.id.clone(),
+            ..channel
+        }));
+        self
+    }
+
+    /// Attach a getter and a setter to the node, paired as state `id`.
+    /// The kind of the state is the kind of the getter.
+    pub fn with_state(self, id: Id<StateId>, getter: Channel<Getter>, setter: Channel<Setter>) -> Self {
+        let state = StateChannel {
+            id: id,
+            getter: getter.id.clone(),
+            setter: setter.id.clone(),
+            kind: getter.mechanism.kind.clone(),
+        };
+        let mut node = self.with_getter(getter).with_setter(setter);
+        node.states.push(state);
+        node
+    }
+}
+
 
 /// The kind of the service, i.e. a strongly-typed description of
 /// _what_ the service can do. Used both for locating services
@@ -62,7 +186,8 @@ pub struct Node {
 /// pre-existing constructors. For this purpose, this enumeration
 /// offers a constructor `Extension`, designed to describe novel
 /// services.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ChannelKind {
     ///
     /// # No payload
@@ -110,6 +235,20 @@ pub enum ChannelKind {
     Thermostat,
     ActualTemperature,
 
+    ///
+    /// # Events
+    ///
+
+    /// A button has been pressed. The payload is the number of
+    /// consecutive presses, e.g. 2 for a double click. Buttons that do
+    /// not distinguish clicks always report 1. Getters of this kind
+    /// emit events, see `Semantics::Event`.
+    ButtonPressed,
+
+    /// A doorbell has rung. Getters of this kind emit events, see
+    /// `Semantics::Event`.
+    DoorbellRang,
+
     /// TODO: Add more
 
     /// An operation of a kind that has not been standardized yet.
@@ -140,23 +279,129 @@ impl ChannelKind {
         use self::ChannelKind::*;
         use values::Type::*;
         match *self {
-            Ready => Unit,
+            Ready | DoorbellRang => Unit,
             OnOff | OpenClosed => Bool,
+            ButtonPressed => Count,
             CurrentTime => TimeStamp,
             CurrentTimeOfDay | RemainingTime => Duration,
             Thermostat | ActualTemperature => Temperature,
             Extension { ref typ, ..} => typ.clone(),
         }
     }
+
+    /// Get whether getters of this kind report a state or emit events.
+    ///
+    /// Extension kinds are assumed to report a state.
+    pub fn semantics(&self) -> Semantics {
+        use self::ChannelKind::*;
+        match *self {
+            ButtonPressed | DoorbellRang => Semantics::Event,
+            _ => Semantics::State,
+        }
+    }
+
+    /// Get a description of this kind, designed to let clients and
+    /// users discover the kinds supported by the FoxBox.
+    ///
+    /// Extension kinds have no standard description, so this returns
+    /// `None` for `Extension`.
+    pub fn describe(&self) -> Option<KindDescription> {
+        use self::ChannelKind::*;
+        let (description, getter, setter) = match *self {
+            Ready => ("The service is ready, e.g. a countdown has reached completion.", true, false),
+            OnOff => ("Whether some device is on or off.", true, true),
+            OpenClosed => ("Whether some device is open or closed.", true, true),
+            CurrentTime => ("The current absolute time.", true, true),
+            CurrentTimeOfDay => ("The current time of day.", true, true),
+            RemainingTime => ("The time remaining until a countdown is elapsed.", true, true),
+            Thermostat => ("The temperature requested from a thermostat.", true, true),
+            ActualTemperature => ("The temperature measured by a sensor.", true, false),
+            ButtonPressed => ("A button has been pressed, as a number of consecutive presses.", true, false),
+            DoorbellRang => ("A doorbell has rung.", true, false),
+            Extension { .. } => return None,
+        };
+        Some(KindDescription {
+            kind: self.clone(),
+            typ: self.get_type(),
+            semantics: self.semantics(),
+            description: description.to_owned(),
+            getter: getter,
+            setter: setter,
+        })
+    }
+
+    /// Get the description of all the standardized kinds, i.e. all
+    /// kinds except `Extension`.
+    pub fn standard_kinds() -> Vec<KindDescription> {
+        use self::ChannelKind::*;
+        vec![Ready, OnOff, OpenClosed, CurrentTime, CurrentTimeOfDay,
+             RemainingTime, Thermostat, ActualTemperature, ButtonPressed,
+             DoorbellRang]
+            .iter()
+            .filter_map(ChannelKind::describe)
+            .collect()
+    }
 }
 
+/// A description of a kind of channel.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct KindDescription {
+    /// The kind being described.
+    pub kind: ChannelKind,
+
+    /// The type of values used to communicate with channels of this kind.
+    pub typ: Type,
+
+    /// Whether getter channels of this kind report a state or emit
+    /// events.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub semantics: Semantics,
+
+    /// A human-readable description of the kind.
+    pub description: String,
+
+    /// If `true`, getter channels may have this kind.
+    pub getter: bool,
+
+    /// If `true`, setter channels may have this kind.
+    pub setter: bool,
+}
+
+/// What the values produced by a getter stand for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Semantics {
+    /// Each value is the current state of the device, e.g. whether a
+    /// door is open. The latest value remains true until the next one.
+    State,
+
+    /// Each value is a discrete occurrence, e.g. a press on a button or
+    /// a ring of a doorbell. There is no current value: fetching the
+    /// channel fails with `Error::EventChannel`, and channels are never
+    /// polled. Watches receive each occurrence, even if equal to the
+    /// previous one, and `WatchOptions::with_min_delta` does not apply.
+    /// Past occurrences may be obtained from the history.
+    Event,
+}
+
+impl Default for Semantics {
+    fn default() -> Self {
+        Semantics::State
+    }
+}
 
 /// A getter operation available on a channel.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Getter {
     /// The kind of value that can be obtained from this channel.
     pub kind: ChannelKind,
 
+    /// Whether the channel reports a state or emits events.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub semantics: Semantics,
+
     /// If `Some(duration)`, this channel can be polled, i.e. it
     /// will respond when the FoxBox requests the latest value.
     /// Parameter `duration` indicates the smallest interval
@@ -170,7 +415,7 @@ pub struct Getter {
     /// - Long-running pollution or humidity sensors typically
     ///   do not accept requests and rather send batches of
     ///   data every 24h.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub poll: Option<ValDuration>,
 
     /// If `Some(duration)`, this channel can send the data to
@@ -179,12 +424,12 @@ pub struct Getter {
     ///
     /// Otherwise, the channel cannot send data to the FoxBox
     /// and needs to be polled.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub trigger: Option<ValDuration>,
 
     /// If `true`, this channel supports watching for specific
     /// changes.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub watch: bool,
 
     /// Date at which the latest value was received, whether through
@@ -192,40 +437,117 @@ pub struct Getter {
     pub updated: Option<TimeStamp>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 impl IOMechanism for Getter {
 }
 
+impl Getter {
+    /// Create a new getter, which can neither be polled nor trigger,
+    /// with the semantics of its kind.
+    pub fn new(kind: ChannelKind) -> Self {
+        Getter {
+            semantics: kind.semantics(),
+            kind: kind,
+            poll: None,
+            trigger: None,
+            watch: false,
+            updated: None,
+            private: (),
+        }
+    }
+
+    /// Let the channel be polled, at most once per `duration`.
+    pub fn with_poll(self, duration: ValDuration) -> Self {
+        Getter {
+            poll: Some(duration),
+            ..self
+        }
+    }
+
+    /// Let the channel send data, at most once per `duration`.
+    pub fn with_trigger(self, duration: ValDuration) -> Self {
+        Getter {
+            trigger: Some(duration),
+            ..self
+        }
+    }
+
+    /// Determine whether the channel supports watching for specific
+    /// changes.
+    pub fn with_watch(self, watch: bool) -> Self {
+        Getter {
+            watch: watch,
+            ..self
+        }
+    }
+
+    /// Determine whether the channel reports a state or emits events.
+    pub fn with_semantics(self, semantics: Semantics) -> Self {
+        Getter {
+            semantics: semantics,
+            ..self
+        }
+    }
+
+    /// Determine whether the channel emits events rather than reporting
+    /// a state.
+    pub fn is_event(&self) -> bool {
+        self.semantics == Semantics::Event
+    }
+}
+
 /// An setter operation available on an channel.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Setter {
     /// The kind of value that can be sent to this channel.
     pub kind: ChannelKind,
 
     /// If `Some(duration)`, this channel supports pushing,
     /// i.e. the FoxBox can send values.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub push: Option<ValDuration>,
 
     /// Date at which the latest value was sent to the channel.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub updated: Option<TimeStamp>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 impl IOMechanism for Setter {
 }
 
+impl Setter {
+    /// Create a new setter, which does not support pushing.
+    pub fn new(kind: ChannelKind) -> Self {
+        Setter {
+            kind: kind,
+            push: None,
+            updated: None,
+            private: (),
+        }
+    }
+
+    /// Let the FoxBox push values, at most once per `duration`.
+    pub fn with_push(self, duration: ValDuration) -> Self {
+        Setter {
+            push: Some(duration),
+            ..self
+        }
+    }
+}
+
 /// An channel represents a single place where data can enter or
 /// leave a device. Note that channels support either a single kind
 /// of getter or a single kind of setter. Devices that support both
 /// getters or setters, or several kinds of getters, or several kinds of
 /// setters, are represented as nodes containing several channels.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Channel<IO> where IO: IOMechanism {
     /// Tags describing the channel.
     ///
@@ -233,12 +555,20 @@ pub struct Channel<IO> where IO: IOMechanism {
     /// applications. They are used to regroup channels for rules.
     ///
     /// For instance "entrance".
-    #[serde(default)]
-    pub tags: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: Vec<TagId>,
 
     /// An id unique to this channel.
     pub id: Id<IO>,
 
+    /// A human-readable name for the channel, e.g. "Kitchen
+    /// temperature".
+    ///
+    /// Unlike tags, labels are designed for display purposes, not to
+    /// find channels.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<String>,
+
     /// The node owning this channel.
     pub node: Id<NodeId>,
 
@@ -246,15 +576,50 @@ pub struct Channel<IO> where IO: IOMechanism {
     pub mechanism: IO,
 
     /// The last time the device was seen.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub last_seen: Option<TimeStamp>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 
+impl<IO> Channel<IO> where IO: IOMechanism {
+    /// Create a new channel, without tags or label.
+    pub fn new(id: Id<IO>, node: Id<NodeId>, mechanism: IO) -> Self {
+        Channel {
+            tags: vec![],
+            id: id,
+            label: None,
+            node: node,
+            mechanism: mechanism,
+            last_seen: None,
+            private: (),
+        }
+    }
+
+    /// Add tags to the channel.
+    pub fn with_tags(mut self, mut tags: Vec<TagId>) -> Self {
+        self.tags.append(&mut tags);
+        self
+    }
+
+    /// Set the label of the channel.
+    pub fn with_label(self, label: String) -> Self {
+        Channel {
+            label: Some(label),
+            ..self
+        }
+    }
+}
+
 /// The communication mechanism used by the channel.
+#[cfg(feature = "serde")]
 pub trait IOMechanism: Deserialize + Serialize {
 }
 
+/// The communication mechanism used by the channel.
+#[cfg(not(feature = "serde"))]
+pub trait IOMechanism {
+}
+
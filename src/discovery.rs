@@ -0,0 +1,140 @@
+//!
+//! Devices found on the network, waiting to be adopted.
+//!
+//! Adapters that scan the network, e.g. for UPnP or mDNS devices,
+//! announce the devices they find through trait `Discovery` rather than
+//! register nodes right away, so that users choose which devices the
+//! FoxBox should manage. Each device is identified by a fingerprint,
+//! e.g. its MAC address or UPnP UDN, which must not change across
+//! restarts, so that announcing the same device twice, or from two
+//! adapters, yields a single `Candidate`. Once a user adopts a
+//! candidate with `API::adopt_candidate`, the manager asks one of the
+//! adapters that announced it to register the corresponding nodes with
+//! `Adapter::adopt`. Rejected candidates are remembered, so that they
+//! are not offered again.
+//!
+//! # Example
+//!
+//! ```ignore
+//! discovery.announce(&adapter.id(), Announcement::new(Id::new("mac:00:17:88:01:02:03".to_owned()))
+//!     .with_label("Philips Hue bridge".to_owned())).unwrap();
+//! ```
+//!
+
+use adapter::RegistrationError;
+use devices::AdapterId;
+use values::TimeStamp;
+use util::Id;
+
+/// A marker for Id.
+/// Only useful for writing `Id<CandidateId>`. The id of a candidate is
+/// its fingerprint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct CandidateId;
+
+/// A device found by an adapter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    /// A fingerprint identifying the device, e.g. "mac:00:17:88:01:02:03".
+    /// Adapters finding the same device must use the same fingerprint.
+    pub fingerprint: Id<CandidateId>,
+
+    /// A human-readable label, e.g. "Philips Hue bridge".
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl Announcement {
+    pub fn new(fingerprint: Id<CandidateId>) -> Self {
+        Announcement {
+            fingerprint: fingerprint,
+            label: None,
+        }
+    }
+
+    pub fn with_label(self, label: String) -> Self {
+        Announcement {
+            label: Some(label),
+            .. self
+        }
+    }
+}
+
+/// The decision of the user regarding a candidate.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CandidateStatus {
+    /// The user has not decided yet.
+    Pending,
+
+    /// The candidate has been adopted by the adapter.
+    Adopted(Id<AdapterId>),
+
+    /// The user does not want the FoxBox to manage this device.
+    Rejected,
+}
+
+/// A device announced by one or more adapters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub id: Id<CandidateId>,
+
+    /// The latest label announced, if any.
+    pub label: Option<String>,
+
+    /// The adapters currently announcing this device, in the order in
+    /// which they announced it. Empty once the device has been
+    /// withdrawn by all adapters.
+    pub adapters: Vec<Id<AdapterId>>,
+
+    pub status: CandidateStatus,
+
+    /// When the device was first announced.
+    pub first_seen: TimeStamp,
+
+    /// When the device was last announced.
+    pub last_seen: TimeStamp,
+}
+
+impl Candidate {
+    pub fn new(id: Id<CandidateId>) -> Self {
+        let now = TimeStamp::now();
+        Candidate {
+            id: id,
+            label: None,
+            adapters: vec![],
+            status: CandidateStatus::Pending,
+            first_seen: now.clone(),
+            last_seen: now,
+        }
+    }
+
+    /// Record that `adapter` has announced the device.
+    pub fn announced(&mut self, adapter: &Id<AdapterId>, announcement: Announcement) {
+        if !self.adapters.contains(adapter) {
+            self.adapters.push(adapter.clone());
+        }
+        if announcement.label.is_some() {
+            self.label = announcement.label;
+        }
+        self.last_seen = TimeStamp::now();
+    }
+
+    /// Record that `adapter` does not see the device anymore.
+    pub fn withdrawn(&mut self, adapter: &Id<AdapterId>) {
+        self.adapters.retain(|id| id != adapter);
+    }
+}
+
+/// The interface through which adapters announce the devices they
+/// find. Implemented by `manager::AdapterManager`.
+pub trait Discovery: Send + Sync {
+    /// Announce a device found by `adapter`. Announcing a device that
+    /// has already been announced, by this adapter or another one,
+    /// merely refreshes the candidate. Returns the id of the candidate.
+    fn announce(&self, adapter: &Id<AdapterId>, announcement: Announcement) -> Result<Id<CandidateId>, RegistrationError>;
+
+    /// Report that `adapter` does not see a device anymore, e.g. it has
+    /// left the network. Pending candidates that are not announced by
+    /// any adapter anymore are forgotten.
+    fn withdraw(&self, adapter: &Id<AdapterId>, candidate: &Id<CandidateId>) -> Result<(), RegistrationError>;
+}
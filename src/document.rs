@@ -0,0 +1,188 @@
+//!
+//! Exporting and importing the state of the taxonomy.
+//!
+//! A `Document` holds everything users have set up on a FoxBox: the
+//! tags and labels of nodes and channels, the tagging rules and the
+//! configuration of adapters. Documents are produced with
+//! `manager::AdapterManager::export_state` and applied with
+//! `manager::AdapterManager::import_state`, e.g. to back up a FoxBox or
+//! to move its setup to another unit.
+//!
+//! Unlike `manager::Snapshot`, which is designed to present the known
+//! topology after a restart, a `Document` does not describe the nodes
+//! themselves, only what users have attached to them, and is designed
+//! to be kept for a long time. Documents are therefore self-describing
+//! and versioned: each document carries `FORMAT` and the `VERSION` of
+//! the format with which it was written. Documents written with older
+//! versions are migrated when they are loaded with `Document::from_json`,
+//! see module `migration`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! {
+//!   "format": "foxbox-taxonomy/state",
+//!   "version": 1,
+//!   "exported": "2016-04-12T10:30:00Z",
+//!   "annotations": [
+//!     { "target": { "Node": "zwave@foxlink.mozilla.com/node-3" },
+//!       "tags": ["kitchen"], "label": "Fridge" }
+//!   ],
+//!   "tagging_rules": [],
+//!   "config": {}
+//! }
+//! ```
+//!
+
+use api::{ Error, Labelled, TaggingRule, TaggingRuleId };
+use devices::AdapterId;
+use migration::{ MigrationError, Migrations };
+use values::{ TimeStamp, Value };
+use util::{ Id, TagId };
+
+use serde_json;
+use serde_json::value::Value as JSON;
+
+use std::collections::HashMap;
+
+/// The format of all documents.
+pub const FORMAT: &'static str = "foxbox-taxonomy/state";
+
+/// The version of the format written by this implementation.
+pub const VERSION: u32 = 1;
+
+/// The steps migrating documents written with older versions of the
+/// format. There are none yet, as version 1 is the first version.
+pub fn migrations() -> Migrations {
+    Migrations::new()
+}
+
+/// What users have attached to a node or channel.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Annotation {
+    pub target: Labelled,
+
+    #[serde(default)]
+    pub tags: Vec<TagId>,
+
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// The state of the taxonomy, as exported.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Document {
+    /// Always `FORMAT`.
+    pub format: String,
+
+    /// The version of the format, at most `VERSION`.
+    pub version: u32,
+
+    /// When the document was exported.
+    pub exported: TimeStamp,
+
+    /// The nodes and channels that have tags or a label.
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+
+    #[serde(default)]
+    pub tagging_rules: Vec<TaggingRule>,
+
+    /// The options of the configuration of adapters that have been set.
+    #[serde(default)]
+    pub config: HashMap<Id<AdapterId>, HashMap<String, Value>>,
+}
+
+impl Document {
+    /// Create an empty document with the current version.
+    pub fn new() -> Self {
+        Document {
+            format: FORMAT.to_owned(),
+            version: VERSION,
+            exported: TimeStamp::now(),
+            annotations: vec![],
+            tagging_rules: vec![],
+            config: HashMap::new(),
+        }
+    }
+
+    /// Load a document written with any version of the format up to
+    /// `VERSION`, migrating it if necessary.
+    pub fn from_json(source: &str) -> Result<Self, DocumentError> {
+        let json = try!(serde_json::from_str(source).map_err(|err| DocumentError::Syntax(format!("{:?}", err))));
+        Document::from_value(json)
+    }
+
+    /// As `from_json`, from a parsed document.
+    pub fn from_value(mut json: JSON) -> Result<Self, DocumentError> {
+        let version = {
+            let format = json.find("format").and_then(|format| format.as_string()).unwrap_or("");
+            if format != FORMAT {
+                return Err(DocumentError::UnknownFormat(format.to_owned()));
+            }
+            match json.find("version").and_then(|version| version.as_u64()) {
+                None => return Err(DocumentError::Syntax("Missing field `version`".to_owned())),
+                Some(version) if version > VERSION as u64 => return Err(DocumentError::UnsupportedVersion(version as u32)),
+                Some(version) => version as u32
+            }
+        };
+        try!(migrations().migrate(&mut json, version, VERSION).map_err(DocumentError::Migration));
+        if let Some(object) = json.as_object_mut() {
+            object.insert("version".to_owned(), JSON::U64(VERSION as u64));
+        }
+        serde_json::from_value(json).map_err(|err| DocumentError::Syntax(format!("{:?}", err)))
+    }
+
+    /// Check that the document may be imported by this implementation.
+    pub fn check(&self) -> Result<(), DocumentError> {
+        if self.format != FORMAT {
+            return Err(DocumentError::UnknownFormat(self.format.clone()));
+        }
+        if self.version > VERSION {
+            return Err(DocumentError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document::new()
+    }
+}
+
+/// A document that cannot be imported.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum DocumentError {
+    /// The document is not a state of the taxonomy.
+    UnknownFormat(String),
+
+    /// The document has been written by a newer implementation.
+    UnsupportedVersion(u32),
+
+    /// The document is not valid JSON, or does not have the expected
+    /// shape.
+    Syntax(String),
+
+    /// The document could not be migrated from an older version.
+    Migration(MigrationError),
+}
+
+/// The outcome of importing a document.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    /// The nodes and channels whose tags and label have been imported.
+    pub applied: Vec<Labelled>,
+
+    /// The nodes and channels that are not registered. Their tags are
+    /// applied if they are registered later and tags are persisted,
+    /// see module `tagstore`, but their label is lost.
+    pub missing: Vec<Labelled>,
+
+    /// The ids given to the imported tagging rules.
+    pub tagging_rules: Vec<Id<TaggingRuleId>>,
+
+    /// The options of configuration that have not been imported, along
+    /// with the reason.
+    pub rejected: Vec<(Id<AdapterId>, String, Error)>,
+}
@@ -0,0 +1,1318 @@
+//!
+//! An in-memory implementation of the API, designed for testing.
+//!
+//! `FakeAPI` holds a topology built by the test itself, records the
+//! values sent to setter channels and lets the test decide which
+//! values getter channels produce, either one at a time with
+//! `FakeAPI::inject_value` or as scripted sequences with
+//! `FakeAPI::script_values`. Watches behave as with any other
+//! implementation of the API, so clients such as the REST frontend or
+//! the rules engine can be tested without standing up any adapter.
+//!
+//! Optional features `PollNow`, `TaggingRules`, `WatchTokens`,
+//! `WatchIntrospection`, `WatchBatching`, `WatchBackpressure`,
+//! `WatchExpiration`, `WatchMinDelta`, `ScheduledWrites`,
+//! `Reservations` and `AggregateGetters` are supported, in memory.
+//! Other optional features report `Error::Unsupported`.
+//!
+//! Deadlines, i.e. the expiry of watches, the delivery of batches and
+//! scheduled writes, are measured with the real clock, on a timer
+//! thread of their own. Aggregate getters belong to node `aggregate`,
+//! and only produce a value when it changes.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use foxbox_taxonomy::fake::*;
+//!
+//! let api = FakeAPI::new();
+//! let node_id = Id::new("thermometer".to_owned());
+//! let getter_id = Id::new("thermometer/temperature".to_owned());
+//! api.add_node(Node::new(node_id.clone())
+//!     .with_getter(Channel::new(getter_id.clone(), node_id, Getter::new(ChannelKind::ActualTemperature))));
+//!
+//! // Each fetch produces the next value of the script.
+//! api.script_values(&getter_id, vec![
+//!     Value::Temperature(Temperature::C(20.)),
+//!     Value::Temperature(Temperature::C(21.)),
+//! ]).unwrap();
+//! let values = api.get_channel_value(&Caller::system(), &vec![GetterSelector::new().with_id(getter_id)]);
+//! ```
+//!
+
+use api::*;
+use arbitration::Priority;
+use auth::{Caller, CallerId, Operation, PermissionPolicy, ScopePolicy};
+use delivery::{self, add_tags, deliver, remove_tags, Callback, Deliveries};
+use delivery::batch::Batch;
+use delivery::buffer::Buffer;
+use delivery::expiry::Expiry;
+use delivery::timer::{TaskId, Timer};
+use devices::*;
+use discovery::{Candidate, CandidateId};
+use history::{ self, Bucket, Sample };
+use metrics::Metrics;
+use selector::*;
+use values::{TimeStamp, Value, ValDuration};
+use util::{ Id, TagId };
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+struct Watch {
+    info: WatchInfo,
+    cb: Callback,
+
+    /// The caller on behalf of which the watch has been registered.
+    caller: Id<CallerId>,
+
+    /// The latest value delivered for each getter, if the watch has
+    /// been registered with `WatchOptions::with_min_delta`.
+    delivered: HashMap<Id<Getter>, Value>,
+
+    /// If the watch has been registered with
+    /// `WatchOptions::with_backpressure`, the buffer through which `cb`
+    /// delivers events.
+    buffer: Option<Arc<Buffer>>,
+}
+
+struct Scheduled {
+    write: ScheduledWrite,
+
+    /// The caller on behalf of which the write is executed.
+    caller: Caller,
+
+    /// The task executing the write.
+    task: TaskId,
+}
+
+struct State {
+    nodes: Vec<Node>,
+
+    /// The current revision of the topology.
+    revision: u64,
+
+    /// A counter used to generate ids.
+    counter: u64,
+
+    /// The latest value produced by each getter channel.
+    values: HashMap<Id<Getter>, FetchedValue>,
+
+    /// The values that each getter channel will produce next.
+    scripts: HashMap<Id<Getter>, VecDeque<Value>>,
+
+    /// All the values sent to setter channels, in order.
+    sent: Vec<(Id<Setter>, Value)>,
+
+    tagging_rules: Vec<(Id<TaggingRuleId>, TaggingRule)>,
+    watches: HashMap<Id<WatchId>, Watch>,
+    schedules: HashMap<Id<ScheduleId>, Scheduled>,
+
+    /// The reservations of setters, including expired reservations,
+    /// which are purged whenever reservations are added or listed.
+    reservations: HashMap<Id<ReservationId>, Reservation>,
+
+    /// The aggregate getters, all of which belong to node `aggregate`.
+    aggregates: HashMap<Id<Getter>, AggregateGetter>,
+}
+
+impl State {
+    fn new_id<T>(&mut self, prefix: &str) -> Id<T> {
+        self.counter += 1;
+        Id::new(format!("{}-{}", prefix, self.counter))
+    }
+
+    fn has_getter(&self, id: &Id<Getter>) -> bool {
+        self.nodes.iter().any(|node| node.getters.iter().any(|channel| channel.id == *id))
+    }
+
+    fn getter_ids(&self, selectors: &Vec<GetterSelector>) -> Vec<Id<Getter>> {
+        let mut ids = vec![];
+        for node in &self.nodes {
+            for channel in &node.getters {
+                if selectors.iter().any(|selector| selector.matches(channel)) {
+                    ids.push(channel.id.clone());
+                }
+            }
+        }
+        ids
+    }
+
+    /// Determine whether a getter emits events rather than reporting a
+    /// state.
+    fn is_event(&self, id: &Id<Getter>) -> bool {
+        self.nodes.iter()
+            .flat_map(|node| node.getters.iter())
+            .any(|channel| channel.id == *id && channel.mechanism.is_event())
+    }
+
+    /// Queue `event` for each watch with at least one option accepted
+    /// by `filter`.
+    fn notify<F>(&mut self, event: WatchEvent, filter: F) -> Deliveries where F: Fn(&WatchOptions) -> bool {
+        let mut deliveries = vec![];
+        for watch in self.watches.values_mut() {
+            if watch.info.options.iter().any(|options| filter(options)) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), event.clone()));
+            }
+        }
+        deliveries
+    }
+
+    /// The getter channels currently watched by each watch, for the
+    /// purpose of determining which channels have been added or
+    /// removed by a change of topology.
+    fn topology_snapshot(&self) -> HashSet<(Id<WatchId>, Id<Getter>)> {
+        let mut snapshot = HashSet::new();
+        for (id, watch) in &self.watches {
+            for node in &self.nodes {
+                for channel in &node.getters {
+                    let watched = watch.info.options.iter().any(|options| {
+                        options.should_watch_topology && options.source.matches(channel)
+                    });
+                    if watched {
+                        snapshot.insert((id.clone(), channel.id.clone()));
+                    }
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Bump the revision and queue `GetterAdded`/`GetterRemoved` for
+    /// the changes since `before`.
+    fn topology_changed(&mut self, before: HashSet<(Id<WatchId>, Id<Getter>)>) -> Deliveries {
+        self.revision += 1;
+        let after = self.topology_snapshot();
+        let mut deliveries = vec![];
+        for &(ref id, ref getter) in before.difference(&after) {
+            if let Some(watch) = self.watches.get_mut(id) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), WatchEvent::GetterRemoved(getter.clone())));
+            }
+        }
+        for &(ref id, ref getter) in after.difference(&before) {
+            if let Some(watch) = self.watches.get_mut(id) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), WatchEvent::GetterAdded(getter.clone())));
+            }
+        }
+        deliveries
+    }
+
+    fn label_changed(&mut self, target: Labelled, label: Option<String>) -> Deliveries {
+        self.notify(WatchEvent::LabelChanged {
+            target: target,
+            label: label,
+        }, |options| options.should_watch_topology)
+    }
+
+    /// Apply the tagging rules to the node at `index`.
+    fn apply_tagging_rules(&mut self, index: usize) {
+        let node = &mut self.nodes[index];
+        for &(_, ref rule) in &self.tagging_rules {
+            if rule.set.iter().any(|selector| selector.matches(node)) {
+                add_tags(&mut node.tags, &rule.tags);
+            }
+        }
+    }
+
+    /// Record a value produced by a getter channel and queue it for
+    /// the watches.
+    fn produce(&mut self, id: &Id<Getter>, value: Value) -> Deliveries {
+        let now = TimeStamp::now();
+        let mut found = None;
+        for node in &mut self.nodes {
+            for channel in &mut node.getters {
+                if channel.id == *id {
+                    let channel = Arc::make_mut(channel);
+                    channel.mechanism.updated = Some(now.clone());
+                    found = Some(channel.clone());
+                }
+            }
+        }
+        let channel = match found {
+            None => return vec![],
+            Some(channel) => channel
+        };
+        self.values.insert(id.clone(), FetchedValue {
+            value: value.clone(),
+            timestamp: now,
+            source: ValueSource::Live,
+            adapter: Id::new("fake".to_owned()),
+            age: None,
+        });
+        let mut deliveries = vec![];
+        for watch in self.watches.values_mut() {
+            let watched = watch.info.options.iter().any(|options| {
+                options.should_watch_values && options.source.matches(&channel)
+            });
+            if watched && delivery::accepts(&watch.info.options, &mut watch.delivered, &channel, &value) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), WatchEvent::Value {
+                    from: id.clone(),
+                    value: value.clone()
+                }));
+            }
+        }
+        // Aggregate getters are never sources of aggregate getters.
+        if !self.aggregates.contains_key(id) {
+            deliveries.append(&mut self.refresh_aggregates());
+        }
+        deliveries
+    }
+
+    /// Recompute the aggregate getters from the latest values of their
+    /// sources, and queue the values that have changed.
+    fn refresh_aggregates(&mut self) -> Deliveries {
+        let mut changed = vec![];
+        for (id, aggregate) in &self.aggregates {
+            let values: Vec<_> = self.nodes.iter()
+                .flat_map(|node| node.getters.iter())
+                .filter(|channel| {
+                    !self.aggregates.contains_key(&channel.id) && channel.mechanism.kind == aggregate.kind &&
+                        aggregate.source.iter().any(|selector| selector.matches(channel))
+                })
+                .filter_map(|channel| self.values.get(&channel.id))
+                .map(|fetched| &fetched.value)
+                .collect();
+            if let Some(value) = history::combine_latest(&values, &aggregate.aggregation) {
+                if self.values.get(id).map(|fetched| &fetched.value) != Some(&value) {
+                    changed.push((id.clone(), value));
+                }
+            }
+        }
+        let mut deliveries = vec![];
+        for (id, value) in changed {
+            deliveries.append(&mut self.produce(&id, value));
+        }
+        deliveries
+    }
+
+    fn purge_reservations(&mut self) {
+        let now = TimeStamp::now();
+        self.reservations.retain(|_, reservation| reservation.expires > now);
+    }
+
+
+    /// Fetch a value from a getter channel, consuming the next value of
+    /// its script if there is one.
+    fn fetch(&mut self, id: &Id<Getter>) -> (Option<FetchedValue>, Deliveries) {
+        let next = match self.scripts.get_mut(id) {
+            Some(script) => script.pop_front(),
+            None => None
+        };
+        match next {
+            Some(value) => {
+                let deliveries = self.produce(id, value);
+                (self.values.get(id).cloned(), deliveries)
+            }
+            None => {
+                let cached = self.values.get(id).map(|fetched| FetchedValue {
+                    source: ValueSource::Cache,
+                    age: TimeStamp::now().duration_since(&fetched.timestamp),
+                    ..fetched.clone()
+                });
+                (cached, vec![])
+            }
+        }
+    }
+}
+
+/// An in-memory implementation of the API.
+///
+/// Permissions are checked with `auth::ScopePolicy`, unless another
+/// policy is specified with `FakeAPI::with_policy`. Tests that do not
+/// care about permissions may simply call the API as `Caller::system()`.
+pub struct FakeAPI {
+    state: Arc<Mutex<State>>,
+    policy: Arc<Box<PermissionPolicy>>,
+
+    /// The thread running the tasks that are due at a deadline, e.g.
+    /// scheduled writes.
+    timer: Timer,
+}
+
+impl FakeAPI {
+    /// Create an implementation of the API without any node.
+    pub fn new() -> Self {
+        FakeAPI {
+            state: Arc::new(Mutex::new(State {
+                nodes: vec![],
+                revision: 0,
+                counter: 0,
+                values: HashMap::new(),
+                scripts: HashMap::new(),
+                sent: vec![],
+                tagging_rules: vec![],
+                watches: HashMap::new(),
+                schedules: HashMap::new(),
+                reservations: HashMap::new(),
+                aggregates: HashMap::new(),
+            })),
+            policy: Arc::new(Box::new(ScopePolicy)),
+            timer: Timer::start(),
+        }
+    }
+
+    /// Check permissions with `policy`.
+    pub fn with_policy(self, policy: Box<PermissionPolicy>) -> Self {
+        FakeAPI {
+            policy: Arc::new(policy),
+            ..self
+        }
+    }
+
+    /// Add a node, along with its channels, as if it had just been
+    /// discovered by an adapter. If a node with the same id already
+    /// exists, it is replaced.
+    ///
+    /// Live tagging rules are applied to the node, and watches receive
+    /// `WatchEvent::GetterAdded` for the getter channels they watch.
+    pub fn add_node(&self, node: Node) {
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.topology_snapshot();
+            state.nodes.retain(|existing| existing.id != node.id);
+            state.nodes.push(node);
+            let index = state.nodes.len() - 1;
+            state.apply_tagging_rules(index);
+            let mut deliveries = state.topology_changed(before);
+            deliveries.append(&mut state.refresh_aggregates());
+            deliveries
+        };
+        deliver(deliveries);
+    }
+
+    /// Simulate a getter channel producing a value, e.g. a sensor
+    /// pushing a new reading. Watches receive `WatchEvent::Value`.
+    ///
+    /// # Errors
+    ///
+    /// `NoSuchGetter` if there is no such getter channel.
+    pub fn inject_value(&self, id: &Id<Getter>, value: Value) -> Result<(), Error> {
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            if !state.has_getter(id) {
+                return Err(Error::NoSuchGetter(id.clone()));
+            }
+            state.produce(id, value)
+        };
+        deliver(deliveries);
+        Ok(())
+    }
+
+    /// Simulate a getter channel failing, e.g. a device becoming
+    /// unreachable. Watches receive `WatchEvent::Error`.
+    ///
+    /// # Errors
+    ///
+    /// `NoSuchGetter` if there is no such getter channel.
+    pub fn inject_error(&self, id: &Id<Getter>, error: Error) -> Result<(), Error> {
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let channel = state.nodes.iter()
+                .flat_map(|node| node.getters.iter())
+                .find(|channel| channel.id == *id)
+                .cloned();
+            let channel = match channel {
+                None => return Err(Error::NoSuchGetter(id.clone())),
+                Some(channel) => channel
+            };
+            state.notify(WatchEvent::Error {
+                from: id.clone(),
+                error: error
+            }, |options| options.should_watch_values && options.source.matches(&channel))
+        };
+        deliver(deliveries);
+        Ok(())
+    }
+
+    /// Append values to the script of a getter channel.
+    ///
+    /// Each call to `API::get_channel_value` or `API::poll_now` on the
+    /// channel consumes the next value of the script, as does
+    /// `FakeAPI::advance`. Once the script is exhausted, the latest
+    /// value is served again.
+    ///
+    /// # Errors
+    ///
+    /// `NoSuchGetter` if there is no such getter channel.
+    pub fn script_values(&self, id: &Id<Getter>, values: Vec<Value>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if !state.has_getter(id) {
+            return Err(Error::NoSuchGetter(id.clone()));
+        }
+        state.scripts.entry(id.clone()).or_insert_with(VecDeque::new).extend(values);
+        Ok(())
+    }
+
+    /// Let each getter channel with a non-empty script produce the next
+    /// value of its script, as if the device had pushed it. Return the
+    /// number of values produced.
+    pub fn advance(&self) -> usize {
+        let mut produced = 0;
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let ids: Vec<_> = state.scripts.keys().cloned().collect();
+            let mut deliveries = vec![];
+            for id in ids {
+                if let (Some(_), mut events) = state.fetch(&id) {
+                    produced += 1;
+                    deliveries.append(&mut events);
+                }
+            }
+            deliveries
+        };
+        deliver(deliveries);
+        produced
+    }
+
+    /// All the values successfully sent to setter channels so far, in
+    /// order.
+    pub fn sent_values(&self) -> Vec<(Id<Setter>, Value)> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    fn register_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        try!(self.policy.check(caller, &Operation::Watch { options: &options }));
+        // The shortest delay and the smallest number of events prevail.
+        let batching = options.iter()
+            .filter_map(|options| options.batching.clone())
+            .fold(None, |merged: Option<Batching>, batching| Some(match merged {
+                None => batching,
+                Some(merged) => Batching {
+                    max_delay: cmp::min(merged.max_delay, batching.max_delay),
+                    max_events: cmp::min(merged.max_events, batching.max_events),
+                }
+            }));
+        // The smallest capacity prevails, along with the policy of the
+        // first option.
+        let backpressure = options.iter()
+            .filter_map(|options| options.backpressure.clone())
+            .fold(None, |merged: Option<Backpressure>, backpressure| Some(match merged {
+                None => backpressure,
+                Some(merged) => Backpressure {
+                    capacity: cmp::min(merged.capacity, backpressure.capacity),
+                    policy: merged.policy,
+                }
+            }));
+        // The shortest duration and the smallest number of events prevail.
+        let expires_after = options.iter().filter_map(|options| options.expires_after.clone()).min();
+        let max_events = options.iter().filter_map(|options| options.max_events).min();
+        let id = self.state.lock().unwrap().new_id("watch");
+        let cb = match batching {
+            None => cb,
+            Some(ref batching) => Batch::start(batching, self.timer.handle(), cb)
+        };
+        let (expiry, cb) = if expires_after.is_none() && max_events.is_none() {
+            (None, cb)
+        } else {
+            let state = Arc::downgrade(&self.state);
+            let watch = id.clone();
+            let (expiry, cb) = Expiry::start(max_events, cb, Box::new(move || {
+                if let Some(state) = state.upgrade() {
+                    state.lock().unwrap().watches.remove(&watch);
+                }
+            }));
+            (Some(expiry), cb)
+        };
+        let (buffer, cb) = match backpressure {
+            None => (None, cb),
+            Some(ref backpressure) => {
+                let (buffer, cb) = Buffer::start(backpressure, cb);
+                (Some(buffer), cb)
+            }
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            let owner = options.iter().filter_map(|options| options.owner.clone()).next();
+            state.watches.insert(id.clone(), Watch {
+                info: WatchInfo {
+                    id: id.clone(),
+                    options: options,
+                    owner: owner,
+                    events: 0,
+                    dropped: 0,
+                    created: TimeStamp::now(),
+                },
+                cb: Arc::new(Mutex::new(cb)),
+                caller: caller.id.clone(),
+                delivered: HashMap::new(),
+                buffer: buffer,
+            });
+        }
+        if let Some(ref expiry) = expiry {
+            if max_events == Some(0) {
+                expiry.expire();
+            } else if let Some(duration) = expires_after {
+                Expiry::expire_after(expiry, &self.timer.handle(), duration.as_duration());
+            }
+        }
+        Ok(id)
+    }
+
+    fn tag_nodes(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &[TagId], add: bool) -> Result<TagResult<NodeId>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: tags }));
+        let (result, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.topology_snapshot();
+            let mut result = TagResult::new();
+            for node in &mut state.nodes {
+                if !set.iter().any(|selector| selector.matches(node)) {
+                    continue;
+                }
+                let changed = if add {
+                    add_tags(&mut node.tags, tags)
+                } else {
+                    remove_tags(&mut node.tags, tags)
+                };
+                if changed {
+                    result.changed.push(node.id.clone());
+                } else {
+                    result.unchanged.push(node.id.clone());
+                }
+            }
+            let deliveries = if result.changed.is_empty() {
+                vec![]
+            } else {
+                state.topology_changed(before)
+            };
+            (result, deliveries)
+        };
+        deliver(deliveries);
+        Ok(result)
+    }
+
+    fn tag_getters(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &[TagId], add: bool) -> Result<TagResult<Getter>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: tags }));
+        let (result, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.topology_snapshot();
+            let mut result = TagResult::new();
+            for node in &mut state.nodes {
+                for channel in &mut node.getters {
+                    if !set.iter().any(|selector| selector.matches(channel)) {
+                        continue;
+                    }
+                    let channel = Arc::make_mut(channel);
+                    let changed = if add {
+                        add_tags(&mut channel.tags, tags)
+                    } else {
+                        remove_tags(&mut channel.tags, tags)
+                    };
+                    if changed {
+                        result.changed.push(channel.id.clone());
+                    } else {
+                        result.unchanged.push(channel.id.clone());
+                    }
+                }
+            }
+            let deliveries = if result.changed.is_empty() {
+                vec![]
+            } else {
+                state.topology_changed(before)
+            };
+            (result, deliveries)
+        };
+        deliver(deliveries);
+        Ok(result)
+    }
+
+    fn tag_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &[TagId], add: bool) -> Result<TagResult<Setter>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: tags }));
+        let mut state = self.state.lock().unwrap();
+        let mut result = TagResult::new();
+        for node in &mut state.nodes {
+            for channel in &mut node.setters {
+                if !set.iter().any(|selector| selector.matches(channel)) {
+                    continue;
+                }
+                let channel = Arc::make_mut(channel);
+                let changed = if add {
+                    add_tags(&mut channel.tags, tags)
+                } else {
+                    remove_tags(&mut channel.tags, tags)
+                };
+                if changed {
+                    result.changed.push(channel.id.clone());
+                } else {
+                    result.unchanged.push(channel.id.clone());
+                }
+            }
+        }
+        if !result.changed.is_empty() {
+            state.revision += 1;
+        }
+        Ok(result)
+    }
+
+    fn label_nodes(&self, caller: &Caller, set: &Vec<NodeSelector>, label: Option<String>) -> Result<Vec<Id<NodeId>>, Error> {
+        try!(self.policy.check(caller, &Operation::Label { label: label.as_ref().map(|label| &**label) }));
+        let (ids, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ids = vec![];
+            for node in &mut state.nodes {
+                if set.iter().any(|selector| selector.matches(node)) {
+                    node.label = label.clone();
+                    ids.push(node.id.clone());
+                }
+            }
+            let mut deliveries = vec![];
+            for id in &ids {
+                deliveries.append(&mut state.label_changed(Labelled::Node(id.clone()), label.clone()));
+            }
+            if !ids.is_empty() {
+                state.revision += 1;
+            }
+            (ids, deliveries)
+        };
+        deliver(deliveries);
+        Ok(ids)
+    }
+
+    fn label_getters(&self, caller: &Caller, set: &Vec<GetterSelector>, label: Option<String>) -> Result<Vec<Id<Getter>>, Error> {
+        try!(self.policy.check(caller, &Operation::Label { label: label.as_ref().map(|label| &**label) }));
+        let (ids, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ids = vec![];
+            for node in &mut state.nodes {
+                for channel in &mut node.getters {
+                    if set.iter().any(|selector| selector.matches(channel)) {
+                        let channel = Arc::make_mut(channel);
+                        channel.label = label.clone();
+                        ids.push(channel.id.clone());
+                    }
+                }
+            }
+            let mut deliveries = vec![];
+            for id in &ids {
+                deliveries.append(&mut state.label_changed(Labelled::Getter(id.clone()), label.clone()));
+            }
+            if !ids.is_empty() {
+                state.revision += 1;
+            }
+            (ids, deliveries)
+        };
+        deliver(deliveries);
+        Ok(ids)
+    }
+
+    fn label_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, label: Option<String>) -> Result<Vec<Id<Setter>>, Error> {
+        try!(self.policy.check(caller, &Operation::Label { label: label.as_ref().map(|label| &**label) }));
+        let (ids, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ids = vec![];
+            for node in &mut state.nodes {
+                for channel in &mut node.setters {
+                    if set.iter().any(|selector| selector.matches(channel)) {
+                        let channel = Arc::make_mut(channel);
+                        channel.label = label.clone();
+                        ids.push(channel.id.clone());
+                    }
+                }
+            }
+            let mut deliveries = vec![];
+            for id in &ids {
+                deliveries.append(&mut state.label_changed(Labelled::Setter(id.clone()), label.clone()));
+            }
+            if !ids.is_empty() {
+                state.revision += 1;
+            }
+            (ids, deliveries)
+        };
+        deliver(deliveries);
+        Ok(ids)
+    }
+}
+
+/// Fail with `Error::Reserved` if `setter` is reserved by a caller
+/// other than `caller`.
+fn check_reservations(reservations: &HashMap<Id<ReservationId>, Reservation>, setter: &Id<Setter>, caller: &Id<CallerId>) -> Result<(), Error> {
+    let now = TimeStamp::now();
+    let reservation = reservations.values().find(|reservation| {
+        reservation.expires > now && reservation.holder != *caller && reservation.setters.contains(setter)
+    });
+    match reservation {
+        None => Ok(()),
+        Some(reservation) => Err(Error::Reserved {
+            setter: setter.clone(),
+            holder: reservation.holder.clone(),
+            expires: reservation.expires.clone(),
+        })
+    }
+}
+
+/// Send a value to the setters matching `selectors`, on behalf of
+/// `caller`. See `API::put_channel_value`.
+fn send_values(state: &Mutex<State>, policy: &PermissionPolicy, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value)
+               -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+    let mut state = state.lock().unwrap();
+    let state = &mut *state;
+    let reservations = &state.reservations;
+    let mut results = vec![];
+    let mut sent = vec![];
+    for node in &mut state.nodes {
+        for channel in &mut node.setters {
+            if !selectors.iter().any(|selector| selector.matches(channel)) {
+                continue;
+            }
+            let result = policy.check(caller, &Operation::Send {
+                setter: &channel.id,
+                value: &value
+            }).and_then(|_| {
+                check_reservations(reservations, &channel.id, &caller.id)
+            }).and_then(|_| {
+                if channel.mechanism.kind.get_type() == value.get_type() {
+                    Ok(Acknowledgement::sent())
+                } else {
+                    Err(Error::TypeError)
+                }
+            });
+            if let Ok(ref ack) = result {
+                Arc::make_mut(channel).mechanism.updated = Some(ack.timestamp.clone());
+                sent.push((channel.id.clone(), value.clone()));
+            }
+            results.push((channel.id.clone(), result));
+        }
+    }
+    state.sent.append(&mut sent);
+    results
+}
+
+impl Default for FakeAPI {
+    fn default() -> Self {
+        FakeAPI::new()
+    }
+}
+
+/// A handle on a watch registered with `FakeAPI`.
+pub struct FakeWatchGuard {
+    id: Id<WatchId>,
+    state: Arc<Mutex<State>>,
+    active: bool,
+}
+
+impl WatchGuard for FakeWatchGuard {
+    fn id(&self) -> &Id<WatchId> {
+        &self.id
+    }
+
+    fn unregister(&mut self) {
+        if self.active {
+            self.state.lock().unwrap().watches.remove(&self.id);
+            self.active = false;
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.active && self.state.lock().unwrap().watches.contains_key(&self.id)
+    }
+}
+
+impl Drop for FakeWatchGuard {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+impl API for FakeAPI {
+    type WatchGuard = FakeWatchGuard;
+
+    fn get_api_capabilities(&self, _: &Caller) -> Vec<Capability> {
+        vec![Capability::PollNow, Capability::TaggingRules,
+             Capability::WatchTokens, Capability::WatchIntrospection,
+             Capability::WatchBatching, Capability::WatchBackpressure,
+             Capability::WatchExpiration, Capability::WatchMinDelta,
+             Capability::ScheduledWrites, Capability::Reservations,
+             Capability::AggregateGetters]
+    }
+
+    fn get_supported_kinds(&self, _: &Caller) -> Vec<KindDescription> {
+        ChannelKind::standard_kinds()
+    }
+
+    fn get_nodes(&self, _: &Caller, selectors: &Vec<NodeSelector>) -> Vec<Arc<Node>> {
+        let state = self.state.lock().unwrap();
+        state.nodes.iter()
+            .filter(|node| selectors.iter().any(|selector| selector.matches(node)))
+            .map(|node| Arc::new(node.clone()))
+            .collect()
+    }
+
+    fn get_topology_revision(&self, _: &Caller) -> Revision {
+        Revision(self.state.lock().unwrap().revision)
+    }
+
+    fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>> {
+        let revision = self.get_topology_revision(caller);
+        if since == Some(revision) {
+            return Conditional::Unchanged(revision);
+        }
+        Conditional::Changed {
+            revision: revision,
+            data: self.get_nodes(caller, selectors),
+        }
+    }
+
+    fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error> {
+        try!(self.policy.check(caller, &Operation::ForgetNode { node: id }));
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let index = match state.nodes.iter().position(|node| node.id == *id) {
+                None => return Err(Error::NoSuchNode(id.clone())),
+                Some(index) => index
+            };
+            let before = state.topology_snapshot();
+            let node = state.nodes.remove(index);
+            for channel in &node.getters {
+                state.values.remove(&channel.id);
+                state.scripts.remove(&channel.id);
+                state.aggregates.remove(&channel.id);
+            }
+            let mut deliveries = state.topology_changed(before);
+            deliveries.append(&mut state.refresh_aggregates());
+            deliveries
+        };
+        deliver(deliveries);
+        Ok(())
+    }
+
+    fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error> {
+        self.tag_nodes(caller, set, tags, true)
+    }
+
+    fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error> {
+        self.tag_nodes(caller, set, &[tags], false)
+    }
+
+    fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: &rule.tags }));
+        let (id, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.topology_snapshot();
+            let id = state.new_id("rule");
+            state.tagging_rules.push((id.clone(), rule));
+            for index in 0..state.nodes.len() {
+                state.apply_tagging_rules(index);
+            }
+            (id, state.topology_changed(before))
+        };
+        deliver(deliveries);
+        Ok(id)
+    }
+
+    fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let index = match state.tagging_rules.iter().position(|&(ref rule, _)| rule == id) {
+            None => return Err(Error::NoSuchTaggingRule(id.clone())),
+            Some(index) => index
+        };
+        try!(self.policy.check(caller, &Operation::Tag { tags: &state.tagging_rules[index].1.tags }));
+        state.tagging_rules.remove(index);
+        Ok(())
+    }
+
+    fn get_tagging_rules(&self, _: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)> {
+        self.state.lock().unwrap().tagging_rules.clone()
+    }
+
+    fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error> {
+        self.label_nodes(caller, set, Some(label))
+    }
+
+    fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error> {
+        self.label_nodes(caller, set, None)
+    }
+
+    fn get_getter_channels(&self, _: &Caller, selectors: &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>> {
+        let state = self.state.lock().unwrap();
+        state.nodes.iter()
+            .flat_map(|node| node.getters.iter())
+            .filter(|channel| selectors.iter().any(|selector| selector.matches(channel)))
+            .cloned()
+            .collect()
+    }
+
+    fn get_setter_channels(&self, _: &Caller, selectors: &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>> {
+        let state = self.state.lock().unwrap();
+        state.nodes.iter()
+            .flat_map(|node| node.setters.iter())
+            .filter(|channel| selectors.iter().any(|selector| selector.matches(channel)))
+            .cloned()
+            .collect()
+    }
+
+    fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error> {
+        try!(self.policy.check(caller, &Operation::Aggregate { source: &aggregate.source }));
+        if !history::supports(&aggregate.kind, &aggregate.aggregation) {
+            return Err(Error::TypeError);
+        }
+        let (id, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.topology_snapshot();
+            let node: Id<NodeId> = Id::new("aggregate".to_owned());
+            if !state.nodes.iter().any(|existing| existing.id == node) {
+                state.nodes.push(Node::new(node.clone()).with_label("Aggregate channels".to_owned()));
+            }
+            let id: Id<Getter> = state.new_id("aggregate");
+            let kind = history::aggregate_kind(&aggregate.kind, &aggregate.aggregation);
+            let mut channel = Channel::new(id.clone(), node.clone(), Getter::new(kind).with_watch(true))
+                .with_tags(aggregate.tags.clone());
+            channel.label = aggregate.label.clone();
+            for existing in &mut state.nodes {
+                if existing.id == node {
+                    existing.getters.push(Arc::new(channel.clone()));
+                }
+            }
+            state.aggregates.insert(id.clone(), aggregate);
+            let mut deliveries = state.topology_changed(before);
+            deliveries.append(&mut state.refresh_aggregates());
+            (id, deliveries)
+        };
+        deliver(deliveries);
+        Ok(id)
+    }
+
+    fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error> {
+        let aggregate = match self.state.lock().unwrap().aggregates.get(id) {
+            None => return Err(Error::NoSuchGetter(id.clone())),
+            Some(aggregate) => aggregate.clone()
+        };
+        try!(self.policy.check(caller, &Operation::Aggregate { source: &aggregate.source }));
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let before = state.topology_snapshot();
+            state.aggregates.remove(id);
+            state.values.remove(id);
+            for node in &mut state.nodes {
+                node.getters.retain(|channel| channel.id != *id);
+            }
+            state.topology_changed(before)
+        };
+        deliver(deliveries);
+        Ok(())
+    }
+
+    fn get_aggregate_getters(&self, _: &Caller) -> Vec<(Id<Getter>, AggregateGetter)> {
+        let state = self.state.lock().unwrap();
+        state.aggregates.iter()
+            .map(|(id, aggregate)| (id.clone(), aggregate.clone()))
+            .collect()
+    }
+
+    fn put_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.tag_getters(caller, set, tags, true)
+    }
+
+    fn put_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.tag_setters(caller, set, tags, true)
+    }
+
+    fn delete_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.tag_getters(caller, set, tags, false)
+    }
+
+    fn delete_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.tag_setters(caller, set, tags, false)
+    }
+
+    fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error> {
+        self.label_getters(caller, set, Some(label))
+    }
+
+    fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error> {
+        self.label_setters(caller, set, Some(label))
+    }
+
+    fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error> {
+        self.label_getters(caller, set, None)
+    }
+
+    fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error> {
+        self.label_setters(caller, set, None)
+    }
+
+    /// Getter channels that have not produced any value yet are
+    /// omitted from the results.
+    fn get_channel_value(&self, _: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let mut results = vec![];
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let mut deliveries = vec![];
+            for id in state.getter_ids(selectors) {
+                if state.is_event(&id) {
+                    results.push((id.clone(), Err(Error::EventChannel(id))));
+                    continue;
+                }
+                let (fetched, mut events) = state.fetch(&id);
+                deliveries.append(&mut events);
+                if let Some(fetched) = fetched {
+                    results.push((id, Ok(fetched)));
+                }
+            }
+            deliveries
+        };
+        deliver(deliveries);
+        results
+    }
+
+    /// Getter channels that have not produced any value yet are
+    /// omitted from the results.
+    fn poll_now(&self, _: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let mut results = vec![];
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let mut deliveries = vec![];
+            let mut pollable = vec![];
+            for node in &state.nodes {
+                for channel in &node.getters {
+                    if selectors.iter().any(|selector| selector.matches(channel)) {
+                        pollable.push((channel.id.clone(), channel.mechanism.poll.is_some()));
+                    }
+                }
+            }
+            for (id, is_pollable) in pollable {
+                if state.is_event(&id) {
+                    results.push((id.clone(), Err(Error::EventChannel(id))));
+                    continue;
+                }
+                if !is_pollable {
+                    results.push((id.clone(), Err(Error::NotPollable(id))));
+                    continue;
+                }
+                let (fetched, mut events) = state.fetch(&id);
+                deliveries.append(&mut events);
+                if let Some(fetched) = fetched {
+                    results.push((id, Ok(FetchedValue {
+                        source: ValueSource::Live,
+                        ..fetched
+                    })));
+                }
+            }
+            deliveries
+        };
+        deliver(deliveries);
+        results
+    }
+
+    /// Priorities are ignored: writes are never suppressed.
+    fn put_channel_value(&self, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, _: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+        send_values(&self.state, &**self.policy, caller, selectors, value)
+    }
+
+    fn get_state_value(&self, _: &Caller, _: &Id<StateId>) -> Result<Option<FetchedValue>, Error> {
+        Err(Error::Unsupported(Capability::StateChannels))
+    }
+
+    fn put_state_value(&self, _: &Caller, _: &Id<StateId>, _: Value, _: Priority) -> Result<Acknowledgement, Error> {
+        Err(Error::Unsupported(Capability::StateChannels))
+    }
+
+    /// Writes are executed on the thread of the timer of the fake.
+    fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error> {
+        try!(self.policy.check(caller, &Operation::Schedule {
+            value: &value,
+            at: &at
+        }));
+        let delay = match at.duration_since(&TimeStamp::now()) {
+            Some(delay) => delay.as_duration(),
+            None => Duration::from_millis(0)
+        };
+        let mut state = self.state.lock().unwrap();
+        let id = state.new_id("schedule");
+        let task = {
+            let weak: Weak<Mutex<State>> = Arc::downgrade(&self.state);
+            let policy = self.policy.clone();
+            let id = id.clone();
+            // The task runs once the state has been unlocked, i.e. once
+            // the write has been recorded.
+            self.timer.handle().schedule(Instant::now() + delay, Box::new(move || {
+                let state = match weak.upgrade() {
+                    None => return,
+                    Some(state) => state
+                };
+                let scheduled = match state.lock().unwrap().schedules.remove(&id) {
+                    None => return,
+                    Some(scheduled) => scheduled
+                };
+                let Scheduled { write, caller, .. } = scheduled;
+                send_values(&state, &**policy, &caller, &write.set, write.value);
+            }))
+        };
+        state.schedules.insert(id.clone(), Scheduled {
+            write: ScheduledWrite {
+                id: id.clone(),
+                set: set.clone(),
+                value: value,
+                at: at,
+                owner: caller.id.clone(),
+            },
+            caller: caller.clone(),
+            task: task,
+        });
+        Ok(id)
+    }
+
+    fn get_scheduled_writes(&self, _: &Caller) -> Vec<ScheduledWrite> {
+        let state = self.state.lock().unwrap();
+        state.schedules.values().map(|scheduled| scheduled.write.clone()).collect()
+    }
+
+    fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        match state.schedules.get(id) {
+            None => return Err(Error::NoSuchSchedule(id.clone())),
+            Some(scheduled) if scheduled.write.owner != caller.id => return Err(Error::PermissionDenied(caller.id.clone())),
+            Some(_) => {}
+        }
+        if let Some(scheduled) = state.schedules.remove(id) {
+            self.timer.handle().cancel(scheduled.task);
+        }
+        Ok(())
+    }
+
+    fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_reservations();
+        let setters: Vec<_> = state.nodes.iter()
+            .flat_map(|node| node.setters.iter())
+            .filter(|channel| set.iter().any(|selector| selector.matches(channel)))
+            .map(|channel| channel.id.clone())
+            .collect();
+        try!(self.policy.check(caller, &Operation::Reserve { setters: &setters }));
+        for setter in &setters {
+            try!(check_reservations(&state.reservations, setter, &caller.id));
+        }
+        let reservation = Reservation {
+            id: state.new_id("reservation"),
+            holder: caller.id.clone(),
+            setters: setters,
+            expires: TimeStamp::now().plus(&duration),
+        };
+        state.reservations.insert(reservation.id.clone(), reservation.clone());
+        Ok(reservation)
+    }
+
+    fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_reservations();
+        match state.reservations.get(id) {
+            None => return Err(Error::NoSuchReservation(id.clone())),
+            Some(reservation) if reservation.holder != caller.id => return Err(Error::PermissionDenied(caller.id.clone())),
+            Some(_) => {}
+        }
+        state.reservations.remove(id);
+        Ok(())
+    }
+
+    fn get_reservations(&self, _: &Caller) -> Vec<Reservation> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_reservations();
+        state.reservations.values().cloned().collect()
+    }
+
+    fn put_polling_hint(&self, _: &Caller, _: &Vec<GetterSelector>, _: ValDuration, _: ValDuration) -> Result<PollingHint, Error> {
+        Err(Error::Unsupported(Capability::PollingHints))
+    }
+
+    fn delete_polling_hint(&self, _: &Caller, id: &Id<PollingHintId>) -> Result<(), Error> {
+        Err(Error::NoSuchPollingHint(id.clone()))
+    }
+
+    fn get_polling_hints(&self, _: &Caller) -> Vec<PollingHint> {
+        vec![]
+    }
+
+    fn register_channel_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error> {
+        let id = try!(self.register_watch(caller, options, cb));
+        Ok(FakeWatchGuard {
+            id: id,
+            state: self.state.clone(),
+            active: true,
+        })
+    }
+
+    fn register_channel_watch_with_token(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        self.register_watch(caller, options, cb)
+    }
+
+    fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        match state.watches.get(token) {
+            None => return Err(Error::NoSuchWatch(token.clone())),
+            Some(watch) => {
+                try!(self.policy.check(caller, &Operation::Watch { options: &watch.info.options }));
+                if watch.caller != caller.id {
+                    return Err(Error::PermissionDenied(caller.id.clone()));
+                }
+            }
+        }
+        state.watches.remove(token);
+        Ok(())
+    }
+
+    fn get_watches(&self, _: &Caller) -> Result<Vec<WatchInfo>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.watches.values().map(|watch| {
+            let mut info = watch.info.clone();
+            if let Some(ref buffer) = watch.buffer {
+                info.dropped = buffer.dropped();
+            }
+            info
+        }).collect())
+    }
+
+    fn get_adapter_health(&self, _: &Caller) -> Result<Vec<AdapterHealth>, Error> {
+        Err(Error::Unsupported(Capability::AdapterHealth))
+    }
+
+    fn get_metrics(&self, _: &Caller) -> Result<Vec<Metrics>, Error> {
+        Err(Error::Unsupported(Capability::Metrics))
+    }
+
+    fn get_adapter_config(&self, _: &Caller, _: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error> {
+        Err(Error::Unsupported(Capability::AdapterConfig))
+    }
+
+    fn put_adapter_config(&self, _: &Caller, _: &Id<AdapterId>, _: String, _: Value) -> Result<(), Error> {
+        Err(Error::Unsupported(Capability::AdapterConfig))
+    }
+
+    fn get_channel_values_since(&self, caller: &Caller, selectors: &Vec<GetterSelector>, _: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)> {
+        self.get_getter_channels(caller, selectors).into_iter()
+            .map(|channel| (channel.id.clone(), Err(Error::Unsupported(Capability::MultiSampleFetch))))
+            .collect()
+    }
+
+    fn get_channel_history(&self, _: &Caller, _: &Vec<GetterSelector>, _: Option<TimeStamp>, _: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error> {
+        Err(Error::Unsupported(Capability::History))
+    }
+
+    fn get_channel_history_aggregate(&self, _: &Caller, _: &Vec<GetterSelector>, _: Option<TimeStamp>, _: Option<TimeStamp>,
+                                     _: ValDuration, _: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error> {
+        Err(Error::Unsupported(Capability::History))
+    }
+
+    fn get_candidates(&self, _: &Caller) -> Result<Vec<Candidate>, Error> {
+        Err(Error::Unsupported(Capability::Discovery))
+    }
+
+    fn adopt_candidate(&self, _: &Caller, _: &Id<CandidateId>, _: Option<Id<AdapterId>>) -> Result<(), Error> {
+        Err(Error::Unsupported(Capability::Discovery))
+    }
+
+    fn reject_candidate(&self, _: &Caller, _: &Id<CandidateId>) -> Result<(), Error> {
+        Err(Error::Unsupported(Capability::Discovery))
+    }
+
+    fn register_durable_watch(&self, _: &Caller, _: String, _: Vec<WatchOptions>, _: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        Err(Error::Unsupported(Capability::DurableWatches))
+    }
+
+    fn resume_watch(&self, _: &Caller, _: &str, _: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        Err(Error::Unsupported(Capability::DurableWatches))
+    }
+}
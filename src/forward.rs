@@ -0,0 +1,289 @@
+//!
+//! Implementations of the API for smart pointers and references.
+//!
+//! These let several components, e.g. the REST frontend and the rules
+//! engine, share a single implementation of the API, without writing
+//! forwarding code for each method:
+//!
+//! ```ignore
+//! let api = Arc::new(manager);
+//! let rules = RulesEngine::new(api.clone());
+//! let frontend = Frontend::new(api);
+//! ```
+//!
+//! Every method, including the methods with a default implementation,
+//! is forwarded to the pointee, so that overrides are preserved.
+//!
+
+use api::*;
+use arbitration::Priority;
+use auth::Caller;
+use devices::*;
+use discovery::{Candidate, CandidateId};
+use history::{ Bucket, Sample };
+use metrics::Metrics;
+use selector::*;
+use values::{TimeStamp, Value, ValDuration};
+use util::{ Id, TagId };
+
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
+
+macro_rules! forward_api {
+    ([$($generics:tt)*] $ptr:ty) => {
+        impl<$($generics)*> API for $ptr where T: API + ?Sized {
+            type WatchGuard = T::WatchGuard;
+
+            fn get_api_capabilities(&self, caller: &Caller) -> Vec<Capability> {
+                (**self).get_api_capabilities(caller)
+            }
+
+            fn get_supported_kinds(&self, caller: &Caller) -> Vec<KindDescription> {
+                (**self).get_supported_kinds(caller)
+            }
+
+            fn get_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>) -> Vec<Arc<Node>> {
+                (**self).get_nodes(caller, selectors)
+            }
+
+            fn get_topology_revision(&self, caller: &Caller) -> Revision {
+                (**self).get_topology_revision(caller)
+            }
+
+            fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>> {
+                (**self).get_nodes_if_changed(caller, selectors, since)
+            }
+
+            fn visit_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>, cb: &mut FnMut(&Node)) {
+                (**self).visit_nodes(caller, selectors, cb)
+            }
+
+            fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error> {
+                (**self).forget_node(caller, id)
+            }
+
+            fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error> {
+                (**self).put_node_tag(caller, set, tags)
+            }
+
+            fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error> {
+                (**self).delete_node_tag(caller, set, tags)
+            }
+
+            fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error> {
+                (**self).put_tagging_rule(caller, rule)
+            }
+
+            fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error> {
+                (**self).delete_tagging_rule(caller, id)
+            }
+
+            fn get_tagging_rules(&self, caller: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)> {
+                (**self).get_tagging_rules(caller)
+            }
+
+            fn get_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>> {
+                (**self).get_getter_channels(caller, selectors)
+            }
+
+            fn get_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>> {
+                (**self).get_setter_channels(caller, selectors)
+            }
+
+            fn visit_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>, cb: &mut FnMut(&Channel<Getter>)) {
+                (**self).visit_getter_channels(caller, selectors, cb)
+            }
+
+            fn visit_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>, cb: &mut FnMut(&Channel<Setter>)) {
+                (**self).visit_setter_channels(caller, selectors, cb)
+            }
+
+            fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error> {
+                (**self).put_aggregate_getter(caller, aggregate)
+            }
+
+            fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error> {
+                (**self).delete_aggregate_getter(caller, id)
+            }
+
+            fn get_aggregate_getters(&self, caller: &Caller) -> Vec<(Id<Getter>, AggregateGetter)> {
+                (**self).get_aggregate_getters(caller)
+            }
+
+            fn put_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+                (**self).put_getter_tag(caller, set, tags)
+            }
+
+            fn put_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+                (**self).put_setter_tag(caller, set, tags)
+            }
+
+            fn delete_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+                (**self).delete_getter_tag(caller, set, tags)
+            }
+
+            fn delete_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+                (**self).delete_setter_tag(caller, set, tags)
+            }
+
+            fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error> {
+                (**self).set_node_label(caller, set, label)
+            }
+
+            fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error> {
+                (**self).clear_node_label(caller, set)
+            }
+
+            fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error> {
+                (**self).set_getter_label(caller, set, label)
+            }
+
+            fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error> {
+                (**self).set_setter_label(caller, set, label)
+            }
+
+            fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error> {
+                (**self).clear_getter_label(caller, set)
+            }
+
+            fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error> {
+                (**self).clear_setter_label(caller, set)
+            }
+
+            fn get_channel_value(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+                (**self).get_channel_value(caller, selectors)
+            }
+
+            fn poll_now(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+                (**self).poll_now(caller, selectors)
+            }
+
+            fn get_channel_values_since(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)> {
+                (**self).get_channel_values_since(caller, selectors, since)
+            }
+
+            fn put_channel_value(&self, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+                (**self).put_channel_value(caller, selectors, value, priority)
+            }
+
+            fn get_state_value(&self, caller: &Caller, id: &Id<StateId>) -> Result<Option<FetchedValue>, Error> {
+                (**self).get_state_value(caller, id)
+            }
+
+            fn put_state_value(&self, caller: &Caller, id: &Id<StateId>, value: Value, priority: Priority) -> Result<Acknowledgement, Error> {
+                (**self).put_state_value(caller, id, value, priority)
+            }
+
+            fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error> {
+                (**self).put_channel_value_at(caller, set, value, at)
+            }
+
+            fn put_channel_value_after(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, delay: ValDuration) -> Result<Id<ScheduleId>, Error> {
+                (**self).put_channel_value_after(caller, set, value, delay)
+            }
+
+            fn get_scheduled_writes(&self, caller: &Caller) -> Vec<ScheduledWrite> {
+                (**self).get_scheduled_writes(caller)
+            }
+
+            fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error> {
+                (**self).cancel_scheduled_write(caller, id)
+            }
+
+            fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error> {
+                (**self).reserve_setters(caller, set, duration)
+            }
+
+            fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error> {
+                (**self).release_reservation(caller, id)
+            }
+
+            fn get_reservations(&self, caller: &Caller) -> Vec<Reservation> {
+                (**self).get_reservations(caller)
+            }
+
+            fn put_polling_hint(&self, caller: &Caller, source: &Vec<GetterSelector>, interval: ValDuration, duration: ValDuration) -> Result<PollingHint, Error> {
+                (**self).put_polling_hint(caller, source, interval, duration)
+            }
+
+            fn delete_polling_hint(&self, caller: &Caller, id: &Id<PollingHintId>) -> Result<(), Error> {
+                (**self).delete_polling_hint(caller, id)
+            }
+
+            fn get_polling_hints(&self, caller: &Caller) -> Vec<PollingHint> {
+                (**self).get_polling_hints(caller)
+            }
+
+            fn register_channel_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error> {
+                (**self).register_channel_watch(caller, options, cb)
+            }
+
+            fn register_channel_watch_channel(&self, caller: &Caller, options: Vec<WatchOptions>) -> Result<(Receiver<WatchEvent>, Self::WatchGuard), Error> {
+                (**self).register_channel_watch_channel(caller, options)
+            }
+
+            fn register_channel_watch_with_token(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+                (**self).register_channel_watch_with_token(caller, options, cb)
+            }
+
+            fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error> {
+                (**self).unregister_watch(caller, token)
+            }
+
+            fn get_watches(&self, caller: &Caller) -> Result<Vec<WatchInfo>, Error> {
+                (**self).get_watches(caller)
+            }
+
+            fn get_adapter_health(&self, caller: &Caller) -> Result<Vec<AdapterHealth>, Error> {
+                (**self).get_adapter_health(caller)
+            }
+
+            fn get_metrics(&self, caller: &Caller) -> Result<Vec<Metrics>, Error> {
+                (**self).get_metrics(caller)
+            }
+
+            fn get_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error> {
+                (**self).get_adapter_config(caller, adapter)
+            }
+
+            fn put_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error> {
+                (**self).put_adapter_config(caller, adapter, key, value)
+            }
+
+            fn get_channel_history(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error> {
+                (**self).get_channel_history(caller, selectors, since, until)
+            }
+
+            fn get_channel_history_aggregate(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>,
+                                             bucket: ValDuration, aggregation: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error> {
+                (**self).get_channel_history_aggregate(caller, selectors, since, until, bucket, aggregation)
+            }
+
+            fn get_candidates(&self, caller: &Caller) -> Result<Vec<Candidate>, Error> {
+                (**self).get_candidates(caller)
+            }
+
+            fn adopt_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>, adapter: Option<Id<AdapterId>>) -> Result<(), Error> {
+                (**self).adopt_candidate(caller, candidate, adapter)
+            }
+
+            fn reject_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>) -> Result<(), Error> {
+                (**self).reject_candidate(caller, candidate)
+            }
+
+            fn register_durable_watch(&self, caller: &Caller, owner: String, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+                (**self).register_durable_watch(caller, owner, options, cb)
+            }
+
+            fn resume_watch(&self, caller: &Caller, owner: &str, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+                (**self).resume_watch(caller, owner, cb)
+            }
+        }
+    }
+}
+
+// `Arc<T>` and `&T` may only be sent between threads if `T` may be
+// shared between threads, hence the additional bound.
+forward_api!([T: Sync] Arc<T>);
+forward_api!(['a, T: Sync] &'a T);
+forward_api!([T] Box<T>);
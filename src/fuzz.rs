@@ -0,0 +1,199 @@
+//!
+//! Entry points for fuzzing the parsing of untrusted input.
+//!
+//! Selectors, values, watch options, WebSocket messages, compressed
+//! data and multipart bodies arrive directly from REST and WebSocket
+//! clients, which cannot be trusted. A panic while parsing them, or
+//! while using what has been parsed, takes down the thread serving the
+//! request, and possibly the FoxBox, so it is a denial of service.
+//! Each function of this module parses arbitrary bytes the way a
+//! frontend does, then exercises the result, e.g. by matching a parsed
+//! selector against a node. None of them may panic, whatever the input.
+//!
+//! The targets of `cargo fuzz`, in directory `fuzz`, call these
+//! functions. This module is only available with feature `fuzzing`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! cargo fuzz run selector
+//! ```
+//!
+
+use api::{ WatchEvent, WatchOptions };
+use body;
+use compression::{ self, Algorithm, Compressed };
+use devices::{ Channel, ChannelKind, Getter, Node, NodeId, Setter };
+use selector::{ GetterSelector, NodeSelector, SetterSelector };
+use sse::EventStream;
+use util::{ Id, TagId };
+use values::{ Bytes, Range, Value };
+use websocket::{ ClientMessage, Session };
+
+use serde::de::Deserialize;
+use serde_json;
+
+use std::str;
+use std::sync::Arc;
+
+/// Parse `data` as the JSON representation of a `T`, as frontends do
+/// with the bodies of requests.
+fn parse<T>(data: &[u8]) -> Option<T> where T: Deserialize {
+    str::from_utf8(data).ok().and_then(|source| serde_json::from_str(source).ok())
+}
+
+/// A node against which parsed selectors are matched.
+fn sample_node() -> Node {
+    let id: Id<NodeId> = Id::new("fuzz@foxlink.mozilla.com/node".to_owned());
+    let kitchen = TagId::new("kitchen").unwrap();
+    Node::new(id.clone())
+        .with_tags(vec![kitchen.clone()])
+        .with_getter(Channel::new(Id::new("fuzz@foxlink.mozilla.com/node/getter".to_owned()), id.clone(),
+                                  Getter::new(ChannelKind::OnOff))
+            .with_tags(vec![kitchen.clone()]))
+        .with_setter(Channel::new(Id::new("fuzz@foxlink.mozilla.com/node/setter".to_owned()), id.clone(),
+                                  Setter::new(ChannelKind::OnOff)))
+}
+
+/// Parse a node, getter or setter selector, then match it against a
+/// node and combine it with itself.
+pub fn fuzz_deserialize_selector(data: &[u8]) {
+    let node = sample_node();
+    if let Some(selector) = parse::<NodeSelector>(data) {
+        selector.matches(&node);
+        selector.clone().and(selector).matches(&node);
+    }
+    if let Some(selector) = parse::<GetterSelector>(data) {
+        for channel in &node.getters {
+            selector.matches(channel);
+        }
+        selector.clone().and(selector);
+    }
+    if let Some(selector) = parse::<SetterSelector>(data) {
+        for channel in &node.setters {
+            selector.matches(channel);
+        }
+        selector.clone().and(selector);
+    }
+}
+
+/// Parse a value, then compare it with itself and serialize it again.
+pub fn fuzz_deserialize_value(data: &[u8]) {
+    if let Some(value) = parse::<Value>(data) {
+        value.get_type();
+        let _ = value.partial_cmp(&value);
+        let _ = serde_json::to_string(&value);
+    }
+}
+
+/// Parse a range, then check whether it contains its own bounds.
+pub fn fuzz_deserialize_range(data: &[u8]) {
+    if let Some(range) = parse::<Range>(data) {
+        let _ = range.get_type();
+        let bounds = match range {
+            Range::Leq(ref value) | Range::Geq(ref value) | Range::Eq(ref value) => vec![value],
+            Range::BetweenEq { ref min, ref max } | Range::OutOfStrict { ref min, ref max } => vec![min, max],
+        };
+        for value in bounds {
+            range.contains(value);
+        }
+    }
+}
+
+/// Parse the options of a watch, as sent to `API::register_channel_watch`.
+pub fn fuzz_deserialize_watch_options(data: &[u8]) {
+    if let Some(options) = parse::<Vec<WatchOptions>>(data) {
+        let node = sample_node();
+        for options in &options {
+            for channel in &node.getters {
+                options.source.matches(channel);
+            }
+        }
+    }
+}
+
+/// Feed a message to a WebSocket session, twice, so that the second
+/// message is checked against the pending requests. Resumes are
+/// answered with a subscription holding a few events.
+pub fn fuzz_websocket_message(data: &[u8]) {
+    if let Ok(text) = str::from_utf8(data) {
+        let mut session = Session::new().with_replay(4);
+        if let Ok(ClientMessage::Resume { request, subscription, sequence }) = session.receive(text) {
+            let mut previous = Session::new().with_replay(4);
+            previous.subscribed(&request, subscription.clone());
+            for missed in 0..8 {
+                previous.event(&subscription, WatchEvent::Missed(missed));
+            }
+            for held in previous.into_subscriptions() {
+                let _ = session.resumed(&request, held, sequence);
+            }
+        }
+        let _ = session.receive(text);
+    }
+}
+
+/// Resume a stream of Server-Sent Events with an arbitrary header
+/// `Last-Event-ID`.
+pub fn fuzz_sse_last_event_id(data: &[u8]) {
+    if let Ok(last_event_id) = str::from_utf8(data) {
+        EventStream::new(16).resume(last_event_id);
+    }
+}
+
+/// Decompress arbitrary data, both as a DEFLATE stream and as a gzip
+/// member, as the manager does with the compressed values sent by
+/// clients. The first 8 bytes of `data`, if any, are the size announced
+/// by the envelope, in little-endian order, as clients choose it too.
+pub fn fuzz_decompress(data: &[u8]) {
+    let (original_size, data) = if data.len() >= 8 {
+        ((0..8).fold(0, |size, i| size | (data[i] as u64) << (8 * i)), &data[8..])
+    } else {
+        (0, data)
+    };
+    let _ = compression::inflate(data, compression::MAX_DECOMPRESSED_SIZE);
+    let _ = compression::gunzip(data, compression::MAX_DECOMPRESSED_SIZE);
+    for &algorithm in &[Algorithm::Deflate, Algorithm::Gzip] {
+        let _ = Compressed {
+            algorithm: algorithm,
+            original_size: original_size,
+            mimetype: Some("application/octet-stream".to_owned()),
+            data: Bytes(Arc::new(data.to_vec())),
+        }.decompress();
+    }
+}
+
+/// Decode a multipart body, with the first line of `data` as its
+/// `Content-Type`, then encode the parts back, as a frontend does when
+/// clients upload binary values. The first line is also negotiated as
+/// an `Accept` header.
+pub fn fuzz_multipart(data: &[u8]) {
+    let split = data.iter().position(|&byte| byte == b'\n').unwrap_or(data.len());
+    let content_type = match str::from_utf8(&data[..split]) {
+        Ok(content_type) => content_type,
+        Err(_) => return
+    };
+    let rest = if split < data.len() { &data[split + 1..] } else { &[] };
+    if let Ok(parts) = body::from_multipart(content_type, rest) {
+        let _ = body::to_multipart(&parts);
+    }
+    let _ = body::negotiate(content_type, &[Some("image/png")]);
+}
+
+/// Parse a GraphQL query. Only available with feature `graphql`.
+#[cfg(feature = "graphql")]
+pub fn fuzz_graphql_query(data: &[u8]) {
+    if let Ok(query) = str::from_utf8(data) {
+        let _ = ::graphql::parse(query);
+    }
+}
+
+/// Decode a value, a selector and a watch event from Protocol Buffers.
+/// Only available with feature `protobuf`.
+#[cfg(feature = "protobuf")]
+pub fn fuzz_decode_protobuf(data: &[u8]) {
+    use protobuf::Message;
+
+    let _ = Value::from_bytes(data);
+    let _ = NodeSelector::from_bytes(data);
+    let _ = WatchEvent::from_bytes(data);
+}
@@ -0,0 +1,538 @@
+//!
+//! A GraphQL view of the topology.
+//!
+//! `API::get_nodes` returns nodes along with all their channels, which
+//! is more than most clients need. This module exposes nodes, channels
+//! and their tags as a GraphQL schema (see `SCHEMA`), so that clients
+//! can fetch exactly the fields they need:
+//!
+//! ```ignore
+//! {
+//!   nodes(tags: ["kitchen"]) {
+//!     id
+//!     label
+//!     getters { id kind value }
+//!   }
+//! }
+//! ```
+//!
+//! Queries are resolved by `execute`, on top of any implementation of
+//! the API. Only a subset of GraphQL is supported: a single query, with
+//! fields, aliases and literal arguments. Fragments, variables,
+//! directives and mutations are rejected with `GraphQLError::Syntax`.
+//!
+//! This module is only available with feature `graphql`.
+//!
+
+use api::API;
+use auth::Caller;
+use devices::*;
+use selector::*;
+use util::{ Id, TagId };
+
+use serde_json;
+use serde_json::value::Value as JSON;
+
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// The schema of the queries accepted by `execute`, in the GraphQL
+/// schema language.
+pub const SCHEMA: &'static str = r#"
+# Any value that can be represented as JSON, e.g. a `ChannelKind`,
+# a `ValDuration` or a `FetchedValue`.
+scalar JSON
+
+type Query {
+  # The current revision of the topology.
+  revision: Int!
+
+  nodes(id: ID, tags: [String!]): [Node!]!
+  getters(id: ID, parent: ID, tags: [String!]): [Getter!]!
+  setters(id: ID, parent: ID, tags: [String!]): [Setter!]!
+}
+
+type Node {
+  id: ID!
+  label: String
+  tags: [String!]!
+  getters: [Getter!]!
+  setters: [Setter!]!
+}
+
+type Getter {
+  id: ID!
+  node: ID!
+  label: String
+  tags: [String!]!
+  kind: JSON!
+  semantics: JSON!
+  poll: JSON
+  trigger: JSON
+  watch: Boolean!
+  updated: JSON
+  last_seen: JSON
+
+  # The latest value, as `API::get_channel_value`.
+  value: JSON
+}
+
+type Setter {
+  id: ID!
+  node: ID!
+  label: String
+  tags: [String!]!
+  kind: JSON!
+  push: JSON
+  updated: JSON
+  last_seen: JSON
+}
+
+schema {
+  query: Query
+}
+"#;
+
+/// An error while parsing or executing a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphQLError {
+    /// The query is malformed, or uses a feature of GraphQL that is not
+    /// supported. Payload is a human-readable description.
+    Syntax(String),
+
+    /// The type has no such field.
+    UnknownField {
+        typ: &'static str,
+        field: String,
+    },
+
+    /// The field has no such argument, or the argument has the wrong type.
+    InvalidArgument {
+        field: String,
+        argument: String,
+    },
+}
+
+/// A literal argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Argument {
+    String(String),
+    Int(i64),
+    Bool(bool),
+    List(Vec<Argument>),
+}
+
+/// A field of a selection set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// If `Some(alias)`, the key under which the result is reported.
+    pub alias: Option<String>,
+    pub name: String,
+    pub arguments: Vec<(String, Argument)>,
+
+    /// The subfields to fetch. Empty for scalar fields.
+    pub selection: Vec<Field>,
+}
+
+impl Field {
+    /// The key under which the result is reported.
+    pub fn key(&self) -> &str {
+        match self.alias {
+            Some(ref alias) => alias,
+            None => &self.name
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Name(String),
+    String(String),
+    Int(i64),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, GraphQLError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            // Commas are insignificant in GraphQL.
+            ' ' | '\t' | '\n' | '\r' | ',' => {}
+            '#' => {
+                while let Some(c) = chars.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '!' => tokens.push(Token::Punctuator(c)),
+            '"' => tokens.push(Token::String(try!(tokenize_string(&mut chars)))),
+            '-' | '0'...'9' => {
+                let mut digits = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !c.is_digit(10) {
+                        break;
+                    }
+                    digits.push(c);
+                    chars.next();
+                }
+                match digits.parse() {
+                    Ok(int) => tokens.push(Token::Int(int)),
+                    Err(_) => return Err(GraphQLError::Syntax(format!("Invalid number {}", digits)))
+                }
+            }
+            'a'...'z' | 'A'...'Z' | '_' => {
+                let mut name = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+            '$' => return Err(GraphQLError::Syntax("Variables are not supported".to_owned())),
+            '@' => return Err(GraphQLError::Syntax("Directives are not supported".to_owned())),
+            '.' => return Err(GraphQLError::Syntax("Fragments are not supported".to_owned())),
+            _ => return Err(GraphQLError::Syntax(format!("Unexpected character {}", c)))
+        }
+    }
+    Ok(tokens)
+}
+
+fn tokenize_string(chars: &mut Peekable<Chars>) -> Result<String, GraphQLError> {
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            None | Some('\n') => return Err(GraphQLError::Syntax("Unterminated string".to_owned())),
+            Some('"') => return Ok(string),
+            Some('\\') => match chars.next() {
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                Some('/') => string.push('/'),
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                _ => return Err(GraphQLError::Syntax("Unsupported escape sequence".to_owned()))
+            },
+            Some(c) => string.push(c)
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, GraphQLError> {
+        match self.tokens.get(self.pos).cloned() {
+            None => Err(GraphQLError::Syntax("Unexpected end of query".to_owned())),
+            Some(token) => {
+                self.pos += 1;
+                Ok(token)
+            }
+        }
+    }
+
+    fn expect(&mut self, punctuator: char) -> Result<(), GraphQLError> {
+        match try!(self.next()) {
+            Token::Punctuator(c) if c == punctuator => Ok(()),
+            token => Err(GraphQLError::Syntax(format!("Expected {}, found {:?}", punctuator, token)))
+        }
+    }
+
+    fn is_next(&self, punctuator: char) -> bool {
+        self.peek() == Some(&Token::Punctuator(punctuator))
+    }
+
+    fn name(&mut self) -> Result<String, GraphQLError> {
+        match try!(self.next()) {
+            Token::Name(name) => Ok(name),
+            token => Err(GraphQLError::Syntax(format!("Expected a name, found {:?}", token)))
+        }
+    }
+
+    fn document(&mut self) -> Result<Vec<Field>, GraphQLError> {
+        match self.peek().cloned() {
+            Some(Token::Name(ref keyword)) if keyword == "query" => {
+                self.pos += 1;
+                if let Some(&Token::Name(_)) = self.peek() {
+                    self.pos += 1;
+                }
+            }
+            Some(Token::Name(keyword)) => {
+                return Err(GraphQLError::Syntax(format!("Unsupported operation {}", keyword)))
+            }
+            _ => {}
+        }
+        let selection = try!(self.selection_set());
+        if self.pos != self.tokens.len() {
+            return Err(GraphQLError::Syntax("Only a single query is supported".to_owned()));
+        }
+        Ok(selection)
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Field>, GraphQLError> {
+        try!(self.expect('{'));
+        let mut fields = vec![];
+        while !self.is_next('}') {
+            fields.push(try!(self.field()));
+        }
+        try!(self.expect('}'));
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, GraphQLError> {
+        let mut alias = None;
+        let mut name = try!(self.name());
+        if self.is_next(':') {
+            self.pos += 1;
+            alias = Some(name);
+            name = try!(self.name());
+        }
+        let mut arguments = vec![];
+        if self.is_next('(') {
+            self.pos += 1;
+            while !self.is_next(')') {
+                let argument = try!(self.name());
+                try!(self.expect(':'));
+                arguments.push((argument, try!(self.value())));
+            }
+            try!(self.expect(')'));
+        }
+        let selection = if self.is_next('{') {
+            try!(self.selection_set())
+        } else {
+            vec![]
+        };
+        Ok(Field {
+            alias: alias,
+            name: name,
+            arguments: arguments,
+            selection: selection,
+        })
+    }
+
+    fn value(&mut self) -> Result<Argument, GraphQLError> {
+        match try!(self.next()) {
+            Token::String(string) => Ok(Argument::String(string)),
+            Token::Int(int) => Ok(Argument::Int(int)),
+            Token::Name(ref name) if name == "true" => Ok(Argument::Bool(true)),
+            Token::Name(ref name) if name == "false" => Ok(Argument::Bool(false)),
+            Token::Punctuator('[') => {
+                let mut values = vec![];
+                while !self.is_next(']') {
+                    values.push(try!(self.value()));
+                }
+                try!(self.expect(']'));
+                Ok(Argument::List(values))
+            }
+            token => Err(GraphQLError::Syntax(format!("Expected a value, found {:?}", token)))
+        }
+    }
+}
+
+/// Parse a query into its selection set.
+pub fn parse(query: &str) -> Result<Vec<Field>, GraphQLError> {
+    let mut parser = Parser {
+        tokens: try!(tokenize(query)),
+        pos: 0,
+    };
+    parser.document()
+}
+
+fn invalid_argument(field: &Field, argument: &str) -> GraphQLError {
+    GraphQLError::InvalidArgument {
+        field: field.name.clone(),
+        argument: argument.to_owned(),
+    }
+}
+
+fn string_argument(field: &Field, argument: &str, value: &Argument) -> Result<String, GraphQLError> {
+    match *value {
+        Argument::String(ref string) => Ok(string.clone()),
+        _ => Err(invalid_argument(field, argument))
+    }
+}
+
+fn tags_argument(field: &Field, argument: &str, value: &Argument) -> Result<Vec<TagId>, GraphQLError> {
+    let tag = |string: &str| TagId::new(string).map_err(|_| invalid_argument(field, argument));
+    match *value {
+        Argument::List(ref values) => values.iter()
+            .map(|value| string_argument(field, argument, value).and_then(|string| tag(&string)))
+            .collect(),
+        // As per GraphQL input coercion, a single value stands for a
+        // list of one value.
+        Argument::String(ref string) => Ok(vec![try!(tag(string))]),
+        _ => Err(invalid_argument(field, argument))
+    }
+}
+
+/// A field without subfields.
+fn scalar(field: &Field, value: JSON) -> Result<JSON, GraphQLError> {
+    if field.selection.is_empty() {
+        Ok(value)
+    } else {
+        Err(GraphQLError::Syntax(format!("Field {} does not have subfields", field.name)))
+    }
+}
+
+fn unknown_field(typ: &'static str, field: &Field) -> GraphQLError {
+    GraphQLError::UnknownField {
+        typ: typ,
+        field: field.name.clone(),
+    }
+}
+
+fn resolve_node<A>(api: &A, caller: &Caller, node: &Node, selection: &[Field]) -> Result<JSON, GraphQLError> where A: API + ?Sized {
+    let mut result = BTreeMap::new();
+    for field in selection {
+        let value = match &*field.name {
+            "id" => try!(scalar(field, serde_json::to_value(&node.id))),
+            "label" => try!(scalar(field, serde_json::to_value(&node.label))),
+            "tags" => try!(scalar(field, serde_json::to_value(&node.tags))),
+            "getters" => JSON::Array(try!(node.getters.iter()
+                .map(|channel| resolve_getter(api, caller, channel, &field.selection))
+                .collect())),
+            "setters" => JSON::Array(try!(node.setters.iter()
+                .map(|channel| resolve_setter(channel, &field.selection))
+                .collect())),
+            _ => return Err(unknown_field("Node", field))
+        };
+        result.insert(field.key().to_owned(), value);
+    }
+    Ok(JSON::Object(result))
+}
+
+fn resolve_getter<A>(api: &A, caller: &Caller, channel: &Channel<Getter>, selection: &[Field]) -> Result<JSON, GraphQLError> where A: API + ?Sized {
+    let mut result = BTreeMap::new();
+    for field in selection {
+        let value = match &*field.name {
+            "id" => serde_json::to_value(&channel.id),
+            "node" => serde_json::to_value(&channel.node),
+            "label" => serde_json::to_value(&channel.label),
+            "tags" => serde_json::to_value(&channel.tags),
+            "kind" => serde_json::to_value(&channel.mechanism.kind),
+            "semantics" => serde_json::to_value(&channel.mechanism.semantics),
+            "poll" => serde_json::to_value(&channel.mechanism.poll),
+            "trigger" => serde_json::to_value(&channel.mechanism.trigger),
+            "watch" => serde_json::to_value(&channel.mechanism.watch),
+            "updated" => serde_json::to_value(&channel.mechanism.updated),
+            "last_seen" => serde_json::to_value(&channel.last_seen),
+            "value" => {
+                let selector = GetterSelector::new().with_id(channel.id.clone());
+                match api.get_channel_value(caller, &vec![selector]).into_iter().next() {
+                    Some((_, Ok(fetched))) => serde_json::to_value(&fetched),
+                    _ => JSON::Null
+                }
+            }
+            _ => return Err(unknown_field("Getter", field))
+        };
+        result.insert(field.key().to_owned(), try!(scalar(field, value)));
+    }
+    Ok(JSON::Object(result))
+}
+
+fn resolve_setter(channel: &Channel<Setter>, selection: &[Field]) -> Result<JSON, GraphQLError> {
+    let mut result = BTreeMap::new();
+    for field in selection {
+        let value = match &*field.name {
+            "id" => serde_json::to_value(&channel.id),
+            "node" => serde_json::to_value(&channel.node),
+            "label" => serde_json::to_value(&channel.label),
+            "tags" => serde_json::to_value(&channel.tags),
+            "kind" => serde_json::to_value(&channel.mechanism.kind),
+            "push" => serde_json::to_value(&channel.mechanism.push),
+            "updated" => serde_json::to_value(&channel.mechanism.updated),
+            "last_seen" => serde_json::to_value(&channel.last_seen),
+            _ => return Err(unknown_field("Setter", field))
+        };
+        result.insert(field.key().to_owned(), try!(scalar(field, value)));
+    }
+    Ok(JSON::Object(result))
+}
+
+fn node_selector(field: &Field) -> Result<NodeSelector, GraphQLError> {
+    let mut selector = NodeSelector::new();
+    for &(ref name, ref value) in &field.arguments {
+        selector = match &**name {
+            "id" => selector.with_id(Id::new(try!(string_argument(field, name, value)))),
+            "tags" => selector.with_tags(try!(tags_argument(field, name, value))),
+            _ => return Err(invalid_argument(field, name))
+        }
+    }
+    Ok(selector)
+}
+
+fn getter_selector(field: &Field) -> Result<GetterSelector, GraphQLError> {
+    let mut selector = GetterSelector::new();
+    for &(ref name, ref value) in &field.arguments {
+        selector = match &**name {
+            "id" => selector.with_id(Id::new(try!(string_argument(field, name, value)))),
+            "parent" => selector.with_parent(Id::new(try!(string_argument(field, name, value)))),
+            "tags" => selector.with_tags(try!(tags_argument(field, name, value))),
+            _ => return Err(invalid_argument(field, name))
+        }
+    }
+    Ok(selector)
+}
+
+fn setter_selector(field: &Field) -> Result<SetterSelector, GraphQLError> {
+    let mut selector = SetterSelector::new();
+    for &(ref name, ref value) in &field.arguments {
+        selector = match &**name {
+            "id" => selector.with_id(Id::new(try!(string_argument(field, name, value)))),
+            "parent" => selector.with_parent(Id::new(try!(string_argument(field, name, value)))),
+            "tags" => selector.with_tags(try!(tags_argument(field, name, value))),
+            _ => return Err(invalid_argument(field, name))
+        }
+    }
+    Ok(selector)
+}
+
+/// Execute a query on behalf of `caller`.
+///
+/// On success, produce the object that frontends are expected to send
+/// as `{"data": result}`.
+pub fn execute<A>(api: &A, caller: &Caller, query: &str) -> Result<JSON, GraphQLError> where A: API + ?Sized {
+    let mut result = BTreeMap::new();
+    for field in try!(parse(query)) {
+        let value = match &*field.name {
+            "revision" => {
+                if !field.arguments.is_empty() {
+                    return Err(invalid_argument(&field, &field.arguments[0].0));
+                }
+                try!(scalar(&field, JSON::U64(api.get_topology_revision(caller).0)))
+            }
+            "nodes" => {
+                let selector = try!(node_selector(&field));
+                JSON::Array(try!(api.get_nodes(caller, &vec![selector]).iter()
+                    .map(|node| resolve_node(api, caller, node, &field.selection))
+                    .collect()))
+            }
+            "getters" => {
+                let selector = try!(getter_selector(&field));
+                JSON::Array(try!(api.get_getter_channels(caller, &vec![selector]).iter()
+                    .map(|channel| resolve_getter(api, caller, channel, &field.selection))
+                    .collect()))
+            }
+            "setters" => {
+                let selector = try!(setter_selector(&field));
+                JSON::Array(try!(api.get_setter_channels(caller, &vec![selector]).iter()
+                    .map(|channel| resolve_setter(channel, &field.selection))
+                    .collect()))
+            }
+            _ => return Err(unknown_field("Query", &field))
+        };
+        result.insert(field.key().to_owned(), value);
+    }
+    Ok(JSON::Object(result))
+}
@@ -0,0 +1,515 @@
+//!
+//! Recording the values produced by getters.
+//!
+//! A `HistoryStore` keeps samples of the values produced by getters,
+//! so that applications may plot e.g. the temperature of the last day
+//! with `API::get_channel_history`. The manager feeds the store with
+//! the values received through watches and polling, for the getters
+//! selected by a `HistoryPolicy`, and watches these getters for as long
+//! as the store is in use, see `manager::AdapterManager::with_history`.
+//!
+//! Each getter is subject to a `RetentionPolicy`, which bounds the age
+//! and number of samples kept and may downsample values.
+//!
+//! To draw e.g. a week-long chart without transferring every sample,
+//! applications may request with `API::get_channel_history_aggregate`
+//! the samples combined per time bucket, see `aggregate`.
+//!
+//! Two implementations are provided: `MemoryHistoryStore`, which keeps
+//! samples in ring buffers, and `SqliteHistoryStore`, which keeps them
+//! in a SQLite database and requires feature `sqlite`.
+//!
+//! The stores require feature `runtime`. Without it, this module only
+//! provides samples, buckets, policies and `aggregate`, e.g. for
+//! clients that aggregate samples by themselves.
+//!
+
+use api::Aggregation;
+use devices::{ Channel, ChannelKind, Getter };
+use selector::GetterSelector;
+#[cfg(feature = "runtime")]
+use tagstore::StoreError;
+use values::{ ExtNumeric, Temperature, TimeStamp, Type, ValDuration, Value };
+#[cfg(feature = "runtime")]
+use util::Id;
+
+#[cfg(feature = "runtime")]
+use std::collections::{ HashMap, VecDeque };
+use std::cmp::Ordering;
+#[cfg(feature = "runtime")]
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A value produced by a getter.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub timestamp: TimeStamp,
+    pub value: Value,
+}
+
+/// How long the samples of a getter are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Samples older than this are discarded.
+    #[serde(default)]
+    pub max_age: Option<ValDuration>,
+
+    /// Beyond this number of samples, the oldest ones are discarded.
+    #[serde(default)]
+    pub max_samples: Option<usize>,
+
+    /// If specified, time is divided into intervals of this duration,
+    /// and only the latest sample of each interval is kept, e.g. to keep
+    /// a single temperature per minute.
+    #[serde(default)]
+    pub downsample: Option<ValDuration>,
+}
+
+impl RetentionPolicy {
+    /// Keep all samples.
+    pub fn new() -> Self {
+        RetentionPolicy::default()
+    }
+
+    pub fn with_max_age(self, max_age: ValDuration) -> Self {
+        RetentionPolicy {
+            max_age: Some(max_age),
+            .. self
+        }
+    }
+
+    pub fn with_max_samples(self, max_samples: usize) -> Self {
+        RetentionPolicy {
+            max_samples: Some(max_samples),
+            .. self
+        }
+    }
+
+    pub fn with_downsample(self, interval: ValDuration) -> Self {
+        RetentionPolicy {
+            downsample: Some(interval),
+            .. self
+        }
+    }
+
+    /// The interval of `timestamp`, if samples are downsampled.
+    pub fn interval_of(&self, timestamp: &TimeStamp) -> Option<i64> {
+        match self.downsample {
+            Some(ref interval) if interval.as_ms() > 0 => {
+                Some(interval_index(timestamp, interval.as_ms() as i64))
+            }
+            _ => None
+        }
+    }
+
+    /// The timestamp before which samples are discarded, if any.
+    pub fn cutoff(&self, now: &TimeStamp) -> Option<i64> {
+        self.max_age.as_ref().map(|max_age| now.as_ms() - max_age.as_ms() as i64)
+    }
+}
+
+/// The index of the interval of `interval` ms containing `timestamp`,
+/// counting from January 1st, 1970, UTC.
+fn interval_index(timestamp: &TimeStamp, interval: i64) -> i64 {
+    let ms = timestamp.as_ms();
+    // Round towards negative infinity, so that intervals have the same
+    // length before 1970.
+    if ms >= 0 { ms / interval } else { (ms + 1) / interval - 1 }
+}
+
+/// A rule of a `HistoryPolicy`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryRule {
+    /// The getters to which the rule applies.
+    pub source: GetterSelector,
+
+    pub retention: RetentionPolicy,
+}
+
+/// Which getters are recorded, and for how long.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HistoryPolicy {
+    /// The rules, in order. A getter is recorded if it matches at least
+    /// one rule, following the retention policy of the first matching
+    /// rule.
+    pub rules: Vec<HistoryRule>,
+}
+
+impl HistoryPolicy {
+    /// Record nothing.
+    pub fn new() -> Self {
+        HistoryPolicy::default()
+    }
+
+    pub fn with_rule(mut self, source: GetterSelector, retention: RetentionPolicy) -> Self {
+        self.rules.push(HistoryRule {
+            source: source,
+            retention: retention,
+        });
+        self
+    }
+
+    /// The retention policy of `channel`, or `None` if it is not
+    /// recorded.
+    pub fn retention_of(&self, channel: &Channel<Getter>) -> Option<&RetentionPolicy> {
+        self.rules.iter()
+            .find(|rule| rule.source.matches(channel))
+            .map(|rule| &rule.retention)
+    }
+}
+
+/// The samples of a getter taken during a time bucket, combined.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// The start of the bucket. The bucket ends where the next one
+    /// starts.
+    pub start: TimeStamp,
+
+    /// The number of samples taken during the bucket. Never 0.
+    pub count: u64,
+
+    /// The combined value, or `None` for `Aggregation::Count` and for
+    /// values that cannot be combined, e.g. the mean of strings.
+    pub value: Option<Value>,
+}
+
+/// The magnitude of a numeric value, temperatures being in Celsius and
+/// durations in ms.
+fn magnitude(value: &Value) -> Option<f64> {
+    match *value {
+        Value::Temperature(ref temperature) => Some(temperature.as_c()),
+        Value::Duration(ref duration) => Some(duration.as_ms() as f64),
+        Value::Count(count) => Some(count as f64),
+        Value::ExtNumeric(ref ext) => Some(ext.value),
+        _ => None
+    }
+}
+
+/// A value of the same type as `template`, with magnitude `magnitude`.
+fn with_magnitude(template: &Value, magnitude: f64) -> Option<Value> {
+    match *template {
+        Value::Temperature(_) => Some(Value::Temperature(Temperature::C(magnitude))),
+        Value::Duration(_) if magnitude >= 0. =>
+            Some(Value::Duration(ValDuration::new(Duration::from_millis(magnitude.round() as u64)))),
+        Value::Count(_) if magnitude >= 0. && magnitude <= u32::max_value() as f64 =>
+            Some(Value::Count(magnitude.round() as u32)),
+        Value::ExtNumeric(ref ext) => Some(Value::ExtNumeric(ExtNumeric {
+            value: magnitude,
+            .. ext.clone()
+        })),
+        _ => None
+    }
+}
+
+/// Combine values, e.g. the samples of a bucket or the latest values of
+/// the sources of an aggregate getter. `values` must not be empty. See
+/// `aggregate` for the values supported by each aggregation.
+pub fn combine(values: &[&Value], aggregation: &Aggregation) -> Option<Value> {
+    let first = values[0];
+    let extremum = |wanted: Ordering| {
+        let mut best = first;
+        for value in &values[1..] {
+            match value.partial_cmp(&best) {
+                Some(ordering) if ordering == wanted => best = *value,
+                Some(_) => {}
+                None => return None
+            }
+        }
+        Some(best.clone())
+    };
+    let sum = || {
+        let mut sum = 0.;
+        for value in values {
+            // `partial_cmp` rejects `ExtNumeric` values of distinct
+            // vendors or kinds.
+            if value.get_type() != first.get_type() || value.partial_cmp(&first).is_none() {
+                return None;
+            }
+            sum += match magnitude(value) {
+                None => return None,
+                Some(magnitude) => magnitude
+            };
+        }
+        Some(sum)
+    };
+    match *aggregation {
+        Aggregation::Min => extremum(Ordering::Less),
+        Aggregation::Max => extremum(Ordering::Greater),
+        Aggregation::Mean => sum().and_then(|sum| with_magnitude(first, sum / values.len() as f64)),
+        Aggregation::Sum => sum().and_then(|sum| with_magnitude(first, sum)),
+        Aggregation::Count => None,
+    }
+}
+
+/// The kind of the values of an aggregate getter combining values of
+/// kind `kind`: `kind` itself, except with `Aggregation::Count`, which
+/// produces `ExtNumeric` counts.
+pub fn aggregate_kind(kind: &ChannelKind, aggregation: &Aggregation) -> ChannelKind {
+    match *aggregation {
+        Aggregation::Count => ChannelKind::Extension {
+            vendor: "foxlink@mozilla.com".to_owned(),
+            adapter: "aggregate".to_owned(),
+            kind: "count".to_owned(),
+            typ: Type::ExtNumeric,
+        },
+        _ => kind.clone()
+    }
+}
+
+/// Determine whether values of kind `kind` may be combined with
+/// `aggregation`. `Aggregation::Mean` and `Aggregation::Sum` require
+/// numeric values.
+pub fn supports(kind: &ChannelKind, aggregation: &Aggregation) -> bool {
+    match *aggregation {
+        Aggregation::Mean | Aggregation::Sum => match kind.get_type() {
+            Type::Temperature | Type::Duration | Type::Count | Type::ExtNumeric => true,
+            _ => false
+        },
+        _ => true
+    }
+}
+
+/// Combine the latest values of the sources of an aggregate getter,
+/// which may be empty. Unlike `combine`, `Aggregation::Count` counts the
+/// values, as a value of `aggregate_kind`.
+pub fn combine_latest(values: &[&Value], aggregation: &Aggregation) -> Option<Value> {
+    match *aggregation {
+        Aggregation::Count => Some(Value::ExtNumeric(ExtNumeric {
+            value: values.len() as f64,
+            vendor: "foxlink@mozilla.com".to_owned(),
+            adapter: "aggregate".to_owned(),
+            kind: "count".to_owned(),
+        })),
+        _ if values.is_empty() => None,
+        _ => combine(values, aggregation)
+    }
+}
+
+/// Divide time into buckets of duration `bucket`, starting on January
+/// 1st, 1970, UTC, then combine the samples of each bucket. Samples
+/// must be sorted oldest first. Buckets without samples are omitted.
+///
+/// `Aggregation::Min` and `Aggregation::Max` require values that are
+/// comparable with each other. `Aggregation::Mean` and
+/// `Aggregation::Sum` require numeric values, i.e. temperatures, which
+/// are combined in Celsius, durations, or `ExtNumeric` values of the
+/// same vendor and kind. `Aggregation::Count` only reports the number
+/// of samples.
+pub fn aggregate(samples: &[Sample], bucket: &ValDuration, aggregation: &Aggregation) -> Vec<Bucket> {
+    let interval = (bucket.as_ms() as i64).max(1);
+    let mut result = vec![];
+    let mut start = 0;
+    while start < samples.len() {
+        let index = interval_index(&samples[start].timestamp, interval);
+        let end = samples[start..].iter()
+            .position(|sample| interval_index(&sample.timestamp, interval) != index)
+            .map_or(samples.len(), |len| start + len);
+        let values: Vec<_> = samples[start..end].iter().map(|sample| &sample.value).collect();
+        result.push(Bucket {
+            start: TimeStamp::from_ms(index * interval).unwrap_or_else(|| samples[start].timestamp.clone()),
+            count: values.len() as u64,
+            value: combine(&values, aggregation),
+        });
+        start = end;
+    }
+    result
+}
+
+#[cfg(feature = "runtime")]
+/// Storage for the samples of getters.
+pub trait HistoryStore: Send + Sync {
+    /// Record a sample of a getter, then discard the samples that are
+    /// not retained by `retention`.
+    fn record(&self, getter: &Id<Getter>, sample: Sample, retention: &RetentionPolicy) -> Result<(), StoreError>;
+
+    /// The samples of a getter taken between `since` and `until`,
+    /// inclusive, oldest first.
+    fn get_samples(&self, getter: &Id<Getter>, since: Option<&TimeStamp>, until: Option<&TimeStamp>) -> Result<Vec<Sample>, StoreError>;
+
+    /// The samples of a getter taken between `since` and `until`,
+    /// inclusive, combined per bucket, see `aggregate`. By default,
+    /// samples are read with `get_samples`, then combined.
+    fn get_aggregate(&self, getter: &Id<Getter>, since: Option<&TimeStamp>, until: Option<&TimeStamp>,
+                     bucket: &ValDuration, aggregation: &Aggregation) -> Result<Vec<Bucket>, StoreError> {
+        let samples = try!(self.get_samples(getter, since, until));
+        Ok(aggregate(&samples, bucket, aggregation))
+    }
+
+    /// Discard all the samples of a getter.
+    fn forget(&self, getter: &Id<Getter>) -> Result<(), StoreError>;
+}
+
+#[cfg(feature = "runtime")]
+/// A `HistoryStore` keeping samples in memory, in a ring buffer for
+/// each getter. Samples are lost when the FoxBox restarts.
+pub struct MemoryHistoryStore {
+    capacity: usize,
+    samples: Mutex<HashMap<Id<Getter>, VecDeque<Sample>>>,
+}
+
+#[cfg(feature = "runtime")]
+impl MemoryHistoryStore {
+    /// A store keeping at most `capacity` samples per getter, in
+    /// addition to the bounds specified by retention policies.
+    pub fn new(capacity: usize) -> Self {
+        MemoryHistoryStore {
+            capacity: capacity,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl HistoryStore for MemoryHistoryStore {
+    fn record(&self, getter: &Id<Getter>, sample: Sample, retention: &RetentionPolicy) -> Result<(), StoreError> {
+        let mut samples = self.samples.lock().unwrap();
+        let samples = samples.entry(getter.clone()).or_insert_with(VecDeque::new);
+        let interval = retention.interval_of(&sample.timestamp);
+        if interval.is_some() && samples.back().map(|last| retention.interval_of(&last.timestamp)) == Some(interval) {
+            samples.pop_back();
+        }
+        let cutoff = retention.cutoff(&sample.timestamp);
+        samples.push_back(sample);
+
+        let max_samples = retention.max_samples.map_or(self.capacity, |max| max.min(self.capacity));
+        while samples.len() > max_samples {
+            samples.pop_front();
+        }
+        if let Some(cutoff) = cutoff {
+            while samples.front().map_or(false, |first| first.timestamp.as_ms() < cutoff) {
+                samples.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    fn get_samples(&self, getter: &Id<Getter>, since: Option<&TimeStamp>, until: Option<&TimeStamp>) -> Result<Vec<Sample>, StoreError> {
+        let samples = self.samples.lock().unwrap();
+        Ok(match samples.get(getter) {
+            None => vec![],
+            Some(samples) => samples.iter()
+                .filter(|sample| since.map_or(true, |since| sample.timestamp >= *since))
+                .filter(|sample| until.map_or(true, |until| sample.timestamp <= *until))
+                .cloned()
+                .collect()
+        })
+    }
+
+    fn forget(&self, getter: &Id<Getter>) -> Result<(), StoreError> {
+        self.samples.lock().unwrap().remove(getter);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteHistoryStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{ HistoryStore, RetentionPolicy, Sample };
+    use devices::Getter;
+    use tagstore::StoreError;
+    use values::TimeStamp;
+    use util::Id;
+
+    use rusqlite::{ self, Connection };
+    use rusqlite::types::ToSql;
+    use serde_json;
+
+    use std::i64;
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    fn backend(err: rusqlite::Error) -> StoreError {
+        StoreError::Io(format!("{}", err))
+    }
+
+    /// A `HistoryStore` keeping samples in a SQLite database, in table
+    /// `samples (getter, timestamp, value)`, where `timestamp` is a
+    /// number of milliseconds since January 1st, 1970, UTC and `value`
+    /// is a JSON-encoded `Value`.
+    pub struct SqliteHistoryStore {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteHistoryStore {
+        /// Open the database at `path`, creating it if necessary.
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+            let connection = try!(Connection::open(path).map_err(backend));
+            try!(connection.execute("CREATE TABLE IF NOT EXISTS samples (
+                                         getter TEXT NOT NULL,
+                                         timestamp INTEGER NOT NULL,
+                                         value TEXT NOT NULL
+                                     )", &[]).map_err(backend));
+            try!(connection.execute("CREATE INDEX IF NOT EXISTS samples_by_getter ON samples (getter, timestamp)",
+                                    &[]).map_err(backend));
+            Ok(SqliteHistoryStore {
+                connection: Mutex::new(connection),
+            })
+        }
+    }
+
+    impl HistoryStore for SqliteHistoryStore {
+        fn record(&self, getter: &Id<Getter>, sample: Sample, retention: &RetentionPolicy) -> Result<(), StoreError> {
+            let getter: &str = getter.as_string();
+            let timestamp = sample.timestamp.as_ms();
+            let value = try!(serde_json::to_string(&sample.value).map_err(|err| StoreError::Corrupted(format!("{:?}", err))));
+            let connection = self.connection.lock().unwrap();
+            // Statements are executed on the connection, and only take
+            // effect once the transaction is committed.
+            let transaction = try!(connection.transaction().map_err(backend));
+            if let (Some(interval), Some(ref duration)) = (retention.interval_of(&sample.timestamp), retention.downsample.as_ref()) {
+                let start = interval * duration.as_ms() as i64;
+                try!(connection.execute("DELETE FROM samples WHERE getter = ?1 AND timestamp >= ?2 AND timestamp < ?3",
+                                        &[&getter as &ToSql, &start, &(start + duration.as_ms() as i64)]).map_err(backend));
+            }
+            try!(connection.execute("INSERT INTO samples (getter, timestamp, value) VALUES (?1, ?2, ?3)",
+                                    &[&getter as &ToSql, &timestamp, &value]).map_err(backend));
+            if let Some(cutoff) = retention.cutoff(&sample.timestamp) {
+                try!(connection.execute("DELETE FROM samples WHERE getter = ?1 AND timestamp < ?2",
+                                        &[&getter as &ToSql, &cutoff]).map_err(backend));
+            }
+            if let Some(max_samples) = retention.max_samples {
+                try!(connection.execute("DELETE FROM samples WHERE getter = ?1 AND rowid NOT IN (
+                                             SELECT rowid FROM samples WHERE getter = ?1
+                                             ORDER BY timestamp DESC LIMIT ?2
+                                         )", &[&getter as &ToSql, &(max_samples as i64)]).map_err(backend));
+            }
+            transaction.commit().map_err(backend)
+        }
+
+        fn get_samples(&self, getter: &Id<Getter>, since: Option<&TimeStamp>, until: Option<&TimeStamp>) -> Result<Vec<Sample>, StoreError> {
+            let getter: &str = getter.as_string();
+            let since = since.map_or(i64::MIN, TimeStamp::as_ms);
+            let until = until.map_or(i64::MAX, TimeStamp::as_ms);
+            let connection = self.connection.lock().unwrap();
+            let mut statement = try!(connection.prepare("SELECT timestamp, value FROM samples
+                                                         WHERE getter = ?1 AND timestamp >= ?2 AND timestamp <= ?3
+                                                         ORDER BY timestamp").map_err(backend));
+            let rows = try!(statement.query(&[&getter as &ToSql, &since, &until]).map_err(backend));
+            let mut samples = vec![];
+            for row in rows {
+                let row = try!(row.map_err(backend));
+                let timestamp: i64 = try!(row.get_checked(0).map_err(backend));
+                let value: String = try!(row.get_checked(1).map_err(backend));
+                let timestamp = match TimeStamp::from_ms(timestamp) {
+                    None => return Err(StoreError::Corrupted(format!("Invalid timestamp {}", timestamp))),
+                    Some(timestamp) => timestamp
+                };
+                let value = try!(serde_json::from_str(&value).map_err(|err| StoreError::Corrupted(format!("{:?}", err))));
+                samples.push(Sample {
+                    timestamp: timestamp,
+                    value: value,
+                });
+            }
+            Ok(samples)
+        }
+
+        fn forget(&self, getter: &Id<Getter>) -> Result<(), StoreError> {
+            let getter: &str = getter.as_string();
+            let connection = self.connection.lock().unwrap();
+            try!(connection.execute("DELETE FROM samples WHERE getter = ?1", &[&getter as &ToSql]).map_err(backend));
+            Ok(())
+        }
+    }
+}
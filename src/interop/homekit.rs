@@ -0,0 +1,206 @@
+//!
+//! Channels as HomeKit services and characteristics.
+//!
+//! A bridge implementing the HomeKit Accessory Protocol (HAP) exposes
+//! each node as an accessory, and each channel as a characteristic of
+//! one of its services, e.g. the `On` characteristic of a `Lightbulb`
+//! service. This module maps standardized kinds to characteristics,
+//! and converts between `Value`s and the values of characteristics.
+//!
+//! A kind may map to several services, e.g. an `OnOff` may be a
+//! `Lightbulb`, a `Switch` or an `Outlet`. The bridge picks the service
+//! matching the tags of the node.
+//!
+//! # Semantics
+//!
+//! - `OpenClosed` values are `true` when open. A lock is open when it
+//!   is unsecured.
+//! - HomeKit always expresses temperatures in degrees Celsius, with
+//!   a precision of 0.1. Temperatures are converted accordingly. Use
+//!   `display_units` to let HomeKit present them in the unit chosen
+//!   by the device.
+//!
+
+use devices::ChannelKind;
+use values::{ Temperature, Type, Value };
+
+/// Short ids of HomeKit services.
+pub mod service {
+    pub const CONTACT_SENSOR: u32 = 0x80;
+    pub const LIGHTBULB: u32 = 0x43;
+    pub const LOCK_MECHANISM: u32 = 0x45;
+    pub const OUTLET: u32 = 0x47;
+    pub const SWITCH: u32 = 0x49;
+    pub const TEMPERATURE_SENSOR: u32 = 0x8A;
+    pub const THERMOSTAT: u32 = 0x4A;
+}
+
+/// Short ids of HomeKit characteristics.
+pub mod characteristic {
+    pub const CONTACT_SENSOR_STATE: u32 = 0x6A;
+    pub const CURRENT_TEMPERATURE: u32 = 0x11;
+    pub const LOCK_CURRENT_STATE: u32 = 0x1D;
+    pub const LOCK_TARGET_STATE: u32 = 0x1E;
+    pub const ON: u32 = 0x25;
+    pub const TARGET_TEMPERATURE: u32 = 0x35;
+    pub const TEMPERATURE_DISPLAY_UNITS: u32 = 0x36;
+}
+
+/// The full UUID of a HomeKit service or characteristic, from its
+/// short id.
+pub fn uuid(short: u32) -> String {
+    format!("{:08X}-0000-1000-8000-0026BB765291", short)
+}
+
+/// The format of the value of a characteristic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Bool,
+    UInt8,
+    Float,
+}
+
+/// The value of a characteristic.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HapValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+}
+
+/// The correspondence between a characteristic and a kind of channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub kind: ChannelKind,
+
+    /// The short id of the service, see module `service`.
+    pub service: u32,
+
+    /// The short id of the characteristic, see module `characteristic`.
+    pub characteristic: u32,
+
+    pub format: Format,
+
+    /// If `true`, the characteristic may be read and notified, so
+    /// it corresponds to a getter.
+    pub getter: bool,
+
+    /// If `true`, the characteristic may be written, so it corresponds
+    /// to a setter.
+    pub setter: bool,
+
+    /// If `Some((min, max))`, the range of values accepted by HomeKit.
+    /// Values are clamped to this range.
+    pub range: Option<(f64, f64)>,
+}
+
+/// All the mappings.
+pub fn mappings() -> Vec<Mapping> {
+    use devices::ChannelKind::*;
+    use self::characteristic::*;
+    use self::service::*;
+    let table = vec![
+        (OnOff, LIGHTBULB, ON, Format::Bool, true, true, None),
+        (OnOff, SWITCH, ON, Format::Bool, true, true, None),
+        (OnOff, OUTLET, ON, Format::Bool, true, true, None),
+        (OpenClosed, CONTACT_SENSOR, CONTACT_SENSOR_STATE, Format::UInt8, true, false, None),
+        (OpenClosed, LOCK_MECHANISM, LOCK_CURRENT_STATE, Format::UInt8, true, false, None),
+        (OpenClosed, LOCK_MECHANISM, LOCK_TARGET_STATE, Format::UInt8, true, true, None),
+        (ActualTemperature, TEMPERATURE_SENSOR, CURRENT_TEMPERATURE, Format::Float, true, false, Some((0., 100.))),
+        (ActualTemperature, THERMOSTAT, CURRENT_TEMPERATURE, Format::Float, true, false, Some((0., 100.))),
+        (Thermostat, THERMOSTAT, TARGET_TEMPERATURE, Format::Float, true, true, Some((10., 38.))),
+    ];
+    table.into_iter()
+        .map(|(kind, service, characteristic, format, getter, setter, range)| Mapping {
+            kind: kind,
+            service: service,
+            characteristic: characteristic,
+            format: format,
+            getter: getter,
+            setter: setter,
+            range: range,
+        })
+        .collect()
+}
+
+/// The mappings of a kind, for all services.
+pub fn find_by_kind(kind: &ChannelKind) -> Vec<Mapping> {
+    mappings().into_iter().filter(|mapping| mapping.kind == *kind).collect()
+}
+
+/// The mapping of a characteristic of a service, or `None` if it has
+/// no counterpart in the taxonomy.
+pub fn find_by_characteristic(service: u32, characteristic: u32) -> Option<Mapping> {
+    mappings().into_iter()
+        .find(|mapping| mapping.service == service && mapping.characteristic == characteristic)
+}
+
+/// An error while converting between characteristics and values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+    /// The value does not have the type expected by the kind.
+    TypeError { expected: Type, got: Type },
+
+    /// The value of the characteristic does not have the expected
+    /// format, or has no counterpart in the taxonomy, e.g. a jammed
+    /// lock.
+    InvalidCharacteristic(HapValue),
+}
+
+impl Mapping {
+    /// Convert a value of a channel of this kind into the value of the
+    /// characteristic.
+    pub fn to_characteristic(&self, value: &Value) -> Result<HapValue, ConversionError> {
+        use self::characteristic::*;
+        let result = match (self.characteristic, value) {
+            (ON, &Value::Bool(on)) => HapValue::Bool(on),
+            // 0: contact detected, i.e. closed; 1: contact not detected.
+            (CONTACT_SENSOR_STATE, &Value::Bool(open)) => HapValue::Int(if open { 1 } else { 0 }),
+            // 0: unsecured, i.e. open; 1: secured.
+            (LOCK_CURRENT_STATE, &Value::Bool(open)) |
+            (LOCK_TARGET_STATE, &Value::Bool(open)) => HapValue::Int(if open { 0 } else { 1 }),
+            (CURRENT_TEMPERATURE, &Value::Temperature(ref temperature)) |
+            (TARGET_TEMPERATURE, &Value::Temperature(ref temperature)) => {
+                let mut c = temperature.as_c();
+                if let Some((min, max)) = self.range {
+                    c = c.max(min).min(max);
+                }
+                HapValue::Float((c * 10.).round() / 10.)
+            }
+            _ => return Err(ConversionError::TypeError {
+                expected: self.kind.get_type(),
+                got: value.get_type()
+            })
+        };
+        Ok(result)
+    }
+
+    /// Convert the value of the characteristic into a value of a
+    /// channel of this kind.
+    pub fn from_characteristic(&self, value: &HapValue) -> Result<Value, ConversionError> {
+        use self::characteristic::*;
+        let result = match (self.characteristic, value) {
+            (ON, &HapValue::Bool(on)) => Value::Bool(on),
+            (CONTACT_SENSOR_STATE, &HapValue::Int(0)) => Value::Bool(false),
+            (CONTACT_SENSOR_STATE, &HapValue::Int(1)) => Value::Bool(true),
+            // Jammed (2) and unknown (3) locks are neither open nor closed.
+            (LOCK_CURRENT_STATE, &HapValue::Int(0)) |
+            (LOCK_TARGET_STATE, &HapValue::Int(0)) => Value::Bool(true),
+            (LOCK_CURRENT_STATE, &HapValue::Int(1)) |
+            (LOCK_TARGET_STATE, &HapValue::Int(1)) => Value::Bool(false),
+            (CURRENT_TEMPERATURE, &HapValue::Float(c)) |
+            (TARGET_TEMPERATURE, &HapValue::Float(c)) => Value::Temperature(Temperature::C(c)),
+            _ => return Err(ConversionError::InvalidCharacteristic(value.clone()))
+        };
+        Ok(result)
+    }
+}
+
+/// The value of characteristic `TEMPERATURE_DISPLAY_UNITS` presenting
+/// temperatures in the unit of `temperature`.
+pub fn display_units(temperature: &Temperature) -> HapValue {
+    match *temperature {
+        Temperature::C(_) => HapValue::Int(0),
+        Temperature::F(_) => HapValue::Int(1),
+    }
+}
@@ -0,0 +1,456 @@
+//!
+//! Values, selectors and watch events as JavaScript objects.
+//!
+//! The JSON representation of the taxonomy follows the conventions of
+//! serde, e.g. kind `OnOff` is `{"OnOff":[]}` and a boolean value is
+//! `{"Bool":true}`, which the web frontend would need to decode by
+//! hand. This module defines the plain objects exchanged with
+//! JavaScript code instead, e.g. through the entry points of module
+//! `wasm` once the crate is compiled to WebAssembly.
+//!
+//! # Values
+//!
+//! Values carry no type information, as the type of a channel is given
+//! by its kind:
+//!
+//! - `Unit` is `null`;
+//! - `Bool` is a boolean;
+//! - `Duration` is a number of milliseconds;
+//! - `TimeStamp` is a number of milliseconds since January 1st, 1970,
+//!   UTC, as expected by `new Date(ms)`;
+//! - `Temperature` is a number of degrees Celsius;
+//! - `String` is a string;
+//! - `Count` is a number;
+//! - `Json` is the JSON value itself;
+//! - `Binary` is `{"mimetype": "image/png", "data": [137, 80, ...]}`;
+//! - `Color` and `ExtNumeric` are their JSON representation;
+//! - `JsonPatch` is `{"patch": [...]}`, where `patch` holds the RFC 6902
+//!   operations to apply to the latest `Json` value of the channel.
+//!
+//! `Compressed` values are decompressed.
+//!
+//! # Kinds and selectors
+//!
+//! Kinds are their name, e.g. `"OnOff"`, or
+//! `{"vendor": "foxlink@mozilla.com", "adapter": "zwave", "kind": "GroundHumidity", "type": "ExtNumeric"}`
+//! for extension kinds. Types are their name, e.g. `"Temperature"`.
+//!
+//! Selectors are `{"id": ..., "parent": ..., "kind": ..., "tags": [...]}`,
+//! where all members are optional.
+//!
+//! # Messages
+//!
+//! Messages of the WebSocket protocol, see module `websocket`, are
+//! objects whose member `type` is the name of the message, e.g.
+//!
+//! ```ignore
+//! // Client
+//! {"type": "Subscribe", "request": "1", "getters": {"kind": "OnOff"}, "values": true, "topology": false}
+//!
+//! // FoxBox
+//! {"type": "Event", "subscription": "watch-4", "sequence": 0, "event": {"type": "Value", "from": "lamp/on", "value": true}}
+//! ```
+//!
+//! Likewise, watch events are objects whose member `type` is the name
+//! of the event. Errors, adapter events and compression policies keep
+//! their JSON representation.
+//!
+
+use api::{ Labelled, WatchEvent, WatchOptions, Error as APIError };
+use compression::CompressionError;
+use devices::{ ChannelKind, KindDescription, Semantics };
+use selector::{ GetterSelector, SetterSelector };
+use util::{ Id, TagId };
+use values::{ Bytes, Json, TimeStamp, Type, ValDuration, Value, Temperature };
+use websocket::{ ClientMessage, ServerMessage };
+
+use serde_json;
+use serde_json::value::Value as JSON;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An error while reading an object received from JavaScript.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsError {
+    /// The object does not have the expected members. Payload is a
+    /// human-readable description of the problem.
+    Malformed(String),
+
+    /// The object cannot be read as a value of this type.
+    InvalidValue(Type),
+}
+
+/// All the types, in the order of their declaration.
+static TYPES: [Type; 11] = [Type::Unit, Type::Bool, Type::Duration, Type::TimeStamp, Type::Temperature,
+    Type::String, Type::Count, Type::Color, Type::Json, Type::Binary, Type::ExtNumeric];
+
+fn type_name(typ: &Type) -> &'static str {
+    match *typ {
+        Type::Unit => "Unit",
+        Type::Bool => "Bool",
+        Type::Duration => "Duration",
+        Type::TimeStamp => "TimeStamp",
+        Type::Temperature => "Temperature",
+        Type::String => "String",
+        Type::Count => "Count",
+        Type::Color => "Color",
+        Type::Json => "Json",
+        Type::Binary => "Binary",
+        Type::ExtNumeric => "ExtNumeric",
+    }
+}
+
+/// The name of a kind, or `None` for extension kinds.
+fn kind_name(kind: &ChannelKind) -> Option<&'static str> {
+    use devices::ChannelKind::*;
+    Some(match *kind {
+        Ready => "Ready",
+        OnOff => "OnOff",
+        OpenClosed => "OpenClosed",
+        CurrentTime => "CurrentTime",
+        CurrentTimeOfDay => "CurrentTimeOfDay",
+        RemainingTime => "RemainingTime",
+        Thermostat => "Thermostat",
+        ActualTemperature => "ActualTemperature",
+        ButtonPressed => "ButtonPressed",
+        DoorbellRang => "DoorbellRang",
+        Extension { .. } => return None,
+    })
+}
+
+/// An object with the given members.
+fn object(members: Vec<(&str, JSON)>) -> JSON {
+    let mut map = BTreeMap::new();
+    for (key, value) in members {
+        map.insert(key.to_owned(), value);
+    }
+    JSON::Object(map)
+}
+
+fn id_to_js<T>(id: &Id<T>) -> JSON {
+    JSON::String(id.to_string())
+}
+
+fn option_to_js<T, F>(option: &Option<T>, f: F) -> JSON where F: Fn(&T) -> JSON {
+    match *option {
+        None => JSON::Null,
+        Some(ref value) => f(value)
+    }
+}
+
+fn member<'a>(js: &'a JSON, key: &str) -> Result<&'a JSON, JsError> {
+    js.find(key).ok_or_else(|| JsError::Malformed(format!("Missing member {}", key)))
+}
+
+fn string_member<'a>(js: &'a JSON, key: &str) -> Result<&'a str, JsError> {
+    try!(member(js, key)).as_string()
+        .ok_or_else(|| JsError::Malformed(format!("Member {} should be a string", key)))
+}
+
+fn id_member<T>(js: &JSON, key: &str) -> Result<Id<T>, JsError> {
+    Id::try_from_str(try!(string_member(js, key)))
+        .map_err(|err| JsError::Malformed(format!("Member {}: {}", key, err)))
+}
+
+fn bool_member(js: &JSON, key: &str, default: bool) -> Result<bool, JsError> {
+    match js.find(key) {
+        None => Ok(default),
+        Some(value) => value.as_boolean()
+            .ok_or_else(|| JsError::Malformed(format!("Member {} should be a boolean", key)))
+    }
+}
+
+/// A value as a JavaScript object, or an error if it cannot be
+/// decompressed.
+pub fn value_to_js(value: &Value) -> Result<JSON, CompressionError> {
+    let js = match *value {
+        Value::Unit => JSON::Null,
+        Value::Bool(b) => JSON::Bool(b),
+        Value::Duration(ref duration) => JSON::U64(duration.as_ms()),
+        Value::TimeStamp(ref timestamp) => JSON::I64(timestamp.as_ms()),
+        Value::Temperature(ref temperature) => JSON::F64(temperature.as_c()),
+        Value::String(ref string) => JSON::String((**string).clone()),
+        Value::Count(count) => JSON::U64(count as u64),
+        Value::Color(ref color) => serde_json::to_value(color),
+        Value::ExtNumeric(ref numeric) => serde_json::to_value(numeric),
+        Value::Json(ref json) => json.0.clone(),
+        Value::Binary { ref data, ref mimetype } => object(vec![
+            ("mimetype", JSON::String(mimetype.clone())),
+            ("data", JSON::Array(data.iter().map(|byte| JSON::U64(*byte as u64)).collect())),
+        ]),
+        Value::JsonPatch(ref patch) => object(vec![("patch", patch.0.clone())]),
+        Value::Compressed(ref compressed) => return value_to_js(&try!(compressed.decompress())),
+    };
+    Ok(js)
+}
+
+/// Read a JavaScript object as a value of type `typ`.
+pub fn value_from_js(typ: &Type, js: &JSON) -> Result<Value, JsError> {
+    let invalid = || JsError::InvalidValue(typ.clone());
+    let value = match *typ {
+        Type::Unit if *js == JSON::Null => Value::Unit,
+        Type::Unit => return Err(invalid()),
+        Type::Bool => Value::Bool(try!(js.as_boolean().ok_or_else(&invalid))),
+        Type::Duration => {
+            let ms = try!(js.as_u64().ok_or_else(&invalid));
+            Value::Duration(ValDuration::new(Duration::new(ms / 1000, (ms % 1000) as u32 * 1_000_000)))
+        }
+        Type::TimeStamp => {
+            let ms = try!(js.as_i64().ok_or_else(&invalid));
+            Value::TimeStamp(try!(TimeStamp::from_ms(ms).ok_or_else(&invalid)))
+        }
+        Type::Temperature => Value::Temperature(Temperature::C(try!(js.as_f64().ok_or_else(&invalid)))),
+        Type::String => Value::String(Arc::new(try!(js.as_string().ok_or_else(&invalid)).to_owned())),
+        Type::Count => match js.as_u64() {
+            Some(count) if count <= u32::max_value() as u64 => Value::Count(count as u32),
+            _ => return Err(invalid())
+        },
+        Type::Color => Value::Color(try!(serde_json::from_value(js.clone()).map_err(|_| invalid()))),
+        Type::ExtNumeric => Value::ExtNumeric(try!(serde_json::from_value(js.clone()).map_err(|_| invalid()))),
+        Type::Json => Value::Json(Arc::new(Json(js.clone()))),
+        Type::Binary => {
+            let mimetype = try!(js.find("mimetype").and_then(JSON::as_string).ok_or_else(&invalid));
+            let bytes = try!(js.find("data").and_then(JSON::as_array).ok_or_else(&invalid));
+            let mut data = Vec::with_capacity(bytes.len());
+            for byte in bytes {
+                match byte.as_u64() {
+                    Some(byte) if byte <= 255 => data.push(byte as u8),
+                    _ => return Err(invalid())
+                }
+            }
+            Value::Binary {
+                data: Bytes(Arc::new(data)),
+                mimetype: mimetype.to_owned()
+            }
+        }
+    };
+    Ok(value)
+}
+
+pub fn type_to_js(typ: &Type) -> JSON {
+    JSON::String(type_name(typ).to_owned())
+}
+
+pub fn type_from_js(js: &JSON) -> Result<Type, JsError> {
+    let name = try!(js.as_string().ok_or_else(|| JsError::Malformed("A type should be a string".to_owned())));
+    TYPES.iter()
+        .find(|typ| type_name(typ) == name)
+        .cloned()
+        .ok_or_else(|| JsError::Malformed(format!("Unknown type {}", name)))
+}
+
+pub fn kind_to_js(kind: &ChannelKind) -> JSON {
+    match *kind {
+        ChannelKind::Extension { ref vendor, ref adapter, ref kind, ref typ } => object(vec![
+            ("vendor", JSON::String(vendor.clone())),
+            ("adapter", JSON::String(adapter.clone())),
+            ("kind", JSON::String(kind.clone())),
+            ("type", type_to_js(typ)),
+        ]),
+        // Only extension kinds have no name.
+        _ => JSON::String(kind_name(kind).unwrap().to_owned())
+    }
+}
+
+pub fn kind_from_js(js: &JSON) -> Result<ChannelKind, JsError> {
+    if let Some(name) = js.as_string() {
+        return ChannelKind::standard_kinds().into_iter()
+            .map(|description| description.kind)
+            .find(|kind| kind_name(kind) == Some(name))
+            .ok_or_else(|| JsError::Malformed(format!("Unknown kind {}", name)));
+    }
+    Ok(ChannelKind::Extension {
+        vendor: try!(string_member(js, "vendor")).to_owned(),
+        adapter: try!(string_member(js, "adapter")).to_owned(),
+        kind: try!(string_member(js, "kind")).to_owned(),
+        typ: try!(type_from_js(try!(member(js, "type")))),
+    })
+}
+
+fn description_to_js(description: &KindDescription) -> JSON {
+    object(vec![
+        ("kind", kind_to_js(&description.kind)),
+        ("type", type_to_js(&description.typ)),
+        ("semantics", JSON::String(match description.semantics {
+            Semantics::State => "State",
+            Semantics::Event => "Event",
+        }.to_owned())),
+        ("description", JSON::String(description.description.clone())),
+        ("getter", JSON::Bool(description.getter)),
+        ("setter", JSON::Bool(description.setter)),
+    ])
+}
+
+/// The members of a selector, as (id, parent, kind, tags).
+fn selector_from_js<T>(js: &JSON) -> Result<(Option<Id<T>>, Option<Id<::devices::NodeId>>, Option<ChannelKind>, Vec<TagId>), JsError> {
+    if js.as_object().is_none() {
+        return Err(JsError::Malformed("A selector should be an object".to_owned()));
+    }
+    let id = match js.find("id") {
+        None => None,
+        Some(_) => Some(try!(id_member(js, "id")))
+    };
+    let parent = match js.find("parent") {
+        None => None,
+        Some(_) => Some(try!(id_member(js, "parent")))
+    };
+    let kind = match js.find("kind") {
+        None => None,
+        Some(kind) => Some(try!(kind_from_js(kind)))
+    };
+    let mut tags = vec![];
+    if let Some(array) = js.find("tags") {
+        let array = try!(array.as_array().ok_or_else(|| JsError::Malformed("Member tags should be an array".to_owned())));
+        for tag in array {
+            let tag = try!(tag.as_string().ok_or_else(|| JsError::Malformed("A tag should be a string".to_owned())));
+            tags.push(try!(TagId::new(tag).map_err(|err| JsError::Malformed(format!("{}", err)))));
+        }
+    }
+    Ok((id, parent, kind, tags))
+}
+
+pub fn getter_selector_from_js(js: &JSON) -> Result<GetterSelector, JsError> {
+    let (id, parent, kind, tags) = try!(selector_from_js(js));
+    let mut selector = GetterSelector::new().with_tags(tags);
+    if let Some(id) = id {
+        selector = selector.with_id(id);
+    }
+    if let Some(parent) = parent {
+        selector = selector.with_parent(parent);
+    }
+    if let Some(kind) = kind {
+        selector = selector.with_kind(kind);
+    }
+    Ok(selector)
+}
+
+pub fn setter_selector_from_js(js: &JSON) -> Result<SetterSelector, JsError> {
+    let (id, parent, kind, tags) = try!(selector_from_js(js));
+    let mut selector = SetterSelector::new().with_tags(tags);
+    if let Some(id) = id {
+        selector = selector.with_id(id);
+    }
+    if let Some(parent) = parent {
+        selector = selector.with_parent(parent);
+    }
+    if let Some(kind) = kind {
+        selector = selector.with_kind(kind);
+    }
+    Ok(selector)
+}
+
+/// A watch event as a JavaScript object.
+///
+/// A value that cannot be decompressed is delivered as an `Error`
+/// event, as by the API.
+pub fn watch_event_to_js(event: &WatchEvent) -> JSON {
+    let (typ, mut members) = match *event {
+        WatchEvent::Value { ref from, ref value } => match value_to_js(value) {
+            Ok(value) => ("Value", vec![("from", id_to_js(from)), ("value", value)]),
+            Err(err) => ("Error", vec![
+                ("from", id_to_js(from)),
+                ("error", serde_json::to_value(&APIError::Compression(err))),
+            ])
+        },
+        WatchEvent::Error { ref from, ref error } =>
+            ("Error", vec![("from", id_to_js(from)), ("error", serde_json::to_value(error))]),
+        WatchEvent::GetterRemoved(ref getter) => ("GetterRemoved", vec![("getter", id_to_js(getter))]),
+        WatchEvent::GetterAdded(ref getter) => ("GetterAdded", vec![("getter", id_to_js(getter))]),
+        WatchEvent::LabelChanged { ref target, ref label } => ("LabelChanged", vec![
+            match *target {
+                Labelled::Node(ref id) => ("node", id_to_js(id)),
+                Labelled::Getter(ref id) => ("getter", id_to_js(id)),
+                Labelled::Setter(ref id) => ("setter", id_to_js(id)),
+            },
+            ("label", option_to_js(label, |label| JSON::String(label.clone()))),
+        ]),
+        WatchEvent::Batch(ref events) =>
+            ("Batch", vec![("events", JSON::Array(events.iter().map(watch_event_to_js).collect()))]),
+        WatchEvent::Adapter(ref event) => ("Adapter", vec![("event", serde_json::to_value(event))]),
+        WatchEvent::NodeAvailability { ref node, available } =>
+            ("NodeAvailability", vec![("node", id_to_js(node)), ("available", JSON::Bool(available))]),
+        WatchEvent::Missed(count) => ("Missed", vec![("count", JSON::U64(count))]),
+        WatchEvent::Expired => ("Expired", vec![]),
+        WatchEvent::KindRegistered { ref adapter, ref description } =>
+            ("KindRegistered", vec![("adapter", id_to_js(adapter)), ("description", description_to_js(description))]),
+        WatchEvent::KindRemoved { ref adapter, ref kind } =>
+            ("KindRemoved", vec![("adapter", id_to_js(adapter)), ("kind", kind_to_js(kind))]),
+    };
+    members.push(("type", JSON::String(typ.to_owned())));
+    object(members)
+}
+
+/// A message of the FoxBox as a JavaScript object.
+pub fn server_message_to_js(message: &ServerMessage) -> JSON {
+    let (typ, mut members) = match *message {
+        ServerMessage::Hello { version, ref compression, ref keepalive } => ("Hello", vec![
+            ("version", JSON::U64(version as u64)),
+            ("compression", option_to_js(compression, serde_json::to_value)),
+            ("keepalive", option_to_js(keepalive, |keepalive| object(vec![
+                ("interval", JSON::U64(keepalive.interval.as_ms())),
+                ("timeout", JSON::U64(keepalive.timeout.as_ms())),
+            ]))),
+        ]),
+        ServerMessage::Ack { ref request, ref subscription } =>
+            ("Ack", vec![("request", id_to_js(request)), ("subscription", option_to_js(subscription, id_to_js))]),
+        ServerMessage::Error { ref request, ref error } =>
+            ("Error", vec![("request", option_to_js(request, id_to_js)), ("error", serde_json::to_value(error))]),
+        ServerMessage::Event { ref subscription, sequence, ref event } => ("Event", vec![
+            ("subscription", id_to_js(subscription)),
+            ("sequence", JSON::U64(sequence)),
+            ("event", watch_event_to_js(event)),
+        ]),
+        ServerMessage::Pong { ref request } => ("Pong", vec![("request", id_to_js(request))]),
+        ServerMessage::Heartbeat => ("Heartbeat", vec![]),
+        ServerMessage::Resync { ref request, ref subscription, sequence } => ("Resync", vec![
+            ("request", id_to_js(request)),
+            ("subscription", id_to_js(subscription)),
+            ("sequence", JSON::U64(sequence)),
+        ]),
+    };
+    members.push(("type", JSON::String(typ.to_owned())));
+    object(members)
+}
+
+/// Read a JavaScript object as a message to the FoxBox.
+///
+/// `Subscribe` registers a single watch, on the getters selected by
+/// member `getters`, by default all of them. Members `values` and
+/// `topology` determine whether to watch values, by default `true`,
+/// and the topology, by default `false`.
+pub fn client_message_from_js(js: &JSON) -> Result<ClientMessage, JsError> {
+    let request = try!(id_member(js, "request"));
+    let message = match try!(string_member(js, "type")) {
+        "Subscribe" => {
+            let getters = match js.find("getters") {
+                None => GetterSelector::new(),
+                Some(getters) => try!(getter_selector_from_js(getters))
+            };
+            let options = WatchOptions::new()
+                .with_getters(getters)
+                .with_watch_values(try!(bool_member(js, "values", true)))
+                .with_watch_topology(try!(bool_member(js, "topology", false)));
+            ClientMessage::Subscribe {
+                request: request,
+                options: vec![options]
+            }
+        }
+        "Unsubscribe" => ClientMessage::Unsubscribe {
+            request: request,
+            subscription: try!(id_member(js, "subscription"))
+        },
+        "Ping" => ClientMessage::Ping {
+            request: request
+        },
+        "Resume" => ClientMessage::Resume {
+            request: request,
+            subscription: try!(id_member(js, "subscription")),
+            sequence: try!(try!(member(js, "sequence")).as_u64()
+                .ok_or_else(|| JsError::Malformed("Member sequence should be a number".to_owned())))
+        },
+        other => return Err(JsError::Malformed(format!("Unknown message {}", other)))
+    };
+    Ok(message)
+}
@@ -0,0 +1,156 @@
+//!
+//! Nodes and channels as JSON-LD, using the SOSA vocabulary.
+//!
+//! This module exports descriptions of nodes and channels as JSON-LD
+//! documents, using the terms of the W3C Sensor, Observation, Sample
+//! and Actuator ontology (SOSA, `http://www.w3.org/ns/sosa/`), so that
+//! installations may be indexed and reasoned about by semantic tooling
+//! outside the FoxBox.
+//!
+//! - a node is a `sosa:Platform`, which `sosa:hosts` its channels;
+//! - a getter is a `sosa:Sensor`, which `sosa:observes` its kind;
+//! - a setter is a `sosa:Actuator`, which acts `sosa:forProperty` its
+//!   kind.
+//!
+//! Kinds, tags and other notions that have no counterpart in SOSA are
+//! terms of the `foxbox` vocabulary, e.g. `foxbox:OnOff` and
+//! `foxbox:tag`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let exporter = Exporter::new("http://foxbox.local/".to_owned());
+//! let document = exporter.document(&api.get_nodes(&caller, &vec![NodeSelector::new()]));
+//! ```
+//!
+
+use devices::{ Channel, ChannelKind, Getter, Node, Setter };
+use util::TagId;
+
+use serde_json::value::Value as JSON;
+
+use std::collections::BTreeMap;
+
+pub const SOSA: &'static str = "http://www.w3.org/ns/sosa/";
+pub const RDFS: &'static str = "http://www.w3.org/2000/01/rdf-schema#";
+
+/// Produces JSON-LD descriptions of nodes and channels.
+#[derive(Debug, Clone)]
+pub struct Exporter {
+    base: String,
+}
+
+fn string(s: &str) -> JSON {
+    JSON::String(s.to_owned())
+}
+
+fn reference(iri: String) -> JSON {
+    let mut result = BTreeMap::new();
+    result.insert("@id".to_owned(), JSON::String(iri));
+    JSON::Object(result)
+}
+
+/// Percent-encode all the characters of `segment` that may not appear
+/// as such in a segment of an IRI path.
+fn escape(segment: &str) -> String {
+    let mut result = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'a' ... b'z' | b'A' ... b'Z' | b'0' ... b'9' | b'-' | b'.' | b'_' | b'~' => result.push(byte as char),
+            _ => result.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    result
+}
+
+impl Exporter {
+    /// An exporter placing all the resources under `base`, e.g.
+    /// `http://foxbox.local/`. The `foxbox` vocabulary is `<base>vocab#`.
+    pub fn new(base: String) -> Self {
+        Exporter {
+            base: base
+        }
+    }
+
+    /// The `@context` shared by all the descriptions.
+    pub fn context(&self) -> JSON {
+        let mut result = BTreeMap::new();
+        result.insert("sosa".to_owned(), string(SOSA));
+        result.insert("rdfs".to_owned(), string(RDFS));
+        result.insert("foxbox".to_owned(), JSON::String(format!("{}vocab#", self.base)));
+        JSON::Object(result)
+    }
+
+    /// The IRI of the property corresponding to a kind, e.g.
+    /// `<base>vocab#OnOff`, or `<base>vocab#<vendor>/<adapter>/<kind>`
+    /// for extensions.
+    pub fn kind_iri(&self, kind: &ChannelKind) -> String {
+        match *kind {
+            ChannelKind::Extension { ref vendor, ref adapter, ref kind, .. } =>
+                format!("{}vocab#{}/{}/{}", self.base, escape(vendor), escape(adapter), escape(kind)),
+            ref kind => format!("{}vocab#{:?}", self.base, kind),
+        }
+    }
+
+    pub fn node_iri(&self, node: &Node) -> String {
+        format!("{}nodes/{}", self.base, escape(node.id.as_string()))
+    }
+
+    pub fn getter_iri(&self, channel: &Channel<Getter>) -> String {
+        format!("{}getters/{}", self.base, escape(channel.id.as_string()))
+    }
+
+    pub fn setter_iri(&self, channel: &Channel<Setter>) -> String {
+        format!("{}setters/{}", self.base, escape(channel.id.as_string()))
+    }
+
+    fn describe(&self, iri: String, typ: &str, tags: &[TagId], label: &Option<String>) -> BTreeMap<String, JSON> {
+        let mut result = BTreeMap::new();
+        result.insert("@id".to_owned(), JSON::String(iri));
+        result.insert("@type".to_owned(), string(typ));
+        if let Some(ref label) = *label {
+            result.insert("rdfs:label".to_owned(), string(label));
+        }
+        if !tags.is_empty() {
+            result.insert("foxbox:tag".to_owned(), JSON::Array(tags.iter().map(|tag| string(tag.as_str())).collect()));
+        }
+        result
+    }
+
+    /// Describe a getter as a `sosa:Sensor`, without `@context`.
+    pub fn getter(&self, channel: &Channel<Getter>) -> JSON {
+        let mut result = self.describe(self.getter_iri(channel), "sosa:Sensor", &channel.tags, &channel.label);
+        result.insert("sosa:observes".to_owned(), reference(self.kind_iri(&channel.mechanism.kind)));
+        result.insert("sosa:isHostedBy".to_owned(),
+            reference(format!("{}nodes/{}", self.base, escape(channel.node.as_string()))));
+        JSON::Object(result)
+    }
+
+    /// Describe a setter as a `sosa:Actuator`, without `@context`.
+    pub fn setter(&self, channel: &Channel<Setter>) -> JSON {
+        let mut result = self.describe(self.setter_iri(channel), "sosa:Actuator", &channel.tags, &channel.label);
+        result.insert("sosa:forProperty".to_owned(), reference(self.kind_iri(&channel.mechanism.kind)));
+        result.insert("sosa:isHostedBy".to_owned(),
+            reference(format!("{}nodes/{}", self.base, escape(channel.node.as_string()))));
+        JSON::Object(result)
+    }
+
+    /// Describe a node as a `sosa:Platform`, along with its channels,
+    /// without `@context`.
+    pub fn node(&self, node: &Node) -> JSON {
+        let mut result = self.describe(self.node_iri(node), "sosa:Platform", &node.tags, &node.label);
+        let hosts = node.getters.iter().map(|channel| self.getter(channel))
+            .chain(node.setters.iter().map(|channel| self.setter(channel)))
+            .collect();
+        result.insert("sosa:hosts".to_owned(), JSON::Array(hosts));
+        JSON::Object(result)
+    }
+
+    /// A complete JSON-LD document describing `nodes`.
+    pub fn document(&self, nodes: &[Node]) -> JSON {
+        let mut result = BTreeMap::new();
+        result.insert("@context".to_owned(), self.context());
+        result.insert("@graph".to_owned(), JSON::Array(nodes.iter().map(|node| self.node(node)).collect()));
+        JSON::Object(result)
+    }
+}
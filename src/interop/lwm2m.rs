@@ -0,0 +1,215 @@
+//!
+//! Channels as LwM2M resources, following the IPSO objects.
+//!
+//! Constrained devices speaking CoAP/LwM2M expose their state as
+//! resources, identified by an object id and a resource id, e.g.
+//! `3303/5700` for the value of an IPSO temperature sensor. This
+//! module maps these resources to standardized `ChannelKind`s, and
+//! converts between the values of resources and `Value`s.
+//!
+//! Several resources may map to the same kind, e.g. both IPSO Light
+//! Control and IPSO Power Control carry an `OnOff`. Conversely, a
+//! number of kinds have no counterpart in LwM2M, e.g.
+//! `CurrentTimeOfDay`.
+//!
+
+use devices::ChannelKind;
+use values::{ Temperature, TimeStamp, Type, ValDuration, Value };
+
+use std::time::Duration;
+
+/// The data type of a LwM2M resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Opaque,
+
+    /// A number of seconds since January 1st, 1970, UTC.
+    Time,
+}
+
+/// The value of a LwM2M resource.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceValue {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Opaque(Vec<u8>),
+    Time(i64),
+}
+
+impl ResourceValue {
+    pub fn get_type(&self) -> ResourceType {
+        match *self {
+            ResourceValue::String(_) => ResourceType::String,
+            ResourceValue::Integer(_) => ResourceType::Integer,
+            ResourceValue::Float(_) => ResourceType::Float,
+            ResourceValue::Boolean(_) => ResourceType::Boolean,
+            ResourceValue::Opaque(_) => ResourceType::Opaque,
+            ResourceValue::Time(_) => ResourceType::Time,
+        }
+    }
+
+    /// Read a value in the plain text content format of LwM2M, in
+    /// which booleans are `0` or `1`. Opaque resources cannot be
+    /// represented as plain text.
+    pub fn parse_text(typ: ResourceType, text: &str) -> Option<Self> {
+        match typ {
+            ResourceType::String => Some(ResourceValue::String(text.to_owned())),
+            ResourceType::Integer => text.parse().ok().map(ResourceValue::Integer),
+            ResourceType::Float => text.parse().ok().map(ResourceValue::Float),
+            ResourceType::Time => text.parse().ok().map(ResourceValue::Time),
+            ResourceType::Boolean => match text {
+                "0" => Some(ResourceValue::Boolean(false)),
+                "1" => Some(ResourceValue::Boolean(true)),
+                _ => None
+            },
+            ResourceType::Opaque => None,
+        }
+    }
+
+    /// Write a value in the plain text content format of LwM2M, or
+    /// `None` for opaque values.
+    pub fn to_text(&self) -> Option<String> {
+        match *self {
+            ResourceValue::String(ref string) => Some(string.clone()),
+            ResourceValue::Integer(i) | ResourceValue::Time(i) => Some(i.to_string()),
+            ResourceValue::Float(f) => Some(f.to_string()),
+            ResourceValue::Boolean(b) => Some(if b { "1" } else { "0" }.to_owned()),
+            ResourceValue::Opaque(_) => None,
+        }
+    }
+}
+
+/// The correspondence between a resource and a kind of channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub kind: ChannelKind,
+
+    /// The id of the object, e.g. 3303 for IPSO Temperature.
+    pub object: u16,
+
+    /// The name of the object, e.g. "Temperature".
+    pub object_name: &'static str,
+
+    /// The id of the resource within the object, e.g. 5700 for
+    /// Sensor Value.
+    pub resource: u16,
+
+    pub typ: ResourceType,
+
+    /// If `Some(unit)`, the unit in which the resource is expressed,
+    /// e.g. "Cel" for degrees Celsius.
+    pub unit: Option<&'static str>,
+}
+
+/// All the mappings, the preferred resource for each kind first.
+pub fn mappings() -> Vec<Mapping> {
+    use devices::ChannelKind::*;
+    let table = vec![
+        (OnOff, 3311, "Light Control", 5850, ResourceType::Boolean, None),
+        (OnOff, 3312, "Power Control", 5850, ResourceType::Boolean, None),
+        (OpenClosed, 3200, "Digital Input", 5500, ResourceType::Boolean, None),
+        (ActualTemperature, 3303, "Temperature", 5700, ResourceType::Float, Some("Cel")),
+        (Thermostat, 3308, "Set Point", 5900, ResourceType::Float, Some("Cel")),
+        (RemainingTime, 3340, "Timer", 5538, ResourceType::Float, Some("s")),
+        (CurrentTime, 3, "Device", 13, ResourceType::Time, None),
+    ];
+    table.into_iter()
+        .map(|(kind, object, object_name, resource, typ, unit)| Mapping {
+            kind: kind,
+            object: object,
+            object_name: object_name,
+            resource: resource,
+            typ: typ,
+            unit: unit,
+        })
+        .collect()
+}
+
+/// The preferred resource for a kind, or `None` if the kind has no
+/// counterpart in LwM2M.
+pub fn find_by_kind(kind: &ChannelKind) -> Option<Mapping> {
+    mappings().into_iter().find(|mapping| mapping.kind == *kind)
+}
+
+/// The mapping of resource `object/resource`, or `None` if the
+/// resource has no counterpart in the taxonomy.
+pub fn find_by_resource(object: u16, resource: u16) -> Option<Mapping> {
+    mappings().into_iter().find(|mapping| mapping.object == object && mapping.resource == resource)
+}
+
+/// An error while converting between resources and values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConversionError {
+    /// The kind has no counterpart in LwM2M.
+    NoMapping(ChannelKind),
+
+    /// The value does not have the type expected by the kind.
+    TypeError { expected: Type, got: Type },
+
+    /// The value of the resource does not have the type expected by
+    /// the mapping, or is out of range.
+    InvalidResource(ResourceValue),
+}
+
+impl Mapping {
+    /// Convert a value of a channel of this kind into the value of the
+    /// resource.
+    pub fn to_resource(&self, value: &Value) -> Result<ResourceValue, ConversionError> {
+        let result = match (self.typ, value) {
+            (ResourceType::Boolean, &Value::Bool(b)) => ResourceValue::Boolean(b),
+            (ResourceType::Float, &Value::Temperature(ref temperature)) => ResourceValue::Float(temperature.as_c()),
+            (ResourceType::Float, &Value::Duration(ref duration)) => ResourceValue::Float(duration.as_ms() as f64 / 1000.),
+            (ResourceType::Time, &Value::TimeStamp(ref timestamp)) => ResourceValue::Time(timestamp.as_ms() / 1000),
+            _ => return Err(ConversionError::TypeError {
+                expected: self.kind.get_type(),
+                got: value.get_type()
+            })
+        };
+        Ok(result)
+    }
+
+    /// Convert the value of the resource into a value of a channel of
+    /// this kind.
+    pub fn from_resource(&self, resource: &ResourceValue) -> Result<Value, ConversionError> {
+        let invalid = || ConversionError::InvalidResource(resource.clone());
+        let result = match (self.kind.get_type(), resource) {
+            (Type::Bool, &ResourceValue::Boolean(b)) => Value::Bool(b),
+            (Type::Temperature, &ResourceValue::Float(c)) => Value::Temperature(Temperature::C(c)),
+            (Type::Duration, &ResourceValue::Float(s)) if s >= 0. => {
+                let ms = (s * 1000.) as u64;
+                Value::Duration(ValDuration::new(Duration::new(ms / 1000, (ms % 1000) as u32 * 1_000_000)))
+            }
+            (Type::TimeStamp, &ResourceValue::Time(s)) => {
+                match s.checked_mul(1000).and_then(TimeStamp::from_ms) {
+                    Some(timestamp) => Value::TimeStamp(timestamp),
+                    None => return Err(invalid())
+                }
+            }
+            _ => return Err(invalid())
+        };
+        Ok(result)
+    }
+}
+
+/// Convert a value of a channel of kind `kind` into the value of the
+/// preferred resource for this kind.
+pub fn to_resource(kind: &ChannelKind, value: &Value) -> Result<(Mapping, ResourceValue), ConversionError> {
+    let mapping = try!(find_by_kind(kind).ok_or_else(|| ConversionError::NoMapping(kind.clone())));
+    let resource = try!(mapping.to_resource(value));
+    Ok((mapping, resource))
+}
+
+/// Convert the value of resource `object/resource` into a value of the
+/// corresponding kind, or `None` if the resource has no counterpart in
+/// the taxonomy.
+pub fn from_resource(object: u16, resource: u16, value: &ResourceValue) -> Option<Result<(ChannelKind, Value), ConversionError>> {
+    find_by_resource(object, resource).map(|mapping| {
+        mapping.from_resource(value).map(|value| (mapping.kind, value))
+    })
+}
@@ -0,0 +1,29 @@
+//!
+//! Mappings between the taxonomy and other ecosystems.
+//!
+//! Each module defines a single, canonical correspondence between the
+//! nodes, channels and values of the taxonomy and the concepts of
+//! another protocol, so that the adapters bridging to this protocol
+//! and the external consumers agree on one scheme.
+//!
+
+/// Channels as MQTT topics, values as MQTT payloads.
+pub mod mqtt;
+
+/// Channels as LwM2M resources, following the IPSO objects.
+pub mod lwm2m;
+
+/// Channels as HomeKit services and characteristics.
+pub mod homekit;
+
+/// Z-Wave command classes as channels.
+pub mod zwave;
+
+/// Channels as Alexa capabilities and Google Home traits.
+pub mod voice;
+
+/// Nodes and channels as JSON-LD, using the SOSA vocabulary.
+pub mod jsonld;
+
+/// Values, selectors and watch events as JavaScript objects.
+pub mod js;
@@ -0,0 +1,293 @@
+//!
+//! Channels as MQTT topics, values as MQTT payloads.
+//!
+//! # Topics
+//!
+//! With the default prefix `foxbox`:
+//!
+//! - `foxbox/nodes/<node>/availability` holds `online` or `offline`.
+//!   Publications are retained. Bridges are expected to register
+//!   `offline` as their last will, so that consumers are informed
+//!   when the bridge disconnects.
+//! - `foxbox/nodes/<node>/getters/<getter>` holds the latest value
+//!   of a getter. Publications are retained, so that consumers
+//!   subscribing late immediately receive the last known value.
+//! - `foxbox/nodes/<node>/setters/<setter>/set` receives the values
+//!   to send to a setter. Publications are not retained, as a value
+//!   must not be sent again to the device whenever the bridge
+//!   reconnects.
+//!
+//! Characters `/`, `+`, `#` and `%` have a special meaning in MQTT
+//! topics, so they are percent-encoded in ids, e.g. id `a/b` appears
+//! as `a%2Fb`.
+//!
+//! # Payloads
+//!
+//! Payloads follow the conventions of most MQTT consumers, i.e.
+//! scalars are plain text:
+//!
+//! - `Unit` is the empty payload;
+//! - `Bool` is `true` or `false`;
+//! - `Duration` is a number of milliseconds, e.g. `1500`;
+//! - `TimeStamp` is a number of milliseconds since January 1st, 1970,
+//!   UTC;
+//! - `String` is the UTF-8 string itself;
+//! - `Count` is a decimal number, e.g. `2`;
+//! - `Binary` is the data itself. As MQTT does not carry mimetypes,
+//!   the mimetype of decoded values is `application/octet-stream`;
+//! - `Temperature`, `Color`, `ExtNumeric` and `Json` are their JSON
+//!   representation.
+//!
+//! Payloads carry no type information, so decoding a payload requires
+//! the type of the channel, as given by `ChannelKind::get_type`.
+//!
+
+use devices::{ Channel, Getter, NodeId, Setter };
+use util::Id;
+use values::{ Bytes, Json, TimeStamp, Type, ValDuration, Value };
+
+use serde_json;
+
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The payload of availability topics while the node is reachable.
+pub const ONLINE: &'static str = "online";
+
+/// The payload of availability topics while the node is unreachable.
+pub const OFFLINE: &'static str = "offline";
+
+/// The mimetype of `Binary` values decoded from MQTT payloads.
+pub const BINARY_MIMETYPE: &'static str = "application/octet-stream";
+
+/// A message to publish on a MQTT broker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publication {
+    pub topic: String,
+    pub payload: Vec<u8>,
+
+    /// If `true`, the broker keeps the message and delivers it to
+    /// future subscribers of the topic.
+    pub retain: bool,
+}
+
+/// A topic, as understood by `Scheme::parse_topic`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Topic {
+    /// The availability of a node.
+    Availability(Id<NodeId>),
+
+    /// The latest value of a getter.
+    Value { node: Id<NodeId>, getter: Id<Getter> },
+
+    /// The values to send to a setter.
+    Set { node: Id<NodeId>, setter: Id<Setter> },
+}
+
+/// An error while decoding a payload.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadError {
+    /// The payload should have been a UTF-8 string.
+    InvalidUtf8,
+
+    /// The payload cannot be read as a value of this type.
+    InvalidValue(Type),
+}
+
+/// The mapping between channels and topics.
+#[derive(Debug, Clone)]
+pub struct Scheme {
+    prefix: String,
+}
+
+impl Default for Scheme {
+    /// A scheme with prefix `foxbox`.
+    fn default() -> Self {
+        Scheme::new("foxbox".to_owned())
+    }
+}
+
+impl Scheme {
+    /// A scheme placing all topics under `prefix`, e.g. `home/foxbox`.
+    pub fn new(prefix: String) -> Self {
+        Scheme {
+            prefix: prefix
+        }
+    }
+
+    pub fn availability_topic(&self, node: &Id<NodeId>) -> String {
+        format!("{}/nodes/{}/availability", self.prefix, escape(node.as_string()))
+    }
+
+    pub fn value_topic(&self, channel: &Channel<Getter>) -> String {
+        format!("{}/nodes/{}/getters/{}", self.prefix,
+            escape(channel.node.as_string()), escape(channel.id.as_string()))
+    }
+
+    pub fn set_topic(&self, channel: &Channel<Setter>) -> String {
+        format!("{}/nodes/{}/setters/{}/set", self.prefix,
+            escape(channel.node.as_string()), escape(channel.id.as_string()))
+    }
+
+    /// A topic filter matching all the `set` topics, for bridges to
+    /// subscribe to.
+    pub fn set_filter(&self) -> String {
+        format!("{}/nodes/+/setters/+/set", self.prefix)
+    }
+
+    /// The retained publication announcing whether a node is reachable.
+    pub fn availability(&self, node: &Id<NodeId>, online: bool) -> Publication {
+        Publication {
+            topic: self.availability_topic(node),
+            payload: if online { ONLINE } else { OFFLINE }.as_bytes().to_vec(),
+            retain: true,
+        }
+    }
+
+    /// The retained publication holding the latest value of a getter.
+    pub fn value(&self, channel: &Channel<Getter>, value: &Value) -> Publication {
+        Publication {
+            topic: self.value_topic(channel),
+            payload: encode_payload(value),
+            retain: true,
+        }
+    }
+
+    /// The publication requesting that a value be sent to a setter.
+    pub fn set(&self, channel: &Channel<Setter>, value: &Value) -> Publication {
+        Publication {
+            topic: self.set_topic(channel),
+            payload: encode_payload(value),
+            retain: false,
+        }
+    }
+
+    /// Determine the meaning of a topic, or `None` if the topic is not
+    /// part of this scheme.
+    pub fn parse_topic(&self, topic: &str) -> Option<Topic> {
+        if !topic.starts_with(&self.prefix) {
+            return None;
+        }
+        let levels: Vec<_> = topic[self.prefix.len()..].split('/').collect();
+        // `levels[0]` is the empty string preceding the first `/`.
+        if levels.len() < 4 || levels[0] != "" || levels[1] != "nodes" {
+            return None;
+        }
+        let node = match unescape(levels[2]).and_then(|node| Id::try_from_str(&node).ok()) {
+            None => return None,
+            Some(node) => node
+        };
+        match (levels.len(), levels[3]) {
+            (4, "availability") => Some(Topic::Availability(node)),
+            (5, "getters") => unescape(levels[4]).and_then(|getter| Id::try_from_str(&getter).ok()).map(|getter| Topic::Value {
+                node: node,
+                getter: getter
+            }),
+            (6, "setters") if levels[5] == "set" => unescape(levels[4]).and_then(|setter| Id::try_from_str(&setter).ok()).map(|setter| Topic::Set {
+                node: node,
+                setter: setter
+            }),
+            _ => None
+        }
+    }
+}
+
+/// Encode a value as a payload.
+pub fn encode_payload(value: &Value) -> Vec<u8> {
+    // Serializing these values to JSON cannot fail.
+    match *value {
+        Value::Unit => vec![],
+        Value::Bool(b) => b.to_string().into_bytes(),
+        Value::Duration(ref duration) => duration.as_ms().to_string().into_bytes(),
+        Value::TimeStamp(ref timestamp) => timestamp.as_ms().to_string().into_bytes(),
+        Value::String(ref string) => string.as_bytes().to_vec(),
+        Value::Count(count) => count.to_string().into_bytes(),
+        Value::Binary { ref data, .. } => (**data).clone(),
+        Value::Temperature(ref temperature) => serde_json::to_string(temperature).unwrap().into_bytes(),
+        Value::Color(ref color) => serde_json::to_string(color).unwrap().into_bytes(),
+        Value::ExtNumeric(ref numeric) => serde_json::to_string(numeric).unwrap().into_bytes(),
+        Value::Json(ref json) => serde_json::to_string(&json.0).unwrap().into_bytes(),
+        Value::JsonPatch(ref patch) => serde_json::to_string(&patch.0).unwrap().into_bytes(),
+        Value::Compressed(ref compressed) => match compressed.decompress() {
+            Ok(value) => encode_payload(&value),
+            // MQTT consumers could not make sense of the data anyway.
+            Err(_) => vec![]
+        },
+    }
+}
+
+/// Decode a payload received for a channel of type `typ`.
+pub fn decode_payload(typ: &Type, payload: &[u8]) -> Result<Value, PayloadError> {
+    if let Type::Binary = *typ {
+        return Ok(Value::Binary {
+            data: Bytes(Arc::new(payload.to_vec())),
+            mimetype: BINARY_MIMETYPE.to_owned()
+        });
+    }
+    let text = match str::from_utf8(payload) {
+        Ok(text) => text,
+        Err(_) => return Err(PayloadError::InvalidUtf8)
+    };
+    let invalid = || PayloadError::InvalidValue(typ.clone());
+    let value = match *typ {
+        Type::Unit if text.is_empty() => Value::Unit,
+        Type::Unit => return Err(invalid()),
+        Type::Bool => match text {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => return Err(invalid())
+        },
+        Type::Duration => {
+            let ms: u64 = try!(text.parse().map_err(|_| invalid()));
+            Value::Duration(ValDuration::new(Duration::new(ms / 1000, (ms % 1000) as u32 * 1_000_000)))
+        }
+        Type::TimeStamp => {
+            let ms: i64 = try!(text.parse().map_err(|_| invalid()));
+            Value::TimeStamp(try!(TimeStamp::from_ms(ms).ok_or_else(&invalid)))
+        }
+        Type::String => Value::String(Arc::new(text.to_owned())),
+        Type::Count => Value::Count(try!(text.parse().map_err(|_| invalid()))),
+        Type::Temperature => Value::Temperature(try!(serde_json::from_str(text).map_err(|_| invalid()))),
+        Type::Color => Value::Color(try!(serde_json::from_str(text).map_err(|_| invalid()))),
+        Type::ExtNumeric => Value::ExtNumeric(try!(serde_json::from_str(text).map_err(|_| invalid()))),
+        Type::Json => Value::Json(Arc::new(Json(try!(serde_json::from_str(text).map_err(|_| invalid()))))),
+        Type::Binary => unreachable!()
+    };
+    Ok(value)
+}
+
+/// Percent-encode the characters of an id that are reserved in topics.
+pub fn escape(id: &str) -> String {
+    let mut result = String::with_capacity(id.len());
+    for c in id.chars() {
+        match c {
+            '/' => result.push_str("%2F"),
+            '+' => result.push_str("%2B"),
+            '#' => result.push_str("%23"),
+            '%' => result.push_str("%25"),
+            c => result.push(c)
+        }
+    }
+    result
+}
+
+/// Reverse `escape`, or `None` if `level` is not a valid escaped id.
+pub fn unescape(level: &str) -> Option<String> {
+    let mut result = String::with_capacity(level.len());
+    let mut rest = level;
+    while let Some(pos) = rest.find('%') {
+        result.push_str(&rest[..pos]);
+        let c = match &rest.as_bytes()[pos + 1 ..] {
+            b if b.starts_with(b"2F") => '/',
+            b if b.starts_with(b"2B") => '+',
+            b if b.starts_with(b"23") => '#',
+            b if b.starts_with(b"25") => '%',
+            _ => return None
+        };
+        result.push(c);
+        rest = &rest[pos + 3..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
@@ -0,0 +1,179 @@
+//!
+//! Channels as Alexa capabilities and Google Home traits.
+//!
+//! Cloud bridges expose the FoxBox to voice assistants. Amazon Alexa
+//! describes devices with interfaces holding properties, e.g. property
+//! `powerState` of interface `Alexa.PowerController`, and controls them
+//! through directives, e.g. `TurnOn`. Google Home describes devices with
+//! traits holding states, e.g. state `on` of trait
+//! `action.devices.traits.OnOff`, and controls them through commands.
+//!
+//! This module maps standardized kinds to both, and translates values
+//! to properties/states and directives/commands to values.
+//!
+//! # Semantics
+//!
+//! - `OpenClosed` values are `true` when open. A lock is open when it
+//!   is unlocked, a contact sensor is open when it detects no contact.
+//! - Google Home expresses temperatures in degrees Celsius, Alexa in
+//!   the scale of the temperature.
+//!
+
+use devices::ChannelKind;
+use values::{ Temperature, Value };
+
+use serde_json::value::Value as JSON;
+
+use std::collections::BTreeMap;
+
+/// A voice assistant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assistant {
+    Alexa,
+    GoogleHome,
+}
+
+/// The correspondence between a capability of a voice assistant and a
+/// kind of channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub assistant: Assistant,
+    pub kind: ChannelKind,
+
+    /// The Alexa interface, e.g. `Alexa.PowerController`, or the Google
+    /// Home trait, e.g. `action.devices.traits.OnOff`.
+    pub capability: &'static str,
+
+    /// The Alexa property or the Google Home state holding the value
+    /// of the getter, e.g. `powerState`.
+    pub property: &'static str,
+
+    /// The Alexa directives or Google Home commands writing to the
+    /// setter. Empty if the capability is read-only.
+    pub commands: &'static [&'static str],
+}
+
+/// All the mappings.
+pub fn mappings() -> Vec<Mapping> {
+    use devices::ChannelKind::*;
+    use self::Assistant::*;
+    let table = vec![
+        (Alexa, OnOff, "Alexa.PowerController", "powerState", &["TurnOn", "TurnOff"] as &'static [&'static str]),
+        (Alexa, OpenClosed, "Alexa.LockController", "lockState", &["Lock", "Unlock"]),
+        (Alexa, OpenClosed, "Alexa.ContactSensor", "detectionState", &[]),
+        (Alexa, ActualTemperature, "Alexa.TemperatureSensor", "temperature", &[]),
+        (Alexa, Thermostat, "Alexa.ThermostatController", "targetSetpoint", &["SetTargetTemperature"]),
+        (GoogleHome, OnOff, "action.devices.traits.OnOff", "on", &["action.devices.commands.OnOff"]),
+        (GoogleHome, OpenClosed, "action.devices.traits.LockUnlock", "isLocked", &["action.devices.commands.LockUnlock"]),
+        (GoogleHome, OpenClosed, "action.devices.traits.OpenClose", "openPercent", &["action.devices.commands.OpenClose"]),
+        (GoogleHome, ActualTemperature, "action.devices.traits.TemperatureSetting", "thermostatTemperatureAmbient", &[]),
+        (GoogleHome, Thermostat, "action.devices.traits.TemperatureSetting", "thermostatTemperatureSetpoint",
+            &["action.devices.commands.ThermostatTemperatureSetpoint"]),
+    ];
+    table.into_iter()
+        .map(|(assistant, kind, capability, property, commands)| Mapping {
+            assistant: assistant,
+            kind: kind,
+            capability: capability,
+            property: property,
+            commands: commands,
+        })
+        .collect()
+}
+
+/// The mappings of a kind for an assistant.
+pub fn find_by_kind(assistant: Assistant, kind: &ChannelKind) -> Vec<Mapping> {
+    mappings().into_iter()
+        .filter(|mapping| mapping.assistant == assistant && mapping.kind == *kind)
+        .collect()
+}
+
+/// The mapping of a property of a capability, or `None` if it has no
+/// counterpart in the taxonomy.
+pub fn find_by_property(assistant: Assistant, capability: &str, property: &str) -> Option<Mapping> {
+    mappings().into_iter()
+        .find(|mapping| mapping.assistant == assistant && mapping.capability == capability && mapping.property == property)
+}
+
+fn string(s: &str) -> JSON {
+    JSON::String(s.to_owned())
+}
+
+/// An Alexa temperature, e.g. `{"value": 21.5, "scale": "CELSIUS"}`.
+fn alexa_temperature(temperature: &Temperature) -> JSON {
+    let (value, scale) = match *temperature {
+        Temperature::C(c) => (c, "CELSIUS"),
+        Temperature::F(f) => (f, "FAHRENHEIT"),
+    };
+    let mut result = BTreeMap::new();
+    result.insert("value".to_owned(), JSON::F64(value));
+    result.insert("scale".to_owned(), string(scale));
+    JSON::Object(result)
+}
+
+fn alexa_temperature_from(json: &JSON) -> Option<Temperature> {
+    let value = match json.find("value").and_then(JSON::as_f64) {
+        None => return None,
+        Some(value) => value
+    };
+    match json.find("scale").and_then(JSON::as_string) {
+        Some("CELSIUS") => Some(Temperature::C(value)),
+        Some("FAHRENHEIT") => Some(Temperature::F(value)),
+        _ => None
+    }
+}
+
+impl Mapping {
+    /// Translate the value of a getter into the Alexa property or
+    /// Google Home state, or `None` if the value does not have the
+    /// type expected by the kind.
+    pub fn to_property(&self, value: &Value) -> Option<JSON> {
+        let result = match (self.capability, value) {
+            ("Alexa.PowerController", &Value::Bool(on)) => string(if on { "ON" } else { "OFF" }),
+            ("Alexa.LockController", &Value::Bool(open)) => string(if open { "UNLOCKED" } else { "LOCKED" }),
+            ("Alexa.ContactSensor", &Value::Bool(open)) => string(if open { "NOT_DETECTED" } else { "DETECTED" }),
+            ("Alexa.TemperatureSensor", &Value::Temperature(ref temperature)) |
+            ("Alexa.ThermostatController", &Value::Temperature(ref temperature)) => alexa_temperature(temperature),
+            ("action.devices.traits.OnOff", &Value::Bool(on)) => JSON::Bool(on),
+            ("action.devices.traits.LockUnlock", &Value::Bool(open)) => JSON::Bool(!open),
+            ("action.devices.traits.OpenClose", &Value::Bool(open)) => JSON::U64(if open { 100 } else { 0 }),
+            ("action.devices.traits.TemperatureSetting", &Value::Temperature(ref temperature)) => JSON::F64(temperature.as_c()),
+            _ => return None
+        };
+        Some(result)
+    }
+
+    /// Translate an Alexa directive or a Google Home command into the
+    /// value to send to the setter.
+    ///
+    /// `payload` is the payload of the Alexa directive or the `params`
+    /// of the Google Home command. Returns `None` if the command is not
+    /// one of `self.commands`, or if its payload is malformed.
+    pub fn from_command(&self, command: &str, payload: &JSON) -> Option<Value> {
+        if !self.commands.contains(&command) {
+            return None;
+        }
+        match command {
+            "TurnOn" => Some(Value::Bool(true)),
+            "TurnOff" => Some(Value::Bool(false)),
+            "Lock" => Some(Value::Bool(false)),
+            "Unlock" => Some(Value::Bool(true)),
+            "SetTargetTemperature" => payload.find("targetSetpoint")
+                .and_then(alexa_temperature_from)
+                .map(Value::Temperature),
+            "action.devices.commands.OnOff" => payload.find("on")
+                .and_then(JSON::as_boolean)
+                .map(Value::Bool),
+            "action.devices.commands.LockUnlock" => payload.find("lock")
+                .and_then(JSON::as_boolean)
+                .map(|lock| Value::Bool(!lock)),
+            "action.devices.commands.OpenClose" => payload.find("openPercent")
+                .and_then(JSON::as_f64)
+                .map(|percent| Value::Bool(percent > 0.)),
+            "action.devices.commands.ThermostatTemperatureSetpoint" => payload.find("thermostatTemperatureSetpoint")
+                .and_then(JSON::as_f64)
+                .map(|c| Value::Temperature(Temperature::C(c))),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//!
+//! Z-Wave command classes as channels.
+//!
+//! Z-Wave devices advertise the command classes they support, e.g.
+//! Binary Switch or Door Lock, and send reports holding their state.
+//! This module maps command classes to channels and reports to values,
+//! so that all Z-Wave adapters produce the same topology for the same
+//! device.
+//!
+//! # Multi-instance devices
+//!
+//! A Z-Wave device may expose several endpoints, e.g. a power strip
+//! with one Binary Switch per outlet. The taxonomy has no notion of
+//! subnodes, so each endpoint is represented as a node of its own,
+//! whose id is derived from the id of the root device, and tagged with
+//! `zwave:endpoint-of:<root>`, so that applications may regroup the
+//! endpoints of a device. As all tags, this tag is normalized, see
+//! `util::TagId`, and is omitted if the id of the root device is too
+//! long to fit in a tag.
+//!
+//! # Payloads
+//!
+//! Payloads of reports and of `Set` commands exclude the leading
+//! command class and command bytes.
+//!
+
+use devices::{ Channel, ChannelKind, Getter, Node, NodeId, Setter };
+use util::{ Id, TagId };
+use values::{ ExtNumeric, Temperature, Type, Value };
+
+/// The vendor of extension kinds introduced by this module.
+pub const VENDOR: &'static str = "foxlink@mozilla.com";
+
+/// The adapter of extension kinds introduced by this module.
+pub const ADAPTER: &'static str = "zwave";
+
+/// The prefix of the tag linking an endpoint to its root device.
+pub const ENDPOINT_OF: &'static str = "zwave:endpoint-of:";
+
+/// Ids of the command classes supported by this module.
+pub mod command_class {
+    pub const SWITCH_BINARY: u8 = 0x25;
+    pub const SENSOR_MULTILEVEL: u8 = 0x31;
+    pub const DOOR_LOCK: u8 = 0x62;
+    pub const BATTERY: u8 = 0x80;
+}
+
+/// Sensor types of command class Multilevel Sensor.
+pub mod sensor_type {
+    pub const AIR_TEMPERATURE: u8 = 0x01;
+    pub const LUMINANCE: u8 = 0x03;
+    pub const RELATIVE_HUMIDITY: u8 = 0x05;
+}
+
+/// A command class, as supported by a node or an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandClass {
+    /// Exposed as an `OnOff` getter and setter.
+    SwitchBinary,
+
+    /// Exposed as an `ActualTemperature` getter for air temperature,
+    /// or as an `ExtNumeric` getter for other sensor types.
+    SensorMultilevel { sensor_type: u8 },
+
+    /// Exposed as an `OpenClosed` getter and setter. The lock is open
+    /// when unsecured.
+    DoorLock,
+
+    /// Exposed as an `ExtNumeric` getter, holding the battery level
+    /// in percents. Nodes with a battery are tagged with `battery`.
+    Battery,
+}
+
+impl CommandClass {
+    /// The id of the command class.
+    pub fn id(&self) -> u8 {
+        match *self {
+            CommandClass::SwitchBinary => command_class::SWITCH_BINARY,
+            CommandClass::SensorMultilevel { .. } => command_class::SENSOR_MULTILEVEL,
+            CommandClass::DoorLock => command_class::DOOR_LOCK,
+            CommandClass::Battery => command_class::BATTERY,
+        }
+    }
+
+    /// A name for the command class, unique within a node, used to
+    /// derive the ids of its channels.
+    pub fn name(&self) -> String {
+        match *self {
+            CommandClass::SwitchBinary => "switch-binary".to_owned(),
+            CommandClass::SensorMultilevel { sensor_type } => format!("sensor-multilevel-{}", sensor_type),
+            CommandClass::DoorLock => "door-lock".to_owned(),
+            CommandClass::Battery => "battery".to_owned(),
+        }
+    }
+
+    /// The kind of the channels exposing this command class.
+    pub fn kind(&self) -> ChannelKind {
+        let ext = |kind: &str| ChannelKind::Extension {
+            vendor: VENDOR.to_owned(),
+            adapter: ADAPTER.to_owned(),
+            kind: kind.to_owned(),
+            typ: Type::ExtNumeric,
+        };
+        match *self {
+            CommandClass::SwitchBinary => ChannelKind::OnOff,
+            CommandClass::SensorMultilevel { sensor_type: sensor_type::AIR_TEMPERATURE } => ChannelKind::ActualTemperature,
+            CommandClass::SensorMultilevel { sensor_type: sensor_type::LUMINANCE } => ext("Luminance"),
+            CommandClass::SensorMultilevel { sensor_type: sensor_type::RELATIVE_HUMIDITY } => ext("RelativeHumidity"),
+            CommandClass::SensorMultilevel { sensor_type } => ext(&format!("SensorMultilevel{}", sensor_type)),
+            CommandClass::DoorLock => ChannelKind::OpenClosed,
+            CommandClass::Battery => ext("BatteryLevel"),
+        }
+    }
+
+    /// If `true`, the command class is exposed as a setter.
+    pub fn is_setter(&self) -> bool {
+        match *self {
+            CommandClass::SwitchBinary | CommandClass::DoorLock => true,
+            CommandClass::SensorMultilevel { .. } | CommandClass::Battery => false,
+        }
+    }
+
+    /// The getter channel exposing this command class on `node`.
+    pub fn getter(&self, node: &Id<NodeId>) -> Channel<Getter> {
+        let id = Id::new(format!("{}/{}", node.as_string(), self.name()));
+        Channel::new(id, node.clone(), Getter::new(self.kind()))
+    }
+
+    /// The setter channel exposing this command class on `node`, if
+    /// any.
+    pub fn setter(&self, node: &Id<NodeId>) -> Option<Channel<Setter>> {
+        if !self.is_setter() {
+            return None;
+        }
+        let id = Id::new(format!("{}/{}", node.as_string(), self.name()));
+        Some(Channel::new(id, node.clone(), Setter::new(self.kind())))
+    }
+
+    /// Decode a report of this command class.
+    ///
+    /// Returns `None` if the payload is malformed, or if it reports a
+    /// state that has no counterpart in the taxonomy, e.g. a lock
+    /// whose state is unknown.
+    pub fn decode_report(&self, payload: &[u8]) -> Option<Value> {
+        match *self {
+            CommandClass::SwitchBinary => match payload.first() {
+                Some(&0x00) => Some(Value::Bool(false)),
+                Some(&0x01 ... 0x63) | Some(&0xFF) => Some(Value::Bool(true)),
+                _ => None
+            },
+            CommandClass::SensorMultilevel { sensor_type } => {
+                if payload.len() < 2 || payload[0] != sensor_type {
+                    return None;
+                }
+                let precision = payload[1] >> 5;
+                let scale = (payload[1] >> 3) & 0x03;
+                let size = (payload[1] & 0x07) as usize;
+                if size == 0 || size > 4 || payload.len() < 2 + size {
+                    return None;
+                }
+                // A big-endian signed integer.
+                let raw = payload[2 .. 2 + size].iter()
+                    .fold(if payload[2] & 0x80 != 0 { -1i64 } else { 0 }, |acc, &byte| (acc << 8) | byte as i64);
+                let value = raw as f64 / 10f64.powi(precision as i32);
+                match (self.kind(), scale) {
+                    (ChannelKind::ActualTemperature, 0) => Some(Value::Temperature(Temperature::C(value))),
+                    (ChannelKind::ActualTemperature, 1) => Some(Value::Temperature(Temperature::F(value))),
+                    (ChannelKind::ActualTemperature, _) => None,
+                    (kind, _) => Some(ext_value(&kind, value))
+                }
+            }
+            CommandClass::DoorLock => match payload.first() {
+                Some(&0xFF) => Some(Value::Bool(false)),
+                // Unsecured, possibly with a timeout or for inside or
+                // outside handles only.
+                Some(&0x00) | Some(&0x01) | Some(&0x10) |
+                Some(&0x11) | Some(&0x20) | Some(&0x21) => Some(Value::Bool(true)),
+                _ => None
+            },
+            CommandClass::Battery => match payload.first() {
+                // Low battery warning.
+                Some(&0xFF) => Some(ext_value(&self.kind(), 0.)),
+                Some(&level) if level <= 100 => Some(ext_value(&self.kind(), level as f64)),
+                _ => None
+            },
+        }
+    }
+
+    /// Encode the payload of the `Set` command sending `value` to the
+    /// setter exposing this command class.
+    ///
+    /// Returns `None` if this command class is not exposed as a setter,
+    /// or if `value` does not have the expected type.
+    pub fn encode_set(&self, value: &Value) -> Option<Vec<u8>> {
+        match (*self, value) {
+            (CommandClass::SwitchBinary, &Value::Bool(on)) => Some(vec![if on { 0xFF } else { 0x00 }]),
+            (CommandClass::DoorLock, &Value::Bool(open)) => Some(vec![if open { 0x00 } else { 0xFF }]),
+            _ => None
+        }
+    }
+}
+
+fn ext_value(kind: &ChannelKind, value: f64) -> Value {
+    let kind = match *kind {
+        ChannelKind::Extension { ref kind, .. } => kind.clone(),
+        _ => unreachable!()
+    };
+    Value::ExtNumeric(ExtNumeric {
+        value: value,
+        vendor: VENDOR.to_owned(),
+        adapter: ADAPTER.to_owned(),
+        kind: kind,
+    })
+}
+
+/// The node exposing a device or endpoint supporting `classes`.
+pub fn node(id: Id<NodeId>, classes: &[CommandClass]) -> Node {
+    let mut node = Node::new(id.clone());
+    for class in classes {
+        node = node.with_getter(class.getter(&id));
+        if let Some(setter) = class.setter(&id) {
+            node = node.with_setter(setter);
+        }
+    }
+    if classes.contains(&CommandClass::Battery) {
+        node = node.with_tags(vec![TagId::new("battery").unwrap()]);
+    }
+    node
+}
+
+/// The id of the node exposing endpoint `endpoint` of device `root`.
+pub fn endpoint_id(root: &Id<NodeId>, endpoint: u8) -> Id<NodeId> {
+    Id::new(format!("{}#{}", root.as_string(), endpoint))
+}
+
+/// The node exposing endpoint `endpoint` of device `root`, supporting
+/// `classes`.
+pub fn endpoint(root: &Id<NodeId>, endpoint: u8, classes: &[CommandClass]) -> Node {
+    node(endpoint_id(root, endpoint), classes)
+        .with_tags(TagId::new(&format!("{}{}", ENDPOINT_OF, root.as_string())).into_iter().collect())
+}
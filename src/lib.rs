@@ -32,24 +32,242 @@
 //! - a `Service<Input>` with `ServiceKind::OnOff`, to determine whether the light is on or off;
 //! - a `Service<Output>` with `ServiceKind::Color`, to change the color of the light;
 //! - ...
+//!
+//!
+//! # Without the standard library
+//!
+//! Firmware and embedded bridges may construct and parse the values
+//! and selectors of the FoxBox with the very same definitions, by
+//! disabling the default feature `std`. The crate is then `no_std`,
+//! only requires `alloc`, and provides modules `devices`, `values`,
+//! `selector` and `util`, with the following restrictions:
+//!
+//! - values of type `Json`, i.e. `Value::Json`, `Value::JsonPatch` and
+//!   `Value::Compressed`, are not available, as they are built upon
+//!   `serde_json`;
+//! - ids are not interned and cannot be generated randomly, and
+//!   `TimeStamp::now` is not available;
+//! - values cannot be serialized, as serde 0.6 itself requires the
+//!   standard library. The implementations of `Serialize` and
+//!   `Deserialize` are behind feature `serde`, so that they can be
+//!   enabled once serde builds without it.
+//!
+//!
+//! # Layers
+//!
+//! The crate is built in three layers, each depending only on the
+//! layers below it:
+//!
+//! - the core data model, i.e. modules `devices`, `values`, `selector`
+//!   and `util`, always available;
+//! - the API, i.e. trait `api::API`, the contract with adapters and
+//!   the types exchanged with clients, e.g. the protocols of modules
+//!   `websocket`, `sse` and `rest`, with feature `std`;
+//! - the runtime, i.e. the manager, its worker threads and schedulers,
+//!   the persistent stores and the helpers that run alongside them,
+//!   i.e. modules `manager`, `watchstore`, `replay`, `derived` and
+//!   `condition`, with feature `runtime`.
+//!
+//! Both features are enabled by default. Clients that only talk to a
+//! FoxBox, e.g. through the WebSocket protocol, may disable feature
+//! `runtime` to avoid building the rest. The stores of modules
+//! `tagstore` and `history` then disappear, leaving only the types
+//! they share with the API.
 #![feature(custom_derive, plugin)]
-#![plugin(serde_macros)]
+#![cfg_attr(feature = "serde", plugin(serde_macros))]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
 
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 extern crate chrono;
+#[cfg(feature = "serde")]
 extern crate serde;
+#[cfg(feature = "std")]
 extern crate serde_json;
 
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+
+#[cfg(feature = "arbitrary")]
+extern crate quickcheck;
+
 /// Metadata on devices
 pub mod devices;
 
 /// Public-facing API
+#[cfg(feature = "std")]
 pub mod api;
 
+/// The contract between the FoxBox and its adapters.
+#[cfg(feature = "std")]
+pub mod adapter;
+
+/// A reference implementation of the API on top of adapters.
+#[cfg(feature = "runtime")]
+pub mod manager;
+
+/// Recording and replaying the traffic of adapters.
+#[cfg(feature = "runtime")]
+pub mod replay;
+
+/// Virtual getters, whose values are derived from other getters.
+#[cfg(feature = "runtime")]
+pub mod derived;
+
+/// Watching getters enter and leave ranges of values, for rules engines.
+#[cfg(feature = "runtime")]
+pub mod condition;
+
+/// Devices found on the network, waiting to be adopted.
+#[cfg(feature = "std")]
+pub mod discovery;
+
+/// Counters and histograms on the traffic of channels and adapters.
+#[cfg(feature = "std")]
+pub mod metrics;
+
+/// Persisting the tags set by users.
+#[cfg(feature = "std")]
+pub mod tagstore;
+
+/// Recording the values produced by getters.
+#[cfg(feature = "std")]
+pub mod history;
+
+/// Persisting the watches that must survive a restart.
+#[cfg(feature = "runtime")]
+pub mod watchstore;
+
+/// Exporting and importing the state of the taxonomy.
+#[cfg(feature = "std")]
+pub mod document;
+
+/// Migrating persisted data to newer formats.
+#[cfg(feature = "std")]
+pub mod migration;
+
+/// Golden examples of the wire format, checked against the current code.
+#[cfg(feature = "std")]
+pub mod compat;
+
+/// Identification and authorization of the callers of the API.
+#[cfg(feature = "std")]
+pub mod auth;
+
+/// Auditing of the operations performed through the API.
+#[cfg(feature = "std")]
+pub mod audit;
+
+/// Arbitration between conflicting writes.
+#[cfg(feature = "std")]
+pub mod arbitration;
+
+/// Enforcing quotas on the API.
+#[cfg(feature = "std")]
+pub mod ratelimit;
+
+/// Caching the results of the API.
+#[cfg(feature = "std")]
+pub mod cache;
+
+/// Implementations of the API for smart pointers and references.
+#[cfg(feature = "std")]
+mod forward;
+
+/// Building blocks shared by the implementations of the API.
+#[cfg(feature = "std")]
+mod delivery;
+
+/// A strongly-typed layer over the API, for Rust clients.
+#[cfg(feature = "std")]
+pub mod typed;
+
+/// An in-memory implementation of the API, designed for testing.
+#[cfg(feature = "std")]
+pub mod fake;
+
+/// Declaring topologies concisely, for tests.
+#[cfg(feature = "std")]
+pub mod topology;
+
+/// The protocol used to watch channels over WebSockets.
+#[cfg(feature = "std")]
+pub mod websocket;
+
+/// Delivering watch events as Server-Sent Events.
+#[cfg(feature = "std")]
+pub mod sse;
+
+/// The REST endpoints of the API, as data.
+#[cfg(feature = "std")]
+pub mod rest;
+
+/// Binary values as raw and multipart HTTP bodies.
+#[cfg(feature = "std")]
+pub mod body;
+
+/// A GraphQL view of the topology.
+#[cfg(feature = "graphql")]
+pub mod graphql;
+
+/// Protocol Buffers encoding of the core types.
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
+/// Simulated adapters, for developing without hardware.
+#[cfg(feature = "simulator")]
+pub mod simulator;
+
+/// Generators of the core types, for property-based testing.
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+
+/// Entry points for fuzzing the parsing of untrusted input.
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+
+/// Mappings between the taxonomy and other ecosystems.
+#[cfg(feature = "std")]
+pub mod interop;
+
+/// Entry points for JavaScript, once compiled to WebAssembly.
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
+pub mod wasm;
+
 /// Selecting one or more devices. Exposed through the API.
 pub mod selector;
 
 /// Values that may be sent to/received from devices
 pub mod values;
 
+/// Patches of JSON values.
+#[cfg(feature = "std")]
+pub mod patch;
+
+/// Compression of large values on the wire.
+#[cfg(feature = "std")]
+pub mod compression;
+
 /// Various utilities
 pub mod util;
+
+/// The parts of the standard library used by the core modules, when
+/// building without it.
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+mod std {
+    pub use core::*;
+    pub use alloc::{ borrow, boxed, fmt, string, sync, vec };
+
+    /// The items of the prelude of the standard library that are not
+    /// in the prelude of `core`.
+    pub mod prelude {
+        pub use alloc::borrow::ToOwned;
+        pub use alloc::boxed::Box;
+        pub use alloc::string::{ String, ToString };
+        pub use alloc::vec::Vec;
+    }
+}
@@ -0,0 +1,264 @@
+//!
+//! Indexing the topology, so that selectors do not scan all channels.
+//!
+//! The manager evaluates selectors on every request and on every change
+//! of topology, for each watch. With thousands of channels, scanning
+//! all of them each time is too slow. The `Index` maps each tag and
+//! each kind to the nodes and channels that carry it, so that the
+//! candidates for a selector are the intersection of a few sets. The
+//! candidates must still be checked with the selector, as the index
+//! ignores the other criteria.
+//!
+//! The index is updated incrementally when nodes or channels are
+//! registered or removed and when tags are edited. It also records the
+//! handle of each node in the `Slab` of nodes of the manager, along
+//! with the position of each channel within its node.
+//!
+
+use devices::{ Channel, ChannelKind, Getter, Node, NodeId, Setter };
+use selector::{ GetterSelector, NodeSelector, SetterSelector };
+use util::{ Exactly, Id, TagId };
+
+use std::collections::{ HashMap, HashSet };
+use std::hash::Hash;
+
+/// The entities of one type carrying each tag and each kind.
+struct Postings<K> where K: Hash + Eq {
+    by_tag: HashMap<TagId, HashSet<K>>,
+    by_kind: HashMap<ChannelKind, HashSet<K>>,
+}
+
+impl<K> Postings<K> where K: Hash + Eq + Clone {
+    fn new() -> Self {
+        Postings {
+            by_tag: HashMap::new(),
+            by_kind: HashMap::new(),
+        }
+    }
+
+    fn tag(&mut self, key: &K, tags: &[TagId]) {
+        for tag in tags {
+            self.by_tag.entry(tag.clone()).or_insert_with(HashSet::new).insert(key.clone());
+        }
+    }
+
+    fn untag(&mut self, key: &K, tags: &[TagId]) {
+        for tag in tags {
+            let empty = match self.by_tag.get_mut(tag) {
+                None => continue,
+                Some(keys) => {
+                    keys.remove(key);
+                    keys.is_empty()
+                }
+            };
+            if empty {
+                self.by_tag.remove(tag);
+            }
+        }
+    }
+
+    fn add_kind(&mut self, key: &K, kind: &ChannelKind) {
+        self.by_kind.entry(kind.clone()).or_insert_with(HashSet::new).insert(key.clone());
+    }
+
+    fn remove_kind(&mut self, key: &K, kind: &ChannelKind) {
+        let empty = match self.by_kind.get_mut(kind) {
+            None => return,
+            Some(keys) => {
+                keys.remove(key);
+                keys.is_empty()
+            }
+        };
+        if empty {
+            self.by_kind.remove(kind);
+        }
+    }
+
+    /// The entities that may match a selector restricted to `id`,
+    /// `tags` and `kind`, or `None` if none of these restrictions
+    /// applies, i.e. if all entities may match.
+    fn candidates(&self, id: &Exactly<K>, tags: &[TagId], kind: Option<&Exactly<ChannelKind>>) -> Option<HashSet<K>> {
+        let mut result: Option<HashSet<K>> = match *id {
+            Exactly::Empty => None,
+            Exactly::Exactly(ref id) => {
+                let mut ids = HashSet::new();
+                ids.insert(id.clone());
+                Some(ids)
+            }
+            Exactly::Conflict => return Some(HashSet::new()),
+        };
+        let mut sets = vec![];
+        for tag in tags {
+            match self.by_tag.get(tag) {
+                None => return Some(HashSet::new()),
+                Some(keys) => sets.push(keys)
+            }
+        }
+        match kind {
+            None | Some(&Exactly::Empty) => {}
+            Some(&Exactly::Conflict) => return Some(HashSet::new()),
+            Some(&Exactly::Exactly(ref kind)) => match self.by_kind.get(kind) {
+                None => return Some(HashSet::new()),
+                Some(keys) => sets.push(keys)
+            }
+        }
+        // Start with the smallest set, to keep intersections small.
+        sets.sort_by_key(|keys| keys.len());
+        for keys in sets {
+            result = Some(match result {
+                None => keys.clone(),
+                Some(result) => result.into_iter().filter(|key| keys.contains(key)).collect()
+            });
+        }
+        result
+    }
+}
+
+/// The inverted indexes of the topology, along with the position of
+/// each node and channel in the nodes of the manager.
+pub struct Index {
+    nodes: Postings<Id<NodeId>>,
+    getters: Postings<Id<Getter>>,
+    setters: Postings<Id<Setter>>,
+
+    node_positions: HashMap<Id<NodeId>, usize>,
+    getter_positions: HashMap<Id<Getter>, (usize, usize)>,
+    setter_positions: HashMap<Id<Setter>, (usize, usize)>,
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Index {
+            nodes: Postings::new(),
+            getters: Postings::new(),
+            setters: Postings::new(),
+            node_positions: HashMap::new(),
+            getter_positions: HashMap::new(),
+            setter_positions: HashMap::new(),
+        }
+    }
+
+    /// Index a node, along with its channels, stored at `position`.
+    pub fn add_node(&mut self, position: usize, node: &Node) {
+        self.nodes.tag(&node.id, &node.tags);
+        self.node_positions.insert(node.id.clone(), position);
+        for (index, channel) in node.getters.iter().enumerate() {
+            self.add_getter((position, index), channel);
+        }
+        for (index, channel) in node.setters.iter().enumerate() {
+            self.add_setter((position, index), channel);
+        }
+    }
+
+    /// Index a getter, stored at `position` within the nodes.
+    pub fn add_getter(&mut self, position: (usize, usize), channel: &Channel<Getter>) {
+        self.getters.tag(&channel.id, &channel.tags);
+        self.getters.add_kind(&channel.id, &channel.mechanism.kind);
+        self.getter_positions.insert(channel.id.clone(), position);
+    }
+
+    /// Index a setter, stored at `position` within the nodes.
+    pub fn add_setter(&mut self, position: (usize, usize), channel: &Channel<Setter>) {
+        self.setters.tag(&channel.id, &channel.tags);
+        self.setters.add_kind(&channel.id, &channel.mechanism.kind);
+        self.setter_positions.insert(channel.id.clone(), position);
+    }
+
+    /// Stop indexing a node, along with its channels.
+    pub fn remove_node(&mut self, node: &Node) {
+        self.nodes.untag(&node.id, &node.tags);
+        self.node_positions.remove(&node.id);
+        for channel in &node.getters {
+            self.remove_getter(channel);
+        }
+        for channel in &node.setters {
+            self.remove_setter(channel);
+        }
+    }
+
+    pub fn remove_getter(&mut self, channel: &Channel<Getter>) {
+        self.getters.untag(&channel.id, &channel.tags);
+        self.getters.remove_kind(&channel.id, &channel.mechanism.kind);
+        self.getter_positions.remove(&channel.id);
+    }
+
+    pub fn remove_setter(&mut self, channel: &Channel<Setter>) {
+        self.setters.untag(&channel.id, &channel.tags);
+        self.setters.remove_kind(&channel.id, &channel.mechanism.kind);
+        self.setter_positions.remove(&channel.id);
+    }
+
+    /// The position of a node within the nodes, if it is indexed.
+    pub fn node_position(&self, id: &Id<NodeId>) -> Option<usize> {
+        self.node_positions.get(id).cloned()
+    }
+
+    pub fn getter_position(&self, id: &Id<Getter>) -> Option<(usize, usize)> {
+        self.getter_positions.get(id).cloned()
+    }
+
+    pub fn setter_position(&self, id: &Id<Setter>) -> Option<(usize, usize)> {
+        self.setter_positions.get(id).cloned()
+    }
+
+    /// Record that the tags of a node have changed from `before` to
+    /// `after`.
+    pub fn retag_node(&mut self, id: &Id<NodeId>, before: &[TagId], after: &[TagId]) {
+        self.nodes.untag(id, before);
+        self.nodes.tag(id, after);
+    }
+
+    pub fn retag_getter(&mut self, id: &Id<Getter>, before: &[TagId], after: &[TagId]) {
+        self.getters.untag(id, before);
+        self.getters.tag(id, after);
+    }
+
+    pub fn retag_setter(&mut self, id: &Id<Setter>, before: &[TagId], after: &[TagId]) {
+        self.setters.untag(id, before);
+        self.setters.tag(id, after);
+    }
+
+    /// The positions of the nodes that may match _either_ of
+    /// `selectors`, in increasing order, or `None` if all nodes may
+    /// match.
+    pub fn nodes<'a, I>(&self, selectors: I) -> Option<Vec<usize>> where I: IntoIterator<Item = &'a NodeSelector> {
+        let mut result = HashSet::new();
+        for selector in selectors {
+            match self.nodes.candidates(&selector.id, &selector.tags, None) {
+                None => return None,
+                Some(ids) => result.extend(ids.iter().filter_map(|id| self.node_positions.get(id)).cloned())
+            }
+        }
+        Some(sorted(result))
+    }
+
+    /// As `Index::nodes`, for getters.
+    pub fn getters<'a, I>(&self, selectors: I) -> Option<Vec<(usize, usize)>> where I: IntoIterator<Item = &'a GetterSelector> {
+        let mut result = HashSet::new();
+        for selector in selectors {
+            match self.getters.candidates(&selector.id, &selector.tags, Some(&selector.kind)) {
+                None => return None,
+                Some(ids) => result.extend(ids.iter().filter_map(|id| self.getter_positions.get(id)).cloned())
+            }
+        }
+        Some(sorted(result))
+    }
+
+    /// As `Index::nodes`, for setters.
+    pub fn setters<'a, I>(&self, selectors: I) -> Option<Vec<(usize, usize)>> where I: IntoIterator<Item = &'a SetterSelector> {
+        let mut result = HashSet::new();
+        for selector in selectors {
+            match self.setters.candidates(&selector.id, &selector.tags, Some(&selector.kind)) {
+                None => return None,
+                Some(ids) => result.extend(ids.iter().filter_map(|id| self.setter_positions.get(id)).cloned())
+            }
+        }
+        Some(sorted(result))
+    }
+}
+
+fn sorted<T>(set: HashSet<T>) -> Vec<T> where T: Hash + Eq + Ord {
+    let mut result: Vec<_> = set.into_iter().collect();
+    result.sort();
+    result
+}
@@ -0,0 +1,3431 @@
+//!
+//! A reference implementation of the API on top of adapters.
+//!
+//! `AdapterManager` owns the registry of adapters, nodes and channels.
+//! Adapters populate it through `adapter::AdapterManagerHandle`, while
+//! applications query it through `api::API`. The manager evaluates
+//! selectors, manages tags, labels and tagging rules, arbitrates
+//! between writes, routes fetches and writes to the adapter managing
+//! each channel and dispatches the values produced by adapters to the
+//! watches of applications.
+//!
+//! Selectors are evaluated against inverted indexes of the tags and
+//! kinds of nodes and channels, so that requests and watches do not
+//! scan the whole topology.
+//!
+//! The manager only asks an adapter to watch a getter while at least
+//! one application watches its values. The watches interested in the
+//! values of each getter are determined when the topology or the
+//! watches change, so that dispatching a value only involves the
+//! watches that receive it.
+//!
+//! If polling is enabled with `AdapterManager::with_polling`, watching
+//! is emulated for getters that do not support watching: they are
+//! polled by a background thread while at least one application
+//! watches their values, and watches only receive values that differ
+//! from the previous ones, unless specified otherwise with
+//! `WatchOptions::with_emulation`. See `PollingPolicy`.
+//!
+//! Fetches and sends are dispatched to worker threads dedicated to each
+//! adapter, through bounded queues, so that an adapter that blocks
+//! cannot delay requests destined to other adapters. Requests are
+//! abandoned after a timeout, and channels failing with a transient
+//! error may be attempted again, as specified for each adapter by the
+//! `RetryPolicy` of each type of operation. See `DispatchPolicy`.
+//!
+//! Changes in the lifecycle of adapters are reported to the listeners
+//! registered with `AdapterManager::with_listener` and to the watches of
+//! the topology, along with the resulting changes in the availability
+//! of nodes.
+//!
+//! The manager keeps the metrics of adapters and channels, see module
+//! `metrics`, along with the latest value received from each getter,
+//! which may be served while the device cannot be reached, see
+//! `AdapterManager::with_offline_fallback`.
+//!
+//! The manager stores the configuration of adapters, which remains
+//! available while adapters are removed and registered again.
+//!
+//! Tags may be persisted with `AdapterManager::with_tag_store`, see
+//! module `tagstore`, watches may be persisted with
+//! `AdapterManager::with_watch_store`, see module `watchstore`, and the
+//! values of getters may be recorded with `AdapterManager::with_history`,
+//! see module `history`.
+//!
+//! The topology may be saved with `AdapterManager::snapshot` and
+//! restored after a restart with `AdapterManager::restore`, so that
+//! known nodes are presented before adapters have found them again.
+//! What users have set up may be exported with
+//! `AdapterManager::export_state` and imported, possibly on another
+//! FoxBox, with `AdapterManager::import_state`, see module `document`.
+//!
+//! The manager implements `discovery::Discovery`, and keeps the devices
+//! announced by adapters until users adopt or reject them.
+//!
+//! Optional features `PollNow`, `MultiSampleFetch`, `StateChannels`,
+//! `TaggingRules`, `WatchTokens`, `WatchIntrospection`, `WatchBatching`,
+//! `WatchBackpressure`, `WatchExpiration`, `WatchMinDelta`,
+//! `ScheduledWrites`, `Reservations`, `AggregateGetters`,
+//! `PollingHints`, `AdapterHealth`, `Metrics`, `AdapterConfig`,
+//! `Discovery`, `History` and `DurableWatches` are supported. Other optional features report `Error::Unsupported`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use foxbox_taxonomy::manager::*;
+//!
+//! let manager = Arc::new(AdapterManager::new());
+//! manager.add_adapter(Arc::new(MyAdapter::new(manager.clone()))).unwrap();
+//! let nodes = manager.get_nodes(&Caller::system(), &vec![NodeSelector::new()]);
+//! ```
+//!
+
+use adapter::{ is_namespaced, Adapter, AdapterError, AdapterEvent, AdapterListener, AdapterManagerHandle, AdapterWatchGuard, RegistrationError };
+use api::*;
+use arbitration::{ ArbitrationPolicy, Priority, WriteRecord };
+use auth::{ Caller, CallerId, Operation, PermissionPolicy, ScopePolicy };
+use compression;
+use derived::{ DerivedAdapter, DerivedError, DerivedGetter };
+use delivery::{ self, add_tags, deliver, remove_tags, Callback, Deliveries };
+use delivery::batch::Batch;
+use delivery::buffer::Buffer;
+use delivery::expiry::Expiry;
+use delivery::timer::{ TaskId, Timer };
+use devices::*;
+use history::{ self, Bucket, HistoryPolicy, HistoryStore, RetentionPolicy, Sample };
+use discovery::{ Announcement, Candidate, CandidateId, CandidateStatus, Discovery };
+use document::{ Annotation, Document, ImportReport };
+use metrics::{ Metrics, Registry, Target };
+use selector::*;
+use tagstore::{ StoreError, TagStore };
+use watchstore::{ Subscription, WatchStore };
+use values::{ Json, TimeStamp, Value, ValDuration };
+use util::{ Id, IdRegistry, TagId, ID_SEPARATOR };
+
+use std::cmp;
+use std::collections::{ HashMap, HashSet };
+use std::sync::{ Arc, Mutex, Weak };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+mod scheduler;
+pub use self::scheduler::PollingPolicy;
+use self::scheduler::Scheduler;
+
+mod worker;
+pub use self::worker::{ DispatchPolicy, RetryPolicies, RetryPolicy };
+use self::worker::Worker;
+
+mod snapshot;
+pub use self::snapshot::{ KnownNode, Snapshot };
+
+mod index;
+use self::index::Index;
+
+mod slab;
+use self::slab::Slab;
+
+/// The value to cache for a getter that has produced `value`, given
+/// the value currently cached. A `Value::JsonPatch` is applied to the
+/// cached document, so that the cache only holds whole documents.
+/// Returns `None` if the patch cannot be applied, in which case the
+/// cached value is stale.
+fn value_to_cache(cached: Option<&FetchedValue>, value: &Value) -> Option<Value> {
+    let patch = match *value {
+        Value::JsonPatch(ref patch) => patch,
+        _ => return Some(value.clone())
+    };
+    match cached.map(|fetched| &fetched.value) {
+        Some(&Value::Json(ref json)) => patch.apply(&json.0).ok().map(|json| Value::Json(Arc::new(Json(json)))),
+        _ => None
+    }
+}
+
+// Nodes and channels are shared with the results of queries, so they
+// are copied before they are modified, if they are still shared.
+
+fn node_mut(nodes: &mut Slab<Arc<Node>>, position: usize) -> &mut Node {
+    Arc::make_mut(&mut nodes[position])
+}
+
+fn getter_mut(nodes: &mut Slab<Arc<Node>>, (position, index): (usize, usize)) -> &mut Channel<Getter> {
+    Arc::make_mut(&mut node_mut(nodes, position).getters[index])
+}
+
+fn setter_mut(nodes: &mut Slab<Arc<Node>>, (position, index): (usize, usize)) -> &mut Channel<Setter> {
+    Arc::make_mut(&mut node_mut(nodes, position).setters[index])
+}
+
+/// A write scheduled with `API::put_channel_value_at`.
+struct Scheduled {
+    write: ScheduledWrite,
+
+    /// The caller on behalf of which the write is executed.
+    caller: Caller,
+
+    /// The task executing the write, on the timer of the manager.
+    task: TaskId,
+}
+
+struct Watch {
+    info: WatchInfo,
+    cb: Callback,
+
+    /// The caller on behalf of which the watch has been registered.
+    caller: Id<CallerId>,
+
+    /// The latest value obtained by polling each getter that does not
+    /// support watching, or `None` if polling has failed.
+    last: HashMap<Id<Getter>, Option<Value>>,
+
+    /// If `Some(owner)`, the watch has been registered with
+    /// `API::register_durable_watch` under this owner.
+    durable: Option<String>,
+
+    /// If the watch has been registered with
+    /// `WatchOptions::with_backpressure`, the buffer through which `cb`
+    /// delivers events.
+    buffer: Option<Arc<Buffer>>,
+
+    /// The latest value delivered for each getter, if the watch has
+    /// been registered with `WatchOptions::with_min_delta`.
+    delivered: HashMap<Id<Getter>, Value>,
+}
+
+impl Watch {
+    /// Determine whether a value produced by `channel` should be
+    /// delivered, as far as `WatchOptions::with_min_delta` is
+    /// concerned, and record it as delivered if so.
+    fn accepts(&mut self, channel: &Channel<Getter>, value: &Value) -> bool {
+        delivery::accepts(&self.info.options, &mut self.delivered, channel, value)
+    }
+}
+
+struct State {
+    adapters: HashMap<Id<AdapterId>, Arc<Adapter>>,
+
+    /// The workers dispatching fetches and sends to each adapter.
+    workers: HashMap<Id<AdapterId>, Worker>,
+
+    /// How to start the workers of adapters.
+    dispatch: DispatchPolicy,
+
+    metrics: Arc<Registry>,
+
+    /// The options of the configuration of adapters that have been set.
+    config: HashMap<Id<AdapterId>, HashMap<String, Value>>,
+
+    /// Where the tags of nodes and channels are persisted, if anywhere.
+    tag_store: Option<Box<TagStore>>,
+
+    /// Where durable watches are persisted, if anywhere.
+    watch_store: Option<Box<WatchStore>>,
+
+    /// Where the values of getters are recorded, if anywhere, and
+    /// which getters are recorded.
+    history: Option<Box<HistoryStore>>,
+    history_policy: HistoryPolicy,
+
+    /// The devices announced by adapters, along with those that have
+    /// been adopted or rejected.
+    candidates: HashMap<Id<CandidateId>, Candidate>,
+
+    /// The nodes, by handle. See module `slab`.
+    nodes: Slab<Arc<Node>>,
+
+    /// The inverted indexes of `nodes`, used to evaluate selectors and
+    /// to find nodes and channels by id.
+    index: Index,
+
+    /// The adapter managing each node.
+    owners: HashMap<Id<NodeId>, Id<AdapterId>>,
+
+    /// The nodes restored from a snapshot that their adapter has not
+    /// registered again yet. They are unavailable.
+    restored: HashSet<Id<NodeId>>,
+
+    /// The adapters that have stopped or failed. Their nodes are
+    /// unavailable.
+    unavailable: HashSet<Id<AdapterId>>,
+
+    /// The error reported by each failed adapter.
+    failures: HashMap<Id<AdapterId>, String>,
+
+    /// The extension kinds described by adapters, along with the
+    /// adapter that described each of them, in the order in which they
+    /// were first described.
+    kinds: Vec<(Id<AdapterId>, KindDescription)>,
+
+    /// The current revision of the topology.
+    revision: u64,
+
+    /// A counter used to generate ids.
+    counter: u64,
+
+    /// The latest accepted write to each setter channel.
+    writes: HashMap<Id<Setter>, WriteRecord>,
+
+    /// The latest value received from each getter channel, whether it
+    /// has been fetched, polled or sent by the adapter.
+    last_values: HashMap<Id<Getter>, FetchedValue>,
+
+    /// If `Some(max_age)`, `API::get_channel_value` answers from
+    /// `last_values` for the getters that cannot be reached. See
+    /// `AdapterManager::with_offline_fallback`.
+    fallback: Option<ValDuration>,
+
+    tagging_rules: Vec<(Id<TaggingRuleId>, TaggingRule)>,
+    watches: HashMap<Id<WatchId>, Watch>,
+
+    /// The polling hints requested with `API::put_polling_hint`. Hints
+    /// that have expired are ignored by the scheduler, and removed
+    /// whenever hints are added or listed.
+    hints: HashMap<Id<PollingHintId>, PollingHint>,
+
+    /// The writes scheduled and not executed yet. A write is removed
+    /// once it starts executing.
+    schedules: HashMap<Id<ScheduleId>, Scheduled>,
+
+    /// The reservations obtained with `API::reserve_setters`.
+    /// Reservations that have expired are ignored, and removed whenever
+    /// reservations are added or listed.
+    reservations: HashMap<Id<ReservationId>, Reservation>,
+
+    /// The aggregate getters registered with `API::put_aggregate_getter`.
+    aggregates: HashMap<Id<Getter>, AggregateGetter>,
+
+    /// The watches interested in the values of each getter channel, so
+    /// that values are dispatched without evaluating the selectors of
+    /// all watches. Updated with `State::update_interests` whenever the
+    /// topology or the watches change, so it may still list watches
+    /// that have been removed since.
+    interests: HashMap<Id<Getter>, Vec<Id<WatchId>>>,
+
+    /// The watches started on adapters, for the getter channels whose
+    /// values are currently watched by applications.
+    adapter_watches: HashMap<Id<Getter>, Box<AdapterWatchGuard>>,
+}
+
+impl State {
+    fn new_id<T>(&mut self, prefix: &str) -> Id<T> {
+        self.counter += 1;
+        Id::new(format!("{}-{}", prefix, self.counter))
+    }
+
+    /// Record that `adapter` does not announce `candidate` anymore, or
+    /// any candidate if `None`. Pending candidates that are not
+    /// announced by any adapter are forgotten, while the decision on
+    /// other candidates is kept.
+    fn withdraw_candidates(&mut self, adapter: &Id<AdapterId>, candidate: Option<&Id<CandidateId>>) {
+        for (id, entry) in self.candidates.iter_mut() {
+            if candidate.map_or(true, |candidate| candidate == id) {
+                entry.withdrawn(adapter);
+            }
+        }
+        self.candidates.retain(|_, entry| entry.status != CandidateStatus::Pending || !entry.adapters.is_empty());
+    }
+
+    fn has_getter(&self, id: &Id<Getter>) -> bool {
+        self.index.getter_position(id).is_some()
+    }
+
+    fn has_setter(&self, id: &Id<Setter>) -> bool {
+        self.index.setter_position(id).is_some()
+    }
+
+    /// The handles in `nodes` of the nodes that may match _either_ of
+    /// `selectors`, in order. See `Index`.
+    fn node_positions(&self, selectors: &[NodeSelector]) -> Vec<usize> {
+        self.index.nodes(selectors).unwrap_or_else(|| self.nodes.handles())
+    }
+
+    /// The positions of the getters that may match _either_ of
+    /// `selectors`, as pairs of the handle of the node and the
+    /// position of the channel within the node, in order.
+    fn getter_positions<'a, I>(&self, selectors: I) -> Vec<(usize, usize)> where I: IntoIterator<Item = &'a GetterSelector> {
+        self.index.getters(selectors).unwrap_or_else(|| {
+            self.nodes.entries()
+                .flat_map(|(position, node)| (0..node.getters.len()).map(move |index| (position, index)))
+                .collect()
+        })
+    }
+
+    /// As `getter_positions`, for setters.
+    fn setter_positions(&self, selectors: &[SetterSelector]) -> Vec<(usize, usize)> {
+        self.index.setters(selectors).unwrap_or_else(|| {
+            self.nodes.entries()
+                .flat_map(|(position, node)| (0..node.setters.len()).map(move |index| (position, index)))
+                .collect()
+        })
+    }
+
+    /// The nodes matching _either_ of `selectors`.
+    fn matching_nodes(&self, selectors: &[NodeSelector]) -> Vec<&Arc<Node>> {
+        self.node_positions(selectors).into_iter()
+            .map(|position| &self.nodes[position])
+            .filter(|node| selectors.iter().any(|selector| selector.matches(node)))
+            .collect()
+    }
+
+    /// The getters matching _either_ of `selectors`.
+    fn matching_getters(&self, selectors: &[GetterSelector]) -> Vec<&Arc<Channel<Getter>>> {
+        self.getter_positions(selectors).into_iter()
+            .map(|(position, index)| &self.nodes[position].getters[index])
+            .filter(|channel| selectors.iter().any(|selector| selector.matches(channel)))
+            .collect()
+    }
+
+    /// The setters matching _either_ of `selectors`.
+    fn matching_setters(&self, selectors: &[SetterSelector]) -> Vec<&Arc<Channel<Setter>>> {
+        self.setter_positions(selectors).into_iter()
+            .map(|(position, index)| &self.nodes[position].setters[index])
+            .filter(|channel| selectors.iter().any(|selector| selector.matches(channel)))
+            .collect()
+    }
+
+    // As the ids of nodes are prefixed with the id of their adapter,
+    // nodes are routed by prefix.
+    fn adapter_of(&self, node: &Id<NodeId>) -> Option<Arc<Adapter>> {
+        node.adapter().and_then(|adapter| self.adapters.get(&adapter)).cloned()
+    }
+
+    fn worker_of(&self, node: &Id<NodeId>) -> Option<Worker> {
+        node.adapter().and_then(|adapter| self.workers.get(&adapter)).cloned()
+    }
+
+    /// The nodes restored from a snapshot that `node`, registered by
+    /// `adapter`, supersedes, i.e. those of the same adapter that have
+    /// the same id or share a channel with `node`.
+    fn superseded(&self, adapter: &Id<AdapterId>, node: &Node) -> Vec<Id<NodeId>> {
+        self.nodes.iter()
+            .filter(|other| self.restored.contains(&other.id) && self.owners.get(&other.id) == Some(adapter))
+            .filter(|other| other.id == node.id
+                || other.getters.iter().any(|channel| node.getters.iter().any(|new| new.id == channel.id))
+                || other.setters.iter().any(|channel| node.setters.iter().any(|new| new.id == channel.id)))
+            .map(|other| other.id.clone())
+            .collect()
+    }
+
+    /// Check that `node` may be registered by `adapter`, along with
+    /// `pending`, the nodes registered at the same time. Nodes restored
+    /// from a snapshot do not conflict with the nodes that supersede
+    /// them.
+    fn check_node(&self, adapter: &Id<AdapterId>, node: &Node, pending: &[Node]) -> Result<(), RegistrationError> {
+        if !is_namespaced(adapter, &node.id) {
+            return Err(RegistrationError::InvalidId(node.id.as_string().clone()));
+        }
+        let superseded = self.superseded(adapter, node);
+        let existing: Vec<&Arc<Node>> = self.nodes.iter()
+            .filter(|other| !superseded.contains(&other.id))
+            .collect();
+        if existing.iter().any(|other| other.id == node.id) || pending.iter().any(|other| other.id == node.id) {
+            return Err(RegistrationError::DuplicateNode(node.id.clone()));
+        }
+        for (index, channel) in node.getters.iter().enumerate() {
+            if !is_namespaced(adapter, &channel.id) {
+                return Err(RegistrationError::InvalidId(channel.id.as_string().clone()));
+            }
+            let duplicate = existing.iter().any(|other| other.getters.iter().any(|other| other.id == channel.id))
+                || node.getters[..index].iter().any(|other| other.id == channel.id)
+                || pending.iter().any(|other| other.getters.iter().any(|other| other.id == channel.id));
+            if duplicate {
+                return Err(RegistrationError::DuplicateGetter(channel.id.clone()));
+            }
+        }
+        for (index, channel) in node.setters.iter().enumerate() {
+            if !is_namespaced(adapter, &channel.id) {
+                return Err(RegistrationError::InvalidId(channel.id.as_string().clone()));
+            }
+            let duplicate = existing.iter().any(|other| other.setters.iter().any(|other| other.id == channel.id))
+                || node.setters[..index].iter().any(|other| other.id == channel.id)
+                || pending.iter().any(|other| other.setters.iter().any(|other| other.id == channel.id));
+            if duplicate {
+                return Err(RegistrationError::DuplicateSetter(channel.id.clone()));
+            }
+        }
+        for (index, pair) in node.states.iter().enumerate() {
+            if !is_namespaced(adapter, &pair.id) {
+                return Err(RegistrationError::InvalidId(pair.id.as_string().clone()));
+            }
+            let duplicate = existing.iter().any(|other| other.states.iter().any(|other| other.id == pair.id))
+                || node.states[..index].iter().any(|other| other.id == pair.id)
+                || pending.iter().any(|other| other.states.iter().any(|other| other.id == pair.id));
+            if duplicate {
+                return Err(RegistrationError::DuplicateState(pair.id.clone()));
+            }
+            let getter = node.getters.iter().find(|channel| channel.id == pair.getter);
+            let setter = node.setters.iter().find(|channel| channel.id == pair.setter);
+            match (getter, setter) {
+                (Some(getter), Some(setter)) if getter.mechanism.kind == pair.kind && setter.mechanism.kind == pair.kind
+                    && !getter.mechanism.is_event() => {}
+                _ => return Err(RegistrationError::InvalidState(pair.id.clone()))
+            }
+        }
+        Ok(())
+    }
+
+    /// The getter and setter paired by state `id`.
+    fn state_channels(&self, id: &Id<StateId>) -> Option<(Id<Getter>, Id<Setter>)> {
+        self.nodes.iter()
+            .filter_map(|node| node.states.iter().find(|pair| pair.id == *id))
+            .map(|pair| (pair.getter.clone(), pair.setter.clone()))
+            .next()
+    }
+
+    /// Replace the tags and label of a node or channel with those of
+    /// `annotation`. Returns `None` if there is no such node or channel,
+    /// otherwise whether the label has changed.
+    fn annotate(&mut self, annotation: &Annotation) -> Option<bool> {
+        fn apply(tags: &mut Vec<TagId>, label: &mut Option<String>, annotation: &Annotation) -> bool {
+            *tags = annotation.tags.clone();
+            if *label == annotation.label {
+                return false;
+            }
+            *label = annotation.label.clone();
+            true
+        }
+        match annotation.target {
+            Labelled::Node(ref id) => {
+                let position = match self.index.node_position(id) {
+                    None => return None,
+                    Some(position) => position
+                };
+                let node = node_mut(&mut self.nodes, position);
+                self.index.retag_node(id, &node.tags, &annotation.tags);
+                Some(apply(&mut node.tags, &mut node.label, annotation))
+            }
+            Labelled::Getter(ref id) => {
+                let position = match self.index.getter_position(id) {
+                    None => return None,
+                    Some(position) => position
+                };
+                let channel = getter_mut(&mut self.nodes, position);
+                self.index.retag_getter(id, &channel.tags, &annotation.tags);
+                Some(apply(&mut channel.tags, &mut channel.label, annotation))
+            }
+            Labelled::Setter(ref id) => {
+                let position = match self.index.setter_position(id) {
+                    None => return None,
+                    Some(position) => position
+                };
+                let channel = setter_mut(&mut self.nodes, position);
+                self.index.retag_setter(id, &channel.tags, &annotation.tags);
+                Some(apply(&mut channel.tags, &mut channel.label, annotation))
+            }
+        }
+    }
+
+    /// Determine whether the channels of a node cannot be reached, i.e.
+    /// whether its adapter is not running or has not registered it
+    /// again since it was restored.
+    fn is_unreachable(&self, node: &Id<NodeId>) -> bool {
+        match self.owners.get(node) {
+            None => true,
+            Some(adapter) => !self.workers.contains_key(adapter) || self.unavailable.contains(adapter) || self.restored.contains(node)
+        }
+    }
+
+    /// The latest value received from a getter, as served from a cache,
+    /// if it is at most `max_age` old.
+    fn cached_value(&self, id: &Id<Getter>, max_age: &ValDuration) -> Option<FetchedValue> {
+        let fetched = match self.last_values.get(id) {
+            None => return None,
+            Some(fetched) => fetched
+        };
+        let age = TimeStamp::now().duration_since(&fetched.timestamp).unwrap_or(ValDuration::new(Duration::from_millis(0)));
+        if age > *max_age {
+            return None;
+        }
+        Some(FetchedValue {
+            source: ValueSource::Cache,
+            age: Some(age),
+            .. fetched.clone()
+        })
+    }
+
+    /// The retention policy of a getter, or `None` if its values are
+    /// not recorded.
+    fn retention_of(&self, channel: &Channel<Getter>) -> Option<&RetentionPolicy> {
+        match self.history {
+            None => None,
+            Some(_) => self.history_policy.retention_of(channel)
+        }
+    }
+
+    /// Record in the history the samples fetched from a getter, if the
+    /// getter is recorded. Samples that are not more recent than the
+    /// latest recorded sample have already been recorded, typically
+    /// by a previous fetch, and are skipped.
+    fn record_samples(&self, id: &Id<Getter>, samples: &[Sample]) {
+        let store = match self.history {
+            None => return,
+            Some(ref store) => store
+        };
+        let retention = match self.index.getter_position(id)
+            .and_then(|position| self.retention_of(&self.nodes[position.0].getters[position.1])) {
+            None => return,
+            Some(retention) => retention
+        };
+        let mut latest = match samples.first() {
+            None => return,
+            Some(first) => store.get_samples(id, Some(&first.timestamp), None).ok()
+                .and_then(|recorded| recorded.last().map(|sample| sample.timestamp.clone()))
+        };
+        for sample in samples {
+            // The history holds whole documents.
+            if let Value::JsonPatch(_) = sample.value {
+                continue;
+            }
+            if latest.as_ref().map_or(false, |latest| sample.timestamp <= *latest) {
+                continue;
+            }
+            // Failing to record a value should not prevent callers
+            // from receiving it.
+            let _ = store.record(id, sample.clone(), retention);
+            latest = Some(sample.timestamp.clone());
+        }
+    }
+
+    /// Forget the polling hints that have expired.
+    fn purge_hints(&mut self) {
+        let now = TimeStamp::now();
+        self.hints.retain(|_, hint| hint.expires > now);
+    }
+
+    fn purge_reservations(&mut self) {
+        let now = TimeStamp::now();
+        self.reservations.retain(|_, reservation| reservation.expires > now);
+    }
+
+    /// Fail with `Error::Reserved` if `setter` is reserved by a caller
+    /// other than `caller`.
+    fn check_reservations(&self, setter: &Id<Setter>, caller: &Id<CallerId>) -> Result<(), Error> {
+        let now = TimeStamp::now();
+        let reservation = self.reservations.values().find(|reservation| {
+            reservation.expires > now && reservation.holder != *caller && reservation.setters.contains(setter)
+        });
+        match reservation {
+            None => Ok(()),
+            Some(reservation) => Err(Error::Reserved {
+                setter: setter.clone(),
+                holder: reservation.holder.clone(),
+                expires: reservation.expires.clone(),
+            })
+        }
+    }
+
+    /// Restore the tags stored for a node or channel, if any.
+    fn restore_tags(&self, target: Labelled, tags: &mut Vec<TagId>) {
+        if let Some(ref store) = self.tag_store {
+            // A store that cannot be read should not prevent devices
+            // from being registered.
+            if let Ok(Some(stored)) = store.get_tags(&target) {
+                add_tags(tags, &stored);
+            }
+        }
+    }
+
+    /// Persist the tags of the nodes or channels that have changed.
+    fn store_tags(&self, changed: Vec<(Labelled, Vec<TagId>)>) -> Result<(), Error> {
+        if let Some(ref store) = self.tag_store {
+            for (target, tags) in changed {
+                try!(store.set_tags(&target, &tags).map_err(Error::TagStore));
+            }
+        }
+        Ok(())
+    }
+
+    /// Register a node checked with `check_node`. The nodes restored
+    /// from a snapshot that it supersedes are replaced, and their tags
+    /// and labels carried over.
+    fn insert_node(&mut self, adapter: &Id<AdapterId>, mut node: Node) {
+        let superseded = self.superseded(adapter, &node);
+        for old in self.retire_nodes(&superseded) {
+            if old.id == node.id {
+                add_tags(&mut node.tags, &old.tags);
+                if old.label.is_some() {
+                    node.label = old.label.clone();
+                }
+            }
+            for old in &old.getters {
+                if let Some(channel) = node.getters.iter_mut().find(|channel| channel.id == old.id) {
+                    let channel = Arc::make_mut(channel);
+                    add_tags(&mut channel.tags, &old.tags);
+                    if old.label.is_some() {
+                        channel.label = old.label.clone();
+                    }
+                }
+            }
+            for old in &old.setters {
+                if let Some(channel) = node.setters.iter_mut().find(|channel| channel.id == old.id) {
+                    let channel = Arc::make_mut(channel);
+                    add_tags(&mut channel.tags, &old.tags);
+                    if old.label.is_some() {
+                        channel.label = old.label.clone();
+                    }
+                }
+            }
+        }
+        self.restore_tags(Labelled::Node(node.id.clone()), &mut node.tags);
+        for channel in &mut node.getters {
+            let channel = Arc::make_mut(channel);
+            channel.node = node.id.clone();
+            self.restore_tags(Labelled::Getter(channel.id.clone()), &mut channel.tags);
+        }
+        for channel in &mut node.setters {
+            let channel = Arc::make_mut(channel);
+            channel.node = node.id.clone();
+            self.restore_tags(Labelled::Setter(channel.id.clone()), &mut channel.tags);
+        }
+        self.owners.insert(node.id.clone(), adapter.clone());
+        let position = self.nodes.insert(Arc::new(node));
+        self.index.add_node(position, &self.nodes[position]);
+        self.apply_tagging_rules(position);
+    }
+
+    /// Apply `change` to the node at `position`, e.g. removing one of
+    /// its channels, then index the node again, as the positions of its
+    /// channels may have changed.
+    fn reindex_node<F>(&mut self, position: usize, change: F) where F: FnOnce(&mut Node) {
+        self.index.remove_node(&self.nodes[position]);
+        change(node_mut(&mut self.nodes, position));
+        self.index.add_node(position, &self.nodes[position]);
+    }
+
+    /// Remove nodes, along with everything the manager knows about
+    /// their channels. Returns the nodes removed.
+    fn retire_nodes(&mut self, ids: &[Id<NodeId>]) -> Vec<Arc<Node>> {
+        let positions: Vec<_> = ids.iter()
+            .filter_map(|id| self.index.node_position(id))
+            .collect();
+        let retired: Vec<_> = positions.into_iter()
+            .filter_map(|position| self.nodes.remove(position))
+            .collect();
+        for node in &retired {
+            self.index.remove_node(node);
+            self.owners.remove(&node.id);
+            self.restored.remove(&node.id);
+            for channel in &node.setters {
+                self.writes.remove(&channel.id);
+                self.metrics.forget(&Target::Setter(channel.id.clone()));
+            }
+            for channel in &node.getters {
+                self.last_values.remove(&channel.id);
+                self.metrics.forget(&Target::Getter(channel.id.clone()));
+                for watch in self.watches.values_mut() {
+                    watch.last.remove(&channel.id);
+                }
+            }
+        }
+        retired
+    }
+
+    /// The getter channels matching `selectors` and `filter`, grouped by
+    /// adapter.
+    fn getters_by_adapter<F>(&self, selectors: &Vec<GetterSelector>, filter: F) -> Vec<(Worker, Vec<Id<Getter>>)>
+        where F: Fn(&Channel<Getter>) -> bool {
+        let mut groups: HashMap<Id<AdapterId>, (Worker, Vec<Id<Getter>>)> = HashMap::new();
+        for channel in self.matching_getters(selectors) {
+            if !filter(channel) {
+                continue;
+            }
+            let worker = match self.worker_of(&channel.node) {
+                None => continue,
+                Some(worker) => worker
+            };
+            groups.entry(worker.adapter().clone())
+                .or_insert_with(|| (worker.clone(), vec![]))
+                .1.push(channel.id.clone());
+        }
+        groups.into_iter().map(|(_, group)| group).collect()
+    }
+
+    /// Queue `event` for each watch with at least one option accepted
+    /// by `filter`.
+    fn notify<F>(&mut self, event: WatchEvent, filter: F) -> Deliveries where F: Fn(&WatchOptions) -> bool {
+        let mut deliveries = vec![];
+        for watch in self.watches.values_mut() {
+            if watch.info.options.iter().any(|options| filter(options)) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), event.clone()));
+            }
+        }
+        deliveries
+    }
+
+    /// The getter channels currently watched by each watch, for the
+    /// purpose of determining which channels have been added or
+    /// removed by a change of topology.
+    fn topology_snapshot(&self) -> HashSet<(Id<WatchId>, Id<Getter>)> {
+        let mut snapshot = HashSet::new();
+        for (id, watch) in &self.watches {
+            for getter in self.topology_of(&watch.info.options) {
+                snapshot.insert((id.clone(), getter));
+            }
+        }
+        snapshot
+    }
+
+    /// The getter channels whose topology is watched by a watch with
+    /// `options`.
+    fn topology_of(&self, options: &[WatchOptions]) -> Vec<Id<Getter>> {
+        self.sources_of(options, |options| options.should_watch_topology)
+    }
+
+    /// The getter channels matching the sources of the `options`
+    /// accepted by `filter`.
+    fn sources_of<F>(&self, options: &[WatchOptions], filter: F) -> Vec<Id<Getter>> where F: Fn(&WatchOptions) -> bool {
+        let sources: Vec<_> = options.iter()
+            .filter(|options| filter(options))
+            .map(|options| &options.source)
+            .collect();
+        self.getter_positions(sources.iter().cloned()).into_iter()
+            .map(|(position, index)| &self.nodes[position].getters[index])
+            .filter(|channel| sources.iter().any(|source| source.matches(channel)))
+            .map(|channel| channel.id.clone())
+            .collect()
+    }
+
+    /// Recompute the watches interested in the values of each getter
+    /// channel.
+    fn update_interests(&mut self) {
+        let mut interests: HashMap<Id<Getter>, Vec<Id<WatchId>>> = HashMap::new();
+        for (id, watch) in &self.watches {
+            for getter in self.sources_of(&watch.info.options, |options| options.should_watch_values) {
+                interests.entry(getter).or_insert_with(Vec::new).push(id.clone());
+            }
+        }
+        self.interests = interests;
+    }
+
+    /// Make a watch durable under `owner`, replacing any other watch
+    /// registered under the same owner, and save it.
+    fn make_durable(&mut self, id: &Id<WatchId>, owner: &str) -> Result<(), StoreError> {
+        let replaced: Vec<_> = self.watches.iter()
+            .filter(|&(other, watch)| other != id && watch.durable.as_ref().map(|durable| &**durable) == Some(owner))
+            .map(|(other, _)| other.clone())
+            .collect();
+        for other in replaced {
+            self.watches.remove(&other);
+        }
+        if let Some(watch) = self.watches.get_mut(id) {
+            watch.durable = Some(owner.to_owned());
+        }
+        self.save_subscription(id)
+    }
+
+    /// Save a durable watch, along with the getters whose topology it
+    /// currently watches. Does nothing if the watch is not durable.
+    fn save_subscription(&self, id: &Id<WatchId>) -> Result<(), StoreError> {
+        let (store, watch) = match (self.watch_store.as_ref(), self.watches.get(id)) {
+            (Some(store), Some(watch)) => (store, watch),
+            _ => return Ok(())
+        };
+        match watch.durable {
+            None => Ok(()),
+            Some(ref owner) => store.save(&Subscription {
+                owner: owner.clone(),
+                options: watch.info.options.clone(),
+                getters: self.topology_of(&watch.info.options),
+                saved: TimeStamp::now(),
+            })
+        }
+    }
+
+    /// Bump the revision and queue `GetterAdded`/`GetterRemoved` for
+    /// the changes since `before`.
+    fn topology_changed(&mut self, before: HashSet<(Id<WatchId>, Id<Getter>)>) -> Deliveries {
+        self.revision += 1;
+        self.update_interests();
+        let after = self.topology_snapshot();
+        let mut deliveries = vec![];
+        for &(ref id, ref getter) in before.difference(&after) {
+            if let Some(watch) = self.watches.get_mut(id) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), WatchEvent::GetterRemoved(getter.clone())));
+            }
+        }
+        for &(ref id, ref getter) in after.difference(&before) {
+            if let Some(watch) = self.watches.get_mut(id) {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), WatchEvent::GetterAdded(getter.clone())));
+            }
+        }
+        let changed: HashSet<_> = before.symmetric_difference(&after)
+            .map(|&(ref id, _)| id.clone())
+            .collect();
+        for id in changed {
+            // Best effort: if the store cannot be written, the missed
+            // changes replayed by `API::resume_watch` may be stale.
+            let _ = self.save_subscription(&id);
+        }
+        deliveries
+    }
+
+    /// Queue `event` and, if the availability of the nodes of `adapter`
+    /// has changed, `NodeAvailability` for each of these nodes.
+    fn lifecycle_changed(&mut self, adapter: &Id<AdapterId>, event: AdapterEvent, available: Option<bool>) -> Deliveries {
+        let mut deliveries = self.notify(WatchEvent::Adapter(event), |options| options.should_watch_topology);
+        if let Some(available) = available {
+            let nodes: Vec<_> = self.nodes.iter()
+                .filter(|node| self.owners.get(&node.id) == Some(adapter))
+                .map(|node| node.id.clone())
+                .collect();
+            for node in nodes {
+                deliveries.append(&mut self.notify(WatchEvent::NodeAvailability {
+                    node: node,
+                    available: available
+                }, |options| options.should_watch_topology));
+            }
+        }
+        deliveries
+    }
+
+    /// Forget the extension kinds described by `adapter`.
+    fn withdraw_kinds(&mut self, adapter: &Id<AdapterId>) -> Deliveries {
+        let (withdrawn, kept): (Vec<_>, Vec<_>) = self.kinds.drain(..)
+            .partition(|&(ref owner, _)| owner == adapter);
+        self.kinds = kept;
+        let mut deliveries = vec![];
+        for (_, description) in withdrawn {
+            deliveries.append(&mut self.notify(WatchEvent::KindRemoved {
+                adapter: adapter.clone(),
+                kind: description.kind
+            }, |options| options.should_watch_topology));
+        }
+        deliveries
+    }
+
+    fn label_changed(&mut self, target: Labelled, label: Option<String>) -> Deliveries {
+        self.notify(WatchEvent::LabelChanged {
+            target: target,
+            label: label,
+        }, |options| options.should_watch_topology)
+    }
+
+    /// Apply the tagging rules to the node at `index`.
+    fn apply_tagging_rules(&mut self, index: usize) {
+        let mut tags = self.nodes[index].tags.clone();
+        let mut changed = false;
+        for &(_, ref rule) in &self.tagging_rules {
+            if rule.set.iter().any(|selector| selector.matches(&self.nodes[index])) {
+                changed |= add_tags(&mut tags, &rule.tags);
+            }
+        }
+        if changed {
+            let node = node_mut(&mut self.nodes, index);
+            self.index.retag_node(&node.id, &node.tags, &tags);
+            node.tags = tags;
+        }
+    }
+
+    /// The getter channels accepted by `filter` whose values are watched
+    /// by at least one watch or recorded, along with their adapter.
+    fn watched_getters<F>(&self, filter: F) -> HashMap<Id<Getter>, Arc<Adapter>> where F: Fn(&Channel<Getter>) -> bool {
+        let mut result = HashMap::new();
+        for node in &self.nodes {
+            let adapter = match self.adapter_of(&node.id) {
+                None => continue,
+                Some(adapter) => adapter
+            };
+            for channel in &node.getters {
+                if !filter(channel) {
+                    continue;
+                }
+                let watched = self.interests.get(&channel.id).map_or(false, |watches| {
+                    watches.iter().any(|watch| self.watches.contains_key(watch))
+                }) || self.retention_of(channel).is_some();
+                if watched {
+                    result.insert(channel.id.clone(), adapter.clone());
+                }
+            }
+        }
+        result
+    }
+
+    /// Dispatch a value or error produced by a getter channel to the
+    /// watches interested in its values.
+    fn produce(&mut self, id: &Id<Getter>, result: Result<Value, Error>) -> Deliveries {
+        let (channel, event) = match self.produced(id, result) {
+            None => return vec![],
+            Some(produced) => produced
+        };
+        let mut deliveries = vec![];
+        if let Some(interested) = self.interests.get(id) {
+            for watch in interested {
+                let watch = match self.watches.get_mut(watch) {
+                    None => continue,
+                    Some(watch) => watch
+                };
+                if let WatchEvent::Value { ref value, .. } = event {
+                    if !watch.accepts(&channel, value) {
+                        continue;
+                    }
+                }
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), event.clone()));
+            }
+        }
+        deliveries
+    }
+
+    /// Dispatch a value or error obtained by polling a getter channel
+    /// that does not support watching to the watches, unless they have
+    /// already received it. See `api::ChangeDetection`.
+    fn produce_polled(&mut self, id: &Id<Getter>, result: Result<Value, Error>) -> Deliveries {
+        let current = match result {
+            Ok(ref value) => Some(value.clone()),
+            Err(_) => None
+        };
+        let (channel, event) = match self.produced(id, result) {
+            None => return vec![],
+            Some(produced) => produced
+        };
+        let mut deliveries = vec![];
+        let interested = match self.interests.get(id) {
+            None => return deliveries,
+            Some(interested) => interested
+        };
+        for watch in interested {
+            let watch = match self.watches.get_mut(watch) {
+                None => continue,
+                Some(watch) => watch
+            };
+            let (watched, always) = {
+                let matching: Vec<_> = watch.info.options.iter()
+                    .filter(|options| options.should_watch_values && options.source.matches(&channel))
+                    .collect();
+                let always = matching.iter().any(|options| match options.emulation {
+                    Some(ref emulation) => emulation.change == ChangeDetection::Always,
+                    None => false
+                });
+                (!matching.is_empty(), always)
+            };
+            if !watched {
+                continue;
+            }
+            let changed = watch.last.get(id) != Some(&current);
+            watch.last.insert(id.clone(), current.clone());
+            let accepted = match (changed || always, current.as_ref()) {
+                (false, _) => false,
+                (true, None) => true,
+                (true, Some(value)) => watch.accepts(&channel, value)
+            };
+            if accepted {
+                watch.info.events += 1;
+                deliveries.push((watch.cb.clone(), event.clone()));
+            }
+        }
+        deliveries
+    }
+
+    /// Record that a getter channel has produced a value or an error,
+    /// including in the history if the channel is recorded. Return the
+    /// channel and the event to dispatch, or `None` if there is no such
+    /// channel.
+    fn produced(&mut self, id: &Id<Getter>, result: Result<Value, Error>) -> Option<(Arc<Channel<Getter>>, WatchEvent)> {
+        let found = match self.index.getter_position(id) {
+            None => None,
+            Some(position) => {
+                if result.is_ok() {
+                    getter_mut(&mut self.nodes, position).mechanism.updated = Some(TimeStamp::now());
+                }
+                Some(self.nodes[position.0].getters[position.1].clone())
+            }
+        };
+        if let (Some(ref channel), &Ok(ref value)) = (found.as_ref(), &result) {
+            // Watches receive patches as such, but the cache and the
+            // history hold whole documents.
+            let value = match value_to_cache(self.last_values.get(id), value) {
+                None => {
+                    self.last_values.remove(id);
+                    None
+                }
+                Some(value) => Some(value)
+            };
+            // Events are not the current value of the channel.
+            let adapter = match self.owners.get(&channel.node) {
+                Some(adapter) if !channel.mechanism.is_event() => Some(adapter.clone()),
+                _ => None
+            };
+            if let (Some(adapter), Some(value)) = (adapter, value.as_ref()) {
+                self.last_values.insert(id.clone(), FetchedValue {
+                    value: value.clone(),
+                    timestamp: TimeStamp::now(),
+                    source: ValueSource::Live,
+                    adapter: adapter,
+                    age: None,
+                });
+            }
+            if let (Some(ref store), Some(retention), Some(value)) = (self.history.as_ref(), self.retention_of(channel), value) {
+                // Failing to record a value should not prevent watches
+                // from receiving it.
+                let _ = store.record(id, Sample {
+                    timestamp: TimeStamp::now(),
+                    value: value,
+                }, retention);
+            }
+        }
+        let event = match result {
+            Ok(value) => WatchEvent::Value {
+                from: id.clone(),
+                value: value
+            },
+            Err(error) => WatchEvent::Error {
+                from: id.clone(),
+                error: error
+            }
+        };
+        found.map(|channel| (channel, event))
+    }
+}
+
+/// Determine whether an error means that the device cannot be reached
+/// at the moment.
+fn is_unreachable(error: &Error) -> bool {
+    match *error {
+        Error::Adapter { ref error, .. } => error.is_transient(),
+        Error::TimedOut { .. } | Error::RetriesExhausted { .. } => true,
+        _ => false
+    }
+}
+
+/// Report the failure to register a channel of the manager itself, e.g.
+/// an aggregate getter, as a permanent failure of its adapter.
+fn registration_error(adapter: &Id<AdapterId>, error: RegistrationError) -> Error {
+    adapter_error(adapter, AdapterError::Permanent(format!("{:?}", error)))
+}
+
+/// Report an error of an adapter to applications.
+fn adapter_error(adapter: &Id<AdapterId>, error: AdapterError) -> Error {
+    Error::Adapter {
+        adapter: adapter.clone(),
+        error: error,
+    }
+}
+
+/// Unregister a watch, forgetting its subscription if it is durable.
+fn remove_watch(state: &Arc<Mutex<State>>, id: &Id<WatchId>) -> Result<(), Error> {
+    let result = {
+        let mut state = state.lock().unwrap();
+        match state.watches.remove(id) {
+            None => return Err(Error::NoSuchWatch(id.clone())),
+            Some(Watch { durable: Some(owner), .. }) => match state.watch_store {
+                Some(ref store) => store.forget(&owner).map_err(Error::WatchStore),
+                None => Ok(())
+            },
+            Some(_) => Ok(())
+        }
+    };
+    sync_adapter_watches(state);
+    result
+}
+
+/// Send a value to the setters matching `selectors`, on behalf of
+/// `caller`. See `API::put_channel_value`.
+fn send_values(state: &Arc<Mutex<State>>, policy: &PermissionPolicy, arbitration: Option<&ArbitrationPolicy>,
+               caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+    let (value, invalid) = match compression::decompressed(value.clone()) {
+        Ok(value) => (value, None),
+        Err(error) => (value, Some(Error::Compression(error)))
+    };
+    let mut results = vec![];
+    let record = WriteRecord {
+        caller: caller.id.clone(),
+        priority: priority,
+        value: value.clone(),
+        timestamp: TimeStamp::now(),
+    };
+    let groups = {
+        let state = state.lock().unwrap();
+        let mut groups: HashMap<Id<AdapterId>, (Worker, Vec<(Id<Setter>, Value)>)> = HashMap::new();
+        for channel in state.matching_setters(selectors) {
+            let worker = match state.worker_of(&channel.node) {
+                None => continue,
+                Some(worker) => worker
+            };
+            let result = policy.check(caller, &Operation::Send {
+                setter: &channel.id,
+                value: &value
+            }).and_then(|_| {
+                state.check_reservations(&channel.id, &caller.id)
+            }).and_then(|_| {
+                match invalid {
+                    Some(ref error) => Err(error.clone()),
+                    None => Ok(())
+                }
+            }).and_then(|_| {
+                if channel.mechanism.kind.get_type() == value.get_type() {
+                    Ok(())
+                } else {
+                    Err(Error::TypeError)
+                }
+            }).and_then(|_| {
+                match (arbitration, state.writes.get(&channel.id)) {
+                    (Some(arbitration), Some(current)) => arbitration.arbitrate(&channel.id, current, &record),
+                    _ => Ok(())
+                }
+            });
+            match result {
+                Ok(()) => groups.entry(worker.adapter().clone())
+                    .or_insert_with(|| (worker.clone(), vec![]))
+                    .1.push((channel.id.clone(), value.clone())),
+                Err(error) => results.push((channel.id.clone(), Err(error)))
+            }
+        }
+        groups
+    };
+
+    // Send to all adapters at once, then wait until they are done.
+    let pending: Vec<_> = groups.into_iter()
+        .map(|(_, (worker, values))| worker.send(values))
+        .collect();
+    let mut sent = vec![];
+    for pending in pending {
+        for (id, result) in pending.wait() {
+            if let Ok(ref ack) = result {
+                sent.push((id.clone(), ack.timestamp.clone()));
+            }
+            results.push((id, result));
+        }
+    }
+
+    let mut state = state.lock().unwrap();
+    let state = &mut *state;
+    for &(ref id, ref timestamp) in &sent {
+        if let Some(position) = state.index.setter_position(id) {
+            setter_mut(&mut state.nodes, position).mechanism.updated = Some(timestamp.clone());
+        }
+    }
+    for (id, _) in sent {
+        state.writes.insert(id, record.clone());
+    }
+    results
+}
+
+/// Start and stop the watches of adapters so that they match the
+/// watches of applications.
+///
+/// Adapters are called without holding the lock, as they may call
+/// back into the manager.
+fn sync_adapter_watches(state: &Arc<Mutex<State>>) {
+    let (to_start, stopped) = {
+        let mut state = state.lock().unwrap();
+        state.update_interests();
+        let wanted = state.watched_getters(|channel| channel.mechanism.watch);
+        let unwanted: Vec<_> = state.adapter_watches.keys()
+            .filter(|id| !wanted.contains_key(id))
+            .cloned()
+            .collect();
+        let stopped: Vec<_> = unwanted.iter()
+            .filter_map(|id| state.adapter_watches.remove(id))
+            .collect();
+        let to_start: Vec<_> = wanted.into_iter()
+            .filter(|&(ref id, _)| !state.adapter_watches.contains_key(id))
+            .collect();
+        (to_start, stopped)
+    };
+    // Stop watching.
+    drop(stopped);
+
+    for (id, adapter) in to_start {
+        let weak: Weak<Mutex<State>> = Arc::downgrade(state);
+        let getter = id.clone();
+        let adapter_id = adapter.id();
+        let cb = Box::new(move |result: Result<Value, AdapterError>| {
+            if let Some(state) = weak.upgrade() {
+                let result = result.map_err(|error| adapter_error(&adapter_id, error));
+                let deliveries = {
+                    let mut state = state.lock().unwrap();
+                    state.metrics.observe_watch_event(&adapter_id, &getter, result.is_ok());
+                    state.produce(&getter, result)
+                };
+                deliver(deliveries);
+            }
+        });
+        match adapter.register_watch(&id, cb) {
+            Ok(guard) => {
+                // If another thread has started a watch in the meantime,
+                // keep only one of them.
+                let previous = state.lock().unwrap().adapter_watches.insert(id, guard);
+                drop(previous);
+            }
+            Err(error) => {
+                let error = adapter_error(&adapter.id(), error);
+                let deliveries = state.lock().unwrap().produce(&id, Err(error));
+                deliver(deliveries);
+            }
+        }
+    }
+}
+
+/// A reference implementation of the API, routing requests to adapters.
+///
+/// Permissions are checked with `auth::ScopePolicy`, unless another
+/// policy is specified with `AdapterManager::with_policy`. Writes are
+/// never suppressed, unless an arbitration policy is specified with
+/// `AdapterManager::with_arbitration`.
+pub struct AdapterManager {
+    state: Arc<Mutex<State>>,
+    policy: Arc<Box<PermissionPolicy>>,
+    arbitration: Option<Arc<Box<ArbitrationPolicy>>>,
+    listeners: Vec<Box<AdapterListener>>,
+
+    /// The thread polling getters, if polling is enabled.
+    scheduler: Mutex<Option<Scheduler>>,
+
+    /// The thread running the tasks that are due at a deadline, e.g.
+    /// the expiry of watches.
+    timer: Timer,
+
+    /// The adapter computing aggregate getters, registered along with
+    /// the first aggregate getter.
+    aggregator: Mutex<Option<Arc<DerivedAdapter>>>,
+}
+
+impl AdapterManager {
+    /// Create a manager without any adapter.
+    pub fn new() -> Self {
+        AdapterManager {
+            state: Arc::new(Mutex::new(State {
+                adapters: HashMap::new(),
+                workers: HashMap::new(),
+                dispatch: DispatchPolicy::default(),
+                metrics: Arc::new(Registry::new()),
+                config: HashMap::new(),
+                candidates: HashMap::new(),
+                tag_store: None,
+                watch_store: None,
+                history: None,
+                history_policy: HistoryPolicy::new(),
+                nodes: Slab::new(),
+                index: Index::new(),
+                owners: HashMap::new(),
+                restored: HashSet::new(),
+                unavailable: HashSet::new(),
+                failures: HashMap::new(),
+                kinds: vec![],
+                revision: 0,
+                counter: 0,
+                writes: HashMap::new(),
+                last_values: HashMap::new(),
+                fallback: None,
+                tagging_rules: vec![],
+                watches: HashMap::new(),
+                hints: HashMap::new(),
+                schedules: HashMap::new(),
+                reservations: HashMap::new(),
+                aggregates: HashMap::new(),
+                interests: HashMap::new(),
+                adapter_watches: HashMap::new(),
+            })),
+            policy: Arc::new(Box::new(ScopePolicy)),
+            arbitration: None,
+            listeners: vec![],
+            scheduler: Mutex::new(None),
+            timer: Timer::start(),
+            aggregator: Mutex::new(None),
+        }
+    }
+
+    /// Check permissions with `policy`.
+    pub fn with_policy(self, policy: Box<PermissionPolicy>) -> Self {
+        AdapterManager {
+            policy: Arc::new(policy),
+            ..self
+        }
+    }
+
+    /// Arbitrate between conflicting writes with `policy`.
+    pub fn with_arbitration(self, policy: Box<ArbitrationPolicy>) -> Self {
+        AdapterManager {
+            arbitration: Some(Arc::new(policy)),
+            ..self
+        }
+    }
+
+    /// Inform `listener` of changes in the lifecycle of adapters.
+    pub fn with_listener(mut self, listener: Box<AdapterListener>) -> Self {
+        self.listeners.push(listener);
+        self
+    }
+
+    /// Dispatch fetches and sends to adapters following `policy`. This
+    /// only affects adapters added afterwards.
+    pub fn with_dispatch(self, policy: DispatchPolicy) -> Self {
+        self.state.lock().unwrap().dispatch = policy;
+        self
+    }
+
+    /// Persist the tags set through the API in `store`, and restore
+    /// them whenever a node or channel with the same id is registered.
+    /// This only affects nodes and channels registered afterwards.
+    pub fn with_tag_store(self, store: Box<TagStore>) -> Self {
+        self.state.lock().unwrap().tag_store = Some(store);
+        self
+    }
+
+    /// Answer `API::get_channel_value` with the latest value received
+    /// from a getter, if it is at most `max_age` old, whenever the
+    /// device cannot be reached, e.g. because it is offline, because its
+    /// adapter has stopped or because it has been restored from a
+    /// snapshot. Such values are marked as `ValueSource::Cache`, along
+    /// with their age.
+    pub fn with_offline_fallback(self, max_age: ValDuration) -> Self {
+        self.state.lock().unwrap().fallback = Some(max_age);
+        self
+    }
+
+    /// Persist the watches registered with `API::register_durable_watch`
+    /// in `store`, so that they may be re-established with
+    /// `API::resume_watch` after a restart.
+    pub fn with_watch_store(self, store: Box<WatchStore>) -> Self {
+        self.state.lock().unwrap().watch_store = Some(store);
+        self
+    }
+
+    /// Record the values produced by the getters selected by `policy`
+    /// in `store`, and serve them through `API::get_channel_history`.
+    /// Recorded getters are watched, or polled if they do not support
+    /// watching and polling is enabled, even while no application
+    /// watches them.
+    pub fn with_history(self, store: Box<HistoryStore>, policy: HistoryPolicy) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.history = Some(store);
+            state.history_policy = policy;
+        }
+        sync_adapter_watches(&self.state);
+        self
+    }
+
+    /// Poll the getters that do not support watching, following
+    /// `policy`. Polling stops once the manager is dropped, or with
+    /// `AdapterManager::stop_polling`.
+    pub fn with_polling(self, policy: PollingPolicy) -> Self {
+        *self.scheduler.lock().unwrap() = Some(Scheduler::start(self.state.clone(), policy));
+        self
+    }
+
+    /// Stop polling, waiting until the polls in progress are complete.
+    /// Does nothing if polling is not enabled.
+    pub fn stop_polling(&self) {
+        let scheduler = self.scheduler.lock().unwrap().take();
+        if let Some(scheduler) = scheduler {
+            scheduler.stop();
+        }
+    }
+
+    /// Determine whether a node is available, i.e. whether its adapter
+    /// is running and has registered it. Returns `false` if there is no
+    /// such node.
+    pub fn is_node_available(&self, id: &Id<NodeId>) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.owners.get(id) {
+            None => false,
+            Some(adapter) => !state.unavailable.contains(adapter) && !state.restored.contains(id)
+        }
+    }
+
+    /// Take a snapshot of the topology, i.e. the nodes and channels
+    /// along with their tags and labels, of the tagging rules and of
+    /// the configuration of adapters, to be restored after a restart
+    /// with `AdapterManager::restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state.lock().unwrap();
+        Snapshot {
+            taken: TimeStamp::now(),
+            nodes: state.nodes.iter()
+                .filter_map(|node| state.owners.get(&node.id).map(|adapter| KnownNode {
+                    adapter: adapter.clone(),
+                    node: node.clone(),
+                }))
+                .collect(),
+            tagging_rules: state.tagging_rules.clone(),
+            config: state.config.clone(),
+        }
+    }
+
+    /// Restore a snapshot taken with `AdapterManager::snapshot`,
+    /// typically at startup, before adapters are registered.
+    ///
+    /// Restored nodes are presented immediately, but remain unavailable
+    /// until their adapter registers them again, at which point the
+    /// tags and labels of the restored node and channels are carried
+    /// over. Restored nodes that the adapter does not register again
+    /// are removed once the adapter calls
+    /// `AdapterManagerHandle::adapter_scanned`, or if it is removed.
+    ///
+    /// Nodes that conflict with nodes already registered are skipped.
+    /// Tagging rules are added with new ids. Configuration options that
+    /// have already been set are kept.
+    pub fn restore(&self, snapshot: Snapshot) {
+        self.change_topology(|state| {
+            let before = state.topology_snapshot();
+            for KnownNode { adapter, node } in snapshot.nodes {
+                if state.check_node(&adapter, &node, &[]).is_err() {
+                    continue;
+                }
+                state.restored.insert(node.id.clone());
+                state.insert_node(&adapter, (*node).clone());
+            }
+            for (_, rule) in snapshot.tagging_rules {
+                let id = state.new_id("rule");
+                state.tagging_rules.push((id, rule));
+            }
+            for position in state.nodes.handles() {
+                state.apply_tagging_rules(position);
+            }
+            for (adapter, values) in snapshot.config {
+                let config = state.config.entry(adapter).or_insert_with(HashMap::new);
+                for (key, value) in values {
+                    config.entry(key).or_insert(value);
+                }
+            }
+            ((), state.topology_changed(before))
+        })
+    }
+
+    /// Export the tags and labels of nodes and channels, the tagging
+    /// rules and the configuration of adapters, e.g. to back them up.
+    /// See module `document`.
+    pub fn export_state(&self) -> Document {
+        fn annotation(target: Labelled, tags: &[TagId], label: &Option<String>) -> Option<Annotation> {
+            if tags.is_empty() && label.is_none() {
+                return None;
+            }
+            Some(Annotation {
+                target: target,
+                tags: tags.to_vec(),
+                label: label.clone(),
+            })
+        }
+        let state = self.state.lock().unwrap();
+        let mut annotations = vec![];
+        for node in &state.nodes {
+            annotations.extend(annotation(Labelled::Node(node.id.clone()), &node.tags, &node.label));
+            for channel in &node.getters {
+                annotations.extend(annotation(Labelled::Getter(channel.id.clone()), &channel.tags, &channel.label));
+            }
+            for channel in &node.setters {
+                annotations.extend(annotation(Labelled::Setter(channel.id.clone()), &channel.tags, &channel.label));
+            }
+        }
+        Document {
+            annotations: annotations,
+            tagging_rules: state.tagging_rules.iter().map(|&(_, ref rule)| rule.clone()).collect(),
+            config: state.config.clone(),
+            .. Document::new()
+        }
+    }
+
+    /// Import a document produced by `AdapterManager::export_state`,
+    /// possibly on another FoxBox.
+    ///
+    /// The tags and labels of the nodes and channels that are
+    /// registered are replaced with those of the document. Tagging
+    /// rules are added with new ids. The configuration of registered
+    /// adapters is changed as with `API::put_adapter_config`, while the
+    /// configuration of other adapters is kept until they are
+    /// registered.
+    ///
+    /// # Errors
+    ///
+    /// `Document` if the document cannot be imported, in which case
+    /// nothing is imported.
+    ///
+    /// `TagStore` if the imported tags could not be persisted.
+    pub fn import_state(&self, document: Document) -> Result<ImportReport, Error> {
+        try!(document.check().map_err(Error::Document));
+        let Document { annotations, tagging_rules, config, .. } = document;
+        let mut report = ImportReport::default();
+        try!(self.change_topology(|state| {
+            let before = state.topology_snapshot();
+            let mut deliveries = vec![];
+            let mut stored = vec![];
+            for annotation in annotations {
+                match state.annotate(&annotation) {
+                    None => report.missing.push(annotation.target.clone()),
+                    Some(label_changed) => {
+                        if label_changed {
+                            deliveries.append(&mut state.label_changed(annotation.target.clone(), annotation.label.clone()));
+                        }
+                        report.applied.push(annotation.target.clone());
+                    }
+                }
+                stored.push((annotation.target, annotation.tags));
+            }
+            for rule in tagging_rules {
+                let id = state.new_id("rule");
+                state.tagging_rules.push((id.clone(), rule));
+                report.tagging_rules.push(id);
+            }
+            for position in state.nodes.handles() {
+                state.apply_tagging_rules(position);
+            }
+            deliveries.append(&mut state.topology_changed(before));
+            (state.store_tags(stored), deliveries)
+        }));
+        for (adapter, values) in config {
+            let registered = self.state.lock().unwrap().adapters.contains_key(&adapter);
+            for (key, value) in values {
+                if registered {
+                    if let Err(error) = self.configure(&adapter, key.clone(), value) {
+                        report.rejected.push((adapter.clone(), key, error));
+                    }
+                } else {
+                    self.state.lock().unwrap().config.entry(adapter.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(key, value);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Deliver the events resulting from a change in the lifecycle of
+    /// an adapter, then inform the listeners.
+    fn report(&self, event: AdapterEvent, deliveries: Deliveries) {
+        deliver(deliveries);
+        for listener in &self.listeners {
+            listener.on_adapter_event(&event);
+        }
+    }
+
+    /// Mark an adapter as stopped or failed.
+    fn adapter_down(&self, id: &Id<AdapterId>, event: AdapterEvent) -> Result<(), RegistrationError> {
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            if !state.adapters.contains_key(id) {
+                return Err(RegistrationError::NoSuchAdapter(id.clone()));
+            }
+            if let AdapterEvent::Failed { ref error, .. } = event {
+                state.failures.insert(id.clone(), error.clone());
+            } else {
+                state.failures.remove(id);
+            }
+            let changed = state.unavailable.insert(id.clone());
+            state.lifecycle_changed(id, event.clone(), if changed { Some(false) } else { None })
+        };
+        self.report(event, deliveries);
+        Ok(())
+    }
+
+    /// Apply a change of topology, then deliver the resulting events
+    /// and update the watches of adapters.
+    fn change_topology<F, T>(&self, change: F) -> T where F: FnOnce(&mut State) -> (T, Deliveries) {
+        let (result, deliveries) = change(&mut self.state.lock().unwrap());
+        deliver(deliveries);
+        sync_adapter_watches(&self.state);
+        result
+    }
+
+    /// The adapter computing aggregate getters, registered on first use.
+    fn aggregator(&self) -> Result<Arc<DerivedAdapter>, Error> {
+        let mut aggregator = self.aggregator.lock().unwrap();
+        if let Some(ref adapter) = *aggregator {
+            return Ok(adapter.clone());
+        }
+        let id = Id::new("aggregate@foxlink.mozilla.com".to_owned());
+        let adapter = Arc::new(DerivedAdapter::with_id(id.clone()));
+        try!(self.add_adapter_with_nodes(adapter.clone(), vec![adapter.node().with_label("Aggregate channels".to_owned())])
+             .map_err(|err| registration_error(&id, err)));
+        *aggregator = Some(adapter.clone());
+        Ok(adapter)
+    }
+
+    fn register_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        try!(self.policy.check(caller, &Operation::Watch { options: &options }));
+        // The shortest delay and the smallest number of events prevail.
+        let batching = options.iter()
+            .filter_map(|options| options.batching.clone())
+            .fold(None, |merged: Option<Batching>, batching| Some(match merged {
+                None => batching,
+                Some(merged) => Batching {
+                    max_delay: cmp::min(merged.max_delay, batching.max_delay),
+                    max_events: cmp::min(merged.max_events, batching.max_events),
+                }
+            }));
+        // The smallest capacity prevails, along with the policy of the
+        // first option.
+        let backpressure = options.iter()
+            .filter_map(|options| options.backpressure.clone())
+            .fold(None, |merged: Option<Backpressure>, backpressure| Some(match merged {
+                None => backpressure,
+                Some(merged) => Backpressure {
+                    capacity: cmp::min(merged.capacity, backpressure.capacity),
+                    policy: merged.policy,
+                }
+            }));
+        // The shortest duration and the smallest number of events prevail.
+        let expires_after = options.iter().filter_map(|options| options.expires_after.clone()).min();
+        let max_events = options.iter().filter_map(|options| options.max_events).min();
+        let id = self.state.lock().unwrap().new_id("watch");
+        let cb = match batching {
+            None => cb,
+            Some(ref batching) => Batch::start(batching, self.timer.handle(), cb)
+        };
+        let (expiry, cb) = if expires_after.is_none() && max_events.is_none() {
+            (None, cb)
+        } else {
+            let state = Arc::downgrade(&self.state);
+            let watch = id.clone();
+            let (expiry, cb) = Expiry::start(max_events, cb, Box::new(move || {
+                // The watch may have been unregistered in the meantime.
+                if let Some(state) = state.upgrade() {
+                    let _ = remove_watch(&state, &watch);
+                }
+            }));
+            (Some(expiry), cb)
+        };
+        let (buffer, cb) = match backpressure {
+            None => (None, cb),
+            Some(ref backpressure) => {
+                let (buffer, cb) = Buffer::start(backpressure, cb);
+                (Some(buffer), cb)
+            }
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            let owner = options.iter().filter_map(|options| options.owner.clone()).next();
+            state.watches.insert(id.clone(), Watch {
+                info: WatchInfo {
+                    id: id.clone(),
+                    options: options,
+                    owner: owner,
+                    events: 0,
+                    dropped: 0,
+                    created: TimeStamp::now(),
+                },
+                cb: Arc::new(Mutex::new(cb)),
+                caller: caller.id.clone(),
+                last: HashMap::new(),
+                durable: None,
+                buffer: buffer,
+                delivered: HashMap::new(),
+            });
+            // Values produced before the watches of adapters are synced
+            // should reach the new watch already.
+            state.update_interests();
+        }
+        sync_adapter_watches(&self.state);
+        if let Some(ref expiry) = expiry {
+            if max_events == Some(0) {
+                expiry.expire();
+            } else if let Some(duration) = expires_after {
+                Expiry::expire_after(expiry, &self.timer.handle(), duration.as_duration());
+            }
+        }
+        Ok(id)
+    }
+
+    /// Change an option of the configuration of a registered adapter.
+    fn configure(&self, id: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error> {
+        let adapter = match self.state.lock().unwrap().adapters.get(id) {
+            None => return Err(Error::NoSuchAdapter(id.clone())),
+            Some(adapter) => adapter.clone()
+        };
+        match adapter.config_schema().into_iter().find(|option| option.key == key) {
+            None => return Err(Error::NoSuchConfigOption {
+                adapter: id.clone(),
+                key: key
+            }),
+            Some(ref option) if option.typ != value.get_type() => return Err(Error::TypeError),
+            Some(_) => {}
+        }
+        // The adapter is called without holding the lock, as it may call
+        // back into the manager.
+        try!(adapter.config_changed(&key, &value).map_err(|error| adapter_error(id, error)));
+        self.state.lock().unwrap().config.entry(id.clone())
+            .or_insert_with(HashMap::new)
+            .insert(key, value);
+        Ok(())
+    }
+
+    /// Fetch from all adapters at once, then wait until they are done.
+    fn fetch(&self, groups: Vec<(Worker, Vec<Id<Getter>>)>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let pending: Vec<_> = groups.into_iter()
+            .map(|(worker, ids)| (worker.adapter().clone(), worker.fetch(ids)))
+            .collect();
+        let mut results = vec![];
+        for (adapter_id, pending) in pending {
+            for (id, result) in pending.wait() {
+                match result {
+                    Ok(None) => {},
+                    Ok(Some(value)) => results.push((id, Ok(FetchedValue {
+                        value: value,
+                        timestamp: TimeStamp::now(),
+                        source: ValueSource::Live,
+                        adapter: adapter_id.clone(),
+                        age: None,
+                    }))),
+                    Err(error) => results.push((id, Err(error))),
+                }
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        for &mut (ref id, ref mut result) in &mut results {
+            if let Ok(ref mut fetched) = *result {
+                // The node may have been removed in the meantime.
+                let position = match state.index.getter_position(id) {
+                    None => continue,
+                    Some(position) => position
+                };
+                // See `API::poll_now`.
+                getter_mut(&mut state.nodes, position).mechanism.updated = Some(fetched.timestamp.clone());
+                match value_to_cache(state.last_values.get(id), &fetched.value) {
+                    None => {
+                        state.last_values.remove(id);
+                    }
+                    Some(value) => {
+                        // Callers receive the whole document.
+                        fetched.value = value;
+                        state.last_values.insert(id.clone(), fetched.clone());
+                    }
+                }
+            }
+        }
+        results
+    }
+
+    fn tag_nodes(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &[TagId], add: bool) -> Result<TagResult<NodeId>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: tags }));
+        self.change_topology(|state| {
+            let before = state.topology_snapshot();
+            let mut result = TagResult::new();
+            let mut stored = vec![];
+            for position in state.node_positions(set) {
+                let mut after = {
+                    let node = &state.nodes[position];
+                    if !set.iter().any(|selector| selector.matches(node)) {
+                        continue;
+                    }
+                    node.tags.clone()
+                };
+                let changed = if add {
+                    add_tags(&mut after, tags)
+                } else {
+                    remove_tags(&mut after, tags)
+                };
+                if changed {
+                    let node = node_mut(&mut state.nodes, position);
+                    state.index.retag_node(&node.id, &node.tags, &after);
+                    node.tags = after;
+                    result.changed.push(node.id.clone());
+                    stored.push((Labelled::Node(node.id.clone()), node.tags.clone()));
+                } else {
+                    result.unchanged.push(state.nodes[position].id.clone());
+                }
+            }
+            let deliveries = if result.changed.is_empty() {
+                vec![]
+            } else {
+                state.topology_changed(before)
+            };
+            (state.store_tags(stored).map(|_| result), deliveries)
+        })
+    }
+
+    fn tag_getters(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &[TagId], add: bool) -> Result<TagResult<Getter>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: tags }));
+        self.change_topology(|state| {
+            let before = state.topology_snapshot();
+            let mut result = TagResult::new();
+            let mut stored = vec![];
+            for (position, index) in state.getter_positions(set) {
+                let mut after = {
+                    let channel = &state.nodes[position].getters[index];
+                    if !set.iter().any(|selector| selector.matches(channel)) {
+                        continue;
+                    }
+                    channel.tags.clone()
+                };
+                let changed = if add {
+                    add_tags(&mut after, tags)
+                } else {
+                    remove_tags(&mut after, tags)
+                };
+                if changed {
+                    let channel = getter_mut(&mut state.nodes, (position, index));
+                    state.index.retag_getter(&channel.id, &channel.tags, &after);
+                    channel.tags = after;
+                    result.changed.push(channel.id.clone());
+                    stored.push((Labelled::Getter(channel.id.clone()), channel.tags.clone()));
+                } else {
+                    result.unchanged.push(state.nodes[position].getters[index].id.clone());
+                }
+            }
+            let deliveries = if result.changed.is_empty() {
+                vec![]
+            } else {
+                state.topology_changed(before)
+            };
+            (state.store_tags(stored).map(|_| result), deliveries)
+        })
+    }
+
+    fn tag_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &[TagId], add: bool) -> Result<TagResult<Setter>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: tags }));
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        let mut result = TagResult::new();
+        let mut stored = vec![];
+        for (position, index) in state.setter_positions(set) {
+            let mut after = {
+                let channel = &state.nodes[position].setters[index];
+                if !set.iter().any(|selector| selector.matches(channel)) {
+                    continue;
+                }
+                channel.tags.clone()
+            };
+            let changed = if add {
+                add_tags(&mut after, tags)
+            } else {
+                remove_tags(&mut after, tags)
+            };
+            if changed {
+                let channel = setter_mut(&mut state.nodes, (position, index));
+                state.index.retag_setter(&channel.id, &channel.tags, &after);
+                channel.tags = after;
+                result.changed.push(channel.id.clone());
+                stored.push((Labelled::Setter(channel.id.clone()), channel.tags.clone()));
+            } else {
+                result.unchanged.push(state.nodes[position].setters[index].id.clone());
+            }
+        }
+        if !result.changed.is_empty() {
+            state.revision += 1;
+        }
+        try!(state.store_tags(stored));
+        Ok(result)
+    }
+
+    fn label_nodes(&self, caller: &Caller, set: &Vec<NodeSelector>, label: Option<String>) -> Result<Vec<Id<NodeId>>, Error> {
+        try!(self.policy.check(caller, &Operation::Label { label: label.as_ref().map(|label| &**label) }));
+        let (ids, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ids = vec![];
+            for position in state.node_positions(set) {
+                if set.iter().any(|selector| selector.matches(&state.nodes[position])) {
+                    let node = node_mut(&mut state.nodes, position);
+                    node.label = label.clone();
+                    ids.push(node.id.clone());
+                }
+            }
+            let mut deliveries = vec![];
+            for id in &ids {
+                deliveries.append(&mut state.label_changed(Labelled::Node(id.clone()), label.clone()));
+            }
+            if !ids.is_empty() {
+                state.revision += 1;
+            }
+            (ids, deliveries)
+        };
+        deliver(deliveries);
+        Ok(ids)
+    }
+
+    fn label_getters(&self, caller: &Caller, set: &Vec<GetterSelector>, label: Option<String>) -> Result<Vec<Id<Getter>>, Error> {
+        try!(self.policy.check(caller, &Operation::Label { label: label.as_ref().map(|label| &**label) }));
+        let (ids, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ids = vec![];
+            for (position, index) in state.getter_positions(set) {
+                if set.iter().any(|selector| selector.matches(&state.nodes[position].getters[index])) {
+                    let channel = getter_mut(&mut state.nodes, (position, index));
+                    channel.label = label.clone();
+                    ids.push(channel.id.clone());
+                }
+            }
+            let mut deliveries = vec![];
+            for id in &ids {
+                deliveries.append(&mut state.label_changed(Labelled::Getter(id.clone()), label.clone()));
+            }
+            if !ids.is_empty() {
+                state.revision += 1;
+            }
+            (ids, deliveries)
+        };
+        deliver(deliveries);
+        Ok(ids)
+    }
+
+    fn label_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, label: Option<String>) -> Result<Vec<Id<Setter>>, Error> {
+        try!(self.policy.check(caller, &Operation::Label { label: label.as_ref().map(|label| &**label) }));
+        let (ids, deliveries) = {
+            let mut state = self.state.lock().unwrap();
+            let mut ids = vec![];
+            for (position, index) in state.setter_positions(set) {
+                if set.iter().any(|selector| selector.matches(&state.nodes[position].setters[index])) {
+                    let channel = setter_mut(&mut state.nodes, (position, index));
+                    channel.label = label.clone();
+                    ids.push(channel.id.clone());
+                }
+            }
+            let mut deliveries = vec![];
+            for id in &ids {
+                deliveries.append(&mut state.label_changed(Labelled::Setter(id.clone()), label.clone()));
+            }
+            if !ids.is_empty() {
+                state.revision += 1;
+            }
+            (ids, deliveries)
+        };
+        deliver(deliveries);
+        Ok(ids)
+    }
+}
+
+impl Default for AdapterManager {
+    fn default() -> Self {
+        AdapterManager::new()
+    }
+}
+
+impl IdRegistry<AdapterId> for AdapterManager {
+    fn is_registered(&self, id: &Id<AdapterId>) -> bool {
+        self.state.lock().unwrap().adapters.contains_key(id)
+    }
+}
+
+impl IdRegistry<NodeId> for AdapterManager {
+    fn is_registered(&self, id: &Id<NodeId>) -> bool {
+        self.state.lock().unwrap().index.node_position(id).is_some()
+    }
+}
+
+impl IdRegistry<Getter> for AdapterManager {
+    fn is_registered(&self, id: &Id<Getter>) -> bool {
+        self.state.lock().unwrap().has_getter(id)
+    }
+}
+
+impl IdRegistry<Setter> for AdapterManager {
+    fn is_registered(&self, id: &Id<Setter>) -> bool {
+        self.state.lock().unwrap().has_setter(id)
+    }
+}
+
+impl AdapterManagerHandle for AdapterManager {
+    fn add_adapter(&self, adapter: Arc<Adapter>) -> Result<(), RegistrationError> {
+        self.add_adapter_with_nodes(adapter, vec![])
+    }
+
+    fn add_adapter_with_nodes(&self, adapter: Arc<Adapter>, nodes: Vec<Node>) -> Result<(), RegistrationError> {
+        let id = adapter.id();
+        let event = AdapterEvent::Registered(id.clone());
+        try!(self.change_topology(|state| {
+            if id.as_string().contains(ID_SEPARATOR) {
+                return (Err(RegistrationError::InvalidId(id.as_string().clone())), vec![]);
+            }
+            if state.adapters.contains_key(&id) {
+                return (Err(RegistrationError::DuplicateAdapter(id.clone())), vec![]);
+            }
+            for (index, node) in nodes.iter().enumerate() {
+                if let Err(error) = state.check_node(&id, node, &nodes[..index]) {
+                    return (Err(error), vec![]);
+                }
+            }
+            let before = state.topology_snapshot();
+            let worker = Worker::start(adapter.clone(), &state.dispatch, state.metrics.clone());
+            state.workers.insert(id.clone(), worker);
+            state.adapters.insert(id.clone(), adapter);
+            let mut deliveries = state.lifecycle_changed(&id, event.clone(), None);
+            if !nodes.is_empty() {
+                for node in nodes {
+                    state.insert_node(&id, node);
+                }
+                deliveries.append(&mut state.topology_changed(before));
+            }
+            (Ok(()), deliveries)
+        }));
+        for listener in &self.listeners {
+            listener.on_adapter_event(&event);
+        }
+        Ok(())
+    }
+
+    fn remove_adapter(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        let adapter = try!(self.change_topology(|state| {
+            let adapter = match state.adapters.remove(id) {
+                None => return (Err(RegistrationError::NoSuchAdapter(id.clone())), vec![]),
+                Some(adapter) => adapter
+            };
+            state.workers.remove(id);
+            state.metrics.forget(&Target::Adapter(id.clone()));
+            let before = state.topology_snapshot();
+            let owned: Vec<_> = state.owners.iter()
+                .filter(|&(_, owner)| owner == id)
+                .map(|(node, _)| node.clone())
+                .collect();
+            state.retire_nodes(&owned);
+            state.unavailable.remove(id);
+            state.failures.remove(id);
+            state.withdraw_candidates(id, None);
+            (Ok(adapter), state.topology_changed(before))
+        }));
+        // The watches of the adapter have been dropped by now.
+        adapter.stop();
+        let event = AdapterEvent::Removed(id.clone());
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            let mut deliveries = state.withdraw_kinds(id);
+            deliveries.append(&mut state.lifecycle_changed(id, event.clone(), None));
+            deliveries
+        };
+        self.report(event, deliveries);
+        Ok(())
+    }
+
+    fn add_node(&self, adapter: &Id<AdapterId>, node: Node) -> Result<(), RegistrationError> {
+        self.change_topology(|state| {
+            if !state.adapters.contains_key(adapter) {
+                return (Err(RegistrationError::NoSuchAdapter(adapter.clone())), vec![]);
+            }
+            if let Err(error) = state.check_node(adapter, &node, &[]) {
+                return (Err(error), vec![]);
+            }
+            let before = state.topology_snapshot();
+            state.insert_node(adapter, node);
+            (Ok(()), state.topology_changed(before))
+        })
+    }
+
+    fn remove_node(&self, id: &Id<NodeId>) -> Result<(), RegistrationError> {
+        self.change_topology(|state| {
+            if !state.owners.contains_key(id) {
+                return (Err(RegistrationError::NoSuchNode(id.clone())), vec![]);
+            }
+            let before = state.topology_snapshot();
+            state.retire_nodes(&[id.clone()]);
+            (Ok(()), state.topology_changed(before))
+        })
+    }
+
+    fn add_getter(&self, mut channel: Channel<Getter>) -> Result<(), RegistrationError> {
+        self.change_topology(|state| {
+            match state.owners.get(&channel.node) {
+                None => return (Err(RegistrationError::NoSuchNode(channel.node.clone())), vec![]),
+                Some(adapter) if !is_namespaced(adapter, &channel.id) =>
+                    return (Err(RegistrationError::InvalidId(channel.id.as_string().clone())), vec![]),
+                Some(_) => {}
+            }
+            if state.has_getter(&channel.id) {
+                return (Err(RegistrationError::DuplicateGetter(channel.id.clone())), vec![]);
+            }
+            let before = state.topology_snapshot();
+            let node_id = channel.node.clone();
+            state.restore_tags(Labelled::Getter(channel.id.clone()), &mut channel.tags);
+            let position = match state.index.node_position(&node_id) {
+                None => return (Err(RegistrationError::NoSuchNode(node_id)), vec![]),
+                Some(position) => position
+            };
+            let index = state.nodes[position].getters.len();
+            state.index.add_getter((position, index), &channel);
+            node_mut(&mut state.nodes, position).getters.push(Arc::new(channel));
+            (Ok(()), state.topology_changed(before))
+        })
+    }
+
+    fn remove_getter(&self, id: &Id<Getter>) -> Result<(), RegistrationError> {
+        self.change_topology(|state| {
+            if !state.has_getter(id) {
+                return (Err(RegistrationError::NoSuchGetter(id.clone())), vec![]);
+            }
+            let before = state.topology_snapshot();
+            if let Some((position, index)) = state.index.getter_position(id) {
+                state.reindex_node(position, |node| {
+                    node.getters.remove(index);
+                    // A state cannot survive without its getter.
+                    node.states.retain(|pair| pair.getter != *id);
+                });
+            }
+            state.metrics.forget(&Target::Getter(id.clone()));
+            (Ok(()), state.topology_changed(before))
+        })
+    }
+
+    fn add_setter(&self, mut channel: Channel<Setter>) -> Result<(), RegistrationError> {
+        let mut state = self.state.lock().unwrap();
+        match state.owners.get(&channel.node) {
+            None => return Err(RegistrationError::NoSuchNode(channel.node.clone())),
+            Some(adapter) if !is_namespaced(adapter, &channel.id) =>
+                return Err(RegistrationError::InvalidId(channel.id.as_string().clone())),
+            Some(_) => {}
+        }
+        if state.has_setter(&channel.id) {
+            return Err(RegistrationError::DuplicateSetter(channel.id.clone()));
+        }
+        state.restore_tags(Labelled::Setter(channel.id.clone()), &mut channel.tags);
+        let position = match state.index.node_position(&channel.node) {
+            None => return Err(RegistrationError::NoSuchNode(channel.node.clone())),
+            Some(position) => position
+        };
+        let index = state.nodes[position].setters.len();
+        state.index.add_setter((position, index), &channel);
+        node_mut(&mut state.nodes, position).setters.push(Arc::new(channel));
+        state.revision += 1;
+        Ok(())
+    }
+
+    fn remove_setter(&self, id: &Id<Setter>) -> Result<(), RegistrationError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.has_setter(id) {
+            return Err(RegistrationError::NoSuchSetter(id.clone()));
+        }
+        if let Some((position, index)) = state.index.setter_position(id) {
+            state.reindex_node(position, |node| {
+                node.setters.remove(index);
+                // A state cannot survive without its setter.
+                node.states.retain(|pair| pair.setter != *id);
+            });
+        }
+        state.writes.remove(id);
+        state.metrics.forget(&Target::Setter(id.clone()));
+        state.revision += 1;
+        Ok(())
+    }
+
+    fn adapter_started(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        let event = AdapterEvent::Started(id.clone());
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            if !state.adapters.contains_key(id) {
+                return Err(RegistrationError::NoSuchAdapter(id.clone()));
+            }
+            state.failures.remove(id);
+            if !state.unavailable.remove(id) {
+                // Already running.
+                return Ok(());
+            }
+            state.lifecycle_changed(id, event.clone(), Some(true))
+        };
+        self.report(event, deliveries);
+        Ok(())
+    }
+
+    fn adapter_scanned(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        self.change_topology(|state| {
+            if !state.adapters.contains_key(id) {
+                return (Err(RegistrationError::NoSuchAdapter(id.clone())), vec![]);
+            }
+            let before = state.topology_snapshot();
+            let stale: Vec<_> = state.restored.iter()
+                .filter(|node| state.owners.get(*node) == Some(id))
+                .cloned()
+                .collect();
+            state.retire_nodes(&stale);
+            (Ok(()), state.topology_changed(before))
+        })
+    }
+
+    fn adapter_stopped(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        self.adapter_down(id, AdapterEvent::Stopped(id.clone()))
+    }
+
+    fn adapter_failed(&self, id: &Id<AdapterId>, error: String) -> Result<(), RegistrationError> {
+        self.adapter_down(id, AdapterEvent::Failed {
+            adapter: id.clone(),
+            error: error
+        })
+    }
+
+    fn get_config(&self, adapter: &Id<AdapterId>, key: &str) -> Option<Value> {
+        let state = self.state.lock().unwrap();
+        state.config.get(adapter).and_then(|values| values.get(key)).cloned()
+    }
+
+    fn add_extension_kind(&self, adapter: &Id<AdapterId>, description: KindDescription) -> Result<(), RegistrationError> {
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            if !state.adapters.contains_key(adapter) {
+                return Err(RegistrationError::NoSuchAdapter(adapter.clone()));
+            }
+            let valid = match description.kind {
+                ChannelKind::Extension { ref typ, .. } => *typ == description.typ,
+                _ => false
+            };
+            if !valid {
+                return Err(RegistrationError::InvalidKind(description.kind.clone()));
+            }
+            match state.kinds.iter().position(|&(_, ref known)| known.kind == description.kind) {
+                Some(index) if state.kinds[index].0 != *adapter =>
+                    return Err(RegistrationError::DuplicateKind(description.kind.clone())),
+                Some(index) => state.kinds[index].1 = description.clone(),
+                None => state.kinds.push((adapter.clone(), description.clone()))
+            }
+            state.notify(WatchEvent::KindRegistered {
+                adapter: adapter.clone(),
+                description: description
+            }, |options| options.should_watch_topology)
+        };
+        deliver(deliveries);
+        Ok(())
+    }
+
+    fn remove_extension_kind(&self, adapter: &Id<AdapterId>, kind: &ChannelKind) -> Result<(), RegistrationError> {
+        let deliveries = {
+            let mut state = self.state.lock().unwrap();
+            match state.kinds.iter().position(|&(ref owner, ref known)| owner == adapter && known.kind == *kind) {
+                None => return Err(RegistrationError::NoSuchKind(kind.clone())),
+                Some(index) => {
+                    state.kinds.remove(index);
+                }
+            }
+            state.notify(WatchEvent::KindRemoved {
+                adapter: adapter.clone(),
+                kind: kind.clone()
+            }, |options| options.should_watch_topology)
+        };
+        deliver(deliveries);
+        Ok(())
+    }
+}
+
+impl Discovery for AdapterManager {
+    fn announce(&self, adapter: &Id<AdapterId>, announcement: Announcement) -> Result<Id<CandidateId>, RegistrationError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.adapters.contains_key(adapter) {
+            return Err(RegistrationError::NoSuchAdapter(adapter.clone()));
+        }
+        let id = announcement.fingerprint.clone();
+        state.candidates.entry(id.clone())
+            .or_insert_with(|| Candidate::new(id.clone()))
+            .announced(adapter, announcement);
+        Ok(id)
+    }
+
+    fn withdraw(&self, adapter: &Id<AdapterId>, candidate: &Id<CandidateId>) -> Result<(), RegistrationError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.adapters.contains_key(adapter) {
+            return Err(RegistrationError::NoSuchAdapter(adapter.clone()));
+        }
+        state.withdraw_candidates(adapter, Some(candidate));
+        Ok(())
+    }
+}
+
+/// A handle on a watch registered with `AdapterManager`.
+pub struct ManagerWatchGuard {
+    id: Id<WatchId>,
+    state: Arc<Mutex<State>>,
+    active: bool,
+}
+
+impl WatchGuard for ManagerWatchGuard {
+    fn id(&self) -> &Id<WatchId> {
+        &self.id
+    }
+
+    fn unregister(&mut self) {
+        if self.active {
+            self.state.lock().unwrap().watches.remove(&self.id);
+            self.active = false;
+            sync_adapter_watches(&self.state);
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        self.active && self.state.lock().unwrap().watches.contains_key(&self.id)
+    }
+}
+
+impl Drop for ManagerWatchGuard {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+impl API for AdapterManager {
+    type WatchGuard = ManagerWatchGuard;
+
+    fn get_api_capabilities(&self, _: &Caller) -> Vec<Capability> {
+        vec![Capability::PollNow, Capability::MultiSampleFetch,
+             Capability::StateChannels, Capability::TaggingRules,
+             Capability::WatchTokens, Capability::WatchIntrospection,
+             Capability::WatchBatching, Capability::WatchBackpressure,
+             Capability::WatchExpiration, Capability::WatchMinDelta,
+             Capability::ScheduledWrites, Capability::Reservations,
+             Capability::AggregateGetters, Capability::PollingHints,
+             Capability::AdapterHealth, Capability::Metrics,
+             Capability::AdapterConfig, Capability::Discovery,
+             Capability::History, Capability::DurableWatches]
+    }
+
+    fn get_supported_kinds(&self, _: &Caller) -> Vec<KindDescription> {
+        let mut kinds = ChannelKind::standard_kinds();
+        let state = self.state.lock().unwrap();
+        kinds.extend(state.kinds.iter().map(|&(_, ref description)| description.clone()));
+        kinds
+    }
+
+    fn get_nodes(&self, _: &Caller, selectors: &Vec<NodeSelector>) -> Vec<Arc<Node>> {
+        let state = self.state.lock().unwrap();
+        state.matching_nodes(selectors).into_iter().cloned().collect()
+    }
+
+    fn get_topology_revision(&self, _: &Caller) -> Revision {
+        Revision(self.state.lock().unwrap().revision)
+    }
+
+    fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>> {
+        let revision = self.get_topology_revision(caller);
+        if since == Some(revision) {
+            return Conditional::Unchanged(revision);
+        }
+        Conditional::Changed {
+            revision: revision,
+            data: self.get_nodes(caller, selectors),
+        }
+    }
+
+    /// The adapter is asked to forget the device with `Adapter::forget`,
+    /// then the node is removed from the topology. If the adapter fails,
+    /// the node is kept.
+    fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error> {
+        try!(self.policy.check(caller, &Operation::ForgetNode { node: id }));
+        let (adapter_id, adapter) = {
+            let state = self.state.lock().unwrap();
+            let adapter_id = match state.owners.get(id) {
+                None => return Err(Error::NoSuchNode(id.clone())),
+                Some(adapter_id) => adapter_id.clone()
+            };
+            let adapter = state.adapters.get(&adapter_id).cloned();
+            (adapter_id, adapter)
+        };
+        // The adapter is called without holding the lock, as it may
+        // call the manager, e.g. to remove the node itself.
+        if let Some(adapter) = adapter {
+            try!(adapter.forget(id).map_err(|error| adapter_error(&adapter_id, error)));
+        }
+        self.change_topology(|state| {
+            if !state.owners.contains_key(id) {
+                // The adapter has already removed the node.
+                return (Ok(()), vec![]);
+            }
+            let before = state.topology_snapshot();
+            let retired = state.retire_nodes(&[id.clone()]);
+            if let Some(ref store) = state.tag_store {
+                // Forgetting a node purges its tags, including from
+                // storage. This is best effort, as the node is gone
+                // anyway.
+                for node in retired {
+                    let _ = store.forget(&Labelled::Node(node.id.clone()));
+                    for channel in &node.getters {
+                        let _ = store.forget(&Labelled::Getter(channel.id.clone()));
+                    }
+                    for channel in &node.setters {
+                        let _ = store.forget(&Labelled::Setter(channel.id.clone()));
+                    }
+                }
+            }
+            (Ok(()), state.topology_changed(before))
+        })
+    }
+
+    fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error> {
+        self.tag_nodes(caller, set, tags, true)
+    }
+
+    fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error> {
+        self.tag_nodes(caller, set, &[tags], false)
+    }
+
+    fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error> {
+        try!(self.policy.check(caller, &Operation::Tag { tags: &rule.tags }));
+        Ok(self.change_topology(|state| {
+            let before = state.topology_snapshot();
+            let id = state.new_id("rule");
+            state.tagging_rules.push((id.clone(), rule));
+            for position in state.nodes.handles() {
+                state.apply_tagging_rules(position);
+            }
+            (id, state.topology_changed(before))
+        }))
+    }
+
+    fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let index = match state.tagging_rules.iter().position(|&(ref rule, _)| rule == id) {
+            None => return Err(Error::NoSuchTaggingRule(id.clone())),
+            Some(index) => index
+        };
+        try!(self.policy.check(caller, &Operation::Tag { tags: &state.tagging_rules[index].1.tags }));
+        state.tagging_rules.remove(index);
+        Ok(())
+    }
+
+    fn get_tagging_rules(&self, _: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)> {
+        self.state.lock().unwrap().tagging_rules.clone()
+    }
+
+    fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error> {
+        self.label_nodes(caller, set, Some(label))
+    }
+
+    fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error> {
+        self.label_nodes(caller, set, None)
+    }
+
+    fn get_getter_channels(&self, _: &Caller, selectors: &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>> {
+        let state = self.state.lock().unwrap();
+        state.matching_getters(selectors).into_iter().cloned().collect()
+    }
+
+    fn get_setter_channels(&self, _: &Caller, selectors: &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>> {
+        let state = self.state.lock().unwrap();
+        state.matching_setters(selectors).into_iter().cloned().collect()
+    }
+
+    /// Aggregate getters are derived getters, see module `derived`,
+    /// attached to the node of adapter `aggregate@foxlink.mozilla.com`.
+    /// They are not persisted.
+    fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error> {
+        try!(self.policy.check(caller, &Operation::Aggregate { source: &aggregate.source }));
+        if !history::supports(&aggregate.kind, &aggregate.aggregation) {
+            return Err(Error::TypeError);
+        }
+        let aggregator = try!(self.aggregator());
+        let name: Id<Getter> = self.state.lock().unwrap().new_id("aggregate");
+        let id = try!(aggregator.add_getter(self, self, DerivedGetter::aggregate(name.as_string(), &aggregate))
+            .map_err(|err| match err {
+                DerivedError::Registration(err) => registration_error(&aggregator.id(), err),
+                DerivedError::Watch(err) => err
+            }));
+        self.state.lock().unwrap().aggregates.insert(id.clone(), aggregate);
+        Ok(id)
+    }
+
+    fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error> {
+        let aggregate = match self.state.lock().unwrap().aggregates.get(id) {
+            None => return Err(Error::NoSuchGetter(id.clone())),
+            Some(aggregate) => aggregate.clone()
+        };
+        try!(self.policy.check(caller, &Operation::Aggregate { source: &aggregate.source }));
+        self.state.lock().unwrap().aggregates.remove(id);
+        let aggregator = try!(self.aggregator());
+        aggregator.remove_getter(self, id).map_err(|err| registration_error(&aggregator.id(), err))
+    }
+
+    fn get_aggregate_getters(&self, _: &Caller) -> Vec<(Id<Getter>, AggregateGetter)> {
+        let state = self.state.lock().unwrap();
+        state.aggregates.iter()
+            .map(|(id, aggregate)| (id.clone(), aggregate.clone()))
+            .collect()
+    }
+
+    fn put_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.tag_getters(caller, set, tags, true)
+    }
+
+    fn put_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.tag_setters(caller, set, tags, true)
+    }
+
+    fn delete_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.tag_getters(caller, set, tags, false)
+    }
+
+    fn delete_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.tag_setters(caller, set, tags, false)
+    }
+
+    fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error> {
+        self.label_getters(caller, set, Some(label))
+    }
+
+    fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error> {
+        self.label_setters(caller, set, Some(label))
+    }
+
+    fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error> {
+        self.label_getters(caller, set, None)
+    }
+
+    fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error> {
+        self.label_setters(caller, set, None)
+    }
+
+    /// Getter channels for which the adapter has no value yet are
+    /// omitted from the results.
+    ///
+    /// With `AdapterManager::with_offline_fallback`, the getters that
+    /// cannot be reached are answered with the latest value received.
+    fn get_channel_value(&self, _: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let (groups, unreachable, events) = {
+            let state = self.state.lock().unwrap();
+            let events: Vec<_> = state.matching_getters(selectors).into_iter()
+                .filter(|channel| channel.mechanism.is_event())
+                .map(|channel| (channel.id.clone(), Err(Error::EventChannel(channel.id.clone()))))
+                .collect();
+            match state.fallback {
+                None => (state.getters_by_adapter(selectors, |channel| !channel.mechanism.is_event()), vec![], events),
+                Some(_) => {
+                    let groups = state.getters_by_adapter(selectors, |channel| {
+                        !channel.mechanism.is_event() && !state.is_unreachable(&channel.node)
+                    });
+                    let unreachable: Vec<_> = state.matching_getters(selectors).into_iter()
+                        .filter(|channel| !channel.mechanism.is_event() && state.is_unreachable(&channel.node))
+                        .map(|channel| channel.id.clone())
+                        .collect();
+                    (groups, unreachable, events)
+                }
+            }
+        };
+        let mut results = self.fetch(groups);
+        results.extend(events);
+        let state = self.state.lock().unwrap();
+        if let Some(ref max_age) = state.fallback {
+            for &mut (ref id, ref mut result) in &mut results {
+                let cached = match *result {
+                    Err(ref error) if is_unreachable(error) => state.cached_value(id, max_age),
+                    _ => None
+                };
+                if let Some(cached) = cached {
+                    *result = Ok(cached);
+                }
+            }
+            for id in unreachable {
+                if let Some(cached) = state.cached_value(&id, max_age) {
+                    results.push((id, Ok(cached)));
+                }
+            }
+        }
+        results
+    }
+
+    /// Getter channels for which the adapter has no value yet are
+    /// omitted from the results.
+    fn poll_now(&self, _: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        let (groups, mut results) = {
+            let state = self.state.lock().unwrap();
+            let now = TimeStamp::now();
+            // The latest value of a getter that has been updated less
+            // than its polling interval ago.
+            let recent = |channel: &Channel<Getter>| match (&channel.mechanism.poll, &channel.mechanism.updated) {
+                (&Some(ref poll), &Some(ref updated)) => match now.duration_since(updated) {
+                    Some(ref age) if age < poll => state.cached_value(&channel.id, poll),
+                    _ => None
+                },
+                _ => None
+            };
+            let groups = state.getters_by_adapter(selectors, |channel| {
+                channel.mechanism.poll.is_some() && !channel.mechanism.is_event() && recent(channel).is_none()
+            });
+            let not_polled: Vec<_> = state.matching_getters(selectors).into_iter()
+                .filter_map(|channel| if channel.mechanism.is_event() {
+                    Some((channel.id.clone(), Err(Error::EventChannel(channel.id.clone()))))
+                } else if channel.mechanism.poll.is_none() {
+                    Some((channel.id.clone(), Err(Error::NotPollable(channel.id.clone()))))
+                } else {
+                    recent(channel).map(|cached| (channel.id.clone(), Ok(cached)))
+                })
+                .collect();
+            (groups, not_polled)
+        };
+        results.append(&mut self.fetch(groups));
+        results
+    }
+
+    /// Samples older than `since` are omitted, even if the adapter
+    /// reports them. Samples are also recorded in the history, for the
+    /// getters that are recorded.
+    fn get_channel_values_since(&self, _: &Caller, selectors: &Vec<GetterSelector>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)> {
+        let groups = self.state.lock().unwrap().getters_by_adapter(selectors, |_| true);
+        let pending: Vec<_> = groups.into_iter()
+            .map(|(worker, ids)| worker.fetch_since(ids, since.clone()))
+            .collect();
+        let mut results = vec![];
+        for pending in pending {
+            for (id, result) in pending.wait() {
+                results.push((id, result.map(|mut samples| {
+                    samples.retain(|sample| sample.timestamp >= since);
+                    samples.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+                    samples
+                })));
+            }
+        }
+        let state = self.state.lock().unwrap();
+        for &(ref id, ref result) in &results {
+            if let Ok(ref samples) = *result {
+                state.record_samples(id, samples);
+            }
+        }
+        results
+    }
+
+    /// Compressed values are decompressed before they are sent to
+    /// adapters.
+    fn put_channel_value(&self, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+        send_values(&self.state, &**self.policy, self.arbitration.as_ref().map(|arbitration| &***arbitration),
+                    caller, selectors, value, priority)
+    }
+
+    fn get_state_value(&self, caller: &Caller, id: &Id<StateId>) -> Result<Option<FetchedValue>, Error> {
+        let getter = match self.state.lock().unwrap().state_channels(id) {
+            None => return Err(Error::NoSuchState(id.clone())),
+            Some((getter, _)) => getter
+        };
+        match self.get_channel_value(caller, &vec![GetterSelector::new().with_id(getter)]).pop() {
+            None => Ok(None),
+            Some((_, result)) => result.map(Some)
+        }
+    }
+
+    fn put_state_value(&self, caller: &Caller, id: &Id<StateId>, value: Value, priority: Priority) -> Result<Acknowledgement, Error> {
+        let setter = match self.state.lock().unwrap().state_channels(id) {
+            None => return Err(Error::NoSuchState(id.clone())),
+            Some((_, setter)) => setter
+        };
+        match self.put_channel_value(caller, &vec![SetterSelector::new().with_id(setter.clone())], value, priority).pop() {
+            None => Err(Error::NoSuchSetter(setter)),
+            Some((_, result)) => result
+        }
+    }
+
+    /// The write is executed by a thread of its own, so that a slow
+    /// adapter does not delay other scheduled writes. Scheduled writes
+    /// are not persisted: they are lost if the manager is dropped
+    /// before their date.
+    fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error> {
+        try!(self.policy.check(caller, &Operation::Schedule {
+            value: &value,
+            at: &at
+        }));
+        let delay = match at.duration_since(&TimeStamp::now()) {
+            Some(delay) => delay.as_duration(),
+            None => Duration::from_millis(0)
+        };
+        let mut state = self.state.lock().unwrap();
+        let id = state.new_id("schedule");
+        let task = {
+            let weak: Weak<Mutex<State>> = Arc::downgrade(&self.state);
+            let policy = self.policy.clone();
+            let arbitration = self.arbitration.clone();
+            let id = id.clone();
+            // The task runs once the state has been unlocked, i.e. once
+            // the write has been recorded.
+            self.timer.handle().schedule(Instant::now() + delay, Box::new(move || {
+                let state = match weak.upgrade() {
+                    None => return,
+                    Some(state) => state
+                };
+                let scheduled = match state.lock().unwrap().schedules.remove(&id) {
+                    None => return,
+                    Some(scheduled) => scheduled
+                };
+                let policy = policy.clone();
+                let arbitration = arbitration.clone();
+                thread::spawn(move || {
+                    let Scheduled { write, caller, .. } = scheduled;
+                    send_values(&state, &**policy, arbitration.as_ref().map(|arbitration| &***arbitration),
+                                &caller, &write.set, write.value, Priority::Automation);
+                });
+            }))
+        };
+        state.schedules.insert(id.clone(), Scheduled {
+            write: ScheduledWrite {
+                id: id.clone(),
+                set: set.clone(),
+                value: value,
+                at: at,
+                owner: caller.id.clone(),
+            },
+            caller: caller.clone(),
+            task: task,
+        });
+        Ok(id)
+    }
+
+    fn get_scheduled_writes(&self, _: &Caller) -> Vec<ScheduledWrite> {
+        let state = self.state.lock().unwrap();
+        state.schedules.values().map(|scheduled| scheduled.write.clone()).collect()
+    }
+
+    fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        match state.schedules.get(id) {
+            None => return Err(Error::NoSuchSchedule(id.clone())),
+            Some(scheduled) if scheduled.write.owner != caller.id => return Err(Error::PermissionDenied(caller.id.clone())),
+            Some(_) => {}
+        }
+        if let Some(scheduled) = state.schedules.remove(id) {
+            self.timer.handle().cancel(scheduled.task);
+        }
+        Ok(())
+    }
+
+    fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_reservations();
+        let setters: Vec<_> = state.matching_setters(set).into_iter()
+            .map(|channel| channel.id.clone())
+            .collect();
+        try!(self.policy.check(caller, &Operation::Reserve { setters: &setters }));
+        for setter in &setters {
+            try!(state.check_reservations(setter, &caller.id));
+        }
+        let reservation = Reservation {
+            id: state.new_id("reservation"),
+            holder: caller.id.clone(),
+            setters: setters,
+            expires: TimeStamp::now().plus(&duration),
+        };
+        state.reservations.insert(reservation.id.clone(), reservation.clone());
+        Ok(reservation)
+    }
+
+    fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_reservations();
+        match state.reservations.get(id) {
+            None => return Err(Error::NoSuchReservation(id.clone())),
+            Some(reservation) if reservation.holder != caller.id => return Err(Error::PermissionDenied(caller.id.clone())),
+            Some(_) => {}
+        }
+        state.reservations.remove(id);
+        Ok(())
+    }
+
+    fn get_reservations(&self, _: &Caller) -> Vec<Reservation> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_reservations();
+        state.reservations.values().cloned().collect()
+    }
+
+    /// Hints take effect the next time the scheduler wakes up, i.e.
+    /// within `PollingPolicy::min_interval`, and only while polling is
+    /// enabled, see `AdapterManager::with_polling`.
+    fn put_polling_hint(&self, caller: &Caller, source: &Vec<GetterSelector>, interval: ValDuration, duration: ValDuration) -> Result<PollingHint, Error> {
+        try!(self.policy.check(caller, &Operation::PollingHint { source: source, interval: &interval }));
+        let mut state = self.state.lock().unwrap();
+        state.purge_hints();
+        let hint = PollingHint {
+            id: state.new_id("hint"),
+            owner: caller.id.clone(),
+            source: source.clone(),
+            interval: interval,
+            expires: TimeStamp::now().plus(&duration),
+        };
+        state.hints.insert(hint.id.clone(), hint.clone());
+        Ok(hint)
+    }
+
+    fn delete_polling_hint(&self, caller: &Caller, id: &Id<PollingHintId>) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_hints();
+        match state.hints.get(id) {
+            None => return Err(Error::NoSuchPollingHint(id.clone())),
+            Some(hint) if hint.owner != caller.id => return Err(Error::PermissionDenied(caller.id.clone())),
+            Some(_) => {}
+        }
+        state.hints.remove(id);
+        Ok(())
+    }
+
+    fn get_polling_hints(&self, _: &Caller) -> Vec<PollingHint> {
+        let mut state = self.state.lock().unwrap();
+        state.purge_hints();
+        state.hints.values().cloned().collect()
+    }
+
+    fn register_channel_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error> {
+        let id = try!(self.register_watch(caller, options, cb));
+        Ok(ManagerWatchGuard {
+            id: id,
+            state: self.state.clone(),
+            active: true,
+        })
+    }
+
+    fn register_channel_watch_with_token(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        self.register_watch(caller, options, cb)
+    }
+
+    fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error> {
+        {
+            let state = self.state.lock().unwrap();
+            let watch = match state.watches.get(token) {
+                None => return Err(Error::NoSuchWatch(token.clone())),
+                Some(watch) => watch
+            };
+            try!(self.policy.check(caller, &Operation::Watch { options: &watch.info.options }));
+            if watch.caller != caller.id {
+                return Err(Error::PermissionDenied(caller.id.clone()));
+            }
+        }
+        remove_watch(&self.state, token)
+    }
+
+    fn get_watches(&self, _: &Caller) -> Result<Vec<WatchInfo>, Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state.watches.values().map(|watch| {
+            let mut info = watch.info.clone();
+            if let Some(ref buffer) = watch.buffer {
+                info.dropped = buffer.dropped();
+            }
+            info
+        }).collect())
+    }
+
+    fn get_metrics(&self, _: &Caller) -> Result<Vec<Metrics>, Error> {
+        Ok(self.state.lock().unwrap().metrics.snapshot())
+    }
+
+    fn get_adapter_config(&self, _: &Caller, id: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error> {
+        let (adapter, mut values) = {
+            let state = self.state.lock().unwrap();
+            match state.adapters.get(id) {
+                None => return Err(Error::NoSuchAdapter(id.clone())),
+                Some(adapter) => (adapter.clone(), state.config.get(id).cloned().unwrap_or_default())
+            }
+        };
+        Ok(adapter.config_schema().into_iter()
+            .map(|option| ConfigEntry {
+                value: values.remove(&option.key),
+                option: option,
+            })
+            .collect())
+    }
+
+    fn put_adapter_config(&self, caller: &Caller, id: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error> {
+        try!(self.policy.check(caller, &Operation::Configure { adapter: id, key: &key }));
+        self.configure(id, key, value)
+    }
+
+    fn get_channel_history(&self, _: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error> {
+        let state = self.state.lock().unwrap();
+        let store = match state.history {
+            None => return Err(Error::Unsupported(Capability::History)),
+            Some(ref store) => store
+        };
+        let mut result = vec![];
+        for channel in state.matching_getters(selectors) {
+            if state.retention_of(channel).is_some() {
+                let samples = try!(store.get_samples(&channel.id, since.as_ref(), until.as_ref()).map_err(Error::HistoryStore));
+                result.push((channel.id.clone(), samples));
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_channel_history_aggregate(&self, _: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>,
+                                     bucket: ValDuration, aggregation: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error> {
+        let state = self.state.lock().unwrap();
+        let store = match state.history {
+            None => return Err(Error::Unsupported(Capability::History)),
+            Some(ref store) => store
+        };
+        let mut result = vec![];
+        for channel in state.matching_getters(selectors) {
+            if state.retention_of(channel).is_some() {
+                let buckets = try!(store.get_aggregate(&channel.id, since.as_ref(), until.as_ref(), &bucket, &aggregation)
+                    .map_err(Error::HistoryStore));
+                result.push((channel.id.clone(), buckets));
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_candidates(&self, _: &Caller) -> Result<Vec<Candidate>, Error> {
+        Ok(self.state.lock().unwrap().candidates.values().cloned().collect())
+    }
+
+    fn adopt_candidate(&self, caller: &Caller, id: &Id<CandidateId>, adapter: Option<Id<AdapterId>>) -> Result<(), Error> {
+        try!(self.policy.check(caller, &Operation::Adopt { candidate: id }));
+        let (adapter_id, adapter) = {
+            let state = self.state.lock().unwrap();
+            let candidate = match state.candidates.get(id) {
+                None => return Err(Error::NoSuchCandidate(id.clone())),
+                Some(candidate) => candidate
+            };
+            let adapter_id = match adapter {
+                Some(ref adapter) if candidate.adapters.contains(adapter) => adapter.clone(),
+                None if !candidate.adapters.is_empty() => candidate.adapters[0].clone(),
+                _ => return Err(Error::NoSuchCandidate(id.clone()))
+            };
+            match state.adapters.get(&adapter_id) {
+                None => return Err(Error::NoSuchAdapter(adapter_id)),
+                Some(adapter) => (adapter_id, adapter.clone())
+            }
+        };
+        // The adapter is called without holding the lock, as it
+        // registers nodes with the manager.
+        try!(adapter.adopt(id).map_err(|error| adapter_error(&adapter_id, error)));
+        if let Some(candidate) = self.state.lock().unwrap().candidates.get_mut(id) {
+            candidate.status = CandidateStatus::Adopted(adapter_id);
+        }
+        Ok(())
+    }
+
+    fn reject_candidate(&self, caller: &Caller, id: &Id<CandidateId>) -> Result<(), Error> {
+        try!(self.policy.check(caller, &Operation::Adopt { candidate: id }));
+        match self.state.lock().unwrap().candidates.get_mut(id) {
+            None => Err(Error::NoSuchCandidate(id.clone())),
+            Some(candidate) => {
+                candidate.status = CandidateStatus::Rejected;
+                Ok(())
+            }
+        }
+    }
+
+    fn register_durable_watch(&self, caller: &Caller, owner: String, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        if self.state.lock().unwrap().watch_store.is_none() {
+            return Err(Error::Unsupported(Capability::DurableWatches));
+        }
+        let id = try!(self.register_watch(caller, options, cb));
+        let result = {
+            let mut state = self.state.lock().unwrap();
+            let result = state.make_durable(&id, &owner);
+            if result.is_err() {
+                state.watches.remove(&id);
+            }
+            result
+        };
+        sync_adapter_watches(&self.state);
+        try!(result.map_err(Error::WatchStore));
+        Ok(id)
+    }
+
+    fn resume_watch(&self, caller: &Caller, owner: &str, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        let subscription = {
+            let state = self.state.lock().unwrap();
+            let store = match state.watch_store {
+                None => return Err(Error::Unsupported(Capability::DurableWatches)),
+                Some(ref store) => store
+            };
+            match try!(store.get(owner).map_err(Error::WatchStore)) {
+                None => return Err(Error::NoSuchSubscription(owner.to_owned())),
+                Some(subscription) => subscription
+            }
+        };
+        let id = try!(self.register_watch(caller, subscription.options.clone(), cb));
+        let result = {
+            let mut state = self.state.lock().unwrap();
+            // Replay the changes of topology since the subscription was
+            // last saved.
+            let current = state.topology_of(&subscription.options);
+            let mut events: Vec<_> = subscription.getters.iter()
+                .filter(|getter| !current.contains(getter))
+                .map(|getter| WatchEvent::GetterRemoved(getter.clone()))
+                .collect();
+            events.extend(current.iter()
+                .filter(|getter| !subscription.getters.contains(getter))
+                .map(|getter| WatchEvent::GetterAdded(getter.clone())));
+            match state.make_durable(&id, owner) {
+                Err(err) => {
+                    state.watches.remove(&id);
+                    Err(err)
+                }
+                Ok(()) => {
+                    let mut deliveries = vec![];
+                    if let Some(watch) = state.watches.get_mut(&id) {
+                        watch.info.events += events.len() as u64;
+                        for event in events {
+                            deliveries.push((watch.cb.clone(), event));
+                        }
+                    }
+                    Ok(deliveries)
+                }
+            }
+        };
+        sync_adapter_watches(&self.state);
+        deliver(try!(result.map_err(Error::WatchStore)));
+        Ok(id)
+    }
+
+    fn get_adapter_health(&self, _: &Caller) -> Result<Vec<AdapterHealth>, Error> {
+        let (adapters, timeout) = {
+            let state = self.state.lock().unwrap();
+            let adapters: Vec<_> = state.adapters.iter()
+                .filter_map(|(id, adapter)| state.workers.get(id).map(|worker| {
+                    let status = if !state.unavailable.contains(id) {
+                        AdapterStatus::Running
+                    } else {
+                        match state.failures.get(id) {
+                            Some(error) => AdapterStatus::Failed(error.clone()),
+                            None => AdapterStatus::Stopped
+                        }
+                    };
+                    (adapter.clone(), worker.clone(), status)
+                }))
+                .collect();
+            (adapters, state.dispatch.health_check_timeout.as_duration())
+        };
+
+        // Check all adapters at once, then wait until they are done.
+        let pending: Vec<_> = adapters.into_iter()
+            .map(|(adapter, worker, status)| {
+                let check = worker.check();
+                (adapter, worker, status, check)
+            })
+            .collect();
+        let deadline = Instant::now() + timeout;
+        Ok(pending.into_iter()
+            .map(|(adapter, worker, status, check)| {
+                let (fetches, sends, last_error) = worker.stats();
+                AdapterHealth {
+                    adapter: adapter.id(),
+                    name: adapter.name().to_owned(),
+                    status: status,
+                    check: check.wait(deadline),
+                    last_error: last_error,
+                    fetches: fetches,
+                    sends: sends,
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdapterManager;
+    use adapter::{ namespaced, Adapter, AdapterError, AdapterManagerHandle, AdapterWatchGuard, Watchers };
+    use api::*;
+    use arbitration::Priority;
+    use auth::{ Caller, CallerKind, Scope };
+    use devices::*;
+    use selector::*;
+    use values::{ Temperature, ValDuration, Value };
+    use util::{ Id, TagId };
+
+    use std::sync::{ Arc, Mutex };
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    const VERSION: [u32; 4] = [0, 0, 0, 0];
+
+    /// An adapter exposing a node with a thermometer, which supports
+    /// watching and may be polled at most once an hour, and a switch.
+    struct Stub {
+        id: Id<AdapterId>,
+        thermometer: Id<Getter>,
+        switch: Id<Setter>,
+
+        /// The value of the thermometer.
+        value: Mutex<Option<Value>>,
+
+        /// The number of values fetched so far.
+        fetches: Mutex<usize>,
+
+        /// The values sent to the switch so far.
+        sent: Mutex<Vec<Value>>,
+
+        /// The nodes forgotten so far, or `None` if the device cannot
+        /// be unpaired.
+        forgotten: Mutex<Option<Vec<Id<NodeId>>>>,
+
+        watchers: Watchers,
+    }
+
+    impl Stub {
+        fn new(name: &str) -> Self {
+            let id = Id::new(format!("{}@test", name));
+            Stub {
+                thermometer: namespaced(&id, "stub/thermometer"),
+                switch: namespaced(&id, "stub/switch"),
+                id: id,
+                value: Mutex::new(None),
+                fetches: Mutex::new(0),
+                sent: Mutex::new(vec![]),
+                forgotten: Mutex::new(Some(vec![])),
+                watchers: Watchers::new(),
+            }
+        }
+
+        fn node(&self) -> Node {
+            let node = namespaced(&self.id, "stub");
+            let mut thermometer = Getter::new(ChannelKind::ActualTemperature).with_watch(true);
+            thermometer.poll = Some(ValDuration::new(Duration::from_secs(3600)));
+            Node::new(node.clone())
+                .with_getter(Channel::new(self.thermometer.clone(), node.clone(), thermometer))
+                .with_setter(Channel::new(self.switch.clone(), node, Setter::new(ChannelKind::OnOff)))
+        }
+
+        /// Simulate the thermometer pushing a new reading.
+        fn push(&self, value: Value) {
+            *self.value.lock().unwrap() = Some(value.clone());
+            self.watchers.notify(&self.thermometer, Ok(value));
+        }
+    }
+
+    impl Adapter for Stub {
+        fn id(&self) -> Id<AdapterId> {
+            self.id.clone()
+        }
+
+        fn name(&self) -> &str {
+            "Stub"
+        }
+
+        fn vendor(&self) -> &str {
+            "test"
+        }
+
+        fn version(&self) -> &[u32; 4] {
+            &VERSION
+        }
+
+        fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+            getters.into_iter().map(|id| {
+                assert_eq!(id, self.thermometer);
+                *self.fetches.lock().unwrap() += 1;
+                (id, Ok(self.value.lock().unwrap().clone()))
+            }).collect()
+        }
+
+        fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+            values.into_iter().map(|(id, value)| {
+                assert_eq!(id, self.switch);
+                self.sent.lock().unwrap().push(value);
+                (id, Ok(()))
+            }).collect()
+        }
+
+        fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+            -> Result<Box<AdapterWatchGuard>, AdapterError> {
+            assert_eq!(*getter, self.thermometer);
+            Ok(self.watchers.add(getter, cb))
+        }
+
+        fn forget(&self, node: &Id<NodeId>) -> Result<(), AdapterError> {
+            match *self.forgotten.lock().unwrap() {
+                None => Err(AdapterError::DeviceUnreachable),
+                Some(ref mut forgotten) => {
+                    forgotten.push(node.clone());
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn celsius(c: f64) -> Value {
+        Value::Temperature(Temperature::C(c))
+    }
+
+    /// A manager with two stub adapters.
+    fn setup() -> (AdapterManager, Arc<Stub>, Arc<Stub>) {
+        let manager = AdapterManager::new();
+        let kitchen = Arc::new(Stub::new("kitchen"));
+        let garage = Arc::new(Stub::new("garage"));
+        manager.add_adapter_with_nodes(kitchen.clone(), vec![kitchen.node()]).unwrap();
+        manager.add_adapter_with_nodes(garage.clone(), vec![garage.node()]).unwrap();
+        (manager, kitchen, garage)
+    }
+
+    #[test]
+    fn test_routing() {
+        let (manager, kitchen, garage) = setup();
+        let system = Caller::system();
+        *kitchen.value.lock().unwrap() = Some(celsius(20.));
+        *garage.value.lock().unwrap() = Some(celsius(5.));
+
+        let mut values = manager.get_channel_value(&system, &vec![GetterSelector::new()]);
+        values.sort_by(|a, b| a.0.as_string().cmp(b.0.as_string()));
+        assert_eq!(values.len(), 2);
+        let garage_value = values[0].1.as_ref().unwrap();
+        assert_eq!(values[0].0, garage.thermometer);
+        assert_eq!(garage_value.value, celsius(5.));
+        assert_eq!(garage_value.adapter, garage.id);
+        let kitchen_value = values[1].1.as_ref().unwrap();
+        assert_eq!(values[1].0, kitchen.thermometer);
+        assert_eq!(kitchen_value.value, celsius(20.));
+        assert_eq!(kitchen_value.adapter, kitchen.id);
+
+        let results = manager.put_channel_value(&system, &vec![SetterSelector::new().with_id(garage.switch.clone())],
+                                                Value::Bool(true), Priority::Manual);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+        assert_eq!(*garage.sent.lock().unwrap(), vec![Value::Bool(true)]);
+        assert!(kitchen.sent.lock().unwrap().is_empty());
+
+        // Values of the wrong type never reach the adapter.
+        let results = manager.put_channel_value(&system, &vec![SetterSelector::new()], celsius(1.), Priority::Manual);
+        assert_eq!(results.len(), 2);
+        for (_, result) in results {
+            match result {
+                Err(Error::TypeError) => {}
+                other => panic!("Unexpected result {:?}", other)
+            }
+        }
+        assert_eq!(garage.sent.lock().unwrap().len(), 1);
+        assert!(kitchen.sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_watch_dispatch() {
+        let (manager, kitchen, garage) = setup();
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let guard = manager.register_channel_watch(&Caller::system(), vec![
+            WatchOptions::new().with_getters(GetterSelector::new().with_id(kitchen.thermometer.clone())).with_watch_values(true)
+        ], Box::new(move |event| tx.lock().unwrap().send(event).unwrap())).unwrap();
+
+        garage.push(celsius(5.));
+        kitchen.push(celsius(21.));
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            WatchEvent::Value { from, value } => {
+                assert_eq!(from, kitchen.thermometer);
+                assert_eq!(value, celsius(21.));
+            }
+            other => panic!("Unexpected event {:?}", other)
+        }
+
+        // Dropping the guard stops the delivery of events.
+        drop(guard);
+        kitchen.push(celsius(22.));
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn test_tagging() {
+        let (manager, kitchen, garage) = setup();
+        let system = Caller::system();
+        let warm = TagId::new("warm").unwrap();
+        let result = manager.put_getter_tag(&system, &vec![GetterSelector::new().with_id(kitchen.thermometer.clone())],
+                                            &vec![warm.clone()]).unwrap();
+        assert_eq!(result.changed, vec![kitchen.thermometer.clone()]);
+
+        let tagged = manager.get_getter_channels(&system, &vec![GetterSelector::new().with_tags(vec![warm.clone()])]);
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].id, kitchen.thermometer);
+
+        let result = manager.delete_getter_tag(&system, &vec![GetterSelector::new()], &vec![warm.clone()]).unwrap();
+        assert_eq!(result.changed, vec![kitchen.thermometer.clone()]);
+        assert_eq!(result.unchanged, vec![garage.thermometer.clone()]);
+        assert!(manager.get_getter_channels(&system, &vec![GetterSelector::new().with_tags(vec![warm])]).is_empty());
+
+        // Tagging rules also apply to nodes added later.
+        let outside = TagId::new("outside").unwrap();
+        manager.put_tagging_rule(&system, TaggingRule {
+            set: vec![NodeSelector::new()],
+            tags: vec![outside.clone()],
+        }).unwrap();
+        let shed = Arc::new(Stub::new("shed"));
+        manager.add_adapter_with_nodes(shed.clone(), vec![shed.node()]).unwrap();
+        let tagged = manager.get_nodes(&system, &vec![NodeSelector::new().with_tags(vec![outside])]);
+        assert_eq!(tagged.len(), 3);
+    }
+
+    #[test]
+    fn test_forget_node() {
+        let (manager, kitchen, garage) = setup();
+        let system = Caller::system();
+        let kitchen_node = namespaced(&kitchen.id, "stub");
+        let garage_node = namespaced(&garage.id, "stub");
+
+        manager.forget_node(&system, &kitchen_node).unwrap();
+        assert_eq!(*kitchen.forgotten.lock().unwrap(), Some(vec![kitchen_node.clone()]));
+        assert!(manager.get_nodes(&system, &vec![NodeSelector::new().with_id(kitchen_node.clone())]).is_empty());
+        match manager.forget_node(&system, &kitchen_node) {
+            Err(Error::NoSuchNode(ref id)) => assert_eq!(*id, kitchen_node),
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        // If the adapter cannot forget the device, the node is kept.
+        *garage.forgotten.lock().unwrap() = None;
+        match manager.forget_node(&system, &garage_node) {
+            Err(Error::Adapter { ref adapter, error: AdapterError::DeviceUnreachable }) => assert_eq!(*adapter, garage.id),
+            other => panic!("Unexpected result {:?}", other)
+        }
+        assert_eq!(manager.get_nodes(&system, &vec![NodeSelector::new().with_id(garage_node)]).len(), 1);
+    }
+
+    #[test]
+    fn test_unregister_watch() {
+        let (manager, _, _) = setup();
+        let system = Caller::system();
+        let alice = Caller::new(Id::new("alice".to_owned()), CallerKind::User).with_scopes(vec![Scope::Watch]);
+        let bob = Caller::new(Id::new("bob".to_owned()), CallerKind::User).with_scopes(vec![Scope::Watch]);
+        let guest = Caller::new(Id::new("guest".to_owned()), CallerKind::User);
+        let options = vec![WatchOptions::new().with_watch_values(true)];
+
+        let token = manager.register_channel_watch_with_token(&alice, options.clone(), Box::new(|_| {})).unwrap();
+        for caller in &[&bob, &guest, &system] {
+            match manager.unregister_watch(caller, &token) {
+                Err(Error::PermissionDenied(ref id)) => assert_eq!(*id, caller.id),
+                other => panic!("Unexpected result {:?}", other)
+            }
+        }
+        assert_eq!(manager.get_watches(&system).unwrap().len(), 1);
+
+        manager.unregister_watch(&alice, &token).unwrap();
+        assert!(manager.get_watches(&system).unwrap().is_empty());
+        match manager.unregister_watch(&alice, &token) {
+            Err(Error::NoSuchWatch(ref id)) => assert_eq!(*id, token),
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_permissions() {
+        let (manager, kitchen, _) = setup();
+        let system = Caller::system();
+        let guest = Caller::new(Id::new("guest".to_owned()), CallerKind::User);
+
+        let results = manager.put_channel_value(&guest, &vec![SetterSelector::new().with_id(kitchen.switch.clone())],
+                                                Value::Bool(true), Priority::Manual);
+        match results[0].1 {
+            Err(Error::PermissionDenied(ref id)) => assert_eq!(*id, guest.id),
+            ref other => panic!("Unexpected result {:?}", other)
+        }
+        assert!(kitchen.sent.lock().unwrap().is_empty());
+
+        let watch = manager.register_channel_watch(&guest, vec![WatchOptions::new().with_watch_values(true)], Box::new(|_| {}));
+        match watch {
+            Err(Error::PermissionDenied(_)) => {}
+            Err(other) => panic!("Unexpected error {:?}", other),
+            Ok(_) => panic!("The watch should have been rejected")
+        }
+
+        let tags = vec![TagId::new("kitchen").unwrap()];
+        match manager.put_getter_tag(&guest, &vec![GetterSelector::new()], &tags) {
+            Err(Error::PermissionDenied(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        let hour = ValDuration::new(Duration::from_secs(3600));
+        match manager.put_polling_hint(&guest, &vec![GetterSelector::new()], hour.clone(), hour.clone()) {
+            Err(Error::PermissionDenied(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+        assert!(manager.get_polling_hints(&system).is_empty());
+        let watcher = guest.clone().with_scopes(vec![Scope::Watch]);
+        manager.put_polling_hint(&watcher, &vec![GetterSelector::new()], hour.clone(), hour).unwrap();
+
+        // Removing a tagging rule requires the same scope as adding it.
+        let rule = manager.put_tagging_rule(&system, TaggingRule {
+            set: vec![NodeSelector::new()],
+            tags: tags.clone(),
+        }).unwrap();
+        match manager.delete_tagging_rule(&guest, &rule) {
+            Err(Error::PermissionDenied(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+        assert_eq!(manager.get_tagging_rules(&system).len(), 1);
+        let tagger = guest.clone().with_scopes(vec![Scope::WriteTags]);
+        manager.delete_tagging_rule(&tagger, &rule).unwrap();
+        assert!(manager.get_tagging_rules(&system).is_empty());
+    }
+
+    #[test]
+    fn test_poll_now_respects_interval() {
+        let (manager, kitchen, _) = setup();
+        let system = Caller::system();
+        let selectors = vec![GetterSelector::new().with_id(kitchen.thermometer.clone())];
+        *kitchen.value.lock().unwrap() = Some(celsius(20.));
+
+        let first = manager.poll_now(&system, &selectors);
+        assert_eq!(first[0].1.as_ref().unwrap().source, ValueSource::Live);
+        assert_eq!(*kitchen.fetches.lock().unwrap(), 1);
+
+        // Polled less than an hour ago: the latest value is served again.
+        *kitchen.value.lock().unwrap() = Some(celsius(25.));
+        let second = manager.poll_now(&system, &selectors);
+        let fetched = second[0].1.as_ref().unwrap();
+        assert_eq!(fetched.source, ValueSource::Cache);
+        assert_eq!(fetched.value, celsius(20.));
+        assert_eq!(*kitchen.fetches.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,303 @@
+//!
+//! Polling getters that do not support watching.
+//!
+//! The scheduler runs on a thread of its own. It polls a getter only
+//! while the getter is watched by at least one application and its
+//! adapter is running, at the interval declared by `Getter::poll` or
+//! requested with `WatchOptions::with_emulation`, whichever is shorter.
+//! Getters that emit events have no current value, so they are never
+//! polled.
+//! Each batch of polls to an adapter is dispatched to the workers of the
+//! adapter by a short-lived thread, so that a slow adapter cannot delay
+//! the polls of other adapters.
+//!
+//! Getters matching a polling hint, as requested with
+//! `API::put_polling_hint`, are also polled while the hint is active,
+//! whether they are watched or not, at the interval requested by the
+//! hint, but never more often than declared by `Getter::poll`. This
+//! lets e.g. an open dashboard obtain fresher values than an idle
+//! system.
+//!
+
+use super::State;
+use super::worker::Worker;
+use api::Emulation;
+use delivery::deliver;
+use devices::{ AdapterId, Getter };
+use values::{ TimeStamp, ValDuration };
+use util::Id;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+/// How `AdapterManager` polls getters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PollingPolicy {
+    /// A random delay added to each poll, as a fraction of the polling
+    /// interval of the getter, between 0 and 1. This prevents getters
+    /// with the same interval from being polled all at once.
+    pub jitter: f64,
+
+    /// The largest number of batches of polls in progress at any time
+    /// for a single adapter, e.g. 1 for an adapter talking to devices
+    /// through a serial port.
+    pub max_concurrent_per_adapter: usize,
+
+    /// The shortest interval between two polls of a getter, whatever
+    /// its declared interval. This is also the longest delay before the
+    /// scheduler starts polling a getter that has just been watched.
+    pub min_interval: ValDuration,
+}
+
+impl Default for PollingPolicy {
+    fn default() -> Self {
+        PollingPolicy {
+            jitter: 0.1,
+            max_concurrent_per_adapter: 1,
+            min_interval: ValDuration::new(Duration::from_secs(1)),
+        }
+    }
+}
+
+struct Control {
+    stopping: bool,
+
+    /// The number of batches of polls in progress for each adapter.
+    in_flight: HashMap<Id<AdapterId>, usize>,
+}
+
+type Shared = Arc<(Mutex<Control>, Condvar)>;
+
+/// The thread polling getters. Dropping the scheduler stops it.
+pub struct Scheduler {
+    control: Shared,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn start(state: Arc<Mutex<State>>, policy: PollingPolicy) -> Self {
+        let control = Arc::new((Mutex::new(Control {
+            stopping: false,
+            in_flight: HashMap::new(),
+        }), Condvar::new()));
+        let thread = {
+            let control = control.clone();
+            thread::spawn(move || run(state, policy, control))
+        };
+        Scheduler {
+            control: control,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop polling, waiting until the polls in progress are complete.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            {
+                let &(ref lock, ref cvar) = &*self.control;
+                lock.lock().unwrap().stopping = true;
+                cvar.notify_all();
+            }
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// A xorshift generator, which is good enough to spread polls over
+/// time.
+struct Jitter(u64);
+
+impl Jitter {
+    fn new() -> Self {
+        let seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() ^ duration.subsec_nanos() as u64,
+            Err(_) => 0
+        };
+        Jitter(seed | 1)
+    }
+
+    /// A random delay between 0 and `fraction * interval`.
+    fn delay(&mut self, interval: Duration, fraction: f64) -> Duration {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        let random = (self.0 % 1_000_000) as f64 / 1_000_000.;
+        let fraction = fraction.max(0.).min(1.);
+        let ms = ValDuration::new(interval).as_ms() as f64 * fraction * random;
+        Duration::from_millis(ms as u64)
+    }
+}
+
+/// The getters to poll, along with the workers of their adapter and
+/// their polling interval.
+fn demand(state: &State) -> HashMap<Id<Getter>, (Worker, Duration)> {
+    let now = TimeStamp::now();
+    let hints: Vec<_> = state.hints.values()
+        .filter(|hint| hint.expires > now)
+        .collect();
+    let mut result = HashMap::new();
+    for node in &state.nodes {
+        let worker = match state.owners.get(&node.id) {
+            Some(owner) if !state.unavailable.contains(owner) => match state.workers.get(owner) {
+                None => continue,
+                Some(worker) => worker
+            },
+            _ => continue
+        };
+        for channel in node.getters.iter().filter(|channel| !channel.mechanism.watch && !channel.mechanism.is_event()) {
+            let mut interval = None;
+            for watch in state.watches.values() {
+                for options in &watch.info.options {
+                    if !options.should_watch_values || !options.source.matches(channel) {
+                        continue;
+                    }
+                    let requested = match options.emulation {
+                        Some(Emulation { interval: Some(ref interval), .. }) => Some(interval),
+                        _ => channel.mechanism.poll.as_ref()
+                    };
+                    interval = match (interval, requested.map(ValDuration::as_duration)) {
+                        (Some(interval), Some(requested)) => Some(cmp::min(interval, requested)),
+                        (interval, requested) => interval.or(requested)
+                    };
+                }
+            }
+            // Polling hints never require polling a getter more often
+            // than it declares.
+            if let Some(ref poll) = channel.mechanism.poll {
+                let hinted = hints.iter()
+                    .filter(|hint| hint.source.iter().any(|selector| selector.matches(channel)))
+                    .map(|hint| cmp::max(hint.interval.as_duration(), poll.as_duration()))
+                    .min();
+                interval = match (interval, hinted) {
+                    (Some(interval), Some(hinted)) => Some(cmp::min(interval, hinted)),
+                    (interval, hinted) => interval.or(hinted)
+                };
+            }
+            // Recorded getters are polled at their own pace, even if no
+            // application watches them.
+            if interval.is_none() && state.retention_of(channel).is_some() {
+                interval = channel.mechanism.poll.as_ref().map(ValDuration::as_duration);
+            }
+            if let Some(interval) = interval {
+                result.insert(channel.id.clone(), (worker.clone(), interval));
+            }
+        }
+    }
+    result
+}
+
+fn run(state: Arc<Mutex<State>>, policy: PollingPolicy, control: Shared) {
+    let min_interval = policy.min_interval.as_duration();
+    let mut jitter = Jitter::new();
+
+    // The date at which each polled getter is due.
+    let mut due: HashMap<Id<Getter>, Instant> = HashMap::new();
+
+    let &(ref lock, ref cvar) = &*control;
+    loop {
+        let demand = demand(&state.lock().unwrap());
+        let now = Instant::now();
+        due.retain(|id, _| demand.contains_key(id));
+        for (id, &(_, interval)) in &demand {
+            if !due.contains_key(id) {
+                due.insert(id.clone(), now + jitter.delay(interval, policy.jitter));
+            }
+        }
+
+        let mut batches: HashMap<Id<AdapterId>, (Worker, Vec<Id<Getter>>)> = HashMap::new();
+        for (id, when) in &due {
+            if *when <= now {
+                let worker = &demand[id].0;
+                batches.entry(worker.adapter().clone())
+                    .or_insert_with(|| (worker.clone(), vec![]))
+                    .1.push(id.clone());
+            }
+        }
+
+        let mut guard = lock.lock().unwrap();
+        if guard.stopping {
+            break;
+        }
+        for (adapter_id, (worker, getters)) in batches {
+            {
+                let count = guard.in_flight.entry(adapter_id.clone()).or_insert(0);
+                if *count >= policy.max_concurrent_per_adapter {
+                    // The getters remain due, and will be polled once a
+                    // batch is complete.
+                    continue;
+                }
+                *count += 1;
+            }
+            for id in &getters {
+                let interval = cmp::max(demand[id].1, min_interval);
+                due.insert(id.clone(), now + interval + jitter.delay(interval, policy.jitter));
+            }
+            let in_flight = InFlight {
+                control: control.clone(),
+                adapter: adapter_id,
+            };
+            let state = state.clone();
+            thread::spawn(move || poll(state, worker, getters, in_flight));
+        }
+
+        let timeout = match due.values().min() {
+            Some(when) if *when > now => cmp::min(*when - now, min_interval),
+            _ => min_interval
+        };
+        let (guard, _) = cvar.wait_timeout(guard, timeout).unwrap();
+        if guard.stopping {
+            break;
+        }
+    }
+
+    // Wait until the polls in progress are complete.
+    let mut guard = lock.lock().unwrap();
+    while guard.in_flight.values().any(|count| *count > 0) {
+        guard = cvar.wait(guard).unwrap();
+    }
+}
+
+/// A batch of polls in progress. Dropping it, even if the adapter has
+/// panicked, lets the scheduler start another batch for the adapter.
+struct InFlight {
+    control: Shared,
+    adapter: Id<AdapterId>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let &(ref lock, ref cvar) = &*self.control;
+        if let Ok(mut guard) = lock.lock() {
+            if let Some(count) = guard.in_flight.get_mut(&self.adapter) {
+                *count -= 1;
+            }
+        }
+        cvar.notify_all();
+    }
+}
+
+fn poll(state: Arc<Mutex<State>>, worker: Worker, getters: Vec<Id<Getter>>, in_flight: InFlight) {
+    for (id, result) in worker.fetch(getters).wait() {
+        let result = match result {
+            Ok(None) => continue,
+            Ok(Some(value)) => Ok(value),
+            Err(error) => Err(error)
+        };
+        let deliveries = state.lock().unwrap().produce_polled(&id, result);
+        deliver(deliveries);
+    }
+    drop(in_flight);
+}
@@ -0,0 +1,169 @@
+//!
+//! Storing the nodes of the manager.
+//!
+//! A `Slab` keeps its values in a single vector and refers to each
+//! value by a handle, its position in the vector. Handles remain valid
+//! until the value is removed, as removing a value leaves a vacant
+//! entry, which is reused by the next value inserted. This lets the
+//! manager resolve ids to nodes and channels with a lookup in the
+//! `Index` followed by an access to the vector, without updating the
+//! handles of other values whenever a node is removed.
+//!
+//! Values are iterated in the order of their handles, which is not
+//! necessarily the order in which they have been inserted.
+//!
+
+use std::iter::Enumerate;
+use std::mem;
+use std::ops;
+use std::slice;
+
+enum Entry<T> {
+    Occupied(T),
+
+    /// A vacant entry, along with the next vacant entry, if any.
+    Vacant(Option<usize>),
+}
+
+pub struct Slab<T> {
+    entries: Vec<Entry<T>>,
+
+    /// The vacant entry to reuse first, if any.
+    vacant: Option<usize>,
+
+    len: usize,
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Slab {
+            entries: vec![],
+            vacant: None,
+            len: 0,
+        }
+    }
+
+    /// The number of values.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Insert a value, returning its handle.
+    pub fn insert(&mut self, value: T) -> usize {
+        self.len += 1;
+        match self.vacant {
+            None => {
+                self.entries.push(Entry::Occupied(value));
+                self.entries.len() - 1
+            }
+            Some(handle) => {
+                if let Entry::Vacant(next) = self.entries[handle] {
+                    self.vacant = next;
+                }
+                self.entries[handle] = Entry::Occupied(value);
+                handle
+            }
+        }
+    }
+
+    /// Remove the value with handle `handle`, if any.
+    pub fn remove(&mut self, handle: usize) -> Option<T> {
+        match self.entries.get(handle) {
+            Some(&Entry::Occupied(_)) => {}
+            _ => return None
+        }
+        self.len -= 1;
+        let vacant = Entry::Vacant(self.vacant);
+        self.vacant = Some(handle);
+        match mem::replace(&mut self.entries[handle], vacant) {
+            Entry::Occupied(value) => Some(value),
+            Entry::Vacant(_) => unreachable!()
+        }
+    }
+
+    pub fn get(&self, handle: usize) -> Option<&T> {
+        match self.entries.get(handle) {
+            Some(&Entry::Occupied(ref value)) => Some(value),
+            _ => None
+        }
+    }
+
+    pub fn get_mut(&mut self, handle: usize) -> Option<&mut T> {
+        match self.entries.get_mut(handle) {
+            Some(&mut Entry::Occupied(ref mut value)) => Some(value),
+            _ => None
+        }
+    }
+
+    /// The values, in the order of their handles.
+    pub fn iter(&self) -> Iter<T> {
+        Iter {
+            entries: self.entries.iter()
+        }
+    }
+
+    /// The handles, along with the values, in the order of the handles.
+    pub fn entries(&self) -> Entries<T> {
+        Entries {
+            entries: self.entries.iter().enumerate()
+        }
+    }
+
+    /// The handles, in increasing order.
+    pub fn handles(&self) -> Vec<usize> {
+        self.entries().map(|(handle, _)| handle).collect()
+    }
+}
+
+impl<T> ops::Index<usize> for Slab<T> {
+    type Output = T;
+    fn index(&self, handle: usize) -> &T {
+        self.get(handle).expect("No value with this handle")
+    }
+}
+
+impl<T> ops::IndexMut<usize> for Slab<T> {
+    fn index_mut(&mut self, handle: usize) -> &mut T {
+        self.get_mut(handle).expect("No value with this handle")
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Slab<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    entries: slice::Iter<'a, Entry<T>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        for entry in &mut self.entries {
+            if let Entry::Occupied(ref value) = *entry {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+pub struct Entries<'a, T: 'a> {
+    entries: Enumerate<slice::Iter<'a, Entry<T>>>,
+}
+
+impl<'a, T> Iterator for Entries<'a, T> {
+    type Item = (usize, &'a T);
+    fn next(&mut self) -> Option<(usize, &'a T)> {
+        for (handle, entry) in &mut self.entries {
+            if let Entry::Occupied(ref value) = *entry {
+                return Some((handle, value));
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,43 @@
+//!
+//! Saving and restoring the topology known to the manager.
+//!
+//! Adapters may take a while to find their devices again after a
+//! reboot, e.g. while they scan a Z-Wave network. A `Snapshot` of the
+//! manager, saved before shutting down and restored at startup with
+//! `AdapterManager::restore`, lets the FoxBox present the known nodes,
+//! along with their tags and labels, immediately. Restored nodes are
+//! reported as unavailable until their adapter registers them again.
+//!
+
+use api::{ TaggingRule, TaggingRuleId };
+use devices::{ AdapterId, Node };
+use values::{ TimeStamp, Value };
+use util::Id;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A node, along with the adapter that registered it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KnownNode {
+    pub adapter: Id<AdapterId>,
+
+    /// The node, its channels, their tags and their labels.
+    pub node: Arc<Node>,
+}
+
+/// The state of the manager worth keeping across reboots.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// When the snapshot was taken.
+    pub taken: TimeStamp,
+
+    pub nodes: Vec<KnownNode>,
+
+    #[serde(default)]
+    pub tagging_rules: Vec<(Id<TaggingRuleId>, TaggingRule)>,
+
+    /// The options of the configuration of adapters that have been set.
+    #[serde(default)]
+    pub config: HashMap<Id<AdapterId>, HashMap<String, Value>>,
+}
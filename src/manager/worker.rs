@@ -0,0 +1,516 @@
+//!
+//! Dispatching fetches and sends to adapters.
+//!
+//! Each adapter is served by worker threads of its own, which take
+//! requests from a bounded queue. A request is queued without waiting,
+//! so an adapter that blocks, e.g. while talking to a serial Z-Wave
+//! stick, only delays the requests destined to this adapter. Once the
+//! queue of an adapter is full, further requests fail immediately with
+//! `Error::Overloaded`.
+//!
+//! Channels that fail with a transient error are attempted again, and
+//! requests that take too long are abandoned, as specified for each
+//! adapter and each type of operation by a `RetryPolicy`.
+//!
+//! Workers also keep the statistics reported by
+//! `API::get_adapter_health` and feed the metrics of adapters and
+//! channels.
+//!
+
+use super::adapter_error;
+use adapter::{ Adapter, AdapterError };
+use api::{ Acknowledgement, Error, RequestStats, TimedError };
+use devices::{ AdapterId, Getter, Setter };
+use history::Sample;
+use metrics::{ Operation, Registry, Target };
+use values::{ TimeStamp, ValDuration, Value };
+use util::Id;
+
+use std::collections::HashMap;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::{ channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+/// How `AdapterManager` dispatches fetches and sends to adapters.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DispatchPolicy {
+    /// The number of threads serving each adapter, i.e. the largest
+    /// number of fetches and sends in progress at any time for a single
+    /// adapter. At least 1.
+    pub workers_per_adapter: usize,
+
+    /// The largest number of fetches and sends waiting for a worker of
+    /// a single adapter. Beyond this, requests fail with
+    /// `Error::Overloaded`.
+    pub queue_capacity: usize,
+
+    /// The longest time `API::get_adapter_health` waits for the health
+    /// check of an adapter, including the time spent in its queue.
+    pub health_check_timeout: ValDuration,
+
+    /// How to retry fetches and sends, for the adapters that are not
+    /// listed in `adapters`.
+    #[serde(default)]
+    pub retries: RetryPolicies,
+
+    /// How to retry the fetches and sends of specific adapters, e.g.
+    /// of an adapter talking to devices over a flaky radio.
+    #[serde(default)]
+    pub adapters: HashMap<Id<AdapterId>, RetryPolicies>,
+}
+
+impl DispatchPolicy {
+    /// How to retry the fetches and sends of `adapter`.
+    pub fn retries_for(&self, adapter: &Id<AdapterId>) -> &RetryPolicies {
+        self.adapters.get(adapter).unwrap_or(&self.retries)
+    }
+}
+
+impl Default for DispatchPolicy {
+    fn default() -> Self {
+        DispatchPolicy {
+            workers_per_adapter: 1,
+            queue_capacity: 64,
+            health_check_timeout: ValDuration::new(Duration::from_secs(5)),
+            retries: RetryPolicies::default(),
+            adapters: HashMap::new(),
+        }
+    }
+}
+
+/// How to retry a type of operation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// The longest time an operation may take, including the time
+    /// spent in the queue of the adapter and retries. Beyond this, the
+    /// operation fails with `Error::TimedOut`.
+    pub timeout: ValDuration,
+
+    /// The number of times channels that fail with a transient error
+    /// are attempted again. Once retries are exhausted, the channels
+    /// fail with `Error::RetriesExhausted`. By default, 0.
+    pub retries: u32,
+
+    /// The delay before the first retry. The delay doubles with each
+    /// retry. Channels are not attempted again if the delay would
+    /// exceed `timeout`.
+    pub backoff: ValDuration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: ValDuration::new(Duration::from_secs(30)),
+            retries: 0,
+            backoff: ValDuration::new(Duration::from_millis(250)),
+        }
+    }
+}
+
+/// How to retry each type of operation.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RetryPolicies {
+    pub fetch: RetryPolicy,
+    pub send: RetryPolicy,
+}
+
+/// The results of a request, along with the number of attempts.
+type Reply<T, V> = (Vec<(Id<T>, Result<V, AdapterError>)>, u32);
+
+enum Request {
+    /// A fetch, to be completed before the deadline.
+    Fetch(Vec<Id<Getter>>, Instant, Sender<Reply<Getter, Option<Value>>>),
+
+    /// A fetch of the values produced since a date, to be completed
+    /// before the deadline.
+    FetchSince(Vec<Id<Getter>>, TimeStamp, Instant, Sender<Reply<Getter, Vec<Sample>>>),
+
+    /// A send, to be completed before the deadline.
+    Send(Vec<(Id<Setter>, Value)>, Instant, Sender<Reply<Setter, Acknowledgement>>),
+
+    /// A health check, replying with the time it took on success.
+    Check(Sender<Result<Duration, AdapterError>>),
+}
+
+/// Statistics on the requests of a given type.
+#[derive(Default)]
+struct Counter {
+    requests: u64,
+    failures: u64,
+    batches: u32,
+    total_latency: Duration,
+    max_latency: Duration,
+}
+
+impl Counter {
+    fn report(&self) -> RequestStats {
+        let (mean, max) = if self.batches == 0 {
+            (None, None)
+        } else {
+            (Some(ValDuration::new(self.total_latency / self.batches)), Some(ValDuration::new(self.max_latency)))
+        };
+        RequestStats {
+            requests: self.requests,
+            failures: self.failures,
+            mean_latency: mean,
+            max_latency: max,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    fetches: Counter,
+    sends: Counter,
+    last_error: Option<TimedError>,
+}
+
+impl Stats {
+    fn failed(&mut self, error: Error) {
+        self.last_error = Some(TimedError {
+            timestamp: TimeStamp::now(),
+            error: error,
+        });
+    }
+}
+
+/// Which counter a batch of requests affects.
+#[derive(Clone, Copy)]
+enum Kind {
+    Fetch,
+    Send,
+}
+
+impl Kind {
+    fn counter<'a>(&self, stats: &'a mut Stats) -> &'a mut Counter {
+        match *self {
+            Kind::Fetch => &mut stats.fetches,
+            Kind::Send => &mut stats.sends,
+        }
+    }
+}
+
+type SharedStats = Arc<Mutex<Stats>>;
+
+/// A handle on the workers of an adapter. The workers stop once all
+/// handles have been dropped and the requests in the queue are
+/// complete.
+#[derive(Clone)]
+pub struct Worker {
+    adapter: Id<AdapterId>,
+    queue: SyncSender<Request>,
+    stats: SharedStats,
+    retries: RetryPolicies,
+}
+
+impl Worker {
+    pub fn start(adapter: Arc<Adapter>, policy: &DispatchPolicy, metrics: Arc<Registry>) -> Self {
+        let (queue, requests) = sync_channel(policy.queue_capacity);
+        let requests = Arc::new(Mutex::new(requests));
+        let stats = SharedStats::default();
+        let retries = policy.retries_for(&adapter.id()).clone();
+        for _ in 0..policy.workers_per_adapter.max(1) {
+            let adapter = adapter.clone();
+            let requests = requests.clone();
+            let stats = stats.clone();
+            let metrics = metrics.clone();
+            let retries = retries.clone();
+            thread::spawn(move || run(adapter, requests, stats, metrics, retries));
+        }
+        Worker {
+            adapter: adapter.id(),
+            queue: queue,
+            stats: stats,
+            retries: retries,
+        }
+    }
+
+    pub fn adapter(&self) -> &Id<AdapterId> {
+        &self.adapter
+    }
+
+    /// Queue a fetch.
+    pub fn fetch(&self, getters: Vec<Id<Getter>>) -> Pending<Getter, Option<Value>> {
+        let (tx, rx) = channel();
+        let count = getters.len() as u64;
+        let ids = getters.clone();
+        let timeout = self.retries.fetch.timeout.clone();
+        let deadline = Instant::now() + timeout.as_duration();
+        Pending {
+            adapter: self.adapter.clone(),
+            ids: ids,
+            timeout: timeout,
+            deadline: deadline,
+            reply: self.queue(Request::Fetch(getters, deadline, tx), Kind::Fetch, count).map(|_| rx),
+        }
+    }
+
+    /// Queue a fetch of the values produced since `since`.
+    pub fn fetch_since(&self, getters: Vec<Id<Getter>>, since: TimeStamp) -> Pending<Getter, Vec<Sample>> {
+        let (tx, rx) = channel();
+        let count = getters.len() as u64;
+        let ids = getters.clone();
+        let timeout = self.retries.fetch.timeout.clone();
+        let deadline = Instant::now() + timeout.as_duration();
+        Pending {
+            adapter: self.adapter.clone(),
+            ids: ids,
+            timeout: timeout,
+            deadline: deadline,
+            reply: self.queue(Request::FetchSince(getters, since, deadline, tx), Kind::Fetch, count).map(|_| rx),
+        }
+    }
+
+    /// Queue a send.
+    pub fn send(&self, values: Vec<(Id<Setter>, Value)>) -> Pending<Setter, Acknowledgement> {
+        let (tx, rx) = channel();
+        let count = values.len() as u64;
+        let ids = values.iter().map(|&(ref id, _)| id.clone()).collect();
+        let timeout = self.retries.send.timeout.clone();
+        let deadline = Instant::now() + timeout.as_duration();
+        Pending {
+            adapter: self.adapter.clone(),
+            ids: ids,
+            timeout: timeout,
+            deadline: deadline,
+            reply: self.queue(Request::Send(values, deadline, tx), Kind::Send, count).map(|_| rx),
+        }
+    }
+
+    /// Queue a health check.
+    pub fn check(&self) -> PendingCheck {
+        let (tx, rx) = channel();
+        let reply = match self.queue.try_send(Request::Check(tx)) {
+            Ok(()) => Ok(rx),
+            Err(TrySendError::Full(_)) => Err(Error::Overloaded(self.adapter.clone())),
+            Err(TrySendError::Disconnected(_)) => Err(stopped(&self.adapter))
+        };
+        PendingCheck {
+            adapter: self.adapter.clone(),
+            reply: reply,
+        }
+    }
+
+    /// The statistics on fetches and sends, along with the latest
+    /// error.
+    pub fn stats(&self) -> (RequestStats, RequestStats, Option<TimedError>) {
+        let stats = self.stats.lock().unwrap();
+        (stats.fetches.report(), stats.sends.report(), stats.last_error.clone())
+    }
+
+    fn queue(&self, request: Request, kind: Kind, count: u64) -> Result<(), Error> {
+        let error = match self.queue.try_send(request) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(_)) => Error::Overloaded(self.adapter.clone()),
+            Err(TrySendError::Disconnected(_)) => stopped(&self.adapter)
+        };
+        let mut stats = self.stats.lock().unwrap();
+        {
+            let counter = kind.counter(&mut stats);
+            counter.requests += count;
+            counter.failures += count;
+        }
+        stats.failed(error.clone());
+        Err(error)
+    }
+}
+
+/// The error reported if the workers of an adapter have stopped.
+fn stopped(adapter: &Id<AdapterId>) -> Error {
+    adapter_error(adapter, AdapterError::Permanent("The adapter is not responding".to_owned()))
+}
+
+/// A request in a queue.
+pub struct Pending<T, V> {
+    adapter: Id<AdapterId>,
+    ids: Vec<Id<T>>,
+    timeout: ValDuration,
+    deadline: Instant,
+    reply: Result<Receiver<Reply<T, V>>, Error>,
+}
+
+impl<T, V> Pending<T, V> {
+    /// Wait until the request is complete, or until its timeout.
+    ///
+    /// If the request could not be queued, has timed out, or if the
+    /// adapter has panicked, every channel of the request reports the
+    /// error.
+    pub fn wait(self) -> Vec<(Id<T>, Result<V, Error>)> {
+        let adapter = self.adapter;
+        let now = Instant::now();
+        let remaining = if self.deadline > now { self.deadline - now } else { Duration::from_millis(0) };
+        let error = match self.reply.map(|rx| rx.recv_timeout(remaining)) {
+            Ok(Ok((results, attempts))) => {
+                return results.into_iter()
+                    .map(|(id, result)| (id, result.map_err(|error| failure(&adapter, attempts, error))))
+                    .collect()
+            }
+            Ok(Err(RecvTimeoutError::Timeout)) => Error::TimedOut {
+                adapter: adapter.clone(),
+                timeout: self.timeout,
+            },
+            Ok(Err(RecvTimeoutError::Disconnected)) => stopped(&adapter),
+            Err(error) => error
+        };
+        self.ids.into_iter()
+            .map(|id| (id, Err(error.clone())))
+            .collect()
+    }
+}
+
+/// The error reported for a channel that has failed after `attempts`
+/// attempts.
+fn failure(adapter: &Id<AdapterId>, attempts: u32, error: AdapterError) -> Error {
+    if attempts > 1 && error.is_transient() {
+        Error::RetriesExhausted {
+            adapter: adapter.clone(),
+            attempts: attempts,
+            error: error,
+        }
+    } else {
+        adapter_error(adapter, error)
+    }
+}
+
+/// Call the adapter with `requests`, then again with the requests whose
+/// channel has failed with a transient error, as permitted by `policy`
+/// and `deadline`. Returns the results along with the number of
+/// attempts.
+fn with_retries<R, T, V, I, F>(policy: &RetryPolicy, deadline: Instant, mut requests: Vec<R>, id_of: I, mut call: F) -> Reply<T, V>
+    where R: Clone, I: Fn(&R) -> &Id<T>, F: FnMut(Vec<R>) -> Vec<(Id<T>, Result<V, AdapterError>)>
+{
+    let mut done = vec![];
+    let mut attempts = 0;
+    let mut delay = policy.backoff.as_duration();
+    loop {
+        attempts += 1;
+        let last = attempts > policy.retries || Instant::now() + delay >= deadline;
+        let mut retry = vec![];
+        for (id, result) in call(requests.clone()) {
+            match result {
+                Err(ref error) if !last && error.is_transient() => retry.push(id),
+                result => done.push((id, result))
+            }
+        }
+        if retry.is_empty() {
+            return (done, attempts);
+        }
+        requests.retain(|request| retry.contains(id_of(request)));
+        thread::sleep(delay);
+        delay = delay * 2;
+    }
+}
+
+/// Record a batch of requests that took `latency`.
+fn record<T, V>(stats: &SharedStats, adapter: &Id<AdapterId>, kind: Kind, latency: Duration, results: &[(Id<T>, Result<V, AdapterError>)]) {
+    let mut stats = stats.lock().unwrap();
+    let mut last_error = None;
+    {
+        let counter = kind.counter(&mut stats);
+        counter.requests += results.len() as u64;
+        counter.batches += 1;
+        counter.total_latency += latency;
+        if latency > counter.max_latency {
+            counter.max_latency = latency;
+        }
+        for &(_, ref result) in results {
+            if let Err(ref error) = *result {
+                counter.failures += 1;
+                last_error = Some(error.clone());
+            }
+        }
+    }
+    if let Some(error) = last_error {
+        stats.failed(adapter_error(adapter, error));
+    }
+}
+
+/// A health check in a queue.
+pub struct PendingCheck {
+    adapter: Id<AdapterId>,
+    reply: Result<Receiver<Result<Duration, AdapterError>>, Error>,
+}
+
+impl PendingCheck {
+    /// Wait until the check is complete, or until `deadline`. Returns
+    /// the time taken by the check on success.
+    pub fn wait(self, deadline: Instant) -> Result<ValDuration, Error> {
+        let now = Instant::now();
+        let timeout = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+        match try!(self.reply).recv_timeout(timeout) {
+            Ok(Ok(duration)) => Ok(ValDuration::new(duration)),
+            Ok(Err(error)) => Err(adapter_error(&self.adapter, error)),
+            Err(RecvTimeoutError::Timeout) =>
+                Err(adapter_error(&self.adapter, AdapterError::Transient("The health check has timed out".to_owned()))),
+            Err(RecvTimeoutError::Disconnected) => Err(stopped(&self.adapter))
+        }
+    }
+}
+
+fn run(adapter: Arc<Adapter>, requests: Arc<Mutex<Receiver<Request>>>, stats: SharedStats, metrics: Arc<Registry>, retries: RetryPolicies) {
+    let id = adapter.id();
+    loop {
+        let request = match requests.lock().unwrap().recv() {
+            Ok(request) => request,
+            Err(_) => return
+        };
+        let (kind, count) = match request {
+            Request::Fetch(ref getters, _, _) |
+            Request::FetchSince(ref getters, _, _, _) => (Some(Kind::Fetch), getters.len() as u64),
+            Request::Send(ref values, _, _) => (Some(Kind::Send), values.len() as u64),
+            Request::Check(_) => (None, 0)
+        };
+        // If the adapter panics, the reply is dropped, which the
+        // requester reports, and the worker carries on.
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match request {
+            Request::Fetch(getters, deadline, reply) => {
+                let (results, attempts) = with_retries(&retries.fetch, deadline, getters,
+                                                       |getter| getter,
+                                                       |getters| adapter.fetch_values(getters));
+                let latency = start.elapsed();
+                record(&stats, &id, Kind::Fetch, latency, &results);
+                metrics.observe_batch(&id, Operation::Fetch, latency, results.iter()
+                    .map(|&(ref getter, ref result)| (Target::Getter(getter.clone()), result.is_ok()))
+                    .collect());
+                let _ = reply.send((results, attempts));
+            }
+            Request::FetchSince(getters, since, deadline, reply) => {
+                let (results, attempts) = with_retries(&retries.fetch, deadline, getters,
+                                                       |getter| getter,
+                                                       |getters| adapter.fetch_values_since(getters, since.clone()));
+                let latency = start.elapsed();
+                record(&stats, &id, Kind::Fetch, latency, &results);
+                metrics.observe_batch(&id, Operation::Fetch, latency, results.iter()
+                    .map(|&(ref getter, ref result)| (Target::Getter(getter.clone()), result.is_ok()))
+                    .collect());
+                let _ = reply.send((results, attempts));
+            }
+            Request::Send(values, deadline, reply) => {
+                let (results, attempts) = with_retries(&retries.send, deadline, values,
+                                                       |&(ref setter, _)| setter,
+                                                       |values| adapter.send_values_acknowledged(values));
+                let latency = start.elapsed();
+                record(&stats, &id, Kind::Send, latency, &results);
+                metrics.observe_batch(&id, Operation::Send, latency, results.iter()
+                    .map(|&(ref setter, ref result)| (Target::Setter(setter.clone()), result.is_ok()))
+                    .collect());
+                let _ = reply.send((results, attempts));
+            }
+            Request::Check(reply) => {
+                let _ = reply.send(adapter.health_check().map(|_| start.elapsed()));
+            }
+        }));
+        if result.is_err() {
+            let mut stats = stats.lock().unwrap();
+            if let Some(kind) = kind {
+                let counter = kind.counter(&mut stats);
+                counter.requests += count;
+                counter.failures += count;
+            }
+            stats.failed(stopped(&id));
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//!
+//! Counters and histograms on the traffic of channels and adapters.
+//!
+//! A `Registry` keeps, for each adapter and each channel, the number of
+//! fetches, sends, watch events and failures, along with a histogram of
+//! the time taken by the adapter to respond. Implementations of the API
+//! feed the registry as they dispatch requests to adapters, and expose
+//! a snapshot through `API::get_metrics`, so that operators can find
+//! out which devices are noisy or failing. Snapshots may also be
+//! rendered in the Prometheus text exposition format, with
+//! `to_prometheus`.
+//!
+
+use devices::{ AdapterId, Getter, Setter };
+use util::Id;
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The upper bounds of the buckets of latency histograms, in seconds.
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 5.];
+
+/// The subject of metrics.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    Adapter(Id<AdapterId>),
+    Getter(Id<Getter>),
+    Setter(Id<Setter>),
+}
+
+/// An operation measured by the registry.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+    Fetch,
+    Send,
+
+    /// A value or an error pushed by an adapter for a watched getter.
+    WatchEvent,
+}
+
+/// A histogram of durations.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Histogram {
+    /// The upper bound of each bucket, in seconds. An implicit last
+    /// bucket holds all larger durations.
+    pub bounds: Vec<f64>,
+
+    /// The number of durations in each bucket, including the implicit
+    /// last bucket. Unlike Prometheus, counts are not cumulative.
+    pub counts: Vec<u64>,
+
+    /// The sum of all durations, in seconds.
+    pub sum: f64,
+
+    /// The number of durations.
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            bounds: BUCKETS.to_vec(),
+            counts: vec![0; BUCKETS.len() + 1],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1_000_000_000.;
+        let index = self.bounds.iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[index] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+/// The metrics of an adapter or a channel.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Metrics {
+    pub target: Target,
+
+    /// For a getter, the number of times it has been fetched, including
+    /// polls. For an adapter, the sum over its getters.
+    pub fetches: u64,
+
+    /// For a setter, the number of values sent. For an adapter, the sum
+    /// over its setters.
+    pub sends: u64,
+
+    /// For a getter, the number of values and errors pushed while it
+    /// was watched. For an adapter, the sum over its getters.
+    pub watch_events: u64,
+
+    /// The number of fetches, sends and watch events that have failed.
+    pub failures: u64,
+
+    /// The time taken by the adapter to respond to fetches and sends.
+    /// For an adapter, each batch of requests is counted once.
+    pub latency: Histogram,
+}
+
+impl Metrics {
+    fn new(target: Target) -> Self {
+        Metrics {
+            target: target,
+            fetches: 0,
+            sends: 0,
+            watch_events: 0,
+            failures: 0,
+            latency: Histogram::new(),
+        }
+    }
+
+    fn count(&mut self, operation: Operation, success: bool) {
+        match operation {
+            Operation::Fetch => self.fetches += 1,
+            Operation::Send => self.sends += 1,
+            Operation::WatchEvent => self.watch_events += 1,
+        }
+        if !success {
+            self.failures += 1;
+        }
+    }
+}
+
+/// The metrics of all adapters and channels.
+#[derive(Default)]
+pub struct Registry {
+    metrics: Mutex<HashMap<Target, Metrics>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Record a batch of fetches or sends to `adapter`, which took
+    /// `latency`, along with the channels concerned and whether the
+    /// request succeeded for each of them.
+    pub fn observe_batch(&self, adapter: &Id<AdapterId>, operation: Operation, latency: Duration, results: Vec<(Target, bool)>) {
+        let mut metrics = self.metrics.lock().unwrap();
+        {
+            let adapter = metrics.entry(Target::Adapter(adapter.clone()))
+                .or_insert_with(|| Metrics::new(Target::Adapter(adapter.clone())));
+            for &(_, success) in &results {
+                adapter.count(operation, success);
+            }
+            adapter.latency.observe(latency);
+        }
+        for (target, success) in results {
+            let channel = metrics.entry(target.clone()).or_insert_with(|| Metrics::new(target));
+            channel.count(operation, success);
+            channel.latency.observe(latency);
+        }
+    }
+
+    /// Record a value or error pushed by `adapter` for a watched getter.
+    pub fn observe_watch_event(&self, adapter: &Id<AdapterId>, getter: &Id<Getter>, success: bool) {
+        let mut metrics = self.metrics.lock().unwrap();
+        for target in vec![Target::Adapter(adapter.clone()), Target::Getter(getter.clone())] {
+            metrics.entry(target.clone())
+                .or_insert_with(|| Metrics::new(target))
+                .count(Operation::WatchEvent, success);
+        }
+    }
+
+    /// Forget the metrics of an adapter or a channel, e.g. once it has
+    /// been removed.
+    pub fn forget(&self, target: &Target) {
+        self.metrics.lock().unwrap().remove(target);
+    }
+
+    pub fn snapshot(&self) -> Vec<Metrics> {
+        self.metrics.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Render metrics in the Prometheus text exposition format, e.g.
+///
+/// ```ignore
+/// # TYPE foxbox_fetches_total counter
+/// foxbox_fetches_total{adapter="zwave@foxlink.mozilla.com"} 42
+/// foxbox_fetches_total{getter="zwave@foxlink.mozilla.com/node-3/temperature"} 12
+/// ```
+pub fn to_prometheus(metrics: &[Metrics]) -> String {
+    fn label(target: &Target) -> String {
+        let (key, value) = match *target {
+            Target::Adapter(ref id) => ("adapter", id.as_string()),
+            Target::Getter(ref id) => ("getter", id.as_string()),
+            Target::Setter(ref id) => ("setter", id.as_string()),
+        };
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        format!("{}=\"{}\"", key, escaped)
+    }
+
+    fn counter<F>(out: &mut String, name: &str, metrics: &[Metrics], value: F) where F: Fn(&Metrics) -> u64 {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for metrics in metrics {
+            let _ = writeln!(out, "{}{{{}}} {}", name, label(&metrics.target), value(metrics));
+        }
+    }
+
+    let mut out = String::new();
+    counter(&mut out, "foxbox_fetches_total", metrics, |metrics| metrics.fetches);
+    counter(&mut out, "foxbox_sends_total", metrics, |metrics| metrics.sends);
+    counter(&mut out, "foxbox_watch_events_total", metrics, |metrics| metrics.watch_events);
+    counter(&mut out, "foxbox_failures_total", metrics, |metrics| metrics.failures);
+
+    let name = "foxbox_latency_seconds";
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for metrics in metrics {
+        let label = label(&metrics.target);
+        let histogram = &metrics.latency;
+        let mut cumulated = 0;
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            cumulated += *count;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, label, bound, cumulated);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, label, histogram.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, label, histogram.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, label, histogram.count);
+    }
+    out
+}
@@ -0,0 +1,141 @@
+//!
+//! Migrating persisted data to newer formats.
+//!
+//! The formats of `Value`, selectors and rules evolve, while FoxBoxes
+//! keep data written by older versions, e.g. exported documents (see
+//! module `document`) or the rules of applications. Such data is kept
+//! along with the version of its format. When it is loaded, the JSON
+//! is first brought up to date by `Migrations`, a list of steps, each
+//! of which rewrites the JSON written with version `n` into the JSON
+//! expected by version `n + 1`, then deserialized as usual.
+//!
+//! Steps are applied on a copy of the data, so that a failing step
+//! leaves the data untouched, and may be tried with
+//! `Migrations::dry_run` without applying them.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let migrations = Migrations::new()
+//!     .with_step(1, "Rename `getter` to `source`", |json| {
+//!         let object = try!(json.as_object_mut().ok_or("Not an object".to_owned()));
+//!         if let Some(getter) = object.remove("getter") {
+//!             object.insert("source".to_owned(), getter);
+//!         }
+//!         Ok(())
+//!     });
+//! let applied = try!(migrations.migrate(&mut json, version, 2));
+//! ```
+//!
+
+use serde_json::value::Value as JSON;
+
+/// A single step, migrating data from version `from` to `from + 1`.
+pub struct Step {
+    pub from: u32,
+
+    /// A human-readable description of the step.
+    pub description: String,
+
+    apply: Box<Fn(&mut JSON) -> Result<(), String> + Send + Sync>,
+}
+
+/// An error while migrating data.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MigrationError {
+    /// The data has been written with a version more recent than the
+    /// version requested.
+    Downgrade {
+        from: u32,
+        to: u32,
+    },
+
+    /// There is no step to migrate from this version.
+    NoStep(u32),
+
+    /// A step has failed, e.g. because the data does not have the
+    /// expected shape.
+    Failed {
+        from: u32,
+        description: String,
+        reason: String,
+    },
+}
+
+/// An ordered list of steps.
+pub struct Migrations {
+    steps: Vec<Step>,
+}
+
+impl Migrations {
+    /// Create an empty list of steps.
+    pub fn new() -> Self {
+        Migrations {
+            steps: vec![],
+        }
+    }
+
+    /// Add a step migrating data from version `from` to `from + 1`,
+    /// replacing any step from the same version.
+    pub fn with_step<F>(mut self, from: u32, description: &str, apply: F) -> Self
+        where F: Fn(&mut JSON) -> Result<(), String> + Send + Sync + 'static {
+        self.steps.retain(|step| step.from != from);
+        self.steps.push(Step {
+            from: from,
+            description: description.to_owned(),
+            apply: Box::new(apply),
+        });
+        self.steps.sort_by(|a, b| a.from.cmp(&b.from));
+        self
+    }
+
+    /// The steps migrating data from version `from` to version `to`,
+    /// in the order in which they are applied.
+    pub fn plan(&self, from: u32, to: u32) -> Result<Vec<&Step>, MigrationError> {
+        if from > to {
+            return Err(MigrationError::Downgrade {
+                from: from,
+                to: to,
+            });
+        }
+        let mut plan = vec![];
+        for version in from..to {
+            match self.steps.iter().find(|step| step.from == version) {
+                None => return Err(MigrationError::NoStep(version)),
+                Some(step) => plan.push(step)
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Migrate a copy of `data` from version `from` to version `to`,
+    /// without changing `data`. Returns the migrated copy, along with
+    /// the descriptions of the steps applied.
+    pub fn dry_run(&self, data: &JSON, from: u32, to: u32) -> Result<(JSON, Vec<String>), MigrationError> {
+        let plan = try!(self.plan(from, to));
+        let mut migrated = data.clone();
+        for step in &plan {
+            try!((step.apply)(&mut migrated).map_err(|reason| MigrationError::Failed {
+                from: step.from,
+                description: step.description.clone(),
+                reason: reason,
+            }));
+        }
+        Ok((migrated, plan.iter().map(|step| step.description.clone()).collect()))
+    }
+
+    /// Migrate `data` from version `from` to version `to`. If any step
+    /// fails, `data` is left unchanged. Returns the descriptions of the
+    /// steps applied.
+    pub fn migrate(&self, data: &mut JSON, from: u32, to: u32) -> Result<Vec<String>, MigrationError> {
+        let (migrated, applied) = try!(self.dry_run(data, from, to));
+        *data = migrated;
+        Ok(applied)
+    }
+}
+
+impl Default for Migrations {
+    fn default() -> Self {
+        Migrations::new()
+    }
+}
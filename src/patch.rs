@@ -0,0 +1,430 @@
+//!
+//! Patches of JSON values, as per RFC 6902.
+//!
+//! A channel of type `Json` may describe a large document, e.g. the
+//! state of a media player or the list of contacts of a phone, of which
+//! only a small part changes at a time. Rather than producing the whole
+//! document at each change, an adapter may produce a
+//! `Value::JsonPatch`, computed with `JsonPatch::diff`. The manager
+//! applies the patch to the latest document of the channel, which it
+//! keeps in full, and delivers the patch to watches, which hold the
+//! document they have received previously.
+//!
+//! A patch is represented as in RFC 6902, i.e. as an array of
+//! operations such as `{"op": "replace", "path": "/volume", "value": 5}`.
+//! Operations `add`, `remove`, `replace`, `move`, `copy` and `test` are
+//! supported.
+//!
+
+use serde_json::value::Value as JSON;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A patch of a JSON value, as an array of RFC 6902 operations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonPatch(pub JSON);
+
+impl PartialOrd for JsonPatch {
+    /// Two patches are never comparable to each other.
+    fn partial_cmp(&self, _: &Self) -> Option<Ordering> {
+        None
+    }
+}
+
+/// An error while applying a patch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PatchError {
+    /// The patch is not an array of valid operations. Payload is a
+    /// description of the problem.
+    InvalidOperation(String),
+
+    /// A path of the patch does not designate a location of the
+    /// document. Payload is the path.
+    NoSuchPath(String),
+
+    /// A `test` operation has failed. Payload is the path tested.
+    TestFailed(String),
+}
+
+/// Parse a JSON pointer, as per RFC 6901, into its reference tokens.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>, PatchError> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    if !pointer.starts_with('/') {
+        return Err(PatchError::NoSuchPath(pointer.to_owned()));
+    }
+    Ok(pointer[1..].split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+/// Escape a key of an object as a reference token of a JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace("~", "~0").replace("/", "~1")
+}
+
+/// Parse a reference token as an index in an array of `len` elements.
+/// Token `-` designates the position after the last element.
+fn parse_index(token: &str, len: usize) -> Option<usize> {
+    if token == "-" {
+        return Some(len);
+    }
+    // RFC 6901 only allows decimal digits, without leading zeroes, so
+    // e.g. `+1` does not designate an index.
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    if !token.bytes().all(|byte| byte >= b'0' && byte <= b'9') {
+        return None;
+    }
+    token.parse().ok()
+}
+
+fn get<'a>(json: &'a JSON, tokens: &[String]) -> Option<&'a JSON> {
+    let mut current = json;
+    for token in tokens {
+        let parent = current;
+        current = match *parent {
+            JSON::Object(ref map) => match map.get(token) {
+                None => return None,
+                Some(child) => child
+            },
+            JSON::Array(ref vec) => match parse_index(token, vec.len()).and_then(|index| vec.get(index)) {
+                None => return None,
+                Some(child) => child
+            },
+            _ => return None
+        };
+    }
+    Some(current)
+}
+
+fn get_mut<'a>(json: &'a mut JSON, tokens: &[String]) -> Option<&'a mut JSON> {
+    let mut current = json;
+    for token in tokens {
+        let parent = current;
+        current = match *parent {
+            JSON::Object(ref mut map) => match map.get_mut(token) {
+                None => return None,
+                Some(child) => child
+            },
+            JSON::Array(ref mut vec) => {
+                let len = vec.len();
+                match parse_index(token, len).and_then(move |index| vec.get_mut(index)) {
+                    None => return None,
+                    Some(child) => child
+                }
+            }
+            _ => return None
+        };
+    }
+    Some(current)
+}
+
+/// The location designated by `path`, as its parent and its last
+/// reference token, or `None` for the root of the document.
+fn split(path: &str) -> Result<Option<(Vec<String>, String)>, PatchError> {
+    let mut tokens = try!(parse_pointer(path));
+    Ok(tokens.pop().map(|last| (tokens, last)))
+}
+
+fn add(json: &mut JSON, path: &str, value: JSON) -> Result<(), PatchError> {
+    let (parent, last) = match try!(split(path)) {
+        None => {
+            *json = value;
+            return Ok(());
+        }
+        Some(split) => split
+    };
+    match get_mut(json, &parent) {
+        Some(&mut JSON::Object(ref mut map)) => {
+            map.insert(last, value);
+            Ok(())
+        }
+        Some(&mut JSON::Array(ref mut vec)) => match parse_index(&last, vec.len()) {
+            Some(index) if index <= vec.len() => {
+                vec.insert(index, value);
+                Ok(())
+            }
+            _ => Err(PatchError::NoSuchPath(path.to_owned()))
+        },
+        _ => Err(PatchError::NoSuchPath(path.to_owned()))
+    }
+}
+
+fn remove(json: &mut JSON, path: &str) -> Result<JSON, PatchError> {
+    let (parent, last) = match try!(split(path)) {
+        None => return Err(PatchError::InvalidOperation("Cannot remove the whole document".to_owned())),
+        Some(split) => split
+    };
+    let removed = match get_mut(json, &parent) {
+        Some(&mut JSON::Object(ref mut map)) => map.remove(&last),
+        Some(&mut JSON::Array(ref mut vec)) => match parse_index(&last, vec.len()) {
+            Some(index) if index < vec.len() => Some(vec.remove(index)),
+            _ => None
+        },
+        _ => None
+    };
+    removed.ok_or_else(|| PatchError::NoSuchPath(path.to_owned()))
+}
+
+/// A string member of an operation.
+fn member<'a>(operation: &'a JSON, key: &str) -> Result<&'a str, PatchError> {
+    operation.find(key)
+        .and_then(JSON::as_string)
+        .ok_or_else(|| PatchError::InvalidOperation(format!("Missing string member {}", key)))
+}
+
+/// The value of an operation.
+fn value_of(operation: &JSON) -> Result<JSON, PatchError> {
+    operation.find("value")
+        .cloned()
+        .ok_or_else(|| PatchError::InvalidOperation("Missing member value".to_owned()))
+}
+
+fn apply_operation(json: &mut JSON, operation: &JSON) -> Result<(), PatchError> {
+    let path = try!(member(operation, "path"));
+    match try!(member(operation, "op")) {
+        "add" => add(json, path, try!(value_of(operation))),
+        "remove" => remove(json, path).map(|_| ()),
+        "replace" => {
+            let value = try!(value_of(operation));
+            if try!(split(path)).is_some() {
+                try!(remove(json, path));
+            }
+            add(json, path, value)
+        }
+        "move" => {
+            let value = try!(remove(json, try!(member(operation, "from"))));
+            add(json, path, value)
+        }
+        "copy" => {
+            let from = try!(member(operation, "from"));
+            let value = match get(json, &try!(parse_pointer(from))) {
+                None => return Err(PatchError::NoSuchPath(from.to_owned())),
+                Some(value) => value.clone()
+            };
+            add(json, path, value)
+        }
+        "test" => {
+            let expected = try!(value_of(operation));
+            match get(json, &try!(parse_pointer(path))) {
+                Some(value) if *value == expected => Ok(()),
+                _ => Err(PatchError::TestFailed(path.to_owned()))
+            }
+        }
+        op => Err(PatchError::InvalidOperation(format!("Unknown operation {}", op)))
+    }
+}
+
+/// An operation, as a JSON object.
+fn operation(op: &str, path: &str, value: Option<&JSON>) -> JSON {
+    let mut map = BTreeMap::new();
+    map.insert("op".to_owned(), JSON::String(op.to_owned()));
+    map.insert("path".to_owned(), JSON::String(path.to_owned()));
+    if let Some(value) = value {
+        map.insert("value".to_owned(), value.clone());
+    }
+    JSON::Object(map)
+}
+
+/// Append to `operations` the operations turning `before` into `after`,
+/// both at location `path`.
+fn diff_at(path: &str, before: &JSON, after: &JSON, operations: &mut Vec<JSON>) {
+    if before == after {
+        return;
+    }
+    match (before, after) {
+        (&JSON::Object(ref before), &JSON::Object(ref after)) => {
+            for key in before.keys() {
+                if !after.contains_key(key) {
+                    operations.push(operation("remove", &format!("{}/{}", path, escape(key)), None));
+                }
+            }
+            for (key, value) in after {
+                let child = format!("{}/{}", path, escape(key));
+                match before.get(key) {
+                    None => operations.push(operation("add", &child, Some(value))),
+                    Some(previous) => diff_at(&child, previous, value, operations)
+                }
+            }
+        }
+        _ => operations.push(operation("replace", path, Some(after)))
+    }
+}
+
+impl JsonPatch {
+    /// A patch turning `before` into `after`.
+    ///
+    /// Members of objects are compared recursively. Other values,
+    /// including arrays, are replaced as a whole whenever they differ.
+    pub fn diff(before: &JSON, after: &JSON) -> Self {
+        let mut operations = vec![];
+        diff_at("", before, after, &mut operations);
+        JsonPatch(JSON::Array(operations))
+    }
+
+    /// Apply the patch to `document`. Operations are applied in order,
+    /// and the patch fails as a whole if any of them fails.
+    pub fn apply(&self, document: &JSON) -> Result<JSON, PatchError> {
+        let operations = match self.0 {
+            JSON::Array(ref operations) => operations,
+            _ => return Err(PatchError::InvalidOperation("A patch must be an array".to_owned()))
+        };
+        let mut result = document.clone();
+        for operation in operations {
+            try!(apply_operation(&mut result, operation));
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ JsonPatch, PatchError, parse_index };
+
+    use serde_json::value::Value as JSON;
+
+    use std::collections::BTreeMap;
+
+    fn string(value: &str) -> JSON {
+        JSON::String(value.to_owned())
+    }
+
+    fn object(members: Vec<(&str, JSON)>) -> JSON {
+        let mut map = BTreeMap::new();
+        for (key, value) in members {
+            map.insert(key.to_owned(), value);
+        }
+        JSON::Object(map)
+    }
+
+    fn patch(operations: Vec<Vec<(&str, JSON)>>) -> JsonPatch {
+        JsonPatch(JSON::Array(operations.into_iter().map(object).collect()))
+    }
+
+    /// Check that `patch` turns `document` into `expected`, and that
+    /// the patch computed by `JsonPatch::diff` does too.
+    fn check(document: JSON, patch: JsonPatch, expected: JSON) {
+        assert_eq!(patch.apply(&document), Ok(expected.clone()));
+        assert_eq!(JsonPatch::diff(&document, &expected).apply(&document), Ok(expected));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        assert_eq!(parse_index("0", 3), Some(0));
+        assert_eq!(parse_index("10", 3), Some(10));
+        assert_eq!(parse_index("-", 3), Some(3));
+        for token in &["", "01", "+1", "-1", " 1", "1 ", "1a", "0x1", "\u{0661}"] {
+            assert_eq!(parse_index(token, 3), None, "{}", token);
+        }
+    }
+
+    // The examples of RFC 6902, appendix A. Example A.13, a patch with
+    // duplicate members, cannot be represented once parsed.
+
+    #[test]
+    fn test_rfc6902_add() {
+        // A.1. Adding an Object Member
+        check(object(vec![("foo", string("bar"))]),
+              patch(vec![vec![("op", string("add")), ("path", string("/baz")), ("value", string("qux"))]]),
+              object(vec![("baz", string("qux")), ("foo", string("bar"))]));
+
+        // A.2. Adding an Array Element
+        check(object(vec![("foo", JSON::Array(vec![string("bar"), string("baz")]))]),
+              patch(vec![vec![("op", string("add")), ("path", string("/foo/1")), ("value", string("qux"))]]),
+              object(vec![("foo", JSON::Array(vec![string("bar"), string("qux"), string("baz")]))]));
+
+        // A.10. Adding a Nested Member Object
+        check(object(vec![("foo", string("bar"))]),
+              patch(vec![vec![("op", string("add")), ("path", string("/child")),
+                              ("value", object(vec![("grandchild", object(vec![]))]))]]),
+              object(vec![("foo", string("bar")), ("child", object(vec![("grandchild", object(vec![]))]))]));
+
+        // A.11. Ignoring Unrecognized Elements
+        check(object(vec![("foo", string("bar"))]),
+              patch(vec![vec![("op", string("add")), ("path", string("/baz")), ("value", string("qux")),
+                              ("xyz", JSON::U64(123))]]),
+              object(vec![("foo", string("bar")), ("baz", string("qux"))]));
+
+        // A.12. Adding to a Nonexistent Target
+        assert_eq!(patch(vec![vec![("op", string("add")), ("path", string("/baz/bat")), ("value", string("qux"))]])
+                       .apply(&object(vec![("foo", string("bar"))])),
+                   Err(PatchError::NoSuchPath("/baz/bat".to_owned())));
+
+        // A.16. Adding an Array Value
+        check(object(vec![("foo", JSON::Array(vec![string("bar")]))]),
+              patch(vec![vec![("op", string("add")), ("path", string("/foo/-")),
+                              ("value", JSON::Array(vec![string("abc"), string("def")]))]]),
+              object(vec![("foo", JSON::Array(vec![string("bar"), JSON::Array(vec![string("abc"), string("def")])]))]));
+    }
+
+    #[test]
+    fn test_rfc6902_remove_and_replace() {
+        // A.3. Removing an Object Member
+        check(object(vec![("baz", string("qux")), ("foo", string("bar"))]),
+              patch(vec![vec![("op", string("remove")), ("path", string("/baz"))]]),
+              object(vec![("foo", string("bar"))]));
+
+        // A.4. Removing an Array Element
+        check(object(vec![("foo", JSON::Array(vec![string("bar"), string("qux"), string("baz")]))]),
+              patch(vec![vec![("op", string("remove")), ("path", string("/foo/1"))]]),
+              object(vec![("foo", JSON::Array(vec![string("bar"), string("baz")]))]));
+
+        // A.5. Replacing a Value
+        check(object(vec![("baz", string("qux")), ("foo", string("bar"))]),
+              patch(vec![vec![("op", string("replace")), ("path", string("/baz")), ("value", string("boo"))]]),
+              object(vec![("baz", string("boo")), ("foo", string("bar"))]));
+    }
+
+    #[test]
+    fn test_rfc6902_move() {
+        // A.6. Moving a Value
+        check(object(vec![("foo", object(vec![("bar", string("baz")), ("waldo", string("fred"))])),
+                          ("qux", object(vec![("corge", string("grault"))]))]),
+              patch(vec![vec![("op", string("move")), ("from", string("/foo/waldo")), ("path", string("/qux/thud"))]]),
+              object(vec![("foo", object(vec![("bar", string("baz"))])),
+                          ("qux", object(vec![("corge", string("grault")), ("thud", string("fred"))]))]));
+
+        // A.7. Moving an Array Element
+        check(object(vec![("foo", JSON::Array(vec![string("all"), string("grass"), string("cows"), string("eat")]))]),
+              patch(vec![vec![("op", string("move")), ("from", string("/foo/1")), ("path", string("/foo/3"))]]),
+              object(vec![("foo", JSON::Array(vec![string("all"), string("cows"), string("eat"), string("grass")]))]));
+    }
+
+    #[test]
+    fn test_rfc6902_test() {
+        // A.8. Testing a Value: Success
+        let document = object(vec![("baz", string("qux")),
+                                   ("foo", JSON::Array(vec![string("a"), JSON::U64(2), string("c")]))]);
+        check(document.clone(),
+              patch(vec![vec![("op", string("test")), ("path", string("/baz")), ("value", string("qux"))],
+                         vec![("op", string("test")), ("path", string("/foo/1")), ("value", JSON::U64(2))]]),
+              document);
+
+        // A.9. Testing a Value: Error
+        assert_eq!(patch(vec![vec![("op", string("test")), ("path", string("/baz")), ("value", string("bar"))]])
+                       .apply(&object(vec![("baz", string("qux"))])),
+                   Err(PatchError::TestFailed("/baz".to_owned())));
+
+        // A.14. ~ Escape Ordering
+        let document = object(vec![("/", JSON::U64(9)), ("~1", JSON::U64(10))]);
+        check(document.clone(),
+              patch(vec![vec![("op", string("test")), ("path", string("/~01")), ("value", JSON::U64(10))]]),
+              document.clone());
+
+        // A.15. Comparing Strings and Numbers
+        assert_eq!(patch(vec![vec![("op", string("test")), ("path", string("/~01")), ("value", string("10"))]])
+                       .apply(&document),
+                   Err(PatchError::TestFailed("/~01".to_owned())));
+    }
+
+    #[test]
+    fn test_diff_escapes_keys() {
+        let before = object(vec![("a/b", JSON::U64(1)), ("c~d", JSON::U64(2))]);
+        let after = object(vec![("a/b", JSON::U64(3)), ("e", object(vec![]))]);
+        assert_eq!(JsonPatch::diff(&before, &after).apply(&before), Ok(after));
+    }
+}
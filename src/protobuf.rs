@@ -0,0 +1,1371 @@
+//!
+//! Protocol Buffers encoding of the core types.
+//!
+//! JSON is convenient for web clients, but wasteful for mobile clients
+//! and constrained bridges. This module encodes values, nodes,
+//! selectors and watch events following the schema of
+//! `proto/taxonomy.proto`, so that clients can use any implementation
+//! of Protocol Buffers to talk to the FoxBox.
+//!
+//! Unknown fields are skipped while decoding, so that peers following
+//! an older version of the schema can still decode messages produced
+//! with a newer one. See the schema for the evolution rules.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use foxbox_taxonomy::protobuf::Message;
+//!
+//! let bytes = event.to_bytes();
+//! let decoded = WatchEvent::from_bytes(&bytes).unwrap();
+//! ```
+//!
+//! This module is only available with feature `protobuf`.
+//!
+
+use adapter::AdapterEvent;
+use api::{Error, Labelled, WatchEvent};
+use compression::{ Algorithm, Compressed };
+use devices::*;
+use patch::JsonPatch;
+use selector::*;
+use values::*;
+use util::{Exactly, Id, TagId};
+
+use serde_json;
+
+use std::mem;
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An error while decoding a message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The data ends in the middle of a field.
+    Truncated,
+
+    /// A varint is longer than 10 bytes.
+    InvalidVarint,
+
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+
+    /// A field has an unexpected wire type or value, or a required
+    /// field is missing. Payload is the name of the message.
+    Invalid(&'static str),
+}
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const BYTES: u8 = 2;
+const FIXED32: u8 = 5;
+
+/// A buffer into which messages are encoded.
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer {
+            buf: vec![]
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    pub fn write_uint64(&mut self, field: u32, value: u64) {
+        self.key(field, VARINT);
+        self.varint(value);
+    }
+
+    /// Write a `sint64`, i.e. a zigzag-encoded integer.
+    pub fn write_sint64(&mut self, field: u32, value: i64) {
+        self.write_uint64(field, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn write_bool(&mut self, field: u32, value: bool) {
+        self.write_uint64(field, value as u64);
+    }
+
+    pub fn write_double(&mut self, field: u32, value: f64) {
+        self.key(field, FIXED64);
+        let bits: u64 = unsafe { mem::transmute(value) };
+        for i in 0..8 {
+            self.buf.push((bits >> (8 * i)) as u8);
+        }
+    }
+
+    pub fn write_bytes(&mut self, field: u32, value: &[u8]) {
+        self.key(field, BYTES);
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    pub fn write_string(&mut self, field: u32, value: &str) {
+        self.write_bytes(field, value.as_bytes());
+    }
+
+    pub fn write_message<M>(&mut self, field: u32, value: &M) where M: Message {
+        self.write_nested(field, |writer| value.encode(writer));
+    }
+
+    /// Write a message that does not have a Rust counterpart.
+    pub fn write_nested<F>(&mut self, field: u32, encode: F) where F: FnOnce(&mut Writer) {
+        let mut nested = Writer::new();
+        encode(&mut nested);
+        self.write_bytes(field, &nested.buf);
+    }
+}
+
+/// The payload of a field, as found on the wire.
+#[derive(Debug, Clone, Copy)]
+pub enum Payload<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Payload<'a> {
+    pub fn as_uint64(self, message: &'static str) -> Result<u64, DecodeError> {
+        match self {
+            Payload::Varint(value) => Ok(value),
+            _ => Err(DecodeError::Invalid(message))
+        }
+    }
+
+    pub fn as_sint64(self, message: &'static str) -> Result<i64, DecodeError> {
+        let value = try!(self.as_uint64(message));
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+
+    pub fn as_bool(self, message: &'static str) -> Result<bool, DecodeError> {
+        Ok(try!(self.as_uint64(message)) != 0)
+    }
+
+    pub fn as_double(self, message: &'static str) -> Result<f64, DecodeError> {
+        match self {
+            Payload::Fixed64(bits) => Ok(unsafe { mem::transmute(bits) }),
+            _ => Err(DecodeError::Invalid(message))
+        }
+    }
+
+    pub fn as_bytes(self, message: &'static str) -> Result<&'a [u8], DecodeError> {
+        match self {
+            Payload::Bytes(bytes) => Ok(bytes),
+            _ => Err(DecodeError::Invalid(message))
+        }
+    }
+
+    pub fn as_str(self, message: &'static str) -> Result<&'a str, DecodeError> {
+        str::from_utf8(try!(self.as_bytes(message))).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    pub fn as_string(self, message: &'static str) -> Result<String, DecodeError> {
+        self.as_str(message).map(|string| string.to_owned())
+    }
+
+    pub fn as_id<T>(self, message: &'static str) -> Result<Id<T>, DecodeError> {
+        self.as_string(message).map(Id::new)
+    }
+
+    pub fn as_tag(self, message: &'static str) -> Result<TagId, DecodeError> {
+        TagId::new(try!(self.as_str(message))).map_err(|_| DecodeError::Invalid(message))
+    }
+
+    pub fn as_message<M>(self, message: &'static str) -> Result<M, DecodeError> where M: Message {
+        M::decode(&mut Reader::new(try!(self.as_bytes(message))))
+    }
+
+    /// Read a message that does not have a Rust counterpart.
+    pub fn as_nested(self, message: &'static str) -> Result<Reader<'a>, DecodeError> {
+        self.as_bytes(message).map(Reader::new)
+    }
+}
+
+/// A buffer from which messages are decoded, one field at a time.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader {
+            buf: buf,
+            pos: 0,
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for i in 0..10 {
+            let byte = match self.buf.get(self.pos) {
+                None => return Err(DecodeError::Truncated),
+                Some(&byte) => byte
+            };
+            self.pos += 1;
+            value |= ((byte & 0x7F) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidVarint)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.buf.len() - self.pos < len {
+            return Err(DecodeError::Truncated);
+        }
+        let bytes = &self.buf[self.pos .. self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn fixed(&mut self, len: usize) -> Result<u64, DecodeError> {
+        let bytes = try!(self.take(len));
+        Ok(bytes.iter().rev().fold(0, |acc, &byte| (acc << 8) | byte as u64))
+    }
+
+    /// Read the next field, or `None` at the end of the message.
+    pub fn next_field(&mut self) -> Result<Option<(u32, Payload<'a>)>, DecodeError> {
+        if self.pos == self.buf.len() {
+            return Ok(None);
+        }
+        let key = try!(self.varint());
+        let field = (key >> 3) as u32;
+        let payload = match (key & 0x7) as u8 {
+            VARINT => Payload::Varint(try!(self.varint())),
+            FIXED64 => Payload::Fixed64(try!(self.fixed(8))),
+            BYTES => {
+                let len = try!(self.varint()) as usize;
+                Payload::Bytes(try!(self.take(len)))
+            }
+            FIXED32 => Payload::Fixed32(try!(self.fixed(4)) as u32),
+            _ => return Err(DecodeError::Invalid("wire type"))
+        };
+        Ok(Some((field, payload)))
+    }
+}
+
+/// A type with a counterpart in `proto/taxonomy.proto`.
+pub trait Message: Sized {
+    fn encode(&self, writer: &mut Writer);
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError>;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer);
+        writer.into_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(&mut Reader::new(bytes))
+    }
+}
+
+impl Message for ValDuration {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_uint64(1, self.as_ms());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut ms = 0;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            if field == 1 {
+                ms = try!(payload.as_uint64("Duration"));
+            }
+        }
+        Ok(ValDuration::new(Duration::new(ms / 1000, (ms % 1000) as u32 * 1_000_000)))
+    }
+}
+
+impl Message for TimeStamp {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_sint64(1, self.as_ms());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut ms = 0;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            if field == 1 {
+                ms = try!(payload.as_sint64("TimeStamp"));
+            }
+        }
+        TimeStamp::from_ms(ms).ok_or(DecodeError::Invalid("TimeStamp"))
+    }
+}
+
+impl Message for Temperature {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            Temperature::F(f) => writer.write_double(1, f),
+            Temperature::C(c) => writer.write_double(2, c),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut result = None;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result = Some(Temperature::F(try!(payload.as_double("Temperature")))),
+                2 => result = Some(Temperature::C(try!(payload.as_double("Temperature")))),
+                _ => {}
+            }
+        }
+        result.ok_or(DecodeError::Invalid("Temperature"))
+    }
+}
+
+impl Message for Color {
+    fn encode(&self, writer: &mut Writer) {
+        let Color::RGBA(r, g, b, a, extra) = *self;
+        writer.write_double(1, r);
+        writer.write_double(2, g);
+        writer.write_double(3, b);
+        writer.write_double(4, a);
+        writer.write_double(5, extra);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut components = [0.; 5];
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            if field >= 1 && field <= 5 {
+                components[field as usize - 1] = try!(payload.as_double("Color"));
+            }
+        }
+        Ok(Color::RGBA(components[0], components[1], components[2], components[3], components[4]))
+    }
+}
+
+impl Message for ExtNumeric {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_double(1, self.value);
+        writer.write_string(2, &self.vendor);
+        writer.write_string(3, &self.adapter);
+        writer.write_string(4, &self.kind);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut result = ExtNumeric {
+            value: 0.,
+            vendor: String::new(),
+            adapter: String::new(),
+            kind: String::new(),
+        };
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result.value = try!(payload.as_double("ExtNumeric")),
+                2 => result.vendor = try!(payload.as_string("ExtNumeric")),
+                3 => result.adapter = try!(payload.as_string("ExtNumeric")),
+                4 => result.kind = try!(payload.as_string("ExtNumeric")),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for Value {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            Value::Unit => writer.write_bytes(1, &[]),
+            Value::Bool(b) => writer.write_bool(2, b),
+            Value::Duration(ref duration) => writer.write_message(3, duration),
+            Value::TimeStamp(ref timestamp) => writer.write_message(4, timestamp),
+            Value::Temperature(ref temperature) => writer.write_message(5, temperature),
+            Value::Color(ref color) => writer.write_message(6, color),
+            Value::String(ref string) => writer.write_string(7, string),
+            Value::ExtNumeric(ref numeric) => writer.write_message(8, numeric),
+            Value::Json(ref json) => {
+                // Serializing a JSON value cannot fail.
+                writer.write_string(9, &serde_json::to_string(&json.0).unwrap())
+            }
+            Value::Binary { ref data, ref mimetype } => writer.write_nested(10, |writer| {
+                writer.write_bytes(1, data);
+                writer.write_string(2, mimetype);
+            }),
+            Value::JsonPatch(ref patch) => writer.write_string(11, &serde_json::to_string(&patch.0).unwrap()),
+            Value::Count(count) => writer.write_uint64(12, count as u64),
+            Value::Compressed(ref compressed) => writer.write_nested(13, |writer| {
+                writer.write_string(1, match compressed.algorithm {
+                    Algorithm::Deflate => "Deflate",
+                    Algorithm::Gzip => "Gzip",
+                });
+                writer.write_uint64(2, compressed.original_size);
+                if let Some(ref mimetype) = compressed.mimetype {
+                    writer.write_string(3, mimetype);
+                }
+                writer.write_bytes(4, &compressed.data);
+            }),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "Value";
+        let mut result = None;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            let value = match field {
+                1 => Value::Unit,
+                2 => Value::Bool(try!(payload.as_bool(NAME))),
+                3 => Value::Duration(try!(payload.as_message(NAME))),
+                4 => Value::TimeStamp(try!(payload.as_message(NAME))),
+                5 => Value::Temperature(try!(payload.as_message(NAME))),
+                6 => Value::Color(try!(payload.as_message(NAME))),
+                7 => Value::String(Arc::new(try!(payload.as_string(NAME)))),
+                8 => Value::ExtNumeric(try!(payload.as_message(NAME))),
+                9 => match serde_json::from_str(try!(payload.as_str(NAME))) {
+                    Ok(json) => Value::Json(Arc::new(Json(json))),
+                    Err(_) => return Err(DecodeError::Invalid(NAME))
+                },
+                10 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let mut data = vec![];
+                    let mut mimetype = String::new();
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => data = try!(payload.as_bytes(NAME)).to_vec(),
+                            2 => mimetype = try!(payload.as_string(NAME)),
+                            _ => {}
+                        }
+                    }
+                    Value::Binary {
+                        data: Bytes(Arc::new(data)),
+                        mimetype: mimetype
+                    }
+                }
+                11 => match serde_json::from_str(try!(payload.as_str(NAME))) {
+                    Ok(patch) => Value::JsonPatch(Arc::new(JsonPatch(patch))),
+                    Err(_) => return Err(DecodeError::Invalid(NAME))
+                },
+                12 => match try!(payload.as_uint64(NAME)) {
+                    count if count <= u32::max_value() as u64 => Value::Count(count as u32),
+                    _ => return Err(DecodeError::Invalid(NAME))
+                },
+                13 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut algorithm, mut original_size, mut mimetype, mut data) = (None, 0, None, vec![]);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => algorithm = match try!(payload.as_str(NAME)) {
+                                "Deflate" => Some(Algorithm::Deflate),
+                                "Gzip" => Some(Algorithm::Gzip),
+                                _ => return Err(DecodeError::Invalid(NAME))
+                            },
+                            2 => original_size = try!(payload.as_uint64(NAME)),
+                            3 => mimetype = Some(try!(payload.as_string(NAME))),
+                            4 => data = try!(payload.as_bytes(NAME)).to_vec(),
+                            _ => {}
+                        }
+                    }
+                    match algorithm {
+                        None => return Err(DecodeError::Invalid(NAME)),
+                        Some(algorithm) => Value::Compressed(Arc::new(Compressed {
+                            algorithm: algorithm,
+                            original_size: original_size,
+                            mimetype: mimetype,
+                            data: Bytes(Arc::new(data)),
+                        }))
+                    }
+                }
+                _ => continue
+            };
+            result = Some(value);
+        }
+        result.ok_or(DecodeError::Invalid(NAME))
+    }
+}
+
+fn type_name(typ: &Type) -> &'static str {
+    match *typ {
+        Type::Unit => "Unit",
+        Type::Bool => "Bool",
+        Type::Duration => "Duration",
+        Type::TimeStamp => "TimeStamp",
+        Type::Temperature => "Temperature",
+        Type::String => "String",
+        Type::Count => "Count",
+        Type::Color => "Color",
+        Type::Json => "Json",
+        Type::Binary => "Binary",
+        Type::ExtNumeric => "ExtNumeric",
+    }
+}
+
+fn type_from_name(name: &str) -> Option<Type> {
+    let typ = match name {
+        "Unit" => Type::Unit,
+        "Bool" => Type::Bool,
+        "Duration" => Type::Duration,
+        "TimeStamp" => Type::TimeStamp,
+        "Temperature" => Type::Temperature,
+        "String" => Type::String,
+        "Count" => Type::Count,
+        "Color" => Type::Color,
+        "Json" => Type::Json,
+        "Binary" => Type::Binary,
+        "ExtNumeric" => Type::ExtNumeric,
+        _ => return None
+    };
+    Some(typ)
+}
+
+fn standard_kind_name(kind: &ChannelKind) -> Option<&'static str> {
+    use devices::ChannelKind::*;
+    let name = match *kind {
+        Ready => "Ready",
+        OnOff => "OnOff",
+        OpenClosed => "OpenClosed",
+        CurrentTime => "CurrentTime",
+        CurrentTimeOfDay => "CurrentTimeOfDay",
+        RemainingTime => "RemainingTime",
+        Thermostat => "Thermostat",
+        ActualTemperature => "ActualTemperature",
+        ButtonPressed => "ButtonPressed",
+        DoorbellRang => "DoorbellRang",
+        Extension { .. } => return None
+    };
+    Some(name)
+}
+
+fn standard_kind_from_name(name: &str) -> Option<ChannelKind> {
+    ChannelKind::standard_kinds().into_iter()
+        .map(|description| description.kind)
+        .find(|kind| standard_kind_name(kind) == Some(name))
+}
+
+impl Message for ChannelKind {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            ChannelKind::Extension { ref vendor, ref adapter, ref kind, ref typ } => writer.write_nested(2, |writer| {
+                writer.write_string(1, vendor);
+                writer.write_string(2, adapter);
+                writer.write_string(3, kind);
+                writer.write_string(4, type_name(typ));
+            }),
+            ref kind => writer.write_string(1, standard_kind_name(kind).unwrap())
+        }
+    }
+
+    /// Standard kinds that are unknown to this version of the crate
+    /// are rejected as `DecodeError::Invalid`.
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "ChannelKind";
+        let mut result = None;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result = Some(try!(standard_kind_from_name(try!(payload.as_str(NAME)))
+                    .ok_or(DecodeError::Invalid(NAME)))),
+                2 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut vendor, mut adapter, mut kind, mut typ) = (String::new(), String::new(), String::new(), None);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => vendor = try!(payload.as_string(NAME)),
+                            2 => adapter = try!(payload.as_string(NAME)),
+                            3 => kind = try!(payload.as_string(NAME)),
+                            4 => typ = type_from_name(try!(payload.as_str(NAME))),
+                            _ => {}
+                        }
+                    }
+                    result = Some(ChannelKind::Extension {
+                        vendor: vendor,
+                        adapter: adapter,
+                        kind: kind,
+                        typ: try!(typ.ok_or(DecodeError::Invalid(NAME))),
+                    });
+                }
+                _ => {}
+            }
+        }
+        result.ok_or(DecodeError::Invalid(NAME))
+    }
+}
+
+impl Message for Channel<Getter> {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_string(1, self.id.as_string());
+        writer.write_string(2, self.node.as_string());
+        for tag in &self.tags {
+            writer.write_string(3, tag.as_str());
+        }
+        if let Some(ref label) = self.label {
+            writer.write_string(4, label);
+        }
+        if let Some(ref last_seen) = self.last_seen {
+            writer.write_message(5, last_seen);
+        }
+        writer.write_message(6, &self.mechanism.kind);
+        if let Some(ref poll) = self.mechanism.poll {
+            writer.write_message(7, poll);
+        }
+        if let Some(ref trigger) = self.mechanism.trigger {
+            writer.write_message(8, trigger);
+        }
+        writer.write_bool(9, self.mechanism.watch);
+        if let Some(ref updated) = self.mechanism.updated {
+            writer.write_message(10, updated);
+        }
+        writer.write_bool(11, self.mechanism.is_event());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "GetterChannel";
+        let (mut id, mut node, mut kind) = (None, None, None);
+        let (mut tags, mut label, mut last_seen) = (vec![], None, None);
+        let (mut poll, mut trigger, mut watch, mut updated) = (None, None, false, None);
+        let mut event = false;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => id = Some(try!(payload.as_id(NAME))),
+                2 => node = Some(try!(payload.as_id(NAME))),
+                3 => tags.push(try!(payload.as_tag(NAME))),
+                4 => label = Some(try!(payload.as_string(NAME))),
+                5 => last_seen = Some(try!(payload.as_message(NAME))),
+                6 => kind = Some(try!(payload.as_message(NAME))),
+                7 => poll = Some(try!(payload.as_message(NAME))),
+                8 => trigger = Some(try!(payload.as_message(NAME))),
+                9 => watch = try!(payload.as_bool(NAME)),
+                10 => updated = Some(try!(payload.as_message(NAME))),
+                11 => event = try!(payload.as_bool(NAME)),
+                _ => {}
+            }
+        }
+        match (id, node, kind) {
+            (Some(id), Some(node), Some(kind)) => {
+                let semantics = if event { Semantics::Event } else { Semantics::State };
+                let mut getter = Getter::new(kind).with_watch(watch).with_semantics(semantics);
+                getter.poll = poll;
+                getter.trigger = trigger;
+                getter.updated = updated;
+                let mut channel = Channel::new(id, node, getter).with_tags(tags);
+                channel.label = label;
+                channel.last_seen = last_seen;
+                Ok(channel)
+            }
+            _ => Err(DecodeError::Invalid(NAME))
+        }
+    }
+}
+
+impl Message for Channel<Setter> {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_string(1, self.id.as_string());
+        writer.write_string(2, self.node.as_string());
+        for tag in &self.tags {
+            writer.write_string(3, tag.as_str());
+        }
+        if let Some(ref label) = self.label {
+            writer.write_string(4, label);
+        }
+        if let Some(ref last_seen) = self.last_seen {
+            writer.write_message(5, last_seen);
+        }
+        writer.write_message(6, &self.mechanism.kind);
+        if let Some(ref push) = self.mechanism.push {
+            writer.write_message(7, push);
+        }
+        if let Some(ref updated) = self.mechanism.updated {
+            writer.write_message(8, updated);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "SetterChannel";
+        let (mut id, mut node, mut kind) = (None, None, None);
+        let (mut tags, mut label, mut last_seen) = (vec![], None, None);
+        let (mut push, mut updated) = (None, None);
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => id = Some(try!(payload.as_id(NAME))),
+                2 => node = Some(try!(payload.as_id(NAME))),
+                3 => tags.push(try!(payload.as_tag(NAME))),
+                4 => label = Some(try!(payload.as_string(NAME))),
+                5 => last_seen = Some(try!(payload.as_message(NAME))),
+                6 => kind = Some(try!(payload.as_message(NAME))),
+                7 => push = Some(try!(payload.as_message(NAME))),
+                8 => updated = Some(try!(payload.as_message(NAME))),
+                _ => {}
+            }
+        }
+        match (id, node, kind) {
+            (Some(id), Some(node), Some(kind)) => {
+                let mut setter = Setter::new(kind);
+                setter.push = push;
+                setter.updated = updated;
+                let mut channel = Channel::new(id, node, setter).with_tags(tags);
+                channel.label = label;
+                channel.last_seen = last_seen;
+                Ok(channel)
+            }
+            _ => Err(DecodeError::Invalid(NAME))
+        }
+    }
+}
+
+impl Message for StateChannel {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_string(1, self.id.as_string());
+        writer.write_string(2, self.getter.as_string());
+        writer.write_string(3, self.setter.as_string());
+        writer.write_message(4, &self.kind);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "StateChannel";
+        let (mut id, mut getter, mut setter, mut kind) = (None, None, None, None);
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => id = Some(try!(payload.as_id(NAME))),
+                2 => getter = Some(try!(payload.as_id(NAME))),
+                3 => setter = Some(try!(payload.as_id(NAME))),
+                4 => kind = Some(try!(payload.as_message(NAME))),
+                _ => {}
+            }
+        }
+        match (id, getter, setter, kind) {
+            (Some(id), Some(getter), Some(setter), Some(kind)) => Ok(StateChannel {
+                id: id,
+                getter: getter,
+                setter: setter,
+                kind: kind,
+            }),
+            _ => Err(DecodeError::Invalid(NAME))
+        }
+    }
+}
+
+impl Message for Node {
+    fn encode(&self, writer: &mut Writer) {
+        writer.write_string(1, self.id.as_string());
+        for tag in &self.tags {
+            writer.write_string(2, tag.as_str());
+        }
+        if let Some(ref label) = self.label {
+            writer.write_string(3, label);
+        }
+        for getter in &self.getters {
+            writer.write_message(4, getter);
+        }
+        for setter in &self.setters {
+            writer.write_message(5, setter);
+        }
+        for state in &self.states {
+            writer.write_message(6, state);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "Node";
+        let (mut id, mut tags, mut label) = (None, vec![], None);
+        let (mut getters, mut setters, mut states) = (vec![], vec![], vec![]);
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => id = Some(try!(payload.as_id(NAME))),
+                2 => tags.push(try!(payload.as_tag(NAME))),
+                3 => label = Some(try!(payload.as_string(NAME))),
+                4 => getters.push(try!(payload.as_message(NAME))),
+                5 => setters.push(try!(payload.as_message(NAME))),
+                6 => states.push(try!(payload.as_message(NAME))),
+                _ => {}
+            }
+        }
+        let mut node = Node::new(try!(id.ok_or(DecodeError::Invalid(NAME)))).with_tags(tags);
+        node.label = label;
+        node.getters = getters;
+        node.setters = setters;
+        node.states = states;
+        Ok(node)
+    }
+}
+
+/// Nodes and channels are shared, see `devices::Node`.
+impl<T> Message for Arc<T> where T: Message {
+    fn encode(&self, writer: &mut Writer) {
+        (**self).encode(writer)
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        T::decode(reader).map(Arc::new)
+    }
+}
+
+impl<T> Message for Exactly<Id<T>> {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            Exactly::Empty => {}
+            Exactly::Exactly(ref id) => writer.write_string(1, id.as_string()),
+            Exactly::Conflict => writer.write_bytes(2, &[]),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut result = Exactly::Empty;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result = Exactly::Exactly(try!(payload.as_id("Exactly"))),
+                2 => result = Exactly::Conflict,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for Exactly<ChannelKind> {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            Exactly::Empty => {}
+            Exactly::Exactly(ref kind) => writer.write_message(1, kind),
+            Exactly::Conflict => writer.write_bytes(2, &[]),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut result = Exactly::Empty;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result = Exactly::Exactly(try!(payload.as_message("ExactlyKind"))),
+                2 => result = Exactly::Conflict,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for Period {
+    fn encode(&self, writer: &mut Writer) {
+        if let Some(ref min) = self.min {
+            writer.write_message(1, min);
+        }
+        if let Some(ref max) = self.max {
+            writer.write_message(2, max);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut result = Period::default();
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result.min = Some(try!(payload.as_message("Period"))),
+                2 => result.max = Some(try!(payload.as_message("Period"))),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for GetterSelector {
+    fn encode(&self, writer: &mut Writer) {
+        if !self.id.is_empty() {
+            writer.write_message(1, &self.id);
+        }
+        if !self.parent.is_empty() {
+            writer.write_message(2, &self.parent);
+        }
+        for tag in &self.tags {
+            writer.write_string(3, tag.as_str());
+        }
+        if !self.kind.is_empty() {
+            writer.write_message(4, &self.kind);
+        }
+        if let Some(ref poll) = self.poll {
+            writer.write_message(5, poll);
+        }
+        if let Some(ref trigger) = self.trigger {
+            writer.write_message(6, trigger);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "GetterSelector";
+        let mut result = GetterSelector::new();
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result.id = try!(payload.as_message(NAME)),
+                2 => result.parent = try!(payload.as_message(NAME)),
+                3 => result.tags.push(try!(payload.as_tag(NAME))),
+                4 => result.kind = try!(payload.as_message(NAME)),
+                5 => result.poll = Some(try!(payload.as_message(NAME))),
+                6 => result.trigger = Some(try!(payload.as_message(NAME))),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for SetterSelector {
+    fn encode(&self, writer: &mut Writer) {
+        if !self.id.is_empty() {
+            writer.write_message(1, &self.id);
+        }
+        if !self.parent.is_empty() {
+            writer.write_message(2, &self.parent);
+        }
+        for tag in &self.tags {
+            writer.write_string(3, tag.as_str());
+        }
+        if !self.kind.is_empty() {
+            writer.write_message(4, &self.kind);
+        }
+        if let Some(ref push) = self.push {
+            writer.write_message(5, push);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "SetterSelector";
+        let mut result = SetterSelector::new();
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result.id = try!(payload.as_message(NAME)),
+                2 => result.parent = try!(payload.as_message(NAME)),
+                3 => result.tags.push(try!(payload.as_tag(NAME))),
+                4 => result.kind = try!(payload.as_message(NAME)),
+                5 => result.push = Some(try!(payload.as_message(NAME))),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for NodeSelector {
+    fn encode(&self, writer: &mut Writer) {
+        if !self.id.is_empty() {
+            writer.write_message(1, &self.id);
+        }
+        for tag in &self.tags {
+            writer.write_string(2, tag.as_str());
+        }
+        for getter in &self.getters {
+            writer.write_message(3, getter);
+        }
+        for setter in &self.setters {
+            writer.write_message(4, setter);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "NodeSelector";
+        let mut result = NodeSelector::new();
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            match field {
+                1 => result.id = try!(payload.as_message(NAME)),
+                2 => result.tags.push(try!(payload.as_tag(NAME))),
+                3 => result.getters.push(try!(payload.as_message(NAME))),
+                4 => result.setters.push(try!(payload.as_message(NAME))),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Message for AdapterEvent {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            AdapterEvent::Registered(ref id) => writer.write_string(1, id.as_string()),
+            AdapterEvent::Started(ref id) => writer.write_string(2, id.as_string()),
+            AdapterEvent::Stopped(ref id) => writer.write_string(3, id.as_string()),
+            AdapterEvent::Failed { ref adapter, ref error } => writer.write_nested(4, |writer| {
+                writer.write_string(1, adapter.as_string());
+                writer.write_string(2, error);
+            }),
+            AdapterEvent::Removed(ref id) => writer.write_string(5, id.as_string()),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "AdapterEvent";
+        let mut result = None;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            let event = match field {
+                1 => AdapterEvent::Registered(try!(payload.as_id(NAME))),
+                2 => AdapterEvent::Started(try!(payload.as_id(NAME))),
+                3 => AdapterEvent::Stopped(try!(payload.as_id(NAME))),
+                4 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut adapter, mut error) = (None, String::new());
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => adapter = Some(try!(payload.as_id(NAME))),
+                            2 => error = try!(payload.as_string(NAME)),
+                            _ => {}
+                        }
+                    }
+                    AdapterEvent::Failed {
+                        adapter: try!(adapter.ok_or(DecodeError::Invalid(NAME))),
+                        error: error
+                    }
+                }
+                5 => AdapterEvent::Removed(try!(payload.as_id(NAME))),
+                _ => continue
+            };
+            result = Some(event);
+        }
+        result.ok_or(DecodeError::Invalid(NAME))
+    }
+}
+
+impl Message for WatchEvent {
+    fn encode(&self, writer: &mut Writer) {
+        match *self {
+            WatchEvent::Value { ref from, ref value } => writer.write_nested(1, |writer| {
+                writer.write_string(1, from.as_string());
+                writer.write_message(2, value);
+            }),
+            WatchEvent::Error { ref from, ref error } => writer.write_nested(2, |writer| {
+                writer.write_string(1, from.as_string());
+                // Serializing an `Error` cannot fail.
+                writer.write_string(2, &serde_json::to_string(error).unwrap());
+            }),
+            WatchEvent::GetterRemoved(ref id) => writer.write_string(3, id.as_string()),
+            WatchEvent::GetterAdded(ref id) => writer.write_string(4, id.as_string()),
+            WatchEvent::LabelChanged { ref target, ref label } => writer.write_nested(5, |writer| {
+                match *target {
+                    Labelled::Node(ref id) => writer.write_string(1, id.as_string()),
+                    Labelled::Getter(ref id) => writer.write_string(2, id.as_string()),
+                    Labelled::Setter(ref id) => writer.write_string(3, id.as_string()),
+                }
+                if let Some(ref label) = *label {
+                    writer.write_string(4, label);
+                }
+            }),
+            WatchEvent::Batch(ref events) => writer.write_nested(6, |writer| {
+                for event in events {
+                    writer.write_message(1, event);
+                }
+            }),
+            WatchEvent::Adapter(ref event) => writer.write_message(7, event),
+            WatchEvent::NodeAvailability { ref node, available } => writer.write_nested(8, |writer| {
+                writer.write_string(1, node.as_string());
+                writer.write_bool(2, available);
+            }),
+            WatchEvent::Missed(missed) => writer.write_uint64(9, missed),
+            WatchEvent::Expired => writer.write_bool(10, true),
+            WatchEvent::KindRegistered { ref adapter, ref description } => writer.write_nested(11, |writer| {
+                writer.write_string(1, adapter.as_string());
+                writer.write_message(2, &description.kind);
+                writer.write_string(3, &description.description);
+                writer.write_bool(4, description.getter);
+                writer.write_bool(5, description.setter);
+                writer.write_bool(6, description.semantics == Semantics::Event);
+            }),
+            WatchEvent::KindRemoved { ref adapter, ref kind } => writer.write_nested(12, |writer| {
+                writer.write_string(1, adapter.as_string());
+                writer.write_message(2, kind);
+            }),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, DecodeError> {
+        const NAME: &'static str = "WatchEvent";
+        let mut result = None;
+        while let Some((field, payload)) = try!(reader.next_field()) {
+            let event = match field {
+                1 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut from, mut value) = (None, None);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => from = Some(try!(payload.as_id(NAME))),
+                            2 => value = Some(try!(payload.as_message(NAME))),
+                            _ => {}
+                        }
+                    }
+                    match (from, value) {
+                        (Some(from), Some(value)) => WatchEvent::Value {
+                            from: from,
+                            value: value
+                        },
+                        _ => return Err(DecodeError::Invalid(NAME))
+                    }
+                }
+                2 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut from, mut error) = (None, None);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => from = Some(try!(payload.as_id(NAME))),
+                            2 => error = serde_json::from_str::<Error>(try!(payload.as_str(NAME))).ok(),
+                            _ => {}
+                        }
+                    }
+                    match (from, error) {
+                        (Some(from), Some(error)) => WatchEvent::Error {
+                            from: from,
+                            error: error
+                        },
+                        _ => return Err(DecodeError::Invalid(NAME))
+                    }
+                }
+                3 => WatchEvent::GetterRemoved(try!(payload.as_id(NAME))),
+                4 => WatchEvent::GetterAdded(try!(payload.as_id(NAME))),
+                5 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut target, mut label) = (None, None);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => target = Some(Labelled::Node(try!(payload.as_id(NAME)))),
+                            2 => target = Some(Labelled::Getter(try!(payload.as_id(NAME)))),
+                            3 => target = Some(Labelled::Setter(try!(payload.as_id(NAME)))),
+                            4 => label = Some(try!(payload.as_string(NAME))),
+                            _ => {}
+                        }
+                    }
+                    WatchEvent::LabelChanged {
+                        target: try!(target.ok_or(DecodeError::Invalid(NAME))),
+                        label: label
+                    }
+                }
+                6 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let mut events = vec![];
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        if field == 1 {
+                            events.push(try!(payload.as_message(NAME)));
+                        }
+                    }
+                    WatchEvent::Batch(events)
+                }
+                7 => WatchEvent::Adapter(try!(payload.as_message(NAME))),
+                8 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut node, mut available) = (None, false);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => node = Some(try!(payload.as_id(NAME))),
+                            2 => available = try!(payload.as_bool(NAME)),
+                            _ => {}
+                        }
+                    }
+                    WatchEvent::NodeAvailability {
+                        node: try!(node.ok_or(DecodeError::Invalid(NAME))),
+                        available: available
+                    }
+                }
+                9 => WatchEvent::Missed(try!(payload.as_uint64(NAME))),
+                10 => WatchEvent::Expired,
+                11 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut adapter, mut kind, mut description) = (None, None, String::new());
+                    let (mut getter, mut setter, mut event) = (false, false, false);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => adapter = Some(try!(payload.as_id(NAME))),
+                            2 => kind = Some(try!(payload.as_message::<ChannelKind>(NAME))),
+                            3 => description = try!(payload.as_string(NAME)),
+                            4 => getter = try!(payload.as_bool(NAME)),
+                            5 => setter = try!(payload.as_bool(NAME)),
+                            6 => event = try!(payload.as_bool(NAME)),
+                            _ => {}
+                        }
+                    }
+                    let kind = try!(kind.ok_or(DecodeError::Invalid(NAME)));
+                    WatchEvent::KindRegistered {
+                        adapter: try!(adapter.ok_or(DecodeError::Invalid(NAME))),
+                        description: KindDescription {
+                            typ: kind.get_type(),
+                            kind: kind,
+                            semantics: if event { Semantics::Event } else { Semantics::State },
+                            description: description,
+                            getter: getter,
+                            setter: setter,
+                        }
+                    }
+                }
+                12 => {
+                    let mut nested = try!(payload.as_nested(NAME));
+                    let (mut adapter, mut kind) = (None, None);
+                    while let Some((field, payload)) = try!(nested.next_field()) {
+                        match field {
+                            1 => adapter = Some(try!(payload.as_id(NAME))),
+                            2 => kind = Some(try!(payload.as_message(NAME))),
+                            _ => {}
+                        }
+                    }
+                    WatchEvent::KindRemoved {
+                        adapter: try!(adapter.ok_or(DecodeError::Invalid(NAME))),
+                        kind: try!(kind.ok_or(DecodeError::Invalid(NAME)))
+                    }
+                }
+                _ => continue
+            };
+            result = Some(event);
+        }
+        result.ok_or(DecodeError::Invalid(NAME))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecodeError, Message, Writer};
+    use adapter::AdapterEvent;
+    use api::{Labelled, WatchEvent};
+    use devices::*;
+    use selector::*;
+    use values::*;
+    use util::{Exactly, Id, TagId};
+
+    use std::fmt::Debug;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Check that `message` survives encoding then decoding. Not all
+    /// types implement `PartialEq`, so they are compared through
+    /// `Debug`.
+    fn round_trip<M>(message: M) where M: Message + Debug {
+        let bytes = message.to_bytes();
+        let decoded = M::from_bytes(&bytes).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", message));
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    fn thermometer() -> Channel<Getter> {
+        let mut getter = Getter::new(ChannelKind::ActualTemperature).with_watch(true);
+        getter.poll = Some(ValDuration::new(Duration::from_millis(1500)));
+        getter.updated = TimeStamp::from_ms(1_466_000_000_123);
+        Channel::new(Id::new("thermometer/temperature".to_owned()), Id::new("thermometer".to_owned()), getter)
+            .with_tags(vec![TagId::new("kitchen").unwrap()])
+            .with_label("Kitchen".to_owned())
+    }
+
+    #[test]
+    fn test_values() {
+        round_trip(Value::Unit);
+        round_trip(Value::Bool(true));
+        round_trip(Value::Bool(false));
+        round_trip(Value::Duration(ValDuration::new(Duration::from_millis(90_061_001))));
+        round_trip(Value::TimeStamp(TimeStamp::from_ms(1_466_000_000_123).unwrap()));
+        round_trip(Value::TimeStamp(TimeStamp::from_ms(-86_400_001).unwrap()));
+        round_trip(Value::Temperature(Temperature::C(-12.5)));
+        round_trip(Value::Temperature(Temperature::F(98.6)));
+        round_trip(Value::Color(Color::RGBA(0.1, 0.2, 0.3, 1., 0.)));
+        round_trip(Value::String(Arc::new("été".to_owned())));
+        round_trip(Value::Count(0));
+        round_trip(Value::Count(u32::max_value()));
+        round_trip(Value::ExtNumeric(ExtNumeric {
+            value: -1e300,
+            vendor: "foxlink@mozilla.com".to_owned(),
+            adapter: "test".to_owned(),
+            kind: "pressure".to_owned(),
+        }));
+        round_trip(Value::Binary {
+            data: Bytes(Arc::new(vec![0, 1, 127, 128, 255])),
+            mimetype: "application/octet-stream".to_owned(),
+        });
+    }
+
+    #[test]
+    fn test_kinds() {
+        round_trip(ChannelKind::OnOff);
+        round_trip(ChannelKind::ActualTemperature);
+        round_trip(ChannelKind::Extension {
+            vendor: "foxlink@mozilla.com".to_owned(),
+            adapter: "test".to_owned(),
+            kind: "pressure".to_owned(),
+            typ: Type::ExtNumeric,
+        });
+    }
+
+    #[test]
+    fn test_nodes() {
+        round_trip(thermometer());
+        round_trip(Channel::new(Id::new("light/on".to_owned()), Id::new("light".to_owned()), Setter::new(ChannelKind::OnOff)));
+        round_trip(Node::new(Id::new("thermometer".to_owned()))
+            .with_label("Thermometer".to_owned())
+            .with_tags(vec![TagId::new("kitchen").unwrap()])
+            .with_getter(thermometer()));
+    }
+
+    #[test]
+    fn test_selectors() {
+        round_trip(GetterSelector::new());
+        round_trip(GetterSelector::new()
+            .with_id(Id::new("thermometer/temperature".to_owned()))
+            .with_parent(Id::new("thermometer".to_owned()))
+            .with_kind(ChannelKind::ActualTemperature)
+            .with_tags(vec![TagId::new("kitchen").unwrap(), TagId::new("upstairs").unwrap()])
+            .with_poll(Period {
+                min: Some(ValDuration::new(Duration::from_millis(10))),
+                max: None,
+            }));
+        round_trip(SetterSelector::new().with_kind(ChannelKind::OnOff));
+        round_trip(NodeSelector::new()
+            .with_id(Id::new("light".to_owned()))
+            .with_getters(vec![GetterSelector::new().with_kind(ChannelKind::OnOff)]));
+        round_trip(Exactly::Exactly(Id::<Getter>::new("thermometer/temperature".to_owned())));
+    }
+
+    #[test]
+    fn test_watch_events() {
+        let id: Id<Getter> = Id::new("thermometer/temperature".to_owned());
+        let value = WatchEvent::Value {
+            from: id.clone(),
+            value: Value::Temperature(Temperature::C(21.)),
+        };
+        round_trip(value.clone());
+        round_trip(WatchEvent::GetterAdded(id.clone()));
+        round_trip(WatchEvent::GetterRemoved(id.clone()));
+        round_trip(WatchEvent::LabelChanged {
+            target: Labelled::Getter(id.clone()),
+            label: Some("Kitchen".to_owned()),
+        });
+        round_trip(WatchEvent::LabelChanged {
+            target: Labelled::Node(Id::new("thermometer".to_owned())),
+            label: None,
+        });
+        round_trip(WatchEvent::Batch(vec![value.clone(), WatchEvent::Missed(3), value]));
+        round_trip(WatchEvent::Adapter(AdapterEvent::Failed {
+            adapter: Id::new("zwave@foxlink.mozilla.com".to_owned()),
+            error: "lost the controller".to_owned(),
+        }));
+        round_trip(WatchEvent::NodeAvailability {
+            node: Id::new("thermometer".to_owned()),
+            available: false,
+        });
+        round_trip(WatchEvent::Missed(u64::max_value()));
+        round_trip(WatchEvent::Expired);
+    }
+
+    #[test]
+    fn test_unknown_fields_are_skipped() {
+        let mut writer = Writer::new();
+        writer.write_string(100, "from a newer schema");
+        writer.write_message(5, &Temperature::C(21.));
+        writer.write_uint64(101, 42);
+        let decoded = Value::from_bytes(&writer.into_bytes()).unwrap();
+        assert_eq!(decoded, Value::Temperature(Temperature::C(21.)));
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let bytes = Value::String(Arc::new("truncated".to_owned())).to_bytes();
+        assert_eq!(Value::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), DecodeError::Truncated);
+        assert_eq!(Value::from_bytes(&[0x10, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).unwrap_err(),
+                   DecodeError::InvalidVarint);
+        assert_eq!(Value::from_bytes(&[0x3a, 2, 0xc3, 0x28]).unwrap_err(), DecodeError::InvalidUtf8);
+        assert_eq!(Value::from_bytes(&[]).unwrap_err(), DecodeError::Invalid("Value"));
+        assert_eq!(Value::from_bytes(&[0x60, 0x80, 0x80, 0x80, 0x80, 0x10]).unwrap_err(), DecodeError::Invalid("Value"));
+    }
+}
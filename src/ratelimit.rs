@@ -0,0 +1,750 @@
+//!
+//! Rate-limiting of the API.
+//!
+//! `RateLimited` wraps any implementation of the API and enforces
+//! quotas on fetches, sends and watch registrations, both per caller
+//! and per channel. Operations that exceed a quota are rejected with
+//! `Error::RateLimited`, without reaching the underlying implementation.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use foxbox_taxonomy::ratelimit::*;
+//!
+//! // At most 10 sends per minute per caller, and one send per second
+//! // per setter channel.
+//! let policy = RateLimitPolicy::new()
+//!     .with_sends_per_caller(Quota::new(10, ValDuration::new(Duration::new(60, 0))))
+//!     .with_sends_per_channel(Quota::new(1, ValDuration::new(Duration::new(1, 0))));
+//! let api = RateLimited::new(manager, policy);
+//! ```
+//!
+
+use api::*;
+use arbitration::Priority;
+use auth::{Caller, CallerId};
+use devices::*;
+use discovery::{Candidate, CandidateId};
+use history::{ Bucket, Sample };
+use metrics::Metrics;
+use selector::*;
+use values::{TimeStamp, Value, ValDuration};
+use util::{ Id, TagId };
+
+use std::cmp;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// A quota: at most `max` operations per `period`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Quota {
+    /// The largest number of operations accepted during `period`.
+    pub max: u32,
+
+    /// The duration of the window during which operations are counted.
+    pub period: ValDuration,
+}
+
+impl Quota {
+    pub fn new(max: u32, period: ValDuration) -> Self {
+        Quota {
+            max: max,
+            period: period,
+        }
+    }
+}
+
+/// The quotas enforced by `RateLimited`. A quota set to `None` is
+/// not enforced.
+///
+/// Fetches include both `API::get_channel_value` and `API::poll_now`.
+/// For per-caller quotas, each call counts as one operation, regardless
+/// of the number of channels involved. For per-channel quotas, each call
+/// counts as one operation for each channel involved.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RateLimitPolicy {
+    #[serde(default)]
+    pub fetches_per_caller: Option<Quota>,
+
+    #[serde(default)]
+    pub fetches_per_channel: Option<Quota>,
+
+    #[serde(default)]
+    pub sends_per_caller: Option<Quota>,
+
+    #[serde(default)]
+    pub sends_per_channel: Option<Quota>,
+
+    #[serde(default)]
+    pub watches_per_caller: Option<Quota>,
+}
+
+impl RateLimitPolicy {
+    /// Create a policy that does not enforce any quota.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of fetches performed by each caller.
+    pub fn with_fetches_per_caller(self, quota: Quota) -> Self {
+        RateLimitPolicy {
+            fetches_per_caller: Some(quota),
+            .. self
+        }
+    }
+
+    /// Limit the number of fetches from each getter channel.
+    pub fn with_fetches_per_channel(self, quota: Quota) -> Self {
+        RateLimitPolicy {
+            fetches_per_channel: Some(quota),
+            .. self
+        }
+    }
+
+    /// Limit the number of sends performed by each caller.
+    pub fn with_sends_per_caller(self, quota: Quota) -> Self {
+        RateLimitPolicy {
+            sends_per_caller: Some(quota),
+            .. self
+        }
+    }
+
+    /// Limit the number of sends to each setter channel.
+    pub fn with_sends_per_channel(self, quota: Quota) -> Self {
+        RateLimitPolicy {
+            sends_per_channel: Some(quota),
+            .. self
+        }
+    }
+
+    /// Limit the number of watches registered by each caller.
+    pub fn with_watches_per_caller(self, quota: Quota) -> Self {
+        RateLimitPolicy {
+            watches_per_caller: Some(quota),
+            .. self
+        }
+    }
+}
+
+/// The operations counted during the current window.
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// Do not bother pruning fewer windows.
+const MIN_PRUNE_ABOVE: usize = 16;
+
+/// The windows of the keys, e.g. callers or channels, subject to a quota.
+struct Windows<K> where K: Hash + Eq {
+    windows: HashMap<K, Window>,
+
+    /// The number of windows beyond which windows that have elapsed are
+    /// pruned, so that keys seen once, e.g. callers that have been
+    /// removed, do not accumulate.
+    prune_above: usize,
+}
+
+impl<K> Default for Windows<K> where K: Hash + Eq {
+    fn default() -> Self {
+        Windows {
+            windows: HashMap::new(),
+            prune_above: MIN_PRUNE_ABOVE,
+        }
+    }
+}
+
+impl<K> Windows<K> where K: Hash + Eq {
+    /// Remove the windows that have elapsed, as they hold no count. The
+    /// threshold grows with the number of remaining windows, so that
+    /// pruning takes amortized constant time.
+    fn prune(&mut self, period: Duration, now: Instant) {
+        if self.windows.len() <= self.prune_above {
+            return;
+        }
+        self.windows = self.windows.drain()
+            .filter(|&(_, ref window)| now.duration_since(window.start) < period)
+            .collect();
+        self.prune_above = cmp::max(2 * self.windows.len(), MIN_PRUNE_ABOVE);
+    }
+}
+
+/// Count one operation for `key`, unless this exceeds `quota`.
+fn acquire<K>(windows: &mut Windows<K>, key: &K, quota: &Option<Quota>, now: Instant) -> Result<(), Error>
+    where K: Hash + Eq + Clone
+{
+    let quota = match *quota {
+        None => return Ok(()),
+        Some(ref quota) => quota
+    };
+    let period = quota.period.as_duration();
+    windows.prune(period, now);
+    let window = windows.windows.entry(key.clone()).or_insert(Window {
+        start: now,
+        count: 0
+    });
+    if now.duration_since(window.start) >= period {
+        window.start = now;
+        window.count = 0;
+    }
+    if window.count >= quota.max {
+        let remaining = period - now.duration_since(window.start);
+        return Err(Error::RateLimited(ValDuration::new(remaining)));
+    }
+    window.count += 1;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Counters {
+    fetches_per_caller: Windows<Id<CallerId>>,
+    fetches_per_channel: Windows<Id<Getter>>,
+    sends_per_caller: Windows<Id<CallerId>>,
+    sends_per_channel: Windows<Id<Setter>>,
+    watches_per_caller: Windows<Id<CallerId>>,
+}
+
+/// An implementation of the API enforcing a `RateLimitPolicy` on top
+/// of another implementation.
+pub struct RateLimited<A> where A: API {
+    inner: A,
+    policy: RateLimitPolicy,
+    counters: Mutex<Counters>,
+}
+
+impl<A> RateLimited<A> where A: API {
+    pub fn new(inner: A, policy: RateLimitPolicy) -> Self {
+        RateLimited {
+            inner: inner,
+            policy: policy,
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+
+    /// Access the underlying implementation, bypassing quotas.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn limit_fetches<V, F>(&self, caller: &Caller, selectors: &Vec<GetterSelector>, fetch: F) -> Vec<(Id<Getter>, Result<V, Error>)>
+        where F: FnOnce(&Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<V, Error>)>
+    {
+        let per_caller = {
+            let mut counters = self.counters.lock().unwrap();
+            acquire(&mut counters.fetches_per_caller, &caller.id, &self.policy.fetches_per_caller, Instant::now())
+        };
+        if per_caller.is_ok() && self.policy.fetches_per_channel.is_none() {
+            return fetch(selectors);
+        }
+
+        let channels = self.inner.get_getter_channels(caller, selectors);
+        if let Err(err) = per_caller {
+            return channels.into_iter().map(|channel| (channel.id.clone(), Err(err.clone()))).collect();
+        }
+
+        let mut allowed = vec![];
+        let mut results = vec![];
+        {
+            let mut counters = self.counters.lock().unwrap();
+            let now = Instant::now();
+            for channel in channels {
+                match acquire(&mut counters.fetches_per_channel, &channel.id, &self.policy.fetches_per_channel, now) {
+                    Ok(()) => allowed.push(GetterSelector::new().with_id(channel.id.clone())),
+                    Err(err) => results.push((channel.id.clone(), Err(err)))
+                }
+            }
+        }
+        if !allowed.is_empty() {
+            let mut fetched = fetch(&allowed);
+            results.append(&mut fetched);
+        }
+        results
+    }
+
+    /// The state `id`, as visible by `caller`, if any.
+    fn state_channel(&self, caller: &Caller, id: &Id<StateId>) -> Option<StateChannel> {
+        let mut found = None;
+        self.inner.visit_nodes(caller, &vec![NodeSelector::new()], &mut |node| {
+            if found.is_none() {
+                found = node.states.iter().find(|pair| pair.id == *id).cloned();
+            }
+        });
+        found
+    }
+
+    /// Reading a state counts as a fetch of its getter, for per-caller
+    /// and per-channel quotas.
+    fn check_state_fetch(&self, caller: &Caller, id: &Id<StateId>) -> Result<(), Error> {
+        let getter = match self.policy.fetches_per_channel {
+            None => None,
+            Some(_) => self.state_channel(caller, id).map(|pair| pair.getter)
+        };
+        let mut counters = self.counters.lock().unwrap();
+        let now = Instant::now();
+        try!(acquire(&mut counters.fetches_per_caller, &caller.id, &self.policy.fetches_per_caller, now));
+        match getter {
+            None => Ok(()),
+            Some(getter) => acquire(&mut counters.fetches_per_channel, &getter, &self.policy.fetches_per_channel, now)
+        }
+    }
+
+    /// Changing a state counts as a send to its setter, for per-caller
+    /// and per-channel quotas.
+    fn check_state_send(&self, caller: &Caller, id: &Id<StateId>) -> Result<(), Error> {
+        let setter = match self.policy.sends_per_channel {
+            None => None,
+            Some(_) => self.state_channel(caller, id).map(|pair| pair.setter)
+        };
+        let mut counters = self.counters.lock().unwrap();
+        let now = Instant::now();
+        try!(acquire(&mut counters.sends_per_caller, &caller.id, &self.policy.sends_per_caller, now));
+        match setter {
+            None => Ok(()),
+            Some(setter) => acquire(&mut counters.sends_per_channel, &setter, &self.policy.sends_per_channel, now)
+        }
+    }
+
+    /// Scheduling a write counts as a send for per-caller quotas.
+    fn check_schedule(&self, caller: &Caller) -> Result<(), Error> {
+        let mut counters = self.counters.lock().unwrap();
+        acquire(&mut counters.sends_per_caller, &caller.id, &self.policy.sends_per_caller, Instant::now())
+    }
+
+    fn check_watch(&self, caller: &Caller) -> Result<(), Error> {
+        let mut counters = self.counters.lock().unwrap();
+        acquire(&mut counters.watches_per_caller, &caller.id, &self.policy.watches_per_caller, Instant::now())
+    }
+}
+
+impl<A> API for RateLimited<A> where A: API {
+    type WatchGuard = A::WatchGuard;
+
+    fn get_api_capabilities(&self, caller: &Caller) -> Vec<Capability> {
+        self.inner.get_api_capabilities(caller)
+    }
+
+    fn get_supported_kinds(&self, caller: &Caller) -> Vec<KindDescription> {
+        self.inner.get_supported_kinds(caller)
+    }
+
+    fn get_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>) -> Vec<Arc<Node>> {
+        self.inner.get_nodes(caller, selectors)
+    }
+
+    fn get_topology_revision(&self, caller: &Caller) -> Revision {
+        self.inner.get_topology_revision(caller)
+    }
+
+    fn get_nodes_if_changed(&self, caller: &Caller, selectors: &Vec<NodeSelector>, since: Option<Revision>) -> Conditional<Vec<Arc<Node>>> {
+        self.inner.get_nodes_if_changed(caller, selectors, since)
+    }
+
+    fn visit_nodes(&self, caller: &Caller, selectors: &Vec<NodeSelector>, cb: &mut FnMut(&Node)) {
+        self.inner.visit_nodes(caller, selectors, cb)
+    }
+
+    fn forget_node(&self, caller: &Caller, id: &Id<NodeId>) -> Result<(), Error> {
+        self.inner.forget_node(caller, id)
+    }
+
+    fn put_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: &Vec<TagId>) -> Result<TagResult<NodeId>, Error> {
+        self.inner.put_node_tag(caller, set, tags)
+    }
+
+    fn delete_node_tag(&self, caller: &Caller, set: &Vec<NodeSelector>, tags: TagId) -> Result<TagResult<NodeId>, Error> {
+        self.inner.delete_node_tag(caller, set, tags)
+    }
+
+    fn put_tagging_rule(&self, caller: &Caller, rule: TaggingRule) -> Result<Id<TaggingRuleId>, Error> {
+        self.inner.put_tagging_rule(caller, rule)
+    }
+
+    fn delete_tagging_rule(&self, caller: &Caller, id: &Id<TaggingRuleId>) -> Result<(), Error> {
+        self.inner.delete_tagging_rule(caller, id)
+    }
+
+    fn get_tagging_rules(&self, caller: &Caller) -> Vec<(Id<TaggingRuleId>, TaggingRule)> {
+        self.inner.get_tagging_rules(caller)
+    }
+
+    fn get_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<Arc<Channel<Getter>>> {
+        self.inner.get_getter_channels(caller, selectors)
+    }
+
+    fn get_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>) -> Vec<Arc<Channel<Setter>>> {
+        self.inner.get_setter_channels(caller, selectors)
+    }
+
+    fn visit_getter_channels(&self, caller: &Caller, selectors: &Vec<GetterSelector>, cb: &mut FnMut(&Channel<Getter>)) {
+        self.inner.visit_getter_channels(caller, selectors, cb)
+    }
+
+    fn visit_setter_channels(&self, caller: &Caller, selectors: &Vec<SetterSelector>, cb: &mut FnMut(&Channel<Setter>)) {
+        self.inner.visit_setter_channels(caller, selectors, cb)
+    }
+
+    fn put_aggregate_getter(&self, caller: &Caller, aggregate: AggregateGetter) -> Result<Id<Getter>, Error> {
+        self.inner.put_aggregate_getter(caller, aggregate)
+    }
+
+    fn delete_aggregate_getter(&self, caller: &Caller, id: &Id<Getter>) -> Result<(), Error> {
+        self.inner.delete_aggregate_getter(caller, id)
+    }
+
+    fn get_aggregate_getters(&self, caller: &Caller) -> Vec<(Id<Getter>, AggregateGetter)> {
+        self.inner.get_aggregate_getters(caller)
+    }
+
+    fn put_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.inner.put_getter_tag(caller, set, tags)
+    }
+
+    fn put_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.inner.put_setter_tag(caller, set, tags)
+    }
+
+    fn delete_getter_tag(&self, caller: &Caller, set: &Vec<GetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Getter>, Error> {
+        self.inner.delete_getter_tag(caller, set, tags)
+    }
+
+    fn delete_setter_tag(&self, caller: &Caller, set: &Vec<SetterSelector>, tags: &Vec<TagId>) -> Result<TagResult<Setter>, Error> {
+        self.inner.delete_setter_tag(caller, set, tags)
+    }
+
+    fn set_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>, label: String) -> Result<Vec<Id<NodeId>>, Error> {
+        self.inner.set_node_label(caller, set, label)
+    }
+
+    fn clear_node_label(&self, caller: &Caller, set: &Vec<NodeSelector>) -> Result<Vec<Id<NodeId>>, Error> {
+        self.inner.clear_node_label(caller, set)
+    }
+
+    fn set_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>, label: String) -> Result<Vec<Id<Getter>>, Error> {
+        self.inner.set_getter_label(caller, set, label)
+    }
+
+    fn set_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>, label: String) -> Result<Vec<Id<Setter>>, Error> {
+        self.inner.set_setter_label(caller, set, label)
+    }
+
+    fn clear_getter_label(&self, caller: &Caller, set: &Vec<GetterSelector>) -> Result<Vec<Id<Getter>>, Error> {
+        self.inner.clear_getter_label(caller, set)
+    }
+
+    fn clear_setter_label(&self, caller: &Caller, set: &Vec<SetterSelector>) -> Result<Vec<Id<Setter>>, Error> {
+        self.inner.clear_setter_label(caller, set)
+    }
+
+    fn get_channel_value(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        self.limit_fetches(caller, selectors, |selectors| self.inner.get_channel_value(caller, selectors))
+    }
+
+    fn poll_now(&self, caller: &Caller, selectors: &Vec<GetterSelector>) -> Vec<(Id<Getter>, Result<FetchedValue, Error>)> {
+        self.limit_fetches(caller, selectors, |selectors| self.inner.poll_now(caller, selectors))
+    }
+
+    fn get_channel_values_since(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: TimeStamp) -> Vec<(Id<Getter>, Result<Vec<Sample>, Error>)> {
+        self.limit_fetches(caller, selectors, |selectors| self.inner.get_channel_values_since(caller, selectors, since))
+    }
+
+    fn put_channel_value(&self, caller: &Caller, selectors: &Vec<SetterSelector>, value: Value, priority: Priority) -> Vec<(Id<Setter>, Result<Acknowledgement, Error>)> {
+        let per_caller = {
+            let mut counters = self.counters.lock().unwrap();
+            acquire(&mut counters.sends_per_caller, &caller.id, &self.policy.sends_per_caller, Instant::now())
+        };
+        if per_caller.is_ok() && self.policy.sends_per_channel.is_none() {
+            return self.inner.put_channel_value(caller, selectors, value, priority);
+        }
+
+        let channels = self.inner.get_setter_channels(caller, selectors);
+        if let Err(err) = per_caller {
+            return channels.into_iter().map(|channel| (channel.id.clone(), Err(err.clone()))).collect();
+        }
+
+        let mut allowed = vec![];
+        let mut results = vec![];
+        {
+            let mut counters = self.counters.lock().unwrap();
+            let now = Instant::now();
+            for channel in channels {
+                match acquire(&mut counters.sends_per_channel, &channel.id, &self.policy.sends_per_channel, now) {
+                    Ok(()) => allowed.push(SetterSelector::new().with_id(channel.id.clone())),
+                    Err(err) => results.push((channel.id.clone(), Err(err)))
+                }
+            }
+        }
+        if !allowed.is_empty() {
+            let mut sent = self.inner.put_channel_value(caller, &allowed, value, priority);
+            results.append(&mut sent);
+        }
+        results
+    }
+
+    fn get_state_value(&self, caller: &Caller, id: &Id<StateId>) -> Result<Option<FetchedValue>, Error> {
+        try!(self.check_state_fetch(caller, id));
+        self.inner.get_state_value(caller, id)
+    }
+
+    fn put_state_value(&self, caller: &Caller, id: &Id<StateId>, value: Value, priority: Priority) -> Result<Acknowledgement, Error> {
+        try!(self.check_state_send(caller, id));
+        self.inner.put_state_value(caller, id, value, priority)
+    }
+
+    fn put_channel_value_at(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, at: TimeStamp) -> Result<Id<ScheduleId>, Error> {
+        try!(self.check_schedule(caller));
+        self.inner.put_channel_value_at(caller, set, value, at)
+    }
+
+    fn put_channel_value_after(&self, caller: &Caller, set: &Vec<SetterSelector>, value: Value, delay: ValDuration) -> Result<Id<ScheduleId>, Error> {
+        try!(self.check_schedule(caller));
+        self.inner.put_channel_value_after(caller, set, value, delay)
+    }
+
+    fn get_scheduled_writes(&self, caller: &Caller) -> Vec<ScheduledWrite> {
+        self.inner.get_scheduled_writes(caller)
+    }
+
+    fn cancel_scheduled_write(&self, caller: &Caller, id: &Id<ScheduleId>) -> Result<(), Error> {
+        self.inner.cancel_scheduled_write(caller, id)
+    }
+
+    fn reserve_setters(&self, caller: &Caller, set: &Vec<SetterSelector>, duration: ValDuration) -> Result<Reservation, Error> {
+        self.inner.reserve_setters(caller, set, duration)
+    }
+
+    fn release_reservation(&self, caller: &Caller, id: &Id<ReservationId>) -> Result<(), Error> {
+        self.inner.release_reservation(caller, id)
+    }
+
+    fn get_reservations(&self, caller: &Caller) -> Vec<Reservation> {
+        self.inner.get_reservations(caller)
+    }
+
+    fn put_polling_hint(&self, caller: &Caller, source: &Vec<GetterSelector>, interval: ValDuration, duration: ValDuration) -> Result<PollingHint, Error> {
+        self.inner.put_polling_hint(caller, source, interval, duration)
+    }
+
+    fn delete_polling_hint(&self, caller: &Caller, id: &Id<PollingHintId>) -> Result<(), Error> {
+        self.inner.delete_polling_hint(caller, id)
+    }
+
+    fn get_polling_hints(&self, caller: &Caller) -> Vec<PollingHint> {
+        self.inner.get_polling_hints(caller)
+    }
+
+    fn register_channel_watch(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Self::WatchGuard, Error> {
+        try!(self.check_watch(caller));
+        self.inner.register_channel_watch(caller, options, cb)
+    }
+
+    fn register_channel_watch_channel(&self, caller: &Caller, options: Vec<WatchOptions>) -> Result<(Receiver<WatchEvent>, Self::WatchGuard), Error> {
+        try!(self.check_watch(caller));
+        self.inner.register_channel_watch_channel(caller, options)
+    }
+
+    fn register_channel_watch_with_token(&self, caller: &Caller, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        try!(self.check_watch(caller));
+        self.inner.register_channel_watch_with_token(caller, options, cb)
+    }
+
+    fn unregister_watch(&self, caller: &Caller, token: &Id<WatchId>) -> Result<(), Error> {
+        self.inner.unregister_watch(caller, token)
+    }
+
+    fn get_watches(&self, caller: &Caller) -> Result<Vec<WatchInfo>, Error> {
+        self.inner.get_watches(caller)
+    }
+
+    fn get_adapter_health(&self, caller: &Caller) -> Result<Vec<AdapterHealth>, Error> {
+        self.inner.get_adapter_health(caller)
+    }
+
+    fn get_metrics(&self, caller: &Caller) -> Result<Vec<Metrics>, Error> {
+        self.inner.get_metrics(caller)
+    }
+
+    fn get_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>) -> Result<Vec<ConfigEntry>, Error> {
+        self.inner.get_adapter_config(caller, adapter)
+    }
+
+    fn put_adapter_config(&self, caller: &Caller, adapter: &Id<AdapterId>, key: String, value: Value) -> Result<(), Error> {
+        self.inner.put_adapter_config(caller, adapter, key, value)
+    }
+
+    fn get_channel_history(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>) -> Result<Vec<(Id<Getter>, Vec<Sample>)>, Error> {
+        self.inner.get_channel_history(caller, selectors, since, until)
+    }
+
+    fn get_channel_history_aggregate(&self, caller: &Caller, selectors: &Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>,
+                                     bucket: ValDuration, aggregation: Aggregation) -> Result<Vec<(Id<Getter>, Vec<Bucket>)>, Error> {
+        self.inner.get_channel_history_aggregate(caller, selectors, since, until, bucket, aggregation)
+    }
+
+    fn get_candidates(&self, caller: &Caller) -> Result<Vec<Candidate>, Error> {
+        self.inner.get_candidates(caller)
+    }
+
+    fn adopt_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>, adapter: Option<Id<AdapterId>>) -> Result<(), Error> {
+        self.inner.adopt_candidate(caller, candidate, adapter)
+    }
+
+    fn reject_candidate(&self, caller: &Caller, candidate: &Id<CandidateId>) -> Result<(), Error> {
+        self.inner.reject_candidate(caller, candidate)
+    }
+
+    fn register_durable_watch(&self, caller: &Caller, owner: String, options: Vec<WatchOptions>, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        try!(self.check_watch(caller));
+        self.inner.register_durable_watch(caller, owner, options, cb)
+    }
+
+    fn resume_watch(&self, caller: &Caller, owner: &str, cb: Box<Fn(WatchEvent) + Send + 'static>) -> Result<Id<WatchId>, Error> {
+        try!(self.check_watch(caller));
+        self.inner.resume_watch(caller, owner, cb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Quota, RateLimitPolicy, RateLimited, Windows, acquire };
+    use api::*;
+    use arbitration::Priority;
+    use auth::{ Caller, CallerKind };
+    use devices::*;
+    use fake::FakeAPI;
+    use selector::*;
+    use values::{ Value, ValDuration };
+    use util::Id;
+
+    use std::time::{ Duration, Instant };
+
+    fn per_hour(max: u32) -> Quota {
+        Quota::new(max, ValDuration::new(Duration::from_secs(3600)))
+    }
+
+    /// A fake with a node holding a getter, which has a value, and two
+    /// setters.
+    fn setup(policy: RateLimitPolicy) -> RateLimited<FakeAPI> {
+        let fake = FakeAPI::new();
+        let node: Id<NodeId> = Id::new("ratelimit-node".to_owned());
+        let getter: Id<Getter> = Id::new("ratelimit-node/getter".to_owned());
+        fake.add_node(Node::new(node.clone())
+            .with_getter(Channel::new(getter.clone(), node.clone(), Getter::new(ChannelKind::OnOff)))
+            .with_setter(Channel::new(Id::new("ratelimit-node/setter-1".to_owned()), node.clone(), Setter::new(ChannelKind::OnOff)))
+            .with_setter(Channel::new(Id::new("ratelimit-node/setter-2".to_owned()), node.clone(), Setter::new(ChannelKind::OnOff))));
+        fake.inject_value(&getter, Value::Bool(true)).unwrap();
+        RateLimited::new(fake, policy)
+    }
+
+    #[test]
+    fn test_acquire() {
+        let quota = Some(Quota::new(2, ValDuration::new(Duration::from_secs(10))));
+        let mut windows = Windows::default();
+        let start = Instant::now();
+        acquire(&mut windows, &1, &quota, start).unwrap();
+        acquire(&mut windows, &1, &quota, start + Duration::from_secs(1)).unwrap();
+        match acquire(&mut windows, &1, &quota, start + Duration::from_secs(4)) {
+            Err(Error::RateLimited(remaining)) => assert_eq!(remaining.as_duration(), Duration::from_secs(6)),
+            other => panic!("Unexpected result {:?}", other)
+        }
+
+        // Other keys have windows of their own.
+        acquire(&mut windows, &2, &quota, start + Duration::from_secs(4)).unwrap();
+
+        // A new window starts once the period has passed.
+        acquire(&mut windows, &1, &quota, start + Duration::from_secs(10)).unwrap();
+
+        // Without a quota, nothing is counted.
+        for _ in 0..10 {
+            acquire(&mut windows, &3, &None, start).unwrap();
+        }
+        assert_eq!(windows.windows.len(), 2);
+    }
+
+    #[test]
+    fn test_prune() {
+        let quota = Some(Quota::new(1, ValDuration::new(Duration::from_secs(10))));
+        let mut windows = Windows::default();
+        let start = Instant::now();
+
+        // Each period, a hundred keys that are never seen again, e.g.
+        // short-lived callers. Windows that have elapsed are pruned, so
+        // they do not accumulate.
+        for period in 0..20 {
+            let now = start + Duration::from_secs(10 * period);
+            for key in 0..100 {
+                acquire(&mut windows, &(period * 100 + key), &quota, now).unwrap();
+            }
+            assert!(windows.windows.len() <= 300, "{}", windows.windows.len());
+        }
+
+        // Windows that have not elapsed are kept.
+        let last = start + Duration::from_secs(10 * 19);
+        for key in 0..100 {
+            assert!(acquire(&mut windows, &(1900 + key), &quota, last).is_err());
+        }
+    }
+
+    #[test]
+    fn test_fetches() {
+        let api = setup(RateLimitPolicy::new().with_fetches_per_caller(per_hour(1)));
+        let alice = Caller::new(Id::new("alice".to_owned()), CallerKind::User);
+        let bob = Caller::new(Id::new("bob".to_owned()), CallerKind::User);
+        let all = vec![GetterSelector::new()];
+
+        let results = api.get_channel_value(&alice, &all);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+        let results = api.get_channel_value(&alice, &all);
+        assert_eq!(results.len(), 1);
+        match results[0].1 {
+            Err(Error::RateLimited(_)) => {}
+            ref other => panic!("Unexpected result {:?}", other)
+        }
+
+        // Polling counts as a fetch.
+        match api.poll_now(&alice, &all)[0].1 {
+            Err(Error::RateLimited(_)) => {}
+            ref other => panic!("Unexpected result {:?}", other)
+        }
+
+        // Quotas are per caller.
+        assert!(api.get_channel_value(&bob, &all)[0].1.is_ok());
+    }
+
+    #[test]
+    fn test_sends() {
+        let api = setup(RateLimitPolicy::new().with_sends_per_channel(per_hour(1)));
+        let system = Caller::system();
+        let first: Id<Setter> = Id::new("ratelimit-node/setter-1".to_owned());
+
+        let results = api.put_channel_value(&system, &vec![SetterSelector::new().with_id(first.clone())], Value::Bool(true), Priority::Manual);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].1.is_ok());
+
+        // Quotas are per channel.
+        let mut results = api.put_channel_value(&system, &vec![SetterSelector::new()], Value::Bool(false), Priority::Manual);
+        results.sort_by(|a, b| a.0.to_string().cmp(&b.0.to_string()));
+        assert_eq!(results.len(), 2);
+        match results[0] {
+            (ref id, Err(Error::RateLimited(_))) => assert_eq!(*id, first),
+            ref other => panic!("Unexpected result {:?}", other)
+        }
+        assert!(results[1].1.is_ok());
+        assert_eq!(api.inner().sent_values().len(), 2);
+    }
+
+    #[test]
+    fn test_watches() {
+        let api = setup(RateLimitPolicy::new().with_watches_per_caller(per_hour(1)));
+        let system = Caller::system();
+        let options = vec![WatchOptions::new().with_watch_values(true)];
+        api.register_channel_watch_with_token(&system, options.clone(), Box::new(|_| {})).unwrap();
+        match api.register_channel_watch_with_token(&system, options, Box::new(|_| {})) {
+            Err(Error::RateLimited(_)) => {}
+            other => panic!("Unexpected result {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//!
+//! Recording and replaying the traffic of adapters.
+//!
+//! A `RecordingHandle` sits between an adapter and the manager. It
+//! records the nodes and channels registered and removed by the
+//! adapter, the changes in its lifecycle and the values it produces and
+//! receives, as a trace holding one JSON `Entry` per line. Function
+//! `replay` later plays a trace back through a `ReplayAdapter`, with the
+//! original timing or faster, so that bug reports and integration tests
+//! can be reproduced without the original devices.
+//!
+//! A trace records a single adapter.
+//!
+//! # Example
+//!
+//! ```ignore
+//! // Recording.
+//! let file = File::create("zwave.trace").unwrap();
+//! let handle = Arc::new(RecordingHandle::new(manager.clone(), Box::new(file)));
+//! handle.add_adapter(Arc::new(ZWaveAdapter::new(handle.clone()))).unwrap();
+//!
+//! // Replaying, ten times faster.
+//! let trace = parse_trace(&text).unwrap();
+//! let adapter = replay(manager.clone(), trace, 10.).unwrap();
+//! ```
+//!
+
+use adapter::{ Adapter, AdapterError, AdapterManagerHandle, AdapterWatchGuard, ConfigOption, RegistrationError, Watchers };
+use api::Acknowledgement;
+use devices::{ AdapterId, Channel, ChannelKind, Getter, KindDescription, Node, NodeId, Setter };
+use discovery::CandidateId;
+use values::{ ValDuration, Value };
+use util::Id;
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+/// Something that happened to the recorded adapter.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Record {
+    /// The adapter has been registered. This is always the first
+    /// record of a trace.
+    Adapter {
+        id: Id<AdapterId>,
+        name: String,
+        vendor: String,
+        version: Vec<u32>,
+    },
+
+    AddNode(Node),
+    RemoveNode(Id<NodeId>),
+    AddGetter(Channel<Getter>),
+    RemoveGetter(Id<Getter>),
+    AddSetter(Channel<Setter>),
+    RemoveSetter(Id<Setter>),
+    AddKind(KindDescription),
+    RemoveKind(ChannelKind),
+
+    /// See `AdapterManagerHandle::adapter_started`.
+    Started,
+
+    /// See `AdapterManagerHandle::adapter_stopped`.
+    Stopped,
+
+    /// See `AdapterManagerHandle::adapter_failed`.
+    Failed(String),
+
+    /// The adapter has been removed. This is the last record of a
+    /// trace.
+    Removed,
+
+    /// A getter has produced a value, either because it was fetched or
+    /// because it was watched.
+    Value {
+        getter: Id<Getter>,
+        value: Value,
+    },
+
+    /// A getter has failed to produce a value.
+    Error {
+        getter: Id<Getter>,
+        error: AdapterError,
+    },
+
+    /// A value has been sent successfully to a setter. Sends are not
+    /// replayed, as they originate from applications.
+    Sent {
+        setter: Id<Setter>,
+        value: Value,
+    },
+}
+
+/// A line of a trace.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    /// The number of milliseconds since the start of the recording.
+    pub at: u64,
+
+    pub record: Record,
+}
+
+/// Parse a trace, as written by `RecordingHandle`.
+pub fn parse_trace(text: &str) -> Result<Vec<Entry>, serde_json::Error> {
+    let mut entries = vec![];
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        entries.push(try!(serde_json::from_str(line)));
+    }
+    Ok(entries)
+}
+
+/// The destination of a trace. Errors while writing are ignored, so
+/// that recording never disturbs the adapter.
+struct Recorder {
+    start: Instant,
+    out: Mutex<Box<Write + Send>>,
+}
+
+impl Recorder {
+    fn record(&self, record: Record) {
+        let entry = Entry {
+            at: ValDuration::new(self.start.elapsed()).as_ms(),
+            record: record,
+        };
+        if let Ok(line) = serde_json::to_string(&entry) {
+            let mut out = self.out.lock().unwrap();
+            let _ = writeln!(out, "{}", line);
+            let _ = out.flush();
+        }
+    }
+}
+
+/// A handle recording everything that happens to the adapter registered
+/// through it, before forwarding to another handle, typically the
+/// manager.
+pub struct RecordingHandle {
+    inner: Arc<AdapterManagerHandle>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingHandle {
+    /// Record to `out`, starting now.
+    pub fn new(inner: Arc<AdapterManagerHandle>, out: Box<Write + Send>) -> Self {
+        RecordingHandle {
+            inner: inner,
+            recorder: Arc::new(Recorder {
+                start: Instant::now(),
+                out: Mutex::new(out),
+            }),
+        }
+    }
+
+    /// Record the registration of `adapter`, then wrap it to record its
+    /// traffic.
+    fn wrap(&self, adapter: Arc<Adapter>) -> Arc<Adapter> {
+        self.recorder.record(Record::Adapter {
+            id: adapter.id(),
+            name: adapter.name().to_owned(),
+            vendor: adapter.vendor().to_owned(),
+            version: adapter.version().to_vec(),
+        });
+        Arc::new(RecordingAdapter {
+            inner: adapter,
+            recorder: self.recorder.clone(),
+        })
+    }
+
+    /// Record `record` if `result` is a success.
+    fn record_if_ok(&self, result: Result<(), RegistrationError>, record: Record) -> Result<(), RegistrationError> {
+        if result.is_ok() {
+            self.recorder.record(record);
+        }
+        result
+    }
+}
+
+impl AdapterManagerHandle for RecordingHandle {
+    fn add_adapter(&self, adapter: Arc<Adapter>) -> Result<(), RegistrationError> {
+        let adapter = self.wrap(adapter);
+        self.inner.add_adapter(adapter)
+    }
+
+    fn add_adapter_with_nodes(&self, adapter: Arc<Adapter>, nodes: Vec<Node>) -> Result<(), RegistrationError> {
+        let adapter = self.wrap(adapter);
+        try!(self.inner.add_adapter_with_nodes(adapter, nodes.clone()));
+        for node in nodes {
+            self.recorder.record(Record::AddNode(node));
+        }
+        Ok(())
+    }
+
+    fn remove_adapter(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        let result = self.inner.remove_adapter(id);
+        self.record_if_ok(result, Record::Removed)
+    }
+
+    fn add_node(&self, adapter: &Id<AdapterId>, node: Node) -> Result<(), RegistrationError> {
+        let result = self.inner.add_node(adapter, node.clone());
+        self.record_if_ok(result, Record::AddNode(node))
+    }
+
+    fn remove_node(&self, id: &Id<NodeId>) -> Result<(), RegistrationError> {
+        let result = self.inner.remove_node(id);
+        self.record_if_ok(result, Record::RemoveNode(id.clone()))
+    }
+
+    fn add_getter(&self, channel: Channel<Getter>) -> Result<(), RegistrationError> {
+        let result = self.inner.add_getter(channel.clone());
+        self.record_if_ok(result, Record::AddGetter(channel))
+    }
+
+    fn remove_getter(&self, id: &Id<Getter>) -> Result<(), RegistrationError> {
+        let result = self.inner.remove_getter(id);
+        self.record_if_ok(result, Record::RemoveGetter(id.clone()))
+    }
+
+    fn add_setter(&self, channel: Channel<Setter>) -> Result<(), RegistrationError> {
+        let result = self.inner.add_setter(channel.clone());
+        self.record_if_ok(result, Record::AddSetter(channel))
+    }
+
+    fn remove_setter(&self, id: &Id<Setter>) -> Result<(), RegistrationError> {
+        let result = self.inner.remove_setter(id);
+        self.record_if_ok(result, Record::RemoveSetter(id.clone()))
+    }
+
+    fn adapter_started(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        let result = self.inner.adapter_started(id);
+        self.record_if_ok(result, Record::Started)
+    }
+
+    fn adapter_scanned(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        self.inner.adapter_scanned(id)
+    }
+
+    fn adapter_stopped(&self, id: &Id<AdapterId>) -> Result<(), RegistrationError> {
+        let result = self.inner.adapter_stopped(id);
+        self.record_if_ok(result, Record::Stopped)
+    }
+
+    fn adapter_failed(&self, id: &Id<AdapterId>, error: String) -> Result<(), RegistrationError> {
+        let result = self.inner.adapter_failed(id, error.clone());
+        self.record_if_ok(result, Record::Failed(error))
+    }
+
+    fn get_config(&self, adapter: &Id<AdapterId>, key: &str) -> Option<Value> {
+        self.inner.get_config(adapter, key)
+    }
+
+    fn add_extension_kind(&self, adapter: &Id<AdapterId>, description: KindDescription) -> Result<(), RegistrationError> {
+        let result = self.inner.add_extension_kind(adapter, description.clone());
+        self.record_if_ok(result, Record::AddKind(description))
+    }
+
+    fn remove_extension_kind(&self, adapter: &Id<AdapterId>, kind: &ChannelKind) -> Result<(), RegistrationError> {
+        let result = self.inner.remove_extension_kind(adapter, kind);
+        self.record_if_ok(result, Record::RemoveKind(kind.clone()))
+    }
+}
+
+/// An adapter recording the values it produces and receives.
+struct RecordingAdapter {
+    inner: Arc<Adapter>,
+    recorder: Arc<Recorder>,
+}
+
+impl Adapter for RecordingAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        self.inner.id()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn vendor(&self) -> &str {
+        self.inner.vendor()
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        self.inner.version()
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        let results = self.inner.fetch_values(getters);
+        for &(ref id, ref result) in &results {
+            match *result {
+                Ok(None) => {},
+                Ok(Some(ref value)) => self.recorder.record(Record::Value {
+                    getter: id.clone(),
+                    value: value.clone(),
+                }),
+                Err(ref error) => self.recorder.record(Record::Error {
+                    getter: id.clone(),
+                    error: error.clone(),
+                }),
+            }
+        }
+        results
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        let sent: HashMap<_, _> = values.iter().cloned().collect();
+        let results = self.inner.send_values(values);
+        for &(ref id, ref result) in &results {
+            if let (&Ok(()), Some(value)) = (result, sent.get(id)) {
+                self.recorder.record(Record::Sent {
+                    setter: id.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        results
+    }
+
+    fn send_values_acknowledged(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<Acknowledgement, AdapterError>)> {
+        let sent: HashMap<_, _> = values.iter().cloned().collect();
+        let results = self.inner.send_values_acknowledged(values);
+        for &(ref id, ref result) in &results {
+            if let (&Ok(_), Some(value)) = (result, sent.get(id)) {
+                self.recorder.record(Record::Sent {
+                    setter: id.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+        results
+    }
+
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        let recorder = self.recorder.clone();
+        let id = getter.clone();
+        self.inner.register_watch(getter, Box::new(move |result| {
+            recorder.record(match result {
+                Ok(ref value) => Record::Value {
+                    getter: id.clone(),
+                    value: value.clone(),
+                },
+                Err(ref error) => Record::Error {
+                    getter: id.clone(),
+                    error: error.clone(),
+                },
+            });
+            cb(result)
+        }))
+    }
+
+    fn stop(&self) {
+        self.inner.stop()
+    }
+
+    fn health_check(&self) -> Result<(), AdapterError> {
+        self.inner.health_check()
+    }
+
+    fn config_schema(&self) -> Vec<ConfigOption> {
+        self.inner.config_schema()
+    }
+
+    fn config_changed(&self, key: &str, value: &Value) -> Result<(), AdapterError> {
+        self.inner.config_changed(key, value)
+    }
+
+    fn adopt(&self, candidate: &Id<CandidateId>) -> Result<(), AdapterError> {
+        self.inner.adopt(candidate)
+    }
+
+    fn forget(&self, node: &Id<NodeId>) -> Result<(), AdapterError> {
+        self.inner.forget(node)
+    }
+}
+
+/// An error while starting to replay a trace.
+#[derive(Debug, Clone)]
+pub enum ReplayError {
+    /// The trace does not start with `Record::Adapter`.
+    MissingAdapter,
+
+    /// The adapter could not be registered.
+    Registration(RegistrationError),
+}
+
+/// An adapter playing back a trace. See `replay`.
+///
+/// Getters report the latest value replayed, and support watching.
+/// Setters accept all values.
+pub struct ReplayAdapter {
+    id: Id<AdapterId>,
+    name: String,
+    vendor: String,
+    version: [u32; 4],
+    values: Mutex<HashMap<Id<Getter>, Value>>,
+    watchers: Watchers,
+    stopped: (Mutex<bool>, Condvar),
+}
+
+impl ReplayAdapter {
+    /// Wait until `when`. Return `false` if the adapter has been
+    /// stopped in the meantime.
+    fn wait_until(&self, when: Instant) -> bool {
+        let (ref lock, ref cvar) = self.stopped;
+        let mut stopped = lock.lock().unwrap();
+        loop {
+            if *stopped {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= when {
+                return true;
+            }
+            stopped = cvar.wait_timeout(stopped, when - now).unwrap().0;
+        }
+    }
+
+    /// Play back a record. Registration errors are ignored, as the
+    /// topology may have been changed by applications, e.g. with
+    /// `API::forget_node`.
+    fn play(&self, handle: &AdapterManagerHandle, record: Record) {
+        let _ = match record {
+            Record::Adapter { .. } | Record::Sent { .. } => Ok(()),
+            Record::AddNode(node) => handle.add_node(&self.id, node),
+            Record::RemoveNode(id) => handle.remove_node(&id),
+            Record::AddGetter(channel) => handle.add_getter(channel),
+            Record::RemoveGetter(id) => handle.remove_getter(&id),
+            Record::AddSetter(channel) => handle.add_setter(channel),
+            Record::RemoveSetter(id) => handle.remove_setter(&id),
+            Record::AddKind(description) => handle.add_extension_kind(&self.id, description),
+            Record::RemoveKind(kind) => handle.remove_extension_kind(&self.id, &kind),
+            Record::Started => handle.adapter_started(&self.id),
+            Record::Stopped => handle.adapter_stopped(&self.id),
+            Record::Failed(error) => handle.adapter_failed(&self.id, error),
+            Record::Removed => handle.remove_adapter(&self.id),
+            Record::Value { getter, value } => {
+                self.values.lock().unwrap().insert(getter.clone(), value.clone());
+                self.watchers.notify(&getter, Ok(value));
+                Ok(())
+            }
+            Record::Error { getter, error } => {
+                self.watchers.notify(&getter, Err(error));
+                Ok(())
+            }
+        };
+    }
+}
+
+impl Adapter for ReplayAdapter {
+    fn id(&self) -> Id<AdapterId> {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn vendor(&self) -> &str {
+        &self.vendor
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        &self.version
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        let values = self.values.lock().unwrap();
+        getters.into_iter()
+            .map(|id| {
+                let value = values.get(&id).cloned();
+                (id, Ok(value))
+            })
+            .collect()
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        values.into_iter().map(|(id, _)| (id, Ok(()))).collect()
+    }
+
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        Ok(self.watchers.add(getter, cb))
+    }
+
+    /// Stop playing back the trace.
+    fn stop(&self) {
+        let (ref lock, ref cvar) = self.stopped;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+}
+
+/// Register the adapter recorded in `trace` with `handle`, then play
+/// back the trace on a thread of its own.
+///
+/// Delays between records are divided by `speed`, e.g. `1.` for the
+/// original timing, `10.` to replay ten times faster or
+/// `std::f64::INFINITY` to replay without any delay. `speed` must be
+/// positive. Playback stops at the end of the trace, or once the
+/// adapter is stopped.
+pub fn replay(handle: Arc<AdapterManagerHandle>, trace: Vec<Entry>, speed: f64) -> Result<Arc<ReplayAdapter>, ReplayError> {
+    let mut trace = trace.into_iter();
+    let adapter = match trace.next() {
+        Some(Entry { record: Record::Adapter { id, name, vendor, version }, .. }) => {
+            let mut fixed = [0; 4];
+            for (dest, src) in fixed.iter_mut().zip(version) {
+                *dest = src;
+            }
+            Arc::new(ReplayAdapter {
+                id: id,
+                name: name,
+                vendor: vendor,
+                version: fixed,
+                values: Mutex::new(HashMap::new()),
+                watchers: Watchers::new(),
+                stopped: (Mutex::new(false), Condvar::new()),
+            })
+        }
+        _ => return Err(ReplayError::MissingAdapter)
+    };
+    try!(handle.add_adapter(adapter.clone()).map_err(ReplayError::Registration));
+
+    {
+        let adapter = adapter.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            for entry in trace {
+                let delay = Duration::from_millis((entry.at as f64 / speed) as u64);
+                if !adapter.wait_until(start + delay) {
+                    return;
+                }
+                adapter.play(&*handle, entry.record);
+            }
+        });
+    }
+    Ok(adapter)
+}
@@ -0,0 +1,223 @@
+//!
+//! The REST API, as data.
+//!
+//! `ROUTES` lists the REST endpoints of the API, along with the method
+//! of `API` that implements each of them and the types of their
+//! requests and responses, as they appear in the documentation of
+//! `API`. HTTP frontends are expected to mount their handlers from
+//! this table rather than from their own list of endpoints, so that
+//! they remain consistent with each other and with the documentation.
+//!
+//! Several routes may share the same method and path, e.g. the getter
+//! and setter variants of `POST /api/v1/channels/tag`. In this case,
+//! the route is determined by the type of the request.
+//!
+//! `/api/v1/channels/watch` is also exposed as a WebSocket, which is not
+//! listed here. See module `websocket`. Neither are the streams of
+//! Server-Sent Events. See module `sse`.
+//!
+//! Responses are documented as JSON. Frontends may also serve binary
+//! values as raw or multipart bodies, as negotiated with the client. See
+//! module `body`.
+//!
+
+/// An HTTP method.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+/// How failures are reported by an endpoint, in addition to syntax
+/// errors, which are always reported as Error 400, accompanied with
+/// a somewhat human-readable JSON string detailing the error.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorShape {
+    /// The operation cannot fail.
+    None,
+
+    /// The operation fails as a whole, with a JSON representing an
+    /// `api::Error`.
+    Whole,
+
+    /// The operation may fail independently for each channel. The
+    /// response is an array of pairs `[id, result]`, where `result` is
+    /// either `{"Ok": ...}` or `{"Err": Error}`.
+    PerChannel,
+}
+
+/// A REST endpoint.
+#[derive(Serialize, Debug, Clone)]
+pub struct Route {
+    pub method: Method,
+
+    /// The path of the endpoint, e.g. "/api/v1/nodes".
+    pub path: &'static str,
+
+    /// If `Some(param)`, the route is only selected if the query
+    /// string holds parameter `param`.
+    pub query: Option<&'static str>,
+
+    /// The name of the method of `API` implementing this endpoint.
+    pub operation: &'static str,
+
+    /// If `Some(typ)`, the type of the body of the request.
+    pub request: Option<&'static str>,
+
+    /// If `Some(typ)`, the type of the body of a successful response.
+    pub response: Option<&'static str>,
+
+    /// How failures are reported.
+    pub errors: ErrorShape,
+}
+
+macro_rules! route {
+    ($method:ident $path:expr => $operation:expr, $request:expr, $response:expr, $errors:ident) => (
+        Route {
+            method: Method::$method,
+            path: $path,
+            query: None,
+            operation: $operation,
+            request: $request,
+            response: $response,
+            errors: ErrorShape::$errors,
+        }
+    )
+}
+
+/// All the REST endpoints of the API.
+pub static ROUTES: &'static [Route] = &[
+    route!(Get "/api/v1/capabilities" => "get_api_capabilities",
+           None, Some("Vec<Capability>"), None),
+    route!(Get "/api/v1/kinds" => "get_supported_kinds",
+           None, Some("Vec<KindDescription>"), None),
+    Route {
+        method: Method::Get,
+        path: "/api/v1/nodes",
+        query: Some("if_changed_since"),
+        operation: "get_nodes_if_changed",
+        request: Some("Vec<NodeSelector>"),
+        response: Some("Conditional<Vec<Node>>"),
+        errors: ErrorShape::None,
+    },
+    route!(Get "/api/v1/nodes" => "get_nodes",
+           Some("Vec<NodeSelector>"), Some("Vec<Node>"), None),
+    route!(Delete "/api/v1/nodes" => "forget_node",
+           Some("Id<NodeId>"), None, Whole),
+    route!(Get "/api/v1/nodes/revision" => "get_topology_revision",
+           None, Some("Revision"), None),
+    route!(Post "/api/v1/nodes/tag" => "put_node_tag",
+           Some("{ set: Vec<NodeSelector>, tags: Vec<TagId> }"), Some("TagResult<NodeId>"), Whole),
+    route!(Delete "/api/v1/nodes/tag" => "delete_node_tag",
+           Some("{ set: Vec<NodeSelector>, tags: TagId }"), Some("TagResult<NodeId>"), Whole),
+    route!(Post "/api/v1/nodes/tag/rules" => "put_tagging_rule",
+           Some("TaggingRule"), Some("Id<TaggingRuleId>"), Whole),
+    route!(Delete "/api/v1/nodes/tag/rules" => "delete_tagging_rule",
+           Some("Id<TaggingRuleId>"), None, Whole),
+    route!(Get "/api/v1/nodes/tag/rules" => "get_tagging_rules",
+           None, Some("Vec<(Id<TaggingRuleId>, TaggingRule)>"), None),
+    route!(Put "/api/v1/nodes/label" => "set_node_label",
+           Some("{ set: Vec<NodeSelector>, label: String }"), Some("Vec<Id<NodeId>>"), Whole),
+    route!(Delete "/api/v1/nodes/label" => "clear_node_label",
+           Some("Vec<NodeSelector>"), Some("Vec<Id<NodeId>>"), Whole),
+    route!(Get "/api/v1/channels" => "get_getter_channels",
+           Some("Vec<GetterSelector>"), Some("Vec<Channel<Getter>>"), None),
+    route!(Get "/api/v1/channels" => "get_setter_channels",
+           Some("Vec<SetterSelector>"), Some("Vec<Channel<Setter>>"), None),
+    route!(Post "/api/v1/channels/aggregates" => "put_aggregate_getter",
+           Some("AggregateGetter"), Some("Id<Getter>"), Whole),
+    route!(Delete "/api/v1/channels/aggregates" => "delete_aggregate_getter",
+           Some("Id<Getter>"), None, Whole),
+    route!(Get "/api/v1/channels/aggregates" => "get_aggregate_getters",
+           None, Some("Vec<(Id<Getter>, AggregateGetter)>"), None),
+    route!(Post "/api/v1/channels/tag" => "put_getter_tag",
+           Some("{ set: Vec<GetterSelector>, tags: Vec<TagId> }"), Some("TagResult<Getter>"), Whole),
+    route!(Post "/api/v1/channels/tag" => "put_setter_tag",
+           Some("{ set: Vec<SetterSelector>, tags: Vec<TagId> }"), Some("TagResult<Setter>"), Whole),
+    route!(Delete "/api/v1/channels/tag" => "delete_getter_tag",
+           Some("{ set: Vec<GetterSelector>, tags: Vec<TagId> }"), Some("TagResult<Getter>"), Whole),
+    route!(Delete "/api/v1/channels/tag" => "delete_setter_tag",
+           Some("{ set: Vec<SetterSelector>, tags: Vec<TagId> }"), Some("TagResult<Setter>"), Whole),
+    route!(Put "/api/v1/channels/label" => "set_getter_label",
+           Some("{ set: Vec<GetterSelector>, label: String }"), Some("Vec<Id<Getter>>"), Whole),
+    route!(Put "/api/v1/channels/label" => "set_setter_label",
+           Some("{ set: Vec<SetterSelector>, label: String }"), Some("Vec<Id<Setter>>"), Whole),
+    route!(Delete "/api/v1/channels/label" => "clear_getter_label",
+           Some("Vec<GetterSelector>"), Some("Vec<Id<Getter>>"), Whole),
+    route!(Delete "/api/v1/channels/label" => "clear_setter_label",
+           Some("Vec<SetterSelector>"), Some("Vec<Id<Setter>>"), Whole),
+    route!(Get "/api/v1/channels/value" => "get_channel_value",
+           Some("Vec<GetterSelector>"), Some("Vec<(Id<Getter>, Result<FetchedValue, Error>)>"), PerChannel),
+    route!(Post "/api/v1/channels/value" => "put_channel_value",
+           Some("{ set: Vec<SetterSelector>, value: Value, priority: Priority }"),
+           Some("Vec<(Id<Setter>, Result<Acknowledgement, Error>)>"), PerChannel),
+    route!(Get "/api/v1/states/value" => "get_state_value",
+           Some("Id<StateId>"), Some("Option<FetchedValue>"), Whole),
+    route!(Put "/api/v1/states/value" => "put_state_value",
+           Some("{ state: Id<StateId>, value: Value, priority: Priority }"), Some("Acknowledgement"), Whole),
+    route!(Post "/api/v1/channels/poll" => "poll_now",
+           Some("Vec<GetterSelector>"), Some("Vec<(Id<Getter>, Result<FetchedValue, Error>)>"), PerChannel),
+    route!(Post "/api/v1/channels/values/since" => "get_channel_values_since",
+           Some("{ selectors: Vec<GetterSelector>, since: TimeStamp }"),
+           Some("Vec<(Id<Getter>, Result<Vec<Sample>, Error>)>"), PerChannel),
+    route!(Post "/api/v1/channels/value/scheduled" => "put_channel_value_at",
+           Some("{ set: Vec<SetterSelector>, value: Value, at: TimeStamp }"), Some("Id<ScheduleId>"), Whole),
+    route!(Post "/api/v1/channels/value/scheduled" => "put_channel_value_after",
+           Some("{ set: Vec<SetterSelector>, value: Value, delay: ValDuration }"), Some("Id<ScheduleId>"), Whole),
+    route!(Get "/api/v1/channels/value/scheduled" => "get_scheduled_writes",
+           None, Some("Vec<ScheduledWrite>"), None),
+    route!(Delete "/api/v1/channels/value/scheduled" => "cancel_scheduled_write",
+           Some("Id<ScheduleId>"), None, Whole),
+    route!(Post "/api/v1/channels/reservations" => "reserve_setters",
+           Some("{ set: Vec<SetterSelector>, duration: ValDuration }"), Some("Reservation"), Whole),
+    route!(Delete "/api/v1/channels/reservations" => "release_reservation",
+           Some("Id<ReservationId>"), None, Whole),
+    route!(Get "/api/v1/channels/reservations" => "get_reservations",
+           None, Some("Vec<Reservation>"), None),
+    route!(Post "/api/v1/channels/polling" => "put_polling_hint",
+           Some("{ source: Vec<GetterSelector>, interval: ValDuration, duration: ValDuration }"), Some("PollingHint"), Whole),
+    route!(Delete "/api/v1/channels/polling" => "delete_polling_hint",
+           Some("Id<PollingHintId>"), None, Whole),
+    route!(Get "/api/v1/channels/polling" => "get_polling_hints",
+           None, Some("Vec<PollingHint>"), None),
+    route!(Post "/api/v1/channels/watch" => "register_channel_watch_with_token",
+           Some("Vec<WatchOptions>"), Some("Id<WatchId>"), Whole),
+    route!(Delete "/api/v1/channels/watch" => "unregister_watch",
+           Some("Id<WatchId>"), None, Whole),
+    route!(Get "/api/v1/channels/watch" => "get_watches",
+           None, Some("Vec<WatchInfo>"), Whole),
+    route!(Post "/api/v1/channels/watch/durable" => "register_durable_watch",
+           Some("{ owner: String, options: Vec<WatchOptions> }"), Some("Id<WatchId>"), Whole),
+    route!(Post "/api/v1/channels/watch/resume" => "resume_watch",
+           Some("String"), Some("Id<WatchId>"), Whole),
+    route!(Get "/api/v1/adapters/health" => "get_adapter_health",
+           None, Some("Vec<AdapterHealth>"), Whole),
+    route!(Get "/api/v1/metrics" => "get_metrics",
+           None, Some("Vec<Metrics>"), Whole),
+    route!(Get "/api/v1/adapters/config" => "get_adapter_config",
+           Some("Id<AdapterId>"), Some("Vec<ConfigEntry>"), Whole),
+    route!(Put "/api/v1/adapters/config" => "put_adapter_config",
+           Some("{ adapter: Id<AdapterId>, key: String, value: Value }"), None, Whole),
+    route!(Get "/api/v1/channels/history" => "get_channel_history",
+           Some("{ selectors: Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp> }"),
+           Some("Vec<(Id<Getter>, Vec<Sample>)>"), Whole),
+    route!(Get "/api/v1/channels/history/aggregate" => "get_channel_history_aggregate",
+           Some("{ selectors: Vec<GetterSelector>, since: Option<TimeStamp>, until: Option<TimeStamp>, bucket: ValDuration, aggregation: Aggregation }"),
+           Some("Vec<(Id<Getter>, Vec<Bucket>)>"), Whole),
+    route!(Get "/api/v1/candidates" => "get_candidates",
+           None, Some("Vec<Candidate>"), Whole),
+    route!(Put "/api/v1/candidates/adopt" => "adopt_candidate",
+           Some("{ candidate: Id<CandidateId>, adapter: Option<Id<AdapterId>> }"), None, Whole),
+    route!(Put "/api/v1/candidates/reject" => "reject_candidate",
+           Some("Id<CandidateId>"), None, Whole),
+];
+
+/// The routes matching a method and a path, in the order in which
+/// they should be attempted.
+pub fn find_routes(method: Method, path: &str) -> Vec<&'static Route> {
+    ROUTES.iter()
+        .filter(|route| route.method == method && route.path == path)
+        .collect()
+}
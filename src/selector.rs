@@ -1,10 +1,15 @@
-use devices::{NodeId, ChannelKind, Channel, Getter, Setter};
-use util::{Exactly, Id};
+use devices::{Node, NodeId, ChannelKind, Channel, Getter, Setter};
+use util::{Exactly, Id, TagId};
 use values;
 
+#[cfg(feature = "serde")]
 use serde::ser::Serializer;
+#[cfg(feature = "serde")]
 use serde::de::Deserializer;
 
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
 use std::cmp;
 
 fn merge<T>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> where T: Ord {
@@ -22,31 +27,33 @@ fn merge<T>(mut a: Vec<T>, mut b: Vec<T>) -> Vec<T> where T: Ord {
 /// ```
 /// use foxbox_taxonomy::selector::*;
 /// use foxbox_taxonomy::devices::*;
+/// use foxbox_taxonomy::util::TagId;
 ///
 /// let selector = NodeSelector::new()
-///   .with_tags(vec!["entrance".to_owned()])
+///   .with_tags(vec![TagId::new("entrance").unwrap()])
 ///   .with_getters(vec![GetterSelector::new() /* can be more restrictive */]);
 /// ```
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeSelector {
     /// If `Exactly(id)`, return only the node with the corresponding id.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub id: Exactly<Id<NodeId>>,
 
     ///  Restrict results to nodes that have all the tags in `tags`.
-    #[serde(default)]
-    pub tags: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: Vec<TagId>,
 
     /// Restrict results to nodes that have all the getters in `getters`.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub getters: Vec<GetterSelector>,
 
     /// Restrict results to nodes that have all the setters in `setters`.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub setters: Vec<SetterSelector>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 
@@ -66,7 +73,7 @@ impl NodeSelector {
     }
 
     ///  Restrict results to nodes that have all the tags in `tags`.
-    pub fn with_tags(self, tags: Vec<String>) -> Self {
+    pub fn with_tags(self, tags: Vec<TagId>) -> Self {
         NodeSelector {
             tags: merge(self.tags, tags),
             .. self
@@ -99,6 +106,27 @@ impl NodeSelector {
             private: (),
         }
     }
+
+    /// Determine if a node is matched by this selector.
+    pub fn matches(&self, node: &Node) -> bool {
+        if !self.id.matches(&node.id) {
+            return false;
+        }
+        if !has_selected_tags(&self.tags, &node.tags) {
+            return false;
+        }
+        for selector in &self.getters {
+            if !node.getters.iter().any(|channel| selector.matches(channel)) {
+                return false;
+            }
+        }
+        for selector in &self.setters {
+            if !node.setters.iter().any(|channel| selector.matches(channel)) {
+                return false;
+            }
+        }
+        return true;
+    }
 }
 
 
@@ -117,38 +145,39 @@ impl NodeSelector {
 ///   .with_parent(Id::new("foxbox".to_owned()))
 ///   .with_kind(ChannelKind::CurrentTimeOfDay);
 /// ```
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GetterSelector {
     /// If `Exactly(id)`, return only the channel with the corresponding id.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub id: Exactly<Id<Getter>>,
 
     /// If `Eactly(id)`, return only channels that are children of
     /// node `id`.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub parent: Exactly<Id<NodeId>>,
 
     ///  Restrict results to channels that have all the tags in `tags`.
-    #[serde(default)]
-    pub tags: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: Vec<TagId>,
 
     /// If `Exatly(k)`, restrict results to channels that produce values
     /// of kind `k`.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub kind: Exactly<ChannelKind>,
 
     /// If `Some(r)`, restrict results to channels that support polling
     /// with the acceptable period.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub poll: Option<Period>,
 
     /// If `Some(r)`, restrict results to channels that support trigger
     /// with the acceptable period.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub trigger: Option<Period>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 impl GetterSelector {
@@ -182,7 +211,7 @@ impl GetterSelector {
     }
 
     ///  Restrict to channels that have all the tags in `tags`.
-    pub fn with_tags(self, tags: Vec<String>) -> Self {
+    pub fn with_tags(self, tags: Vec<TagId>) -> Self {
         GetterSelector {
             tags: merge(self.tags, tags),
             .. self
@@ -245,33 +274,34 @@ impl GetterSelector {
 }
 
 /// A selector for one or more setter channels.
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SetterSelector {
     /// If `Exactly(id)`, return only the channel with the corresponding id.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub id: Exactly<Id<Setter>>,
 
     /// If `Exactly(id)`, return only channels that are immediate children
     /// of node `id`.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub parent: Exactly<Id<NodeId>>,
 
     ///  Restrict results to channels that have all the tags in `tags`.
-    #[serde(default)]
-    pub tags: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: Vec<TagId>,
 
     /// If `Exactly(k)`, restrict results to channels that accept values
     /// of kind `k`.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub kind: Exactly<ChannelKind>,
 
     /// If `Some(r)`, restrict results to channels that support pushing
     /// with the acceptable period.
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub push: Option<Period>,
 
     /// Make sure that we can't instantiate from another crate.
-    #[serde(default, skip_serializing)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
     private: (),
 }
 
@@ -306,7 +336,7 @@ impl SetterSelector {
     }
 
     ///  Restrict to channels that have all the tags in `tags`.
-    pub fn with_tags(self, tags: Vec<String>) -> Self {
+    pub fn with_tags(self, tags: Vec<TagId>) -> Self {
         SetterSelector {
             tags: merge(self.tags, tags),
             .. self
@@ -356,11 +386,12 @@ impl SetterSelector {
 }
 
 /// An acceptable interval of time.
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Period {
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub min: Option<values::ValDuration>,
-    #[serde(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub max: Option<values::ValDuration>,
 }
 impl Period {
@@ -413,7 +444,7 @@ impl Period {
     }
 }
 
-fn has_selected_tags(actual: &Vec<String>, requested: &Vec<String>) -> bool {
+fn has_selected_tags(actual: &Vec<TagId>, requested: &Vec<TagId>) -> bool {
     for tag in &*actual {
         if requested.iter().find(|x| *x == tag).is_none() {
             return false;
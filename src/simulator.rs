@@ -0,0 +1,623 @@
+//!
+//! Simulated adapters, for developing without hardware.
+//!
+//! These adapters register through the normal adapter API, so that
+//! frontends and rules can be developed and demonstrated against a full
+//! stack:
+//!
+//! - `Clock`, a getter of the current time and time of day, which must
+//!   be polled;
+//! - `Thermometer`, a sensor whose temperature follows a random walk;
+//! - `Echo`, a setter whose values are reported by a getter, e.g. a
+//!   virtual light;
+//! - `DoorSensor`, a sensor following a script of openings and
+//!   closings.
+//!
+//! Sensors that produce values on their own run a thread each, which
+//! stops once the adapter is stopped.
+//!
+//! A `Scenario` describes a fake home of its own, as JSON: the nodes of
+//! an adapter, and a timeline of values and changes to the topology,
+//! using the records of module `replay`. Binary `taxonomy-sim` plays a
+//! scenario and serves the API over HTTP, e.g.
+//!
+//! ```ignore
+//! {
+//!   "adapter": "scenario@foxlink.mozilla.com",
+//!   "builtin": true,
+//!   "nodes": [ ... ],
+//!   "timeline": [
+//!     { "at": 5000, "record": { "Value": { "getter": "scenario@foxlink.mozilla.com/door/open", "value": {"Bool":true} } } },
+//!     { "at": 9000, "record": { "RemoveNode": "scenario@foxlink.mozilla.com/door" } }
+//!   ]
+//! }
+//! ```
+//!
+//! This module is only available with feature `simulator`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let manager = Arc::new(AdapterManager::new().with_polling(PollingPolicy::default()));
+//! simulator::register_all(&*manager).unwrap();
+//!
+//! let scenario = Scenario::parse(&text).unwrap();
+//! scenario.play(manager.clone(), 1.).unwrap();
+//! ```
+//!
+
+use adapter::{ namespaced, Adapter, AdapterError, AdapterManagerHandle, AdapterWatchGuard, RegistrationError, Watchers };
+use api::Acknowledgement;
+use devices::{ AdapterId, Channel, ChannelKind, Getter, Node, Setter };
+use replay::{ self, Entry, Record, ReplayAdapter, ReplayError };
+use values::{ Temperature, TimeStamp, ValDuration, Value };
+use util::Id;
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+const VERSION: [u32; 4] = [0, 1, 0, 0];
+
+/// A thread calling `step` repeatedly, after the delay returned by the
+/// previous call, until it is stopped.
+struct Ticker {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Ticker {
+    fn start<F>(first: Duration, mut step: F) -> Self where F: FnMut() -> Duration + Send + 'static {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        {
+            let stopped = stopped.clone();
+            thread::spawn(move || {
+                let &(ref lock, ref cvar) = &*stopped;
+                let mut delay = first;
+                loop {
+                    let guard = lock.lock().unwrap();
+                    if *guard {
+                        return;
+                    }
+                    let (guard, _) = cvar.wait_timeout(guard, delay).unwrap();
+                    if *guard {
+                        return;
+                    }
+                    drop(guard);
+                    delay = step();
+                }
+            });
+        }
+        Ticker {
+            stopped: stopped,
+        }
+    }
+
+    /// Stop the thread. This does not wait until the thread is done, so
+    /// that the ticker may be stopped from `step`.
+    fn stop(&self) {
+        let &(ref lock, ref cvar) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// A xorshift generator, which is good enough for simulations.
+struct Random(u64);
+
+impl Random {
+    fn new() -> Self {
+        let seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() ^ duration.subsec_nanos() as u64,
+            Err(_) => 0
+        };
+        Random(seed | 1)
+    }
+
+    /// A random number between -1 and 1.
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % 2_000_001) as f64 / 1_000_000. - 1.
+    }
+}
+
+fn unknown_getters(getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+    getters.into_iter()
+        .map(|id| (id, Err(AdapterError::Permanent("No such getter".to_owned()))))
+        .collect()
+}
+
+fn unknown_setters(values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+    values.into_iter()
+        .map(|(id, _)| (id, Err(AdapterError::UnsupportedOperation("No setter".to_owned()))))
+        .collect()
+}
+
+/// A clock, exposing getters `CurrentTime` and `CurrentTimeOfDay`, in
+/// UTC. The clock does not support watching, and should be polled.
+pub struct Clock {
+    id: Id<AdapterId>,
+    time: Id<Getter>,
+    time_of_day: Id<Getter>,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        let id = Id::new("simulator-clock".to_owned());
+        Clock {
+            time: namespaced(&id, "clock/time"),
+            time_of_day: namespaced(&id, "clock/time-of-day"),
+            id: id,
+        }
+    }
+
+    /// The node exposing the clock, polled every second.
+    pub fn node(&self) -> Node {
+        let second = || Some(ValDuration::new(Duration::from_secs(1)));
+        let node_id = namespaced(&self.id, "clock");
+        let mut time = Getter::new(ChannelKind::CurrentTime);
+        time.poll = second();
+        let mut time_of_day = Getter::new(ChannelKind::CurrentTimeOfDay);
+        time_of_day.poll = second();
+        Node::new(node_id.clone())
+            .with_label("Clock".to_owned())
+            .with_getter(Channel::new(self.time.clone(), node_id.clone(), time))
+            .with_getter(Channel::new(self.time_of_day.clone(), node_id, time_of_day))
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new()
+    }
+}
+
+impl Adapter for Clock {
+    fn id(&self) -> Id<AdapterId> {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "Simulated clock"
+    }
+
+    fn vendor(&self) -> &str {
+        "foxlink@mozilla.com"
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        &VERSION
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        let now = TimeStamp::now();
+        getters.into_iter().map(|id| {
+            let result = if id == self.time {
+                Ok(Some(Value::TimeStamp(now.clone())))
+            } else if id == self.time_of_day {
+                let ms = now.as_ms() % (24 * 3600 * 1000);
+                Ok(Some(Value::Duration(ValDuration::new(Duration::from_millis(ms as u64)))))
+            } else {
+                Err(AdapterError::Permanent("No such getter".to_owned()))
+            };
+            (id, result)
+        }).collect()
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        unknown_setters(values)
+    }
+
+    fn register_watch(&self, _: &Id<Getter>, _: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        Err(AdapterError::UnsupportedOperation("The clock must be polled".to_owned()))
+    }
+}
+
+/// A thermometer, exposing an `ActualTemperature` getter whose value
+/// follows a random walk and supports watching.
+pub struct Thermometer {
+    id: Id<AdapterId>,
+    getter: Id<Getter>,
+    temperature: Arc<Mutex<f64>>,
+    watchers: Watchers,
+    ticker: Ticker,
+}
+
+impl Thermometer {
+    /// A thermometer starting at `initial` degrees Celsius, changing by
+    /// at most `step` degrees every `period`.
+    pub fn new(initial: f64, step: f64, period: Duration) -> Self {
+        let id = Id::new("simulator-thermometer".to_owned());
+        let getter = namespaced(&id, "thermometer/temperature");
+        let temperature = Arc::new(Mutex::new(initial));
+        let watchers = Watchers::new();
+        let ticker = {
+            let getter = getter.clone();
+            let temperature = temperature.clone();
+            let watchers = watchers.clone();
+            let mut random = Random::new();
+            Ticker::start(period, move || {
+                let value = {
+                    let mut temperature = temperature.lock().unwrap();
+                    *temperature += random.next() * step;
+                    Value::Temperature(Temperature::C(*temperature))
+                };
+                watchers.notify(&getter, Ok(value));
+                period
+            })
+        };
+        Thermometer {
+            id: id,
+            getter: getter,
+            temperature: temperature,
+            watchers: watchers,
+            ticker: ticker,
+        }
+    }
+
+    pub fn node(&self) -> Node {
+        let node_id = namespaced(&self.id, "thermometer");
+        let mut getter = Getter::new(ChannelKind::ActualTemperature);
+        getter.watch = true;
+        Node::new(node_id.clone())
+            .with_label("Thermometer".to_owned())
+            .with_getter(Channel::new(self.getter.clone(), node_id, getter))
+    }
+}
+
+impl Default for Thermometer {
+    /// A thermometer starting at 20°C, changing by at most 0.2°C every
+    /// 5 seconds.
+    fn default() -> Self {
+        Thermometer::new(20., 0.2, Duration::from_secs(5))
+    }
+}
+
+impl Adapter for Thermometer {
+    fn id(&self) -> Id<AdapterId> {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "Simulated thermometer"
+    }
+
+    fn vendor(&self) -> &str {
+        "foxlink@mozilla.com"
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        &VERSION
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        if getters.iter().any(|id| *id != self.getter) {
+            return unknown_getters(getters);
+        }
+        let value = Value::Temperature(Temperature::C(*self.temperature.lock().unwrap()));
+        getters.into_iter().map(|id| (id, Ok(Some(value.clone())))).collect()
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        unknown_setters(values)
+    }
+
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        if *getter != self.getter {
+            return Err(AdapterError::Permanent("No such getter".to_owned()));
+        }
+        Ok(self.watchers.add(getter, cb))
+    }
+
+    fn stop(&self) {
+        self.ticker.stop();
+    }
+}
+
+/// A setter and a getter of the same kind, the getter reporting the
+/// latest value sent to the setter, e.g. a virtual light.
+pub struct Echo {
+    id: Id<AdapterId>,
+    kind: ChannelKind,
+    getter: Id<Getter>,
+    setter: Id<Setter>,
+    value: Mutex<Option<Value>>,
+    watchers: Watchers,
+}
+
+impl Echo {
+    pub fn new(kind: ChannelKind) -> Self {
+        let id = Id::new("simulator-echo".to_owned());
+        Echo {
+            getter: namespaced(&id, "echo/getter"),
+            setter: namespaced(&id, "echo/setter"),
+            id: id,
+            kind: kind,
+            value: Mutex::new(None),
+            watchers: Watchers::new(),
+        }
+    }
+
+    pub fn node(&self) -> Node {
+        let node_id = namespaced(&self.id, "echo");
+        let mut getter = Getter::new(self.kind.clone());
+        getter.watch = true;
+        Node::new(node_id.clone())
+            .with_label("Echo".to_owned())
+            .with_getter(Channel::new(self.getter.clone(), node_id.clone(), getter))
+            .with_setter(Channel::new(self.setter.clone(), node_id, Setter::new(self.kind.clone())))
+    }
+}
+
+impl Default for Echo {
+    /// A virtual light, of kind `OnOff`.
+    fn default() -> Self {
+        Echo::new(ChannelKind::OnOff)
+    }
+}
+
+impl Adapter for Echo {
+    fn id(&self) -> Id<AdapterId> {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "Simulated echo"
+    }
+
+    fn vendor(&self) -> &str {
+        "foxlink@mozilla.com"
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        &VERSION
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        if getters.iter().any(|id| *id != self.getter) {
+            return unknown_getters(getters);
+        }
+        let value = self.value.lock().unwrap().clone();
+        getters.into_iter().map(|id| (id, Ok(value.clone()))).collect()
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        if values.iter().any(|&(ref id, _)| *id != self.setter) {
+            return unknown_setters(values);
+        }
+        values.into_iter().map(|(id, value)| {
+            *self.value.lock().unwrap() = Some(value.clone());
+            self.watchers.notify(&self.getter, Ok(value));
+            (id, Ok(()))
+        }).collect()
+    }
+
+    /// The echo confirms each value, as it is now the value of the
+    /// getter.
+    fn send_values_acknowledged(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<Acknowledgement, AdapterError>)> {
+        let sent: HashMap<_, _> = values.iter().cloned().collect();
+        self.send_values(values).into_iter()
+            .map(|(id, result)| {
+                let value = sent.get(&id).cloned();
+                (id, result.map(|()| Acknowledgement {
+                    value: value,
+                    timestamp: TimeStamp::now(),
+                }))
+            })
+            .collect()
+    }
+
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        if *getter != self.getter {
+            return Err(AdapterError::Permanent("No such getter".to_owned()));
+        }
+        Ok(self.watchers.add(getter, cb))
+    }
+}
+
+/// A door sensor, exposing an `OpenClosed` getter that follows a script
+/// and supports watching. The door is initially closed.
+pub struct DoorSensor {
+    id: Id<AdapterId>,
+    getter: Id<Getter>,
+    open: Arc<Mutex<bool>>,
+    watchers: Watchers,
+    ticker: Ticker,
+}
+
+impl DoorSensor {
+    /// A door sensor following `script`, in a loop. Each step of the
+    /// script holds a delay and the state of the door after that delay,
+    /// `true` if open. `script` may not be empty.
+    pub fn new(script: Vec<(ValDuration, bool)>) -> Self {
+        assert!(!script.is_empty());
+        let id = Id::new("simulator-door".to_owned());
+        let getter = namespaced(&id, "door/open-closed");
+        let open = Arc::new(Mutex::new(false));
+        let watchers = Watchers::new();
+        let ticker = {
+            let getter = getter.clone();
+            let open = open.clone();
+            let watchers = watchers.clone();
+            let first = script[0].0.as_duration();
+            let mut index = 0;
+            Ticker::start(first, move || {
+                let state = script[index].1;
+                *open.lock().unwrap() = state;
+                watchers.notify(&getter, Ok(Value::Bool(state)));
+                index = (index + 1) % script.len();
+                script[index].0.as_duration()
+            })
+        };
+        DoorSensor {
+            id: id,
+            getter: getter,
+            open: open,
+            watchers: watchers,
+            ticker: ticker,
+        }
+    }
+
+    pub fn node(&self) -> Node {
+        let node_id = namespaced(&self.id, "door");
+        let mut getter = Getter::new(ChannelKind::OpenClosed);
+        getter.watch = true;
+        Node::new(node_id.clone())
+            .with_label("Door".to_owned())
+            .with_getter(Channel::new(self.getter.clone(), node_id, getter))
+    }
+}
+
+impl Default for DoorSensor {
+    /// A door opening for 10 seconds every minute.
+    fn default() -> Self {
+        DoorSensor::new(vec![
+            (ValDuration::new(Duration::from_secs(50)), true),
+            (ValDuration::new(Duration::from_secs(10)), false),
+        ])
+    }
+}
+
+impl Adapter for DoorSensor {
+    fn id(&self) -> Id<AdapterId> {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "Simulated door sensor"
+    }
+
+    fn vendor(&self) -> &str {
+        "foxlink@mozilla.com"
+    }
+
+    fn version(&self) -> &[u32; 4] {
+        &VERSION
+    }
+
+    fn fetch_values(&self, getters: Vec<Id<Getter>>) -> Vec<(Id<Getter>, Result<Option<Value>, AdapterError>)> {
+        if getters.iter().any(|id| *id != self.getter) {
+            return unknown_getters(getters);
+        }
+        let value = Value::Bool(*self.open.lock().unwrap());
+        getters.into_iter().map(|id| (id, Ok(Some(value.clone())))).collect()
+    }
+
+    fn send_values(&self, values: Vec<(Id<Setter>, Value)>) -> Vec<(Id<Setter>, Result<(), AdapterError>)> {
+        unknown_setters(values)
+    }
+
+    fn register_watch(&self, getter: &Id<Getter>, cb: Box<Fn(Result<Value, AdapterError>) + Send + 'static>)
+        -> Result<Box<AdapterWatchGuard>, AdapterError> {
+        if *getter != self.getter {
+            return Err(AdapterError::Permanent("No such getter".to_owned()));
+        }
+        Ok(self.watchers.add(getter, cb))
+    }
+
+    fn stop(&self) {
+        self.ticker.stop();
+    }
+}
+
+/// Register each of the simulated adapters, with its default settings,
+/// along with its node.
+pub fn register_all(handle: &AdapterManagerHandle) -> Result<(), RegistrationError> {
+    let clock = Clock::new();
+    let node = clock.node();
+    try!(handle.add_adapter_with_nodes(Arc::new(clock), vec![node]));
+
+    let thermometer = Thermometer::default();
+    let node = thermometer.node();
+    try!(handle.add_adapter_with_nodes(Arc::new(thermometer), vec![node]));
+
+    let echo = Echo::default();
+    let node = echo.node();
+    try!(handle.add_adapter_with_nodes(Arc::new(echo), vec![node]));
+
+    let door = DoorSensor::default();
+    let node = door.node();
+    handle.add_adapter_with_nodes(Arc::new(door), vec![node])
+}
+
+/// A fake home, described as JSON. See the documentation of the module.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Scenario {
+    /// The adapter owning the nodes of the scenario.
+    pub adapter: Id<AdapterId>,
+
+    /// If `true`, the simulated adapters of this module are registered
+    /// along with the scenario. See `register_all`.
+    #[serde(default)]
+    pub builtin: bool,
+
+    /// The nodes present from the start, along with their channels.
+    #[serde(default)]
+    pub nodes: Vec<Node>,
+
+    /// Values, errors and changes to the topology, with the number of
+    /// milliseconds since the start of the scenario. Records
+    /// `Adapter` and `Sent` are ignored. The timeline is played once.
+    #[serde(default)]
+    pub timeline: Vec<Entry>,
+}
+
+impl Scenario {
+    /// Parse a scenario from its JSON description.
+    pub fn parse(text: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    /// The scenario as a trace, in the order of playback.
+    pub fn to_trace(&self) -> Vec<Entry> {
+        let mut trace = vec![Entry {
+            at: 0,
+            record: Record::Adapter {
+                id: self.adapter.clone(),
+                name: "Simulated scenario".to_owned(),
+                vendor: "foxlink@mozilla.com".to_owned(),
+                version: VERSION.to_vec(),
+            }
+        }];
+        for node in &self.nodes {
+            trace.push(Entry {
+                at: 0,
+                record: Record::AddNode(node.clone())
+            });
+        }
+        let mut timeline: Vec<_> = self.timeline.iter()
+            .filter(|entry| match entry.record {
+                Record::Adapter { .. } | Record::Sent { .. } => false,
+                _ => true
+            })
+            .cloned()
+            .collect();
+        // Stable, so that records sharing the same time keep their order.
+        timeline.sort_by_key(|entry| entry.at);
+        trace.extend(timeline);
+        trace
+    }
+
+    /// Register the adapter of the scenario with `handle`, along with
+    /// the simulated adapters if `builtin` is set, then play the
+    /// timeline. `speed` is interpreted as by `replay::replay`.
+    pub fn play(&self, handle: Arc<AdapterManagerHandle>, speed: f64) -> Result<Arc<ReplayAdapter>, ReplayError> {
+        if self.builtin {
+            try!(register_all(&*handle).map_err(ReplayError::Registration));
+        }
+        replay::replay(handle, self.to_trace(), speed)
+    }
+}
@@ -0,0 +1,176 @@
+//!
+//! Delivering watch events as Server-Sent Events.
+//!
+//! Server-Sent Events are a simpler alternative to the WebSocket API
+//! (see module `websocket`), designed for clients that only need to
+//! receive events, e.g. simple web pages using `EventSource` or
+//! command-line tools such as `curl`.
+//!
+//! A client first registers a watch with `POST /api/v1/channels/watch`
+//! (see `API::register_channel_watch_with_token`), then receives its
+//! events from `GET /api/v1/channels/watch/<token>/events`, with
+//! content type `text/event-stream`. Each `WatchEvent` is sent as one
+//! Server-Sent Event, whose name is given by `event_name` and whose data
+//! is the JSON representation of the `WatchEvent`:
+//!
+//! ```ignore
+//! id: 12
+//! event: value
+//! data: {"Value":{"from":"thermometer/temperature","value":...}}
+//!
+//! ```
+//!
+//! Events are numbered, starting from 0. A client reconnecting after a
+//! network failure sends the id of the last event it has received in
+//! header `Last-Event-ID`, and the FoxBox replays the events it has
+//! missed, if it still holds them. Otherwise, the FoxBox sends an event
+//! `reset`, after which the client should fetch the state it needs
+//! again, e.g. with `API::get_channel_value`.
+//!
+
+use api::{WatchEvent, WatchId};
+use util::Id;
+
+use serde_json;
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// The content type of a stream of Server-Sent Events.
+pub const CONTENT_TYPE: &'static str = "text/event-stream";
+
+/// The path from which the events of a watch may be received, given
+/// its token.
+pub fn events_path(token: &Id<WatchId>) -> String {
+    format!("/api/v1/channels/watch/{}/events", token.as_string())
+}
+
+/// The name of the Server-Sent Event used to deliver a `WatchEvent`.
+pub fn event_name(event: &WatchEvent) -> &'static str {
+    match *event {
+        WatchEvent::Value { .. } => "value",
+        WatchEvent::Error { .. } => "error",
+        WatchEvent::GetterAdded(_) => "getter-added",
+        WatchEvent::GetterRemoved(_) => "getter-removed",
+        WatchEvent::LabelChanged { .. } => "label-changed",
+        WatchEvent::Batch(_) => "batch",
+        WatchEvent::Adapter(_) => "adapter",
+        WatchEvent::NodeAvailability { .. } => "node-availability",
+        WatchEvent::Missed(_) => "missed",
+        WatchEvent::Expired => "expired",
+        WatchEvent::KindRegistered { .. } => "kind-registered",
+        WatchEvent::KindRemoved { .. } => "kind-removed",
+    }
+}
+
+/// A single Server-Sent Event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSentEvent {
+    /// The id of the event, sent back by the client in header
+    /// `Last-Event-ID` when reconnecting.
+    pub id: Option<String>,
+
+    /// The name of the event.
+    pub event: String,
+
+    /// The payload. May span several lines.
+    pub data: String,
+}
+
+/// Format the event as it is sent over the wire, including the empty
+/// line that terminates it.
+impl fmt::Display for ServerSentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref id) = self.id {
+            try!(write!(f, "id: {}\n", id));
+        }
+        try!(write!(f, "event: {}\n", self.event));
+        for line in self.data.lines() {
+            try!(write!(f, "data: {}\n", line));
+        }
+        write!(f, "\n")
+    }
+}
+
+/// The events of one watch, as delivered to a client over Server-Sent
+/// Events.
+///
+/// The stream numbers the events and holds the latest of them, so that
+/// they may be replayed to a client reconnecting with `Last-Event-ID`.
+pub struct EventStream {
+    /// The number of events pushed so far, i.e. the id of the next
+    /// event.
+    next: u64,
+
+    /// The latest events, along with their id, oldest first.
+    replay: VecDeque<(u64, ServerSentEvent)>,
+
+    /// The largest number of events held for replay.
+    capacity: usize,
+}
+
+impl EventStream {
+    /// Create a stream holding at most `capacity` events for replay.
+    pub fn new(capacity: usize) -> Self {
+        EventStream {
+            next: 0,
+            replay: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+        }
+    }
+
+    /// Convert an event to a Server-Sent Event, to be sent to the
+    /// client, and hold it for replay.
+    pub fn push(&mut self, event: &WatchEvent) -> Result<ServerSentEvent, serde_json::Error> {
+        let data = try!(serde_json::to_string(event));
+        let id = self.next;
+        self.next += 1;
+        let sse = ServerSentEvent {
+            id: Some(id.to_string()),
+            event: event_name(event).to_owned(),
+            data: data,
+        };
+        if self.capacity > 0 {
+            if self.replay.len() == self.capacity {
+                self.replay.pop_front();
+            }
+            self.replay.push_back((id, sse.clone()));
+        }
+        Ok(sse)
+    }
+
+    /// The events to send to a client reconnecting with header
+    /// `Last-Event-ID: last_event_id`, i.e. all the events pushed
+    /// after that one.
+    ///
+    /// Produce `None` if some of these events are not held anymore, or
+    /// if `last_event_id` is not the id of an event of this stream. In
+    /// this case, the frontend is expected to send `EventStream::reset()`.
+    pub fn resume(&self, last_event_id: &str) -> Option<Vec<ServerSentEvent>> {
+        let last = match last_event_id.trim().parse::<u64>() {
+            Ok(last) if last < self.next => last,
+            _ => return None
+        };
+        if last + 1 == self.next {
+            return Some(vec![]);
+        }
+        match self.replay.front() {
+            Some(&(oldest, _)) if oldest <= last + 1 => {}
+            _ => return None
+        }
+        Some(self.replay.iter()
+            .filter(|&&(id, _)| id > last)
+            .map(|&(_, ref sse)| sse.clone())
+            .collect())
+    }
+
+    /// The event telling the client that some events have been lost.
+    pub fn reset(&self) -> ServerSentEvent {
+        // Clients ignore events without data.
+        ServerSentEvent {
+            id: None,
+            event: "reset".to_owned(),
+            data: "null".to_owned(),
+        }
+    }
+}
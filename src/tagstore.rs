@@ -0,0 +1,268 @@
+//!
+//! Persisting the tags set by users.
+//!
+//! Tags are typically set by hand, so losing them to a restart of the
+//! FoxBox, or to a device being paired again, is a real loss for users.
+//! A `TagStore` keeps the tags of each node and channel by id. The
+//! manager writes to the store whenever tags are changed through the
+//! API, and restores stored tags whenever a node or channel with the
+//! same id is registered again, see `manager::AdapterManager::with_tag_store`.
+//! Adapters should therefore give the same ids to the same devices, see
+//! `adapter::stable_id`.
+//!
+//! Two implementations are provided: `JsonTagStore`, which keeps tags
+//! in a JSON file, and `SqliteTagStore`, which keeps them in a SQLite
+//! database and requires feature `sqlite`.
+//!
+//! The stores require feature `runtime`. Without it, this module only
+//! provides `StoreError`, as reported by the API.
+//!
+
+#[cfg(feature = "runtime")]
+use api::Labelled;
+#[cfg(feature = "runtime")]
+use devices::{ Getter, NodeId, Setter };
+#[cfg(feature = "runtime")]
+use util::{ Id, TagId };
+
+#[cfg(feature = "runtime")]
+use serde_json;
+
+#[cfg(feature = "runtime")]
+use std::collections::HashMap;
+#[cfg(feature = "runtime")]
+use std::fs::{ self, File };
+#[cfg(feature = "runtime")]
+use std::io::{ ErrorKind, Read, Write };
+#[cfg(feature = "runtime")]
+use std::path::{ Path, PathBuf };
+#[cfg(feature = "runtime")]
+use std::sync::Mutex;
+
+/// An error while reading or writing persistent storage, e.g. a
+/// `TagStore` or a `history::HistoryStore`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StoreError {
+    /// The storage could not be read or written.
+    Io(String),
+
+    /// The stored data could not be decoded.
+    Corrupted(String),
+}
+
+#[cfg(feature = "runtime")]
+/// Replace the content of the file at `path` with `source`. The content
+/// is written to a temporary file, then renamed, so that a crash never
+/// leaves a truncated file.
+pub fn write_atomically(path: &Path, source: &str) -> Result<(), StoreError> {
+    let mut temporary = path.to_path_buf().into_os_string();
+    temporary.push(".tmp");
+    let result = File::create(&temporary)
+        .and_then(|mut file| file.write_all(source.as_bytes()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temporary, path));
+    result.map_err(|err| StoreError::Io(format!("{}", err)))
+}
+
+#[cfg(feature = "runtime")]
+/// Storage for the tags of nodes and channels.
+pub trait TagStore: Send + Sync {
+    /// The tags stored for a node or channel, or `None` if none have
+    /// been stored.
+    fn get_tags(&self, target: &Labelled) -> Result<Option<Vec<TagId>>, StoreError>;
+
+    /// Replace the tags stored for a node or channel.
+    fn set_tags(&self, target: &Labelled, tags: &[TagId]) -> Result<(), StoreError>;
+
+    /// Remove the tags stored for a node or channel, e.g. once it has
+    /// been forgotten.
+    fn forget(&self, target: &Labelled) -> Result<(), StoreError>;
+}
+
+#[cfg(feature = "runtime")]
+/// The content of a `JsonTagStore`.
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    #[serde(default)]
+    nodes: HashMap<Id<NodeId>, Vec<TagId>>,
+
+    #[serde(default)]
+    getters: HashMap<Id<Getter>, Vec<TagId>>,
+
+    #[serde(default)]
+    setters: HashMap<Id<Setter>, Vec<TagId>>,
+}
+
+#[cfg(feature = "runtime")]
+/// A `TagStore` keeping tags in a JSON file, e.g.
+///
+/// ```ignore
+/// {
+///   "nodes": { "zwave@foxlink.mozilla.com/node-3": ["kitchen"] },
+///   "getters": {},
+///   "setters": {}
+/// }
+/// ```
+///
+/// The file is read once, then written again in its entirety after each
+/// change, see `write_atomically`.
+pub struct JsonTagStore {
+    path: PathBuf,
+    stored: Mutex<Stored>,
+}
+
+#[cfg(feature = "runtime")]
+impl JsonTagStore {
+    /// Open the store kept at `path`, which is created on the first
+    /// change if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        let stored = match File::open(&path) {
+            Ok(mut file) => {
+                let mut source = String::new();
+                try!(file.read_to_string(&mut source).map_err(|err| StoreError::Io(format!("{}", err))));
+                try!(serde_json::from_str(&source).map_err(|err| StoreError::Corrupted(format!("{:?}", err))))
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Stored::default(),
+            Err(err) => return Err(StoreError::Io(format!("{}", err)))
+        };
+        Ok(JsonTagStore {
+            path: path,
+            stored: Mutex::new(stored),
+        })
+    }
+
+    fn write(&self, stored: &Stored) -> Result<(), StoreError> {
+        let source = try!(serde_json::to_string_pretty(stored).map_err(|err| StoreError::Corrupted(format!("{:?}", err))));
+        write_atomically(&self.path, &source)
+    }
+
+    /// Apply `change` to the stored tags, then write them if `change`
+    /// returns `true`.
+    fn change<F>(&self, change: F) -> Result<(), StoreError> where F: FnOnce(&mut Stored) -> bool {
+        let mut stored = self.stored.lock().unwrap();
+        if change(&mut stored) {
+            self.write(&stored)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "runtime")]
+impl TagStore for JsonTagStore {
+    fn get_tags(&self, target: &Labelled) -> Result<Option<Vec<TagId>>, StoreError> {
+        let stored = self.stored.lock().unwrap();
+        Ok(match *target {
+            Labelled::Node(ref id) => stored.nodes.get(id).cloned(),
+            Labelled::Getter(ref id) => stored.getters.get(id).cloned(),
+            Labelled::Setter(ref id) => stored.setters.get(id).cloned(),
+        })
+    }
+
+    fn set_tags(&self, target: &Labelled, tags: &[TagId]) -> Result<(), StoreError> {
+        let tags = tags.to_vec();
+        self.change(|stored| {
+            let previous = match *target {
+                Labelled::Node(ref id) => stored.nodes.insert(id.clone(), tags.clone()),
+                Labelled::Getter(ref id) => stored.getters.insert(id.clone(), tags.clone()),
+                Labelled::Setter(ref id) => stored.setters.insert(id.clone(), tags.clone()),
+            };
+            previous != Some(tags)
+        })
+    }
+
+    fn forget(&self, target: &Labelled) -> Result<(), StoreError> {
+        self.change(|stored| {
+            match *target {
+                Labelled::Node(ref id) => stored.nodes.remove(id),
+                Labelled::Getter(ref id) => stored.getters.remove(id),
+                Labelled::Setter(ref id) => stored.setters.remove(id),
+            }.is_some()
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteTagStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{ StoreError, TagStore };
+    use api::Labelled;
+    use util::TagId;
+
+    use rusqlite::{ self, Connection };
+    use rusqlite::types::ToSql;
+    use serde_json;
+
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    fn backend(err: rusqlite::Error) -> StoreError {
+        StoreError::Io(format!("{}", err))
+    }
+
+    /// The table and id of a node or channel.
+    fn key(target: &Labelled) -> (&'static str, &str) {
+        match *target {
+            Labelled::Node(ref id) => ("node", id.as_string()),
+            Labelled::Getter(ref id) => ("getter", id.as_string()),
+            Labelled::Setter(ref id) => ("setter", id.as_string()),
+        }
+    }
+
+    /// A `TagStore` keeping tags in a SQLite database, in table
+    /// `tags (target, id, tags)`, where `target` is one of "node",
+    /// "getter" or "setter", and `tags` is a JSON array.
+    pub struct SqliteTagStore {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteTagStore {
+        /// Open the database at `path`, creating it if necessary.
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+            let connection = try!(Connection::open(path).map_err(backend));
+            try!(connection.execute("CREATE TABLE IF NOT EXISTS tags (
+                                         target TEXT NOT NULL,
+                                         id TEXT NOT NULL,
+                                         tags TEXT NOT NULL,
+                                         PRIMARY KEY (target, id)
+                                     )", &[]).map_err(backend));
+            Ok(SqliteTagStore {
+                connection: Mutex::new(connection),
+            })
+        }
+    }
+
+    impl TagStore for SqliteTagStore {
+        fn get_tags(&self, target: &Labelled) -> Result<Option<Vec<TagId>>, StoreError> {
+            let (table, id) = key(target);
+            let connection = self.connection.lock().unwrap();
+            let mut statement = try!(connection.prepare("SELECT tags FROM tags WHERE target = ?1 AND id = ?2").map_err(backend));
+            let mut rows = try!(statement.query(&[&table as &ToSql, &id]).map_err(backend));
+            let source: String = match rows.next() {
+                None => return Ok(None),
+                Some(row) => try!(try!(row.map_err(backend)).get_checked(0).map_err(backend))
+            };
+            serde_json::from_str(&source)
+                .map(Some)
+                .map_err(|err| StoreError::Corrupted(format!("{:?}", err)))
+        }
+
+        fn set_tags(&self, target: &Labelled, tags: &[TagId]) -> Result<(), StoreError> {
+            let (table, id) = key(target);
+            let source = try!(serde_json::to_string(&tags).map_err(|err| StoreError::Corrupted(format!("{:?}", err))));
+            let connection = self.connection.lock().unwrap();
+            try!(connection.execute("INSERT OR REPLACE INTO tags (target, id, tags) VALUES (?1, ?2, ?3)",
+                                    &[&table as &ToSql, &id, &source]).map_err(backend));
+            Ok(())
+        }
+
+        fn forget(&self, target: &Labelled) -> Result<(), StoreError> {
+            let (table, id) = key(target);
+            let connection = self.connection.lock().unwrap();
+            try!(connection.execute("DELETE FROM tags WHERE target = ?1 AND id = ?2", &[&table as &ToSql, &id]).map_err(backend));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//!
+//! Declaring topologies concisely, for tests.
+//!
+//! Building a `Node` by hand requires an id for the node and for each
+//! of its channels, each channel pointing back at its node. `node`
+//! derives all these ids from a single local name, so that a test can
+//! declare each node on a single line:
+//!
+//! ```ignore
+//! use foxbox_taxonomy::devices::ChannelKind::*;
+//! use foxbox_taxonomy::topology::*;
+//!
+//! let home = topology()
+//!     .with(node("kitchen-lamp").getter(OnOff).setter(OnOff).tag("kitchen"))
+//!     .with(node("thermometer").getter(ActualTemperature).label("Thermometer"));
+//!
+//! // Register the nodes with an adapter of the test.
+//! let nodes = home.register(&manager, Arc::new(TestAdapter::new())).unwrap();
+//!
+//! // Or add them to a `FakeAPI`.
+//! home.add_to(&api, &Id::new("test@foxlink.mozilla.com".to_owned()));
+//! ```
+//!
+//! Once built for adapter `test@foxlink.mozilla.com`, node
+//! `kitchen-lamp` has id `test@foxlink.mozilla.com/kitchen-lamp`, its
+//! getters are `test@foxlink.mozilla.com/kitchen-lamp/getter-0`,
+//! `.../getter-1`, etc. in the order of declaration, and its setters
+//! `.../setter-0`, etc. Use `getter_id` and `setter_id` to refer to
+//! them, or `named_getter` and `named_setter` to pick a local name.
+//!
+
+use adapter::{ namespaced, Adapter, AdapterManagerHandle, RegistrationError };
+use devices::{ AdapterId, Channel, ChannelKind, Getter, Node, NodeId, Setter };
+use fake::FakeAPI;
+use util::{ Id, TagId, ID_SEPARATOR };
+
+use std::sync::Arc;
+
+/// A node, as declared by a test. See `node`.
+#[derive(Debug, Clone)]
+pub struct NodeBuilder {
+    local: String,
+    label: Option<String>,
+    tags: Vec<TagId>,
+    getters: Vec<(String, Getter)>,
+    setters: Vec<(String, Setter)>,
+}
+
+/// Start declaring a node with local name `local`.
+pub fn node(local: &str) -> NodeBuilder {
+    NodeBuilder {
+        local: local.to_owned(),
+        label: None,
+        tags: vec![],
+        getters: vec![],
+        setters: vec![],
+    }
+}
+
+/// The local part of the id of channel `channel` of node `node`.
+fn channel_local(node: &str, channel: &str) -> String {
+    format!("{}{}{}", node, ID_SEPARATOR, channel)
+}
+
+/// Parse a tag declared by a test.
+///
+/// # Panics
+///
+/// If `tag` is not a valid tag.
+fn tag_id(tag: &str) -> TagId {
+    match TagId::new(tag) {
+        Ok(tag) => tag,
+        Err(err) => panic!("Invalid tag {:?}: {:?}", tag, err)
+    }
+}
+
+impl NodeBuilder {
+    /// Add a getter of kind `kind`, with the default mechanism.
+    pub fn getter(self, kind: ChannelKind) -> Self {
+        self.getter_with(Getter::new(kind))
+    }
+
+    /// Add a getter with a specific mechanism, e.g. polled.
+    pub fn getter_with(self, getter: Getter) -> Self {
+        let local = format!("getter-{}", self.getters.len());
+        self.named_getter(&local, getter)
+    }
+
+    /// Add a getter with local name `local`, instead of `getter-<n>`.
+    pub fn named_getter(mut self, local: &str, getter: Getter) -> Self {
+        self.getters.push((local.to_owned(), getter));
+        self
+    }
+
+    /// Add a setter of kind `kind`, with the default mechanism.
+    pub fn setter(self, kind: ChannelKind) -> Self {
+        self.setter_with(Setter::new(kind))
+    }
+
+    /// Add a setter with a specific mechanism.
+    pub fn setter_with(self, setter: Setter) -> Self {
+        let local = format!("setter-{}", self.setters.len());
+        self.named_setter(&local, setter)
+    }
+
+    /// Add a setter with local name `local`, instead of `setter-<n>`.
+    pub fn named_setter(mut self, local: &str, setter: Setter) -> Self {
+        self.setters.push((local.to_owned(), setter));
+        self
+    }
+
+    /// Tag the node.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` is not a valid tag.
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tags.push(tag_id(tag));
+        self
+    }
+
+    /// Set the label of the node.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    /// The id of the node, once built for `adapter`.
+    pub fn id(&self, adapter: &Id<AdapterId>) -> Id<NodeId> {
+        namespaced(adapter, &self.local)
+    }
+
+    /// Build the node, with the ids of `adapter`.
+    pub fn build(&self, adapter: &Id<AdapterId>) -> Node {
+        let id = self.id(adapter);
+        let mut node = Node::new(id.clone()).with_tags(self.tags.clone());
+        if let Some(ref label) = self.label {
+            node = node.with_label(label.clone());
+        }
+        for &(ref local, ref getter) in &self.getters {
+            let channel_id = namespaced(adapter, &channel_local(&self.local, local));
+            node = node.with_getter(Channel::new(channel_id, id.clone(), getter.clone()));
+        }
+        for &(ref local, ref setter) in &self.setters {
+            let channel_id = namespaced(adapter, &channel_local(&self.local, local));
+            node = node.with_setter(Channel::new(channel_id, id.clone(), setter.clone()));
+        }
+        node
+    }
+}
+
+/// The id of getter `index` of node `local`, as declared with
+/// `NodeBuilder::getter` or `NodeBuilder::getter_with`.
+pub fn getter_id(adapter: &Id<AdapterId>, local: &str, index: usize) -> Id<Getter> {
+    namespaced(adapter, &channel_local(local, &format!("getter-{}", index)))
+}
+
+/// The id of setter `index` of node `local`, as declared with
+/// `NodeBuilder::setter` or `NodeBuilder::setter_with`.
+pub fn setter_id(adapter: &Id<AdapterId>, local: &str, index: usize) -> Id<Setter> {
+    namespaced(adapter, &channel_local(local, &format!("setter-{}", index)))
+}
+
+/// A set of nodes, as declared by a test. See `topology`.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    pub nodes: Vec<NodeBuilder>,
+}
+
+/// Start declaring a topology, without any node.
+pub fn topology() -> Topology {
+    Topology::default()
+}
+
+impl Topology {
+    /// Add a node.
+    pub fn with(mut self, node: NodeBuilder) -> Self {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Build the nodes, with the ids of `adapter`.
+    pub fn build(&self, adapter: &Id<AdapterId>) -> Vec<Node> {
+        self.nodes.iter().map(|node| node.build(adapter)).collect()
+    }
+
+    /// Register `adapter` with `handle`, along with the nodes, built
+    /// with the ids of `adapter`. Returns the nodes registered.
+    pub fn register(&self, handle: &AdapterManagerHandle, adapter: Arc<Adapter>) -> Result<Vec<Node>, RegistrationError> {
+        let nodes = self.build(&adapter.id());
+        try!(handle.add_adapter_with_nodes(adapter, nodes.clone()));
+        Ok(nodes)
+    }
+
+    /// Add the nodes to `api`, built with the ids of `adapter`.
+    pub fn add_to(&self, api: &FakeAPI, adapter: &Id<AdapterId>) {
+        for node in self.build(adapter) {
+            api.add_node(node);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//!
+//! A strongly-typed layer over the API, for Rust clients.
+//!
+//! The API exchanges `Value`s, so that a single method serves channels
+//! of all kinds, but leaves clients to match on the variant they expect
+//! each time they receive a value. `TypedAPI` lets clients state the
+//! Rust type they expect instead: it checks that all the channels
+//! matching a selector carry values of this type before fetching,
+//! sending or watching, then converts the values.
+//!
+//! ```ignore
+//! use foxbox_taxonomy::typed::TypedAPI;
+//! use foxbox_taxonomy::values::Temperature;
+//!
+//! let api = TypedAPI::new(manager, Caller::system());
+//! let lamps = api.get::<bool>(&GetterSelector::new().with_kind(ChannelKind::OnOff)).unwrap();
+//! api.set(&SetterSelector::new().with_kind(ChannelKind::Thermostat), Temperature::C(21.0)).unwrap();
+//! let guard = api.watch::<Temperature, _>(&GetterSelector::new().with_kind(ChannelKind::ActualTemperature),
+//!     |getter, temperature| println!("{}: {:?}", getter, temperature)).unwrap();
+//! ```
+//!
+//! # Extension kinds
+//!
+//! Adapter crates declare their extension kinds with `declare_kind!`,
+//! which defines a Rust type for the values of the kind, usable with
+//! `TypedAPI`, along with the `ChannelKind` and its `KindDescription`,
+//! to be registered with `AdapterManagerHandle::add_extension_kind`:
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate foxbox_taxonomy;
+//!
+//! declare_kind! {
+//!     /// The humidity of the ground, in percents.
+//!     pub struct GroundHumidity(f64) {
+//!         vendor: "foxlink@mozilla.com",
+//!         adapter: "zwave",
+//!         description: "The humidity of the ground, in percents.",
+//!         getter: true,
+//!         setter: false,
+//!     }
+//! }
+//!
+//! handle.add_extension_kind(&adapter, GroundHumidity::description()).unwrap();
+//! let getter = Getter::new(GroundHumidity::kind());
+//! let value = GroundHumidity(42.).into_value();
+//! ```
+//!
+
+use api::{ Acknowledgement, Error, WatchEvent, WatchOptions, API };
+use arbitration::Priority;
+use auth::Caller;
+use compression;
+use devices::{ Getter, Setter };
+use selector::{ GetterSelector, SetterSelector };
+use values::{ Color, Temperature, TimeStamp, Type, ValDuration, Value };
+use util::Id;
+
+use std::sync::Arc;
+
+/// A Rust type representing the values of a `Type`.
+pub trait Typed: Sized + Send + 'static {
+    /// The type of the values represented by `Self`.
+    fn typ() -> Type;
+
+    fn into_value(self) -> Value;
+
+    /// Convert a value, or produce `None` if it does not have type
+    /// `Self::typ()`. `value` is never a `Value::Compressed`.
+    fn from_value(value: Value) -> Option<Self>;
+}
+
+macro_rules! typed {
+    ($rust:ty, $typ:ident, $value:ident => $into:expr, $pattern:pat => $from:expr) => (
+        impl Typed for $rust {
+            fn typ() -> Type {
+                Type::$typ
+            }
+            fn into_value(self) -> Value {
+                let $value = self;
+                $into
+            }
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    $pattern => Some($from),
+                    _ => None
+                }
+            }
+        }
+    )
+}
+
+typed!((), Unit, _unit => Value::Unit, Value::Unit => ());
+typed!(bool, Bool, value => Value::Bool(value), Value::Bool(value) => value);
+typed!(u32, Count, value => Value::Count(value), Value::Count(value) => value);
+typed!(ValDuration, Duration, value => Value::Duration(value), Value::Duration(value) => value);
+typed!(TimeStamp, TimeStamp, value => Value::TimeStamp(value), Value::TimeStamp(value) => value);
+typed!(Temperature, Temperature, value => Value::Temperature(value), Value::Temperature(value) => value);
+typed!(Color, Color, value => Value::Color(value), Value::Color(value) => value);
+typed!(String, String, value => Value::String(Arc::new(value)), Value::String(value) => (*value).clone());
+
+/// Convert a value received from the API, decompressing it if needed.
+fn convert<T>(value: Value) -> Result<T, Error> where T: Typed {
+    let value = try!(compression::decompressed(value).map_err(Error::Compression));
+    T::from_value(value).ok_or(Error::TypeError)
+}
+
+/// Check that all the `types` of channels are the type of `T`.
+fn check<T, I>(mut types: I) -> Result<(), Error> where T: Typed, I: Iterator<Item=Type> {
+    if types.all(|typ| typ == T::typ()) {
+        Ok(())
+    } else {
+        Err(Error::TypeError)
+    }
+}
+
+/// A strongly-typed view of an implementation of the API, on behalf
+/// of a single caller.
+pub struct TypedAPI<A> where A: API {
+    api: A,
+    caller: Caller,
+}
+
+impl<A> TypedAPI<A> where A: API {
+    /// Call `api` on behalf of `caller`.
+    pub fn new(api: A, caller: Caller) -> Self {
+        TypedAPI {
+            api: api,
+            caller: caller,
+        }
+    }
+
+    /// The underlying API, for the operations that are not typed.
+    pub fn api(&self) -> &A {
+        &self.api
+    }
+
+    fn check_getters<T>(&self, selectors: &Vec<GetterSelector>) -> Result<(), Error> where T: Typed {
+        let getters = self.api.get_getter_channels(&self.caller, selectors);
+        check::<T, _>(getters.iter().map(|channel| channel.mechanism.kind.get_type()))
+    }
+
+    fn check_setters<T>(&self, selectors: &Vec<SetterSelector>) -> Result<(), Error> where T: Typed {
+        let setters = self.api.get_setter_channels(&self.caller, selectors);
+        check::<T, _>(setters.iter().map(|channel| channel.mechanism.kind.get_type()))
+    }
+
+    /// Fetch the values of the getters matching `selector`, as
+    /// `API::get_channel_value`.
+    ///
+    /// # Errors
+    ///
+    /// `Error::TypeError` if any of the getters matching `selector` does
+    /// not carry values of type `T`, in which case nothing is fetched.
+    pub fn get<T>(&self, selector: &GetterSelector) -> Result<Vec<(Id<Getter>, Result<T, Error>)>, Error> where T: Typed {
+        let selectors = vec![selector.clone()];
+        try!(self.check_getters::<T>(&selectors));
+        Ok(self.api.get_channel_value(&self.caller, &selectors).into_iter()
+            .map(|(id, result)| (id, result.and_then(|fetched| convert(fetched.value))))
+            .collect())
+    }
+
+    /// Send `value` to the setters matching `selector`, as
+    /// `API::put_channel_value`, with the default priority.
+    ///
+    /// # Errors
+    ///
+    /// `Error::TypeError` if any of the setters matching `selector` does
+    /// not carry values of type `T`, in which case nothing is sent.
+    pub fn set<T>(&self, selector: &SetterSelector, value: T) -> Result<Vec<(Id<Setter>, Result<Acknowledgement, Error>)>, Error> where T: Typed {
+        self.set_with_priority(selector, value, Priority::default())
+    }
+
+    /// Send `value` to the setters matching `selector`, with priority
+    /// `priority`. See `TypedAPI::set`.
+    pub fn set_with_priority<T>(&self, selector: &SetterSelector, value: T, priority: Priority)
+        -> Result<Vec<(Id<Setter>, Result<Acknowledgement, Error>)>, Error> where T: Typed {
+        let selectors = vec![selector.clone()];
+        try!(self.check_setters::<T>(&selectors));
+        Ok(self.api.put_channel_value(&self.caller, &selectors, value.into_value(), priority))
+    }
+
+    /// Watch the values of the getters matching `selector`, as
+    /// `API::register_channel_watch`. `cb` receives each value, or the
+    /// error reported by the getter. Events other than values and
+    /// errors are not delivered.
+    ///
+    /// Getters added after the watch has been registered are not
+    /// checked: their values of another type are reported to `cb` as
+    /// `Error::TypeError`.
+    ///
+    /// # Errors
+    ///
+    /// `Error::TypeError` if any of the getters matching `selector` does
+    /// not carry values of type `T`, in which case nothing is watched.
+    pub fn watch<T, F>(&self, selector: &GetterSelector, cb: F) -> Result<A::WatchGuard, Error>
+        where T: Typed, F: Fn(Id<Getter>, Result<T, Error>) + Send + 'static {
+        try!(self.check_getters::<T>(&vec![selector.clone()]));
+        let options = vec![WatchOptions::new()
+            .with_getters(selector.clone())
+            .with_watch_values(true)];
+        self.api.register_channel_watch(&self.caller, options, Box::new(move |event| {
+            match event {
+                WatchEvent::Value { from, value } => cb(from, convert(value)),
+                WatchEvent::Error { from, error } => cb(from, Err(error)),
+                _ => {}
+            }
+        }))
+    }
+}
+
+/// Declare an extension kind, along with a Rust type for its values.
+///
+/// `declare_kind! { pub struct Name(Inner) { ... } }` defines a tuple
+/// struct `Name(pub Inner)`, implementing `Typed`, with methods:
+///
+/// - `Name::kind()`, the `ChannelKind::Extension` of vendor `vendor`,
+///   adapter `adapter` and kind `"Name"`;
+/// - `Name::description()`, its `KindDescription`.
+///
+/// If `Inner` is `f64`, values are carried as `Value::ExtNumeric`,
+/// tagged with the vendor, adapter and kind, and values of other
+/// extension kinds are rejected. Otherwise, `Inner` must implement
+/// `Typed`, and values are carried as values of `Inner`.
+///
+/// Extension kinds report a state, see `ChannelKind::semantics`.
+///
+/// See the documentation of module `typed` for an example.
+#[macro_export]
+macro_rules! declare_kind {
+    ($(#[$attr:meta])* pub struct $name:ident(f64) {
+        vendor: $vendor:expr,
+        adapter: $adapter:expr,
+        description: $description:expr,
+        getter: $getter:expr,
+        setter: $setter:expr $(,)*
+    }) => (
+        $(#[$attr])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name(pub f64);
+
+        impl $crate::typed::Typed for $name {
+            fn typ() -> $crate::values::Type {
+                $crate::values::Type::ExtNumeric
+            }
+            fn into_value(self) -> $crate::values::Value {
+                $crate::values::Value::ExtNumeric($crate::values::ExtNumeric {
+                    value: self.0,
+                    vendor: $vendor.to_owned(),
+                    adapter: $adapter.to_owned(),
+                    kind: stringify!($name).to_owned(),
+                })
+            }
+            fn from_value(value: $crate::values::Value) -> Option<Self> {
+                match value {
+                    $crate::values::Value::ExtNumeric(ref ext) if ext.vendor == $vendor &&
+                        ext.adapter == $adapter && ext.kind == stringify!($name) => Some($name(ext.value)),
+                    _ => None
+                }
+            }
+        }
+
+        declare_kind!(@kind $name, $vendor, $adapter, $description, $getter, $setter);
+    );
+
+    ($(#[$attr:meta])* pub struct $name:ident($inner:ty) {
+        vendor: $vendor:expr,
+        adapter: $adapter:expr,
+        description: $description:expr,
+        getter: $getter:expr,
+        setter: $setter:expr $(,)*
+    }) => (
+        $(#[$attr])*
+        #[derive(Debug, Clone)]
+        pub struct $name(pub $inner);
+
+        impl $crate::typed::Typed for $name {
+            fn typ() -> $crate::values::Type {
+                <$inner as $crate::typed::Typed>::typ()
+            }
+            fn into_value(self) -> $crate::values::Value {
+                $crate::typed::Typed::into_value(self.0)
+            }
+            fn from_value(value: $crate::values::Value) -> Option<Self> {
+                <$inner as $crate::typed::Typed>::from_value(value).map($name)
+            }
+        }
+
+        declare_kind!(@kind $name, $vendor, $adapter, $description, $getter, $setter);
+    );
+
+    (@kind $name:ident, $vendor:expr, $adapter:expr, $description:expr, $getter:expr, $setter:expr) => (
+        impl $name {
+            /// The kind of the channels carrying these values.
+            #[allow(dead_code)]
+            pub fn kind() -> $crate::devices::ChannelKind {
+                $crate::devices::ChannelKind::Extension {
+                    vendor: $vendor.to_owned(),
+                    adapter: $adapter.to_owned(),
+                    kind: stringify!($name).to_owned(),
+                    typ: <$name as $crate::typed::Typed>::typ(),
+                }
+            }
+
+            /// The description of the kind, as registered by adapters.
+            #[allow(dead_code)]
+            pub fn description() -> $crate::devices::KindDescription {
+                $crate::devices::KindDescription {
+                    kind: $name::kind(),
+                    typ: <$name as $crate::typed::Typed>::typ(),
+                    semantics: $crate::devices::Semantics::State,
+                    description: $description.to_owned(),
+                    getter: $getter,
+                    setter: $setter,
+                }
+            }
+        }
+    );
+}
@@ -1,13 +1,47 @@
 use std::marker::PhantomData;
 
+#[cfg(feature = "serde")]
 use serde::ser::{Serialize, Serializer};
-use serde::de::{Deserialize, Deserializer};
+#[cfg(feature = "serde")]
+use serde::de::{Deserialize, Deserializer, Error as DeError, Visitor};
 
+use devices::AdapterId;
+
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use std::borrow::Cow;
 use std::cmp::PartialEq;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+
+#[cfg(feature = "std")]
+use std::borrow::Borrow;
+#[cfg(feature = "std")]
+use std::cmp;
+#[cfg(feature = "std")]
+use std::collections::HashSet;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::hash::BuildHasher;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, Once, ONCE_INIT};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(all(feature = "std", target_arch = "wasm32"))]
+use wasm::random_u64;
 
 /// A marker for a request that a expects a specific value.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Exactly<T> {
     /// No constraint.
     Empty,
@@ -73,12 +107,14 @@ impl<T> Phantom<T> {
     }
 }
 
+#[cfg(feature = "serde")]
 impl<T> Serialize for Phantom<T> {
     fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
         where S: Serializer {
         serializer.visit_unit()
     }
 }
+#[cfg(feature = "serde")]
 impl<T> Deserialize for Phantom<T> {
     fn deserialize<D>(_: &mut D) -> Result<Self, D::Error>
         where D: Deserializer {
@@ -89,49 +125,623 @@ impl<T> Deserialize for Phantom<T> {
     }
 }
 
+/// The maximal length of an id, in bytes.
+pub const MAX_ID_LENGTH: usize = 1024;
+
+/// The separator between the parts of composite ids.
+pub const ID_SEPARATOR: char = '/';
+
+/// A string that is not a valid `Id`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IdError {
+    /// The string is empty.
+    Empty,
+
+    /// The string is longer than `MAX_ID_LENGTH` bytes.
+    TooLong(usize),
+
+    /// The string contains whitespace or a control character.
+    InvalidChar(char),
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IdError::Empty => write!(f, "Empty id"),
+            IdError::TooLong(len) => write!(f, "Id is {} bytes long, at most {} expected", len, MAX_ID_LENGTH),
+            IdError::InvalidChar(c) => write!(f, "Invalid character {:?} in id", c),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for IdError {
+    fn description(&self) -> &str {
+        match *self {
+            IdError::Empty => "Empty id",
+            IdError::TooLong(_) => "Id too long",
+            IdError::InvalidChar(_) => "Invalid character in id",
+        }
+    }
+}
+
+/// A counter ensuring that ids generated during the same nanosecond
+/// differ.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+static RANDOM_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// 64 random bits, derived from the random keys of the standard library
+/// hasher. Not suitable for cryptographic purposes.
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    RANDOM_COUNTER.fetch_add(1, Ordering::Relaxed).hash(&mut hasher);
+    if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        now.as_secs().hash(&mut hasher);
+        now.subsec_nanos().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// An interned string, which may be looked up by `&str`.
+#[cfg(feature = "std")]
+#[derive(PartialEq, Eq, Hash)]
+struct Interned(Arc<String>);
+
+#[cfg(feature = "std")]
+impl Borrow<str> for Interned {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The strings of all the ids currently alive, so that equal ids share
+/// the same string.
+#[cfg(feature = "std")]
+struct Interner {
+    strings: HashSet<Interned>,
+
+    /// The number of strings past which strings that are not used by
+    /// any id anymore are released.
+    threshold: usize,
+}
+
+#[cfg(feature = "std")]
+impl Interner {
+    /// Intern a string. A borrowed string is only copied if it has not
+    /// been interned yet.
+    fn intern(&mut self, string: Cow<str>) -> Arc<String> {
+        if let Some(interned) = self.strings.get(&*string) {
+            return interned.0.clone();
+        }
+        if self.strings.len() >= self.threshold {
+            // Strings only referenced by the interner are not used
+            // anymore. As the lock is held, no id can be created from
+            // them in the meantime.
+            self.strings.retain(|interned| Arc::strong_count(&interned.0) > 1);
+            self.threshold = cmp::max(MIN_INTERNER_THRESHOLD, 2 * self.strings.len());
+        }
+        let interned = Arc::new(string.into_owned());
+        self.strings.insert(Interned(interned.clone()));
+        interned
+    }
+}
+
+#[cfg(feature = "std")]
+const MIN_INTERNER_THRESHOLD: usize = 1024;
+
+#[cfg(feature = "std")]
+fn interner() -> &'static Mutex<Interner> {
+    static INIT: Once = ONCE_INIT;
+    static mut INTERNER: *const Mutex<Interner> = 0 as *const Mutex<Interner>;
+    unsafe {
+        INIT.call_once(|| {
+            INTERNER = Box::into_raw(Box::new(Mutex::new(Interner {
+                strings: HashSet::new(),
+                threshold: MIN_INTERNER_THRESHOLD,
+            })));
+        });
+        &*INTERNER
+    }
+}
+
+/// A set of entities of kind `T`, known by id, against which
+/// conversions with `Id::try_cast` are checked.
+pub trait IdRegistry<T> {
+    fn is_registered(&self, id: &Id<T>) -> bool;
+}
+
 /// A unique id for values of a given kind.
+///
+/// Ids are opaque strings, which must be non-empty, at most
+/// `MAX_ID_LENGTH` bytes long, and contain neither whitespace nor
+/// control characters. Ids received from outside the FoxBox should be
+/// parsed with `Id::try_from_str`.
+///
+/// Ids are interned: all the ids with the same string share a single
+/// copy of the string, so cloning an id does not allocate, while
+/// comparing or hashing ids only involves a pointer. Likewise, parsing
+/// or deserializing an id that is already known does not allocate.
+/// Without feature `std`, ids are not interned, and are compared and
+/// hashed by string.
+///
+/// # Composite ids
+///
+/// The ids of nodes and channels are composite: they are made of the
+/// id of the adapter that registers them, which may not contain
+/// `ID_SEPARATOR`, followed by `ID_SEPARATOR` and a local part chosen
+/// by the adapter, see `adapter::namespaced`. By convention, the local
+/// part of the id of a channel is itself made of a device part, the
+/// local part of the id of its node, and a channel part, e.g.
+/// `simulator@foxlink.mozilla.com/clock/time`. The parts are available
+/// with `Id::adapter`, `Id::local_part`, `Id::device_part` and
+/// `Id::channel_part`.
 #[derive(Debug, Clone)]
 pub struct Id<T> {
-    id: String,
+    id: Arc<String>,
 
     phantom: Phantom<T>
 }
 impl<T> Id<T> {
+    /// Create an id from a string known to be valid, e.g. a constant.
+    /// The string is not checked, see `Id::try_from_str`.
     pub fn new(id: String) -> Self {
+        Id::interned(Cow::Owned(id))
+    }
+
+    #[cfg(feature = "std")]
+    fn interned(id: Cow<str>) -> Self {
         Id {
-            id: id,
+            id: interner().lock().unwrap().intern(id),
             phantom: Phantom::new()
         }
     }
 
+    #[cfg(not(feature = "std"))]
+    fn interned(id: Cow<str>) -> Self {
+        Id {
+            id: Arc::new(id.into_owned()),
+            phantom: Phantom::new()
+        }
+    }
+
+    /// Create an id from a string, checking that it is valid.
+    pub fn try_from_str(id: &str) -> Result<Self, IdError> {
+        if id.is_empty() {
+            return Err(IdError::Empty);
+        }
+        if id.len() > MAX_ID_LENGTH {
+            return Err(IdError::TooLong(id.len()));
+        }
+        if let Some(c) = id.chars().find(|c| c.is_whitespace() || c.is_control()) {
+            return Err(IdError::InvalidChar(c));
+        }
+        Ok(Id::interned(Cow::Borrowed(id)))
+    }
+
+    /// Generate a random id, formatted as a version 4 UUID, e.g.
+    /// "0f8fad5b-d9cb-469f-a165-70867728950e".
+    #[cfg(feature = "std")]
+    pub fn new_random() -> Self {
+        let (high, low) = (random_u64(), random_u64());
+        // Set the version (4) and the variant (RFC 4122).
+        let high = (high & 0xffff_ffff_ffff_0fff) | 0x0000_0000_0000_4000;
+        let low = (low & 0x3fff_ffff_ffff_ffff) | 0x8000_0000_0000_0000;
+        Id::new(format!("{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                        high >> 32, (high >> 16) & 0xffff, high & 0xffff,
+                        low >> 48, low & 0xffff_ffff_ffff))
+    }
+
+    /// Reinterpret an id known under another kind, e.g. the id of a
+    /// node that is also used as the id of one of its channels. The
+    /// conversion is not checked, see `Id::try_cast`.
+    pub fn from_unchecked<U>(id: Id<U>) -> Self {
+        Id {
+            id: id.id,
+            phantom: Phantom::new()
+        }
+    }
+
+    /// Reinterpret this id as an id of kind `U`, if `registry` knows
+    /// an entity of kind `U` with this id, e.g.
+    /// `id.try_cast::<Setter>(&manager)`.
+    pub fn try_cast<U>(&self, registry: &IdRegistry<U>) -> Option<Id<U>> {
+        let id = Id {
+            id: self.id.clone(),
+            phantom: Phantom::new()
+        };
+        if registry.is_registered(&id) {
+            Some(id)
+        } else {
+            None
+        }
+    }
+
     pub fn as_string(&self) -> &String {
         &self.id
     }
+
+    /// The id of the adapter that registered this node or channel, or
+    /// `None` if this is not a composite id.
+    pub fn adapter(&self) -> Option<Id<AdapterId>> {
+        self.adapter_part().map(|adapter| Id::new(adapter.to_owned()))
+    }
+
+    /// As `Id::adapter`, without creating an id.
+    pub fn adapter_part(&self) -> Option<&str> {
+        self.split().map(|(adapter, _)| adapter)
+    }
+
+    /// Everything after the id of the adapter, e.g. `clock/time`, or
+    /// `None` if this is not a composite id.
+    pub fn local_part(&self) -> Option<&str> {
+        self.split().map(|(_, local)| local)
+    }
+
+    /// The first segment of the local part, e.g. `clock`.
+    pub fn device_part(&self) -> Option<&str> {
+        self.local_part().map(|local| local.split(ID_SEPARATOR).next().unwrap_or(local))
+    }
+
+    /// The rest of the local part, after the device part, e.g. `time`,
+    /// or `None` if the local part has a single segment.
+    pub fn channel_part(&self) -> Option<&str> {
+        self.local_part()
+            .and_then(|local| local.find(ID_SEPARATOR).map(|index| &local[index + 1..]))
+            .and_then(|channel| if channel.is_empty() { None } else { Some(channel) })
+    }
+
+    fn split(&self) -> Option<(&str, &str)> {
+        let index = match self.id.find(ID_SEPARATOR) {
+            None => return None,
+            Some(index) => index
+        };
+        let (adapter, local) = (&self.id[..index], &self.id[index + 1..]);
+        if adapter.is_empty() || local.is_empty() {
+            None
+        } else {
+            Some((adapter, local))
+        }
+    }
+}
+impl<T> fmt::Display for Id<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+impl<T> FromStr for Id<T> {
+    type Err = IdError;
+    fn from_str(s: &str) -> Result<Self, IdError> {
+        Id::try_from_str(s)
+    }
+}
+impl<T> AsRef<str> for Id<T> {
+    fn as_ref(&self) -> &str {
+        &self.id
+    }
 }
 impl<T> PartialEq for Id<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.id.eq(&other.id)
+        if cfg!(feature = "std") {
+            // Equal ids share the same string.
+            Arc::ptr_eq(&self.id, &other.id)
+        } else {
+            self.id == other.id
+        }
     }
 }
 impl<T> Eq for Id<T> {
 }
 impl<T> Hash for Id<T> {
     fn hash<H>(&self, state: &mut H) where H: Hasher {
-        self.id.hash(state)
+        if cfg!(feature = "std") {
+            (&*self.id as *const String as usize).hash(state)
+        } else {
+            self.id.hash(state)
+        }
     }
 }
+#[cfg(feature = "serde")]
 impl<T> Serialize for Id<T> {
     fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
         where S: Serializer {
         serializer.visit_str(&self.id)
     }
 }
+#[cfg(feature = "serde")]
 impl<T> Deserialize for Id<T> {
     fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
         where D: Deserializer {
-        Ok(Id {
-            id: try!(String::deserialize(deserializer)),
-            phantom: Phantom::new()
+        // Ids received from outside the FoxBox are checked, and ids that
+        // are already known are not copied.
+        struct IdVisitor<T>(PhantomData<T>);
+        impl<T> Visitor for IdVisitor<T> {
+            type Value = Id<T>;
+            fn visit_str<E>(&mut self, value: &str) -> Result<Id<T>, E> where E: DeError {
+                Id::try_from_str(value).map_err(|err| E::syntax(&format!("{}", err)))
+            }
+            fn visit_string<E>(&mut self, value: String) -> Result<Id<T>, E> where E: DeError {
+                self.visit_str(&value)
+            }
+        }
+        deserializer.visit_string(IdVisitor(PhantomData))
+    }
+}
+
+/// The maximal length of a `TagId`, in characters, once normalized.
+pub const MAX_TAG_LENGTH: usize = 256;
+
+/// A string that is not a valid `TagId`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TagError {
+    /// The string is empty, or only contains whitespace.
+    Empty,
+
+    /// The normalized string is longer than `MAX_TAG_LENGTH` characters.
+    TooLong(usize),
+
+    /// The string contains a control character.
+    InvalidChar(char),
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TagError::Empty => write!(f, "Empty tag"),
+            TagError::TooLong(len) => write!(f, "Tag is {} characters long, at most {} expected", len, MAX_TAG_LENGTH),
+            TagError::InvalidChar(c) => write!(f, "Invalid character {:?} in tag", c),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for TagError {
+    fn description(&self) -> &str {
+        match *self {
+            TagError::Empty => "Empty tag",
+            TagError::TooLong(_) => "Tag too long",
+            TagError::InvalidChar(_) => "Invalid character in tag",
+        }
+    }
+}
+
+/// A tag, as attached to nodes and channels and used by selectors.
+///
+/// Tags are typed by users, so they are normalized when they are
+/// created: leading and trailing whitespace is removed, inner runs of
+/// whitespace are replaced with a single space and letters are
+/// lowercased, following Unicode case mappings. Consequently, "Kitchen",
+/// "kitchen " and "KITCHEN" are the same tag. Note that canonical
+/// composition is not performed, so a precomposed "é" and an "e"
+/// followed by a combining accent remain distinct.
+///
+/// Normalized tags must be non-empty, at most `MAX_TAG_LENGTH`
+/// characters long and may not contain control characters. Tags are
+/// normalized and checked when they are deserialized, so tags received
+/// through the REST API or read from storage are always valid.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TagId {
+    tag: String,
+}
+impl TagId {
+    /// Normalize a string into a tag, checking that it is valid.
+    pub fn new(tag: &str) -> Result<Self, TagError> {
+        TagId::checked(tag).map(|tag| TagId {
+            tag: tag.into_owned()
         })
     }
+
+    /// As `TagId::new`, reusing `tag` if it is already normalized.
+    #[cfg(feature = "serde")]
+    fn from_string(tag: String) -> Result<Self, TagError> {
+        let normalized = match try!(TagId::checked(&tag)) {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(normalized) => Some(normalized)
+        };
+        Ok(TagId {
+            tag: normalized.unwrap_or(tag)
+        })
+    }
+
+    /// Normalize and check a string. The string is only copied if it
+    /// is not normalized yet, which is rarely the case of tags sent by
+    /// applications, as they typically send back tags they have
+    /// received.
+    fn checked(tag: &str) -> Result<Cow<str>, TagError> {
+        if let Some(c) = tag.chars().find(|c| c.is_control() && !c.is_whitespace()) {
+            return Err(TagError::InvalidChar(c));
+        }
+        let is_normalized = !tag.starts_with(' ') && !tag.ends_with(' ') && !tag.contains("  ")
+            && tag.chars().all(|c| (c == ' ' || !c.is_whitespace()) && c.to_lowercase().eq(Some(c)));
+        let normalized = if is_normalized {
+            Cow::Borrowed(tag)
+        } else {
+            Cow::Owned(tag.split_whitespace()
+                .map(|word| word.to_lowercase())
+                .collect::<Vec<_>>()
+                .join(" "))
+        };
+        if normalized.is_empty() {
+            return Err(TagError::Empty);
+        }
+        let len = normalized.chars().count();
+        if len > MAX_TAG_LENGTH {
+            return Err(TagError::TooLong(len));
+        }
+        Ok(normalized)
+    }
+
+    /// The normalized string.
+    pub fn as_str(&self) -> &str {
+        &self.tag
+    }
+}
+impl fmt::Display for TagId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.tag)
+    }
+}
+impl FromStr for TagId {
+    type Err = TagError;
+    fn from_str(s: &str) -> Result<Self, TagError> {
+        TagId::new(s)
+    }
+}
+impl AsRef<str> for TagId {
+    fn as_ref(&self) -> &str {
+        &self.tag
+    }
+}
+#[cfg(feature = "serde")]
+impl Serialize for TagId {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer {
+        serializer.visit_str(&self.tag)
+    }
+}
+#[cfg(feature = "serde")]
+impl Deserialize for TagId {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer {
+        struct TagVisitor;
+        impl Visitor for TagVisitor {
+            type Value = TagId;
+            fn visit_str<E>(&mut self, value: &str) -> Result<TagId, E> where E: DeError {
+                TagId::new(value).map_err(|err| E::syntax(&format!("{}", err)))
+            }
+            fn visit_string<E>(&mut self, value: String) -> Result<TagId, E> where E: DeError {
+                TagId::from_string(value).map_err(|err| E::syntax(&format!("{}", err)))
+            }
+        }
+        deserializer.visit_string(TagVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Id, IdError, MAX_ID_LENGTH, MAX_TAG_LENGTH, TagError, TagId };
+    use devices::{ Getter, NodeId };
+
+    #[cfg(feature = "std")]
+    use super::interner;
+    #[cfg(feature = "std")]
+    use serde_json;
+
+    #[cfg(not(feature = "std"))]
+    use std::prelude::*;
+
+    use std::iter;
+    use std::str::FromStr;
+    #[cfg(feature = "std")]
+    use std::sync::Arc;
+
+    #[test]
+    fn test_valid_ids() {
+        let id: Id<Getter> = Id::try_from_str("simulator@foxlink.mozilla.com/clock/time").unwrap();
+        assert_eq!(id.to_string(), "simulator@foxlink.mozilla.com/clock/time");
+        assert_eq!(id.adapter_part(), Some("simulator@foxlink.mozilla.com"));
+        assert_eq!(id.local_part(), Some("clock/time"));
+        assert_eq!(id.device_part(), Some("clock"));
+        assert_eq!(id.channel_part(), Some("time"));
+
+        // Ids are opaque, so any other character is accepted.
+        for source in &["x", "cuisine/éclairage", "a\"b", "0f8fad5b-d9cb-469f-a165-70867728950e"] {
+            let id: Id<NodeId> = Id::from_str(source).unwrap();
+            assert_eq!(id.as_ref(), *source);
+        }
+
+        let longest: String = iter::repeat('a').take(MAX_ID_LENGTH).collect();
+        assert!(Id::<NodeId>::try_from_str(&longest).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ids() {
+        assert_eq!(Id::<NodeId>::try_from_str("").unwrap_err(), IdError::Empty);
+        for &(source, c) in &[("kitchen light", ' '), (" light", ' '), ("light\n", '\n'), ("a\tb", '\t'),
+                              ("a\u{a0}b", '\u{a0}'), ("a\u{7f}", '\u{7f}'), ("\u{0}", '\u{0}')] {
+            assert_eq!(source.parse::<Id<NodeId>>().unwrap_err(), IdError::InvalidChar(c), "{:?}", source);
+        }
+
+        // The length is measured in bytes.
+        let long: String = iter::repeat('a').take(MAX_ID_LENGTH + 1).collect();
+        assert_eq!(Id::<NodeId>::try_from_str(&long).unwrap_err(), IdError::TooLong(MAX_ID_LENGTH + 1));
+        let long: String = iter::repeat('é').take(MAX_ID_LENGTH / 2 + 1).collect();
+        assert_eq!(Id::<NodeId>::try_from_str(&long).unwrap_err(), IdError::TooLong(MAX_ID_LENGTH + 2));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_interning() {
+        // Equal ids share their string, however they have been created.
+        let created: Id<NodeId> = Id::new("util-test/interned".to_owned());
+        let parsed: Id<NodeId> = Id::try_from_str("util-test/interned").unwrap();
+        let cast: Id<Getter> = Id::from_unchecked(parsed.clone());
+        assert_eq!(created, parsed);
+        assert!(Arc::ptr_eq(&created.id, &parsed.id));
+        assert!(Arc::ptr_eq(&created.id, &cast.id));
+        assert!(created != Id::new("util-test/other".to_owned()));
+
+        // Strings that are not used by any id anymore are released,
+        // while strings still in use are kept.
+        let count = 3000;
+        for i in 0..count {
+            let _: Id<NodeId> = Id::new(format!("util-test/released/{}", i));
+        }
+        let released = interner().lock().unwrap().strings.iter()
+            .filter(|interned| interned.0.starts_with("util-test/released/"))
+            .count();
+        assert!(released < count, "{}", released);
+        let again: Id<NodeId> = Id::new("util-test/interned".to_owned());
+        assert!(Arc::ptr_eq(&created.id, &again.id));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_serde() {
+        let id: Id<NodeId> = Id::new("util-test/serde".to_owned());
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, "\"util-test/serde\"");
+        let deserialized: Id<NodeId> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, id);
+
+        // Ids received from outside the FoxBox are checked.
+        for json in &["\"\"", "\"util-test serde\"", "\"util-test\\nserde\""] {
+            assert!(serde_json::from_str::<Id<NodeId>>(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_tag_normalization() {
+        for &(source, normalized) in &[("kitchen", "kitchen"), ("Kitchen", "kitchen"), ("KITCHEN", "kitchen"),
+                                       ("  kitchen \t", "kitchen"), ("living   room", "living room"),
+                                       ("Living\n\tRoom", "living room"), ("\u{a0}salle à manger\u{2003}", "salle à manger"),
+                                       ("ÉCLAIRAGE", "éclairage"), ("STRASSE", "strasse"), ("ΣΟΦΙΑ", "σοφια")] {
+            let tag = TagId::new(source).unwrap();
+            assert_eq!(tag.as_str(), normalized, "{:?}", source);
+            assert_eq!(tag, TagId::new(normalized).unwrap());
+            assert_eq!(source.parse::<TagId>(), Ok(tag));
+        }
+
+        // Canonical composition is not performed.
+        assert!(TagId::new("caf\u{e9}").unwrap() != TagId::new("cafe\u{301}").unwrap());
+    }
+
+    #[test]
+    fn test_invalid_tags() {
+        for source in &["", " ", "\t\n", "\u{a0}"] {
+            assert_eq!(TagId::new(source), Err(TagError::Empty), "{:?}", source);
+        }
+        assert_eq!(TagId::new("kitchen\u{0}"), Err(TagError::InvalidChar('\u{0}')));
+        assert_eq!(TagId::new("kit\u{7f}chen"), Err(TagError::InvalidChar('\u{7f}')));
+
+        // The length is measured in characters, once normalized.
+        let longest: String = iter::repeat('é').take(MAX_TAG_LENGTH).collect();
+        assert_eq!(TagId::new(&longest).unwrap().as_str(), longest);
+        let padded = format!("  {}  ", longest.to_uppercase());
+        assert_eq!(TagId::new(&padded).unwrap().as_str(), longest);
+        let long: String = iter::repeat('a').take(MAX_TAG_LENGTH + 1).collect();
+        assert_eq!(TagId::new(&long), Err(TagError::TooLong(MAX_TAG_LENGTH + 1)));
+    }
 }
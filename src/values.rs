@@ -2,19 +2,33 @@
 //! Values manipulated by services
 //!
 use std::cmp::{PartialOrd, Ordering};
+use std::fmt;
+use std::ops::Deref;
 use std::time::Duration;
-use std::str::FromStr;
 use std::sync::Arc;
 
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+#[cfg(feature = "std")]
+use compression::Compressed;
+#[cfg(feature = "std")]
+use patch::JsonPatch;
+
+#[cfg(feature = "std")]
 use serde_json;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
 use chrono;
+#[cfg(feature = "serde")]
 use serde::ser::{Serialize, Serializer};
-use serde::de::{Deserialize, Deserializer, Error};
+#[cfg(feature = "serde")]
+use serde::de::{Deserialize, Deserializer, Error, SeqVisitor, Visitor};
 
 ///
 /// The type of values manipulated by endpoints.
 ///
-#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Type {
     ///
     /// # Trivial values
@@ -41,6 +55,11 @@ pub enum Type {
 
     Temperature,
     String, 
+
+    /// A number of occurrences. Used for instance for the number of
+    /// consecutive presses making up a single press on a button, e.g.
+    /// 2 for a double click.
+    Count,
     ///
     /// ...
     ///
@@ -53,7 +72,8 @@ pub enum Type {
 /// A temperature. Internal representation may be either Fahrenheit or
 /// Celcius. The FoxBox adapters are expected to perform conversions
 /// to the format requested by their devices.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Temperature {
     /// Fahrenheit
     F(f64),
@@ -64,12 +84,18 @@ pub enum Temperature {
 impl Temperature {
     /// Get a temperature in Fahrenheit.
     pub fn as_f(&self) -> f64 {
-        unimplemented!();
+        match *self {
+            Temperature::F(f) => f,
+            Temperature::C(c) => c * 9. / 5. + 32.,
+        }
     }
 
     /// Get a temperature in Celcius.
     pub fn as_c(&self) -> f64 {
-        unimplemented!();
+        match *self {
+            Temperature::F(f) => (f - 32.) * 5. / 9.,
+            Temperature::C(c) => c,
+        }
     }
 }
 
@@ -82,7 +108,8 @@ impl PartialOrd for Temperature {
 /// A color. Internal representation may vary. The FoxBox adapters are
 /// expected to perform conversions to the format requested by their
 /// device.
-#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Color {
     RGBA(f64, f64, f64, f64, f64)
 }
@@ -91,9 +118,13 @@ pub enum Color {
 /// always) possible to choose a more precise data structure for
 /// representing values send/accepted by a service. If possible,
 /// adapters should rather pick such more precise data structure.
+///
+/// Only available with feature `std`.
+#[cfg(feature = "std")]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Json(pub serde_json::value::Value);
 
+#[cfg(feature = "std")]
 impl PartialOrd for Json {
     /// Two Json objects are never comparable to each other.
     fn partial_cmp(&self, _: &Self) -> Option<Ordering> {
@@ -101,9 +132,61 @@ impl PartialOrd for Json {
     }
 }
 
+/// Binary data. We put it behind an `Arc` to make sure that cloning
+/// remains unexpensive.
+///
+/// The data is serialized as bytes, rather than as a sequence of
+/// numbers, so that formats supporting bytes may encode and decode it
+/// in one go. Formats that do not, such as JSON, still represent it as
+/// an array of numbers.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Bytes(pub Arc<Vec<u8>>);
+
+impl Deref for Bytes {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Bytes {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer {
+        serializer.visit_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Deserialize for Bytes {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer {
+        struct BytesVisitor;
+        impl Visitor for BytesVisitor {
+            type Value = Bytes;
+            fn visit_bytes<E>(&mut self, value: &[u8]) -> Result<Bytes, E> where E: Error {
+                Ok(Bytes(Arc::new(value.to_vec())))
+            }
+            fn visit_byte_buf<E>(&mut self, value: Vec<u8>) -> Result<Bytes, E> where E: Error {
+                Ok(Bytes(Arc::new(value)))
+            }
+            fn visit_seq<V>(&mut self, mut visitor: V) -> Result<Bytes, V::Error> where V: SeqVisitor {
+                let mut data = vec![];
+                while let Some(byte) = try!(visitor.visit()) {
+                    data.push(byte);
+                }
+                try!(visitor.end());
+                Ok(Bytes(Arc::new(data)))
+            }
+        }
+        deserializer.visit_bytes(BytesVisitor)
+    }
+}
+
 /// A data structure holding a numeric value of a type that has not
 /// been standardized yet.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExtNumeric {
     pub value: f64,
 
@@ -137,7 +220,11 @@ impl PartialOrd for ExtNumeric {
 
 /// Representation of an actual value that can be sent to/received
 /// from a service.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+///
+/// Without feature `std`, variants `Json`, `JsonPatch` and `Compressed`
+/// are not available.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Value {
     Unit,
     Bool(bool),
@@ -146,6 +233,7 @@ pub enum Value {
     Temperature(Temperature),
     Color(Color),
     String(Arc<String>),
+    Count(u32),
 
     // FIXME: Add more as we identify needs
 
@@ -155,15 +243,25 @@ pub enum Value {
 
     /// A Json value. We put it behind an `Arc` to make sure that
     /// cloning remains unexpensive.
+    #[cfg(feature = "std")]
     Json(Arc<Json>),
 
     /// Binary data.
     Binary {
-        /// The actual data. We put it behind an `Arc` to make sure
-        /// that cloning remains unexpensive.
-        data: Arc<Vec<u8>>,
+        /// The actual data.
+        data: Bytes,
         mimetype: String
-    }
+    },
+
+    /// A change to the latest Json value of a channel, rather than the
+    /// whole value. Its type is `Type::Json`. See module `patch`.
+    #[cfg(feature = "std")]
+    JsonPatch(Arc<JsonPatch>),
+
+    /// A `Binary` or `Json` value, compressed for the wire. Its type is
+    /// that of the original value. See module `compression`.
+    #[cfg(feature = "std")]
+    Compressed(Arc<Compressed>),
 }
 
 impl Value {
@@ -172,13 +270,51 @@ impl Value {
             Value::Unit => Type::Unit,
             Value::Bool(_) => Type::Bool,
             Value::String(_) => Type::String,
+            Value::Count(_) => Type::Count,
             Value::Duration(_) => Type::Duration,
             Value::TimeStamp(_) => Type::TimeStamp,
             Value::Temperature(_) => Type::Temperature,
             Value::Color(_) => Type::Color,
-            Value::Json(_) => Type::Json,
             Value::Binary{..} => Type::Binary,
             Value::ExtNumeric(_) => Type::ExtNumeric,
+            #[cfg(feature = "std")]
+            Value::Json(_) | Value::JsonPatch(_) => Type::Json,
+            #[cfg(feature = "std")]
+            Value::Compressed(ref compressed) => compressed.get_type(),
+        }
+    }
+
+    /// Determine whether `self` and `other` differ by at least `delta`,
+    /// in absolute value.
+    ///
+    /// `delta` is a temperature for temperatures, where a delta in
+    /// Fahrenheit is a difference of degrees, a duration for durations
+    /// and timestamps, a count for counts, and an `ExtNumeric` of the
+    /// same vendor and kind for `ExtNumeric` values. Returns `None` for
+    /// values that have no magnitude, e.g. strings, or that do not
+    /// match `delta`.
+    pub fn differs_by(&self, other: &Value, delta: &Value) -> Option<bool> {
+        match (self, other, delta) {
+            (&Value::Temperature(ref a), &Value::Temperature(ref b), &Value::Temperature(ref delta)) => {
+                let delta = match *delta {
+                    Temperature::C(c) => c,
+                    Temperature::F(f) => f * 5. / 9.,
+                };
+                Some((a.as_c() - b.as_c()).abs() >= delta.abs())
+            }
+            (&Value::Duration(ref a), &Value::Duration(ref b), &Value::Duration(ref delta)) => {
+                let (a, b) = (a.as_ms(), b.as_ms());
+                let diff = if a > b { a - b } else { b - a };
+                Some(diff >= delta.as_ms())
+            }
+            (&Value::TimeStamp(ref a), &Value::TimeStamp(ref b), &Value::Duration(ref delta)) =>
+                Some((a.as_ms() - b.as_ms()).abs() as u64 >= delta.as_ms()),
+            (&Value::Count(a), &Value::Count(b), &Value::Count(delta)) =>
+                Some(if a > b { a - b } else { b - a } >= delta),
+            (&Value::ExtNumeric(ref a), &Value::ExtNumeric(ref b), &Value::ExtNumeric(ref delta))
+                if a.vendor == b.vendor && a.kind == b.kind && a.vendor == delta.vendor && a.kind == delta.kind =>
+                Some((a.value - b.value).abs() >= delta.value.abs()),
+            _ => None
         }
     }
 }
@@ -215,12 +351,25 @@ impl PartialOrd for Value {
             (&String(ref a), &String(ref b)) => a.partial_cmp(b),
             (&String(_), _) => None,
 
+            (&Count(a), &Count(b)) => a.partial_cmp(&b),
+            (&Count(_), _) => None,
+
+            #[cfg(feature = "std")]
             (&Json(ref a), &Json(ref b)) => a.partial_cmp(b),
+            #[cfg(feature = "std")]
             (&Json(_), _) => None,
 
             (&Binary{mimetype: ref a_mimetype, data: ref a_data},
              &Binary{mimetype: ref b_mimetype, data: ref b_data}) if a_mimetype == b_mimetype => a_data.partial_cmp(b_data),
             (&Binary{..}, _) => None,
+
+            #[cfg(feature = "std")]
+            (&JsonPatch(ref a), &JsonPatch(ref b)) => a.partial_cmp(b),
+            #[cfg(feature = "std")]
+            (&JsonPatch(_), _) => None,
+
+            #[cfg(feature = "std")]
+            (&Compressed(_), _) => None,
         }
     }
 }
@@ -231,56 +380,340 @@ impl ValDuration {
     pub fn new(duration: Duration) -> Self {
         ValDuration(duration)
     }
+    pub fn as_duration(&self) -> Duration {
+        self.0
+    }
+    pub fn as_ms(&self) -> u64 {
+        self.0.as_secs() * 1000 + (self.0.subsec_nanos() as u64) / 1_000_000
+    }
 }
+#[cfg(feature = "serde")]
 impl Serialize for ValDuration {
     fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
         where S: Serializer {
-        let as_ms : u64 = self.0.as_secs() * 1000
-            + (self.0.subsec_nanos() as u64) / 1_000_000;
-        as_ms.serialize(serializer)
+        self.as_ms().serialize(serializer)
     }
 }
+#[cfg(feature = "serde")]
 impl Deserialize for ValDuration {
     fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
         where D: Deserializer {
-        let as_sec : f64 = try!(f64::deserialize(deserializer));
-        Ok(ValDuration(Duration::new(as_sec as u64, as_sec.fract() as u32)))
+        // Durations are serialized as a number of milliseconds.
+        let ms : f64 = try!(f64::deserialize(deserializer));
+        if !(ms >= 0.) || ms >= ::std::u64::MAX as f64 {
+            return Err(D::Error::syntax("Invalid duration"));
+        }
+        let secs = (ms / 1000.) as u64;
+        let nanos = (ms - secs as f64 * 1000.) * 1_000_000.;
+        Ok(ValDuration(Duration::new(secs, nanos as u32)))
     }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord)]
-pub struct TimeStamp(chrono::DateTime<chrono::UTC>);
+/// A precise timestamp, in UTC.
+///
+/// Timestamps are serialized as RFC 3339 strings, e.g.
+/// "2016-04-12T10:30:00+00:00", and cover years -262144 to 262143.
+#[derive(Clone, PartialEq, PartialOrd, Eq, Ord)]
+pub struct TimeStamp {
+    /// Seconds since January 1st, 1970, UTC.
+    secs: i64,
+
+    /// Nanoseconds since `secs`, less than 1_000_000_000.
+    nanos: u32,
+}
 impl TimeStamp {
+    /// Only available with feature `std`, and not on WebAssembly.
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
     pub fn from_datetime(datetime: chrono::DateTime<chrono::UTC>) -> Self {
-        TimeStamp(datetime)
+        // Leap seconds are represented as a nanosecond part past the
+        // end of the second.
+        let nanos = datetime.timestamp_subsec_nanos();
+        TimeStamp {
+            secs: datetime.timestamp() + (nanos / 1_000_000_000) as i64,
+            nanos: nanos % 1_000_000_000,
+        }
+    }
+    /// Only available with feature `std`.
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    pub fn now() -> Self {
+        TimeStamp::from_datetime(chrono::UTC::now())
+    }
+    /// Only available with feature `std`. On WebAssembly, the clock is
+    /// that of JavaScript, see module `wasm`.
+    #[cfg(all(feature = "std", target_arch = "wasm32"))]
+    pub fn now() -> Self {
+        TimeStamp::from_ms(::wasm::now_ms()).expect("The clock of JavaScript is out of range")
+    }
+    /// The timestamp `duration` after this one.
+    pub fn plus(&self, duration: &ValDuration) -> Self {
+        let ms = duration.as_ms();
+        let nanos = self.nanos + (ms % 1000) as u32 * 1_000_000;
+        TimeStamp {
+            secs: self.secs + (ms / 1000) as i64 + (nanos / 1_000_000_000) as i64,
+            nanos: nanos % 1_000_000_000,
+        }
     }
     pub fn from_s(s: i64) -> Self {
-        use chrono::*;
-        let naive = chrono::naive::datetime::NaiveDateTime::from_timestamp(s, 0);
-        let date = DateTime::<UTC>::from_utc(naive, chrono::UTC);
-        TimeStamp(date)
+        TimeStamp {
+            secs: s,
+            nanos: 0,
+        }
+    }
+    /// The number of milliseconds since January 1st, 1970, UTC.
+    pub fn as_ms(&self) -> i64 {
+        self.secs * 1000 + (self.nanos / 1_000_000) as i64
+    }
+    /// The timestamp `ms` milliseconds after January 1st, 1970, UTC, or
+    /// `None` if it is out of range.
+    pub fn from_ms(ms: i64) -> Option<Self> {
+        let (mut s, mut rem) = (ms / 1000, ms % 1000);
+        if rem < 0 {
+            s -= 1;
+            rem += 1000;
+        }
+        let timestamp = TimeStamp {
+            secs: s,
+            nanos: rem as u32 * 1_000_000,
+        };
+        if timestamp.is_in_range() {
+            Some(timestamp)
+        } else {
+            None
+        }
+    }
+    /// The time elapsed between `earlier` and this timestamp, or `None`
+    /// if `earlier` is after this timestamp.
+    pub fn duration_since(&self, earlier: &TimeStamp) -> Option<ValDuration> {
+        let ms = self.as_ms() - earlier.as_ms();
+        if ms < 0 {
+            None
+        } else {
+            Some(ValDuration::new(Duration::from_millis(ms as u64)))
+        }
+    }
+
+    /// The timestamp as a RFC 3339 string, e.g.
+    /// "2016-04-12T10:30:00+00:00" or "2016-04-12T10:30:00.250+00:00".
+    pub fn to_rfc3339(&self) -> String {
+        let (mut days, mut secs) = (self.secs / 86400, self.secs % 86400);
+        if secs < 0 {
+            days -= 1;
+            secs += 86400;
+        }
+        let (year, month, day) = civil_from_days(days);
+        let year = if year >= 0 && year <= 9999 {
+            format!("{:04}", year)
+        } else {
+            format!("{:+05}", year)
+        };
+        let fraction = if self.nanos == 0 {
+            String::new()
+        } else if self.nanos % 1_000_000 == 0 {
+            format!(".{:03}", self.nanos / 1_000_000)
+        } else if self.nanos % 1000 == 0 {
+            format!(".{:06}", self.nanos / 1000)
+        } else {
+            format!(".{:09}", self.nanos)
+        };
+        format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}{}+00:00",
+                year, month, day, secs / 3600, secs / 60 % 60, secs % 60, fraction)
+    }
+
+    /// Parse a RFC 3339 string, with any offset, e.g.
+    /// "2016-04-12T10:30:00Z" or "2016-04-12T12:30:00.25+02:00".
+    /// Digits past the nanosecond are ignored.
+    pub fn from_rfc3339(source: &str) -> Option<Self> {
+        let mut parser = Rfc3339 {
+            bytes: source.as_bytes(),
+            pos: 0,
+        };
+        parser.timestamp().ok()
+    }
+
+    /// Determine whether the timestamp is within years -262144 to
+    /// 262143.
+    fn is_in_range(&self) -> bool {
+        self.secs >= days_from_civil(-262144, 1, 1) * 86400 &&
+            self.secs < days_from_civil(262144, 1, 1) * 86400
+    }
+}
+impl fmt::Debug for TimeStamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TimeStamp({})", self.to_rfc3339())
     }
 }
+#[cfg(feature = "serde")]
 impl Serialize for TimeStamp {
     fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
         where S: Serializer {
-        let str = self.0.to_rfc3339();
+        let str = self.to_rfc3339();
         str.serialize(serializer)
     }
 }
+#[cfg(feature = "serde")]
 impl Deserialize for TimeStamp {
     fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
         where D: Deserializer {
         let str = try!(String::deserialize(deserializer));
-        match chrono::DateTime::<chrono::UTC>::from_str(&str) {
-            Ok(dt) => Ok(TimeStamp(dt)),
-            Err(_) => Err(D::Error::syntax("Invalid date"))
+        match TimeStamp::from_rfc3339(&str) {
+            Some(timestamp) => Ok(timestamp),
+            None => Err(D::Error::syntax("Invalid date"))
         }
     }
 }
 
+/// The number of days from January 1st, 1970 to a date of the
+/// proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Years start on March 1st, so that leap days end them.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date of the proleptic Gregorian calendar `days` days after
+/// January 1st, 1970, as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31
+    }
+}
+
+/// A position in a RFC 3339 string.
+struct Rfc3339<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl<'a> Rfc3339<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    /// Consume a digit.
+    fn digit(&mut self) -> Option<i64> {
+        match self.peek() {
+            Some(byte) if byte >= b'0' && byte <= b'9' => {
+                self.pos += 1;
+                Some((byte - b'0') as i64)
+            }
+            _ => None
+        }
+    }
+
+    /// Consume a number of `min` to `max` digits.
+    fn number(&mut self, min: usize, max: usize) -> Result<i64, ()> {
+        let mut number = 0;
+        for i in 0..max {
+            match self.digit() {
+                Some(digit) => number = number * 10 + digit,
+                None if i >= min => break,
+                None => return Err(())
+            }
+        }
+        Ok(number)
+    }
+
+    /// Consume one of `expected`.
+    fn expect(&mut self, expected: &[u8]) -> Result<u8, ()> {
+        match self.peek() {
+            Some(byte) if expected.contains(&byte) => {
+                self.pos += 1;
+                Ok(byte)
+            }
+            _ => Err(())
+        }
+    }
+
+    /// Consume the whole string, as a timestamp.
+    fn timestamp(&mut self) -> Result<TimeStamp, ()> {
+        // Years outside of 0 to 9999 are signed, as produced by
+        // `TimeStamp::to_rfc3339`.
+        let year = match self.peek() {
+            Some(b'+') | Some(b'-') => {
+                let sign = try!(self.expect(b"+-"));
+                let year = try!(self.number(4, 6));
+                if sign == b'-' { -year } else { year }
+            }
+            _ => try!(self.number(4, 4))
+        };
+        try!(self.expect(b"-"));
+        let month = try!(self.number(2, 2));
+        try!(self.expect(b"-"));
+        let day = try!(self.number(2, 2));
+        try!(self.expect(b"Tt "));
+        let hour = try!(self.number(2, 2));
+        try!(self.expect(b":"));
+        let minute = try!(self.number(2, 2));
+        try!(self.expect(b":"));
+        let second = try!(self.number(2, 2));
+        let mut nanos = 0;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            let start = self.pos;
+            while let Some(digit) = self.digit() {
+                if self.pos - start <= 9 {
+                    nanos = nanos * 10 + digit as u32;
+                }
+            }
+            let len = self.pos - start;
+            if len == 0 {
+                return Err(());
+            }
+            for _ in len..9 {
+                nanos *= 10;
+            }
+        }
+        let offset = match try!(self.expect(b"Zz+-")) {
+            b'Z' | b'z' => 0,
+            sign => {
+                let hours = try!(self.number(2, 2));
+                try!(self.expect(b":"));
+                let minutes = try!(self.number(2, 2));
+                if hours > 23 || minutes > 59 {
+                    return Err(());
+                }
+                let offset = hours * 3600 + minutes * 60;
+                if sign == b'-' { -offset } else { offset }
+            }
+        };
+        // Second 60 is a leap second.
+        if self.pos != self.bytes.len() || month < 1 || month > 12 || day < 1 ||
+            day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+            return Err(());
+        }
+        let timestamp = TimeStamp {
+            secs: days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset,
+            nanos: nanos,
+        };
+        if timestamp.is_in_range() {
+            Ok(timestamp)
+        } else {
+            Err(())
+        }
+    }
+}
 
-#[derive(Clone, Deserialize, Serialize)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 /// A comparison between two values.
 pub enum Range {
     /// Leq(x) accepts any value v such that v <= x.
@@ -335,3 +768,114 @@ impl Range {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TimeStamp;
+
+    /// Check that `source` parses to the same timestamp as `canonical`,
+    /// and that this timestamp is printed as `canonical`.
+    fn round_trip(source: &str, canonical: &str) {
+        let timestamp = TimeStamp::from_rfc3339(source).unwrap();
+        assert_eq!(timestamp.to_rfc3339(), canonical);
+        assert_eq!(TimeStamp::from_rfc3339(canonical), Some(timestamp));
+    }
+
+    #[test]
+    fn test_rfc3339_dates() {
+        round_trip("1970-01-01T00:00:00Z", "1970-01-01T00:00:00+00:00");
+        round_trip("2016-04-12T10:30:00+00:00", "2016-04-12T10:30:00+00:00");
+        assert_eq!(TimeStamp::from_rfc3339("1970-01-01T00:00:00Z"), Some(TimeStamp::from_s(0)));
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-12T10:30:00Z"), Some(TimeStamp::from_s(1_460_457_000)));
+
+        // Leap years.
+        round_trip("2016-02-29T23:59:59Z", "2016-02-29T23:59:59+00:00");
+        round_trip("2000-02-29T12:00:00Z", "2000-02-29T12:00:00+00:00");
+        round_trip("2016-12-31T00:00:00Z", "2016-12-31T00:00:00+00:00");
+        assert_eq!(TimeStamp::from_rfc3339("2015-02-29T00:00:00Z"), None);
+        assert_eq!(TimeStamp::from_rfc3339("1900-02-29T00:00:00Z"), None);
+        assert_eq!(TimeStamp::from_rfc3339("2016-02-30T00:00:00Z"), None);
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-31T00:00:00Z"), None);
+
+        // Years before 1970, before year 0 and after year 9999.
+        round_trip("1969-12-31T23:59:59Z", "1969-12-31T23:59:59+00:00");
+        round_trip("0000-02-29T00:00:00Z", "0000-02-29T00:00:00+00:00");
+        round_trip("-0001-12-31T23:59:59Z", "-0001-12-31T23:59:59+00:00");
+        round_trip("-0400-02-29T00:00:00Z", "-0400-02-29T00:00:00+00:00");
+        round_trip("+10000-01-01T00:00:00Z", "+10000-01-01T00:00:00+00:00");
+        round_trip("+262143-12-31T23:59:59Z", "+262143-12-31T23:59:59+00:00");
+        round_trip("-262144-01-01T00:00:00Z", "-262144-01-01T00:00:00+00:00");
+        assert_eq!(TimeStamp::from_rfc3339("+262144-01-01T00:00:00Z"), None);
+        assert_eq!(TimeStamp::from_rfc3339("-262145-12-31T23:59:59Z"), None);
+        assert_eq!(TimeStamp::from_s(-1).to_rfc3339(), "1969-12-31T23:59:59+00:00");
+        assert_eq!(TimeStamp::from_s(-86_400).to_rfc3339(), "1969-12-31T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_rfc3339_offsets() {
+        round_trip("2016-04-12T12:30:00+02:00", "2016-04-12T10:30:00+00:00");
+        round_trip("2016-04-12T05:00:00-05:30", "2016-04-12T10:30:00+00:00");
+        round_trip("2016-04-12t10:30:00z", "2016-04-12T10:30:00+00:00");
+        round_trip("2016-04-12 10:30:00Z", "2016-04-12T10:30:00+00:00");
+
+        // Offsets may move the timestamp to another day, month or year.
+        round_trip("2016-03-01T01:00:00+02:00", "2016-02-29T23:00:00+00:00");
+        round_trip("2016-12-31T23:00:00-01:30", "2017-01-01T00:30:00+00:00");
+
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-12T10:30:00"), None);
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-12T10:30:00+24:00"), None);
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-12T10:30:00+02:60"), None);
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-12T10:30:00+0200"), None);
+    }
+
+    #[test]
+    fn test_rfc3339_fractions() {
+        round_trip("2016-04-12T10:30:00.25Z", "2016-04-12T10:30:00.250+00:00");
+        round_trip("2016-04-12T10:30:00.000001Z", "2016-04-12T10:30:00.000001+00:00");
+        round_trip("2016-04-12T10:30:00.123456789Z", "2016-04-12T10:30:00.123456789+00:00");
+        round_trip("2016-04-12T10:30:00.0Z", "2016-04-12T10:30:00+00:00");
+        round_trip("2016-04-12T12:30:00.5+02:00", "2016-04-12T10:30:00.500+00:00");
+        round_trip("1969-12-31T23:59:59.500Z", "1969-12-31T23:59:59.500+00:00");
+
+        // Digits past the nanosecond are ignored.
+        round_trip("2016-04-12T10:30:00.1234567899Z", "2016-04-12T10:30:00.123456789+00:00");
+
+        assert_eq!(TimeStamp::from_rfc3339("2016-04-12T10:30:00.Z"), None);
+
+        let timestamp = TimeStamp::from_rfc3339("1969-12-31T23:59:59.500Z").unwrap();
+        assert_eq!(timestamp.as_ms(), -500);
+        assert_eq!(TimeStamp::from_ms(-500), Some(timestamp));
+        assert_eq!(TimeStamp::from_ms(1_460_457_000_250).unwrap().to_rfc3339(), "2016-04-12T10:30:00.250+00:00");
+    }
+
+    /// Check the hand-written conversions against chrono, which the
+    /// FoxBox used before the core data model could be built without
+    /// the standard library.
+    #[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_rfc3339_matches_chrono() {
+        use chrono::{DateTime, UTC};
+        use chrono::naive::datetime::NaiveDateTime;
+        use std::str::FromStr;
+
+        // A linear congruential generator, to cover years -5000 to
+        // 5000 reproducibly.
+        let mut state: u64 = 42;
+        for i in 0..10_000 {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            let secs = (state >> 11) as i64 % 315_569_520_000 - 157_784_760_000;
+            let nanos = match i % 4 {
+                0 => 0,
+                1 => (state % 1000) as u32 * 1_000_000,
+                2 => (state % 1_000_000) as u32 * 1000,
+                _ => (state % 1_000_000_000) as u32
+            };
+            let datetime = DateTime::<UTC>::from_utc(NaiveDateTime::from_timestamp(secs, nanos), UTC);
+            let timestamp = TimeStamp::from_datetime(datetime);
+            assert_eq!(timestamp.to_rfc3339(), datetime.to_rfc3339());
+            assert_eq!(TimeStamp::from_rfc3339(&datetime.to_rfc3339()), Some(timestamp.clone()));
+            let parsed = DateTime::<UTC>::from_str(&timestamp.to_rfc3339()).unwrap();
+            assert_eq!(TimeStamp::from_datetime(parsed), timestamp);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//!
+//! Entry points for JavaScript, once compiled to WebAssembly.
+//!
+//! Built for `wasm32-unknown-unknown`, the crate lets the web frontend
+//! construct selectors and read watch events with the very same code
+//! as the FoxBox, rather than duplicating it in JavaScript. Only the
+//! client-side types are meant to be used this way: values, selectors
+//! and the messages of the WebSocket protocol. The runtime relies on
+//! threads, which are not available, so the crate should be built with
+//! `--no-default-features --features std`.
+//!
+//! # Imports
+//!
+//! WebAssembly offers no clock and no source of randomness, so the
+//! module imports them from JavaScript, as used by `TimeStamp::now`
+//! and `Id::new_random`:
+//!
+//! ```ignore
+//! let { instance } = await WebAssembly.instantiate(bytes, {
+//!     env: { foxbox_now: Date.now, foxbox_random: Math.random }
+//! });
+//! ```
+//!
+//! # Exports
+//!
+//! Strings are exchanged as UTF-8 through the memory of the module.
+//! JavaScript allocates the input of a function with `foxbox_alloc`,
+//! which the function takes over, and receives a NUL-terminated string,
+//! which it releases with `foxbox_free_string` once it has read it.
+//!
+//! - `foxbox_server_message` reads a message of the FoxBox, as
+//!   received from the WebSocket, and returns it as a JavaScript
+//!   object, see module `interop::js`;
+//! - `foxbox_client_message` reads a message to the FoxBox, as a
+//!   JavaScript object, and returns it as the text to send on the
+//!   WebSocket.
+//!
+//! Both return `{"error": "..."}` if their input is invalid.
+//!
+
+use interop::js;
+use websocket::ServerMessage;
+
+use serde_json;
+use serde_json::value::Value as JSON;
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::c_char;
+
+extern "C" {
+    /// `Date.now()`.
+    fn foxbox_now() -> f64;
+
+    /// `Math.random()`.
+    fn foxbox_random() -> f64;
+}
+
+/// The number of milliseconds since January 1st, 1970, UTC, according
+/// to JavaScript.
+pub fn now_ms() -> i64 {
+    unsafe { foxbox_now() as i64 }
+}
+
+/// 64 random bits, according to JavaScript. Not suitable for
+/// cryptographic purposes.
+pub fn random_u64() -> u64 {
+    // `Math.random()` offers at least 32 random bits.
+    let (high, low) = unsafe { (foxbox_random(), foxbox_random()) };
+    ((high * 4294967296.) as u64) << 32 | (low * 4294967296.) as u64
+}
+
+/// Allocate `len` bytes, to hold the input of another function.
+#[no_mangle]
+pub extern "C" fn foxbox_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    mem::forget(buffer);
+    ptr
+}
+
+/// Release a string returned by another function.
+#[no_mangle]
+pub unsafe extern "C" fn foxbox_free_string(ptr: *mut c_char) {
+    drop(CString::from_raw(ptr));
+}
+
+/// Take over an input allocated with `foxbox_alloc`.
+unsafe fn input(ptr: *mut u8, len: usize) -> Result<String, String> {
+    String::from_utf8(Vec::from_raw_parts(ptr, len, len))
+        .map_err(|_| "Invalid UTF-8".to_owned())
+}
+
+fn output(result: Result<String, String>) -> *mut c_char {
+    let text = result.unwrap_or_else(|err| {
+        let mut map = BTreeMap::new();
+        map.insert("error".to_owned(), JSON::String(err));
+        serde_json::to_string(&JSON::Object(map)).unwrap()
+    });
+    // JSON escapes NUL characters.
+    CString::new(text).unwrap().into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn foxbox_server_message(ptr: *mut u8, len: usize) -> *mut c_char {
+    output(input(ptr, len).and_then(|text| {
+        let message: ServerMessage = try!(serde_json::from_str(&text).map_err(|err| format!("{:?}", err)));
+        Ok(serde_json::to_string(&js::server_message_to_js(&message)).unwrap())
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn foxbox_client_message(ptr: *mut u8, len: usize) -> *mut c_char {
+    output(input(ptr, len).and_then(|text| {
+        let object: JSON = try!(serde_json::from_str(&text).map_err(|err| format!("{:?}", err)));
+        let message = try!(js::client_message_from_js(&object).map_err(|err| format!("{:?}", err)));
+        Ok(serde_json::to_string(&message).unwrap())
+    }))
+}
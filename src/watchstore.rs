@@ -0,0 +1,117 @@
+//!
+//! Persisting the watches that must survive a restart.
+//!
+//! Applications such as rule engines expect their watches to keep
+//! firing after the FoxBox has been power cycled. Such applications
+//! register their watches with `API::register_durable_watch`, under an
+//! owner id that they choose, e.g. "rules/turn-on-the-light". The
+//! manager saves the subscription in a `WatchStore`, along with the
+//! getters it currently watches. After a restart, the application
+//! re-establishes the watch with `API::resume_watch`, and first
+//! receives `GetterAdded`/`GetterRemoved` for the changes of topology
+//! that it has missed. See `manager::AdapterManager::with_watch_store`.
+//!
+//! Values produced while the watch was not established are not
+//! replayed, see module `history` for that purpose.
+//!
+
+use api::WatchOptions;
+use devices::Getter;
+use tagstore::{ write_atomically, StoreError };
+use values::TimeStamp;
+use util::Id;
+
+use serde_json;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ ErrorKind, Read };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+
+/// A watch registered with `API::register_durable_watch`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subscription {
+    /// The id chosen by the owner of the watch to re-establish it.
+    pub owner: String,
+
+    /// The options with which the watch was registered.
+    pub options: Vec<WatchOptions>,
+
+    /// The getters whose topology was watched when the subscription was
+    /// last saved.
+    pub getters: Vec<Id<Getter>>,
+
+    /// When the subscription was last saved.
+    pub saved: TimeStamp,
+}
+
+/// Storage for durable watches.
+pub trait WatchStore: Send + Sync {
+    /// The subscription stored for `owner`, or `None` if there is none.
+    fn get(&self, owner: &str) -> Result<Option<Subscription>, StoreError>;
+
+    /// Store a subscription, replacing any subscription with the same
+    /// owner.
+    fn save(&self, subscription: &Subscription) -> Result<(), StoreError>;
+
+    /// Remove the subscription stored for `owner`, if any.
+    fn forget(&self, owner: &str) -> Result<(), StoreError>;
+}
+
+/// A `WatchStore` keeping subscriptions in a JSON file, as an object
+/// mapping each owner to its `Subscription`.
+///
+/// As `tagstore::JsonTagStore`, the file is read once, then written
+/// again in its entirety after each change.
+pub struct JsonWatchStore {
+    path: PathBuf,
+    stored: Mutex<HashMap<String, Subscription>>,
+}
+
+impl JsonWatchStore {
+    /// Open the store kept at `path`, which is created on the first
+    /// change if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StoreError> {
+        let path = path.as_ref().to_path_buf();
+        let stored = match File::open(&path) {
+            Ok(mut file) => {
+                let mut source = String::new();
+                try!(file.read_to_string(&mut source).map_err(|err| StoreError::Io(format!("{}", err))));
+                try!(serde_json::from_str(&source).map_err(|err| StoreError::Corrupted(format!("{:?}", err))))
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(StoreError::Io(format!("{}", err)))
+        };
+        Ok(JsonWatchStore {
+            path: path,
+            stored: Mutex::new(stored),
+        })
+    }
+
+    fn write(&self, stored: &HashMap<String, Subscription>) -> Result<(), StoreError> {
+        let source = try!(serde_json::to_string_pretty(stored).map_err(|err| StoreError::Corrupted(format!("{:?}", err))));
+        write_atomically(&self.path, &source)
+    }
+}
+
+impl WatchStore for JsonWatchStore {
+    fn get(&self, owner: &str) -> Result<Option<Subscription>, StoreError> {
+        Ok(self.stored.lock().unwrap().get(owner).cloned())
+    }
+
+    fn save(&self, subscription: &Subscription) -> Result<(), StoreError> {
+        let mut stored = self.stored.lock().unwrap();
+        stored.insert(subscription.owner.clone(), subscription.clone());
+        self.write(&stored)
+    }
+
+    fn forget(&self, owner: &str) -> Result<(), StoreError> {
+        let mut stored = self.stored.lock().unwrap();
+        if stored.remove(owner).is_some() {
+            self.write(&stored)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,581 @@
+//!
+//! The protocol used to watch channels over WebSockets.
+//!
+//! Clients connect to `/api/v1/channels/watch` and exchange JSON
+//! messages with the FoxBox: each `ClientMessage` carries a request id
+//! chosen by the client, which the FoxBox echoes in the `ServerMessage`
+//! answering it. Each subscription, i.e. each watch registered through
+//! `ClientMessage::Subscribe`, is identified by the id of the
+//! underlying watch, and the events it produces are delivered as
+//! `ServerMessage::Event`, numbered so that clients can detect lost
+//! events.
+//!
+//! # Example
+//!
+//! ```ignore
+//! // Client
+//! {"Subscribe": {"request": "1", "options": [{"source": {}, "should_watch_values": true, "should_watch_topology": false}]}}
+//!
+//! // FoxBox
+//! {"Ack": {"request": "1", "subscription": "watch-4"}}
+//! {"Event": {"subscription": "watch-4", "sequence": 0, "event": {"Value": {"from": "thermometer/temperature", "value": ...}}}}
+//! ```
+//!
+//! Sessions created with `Session::with_compression` deliver large
+//! `Binary` and `Json` values as `Value::Compressed`, and announce it
+//! in `ServerMessage::Hello`. See module `compression`.
+//!
+//! # Keepalive and resume
+//!
+//! Sessions created with `Session::with_keepalive` announce a
+//! `KeepAlive` policy in `ServerMessage::Hello`: the FoxBox sends
+//! `ServerMessage::Heartbeat` whenever it has not sent anything else
+//! for a while, and clients send `ClientMessage::Ping`, so that both
+//! sides detect dead connections, e.g. when a phone leaves the Wi-Fi.
+//!
+//! When a connection is lost, its subscriptions need not be lost with
+//! it. The frontend may keep them, as returned by
+//! `Session::into_subscriptions`, for a grace period, during which it
+//! holds their events with `Subscription::push`. A client reconnecting
+//! sends `ClientMessage::Resume` with the sequence number of the next
+//! event it expects for each subscription, and the frontend answers
+//! with `Session::resumed`: if the subscription still holds the events
+//! the client has missed, they are replayed after a `ServerMessage::Ack`,
+//! so that no event is lost or delivered twice. Otherwise, the FoxBox
+//! sends `ServerMessage::Resync`, after which the client should fetch
+//! the state it needs again, e.g. with `API::get_channel_value`.
+//!
+//! ```ignore
+//! // Client, reconnecting after having received events 0 to 11.
+//! {"Resume": {"request": "7", "subscription": "watch-4", "sequence": 12}}
+//!
+//! // FoxBox
+//! {"Ack": {"request": "7", "subscription": "watch-4"}}
+//! {"Event": {"subscription": "watch-4", "sequence": 12, "event": ...}}
+//! ```
+//!
+
+use api::{Error, WatchEvent, WatchId, WatchOptions};
+use compression::CompressionPolicy;
+use util::Id;
+use values::ValDuration;
+
+use serde_json;
+
+use std::collections::{ HashMap, VecDeque };
+
+/// The version of the protocol implemented by this module.
+///
+/// Version 2 adds heartbeats and resuming subscriptions.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// A marker for Id.
+/// Only useful for writing `Id<RequestId>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Hash, Eq)]
+pub struct RequestId;
+
+/// A message sent by a client to the FoxBox.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ClientMessage {
+    /// Register a watch, as `API::register_channel_watch`.
+    ///
+    /// Answered with `ServerMessage::Ack`, carrying the id of the new
+    /// subscription, or `ServerMessage::Error`.
+    Subscribe {
+        request: Id<RequestId>,
+        options: Vec<WatchOptions>,
+    },
+
+    /// Unregister a watch.
+    ///
+    /// Answered with `ServerMessage::Ack` or `ServerMessage::Error`.
+    /// Once the `Ack` has been received, no further event is delivered
+    /// for this subscription.
+    Unsubscribe {
+        request: Id<RequestId>,
+        subscription: Id<WatchId>,
+    },
+
+    /// Check that the connection is alive.
+    ///
+    /// Answered with `ServerMessage::Pong`.
+    Ping {
+        request: Id<RequestId>,
+    },
+
+    /// Resume a subscription of a previous connection.
+    ///
+    /// Answered with `ServerMessage::Ack`, followed by the events
+    /// missed by the client, with `ServerMessage::Resync` if some of
+    /// them have been lost, or with `ServerMessage::Error` if the
+    /// subscription has been unregistered in the meantime, in which
+    /// case the client should subscribe again.
+    Resume {
+        request: Id<RequestId>,
+        subscription: Id<WatchId>,
+
+        /// The sequence number of the next event expected by the
+        /// client, i.e. one more than that of the last event it has
+        /// received, or 0 if it has not received any.
+        sequence: u64,
+    },
+}
+
+impl ClientMessage {
+    /// The id of the request.
+    pub fn request(&self) -> &Id<RequestId> {
+        match *self {
+            ClientMessage::Subscribe { ref request, .. } |
+            ClientMessage::Unsubscribe { ref request, .. } |
+            ClientMessage::Ping { ref request } |
+            ClientMessage::Resume { ref request, .. } => request
+        }
+    }
+}
+
+/// A message sent by the FoxBox to a client.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ServerMessage {
+    /// Sent once, as soon as the connection is established.
+    Hello {
+        /// The version of the protocol spoken by the FoxBox.
+        version: u32,
+
+        /// If `Some(policy)`, values are compressed according to
+        /// `policy`.
+        #[serde(default)]
+        compression: Option<CompressionPolicy>,
+
+        /// If `Some(keepalive)`, the FoxBox sends heartbeats and
+        /// expects pings according to `keepalive`.
+        #[serde(default)]
+        keepalive: Option<KeepAlive>,
+    },
+
+    /// A request has succeeded.
+    Ack {
+        request: Id<RequestId>,
+
+        /// If the request was a `ClientMessage::Subscribe`, the id of
+        /// the new subscription.
+        #[serde(default)]
+        subscription: Option<Id<WatchId>>,
+    },
+
+    /// A request has failed, or a message could not be understood.
+    Error {
+        /// The request that has failed, or `None` if the message could
+        /// not be parsed.
+        #[serde(default)]
+        request: Option<Id<RequestId>>,
+
+        error: ProtocolError,
+    },
+
+    /// An event for a subscription.
+    Event {
+        subscription: Id<WatchId>,
+
+        /// The number of events delivered for this subscription before
+        /// this one. A gap indicates that events have been lost.
+        sequence: u64,
+
+        event: WatchEvent,
+    },
+
+    /// Answer to `ClientMessage::Ping`.
+    Pong {
+        request: Id<RequestId>,
+    },
+
+    /// Sent when nothing else has been sent for `KeepAlive::interval`.
+    Heartbeat,
+
+    /// A `ClientMessage::Resume` has succeeded, but some of the events
+    /// missed by the client have been lost. The client should fetch
+    /// the state it needs again.
+    Resync {
+        request: Id<RequestId>,
+        subscription: Id<WatchId>,
+
+        /// The sequence number of the next event of the subscription.
+        sequence: u64,
+    },
+}
+
+/// How each side of a connection checks that the other is alive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeepAlive {
+    /// The longest time during which the FoxBox sends nothing. Once it
+    /// has elapsed, the frontend sends `Session::heartbeat()`.
+    pub interval: ValDuration,
+
+    /// The longest time during which the FoxBox waits for a message of
+    /// the client, e.g. `ClientMessage::Ping`, before closing the
+    /// connection. Clients should likewise consider the connection dead
+    /// after this time without receiving anything.
+    pub timeout: ValDuration,
+}
+
+/// A subscription, i.e. the numbering of the events of a watch, along
+/// with the latest of them, held so that they may be replayed to a
+/// client resuming the subscription.
+pub struct Subscription {
+    id: Id<WatchId>,
+
+    /// The sequence number of the next event.
+    next: u64,
+
+    /// The latest events, along with their sequence number, oldest
+    /// first.
+    replay: VecDeque<(u64, WatchEvent)>,
+
+    /// The largest number of events held for replay.
+    capacity: usize,
+}
+
+impl Subscription {
+    fn new(id: Id<WatchId>, capacity: usize) -> Self {
+        Subscription {
+            id: id,
+            next: 0,
+            replay: VecDeque::with_capacity(capacity),
+            capacity: capacity,
+        }
+    }
+
+    /// The id of the subscription, i.e. of its watch.
+    pub fn id(&self) -> &Id<WatchId> {
+        &self.id
+    }
+
+    /// Number an event and hold it for replay. Returns its sequence
+    /// number.
+    ///
+    /// Used by frontends to hold the events of subscriptions whose
+    /// connection has been lost. Subscriptions attached to a `Session`
+    /// are fed through `Session::event`.
+    pub fn push(&mut self, event: WatchEvent) -> u64 {
+        let sequence = self.next;
+        self.next += 1;
+        if self.capacity > 0 {
+            if self.replay.len() == self.capacity {
+                self.replay.pop_front();
+            }
+            self.replay.push_back((sequence, event));
+        }
+        sequence
+    }
+
+    /// The events pushed from sequence number `sequence`, or `None` if
+    /// some of them are not held anymore or `sequence` is in the future.
+    fn replay(&self, sequence: u64) -> Option<Vec<(u64, WatchEvent)>> {
+        if sequence > self.next {
+            return None;
+        }
+        if sequence == self.next {
+            return Some(vec![]);
+        }
+        match self.replay.front() {
+            Some(&(oldest, _)) if oldest <= sequence => {}
+            _ => return None
+        }
+        Some(self.replay.iter()
+            .filter(|&&(id, _)| id >= sequence)
+            .cloned()
+            .collect())
+    }
+}
+
+/// An error reported by `ServerMessage::Error`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ProtocolError {
+    /// The message is not a valid `ClientMessage`. Payload is a
+    /// human-readable description of the problem.
+    Malformed(String),
+
+    /// Another request with the same id is still being processed.
+    DuplicateRequest(Id<RequestId>),
+
+    /// There is no such subscription on this connection, or it has
+    /// already been unregistered.
+    NoSuchSubscription(Id<WatchId>),
+
+    /// The subscription is already active on this connection.
+    AlreadySubscribed(Id<WatchId>),
+
+    /// The API has rejected the request.
+    API(Error),
+}
+
+/// The state of the protocol on one connection, as seen by the FoxBox.
+///
+/// The session keeps track of the requests being processed and of the
+/// active subscriptions, and produces the messages to send to the
+/// client. It does not register watches itself: this is the
+/// responsibility of the frontend, e.g.
+///
+/// ```ignore
+/// match session.receive(&text) {
+///     Err(message) => send(message),
+///     Ok(ClientMessage::Subscribe { request, options }) => {
+///         let reply = match api.register_channel_watch_with_token(&caller, options, cb) {
+///             Ok(id) => session.subscribed(&request, id),
+///             Err(err) => session.failed(&request, ProtocolError::API(err)),
+///         };
+///         send(reply)
+///     }
+///     ...
+/// }
+/// ```
+pub struct Session {
+    /// The requests received but not answered yet.
+    pending: Vec<Id<RequestId>>,
+
+    /// The active subscriptions.
+    subscriptions: HashMap<Id<WatchId>, Subscription>,
+
+    /// How values are compressed, if they are.
+    compression: Option<CompressionPolicy>,
+
+    /// How the connection is kept alive, if it is.
+    keepalive: Option<KeepAlive>,
+
+    /// The largest number of events held by each subscription, for
+    /// replay.
+    replay: usize,
+}
+
+impl Session {
+    /// Start a new session. The frontend is expected to send the
+    /// result of `Session::hello()` to the client.
+    pub fn new() -> Self {
+        Session {
+            pending: vec![],
+            subscriptions: HashMap::new(),
+            compression: None,
+            keepalive: None,
+            replay: 0,
+        }
+    }
+
+    /// Compress the values delivered by `Session::event` according to
+    /// `policy`. Only use this for clients that can decompress values,
+    /// e.g. as negotiated by the frontend when the connection is
+    /// established.
+    pub fn with_compression(self, policy: CompressionPolicy) -> Self {
+        Session {
+            compression: Some(policy),
+            ..self
+        }
+    }
+
+    /// Send heartbeats and expect pings according to `keepalive`.
+    pub fn with_keepalive(self, keepalive: KeepAlive) -> Self {
+        Session {
+            keepalive: Some(keepalive),
+            ..self
+        }
+    }
+
+    /// Hold the latest `capacity` events of each subscription, so that
+    /// they may be replayed to a client resuming it. By default, no
+    /// event is held, and clients resuming a subscription are always
+    /// told to resync.
+    pub fn with_replay(self, capacity: usize) -> Self {
+        Session {
+            replay: capacity,
+            ..self
+        }
+    }
+
+    /// The first message of a session.
+    pub fn hello(&self) -> ServerMessage {
+        ServerMessage::Hello {
+            version: PROTOCOL_VERSION,
+            compression: self.compression.clone(),
+            keepalive: self.keepalive.clone(),
+        }
+    }
+
+    /// The message to send when nothing has been sent for
+    /// `KeepAlive::interval`.
+    pub fn heartbeat(&self) -> ServerMessage {
+        ServerMessage::Heartbeat
+    }
+
+    /// Parse a message received from the client.
+    ///
+    /// Pings are answered immediately. Other requests are recorded as
+    /// pending until they are answered with `Session::subscribed`,
+    /// `Session::unsubscribed`, `Session::resumed` or `Session::failed`.
+    ///
+    /// # Errors
+    ///
+    /// The message to send back to the client if the message is
+    /// malformed, reuses the id of a pending request, is a ping,
+    /// attempts to unregister an unknown subscription, or attempts to
+    /// resume a subscription that is already active on this connection.
+    pub fn receive(&mut self, text: &str) -> Result<ClientMessage, ServerMessage> {
+        let message: ClientMessage = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(err) => return Err(ServerMessage::Error {
+                request: None,
+                error: ProtocolError::Malformed(format!("{}", err))
+            })
+        };
+        let request = message.request().clone();
+        if self.pending.contains(&request) {
+            return Err(ServerMessage::Error {
+                request: Some(request.clone()),
+                error: ProtocolError::DuplicateRequest(request)
+            });
+        }
+        match message {
+            ClientMessage::Ping { .. } => return Err(ServerMessage::Pong {
+                request: request
+            }),
+            ClientMessage::Unsubscribe { ref subscription, .. } if !self.subscriptions.contains_key(subscription) => {
+                return Err(ServerMessage::Error {
+                    request: Some(request),
+                    error: ProtocolError::NoSuchSubscription(subscription.clone())
+                });
+            }
+            ClientMessage::Resume { ref subscription, .. } if self.subscriptions.contains_key(subscription) => {
+                return Err(ServerMessage::Error {
+                    request: Some(request),
+                    error: ProtocolError::AlreadySubscribed(subscription.clone())
+                });
+            }
+            _ => {}
+        }
+        self.pending.push(request);
+        Ok(message)
+    }
+
+    /// Answer a `ClientMessage::Subscribe` that has succeeded.
+    pub fn subscribed(&mut self, request: &Id<RequestId>, subscription: Id<WatchId>) -> ServerMessage {
+        self.answered(request);
+        self.subscriptions.insert(subscription.clone(), Subscription::new(subscription.clone(), self.replay));
+        ServerMessage::Ack {
+            request: request.clone(),
+            subscription: Some(subscription)
+        }
+    }
+
+    /// Answer a `ClientMessage::Unsubscribe` that has succeeded.
+    pub fn unsubscribed(&mut self, request: &Id<RequestId>, subscription: &Id<WatchId>) -> ServerMessage {
+        self.answered(request);
+        self.subscriptions.remove(subscription);
+        ServerMessage::Ack {
+            request: request.clone(),
+            subscription: None
+        }
+    }
+
+    /// Answer a `ClientMessage::Resume` for `subscription`, as held by
+    /// the frontend since the previous connection was lost. The
+    /// subscription becomes active on this connection, and keeps the
+    /// capacity it had.
+    ///
+    /// Produces a `ServerMessage::Ack` followed by the events missed by
+    /// the client, or a `ServerMessage::Resync` if some of them are not
+    /// held anymore.
+    pub fn resumed(&mut self, request: &Id<RequestId>, subscription: Subscription, sequence: u64) -> Vec<ServerMessage> {
+        self.answered(request);
+        let id = subscription.id.clone();
+        let messages = match subscription.replay(sequence) {
+            None => vec![ServerMessage::Resync {
+                request: request.clone(),
+                subscription: id.clone(),
+                sequence: subscription.next
+            }],
+            Some(events) => {
+                let mut messages = vec![ServerMessage::Ack {
+                    request: request.clone(),
+                    subscription: Some(id.clone())
+                }];
+                for (sequence, event) in events {
+                    messages.push(ServerMessage::Event {
+                        subscription: id.clone(),
+                        sequence: sequence,
+                        event: self.compress(event)
+                    });
+                }
+                messages
+            }
+        };
+        self.subscriptions.insert(id, subscription);
+        messages
+    }
+
+    /// Answer a request that has failed.
+    pub fn failed(&mut self, request: &Id<RequestId>, error: ProtocolError) -> ServerMessage {
+        self.answered(request);
+        ServerMessage::Error {
+            request: Some(request.clone()),
+            error: error
+        }
+    }
+
+    /// Wrap an event produced by a subscription, or produce `None` if
+    /// the subscription is not active anymore, in which case the event
+    /// should not be sent. `WatchEvent::Expired` ends the subscription,
+    /// as its watch has been unregistered.
+    pub fn event(&mut self, subscription: &Id<WatchId>, event: WatchEvent) -> Option<ServerMessage> {
+        let expired = match event {
+            WatchEvent::Expired => true,
+            _ => false
+        };
+        let sequence = match self.subscriptions.get_mut(subscription) {
+            None => return None,
+            Some(state) => state.push(event.clone())
+        };
+        if expired {
+            self.subscriptions.remove(subscription);
+        }
+        Some(ServerMessage::Event {
+            subscription: subscription.clone(),
+            sequence: sequence,
+            event: self.compress(event)
+        })
+    }
+
+    /// Compress the value of an event, according to the policy of the
+    /// session. Events are held for replay uncompressed, so that they
+    /// may be replayed to a session with another policy.
+    fn compress(&self, event: WatchEvent) -> WatchEvent {
+        match (event, self.compression.as_ref()) {
+            (WatchEvent::Value { from, value }, Some(policy)) => WatchEvent::Value {
+                from: from,
+                value: policy.apply(value)
+            },
+            (event, _) => event
+        }
+    }
+
+    /// The subscriptions currently active on this connection. Once
+    /// the connection is closed, the frontend is expected to unregister
+    /// the corresponding watches.
+    pub fn subscriptions(&self) -> Vec<Id<WatchId>> {
+        self.subscriptions.keys().cloned().collect()
+    }
+
+    /// End the session, keeping its active subscriptions, e.g. so that
+    /// a client may resume them after the connection has been lost.
+    /// Frontends that do not support resuming should unregister their
+    /// watches instead.
+    pub fn into_subscriptions(self) -> Vec<Subscription> {
+        self.subscriptions.into_iter().map(|(_, subscription)| subscription).collect()
+    }
+
+    fn answered(&mut self, request: &Id<RequestId>) {
+        self.pending.retain(|pending| pending != request);
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}